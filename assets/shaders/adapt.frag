@@ -0,0 +1,22 @@
+// The luminances of the cells of the scene, see luminance.frag, and the
+// luminance adapted to the frame before.
+uniform sampler2D u_luminance;
+uniform sampler2D u_adapted;
+// The fraction of the way to the scene's luminance adapted this frame.
+uniform float u_adaptation;
+
+out vec4 adapted;
+
+void main() {
+  ivec2 size = textureSize(u_luminance, 0);
+  vec2 sum = vec2(0.0);
+  for (int y = 0; y < size.y; ++y) {
+    for (int x = 0; x < size.x; ++x) {
+      sum += texelFetch(u_luminance, ivec2(x, y), 0).rg;
+    }
+  }
+  float previous = texelFetch(u_adapted, ivec2(0), 0).r;
+  // Only the sky showing, there's nothing to adapt to.
+  float scene = sum.y > 0.0 ? exp(sum.x / sum.y) : previous;
+  adapted = vec4(mix(previous, scene, u_adaptation), 0.0, 0.0, 1.0);
+}
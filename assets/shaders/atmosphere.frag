@@ -0,0 +1,106 @@
+// Positions are relative to the centre of the planet.
+uniform vec3 camera_position;
+uniform vec3 u_sun_direction;
+uniform float u_sun_intensity;
+// The air starts at the ground radius and thins out up to the atmosphere's.
+uniform float u_ground_radius;
+uniform float u_atmosphere_radius;
+// Relative to the Earth's.
+uniform float u_rayleigh_density;
+uniform float u_mie_density;
+
+in vec3 v_pos;
+
+out vec4 color;
+
+const float PI = 3.14159265;
+
+// The scale heights of the air and the aerosols, as fractions of the height
+// of the atmosphere, like the Earth's 8 km and 1.2 km of 100 km.
+const float RAYLEIGH_SCALE_HEIGHT = 0.08;
+const float MIE_SCALE_HEIGHT = 0.012;
+// The Earth's scattering coefficients times their scale heights, the optical
+// depths straight up, which stay the same whatever the size of the planet.
+const vec3 RAYLEIGH_DEPTH = vec3(0.0464, 0.108, 0.265);
+const float MIE_DEPTH = 0.0252;
+// Aerosols absorb some of the light too, and scatter it mostly forwards.
+const float MIE_EXTINCTION = 1.1;
+const float MIE_ASYMMETRY = 0.76;
+
+const int VIEW_SAMPLES = 16;
+const int LIGHT_SAMPLES = 8;
+
+// The densities of the air and of the aerosols at `position`, relative to
+// those on the ground.
+vec2 density(vec3 position, vec2 scale_heights) {
+  float altitude = max(0.0, length(position) - u_ground_radius);
+  return exp(-altitude / scale_heights);
+}
+
+void main()
+{
+  vec3 to_shell = v_pos - camera_position;
+  vec3 direction = normalize(to_shell);
+  // Only the side of the shell nearest to the camera is kept, the one facing
+  // it from outside and the far one from inside.
+  bool inside = length(camera_position) < u_atmosphere_radius;
+  bool facing = dot(v_pos, to_shell) < 0.0;
+  if (inside == facing) {
+    discard;
+  }
+
+  vec2 atmosphere = intersect_sphere(camera_position, direction, u_atmosphere_radius);
+  float start = max(0.0, atmosphere.x);
+  float end = atmosphere.y;
+  vec2 ground = intersect_sphere(camera_position, direction, u_ground_radius);
+  if (ground.x <= ground.y && ground.x > 0.0) {
+    end = min(end, ground.x);
+  }
+  if (end <= start) {
+    discard;
+  }
+
+  float height = u_atmosphere_radius - u_ground_radius;
+  vec2 scale_heights = height * vec2(RAYLEIGH_SCALE_HEIGHT, MIE_SCALE_HEIGHT);
+  vec3 rayleigh = RAYLEIGH_DEPTH * u_rayleigh_density / scale_heights.x;
+  float mie = MIE_DEPTH * u_mie_density / scale_heights.y;
+
+  float view_step = (end - start) / float(VIEW_SAMPLES);
+  vec2 view_depth = vec2(0.0);
+  vec3 rayleigh_light = vec3(0.0);
+  vec3 mie_light = vec3(0.0);
+  for (int i = 0; i < VIEW_SAMPLES; ++i) {
+    vec3 position = camera_position + direction * (start + view_step * (float(i) + 0.5));
+    vec2 sample_density = density(position, scale_heights) * view_step;
+    view_depth += sample_density;
+
+    // The night side gets no sunlight.
+    vec2 ground_hit = intersect_sphere(position, u_sun_direction, u_ground_radius);
+    if (ground_hit.x <= ground_hit.y && ground_hit.x > 0.0) {
+      continue;
+    }
+    float light_length = intersect_sphere(position, u_sun_direction, u_atmosphere_radius).y;
+    float light_step = light_length / float(LIGHT_SAMPLES);
+    vec2 light_depth = vec2(0.0);
+    for (int j = 0; j < LIGHT_SAMPLES; ++j) {
+      vec3 light_position = position + u_sun_direction * (light_step * (float(j) + 0.5));
+      light_depth += density(light_position, scale_heights) * light_step;
+    }
+
+    vec2 depth = view_depth + light_depth;
+    vec3 attenuation = exp(-(rayleigh * depth.x + mie * MIE_EXTINCTION * depth.y));
+    rayleigh_light += attenuation * sample_density.x;
+    mie_light += attenuation * sample_density.y;
+  }
+
+  float mu = dot(direction, u_sun_direction);
+  float g = MIE_ASYMMETRY;
+  float rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+  float mie_phase = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu)) /
+    ((2.0 + g * g) * pow(1.0 + g * g - 2.0 * g * mu, 1.5));
+  vec3 scattered = u_sun_intensity *
+    (rayleigh_light * rayleigh * rayleigh_phase + mie_light * mie * mie_phase);
+
+  vec3 transmittance = exp(-(rayleigh * view_depth.x + mie * MIE_EXTINCTION * view_depth.y));
+  color = vec4(1.0 - exp(-scattered), 1.0 - dot(transmittance, vec3(1.0 / 3.0)));
+}
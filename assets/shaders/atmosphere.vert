@@ -0,0 +1,13 @@
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+
+in vec3 position;
+
+out vec3 v_pos;
+
+void main()
+{
+  v_pos = position;
+  gl_Position = perspective * view * model * vec4(position, 1.0);
+}
@@ -0,0 +1,22 @@
+// A level of the bloom, blurred along `u_direction`, a unit vector along x
+// or y, into a texture of the same size.
+uniform sampler2D u_source;
+uniform vec2 u_direction;
+
+out vec4 color;
+
+// A 9 tap Gaussian, sampled between pairs of texels to take 5 samples.
+const float OFFSETS[3] = float[3](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[3](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+  vec2 size = vec2(textureSize(u_source, 0));
+  vec2 uv = gl_FragCoord.xy / size;
+  vec2 texel = u_direction / size;
+  vec3 sum = texture(u_source, uv).rgb * WEIGHTS[0];
+  for (int i = 1; i < 3; ++i) {
+    sum += texture(u_source, uv + texel * OFFSETS[i]).rgb * WEIGHTS[i];
+    sum += texture(u_source, uv - texel * OFFSETS[i]).rgb * WEIGHTS[i];
+  }
+  color = vec4(sum, 1.0);
+}
@@ -0,0 +1,21 @@
+// The scene, see post.frag, sampled between its texels so that the bright
+// pass downsamples it, and the luminance the eye is adapted to.
+uniform sampler2D u_scene;
+uniform sampler2D u_adapted;
+
+out vec4 color;
+
+// How many times brighter than the adapted luminance the light that blooms
+// is, and over how much brighter still it fades in.
+const float BLOOM_THRESHOLD = 4.0;
+const float BLOOM_KNEE = 4.0;
+const vec3 LUMA = vec3(0.2126, 0.7152, 0.0722);
+
+void main() {
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(u_scene, 0) / 2);
+  vec3 scene = texture(u_scene, uv).rgb;
+  float adapted = texelFetch(u_adapted, ivec2(0), 0).r;
+  float brightness = dot(scene, LUMA) / adapted;
+  float bloom = smoothstep(BLOOM_THRESHOLD, BLOOM_THRESHOLD + BLOOM_KNEE, brightness);
+  color = vec4(scene * bloom, 1.0);
+}
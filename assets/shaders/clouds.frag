@@ -0,0 +1,156 @@
+uniform mat4 perspective;
+uniform mat4 view;
+// The positions of the g-buffer, relative to the camera, see `RenderGraph`.
+uniform sampler2D u_position;
+// Positions are relative to the centre of the planet.
+uniform vec3 u_camera;
+uniform vec3 u_sun_direction;
+// The clouds are between the spheres of these radii.
+uniform float u_bottom_radius;
+uniform float u_top_radius;
+// See `CloudSpec`, `u_wind` being how far the wind has blown the clouds.
+uniform float u_coverage;
+uniform float u_density;
+uniform float u_wind;
+
+out vec4 color;
+
+const int VIEW_SAMPLES = 48;
+const int LIGHT_SAMPLES = 4;
+// The march stops this far away, and once the clouds hide what's behind.
+const float MAX_DISTANCE = 20000.0;
+const float MIN_TRANSMITTANCE = 0.01;
+
+// World units across the largest clouds, and the octaves of their noise.
+const float NOISE_SCALE = 300.0;
+const int NOISE_OCTAVES = 4;
+
+// The sunlight the clouds scatter, mostly forwards for their silver linings
+// and some backwards, and the sky's light on them.
+const vec3 SUN_COLOR = vec3(1.2, 1.16, 1.1);
+const vec3 SKY_COLOR = vec3(0.18, 0.21, 0.28);
+const float FORWARD_ASYMMETRY = 0.6;
+const float BACKWARD_ASYMMETRY = -0.3;
+const float BACKWARD_SCATTERING = 0.4;
+
+float hash(vec3 p) {
+  p = fract(p * 0.3183099 + 0.1);
+  p *= 17.0;
+  return fract(p.x * p.y * p.z * (p.x + p.y + p.z));
+}
+
+// Value noise in [0, 1], smoothly interpolated between the lattice's.
+float value_noise(vec3 p) {
+  vec3 cell = floor(p);
+  vec3 f = fract(p);
+  f = f * f * (3.0 - 2.0 * f);
+  return mix(mix(mix(hash(cell), hash(cell + vec3(1.0, 0.0, 0.0)), f.x),
+                 mix(hash(cell + vec3(0.0, 1.0, 0.0)), hash(cell + vec3(1.0, 1.0, 0.0)), f.x),
+                 f.y),
+             mix(mix(hash(cell + vec3(0.0, 0.0, 1.0)), hash(cell + vec3(1.0, 0.0, 1.0)), f.x),
+                 mix(hash(cell + vec3(0.0, 1.0, 1.0)), hash(cell + vec3(1.0, 1.0, 1.0)), f.x),
+                 f.y),
+             f.z);
+}
+
+float fbm(vec3 p) {
+  float sum = 0.0;
+  float amplitude = 0.5;
+  float total = 0.0;
+  for (int octave = 0; octave < NOISE_OCTAVES; ++octave) {
+    sum += amplitude * value_noise(p);
+    total += amplitude;
+    p *= 2.03;
+    amplitude *= 0.5;
+  }
+  return sum / total;
+}
+
+// How much the cloud at `position` scatters and absorbs per world unit. The
+// clouds are rounded at their bases and thin out towards the top of the
+// layer, and the wind blows them around.
+float density(vec3 position) {
+  float height = (length(position) - u_bottom_radius) / (u_top_radius - u_bottom_radius);
+  if (height < 0.0 || height > 1.0) {
+    return 0.0;
+  }
+  float profile = smoothstep(0.0, 0.2, height) * (1.0 - smoothstep(0.6, 1.0, height));
+  vec3 p = (position + vec3(u_wind, 0.0, 0.3 * u_wind)) / NOISE_SCALE;
+  float cloud = (fbm(p) * profile - (1.0 - u_coverage)) / max(u_coverage, 0.01);
+  return u_density * clamp(cloud, 0.0, 1.0);
+}
+
+// The fraction of the sunlight reaching `position` through the cloud above
+// it towards the sun.
+float sun_transmittance(vec3 position) {
+  float step_length = (u_top_radius - u_bottom_radius) / float(LIGHT_SAMPLES);
+  float depth = 0.0;
+  for (int i = 0; i < LIGHT_SAMPLES; ++i) {
+    depth += density(position + u_sun_direction * (float(i) + 0.5) * step_length);
+  }
+  return exp(-depth * step_length);
+}
+
+// The Henyey-Greenstein phase function, 1 if the light scatters evenly.
+float phase(float cos_angle, float asymmetry) {
+  float g2 = asymmetry * asymmetry;
+  return (1.0 - g2) / pow(1.0 + g2 - 2.0 * asymmetry * cos_angle, 1.5);
+}
+
+void main()
+{
+  // The view ray through the pixel, from the projection's scale along x and
+  // y, in the frame of the planet, which is the world's moved, so only the
+  // view's rotation turns it.
+  vec2 corner = gl_FragCoord.xy / vec2(textureSize(u_position, 0)) * 2.0 - 1.0;
+  vec3 ray = vec3(corner.x / perspective[0][0], corner.y / perspective[1][1], 1.0);
+  vec3 direction = normalize(transpose(mat3(view)) * ray);
+  vec4 surface = texelFetch(u_position, ivec2(gl_FragCoord.xy), 0);
+  float surface_distance = surface.w > 0.5 ? length(surface.xyz) : MAX_DISTANCE;
+
+  // The ray is marched through the layer between the spheres, up to the
+  // surfaces, from below, inside or above it.
+  vec2 top = intersect_sphere(u_camera, direction, u_top_radius);
+  if (top.x > top.y) {
+    discard;
+  }
+  float start = max(top.x, 0.0);
+  float end = min(min(top.y, surface_distance), MAX_DISTANCE);
+  vec2 bottom = intersect_sphere(u_camera, direction, u_bottom_radius);
+  if (bottom.x < bottom.y) {
+    if (bottom.x > 0.0) {
+      end = min(end, bottom.x);
+    } else {
+      start = max(start, bottom.y);
+    }
+  }
+  if (end <= start) {
+    discard;
+  }
+
+  float step_length = (end - start) / float(VIEW_SAMPLES);
+  // Offset per pixel, so the banding of the steps turns into noise.
+  float jitter = hash(vec3(gl_FragCoord.xy, 0.0));
+  float cos_angle = dot(direction, u_sun_direction);
+  float scattering = mix(phase(cos_angle, FORWARD_ASYMMETRY),
+                         phase(cos_angle, BACKWARD_ASYMMETRY), BACKWARD_SCATTERING);
+  float transmittance = 1.0;
+  vec3 light = vec3(0.0);
+  for (int i = 0; i < VIEW_SAMPLES; ++i) {
+    vec3 position = u_camera + direction * (start + (float(i) + jitter) * step_length);
+    float sample_density = density(position);
+    if (sample_density <= 0.0) {
+      continue;
+    }
+    // The night side of the planet is lit by neither the sun nor the sky.
+    float daylight = smoothstep(-0.1, 0.1, dot(normalize(position), u_sun_direction));
+    vec3 incoming = daylight * (SUN_COLOR * scattering * sun_transmittance(position) + SKY_COLOR);
+    float sample_transmittance = exp(-sample_density * step_length);
+    light += transmittance * (1.0 - sample_transmittance) * incoming;
+    transmittance *= sample_transmittance;
+    if (transmittance < MIN_TRANSMITTANCE) {
+      break;
+    }
+  }
+  color = vec4(light, 1.0 - transmittance);
+}
@@ -0,0 +1,34 @@
+// The whole sky, its longitude across and latitude down, the face of the cube
+// drawn, in the order of `FACE_NAMES`, and the texels along its side.
+uniform sampler2D u_map;
+uniform int u_face;
+uniform float u_size;
+
+out vec4 color;
+
+const float PI = 3.14159265359;
+
+// The direction through `uv`, in [-1, 1] across the face and down it, the
+// way OpenGL lays out the faces of cubemaps.
+vec3 face_direction(vec2 uv) {
+  if (u_face == 0) {
+    return vec3(1.0, -uv.y, -uv.x);
+  } else if (u_face == 1) {
+    return vec3(-1.0, -uv.y, uv.x);
+  } else if (u_face == 2) {
+    return vec3(uv.x, 1.0, uv.y);
+  } else if (u_face == 3) {
+    return vec3(uv.x, -1.0, -uv.y);
+  } else if (u_face == 4) {
+    return vec3(uv.x, -uv.y, 1.0);
+  }
+  return vec3(-uv.x, -uv.y, -1.0);
+}
+
+void main()
+{
+  vec3 direction = normalize(face_direction(gl_FragCoord.xy / u_size * 2.0 - 1.0));
+  vec2 uv = vec2(atan(direction.z, direction.x) / (2.0 * PI) + 0.5,
+                 acos(clamp(direction.y, -1.0, 1.0)) / PI);
+  color = vec4(texture(u_map, uv).rgb, 1.0);
+}
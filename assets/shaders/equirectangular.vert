@@ -0,0 +1,6 @@
+// A triangle over the whole face, its corners told by their index.
+void main()
+{
+  vec2 corner = vec2(float((gl_VertexID & 1) * 4), float((gl_VertexID & 2) * 2)) - 1.0;
+  gl_Position = vec4(corner, 0.0, 1.0);
+}
@@ -0,0 +1,19 @@
+// How much of the sun is visible, see flare_visibility.frag, and the colour
+// of the ghost, times the flare's intensity.
+uniform sampler2D u_visibility;
+uniform vec3 u_color;
+
+in vec2 v_offset;
+out vec4 color;
+
+void main() {
+  float radius = length(v_offset);
+  if (radius >= 1.0) {
+    discard;
+  }
+  // A soft disc with a brighter rim, like the aperture's reflections.
+  float disc = 1.0 - smoothstep(0.6, 1.0, radius);
+  float rim = smoothstep(0.7, 0.9, radius) * (1.0 - smoothstep(0.9, 1.0, radius));
+  float visibility = texelFetch(u_visibility, ivec2(0), 0).r;
+  color = vec4(u_color * (0.6 * disc + 0.4 * rim) * visibility, 0.0);
+}
@@ -0,0 +1,15 @@
+// A corner of the quad around a ghost, centred on it in normalized device
+// coordinates, with the ghost's radius as a fraction of the screen's height
+// and the screen's width over its height.
+in vec2 position;
+uniform vec2 u_centre;
+uniform float u_size;
+uniform float u_aspect;
+
+out vec2 v_offset;
+
+void main()
+{
+  v_offset = position;
+  gl_Position = vec4(u_centre + position * u_size * 2.0 * vec2(1.0 / u_aspect, 1.0), 0.0, 1.0);
+}
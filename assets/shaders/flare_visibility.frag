@@ -0,0 +1,28 @@
+// The g-buffer's positions, whose w is 0 for the sky, the sun in [0, 1]
+// across the screen and the screen's width over its height.
+uniform sampler2D u_position;
+uniform vec2 u_sun;
+uniform float u_aspect;
+
+out vec4 visibility;
+
+// The samples across the disc around the sun tested for the surfaces hiding
+// it, and its radius as a fraction of the screen's height.
+const int VISIBILITY_SAMPLES = 8;
+const float SUN_RADIUS = 0.01;
+
+void main() {
+  float visible = 0.0;
+  for (int y = 0; y < VISIBILITY_SAMPLES; ++y) {
+    for (int x = 0; x < VISIBILITY_SAMPLES; ++x) {
+      vec2 offset = (vec2(x, y) + 0.5) / float(VISIBILITY_SAMPLES) * 2.0 - 1.0;
+      vec2 uv = u_sun + offset * SUN_RADIUS * vec2(1.0 / u_aspect, 1.0);
+      // Off the screen, the sun is as good as hidden.
+      bool on_screen = all(greaterThanEqual(uv, vec2(0.0))) && all(lessThan(uv, vec2(1.0)));
+      if (on_screen && texture(u_position, uv).w < 0.5) {
+        visible += 1.0;
+      }
+    }
+  }
+  visibility = vec4(visible / float(VISIBILITY_SAMPLES * VISIBILITY_SAMPLES), 0.0, 0.0, 1.0);
+}
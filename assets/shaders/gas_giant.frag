@@ -0,0 +1,102 @@
+uniform vec3 u_light;
+uniform float u_time;
+uniform float u_rotation_period;
+uniform float u_num_bands;
+uniform float u_turbulence;
+uniform int u_num_storms;
+uniform float u_seed;
+
+in vec3 v_normal;
+in vec3 v_pos;
+
+out vec4 color;
+
+const float PI = 3.14159265;
+// The `MAX_STORMS` of gas_giant.rs.
+const int MAX_STORMS = 16;
+
+float hash(vec3 p) {
+  p = fract(p * 0.3183099 + 0.1);
+  p *= 17.0;
+  return fract(p.x * p.y * p.z * (p.x + p.y + p.z));
+}
+
+// Value noise in [-1, 1].
+float value_noise(vec3 p) {
+  vec3 i = floor(p);
+  vec3 f = fract(p);
+  f = f * f * (3.0 - 2.0 * f);
+  return mix(mix(mix(hash(i), hash(i + vec3(1, 0, 0)), f.x),
+                 mix(hash(i + vec3(0, 1, 0)), hash(i + vec3(1, 1, 0)), f.x), f.y),
+             mix(mix(hash(i + vec3(0, 0, 1)), hash(i + vec3(1, 0, 1)), f.x),
+                 mix(hash(i + vec3(0, 1, 1)), hash(i + vec3(1, 1, 1)), f.x), f.y),
+             f.z) * 2.0 - 1.0;
+}
+
+float fbm(vec3 p) {
+  float value = 0.0;
+  float amplitude = 0.5;
+  for (int octave = 0; octave < 5; octave++) {
+    value += amplitude * value_noise(p);
+    p *= 2.03;
+    amplitude *= 0.5;
+  }
+  return value;
+}
+
+// How far the clouds at `latitude` have turned after `u_time`: the bands
+// alternate between faster and slower winds, and all slow towards the poles.
+float band_rotation(float latitude) {
+  float wind = 1.0 + 0.15 * cos(latitude * u_num_bands);
+  return 2.0 * PI * u_time / u_rotation_period * wind * cos(latitude);
+}
+
+vec3 rotate_y(vec3 p, float angle) {
+  float c = cos(angle);
+  float s = sin(angle);
+  return vec3(c * p.x + s * p.z, p.y, -s * p.x + c * p.z);
+}
+
+void main()
+{
+  vec3 direction = normalize(v_pos);
+  float latitude = asin(clamp(direction.y, -1.0, 1.0));
+  vec3 cloud_pos = rotate_y(direction, -band_rotation(latitude));
+
+  // Eddies warp the bands, and slowly change as the clouds churn.
+  vec3 churn = vec3(0.0, u_time * 0.002, u_seed);
+  float warp = fbm(cloud_pos * 4.0 + churn) * u_turbulence;
+  float band = 0.5 + 0.5 * sin((latitude + warp * 0.2) * u_num_bands);
+  float detail = fbm(cloud_pos * vec3(3.0, 24.0, 3.0) + churn);
+
+  vec3 light_color = vec3(0.92, 0.86, 0.74);
+  vec3 dark_color = vec3(0.68, 0.47, 0.31);
+  vec3 cloud_color = mix(dark_color, light_color, clamp(band + 0.25 * detail, 0.0, 1.0));
+
+  // Storms are ovals stretched along their band and drifting with it, each
+  // swirling around its centre.
+  for (int i = 0; i < MAX_STORMS; i++) {
+    if (i >= u_num_storms) {
+      break;
+    }
+    vec3 key = vec3(float(i), u_seed, 7.0);
+    float storm_latitude = (hash(key) - 0.5) * PI * 0.7;
+    float storm_longitude = hash(key + 13.0) * 2.0 * PI + band_rotation(storm_latitude);
+    float size = mix(0.04, 0.15, hash(key + 29.0));
+
+    float longitude = atan(direction.z, direction.x);
+    vec2 offset = vec2(mod(longitude - storm_longitude + PI, 2.0 * PI) - PI,
+                       latitude - storm_latitude);
+    offset.x *= cos(latitude) * 0.5;
+    float distance = length(offset) / size;
+    if (distance < 1.0) {
+      float swirl = atan(offset.y, offset.x) + distance * 6.0 - u_time * 0.05;
+      float rings = 0.5 + 0.5 * sin(swirl * 3.0 + fbm(vec3(offset * 20.0, u_seed)));
+      vec3 storm_color = mix(vec3(0.75, 0.33, 0.2), vec3(0.95, 0.8, 0.7), rings);
+      cloud_color = mix(storm_color, cloud_color, smoothstep(0.6, 1.0, distance));
+    }
+  }
+
+  float brightness = max(0.02, dot(normalize(v_normal), normalize(v_pos - u_light)));
+  color = vec4(cloud_color * brightness, 1.0);
+}
@@ -0,0 +1,15 @@
+uniform mat4 perspective;
+uniform mat4 view;
+
+in vec3 position;
+in vec3 normal;
+
+out vec3 v_normal;
+out vec3 v_pos;
+
+void main()
+{
+  v_pos = position;
+  v_normal = normal;
+  gl_Position = perspective * view * vec4(position, 1.0);
+}
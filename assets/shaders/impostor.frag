@@ -0,0 +1,29 @@
+uniform mat4 view;
+// The camera and the index the asteroids are lit by, see planet.frag.
+uniform vec3 u_camera;
+uniform float u_body;
+
+in vec2 v_corner;
+in vec3 v_centre;
+in float v_size;
+
+out vec4 albedo;
+out vec4 normal;
+out vec4 position;
+
+const float AMBIENT = 0.02;
+
+void main()
+{
+  float radius_squared = dot(v_corner, v_corner);
+  if (radius_squared > 1.0) {
+    discard;
+  }
+  // Shaded as the sphere the disc is the silhouette of, its normal turned
+  // from view space back into that of the centre.
+  vec3 view_normal = vec3(v_corner, -sqrt(1.0 - radius_squared));
+  vec3 sphere_normal = transpose(mat3(view)) * view_normal;
+  albedo = vec4(0.45, 0.4, 0.36, AMBIENT);
+  normal = vec4(sphere_normal, 1.0);
+  position = vec4(v_centre + sphere_normal * v_size - u_camera, u_body);
+}
@@ -0,0 +1,21 @@
+uniform mat4 perspective;
+uniform mat4 view;
+
+// A corner of the quad, in [-1, 1]^2.
+in vec3 position;
+in vec3 centre;
+in float size;
+
+out vec2 v_corner;
+out vec3 v_centre;
+out float v_size;
+
+void main()
+{
+  v_corner = position.xy;
+  v_centre = centre;
+  v_size = size;
+  // The quad faces the camera: its corners are offset in view space.
+  vec4 view_centre = view * vec4(centre, 1.0);
+  gl_Position = perspective * (view_centre + vec4(position.xy * size, 0.0, 0.0));
+}
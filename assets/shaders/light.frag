@@ -0,0 +1,44 @@
+// The g-buffer, see `RenderGraph`.
+uniform sampler2D u_albedo;
+uniform sampler2D u_normal;
+uniform sampler2D u_position;
+
+// The light's position relative to the camera, like the g-buffer's, and the
+// cosines of the angles of its cone, see `PointLight`.
+uniform vec3 u_light_position;
+uniform vec3 u_light_color;
+uniform float u_light_radius;
+uniform vec3 u_spot_direction;
+uniform float u_spot_inner;
+uniform float u_spot_outer;
+
+out vec4 color;
+
+// The inverse square falloff of the light, windowed to reach 0 at its radius
+// rather than lighting everything a little.
+float attenuation(float distance) {
+  float window = clamp(1.0 - pow(distance / u_light_radius, 4.0), 0.0, 1.0);
+  return window * window / (1.0 + distance * distance);
+}
+
+void main() {
+  ivec2 texel = ivec2(gl_FragCoord.xy);
+  vec4 position = texelFetch(u_position, texel, 0);
+  vec4 albedo = texelFetch(u_albedo, texel, 0);
+  // Neither the sky nor surfaces glowing on their own are lit.
+  if (position.w < 0.5 || albedo.a > 0.99) {
+    discard;
+  }
+  vec3 ray = position.xyz - u_light_position;
+  float distance = length(ray);
+  if (distance >= u_light_radius) {
+    discard;
+  }
+  vec3 direction = ray / distance;
+  float cone = u_spot_outer < -1.0 ? 1.0 :
+      smoothstep(u_spot_outer, u_spot_inner, dot(direction, u_spot_direction));
+  // The normals point into the ground, like the light's rays onto it.
+  vec3 surface_normal = normalize(texelFetch(u_normal, texel, 0).xyz);
+  float diffuse = max(0.0, dot(surface_normal, direction));
+  color = vec4(albedo.rgb * u_light_color * (diffuse * cone * attenuation(distance)), 0.0);
+}
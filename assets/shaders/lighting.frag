@@ -0,0 +1,146 @@
+// The g-buffer, see `RenderGraph`.
+uniform sampler2D u_albedo;
+uniform sampler2D u_normal;
+uniform sampler2D u_position;
+// The index of the body whose surfaces are lit. The camera, the light and
+// the sea are in its frame.
+uniform float u_body;
+uniform vec3 u_camera;
+uniform vec3 u_light;
+// The nearest moon, in the same frame, and how bright its light is; 0
+// without moons.
+uniform vec3 u_moon;
+uniform float u_moonlight;
+// The radius of the sea, 0 without a sea.
+uniform float u_sea_radius;
+// How far from the camera the fog all but hides the surfaces, 0 without fog,
+// and its color in daylight.
+uniform float u_fog_distance;
+uniform vec3 u_fog_color;
+// The depths of the terrain nearest to the sun in each cascade, side by side,
+// and the matrices taking positions to them. No shadows without cascades.
+uniform sampler2D u_shadow_map;
+uniform int u_num_cascades;
+uniform mat4 u_shadow_matrix0;
+uniform mat4 u_shadow_matrix1;
+uniform mat4 u_shadow_matrix2;
+uniform mat4 u_shadow_matrix3;
+
+out vec4 color;
+
+// How much brighter than the sunlit ground surfaces glowing on their own are.
+const float EMISSIVE_INTENSITY = 4.0;
+
+const vec3 MOONLIGHT_COLOR = vec3(0.6, 0.7, 1.0);
+
+// How much further from the sun than the depth in the shadow map the terrain
+// must be to be in its shadow, so it doesn't shadow itself, and the texels
+// around each the shadow is averaged over, for softer edges.
+const float SHADOW_BIAS = 0.0005;
+const int SHADOW_KERNEL = 1;
+
+// The fraction of each of red, green and blue the water absorbs per world
+// unit the light crosses, and the color it scatters back.
+const vec3 WATER_ABSORPTION = vec3(0.25, 0.06, 0.04);
+const vec3 WATER_COLOR = vec3(0.02, 0.12, 0.25);
+
+// How thick the fog is at its distance, hiding all but e^-FOG_DENSITY of the
+// surfaces, and how much of its daylight color is left at night.
+const float FOG_DENSITY = 4.0;
+const float FOG_NIGHT = 0.05;
+
+mat4 shadow_matrix(int cascade) {
+  if (cascade == 0) {
+    return u_shadow_matrix0;
+  } else if (cascade == 1) {
+    return u_shadow_matrix1;
+  } else if (cascade == 2) {
+    return u_shadow_matrix2;
+  }
+  return u_shadow_matrix3;
+}
+
+// How much of the sun reaches `position` in the map of `cascade`, averaged
+// over the texels around it without straying into the next cascade.
+float cascade_visibility(int cascade, vec3 position) {
+  vec2 texel = 1.0 / vec2(textureSize(u_shadow_map, 0));
+  texel.x *= float(u_num_cascades);
+  float lit = 0.0;
+  for (int x = -SHADOW_KERNEL; x <= SHADOW_KERNEL; ++x) {
+    for (int y = -SHADOW_KERNEL; y <= SHADOW_KERNEL; ++y) {
+      vec2 uv = clamp(position.xy + vec2(x, y) * texel, 0.0, 1.0);
+      uv.x = (float(cascade) + uv.x) / float(u_num_cascades);
+      float depth = texture(u_shadow_map, uv).r;
+      lit += position.z - SHADOW_BIAS > depth ? 0.0 : 1.0;
+    }
+  }
+  float side = float(2 * SHADOW_KERNEL + 1);
+  return lit / (side * side);
+}
+
+// How much of the sun reaches `surface`, in the nearest cascade it's in, 1
+// where nothing in view stands in its way and beyond the cascades.
+float sun_visibility(vec3 surface) {
+  for (int cascade = 0; cascade < u_num_cascades; ++cascade) {
+    vec3 position = (shadow_matrix(cascade) * vec4(surface, 1.0)).xyz * 0.5 + 0.5;
+    if (all(greaterThanEqual(position, vec3(0.0))) &&
+        all(lessThanEqual(position, vec3(1.0)))) {
+      return cascade_visibility(cascade, position);
+    }
+  }
+  return 1.0;
+}
+
+// How far the ray from the camera to `surface` goes under the sea.
+float water_depth(vec3 surface) {
+  vec3 ray = surface - u_camera;
+  float length_to_fragment = length(ray);
+  vec3 direction = ray / length_to_fragment;
+  vec2 sea = intersect_sphere(u_camera, direction, u_sea_radius);
+  return max(0.0, min(length_to_fragment, sea.y) - max(0.0, sea.x));
+}
+
+void main() {
+  ivec2 texel = ivec2(gl_FragCoord.xy);
+  vec4 position = texelFetch(u_position, texel, 0);
+  if (abs(position.w - u_body) > 0.5) {
+    discard;
+  }
+  // Its alpha is the least the sun lights it, 1 for surfaces glowing on
+  // their own.
+  vec4 albedo = texelFetch(u_albedo, texel, 0);
+  if (albedo.a > 0.99) {
+    color = vec4(albedo.rgb * EMISSIVE_INTENSITY, 1.0);
+    return;
+  }
+  vec4 normal = texelFetch(u_normal, texel, 0);
+  vec3 surface = position.xyz + u_camera;
+  vec3 surface_normal = normalize(normal.xyz);
+
+  float sunlight = dot(surface_normal, normalize(surface - u_light));
+  float brightness = max(albedo.a, sunlight * sun_visibility(surface));
+  // Moonlight only shows where the sun doesn't reach.
+  float night = 1.0 - smoothstep(-0.1, 0.1, sunlight);
+  float moonlight = u_moonlight * night *
+      max(0.0, dot(surface_normal, normalize(u_moon - surface)));
+  vec3 dark_color = albedo.rgb * 0.2;
+  // The normal's alpha is how much of the sky the surface sees.
+  color = vec4((mix(dark_color, albedo.rgb, brightness) +
+                moonlight * MOONLIGHT_COLOR * albedo.rgb) * normal.a, 1.0);
+
+  // Under the sea, the water between the ground and the camera absorbs the
+  // reds first and scatters back its own color, the deeper the more.
+  if (u_sea_radius > 0.0) {
+    vec3 transmittance = exp(-WATER_ABSORPTION * water_depth(surface));
+    color.rgb = mix(WATER_COLOR * brightness, color.rgb, transmittance);
+  }
+
+  // Squared exponential in the distance, so the fog gathers in the distance
+  // rather than veiling what's near, and as dark as the sky at night.
+  if (u_fog_distance > 0.0) {
+    float distance = length(position.xyz) / u_fog_distance;
+    float fog = 1.0 - exp(-FOG_DENSITY * distance * distance);
+    float day = smoothstep(-0.1, 0.1, dot(normalize(surface), normalize(surface - u_light)));
+    color.rgb = mix(color.rgb, u_fog_color * mix(FOG_NIGHT, 1.0, day), fog);
+  }
+}
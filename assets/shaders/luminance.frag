@@ -0,0 +1,34 @@
+// The scene, premultiplied by how much of the sky it covers, reduced to
+// `u_cells` texels across.
+uniform sampler2D u_scene;
+uniform int u_cells;
+
+// The sum of the log luminances of the samples of the cell, weighted by how
+// much of the sky they cover, and the sum of the weights, in r and g, over
+// the number of samples.
+out vec4 luminance;
+
+// Samples across a cell of the scene.
+const int SAMPLES = 4;
+// The perceived brightness of the channels.
+const vec3 LUMA = vec3(0.2126, 0.7152, 0.0722);
+// The darkest luminance told apart, for the logarithm.
+const float MIN_LUMINANCE = 1e-4;
+
+void main() {
+  vec2 cell = vec2(textureSize(u_scene, 0)) / float(u_cells);
+  float total = 0.0;
+  float coverage = 0.0;
+  for (int y = 0; y < SAMPLES; ++y) {
+    for (int x = 0; x < SAMPLES; ++x) {
+      vec2 point = floor(gl_FragCoord.xy) + (vec2(x, y) + 0.5) / float(SAMPLES);
+      vec4 scene = texelFetch(u_scene, ivec2(point * cell), 0);
+      if (scene.a > 0.01) {
+        float brightness = dot(scene.rgb, LUMA) / scene.a;
+        total += log(max(brightness, MIN_LUMINANCE)) * scene.a;
+        coverage += scene.a;
+      }
+    }
+  }
+  luminance = vec4(total, coverage, 0.0, 1.0) / float(SAMPLES * SAMPLES);
+}
@@ -0,0 +1,96 @@
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+uniform vec3 camera_position;
+uniform vec3 u_light;
+// Seconds since the start, for the waves.
+uniform float u_time;
+// The frame as drawn before the water.
+uniform sampler2D u_scene;
+
+in vec3 v_normal;
+in vec3 v_pos;
+
+out vec4 color;
+
+// Waves travelling across the sphere, each a direction and a wavelength in
+// world units, with the slope of the steepest of them.
+const int NUM_WAVES = 4;
+const vec4 WAVES[NUM_WAVES] = vec4[NUM_WAVES](
+  vec4(0.8, 0.0, 0.6, 23.0),
+  vec4(-0.3, 0.9, 0.3, 11.0),
+  vec4(0.2, -0.5, -0.84, 5.3),
+  vec4(-0.7, -0.2, 0.68, 2.1)
+);
+const float WAVE_SLOPE = 0.12;
+const float GRAVITY = 9.81;
+
+// How far the waves shift what's refracted, across the screen, and how far
+// away the reflections are taken to be, in distances to the camera.
+const float REFRACTION_STRENGTH = 0.04;
+const float REFLECTION_DISTANCE = 1.0;
+// The fraction of the screen near its edges over which the reflections fade
+// into the sky's color, for what's reflected from out of view.
+const float REFLECTION_EDGE = 0.1;
+
+const vec3 DEEP_COLOR = vec3(0.02, 0.12, 0.25);
+const vec3 SKY_COLOR = vec3(0.35, 0.5, 0.65);
+const float SHININESS = 200.0;
+
+// The normal of the surface perturbed by the waves, each moving at the speed
+// of deep water waves of its wavelength.
+vec3 wave_normal(vec3 normal) {
+  vec3 slope = vec3(0.0);
+  for (int i = 0; i < NUM_WAVES; ++i) {
+    float wavenumber = 2.0 * 3.14159265 / WAVES[i].w;
+    vec3 direction = normalize(WAVES[i].xyz);
+    float speed = sqrt(GRAVITY * wavenumber);
+    float phase = wavenumber * dot(direction, v_pos) - speed * u_time;
+    // The longer waves are the steeper, like in a developed sea.
+    slope += direction * cos(phase) * WAVES[i].w / WAVES[0].w;
+  }
+  slope -= dot(slope, normal) * normal;
+  return normalize(normal - slope * WAVE_SLOPE);
+}
+
+// Where `position` is on the screen, in [0, 1] across it, and whether it's
+// in front of the camera.
+vec3 screen_position(vec3 position) {
+  vec4 clip = perspective * view * model * vec4(position, 1.0);
+  return vec3(clip.xy / clip.w * 0.5 + 0.5, clip.w);
+}
+
+void main()
+{
+  vec3 surface_normal = normalize(v_normal);
+  vec3 from_camera = v_pos - camera_position;
+  vec3 to_camera = normalize(-from_camera);
+  // Seen from below, the surface faces down.
+  if (dot(surface_normal, to_camera) < 0.0) {
+    surface_normal = -surface_normal;
+  }
+  vec3 normal = wave_normal(surface_normal);
+  vec3 to_light = normalize(v_pos - u_light);
+  float brightness = max(0.05, dot(surface_normal, to_light));
+  // Water gets more opaque and reflective at grazing angles.
+  float fresnel = pow(1.0 - abs(dot(normal, to_camera)), 3.0);
+
+  vec2 screen_size = vec2(textureSize(u_scene, 0));
+  vec2 offset = (mat3(view * model) * (normal - surface_normal)).xy * REFRACTION_STRENGTH;
+  vec3 refracted = texture(u_scene, gl_FragCoord.xy / screen_size + offset).rgb;
+  refracted = mix(refracted, DEEP_COLOR * brightness, 0.3);
+
+  vec3 reflection = reflect(-to_camera, normal);
+  vec3 reflected_position = v_pos + reflection * length(from_camera) * REFLECTION_DISTANCE;
+  vec3 reflected_screen = screen_position(reflected_position);
+  vec2 edge = min(reflected_screen.xy, 1.0 - reflected_screen.xy) / REFLECTION_EDGE;
+  float in_view = reflected_screen.z > 0.0 ? clamp(min(edge.x, edge.y), 0.0, 1.0) : 0.0;
+  vec3 sky = SKY_COLOR * brightness;
+  // The sky drawn behind the scene isn't in it, where the scene's alpha is 0.
+  vec4 scene = texture(u_scene, reflected_screen.xy);
+  vec3 reflected = mix(sky, scene.rgb, in_view * scene.a);
+
+  vec3 half_vector = normalize(to_light + to_camera);
+  float specular = pow(max(0.0, dot(normal, half_vector)), SHININESS);
+  color = vec4(mix(refracted, reflected, fresnel) + vec3(specular), 1.0);
+}
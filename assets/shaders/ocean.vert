@@ -0,0 +1,16 @@
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+
+in vec3 position;
+in vec3 normal;
+
+out vec3 v_normal;
+out vec3 v_pos;
+
+void main()
+{
+  v_pos = position;
+  v_normal = normal;
+  gl_Position = perspective * view * model * vec4(position, 1.0);
+}
@@ -0,0 +1,14 @@
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+
+in vec3 position;
+in vec4 color;
+
+out vec4 v_color;
+
+void main()
+{
+  gl_Position = perspective * view * model * vec4(position, 1.0);
+  v_color = color;
+}
@@ -1,12 +1,87 @@
 #version 140
 
-uniform vec3 u_light;
+// The camera, in the same frame as `v_pos`, and the index of the body the
+// surface is on for the lighting pass, see `RenderGraph`.
+uniform vec3 u_camera;
+uniform float u_body;
+
+// The layers of `TerrainMaterial` and where they're splatted, see
+// `Splatting`. Altitudes are above `u_base_radius`, slopes in degrees and
+// `u_grass` is 0 where no grass grows.
+uniform sampler2DArray u_materials;
+uniform sampler2DArray u_material_normals;
+uniform float u_base_radius;
+uniform float u_grass;
+uniform float u_grass_line;
+uniform float u_max_grass_slope;
+
+// A value of `ShadingMode`, and the level of the octree and the uid of the
+// chunk drawn, for the debug shadings.
+uniform int u_shading;
+uniform float u_level;
+uniform float u_chunk;
 
 in vec3 v_normal;
+in vec3 v_model_normal;
 in vec3 v_pos;
 in vec3 v_bary_coord;
-
-out vec4 color;
+in float v_material;
+// The layer of rock, negative if it isn't layered.
+in float v_stratum;
+// How much of the sky the surface sees, 1 in the open.
+in float v_occlusion;
+
+out vec4 albedo;
+out vec4 normal;
+out vec4 position;
+
+// Values of `Material` in math.rs.
+const float MATERIAL_LAVA = 1.0;
+const float MATERIAL_SNOW = 2.0;
+const float MATERIAL_SAND = 3.0;
+
+// Values of `ShadingMode` in solar_system.rs.
+const int SHADING_WIREFRAME = 1;
+const int SHADING_NORMALS = 2;
+const int SHADING_LOD_LEVEL = 3;
+const int SHADING_CHUNK_ID = 4;
+// The levels the hues of `SHADING_LOD_LEVEL` go around the color wheel in.
+const float LEVEL_HUES = 16.0;
+
+// The least the sun lights the terrain, in the alpha of its albedo.
+const float AMBIENT = 0.02;
+
+// Sandstone, clay and shale of stratified rock.
+const int NUM_STRATA_COLORS = 5;
+const vec3 STRATA_COLORS[NUM_STRATA_COLORS] = vec3[NUM_STRATA_COLORS](
+  vec3(0.83, 0.25, 0.07),
+  vec3(0.91, 0.55, 0.32),
+  vec3(0.62, 0.22, 0.12),
+  vec3(0.95, 0.82, 0.62),
+  vec3(0.45, 0.30, 0.24)
+);
+// Fraction of a stratum blending into the next one.
+const float STRATA_BLEND = 0.15;
+
+// Layers of `TerrainMaterial` in splatting.rs.
+const float LAYER_GRASS = 0.0;
+const float LAYER_ROCK = 1.0;
+const float LAYER_SAND = 2.0;
+const float LAYER_SNOW = 3.0;
+
+// World units across a tile of the materials, how much the grain of the rock
+// brightens and darkens the strata, and how sharply the projections of the
+// tiles blend on slopes.
+const float MATERIAL_SCALE = 6.0;
+const float ROCK_GRAIN_CONTRAST = 0.6;
+const float TRIPLANAR_SHARPNESS = 4.0;
+
+// The slopes, in degrees, past which snow and sand slide off the rock, and
+// over how many degrees and world units the materials blend into each other.
+const float MAX_SNOW_SLOPE = 50.0;
+const float MAX_SAND_SLOPE = 35.0;
+const float SPLAT_SLOPE_BLEND = 10.0;
+const float SPLAT_ALTITUDE_BLEND = 20.0;
 
 //
 //  Wombat
@@ -235,29 +310,144 @@ float edgeFactor(){
 void main2() {
   // float brightness = max(0.2, dot(normalize(v_normal),
   //                                 normalize(v_pos - u_light))) * edgeFactor();
-  color.rgb = mix(vec3(0.01), vec3(0.5), edgeFactor());
+  albedo.rgb = mix(vec3(0.01), vec3(0.5), edgeFactor());
+}
+
+// Lava glows on its own, unlit, with a slowly varying crust. Its albedo's
+// alpha of 1 tells the lighting pass so.
+vec3 lava_color() {
+  float crust = 0.5 + 0.5 * SimplexPerlin3D(v_pos * 0.08);
+  return mix(vec3(1.0, 0.35, 0.05), vec3(0.25, 0.03, 0.01), crust * crust);
+}
+
+// The color of a stratum, picked by hashing its index so that the layers
+// don't repeat in an obvious order.
+vec3 stratum_color(float layer) {
+  float hash = fract(sin(layer * 12.9898) * 43758.5453);
+  return STRATA_COLORS[int(hash * float(NUM_STRATA_COLORS))];
+}
+
+vec3 strata_color() {
+  float layer = floor(v_stratum);
+  float blend = smoothstep(1.0 - STRATA_BLEND, 1.0, v_stratum - layer);
+  return mix(stratum_color(layer), stratum_color(layer + 1.0), blend);
+}
+
+// The weights of the projections along x, y and z on a surface with
+// `normal`, so each is used where it's least stretched.
+vec3 triplanar_weights(vec3 normal) {
+  vec3 weights = pow(abs(normalize(normal)), vec3(TRIPLANAR_SHARPNESS));
+  return weights / (weights.x + weights.y + weights.z);
+}
+
+// The `layer` of the materials projected along each axis and blended, so the
+// terrain needs no texture coordinates of its own.
+vec3 material_color(float layer) {
+  vec3 weights = triplanar_weights(v_model_normal);
+  vec3 p = v_pos / MATERIAL_SCALE;
+  return weights.x * texture(u_materials, vec3(p.yz, layer)).rgb +
+      weights.y * texture(u_materials, vec3(p.zx, layer)).rgb +
+      weights.z * texture(u_materials, vec3(p.xy, layer)).rgb;
+}
+
+// The normal of the surface out of the ground, perturbed by the normal map of
+// `layer` projected like its color. Each projection's normal is turned from
+// the frame of its texture to the body's and added to the surface's
+// ("whiteout" blending), so the bumps follow the surface on every slope.
+vec3 material_normal(float layer) {
+  vec3 up = -normalize(v_model_normal);
+  vec3 weights = triplanar_weights(up);
+  vec3 p = v_pos / MATERIAL_SCALE;
+  vec3 x = texture(u_material_normals, vec3(p.yz, layer)).xyz * 2.0 - 1.0;
+  vec3 y = texture(u_material_normals, vec3(p.zx, layer)).xyz * 2.0 - 1.0;
+  vec3 z = texture(u_material_normals, vec3(p.xy, layer)).xyz * 2.0 - 1.0;
+  x = vec3(x.xy + up.yz, abs(x.z) * up.x);
+  y = vec3(y.xy + up.zx, abs(y.z) * up.y);
+  z = vec3(z.xy + up.xy, abs(z.z) * up.z);
+  return normalize(weights.x * x.zxy + weights.y * y.yzx + weights.z * z.xyz);
+}
+
+// The rock, its strata if it's layered, with the grain of the rock texture.
+vec3 rock_color() {
+  vec3 color = v_stratum >= 0.0 ? strata_color() : vec3(0.83, 0.25, 0.07);
+  float grain = material_color(LAYER_ROCK).r;
+  return color * (1.0 + ROCK_GRAIN_CONTRAST * (grain - 0.5));
+}
+
+// 1 on slopes gentler than `max_slope`, fading to 0 over the blend.
+float below_slope(float slope, float max_slope) {
+  return 1.0 - smoothstep(max_slope - SPLAT_SLOPE_BLEND, max_slope, slope);
+}
+
+// How much of the grass, rock, sand and snow cover the surface. The material
+// of the field covers the rock where it's flat enough to lie on, the snow and
+// sand on the ground the field made them and the grass on the rock below the
+// grass line. The terrain's normals point into the ground.
+vec4 splat_weights() {
+  float slope = degrees(acos(clamp(
+      dot(-normalize(v_model_normal), normalize(v_pos)), -1.0, 1.0)));
+  if (abs(v_material - MATERIAL_SNOW) < 0.5) {
+    float snow = below_slope(slope, MAX_SNOW_SLOPE);
+    return vec4(0.0, 1.0 - snow, 0.0, snow);
+  } else if (abs(v_material - MATERIAL_SAND) < 0.5) {
+    float sand = below_slope(slope, MAX_SAND_SLOPE);
+    return vec4(0.0, 1.0 - sand, sand, 0.0);
+  }
+  float altitude = length(v_pos) - u_base_radius;
+  float grass = u_grass * below_slope(slope, u_max_grass_slope) *
+      (1.0 - smoothstep(u_grass_line - SPLAT_ALTITUDE_BLEND, u_grass_line, altitude));
+  return vec4(grass, 1.0 - grass, 0.0, 0.0);
+}
+
+// A saturated color of `hue`, in [0, 1] around the color wheel.
+vec3 hue_color(float hue) {
+  return clamp(abs(mod(hue * 6.0 + vec3(0.0, 4.0, 2.0), 6.0) - 3.0) - 1.0, 0.0, 1.0);
+}
+
+// Replaces the albedo written with that of the debug shading, if any. The
+// debug colors glow on their own, for the lighting not to change them.
+void debug_shading() {
+  if (u_shading == SHADING_WIREFRAME) {
+    albedo.rgb = mix(vec3(0.01), albedo.rgb, edgeFactor());
+  } else if (u_shading == SHADING_NORMALS) {
+    albedo = vec4(0.5 - 0.5 * normalize(normal.xyz), 1.0);
+  } else if (u_shading == SHADING_LOD_LEVEL) {
+    albedo = vec4(hue_color(fract(u_level / LEVEL_HUES)) * edgeFactor(), 1.0);
+  } else if (u_shading == SHADING_CHUNK_ID) {
+    // Consecutive chunks get hues far apart, by the golden ratio.
+    albedo = vec4(hue_color(fract(u_chunk * 0.618034)), 1.0);
+  }
 }
 
 void main() {
-  float brightness = max(0.02, dot(normalize(v_normal),
-                                   normalize(v_pos - u_light)));
-  // float s = (1.3 + sqrt(dot(v_pos, v_pos))) / 2.3;
-
-  // float x = max((sqrt(dot(v_pos, v_pos)) - 32.0) / 10.0, 1.0);
-  // vec3 regular_color = vec3(x * z, y, sqrt(x * y));
-  // vec3 dark_color = regular_color * 0.3;
-
-  // float x = (Cellular2D(vec2(2 * v_pos.x, 12 * v_pos.y)) + .3) / 1.3;
-  // float y = (Cellular2D(vec2(3 * v_pos.z, 8 * v_pos.y)) + .8) / 1.8;
-  // float z = (Cellular2D(vec2(10, 10 * v_pos.y * v_pos.x)) + .3) / 1.3;
-  // float x = pow((sqrt(dot(v_pos, v_pos)) / 28.0), 4);
-  // float x = 0.5;
-  // vec3 regular_color = vec3(x * z, y, x + y + z);
-  // vec3 dark_color = regular_color * 0.1;
-  vec3 regular_color = vec3(0.83, 0.25, 0.07);
-  vec3 dark_color = regular_color * 0.2;
-
-  // vec3 dark_color = vec3(0.5, 0.5, 0.5);
-  // vec3 regular_color = vec3(0.8, 0.8, 0.8);
-  color = vec4(mix(dark_color, regular_color, brightness), 1.0);
+  position = vec4(v_pos - u_camera, u_body);
+  if (abs(v_material - MATERIAL_LAVA) < 0.5) {
+    albedo = vec4(lava_color(), 1.0);
+    normal = vec4(normalize(v_model_normal), v_occlusion);
+    debug_shading();
+    return;
+  }
+  // Only the materials on the surface are sampled.
+  vec4 weights = splat_weights();
+  vec3 color = vec3(0.0);
+  vec3 up = vec3(0.0);
+  if (weights.x > 0.0) {
+    color += weights.x * material_color(LAYER_GRASS);
+    up += weights.x * material_normal(LAYER_GRASS);
+  }
+  if (weights.y > 0.0) {
+    color += weights.y * rock_color();
+    up += weights.y * material_normal(LAYER_ROCK);
+  }
+  if (weights.z > 0.0) {
+    color += weights.z * material_color(LAYER_SAND);
+    up += weights.z * material_normal(LAYER_SAND);
+  }
+  if (weights.w > 0.0) {
+    color += weights.w * material_color(LAYER_SNOW);
+    up += weights.w * material_normal(LAYER_SNOW);
+  }
+  albedo = vec4(color, AMBIENT);
+  normal = vec4(-normalize(up), v_occlusion);
+  debug_shading();
 }
@@ -8,16 +8,30 @@ uniform mat4 model;
 in vec3 position;
 in vec3 normal;
 in vec3 bary_coord;
+in float material;
+in float stratum;
+in float occlusion;
 
 out vec3 v_normal;
+// The position and normal in the body's frame, which is the world's moved
+// with the body, for the texture projections, so the textures stay put on
+// an orbiting body.
+out vec3 v_model_normal;
 out vec3 v_pos;
 out vec3 v_bary_coord;
+out float v_material;
+out float v_stratum;
+out float v_occlusion;
 
 void main() {
   mat4 modelview = view * model;
   v_pos = position.xyz;
   v_normal = transpose(inverse(mat3(modelview))) * normal;
+  v_model_normal = normal;
   v_bary_coord = bary_coord;
+  v_material = material;
+  v_stratum = stratum;
+  v_occlusion = occlusion;
   // v_normal = normal;
   gl_Position = perspective * modelview * vec4(position, 1.0);
 }
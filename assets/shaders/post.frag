@@ -0,0 +1,55 @@
+// The scene lit and with its transparent surfaces, in HDR and premultiplied
+// by how much of the sky behind it it covers, the luminance the eye is
+// adapted to, see adapt.frag, the levels of the bloom, see `RenderGraph`,
+// and the sun's light blurred into shafts, see shafts.frag, with how much of
+// it is added. The scene is drawn at u_scale texels across each pixel of the
+// frame, up to 4, which are averaged into it.
+uniform sampler2D u_scene;
+uniform sampler2D u_adapted;
+uniform sampler2D u_bloom0;
+uniform sampler2D u_bloom1;
+uniform sampler2D u_bloom2;
+uniform sampler2D u_bloom3;
+uniform sampler2D u_shafts;
+uniform float u_shaft_strength;
+uniform int u_scale;
+
+out vec4 color;
+
+// The brightness the adapted luminance is exposed to, and the range of the
+// exposure, so that neither the night nor the sun are seen as daylight.
+const float EXPOSURE_KEY = 0.4;
+const float MIN_EXPOSURE = 0.25;
+const float MAX_EXPOSURE = 8.0;
+
+// How much of the blurred light is added to the scene, the wider levels
+// spreading it further.
+const float BLOOM_STRENGTH = 0.15;
+
+// Narkowicz's fit of the ACES filmic curve, compressing the highlights
+// rather than clipping them.
+vec3 aces(vec3 x) {
+  return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main()
+{
+  // Averaged premultiplied, the edges of the surfaces partly covering the sky
+  // behind them.
+  ivec2 texel = ivec2(gl_FragCoord.xy) * u_scale;
+  vec4 scene = vec4(0.0);
+  for (int y = 0; y < u_scale; y++) {
+    for (int x = 0; x < u_scale; x++) {
+      scene += texelFetch(u_scene, texel + ivec2(x, y), 0);
+    }
+  }
+  scene /= float(u_scale * u_scale);
+  vec2 uv = gl_FragCoord.xy * float(u_scale) / vec2(textureSize(u_scene, 0));
+  // The bloom spills over the sky too, which it doesn't hide.
+  scene.rgb += BLOOM_STRENGTH * (texture(u_bloom0, uv).rgb + texture(u_bloom1, uv).rgb +
+                                 texture(u_bloom2, uv).rgb + texture(u_bloom3, uv).rgb);
+  scene.rgb += u_shaft_strength * texture(u_shafts, uv).rgb;
+  float adapted = texelFetch(u_adapted, ivec2(0), 0).r;
+  float exposure = clamp(EXPOSURE_KEY / adapted, MIN_EXPOSURE, MAX_EXPOSURE);
+  color = vec4(aces(scene.rgb * exposure), scene.a);
+}
@@ -0,0 +1,21 @@
+uniform vec3 u_color;
+// How much light reaches the prefab facing away from the sun.
+uniform float u_ambient;
+// As in planet.frag, `v_pos` being placed on the body by the instance's
+// transform.
+uniform vec3 u_camera;
+uniform float u_body;
+
+in vec3 v_normal;
+in vec3 v_pos;
+
+out vec4 albedo;
+out vec4 normal;
+out vec4 position;
+
+void main()
+{
+  albedo = vec4(u_color, u_ambient);
+  normal = vec4(normalize(v_normal), 1.0);
+  position = vec4(v_pos - u_camera, u_body);
+}
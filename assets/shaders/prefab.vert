@@ -0,0 +1,20 @@
+uniform mat4 perspective;
+uniform mat4 view;
+uniform mat4 model;
+
+in vec3 position;
+in vec3 normal;
+// Places the prefab's mesh on the body, see `prefab::instance_transform`.
+in mat4 transform;
+
+out vec3 v_normal;
+out vec3 v_pos;
+
+void main()
+{
+  vec4 body_position = transform * vec4(position, 1.0);
+  v_pos = body_position.xyz;
+  // Prefabs are scaled uniformly, so the normal needs no inverse transpose.
+  v_normal = mat3(transform) * normal;
+  gl_Position = perspective * view * model * body_position;
+}
@@ -0,0 +1,7 @@
+// A corner of a quad over the whole screen.
+in vec2 position;
+
+void main()
+{
+  gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,4 @@
+// Only the depth is written.
+void main()
+{
+}
@@ -0,0 +1,8 @@
+uniform mat4 u_light_matrix;
+
+in vec3 position;
+
+void main()
+{
+  gl_Position = u_light_matrix * vec4(position, 1.0);
+}
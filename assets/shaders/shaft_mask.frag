@@ -0,0 +1,25 @@
+// The g-buffer's positions, whose w is 0 for the sky, and the scene, see
+// post.frag, both at twice the size of the mask. The sun is in [0, 1] across
+// the screen, see `LightShafts`, and the aspect is the screen's width over
+// its height.
+uniform sampler2D u_position;
+uniform sampler2D u_scene;
+uniform vec2 u_sun;
+uniform float u_aspect;
+
+out vec4 color;
+
+// How far from the sun, as a fraction of the screen's height, the sky's light
+// shines through in shafts.
+const float SHAFT_RADIUS = 0.35;
+
+void main() {
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(u_scene, 0) / 2);
+  // Only the sky shines, so the mountains and clouds in front of the sun
+  // cut the shafts out of its light.
+  float sky = 1.0 - texture(u_position, uv).w;
+  vec2 offset = (uv - u_sun) * vec2(u_aspect, 1.0);
+  float falloff = 1.0 - smoothstep(0.0, SHAFT_RADIUS, length(offset));
+  vec4 scene = texture(u_scene, uv);
+  color = vec4(scene.rgb * (sky * falloff * falloff), 1.0);
+}
@@ -0,0 +1,27 @@
+// The light of the sky around the sun, see shaft_mask.frag, and the sun in
+// [0, 1] across the screen.
+uniform sampler2D u_mask;
+uniform vec2 u_sun;
+
+out vec4 color;
+
+// The samples of the radial blur, how far of the way to the sun they reach
+// and how much each further one weighs less than the one before.
+const int SHAFT_SAMPLES = 64;
+const float SHAFT_DENSITY = 0.9;
+const float SHAFT_DECAY = 0.96;
+
+void main() {
+  vec2 uv = gl_FragCoord.xy / vec2(textureSize(u_mask, 0));
+  vec2 delta = (u_sun - uv) * (SHAFT_DENSITY / float(SHAFT_SAMPLES));
+  vec3 shafts = vec3(0.0);
+  float weight = 1.0;
+  float total = 0.0;
+  for (int i = 0; i < SHAFT_SAMPLES; ++i) {
+    shafts += texture(u_mask, uv).rgb * weight;
+    total += weight;
+    weight *= SHAFT_DECAY;
+    uv += delta;
+  }
+  color = vec4(shafts / total, 1.0);
+}
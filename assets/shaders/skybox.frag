@@ -2,9 +2,14 @@ in vec3 tex_coords;
 out vec4 color;
 
 uniform samplerCube skybox;
+// Whether the sky is in HDR, tonemapped as it's drawn straight on the frame.
+uniform bool u_hdr;
 
 void main()
 {
   color = texture(skybox, tex_coords) * 0.2;
+  if (u_hdr) {
+    color.rgb /= 1.0 + color.rgb;
+  }
   // color.rgb = normalize(tex_coords);
 }
@@ -0,0 +1,12 @@
+// The distances along the ray from `origin` in `direction` to where it enters
+// and leaves the sphere of `radius`, the first greater if it misses.
+vec2 intersect_sphere(vec3 origin, vec3 direction, float radius) {
+  float b = dot(origin, direction);
+  float c = dot(origin, origin) - radius * radius;
+  float discriminant = b * b - c;
+  if (discriminant < 0.0) {
+    return vec2(1.0, -1.0);
+  }
+  float root = sqrt(discriminant);
+  return vec2(-b - root, -b + root);
+}
@@ -0,0 +1,10 @@
+in vec2 v_tex_coords;
+in vec4 v_color;
+out vec4 color;
+
+uniform sampler2D font;
+
+void main()
+{
+  color = vec4(v_color.rgb, v_color.a * texture(font, v_tex_coords).a);
+}
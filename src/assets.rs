@@ -0,0 +1,70 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use errors::{ErrorKind, Result};
+
+/// Resolves asset names (shaders, textures, heightmaps) to paths inside an
+/// assets directory.
+#[derive(Clone, Debug)]
+pub struct Assets {
+    root: PathBuf,
+}
+
+impl Assets {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Assets { root: root.into() }
+    }
+
+    /// Looks for an `assets` directory next to the executable, then two levels
+    /// up from it (i.e. `target/<profile>/../../assets` in a cargo checkout),
+    /// and finally in the current working directory.
+    pub fn from_default_location() -> Self {
+        let mut candidates = vec![];
+        if let Ok(exe) = env::current_exe() {
+            if let Some(exe_dir) = exe.parent() {
+                candidates.push(exe_dir.join(ASSETS_DIR_NAME));
+                candidates.push(exe_dir.join("..").join("..").join(ASSETS_DIR_NAME));
+            }
+        }
+        candidates.push(PathBuf::from(ASSETS_DIR_NAME));
+
+        let root = candidates
+            .into_iter()
+            .find(|candidate| candidate.is_dir())
+            .unwrap_or_else(|| PathBuf::from(ASSETS_DIR_NAME));
+        info!("Using assets directory {:?}", root);
+        Assets::new(root)
+    }
+
+    /// Returns the path of the asset `name` without checking it exists.
+    pub fn path<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Returns the path of the asset `name`, or an error if there's no such file.
+    pub fn resolve<P: AsRef<Path>>(&self, name: P) -> Result<PathBuf> {
+        let path = self.path(name.as_ref());
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(self.not_found(name.as_ref()))
+        }
+    }
+
+    /// Like `resolve`, but the asset may also be a directory of files.
+    pub fn resolve_entry<P: AsRef<Path>>(&self, name: P) -> Result<PathBuf> {
+        let path = self.path(name.as_ref());
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(self.not_found(name.as_ref()))
+        }
+    }
+
+    fn not_found(&self, name: &Path) -> ::errors::Error {
+        ErrorKind::AssetNotFound(name.display().to_string(), self.root.display().to_string())
+            .into()
+    }
+}
+
+const ASSETS_DIR_NAME: &'static str = "assets";
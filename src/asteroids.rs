@@ -0,0 +1,470 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+use chan::{self, Receiver, Sender};
+use glium::{Depth, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest};
+use glium::index::{NoIndices, PrimitiveType};
+use nalgebra::{Isometry3, Norm, Point3, ToHomogeneous, Vector3};
+use noise::Seed;
+use num::Zero;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use threadpool::ThreadPool;
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use fractal::{Fractal, FractalNoise, NoiseBasis};
+use gfx::{marching_cubes, BarycentricVertex, Mesh, Window};
+use gfx::mesh::PlainVertex;
+use gfx::splatting::{Splatting, TerrainTextures};
+use math::{CpuScalar, GpuScalar, Matrix4f, ScalarField3, Vec3f};
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsteroidBeltSpec {
+    /// 0 for no belt.
+    pub num_asteroids: usize,
+    /// The belt is a ring in the xz plane of the main planet, between these
+    /// distances from its centre.
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    /// Asteroids are spread up to half of this above and below the plane.
+    pub thickness: f32,
+    /// The largest radii of the asteroids are uniform between these.
+    pub min_size: f32,
+    pub max_size: f32,
+    /// Height of the ridges, as a fraction of an asteroid's size.
+    pub roughness: f32,
+    /// Only this many of the asteroids nearest to the camera are meshed, the
+    /// others are drawn as impostors.
+    pub num_meshed: usize,
+}
+
+impl Default for AsteroidBeltSpec {
+    fn default() -> Self {
+        AsteroidBeltSpec {
+            num_asteroids: 0,
+            inner_radius: 7000.0,
+            outer_radius: 9500.0,
+            thickness: 400.0,
+            min_size: 8.0,
+            max_size: 60.0,
+            roughness: 0.25,
+            num_meshed: 8,
+        }
+    }
+}
+
+/// One of the asteroids of a belt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Asteroid {
+    pub seed: u32,
+    /// Relative to the centre of the belt.
+    pub position: Vec3f,
+    /// Of the ellipsoid the asteroid is carved from, the largest first.
+    pub semi_axes: Vec3f,
+}
+
+impl Asteroid {
+    /// Places `spec.num_asteroids` asteroids uniformly over the area of the
+    /// ring, denser towards its middle plane, the same ones for a given seed.
+    pub fn scatter_belt(seed: u32, spec: &AsteroidBeltSpec) -> Vec<Asteroid> {
+        let mut rng = XorShiftRng::from_seed([seed, 0x1b87_3593, 0xcc9e_2d51, 0xe654_6b64]);
+        let inner_squared = spec.inner_radius * spec.inner_radius;
+        let outer_squared = (spec.outer_radius * spec.outer_radius).max(inner_squared + 1.0);
+        let min_size = spec.min_size.max(1e-3);
+        let max_size = spec.max_size.max(min_size + 1e-3);
+        (0..spec.num_asteroids)
+            .map(|_| {
+                let distance = rng.gen_range(inner_squared, outer_squared).sqrt();
+                let angle: f32 = rng.gen_range(0.0, 2.0 * PI);
+                // The sum of two uniforms, a triangular distribution.
+                let height = (rng.gen_range(-0.25, 0.25) + rng.gen_range(-0.25, 0.25)) *
+                    spec.thickness;
+                let size = rng.gen_range(min_size, max_size);
+                Asteroid {
+                    seed: rng.gen(),
+                    position: Vec3f::new(distance * angle.cos(), height, distance * angle.sin()),
+                    semi_axes: Vec3f::new(
+                        size,
+                        size * rng.gen_range(0.5, 1.0),
+                        size * rng.gen_range(0.3, 0.8),
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// The radius of a sphere around the asteroid's centre containing it.
+    pub fn bounding_radius(&self, roughness: f32) -> f32 {
+        self.semi_axes[0] * (1.0 + roughness)
+    }
+}
+
+/// An asteroid centred at the origin: an ellipsoid whose surface is pushed
+/// in and out by ridged noise.
+pub struct AsteroidField {
+    semi_axes: Vec3f,
+    ridge_height: f32,
+    noise: FractalNoise,
+    seed: Seed,
+}
+
+impl AsteroidField {
+    pub fn new(asteroid: &Asteroid, roughness: f32) -> Self {
+        AsteroidField {
+            semi_axes: asteroid.semi_axes,
+            ridge_height: asteroid.semi_axes[0] * roughness,
+            noise: FractalNoise {
+                basis: NoiseBasis::Simplex,
+                fractal: Fractal::Ridged,
+                num_octaves: 4,
+                persistence: 0.5,
+                wavelength: 0.6,
+                lacunarity: 2.0,
+            },
+            seed: Seed::new(asteroid.seed),
+        }
+    }
+}
+
+impl ScalarField3 for AsteroidField {
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let axes = &self.semi_axes;
+        let scaled = Vector3::new(
+            position[0] / axes[0],
+            position[1] / axes[1],
+            position[2] / axes[2],
+        );
+        let scaled_norm = scaled.norm();
+        if scaled_norm < 1e-6 {
+            return -axes[2];
+        }
+        // A bound on the distance to the ellipsoid, exact on its surface.
+        let gradient_norm =
+            Vector3::new(scaled[0] / axes[0], scaled[1] / axes[1], scaled[2] / axes[2]).norm();
+        let ellipsoid = scaled_norm * (scaled_norm - 1.0) / gradient_norm;
+
+        let direction = scaled / scaled_norm;
+        let ridges = self.noise.apply_octaves(
+            &self.seed,
+            &[direction[0], direction[1], direction[2]],
+            self.noise.num_octaves,
+        );
+        ellipsoid - self.ridge_height * ridges
+    }
+}
+
+/// Draws an asteroid belt around the main planet. The asteroids nearest to
+/// the camera are meshed on the thread pool and drawn like terrain, the rest
+/// are billboards shaded like spheres.
+pub struct AsteroidBeltRenderer<'a> {
+    spec: AsteroidBeltSpec,
+    asteroids: Vec<Asteroid>,
+    thread_pool: &'a ThreadPool,
+    mesh_send: Sender<(usize, Mesh<BarycentricVertex>)>,
+    mesh_recv: Receiver<(usize, Mesh<BarycentricVertex>)>,
+    meshes: HashMap<usize, AsteroidMesh>,
+    pending: HashSet<usize>,
+    impostor_program: Program,
+    impostor_parameters: DrawParameters<'static>,
+    quad: VertexBuffer<PlainVertex>,
+}
+
+impl<'a> AsteroidBeltRenderer<'a> {
+    pub fn new(
+        window: &Window,
+        assets: &Assets,
+        thread_pool: &'a ThreadPool,
+        seed: u32,
+        spec: &AsteroidBeltSpec,
+    ) -> Result<Self> {
+        let impostor_program = try!(window.program(
+            try!(assets.resolve(IMPOSTOR_VERTEX_SHADER)),
+            try!(assets.resolve(IMPOSTOR_FRAGMENT_SHADER)),
+        ));
+        let corners: Vec<_> = QUAD_CORNERS.iter().map(PlainVertex::from).collect();
+        let quad = try!(
+            VertexBuffer::new(window.facade(), &corners)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        // Billboards face the camera, whichever way round their corners are.
+        let impostor_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling: BackfaceCullingMode::CullingDisabled,
+            ..Default::default()
+        };
+        let (mesh_send, mesh_recv) = chan::async();
+        Ok(AsteroidBeltRenderer {
+            spec: spec.clone(),
+            asteroids: Asteroid::scatter_belt(seed, spec),
+            thread_pool: thread_pool,
+            mesh_send: mesh_send,
+            mesh_recv: mesh_recv,
+            meshes: HashMap::new(),
+            pending: HashSet::new(),
+            impostor_program: impostor_program,
+            impostor_parameters: impostor_parameters,
+            quad: quad,
+        })
+    }
+
+    /// Draws the belt, centred at `centre`, into the g-buffer as the body
+    /// with index `body`. The meshed asteroids are drawn with
+    /// `terrain_program`, given the uniforms of the terrain shaders.
+    pub fn render<S: Surface>(
+        &mut self,
+        window: &Window,
+        gbuffer: &mut S,
+        terrain_program: &Program,
+        draw_parameters: &DrawParameters,
+        terrain_textures: &TerrainTextures,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        centre: &Vec3f,
+        eye: &Vec3f,
+        body: f32,
+    ) -> Result<()> {
+        let _span = trace::span("draw_asteroids");
+        try!(self.receive_meshes(window));
+        self.remesh_nearest(&(*eye - *centre));
+
+        let splatting = Splatting::barren();
+
+        for (&index, mesh) in self.meshes.iter() {
+            let position = *centre + self.asteroids[index].position;
+            let model = Matrix4f::from(Isometry3::new(*position, Vector3::zero()).to_homogeneous());
+            let local_eye = *eye - position;
+            let uniforms =
+                uniform! {
+                perspective: perspective,
+                model: &model,
+                view: view,
+                u_camera: &local_eye,
+                u_body: body,
+                u_materials: terrain_textures.sampled(),
+                u_material_normals: terrain_textures.sampled_normals(),
+                u_base_radius: splatting.base_radius,
+                u_grass: 0.0f32,
+                u_grass_line: splatting.grass_line,
+                u_max_grass_slope: splatting.max_grass_slope,
+                // The debug shadings are of the chunks of the bodies.
+                u_shading: 0i32,
+            };
+            try!(
+                gbuffer
+                    .draw(
+                        &mesh.vertex_buffer,
+                        &mesh.index_buffer,
+                        terrain_program,
+                        &uniforms,
+                        draw_parameters,
+                    )
+                    .chain_err(|| "Could not render an asteroid.")
+            );
+        }
+
+        let instances: Vec<_> = self.asteroids
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| !self.meshes.contains_key(&index))
+            .map(|(_, asteroid)| {
+                ImpostorInstance {
+                    centre: *centre + asteroid.position,
+                    size: asteroid.semi_axes[0],
+                }
+            })
+            .collect();
+        if instances.is_empty() {
+            return Ok(());
+        }
+        let instances = try!(
+            VertexBuffer::new(window.facade(), &instances)
+                .chain_err(|| "Cannot create instance buffer.")
+        );
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            u_camera: eye,
+            u_body: body,
+        };
+        let per_instance = match instances.per_instance() {
+            Ok(per_instance) => per_instance,
+            Err(_) => return Err("Instancing is not supported.".into()),
+        };
+        gbuffer
+            .draw(
+                (&self.quad, per_instance),
+                &NoIndices(PrimitiveType::TriangleStrip),
+                &self.impostor_program,
+                &uniforms,
+                &DrawParameters {
+                    time_elapsed_query: draw_parameters.time_elapsed_query,
+                    ..self.impostor_parameters.clone()
+                },
+            )
+            .chain_err(|| "Could not render the asteroid impostors.")
+    }
+
+    fn receive_meshes(&mut self, window: &Window) -> Result<()> {
+        let AsteroidBeltRenderer {
+            ref mesh_recv,
+            ref mut meshes,
+            ref mut pending,
+            ..
+        } = *self;
+        while let Some((index, mesh)) = (|| {
+            chan_select! {
+                default => { return None; },
+                mesh_recv.recv() -> message => { return message; },
+            }
+        })()
+        {
+            // Asteroids which went out of range while being meshed are
+            // dropped, they are impostors again.
+            if pending.remove(&index) {
+                meshes.insert(index, try!(AsteroidMesh::new(window, &mesh)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees the meshes of the asteroids which aren't among the nearest to
+    /// `eye` anymore and submits the ones which just became so.
+    fn remesh_nearest(&mut self, eye: &Vec3f) {
+        let mut by_distance: Vec<(usize, f32)> = self.asteroids
+            .iter()
+            .enumerate()
+            .map(|(index, asteroid)| (index, (asteroid.position - *eye).norm()))
+            .collect();
+        by_distance.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let nearest: HashSet<usize> = by_distance
+            .iter()
+            .take(self.spec.num_meshed)
+            .map(|&(index, _)| index)
+            .collect();
+
+        let far: Vec<usize> = self.meshes
+            .keys()
+            .chain(self.pending.iter())
+            .filter(|index| !nearest.contains(index))
+            .cloned()
+            .collect();
+        for index in far.into_iter() {
+            self.meshes.remove(&index);
+            self.pending.remove(&index);
+        }
+        for index in nearest.into_iter() {
+            if !self.meshes.contains_key(&index) && self.pending.insert(index) {
+                self.submit(index);
+            }
+        }
+    }
+
+    fn submit(&self, index: usize) {
+        let asteroid = self.asteroids[index];
+        let roughness = self.spec.roughness;
+        let sender = self.mesh_send.clone();
+        self.thread_pool.execute(move || {
+            let _span = trace::span("mesh_asteroid");
+            let field = AsteroidField::new(&asteroid, roughness);
+            let radius = asteroid.bounding_radius(roughness);
+            let step = 2.0 * radius / ASTEROID_MESH_STEPS;
+            let extent = Vec3f::new(radius + step, radius + step, radius + step);
+            let mesh = marching_cubes(&field, &(Vec3f::zero() - extent), &extent, step, 0.0);
+            sender.send((index, mesh.with_barycentric_coordinates()));
+        });
+    }
+}
+
+struct AsteroidMesh {
+    vertex_buffer: VertexBuffer<BarycentricVertex>,
+    index_buffer: IndexBuffer<u32>,
+}
+
+impl AsteroidMesh {
+    fn new(window: &Window, mesh: &Mesh<BarycentricVertex>) -> Result<Self> {
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &mesh.vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer =
+            try!(
+                IndexBuffer::new(window.facade(), PrimitiveType::TrianglesList, &mesh.indices)
+                    .chain_err(|| "Cannot create index buffer.")
+            );
+        Ok(AsteroidMesh {
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ImpostorInstance {
+    centre: Vec3f,
+    size: GpuScalar,
+}
+
+implement_vertex!(ImpostorInstance, centre, size);
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+    use num::Zero;
+
+    use gfx::marching_cubes;
+    use math::{ScalarField3, Vec3f};
+    use super::{Asteroid, AsteroidBeltSpec, AsteroidField};
+
+    #[test]
+    fn test_belt_asteroids() {
+        let spec = AsteroidBeltSpec {
+            num_asteroids: 200,
+            ..Default::default()
+        };
+        let asteroids = Asteroid::scatter_belt(7, &spec);
+        assert_eq!(asteroids, Asteroid::scatter_belt(7, &spec));
+        for asteroid in asteroids.iter() {
+            let position = asteroid.position;
+            let distance = (position[0] * position[0] + position[2] * position[2]).sqrt();
+            assert!(spec.inner_radius <= distance && distance <= spec.outer_radius + 1e-2);
+            assert!(position[1].abs() <= spec.thickness / 2.0);
+
+            // The surface is within the bounding sphere and around the
+            // ellipsoid.
+            let field = AsteroidField::new(asteroid, spec.roughness);
+            let radius = asteroid.bounding_radius(spec.roughness);
+            assert!(field.value_at(&Point3::new(0.0, 0.0, 0.0)) < 0.0);
+            assert!(field.value_at(&Point3::new(radius * 1.01, 0.0, 0.0)) > 0.0);
+            assert!(field.value_at(&Point3::new(0.0, 0.0, -radius * 1.01)) > 0.0);
+            let on_axis = Point3::new(0.0, asteroid.semi_axes[1], 0.0);
+            let ridge_height = asteroid.semi_axes[0] * spec.roughness;
+            assert!(field.value_at(&on_axis).abs() <= ridge_height * 1.01);
+        }
+
+        let field = AsteroidField::new(&asteroids[0], spec.roughness);
+        let extent = asteroids[0].bounding_radius(spec.roughness) * 1.1;
+        let extent = Vec3f::new(extent, extent, extent);
+        let mesh = marching_cubes(&field, &(Vec3f::zero() - extent), &extent, extent[0] / 8.0, 0.0);
+        assert!(!mesh.vertices.is_empty());
+    }
+}
+
+const IMPOSTOR_VERTEX_SHADER: &'static str = "shaders/impostor.vert";
+const IMPOSTOR_FRAGMENT_SHADER: &'static str = "shaders/impostor.frag";
+
+// A triangle strip covering [-1, 1]^2.
+const QUAD_CORNERS: [[GpuScalar; 3]; 4] = [
+    [-1.0, -1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, 1.0, 0.0],
+];
+// Cells along the side of the box an asteroid is meshed in.
+const ASTEROID_MESH_STEPS: f32 = 32.0;
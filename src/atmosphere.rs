@@ -0,0 +1,157 @@
+use glium::{Depth, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest, TimeElapsedQuery};
+use glium::index::PrimitiveType;
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::{Mesh, Vertex, Window};
+use gfx::render_graph::{premultiplied_blending, SPHERE_SHADER_LIBRARY};
+use math::{GpuScalar, Matrix4f, Vec3f};
+use planet::PlanetSpec;
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtmosphereSpec {
+    /// How far above the planet's base radius the air reaches, in world
+    /// units. No atmosphere if 0.
+    pub height: f32,
+    /// How bright the sunlight scattered by the air is.
+    pub sun_intensity: f32,
+    /// How much the air scatters, relative to that of the Earth, for thinner
+    /// or hazier skies. Rayleigh scattering turns the sky blue and sunsets
+    /// red, Mie scattering by aerosols makes the glow around the sun.
+    pub rayleigh_density: f32,
+    pub mie_density: f32,
+}
+
+impl Default for AtmosphereSpec {
+    fn default() -> Self {
+        AtmosphereSpec {
+            height: 400.0,
+            sun_intensity: 20.0,
+            rayleigh_density: 1.0,
+            mie_density: 1.0,
+        }
+    }
+}
+
+/// The light the air of a planet scatters towards the camera, drawn over
+/// everything else as a shell around it. The scattering is integrated along
+/// each view ray through the shell, down to the ground or the sea, so from
+/// space the planet has a halo and a haze over its surface, and from the
+/// ground the sky is lit by the sun. The nearest terrain hides the shell
+/// behind it and isn't hazed.
+pub struct AtmosphereRenderer<'a> {
+    draw_parameters: DrawParameters<'a>,
+    program: Program,
+    vertex_buffer: VertexBuffer<Vertex>,
+    index_buffer: IndexBuffer<u32>,
+    ground_radius: GpuScalar,
+    radius: GpuScalar,
+    spec: AtmosphereSpec,
+}
+
+impl<'a> AtmosphereRenderer<'a> {
+    /// The air of the planet with `planet` spec, which starts at the sea if
+    /// it has one.
+    pub fn new(
+        window: &Window,
+        assets: &Assets,
+        planet: &PlanetSpec,
+        spec: &AtmosphereSpec,
+    ) -> Result<Self> {
+        let program = try!(window.program_with_library(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(SPHERE_SHADER_LIBRARY)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let radius = planet.base_radius + spec.height;
+        // The faces of the sphere are within it, the shell is made a little
+        // larger so that it encloses all of the air the shader integrates.
+        let mesh = Mesh::icosphere(radius * SHELL_MARGIN, SPHERE_SUBDIVISIONS);
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &mesh.vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer = try!(
+            IndexBuffer::new(
+                window.facade(),
+                PrimitiveType::TrianglesList,
+                &mesh.indices,
+            ).chain_err(|| "Cannot create index buffer.")
+        );
+
+        // Both sides are drawn, the shader keeping the one nearest to the
+        // camera, from outside or inside the shell. The light it scatters is
+        // added to what's behind, which the air dims by its transmittance in
+        // the alpha, and the alpha adds up to how much of the sky behind the
+        // scene is hidden.
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: false,
+                ..Default::default()
+            },
+            blend: premultiplied_blending(),
+            backface_culling: BackfaceCullingMode::CullingDisabled,
+            ..Default::default()
+        };
+        Ok(AtmosphereRenderer {
+            draw_parameters: draw_parameters,
+            program: program,
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+            ground_radius: planet.sea_radius().unwrap_or(planet.base_radius),
+            radius: radius,
+            spec: spec.clone(),
+        })
+    }
+
+    /// The camera position is relative to the centre of the planet, and the
+    /// sun direction points towards the sun.
+    pub fn render<S: Surface>(
+        &self,
+        target: &mut S,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        model: &Matrix4f,
+        camera_position: &Vec3f,
+        sun_direction: &Vec3f,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_atmosphere");
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            model: model,
+            camera_position: camera_position,
+            u_sun_direction: sun_direction,
+            u_sun_intensity: self.spec.sun_intensity,
+            u_ground_radius: self.ground_radius,
+            u_atmosphere_radius: self.radius,
+            u_rayleigh_density: self.spec.rayleigh_density,
+            u_mie_density: self.spec.mie_density,
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        target
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            )
+            .chain_err(|| "Could not render the atmosphere.")
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/atmosphere.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/atmosphere.frag";
+
+// 5120 triangles, the shader computes the sphere exactly.
+const SPHERE_SUBDIVISIONS: u32 = 4;
+const SHELL_MARGIN: f32 = 1.01;
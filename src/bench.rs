@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant};
+
+use errors::Result;
+use gfx::{LodSpec, LodStats, NO_SEAMS};
+use gfx::lod::{chunk_to_mesh, surface_chunk_ids};
+use math::ScalarField3;
+
+/// Generates up to `num_chunks` surface chunks at each of `levels`, one at a
+/// time, and prints the meshing throughput. No window is created, so this
+/// measures the mesher and `value_at` only. The meshing times are counted
+/// like by the chunk pipeline, whose overall mean is printed last.
+pub fn run_benchmark<Field>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    levels: &[u8],
+    num_chunks: usize,
+) -> Result<()>
+where
+    Field: ScalarField3 + Sync,
+{
+    let num_chunks = num_chunks.max(1);
+    let mut stats = LodStats::default();
+    println!(
+        "{:>5} {:>7} {:>10} {:>12} {:>9} {:>9} {:>9} {:>9} {:>9}",
+        "level",
+        "chunks",
+        "vertices",
+        "vertices/s",
+        "mean ms",
+        "p50 ms",
+        "p90 ms",
+        "p99 ms",
+        "max ms"
+    );
+    for &level in levels.iter() {
+        let chunk_ids = surface_chunk_ids(scalar_field, lod_spec, level);
+        if chunk_ids.is_empty() {
+            warn!("No surface chunks at level {}, skipping.", level);
+            continue;
+        }
+
+        // Spread the sampled chunks over the whole surface rather than taking
+        // the first ones, which all come from the same corner of the octree.
+        let stride = (chunk_ids.len() / num_chunks).max(1);
+        let mut chunk_times = vec![];
+        let mut num_vertices = 0;
+        for index in 0..num_chunks.min(chunk_ids.len()) {
+            let chunk_id = &chunk_ids[index * stride];
+            let time = Instant::now();
+            let mesh = try!(chunk_to_mesh(
+                scalar_field,
+                lod_spec,
+                chunk_id,
+                lod_spec.mesher(scalar_field, chunk_id),
+                &NO_SEAMS,
+                true,
+            ));
+            let elapsed = time.elapsed();
+            stats.chunks_generated += 1;
+            stats.record_meshing(level, elapsed);
+            chunk_times.push(duration_to_secs(elapsed));
+            num_vertices += mesh.vertices.len();
+        }
+        chunk_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let total_time: f64 = chunk_times.iter().sum();
+        println!(
+            "{:>5} {:>7} {:>10} {:>12.0} {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+            level,
+            chunk_times.len(),
+            num_vertices,
+            num_vertices as f64 / total_time,
+            stats.mean_meshing_time(level).map_or(0.0, duration_to_secs) * 1e3,
+            percentile(&chunk_times, 0.5) * 1e3,
+            percentile(&chunk_times, 0.9) * 1e3,
+            percentile(&chunk_times, 0.99) * 1e3,
+            chunk_times[chunk_times.len() - 1] * 1e3
+        );
+    }
+    if let Some(mean) = stats.overall_mean_meshing_time() {
+        println!(
+            "{} chunks meshed in {:.2} ms each on average.",
+            stats.chunks_generated,
+            duration_to_secs(mean) * 1e3
+        );
+    }
+    Ok(())
+}
+
+#[inline]
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice.
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
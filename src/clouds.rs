@@ -0,0 +1,124 @@
+use glium::{DrawParameters, Program, Surface};
+use glium::draw_parameters::TimeElapsedQuery;
+use glium::texture::Texture2d;
+
+use assets::Assets;
+use errors::Result;
+use gfx::Window;
+use gfx::render_graph::{premultiplied_blending, screen_sampler, RenderGraph, QUAD_VERTEX_SHADER,
+                        SPHERE_SHADER_LIBRARY};
+use math::{GpuScalar, Matrix4f, Vec3f};
+use planet::PlanetSpec;
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloudSpec {
+    /// The altitudes above the planet's base radius the clouds are between,
+    /// in world units. No clouds if the top isn't above the bottom.
+    pub bottom: f32,
+    pub top: f32,
+    /// The fraction of the sky the clouds cover, from clear at 0 to overcast
+    /// at 1.
+    pub coverage: f32,
+    /// How much light the clouds scatter and absorb per world unit through
+    /// their thickest parts.
+    pub density: f32,
+    /// How fast the wind blows the clouds, in world units per second.
+    pub wind_speed: f32,
+}
+
+impl Default for CloudSpec {
+    fn default() -> Self {
+        CloudSpec {
+            bottom: 150.0,
+            top: 250.0,
+            coverage: 0.45,
+            density: 0.02,
+            wind_speed: 4.0,
+        }
+    }
+}
+
+impl CloudSpec {
+    pub fn enabled(&self) -> bool {
+        self.top > self.bottom && self.coverage > 0.0 && self.density > 0.0
+    }
+}
+
+/// A layer of clouds between two altitudes around a planet, raymarched
+/// through 3D noise by a quad over the screen in the transparent pass, lit
+/// by the sun through the cloud above each sample. The march stops at the
+/// surfaces in the g-buffer, so mountains poke through the clouds.
+pub struct CloudRenderer<'a> {
+    program: Program,
+    draw_parameters: DrawParameters<'a>,
+    bottom_radius: GpuScalar,
+    top_radius: GpuScalar,
+    spec: CloudSpec,
+}
+
+impl<'a> CloudRenderer<'a> {
+    pub fn new(
+        window: &Window,
+        assets: &Assets,
+        planet: &PlanetSpec,
+        spec: &CloudSpec,
+    ) -> Result<Self> {
+        let program = try!(window.program_with_library(
+            try!(assets.resolve(QUAD_VERTEX_SHADER)),
+            try!(assets.resolve(SPHERE_SHADER_LIBRARY)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        // Like the air, the light the clouds scatter is added to what's
+        // behind them, which they dim by their transmittance in the alpha.
+        let draw_parameters = DrawParameters {
+            blend: premultiplied_blending(),
+            ..Default::default()
+        };
+        Ok(CloudRenderer {
+            program: program,
+            draw_parameters: draw_parameters,
+            bottom_radius: planet.base_radius + spec.bottom,
+            top_radius: planet.base_radius + spec.top,
+            spec: spec.clone(),
+        })
+    }
+
+    /// The camera position is relative to the centre of the planet, and the
+    /// sun direction points towards the sun. `positions` are those of the
+    /// g-buffer, relative to the camera.
+    pub fn render<S: Surface>(
+        &self,
+        render_graph: &RenderGraph,
+        target: &mut S,
+        positions: &Texture2d,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        camera_position: &Vec3f,
+        sun_direction: &Vec3f,
+        time: f32,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_clouds");
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            u_position: screen_sampler(positions),
+            u_camera: camera_position,
+            u_sun_direction: sun_direction,
+            u_bottom_radius: self.bottom_radius,
+            u_top_radius: self.top_radius,
+            u_coverage: self.spec.coverage,
+            u_density: self.spec.density,
+            u_wind: self.spec.wind_speed * time,
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        render_graph.draw_quad_with(target, &self.program, &uniforms, &draw_parameters)
+    }
+}
+
+const FRAGMENT_SHADER: &'static str = "shaders/clouds.frag";
@@ -0,0 +1,836 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use toml::{Parser, Table, Value};
+
+use asteroids::AsteroidBeltSpec;
+use atmosphere::AtmosphereSpec;
+use clouds::CloudSpec;
+use erosion::ErosionSpec;
+use errors::{ChainErr, ErrorKind, Result};
+use fields::{SolidOperation, SolidShape, SolidSpec};
+use floating_islands::FloatingIslandsSpec;
+use flora::FloraSpec;
+use fractal::{Fractal, NoiseBasis};
+use gas_giant::GasGiantSpec;
+use game::ControlsSpec;
+use gfx::{FullscreenMode, KeyCode, LodSpec, SkyboxSpec, WindowSpec, key_code_from_name};
+use gfx::lens_flare::{FlareGhost, LensFlareSpec};
+use gfx::render_graph::supersampling_scale;
+use gfx::shadow_map::ShadowSpec;
+use gfx::skybox::FACE_NAMES;
+use math::{Mesher, Vec3f};
+use planet::{PhysicsSpec, PlanetSpec, SurfaceMapping};
+use solar_system::{BodySpec, FogSpec};
+use structures::StructureSpec;
+use utils::read_utf8_file;
+
+/// All tunable parameters of the app. Every value has a default, a config file
+/// only needs to list the values it overrides, e.g.
+///
+/// ```toml
+/// [planet]
+/// base_radius = 5000.0
+/// num_octaves = 6
+///
+/// [lod]
+/// max_level = 10
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub planet: PlanetSpec,
+    pub window: WindowSpec,
+    pub lod: LodSpec,
+    pub controls: ControlsSpec,
+    pub physics: PhysicsSpec,
+    pub erosion: ErosionSpec,
+    /// The bodies besides the main planet, none by default.
+    pub bodies: Vec<BodySpec>,
+    pub asteroids: AsteroidBeltSpec,
+    /// Around the main planet.
+    pub atmosphere: AtmosphereSpec,
+    /// Around the main planet, below the top of its air.
+    pub clouds: CloudSpec,
+    /// Cast by the sun on the terrain in view.
+    pub shadows: ShadowSpec,
+    /// Over the surfaces of the bodies, applied as the config reloads.
+    pub fog: FogSpec,
+    /// Behind everything, a restart applies it.
+    pub skybox: SkyboxSpec,
+    /// Of the sun, over the frame.
+    pub lens_flare: LensFlareSpec,
+    pub flora: FloraSpec,
+    pub structures: StructureSpec,
+    /// Used with `--field floating_islands`.
+    pub floating_islands: FloatingIslandsSpec,
+    /// Used instead of the planet with `--field gas_giant`.
+    pub gas_giant: GasGiantSpec,
+}
+
+impl Config {
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let contents = try!(read_utf8_file(path.as_ref()));
+        Config::from_str(&contents).chain_err(|| format!("Could not load config from {:?}", path))
+    }
+
+    pub fn from_str(contents: &str) -> Result<Self> {
+        let mut config = Config::default();
+        try!(config.merge_str(contents));
+        Ok(config)
+    }
+
+    /// Overrides the values in `self` with the ones present in `contents`.
+    pub fn merge_str(&mut self, contents: &str) -> Result<()> {
+        let mut parser = Parser::new(contents);
+        let root = match parser.parse() {
+            Some(root) => root,
+            None => {
+                let messages: Vec<String> = parser
+                    .errors
+                    .iter()
+                    .map(|error| {
+                        let (line, column) = parser.to_linecol(error.lo);
+                        format!("{}:{}: {}", line + 1, column + 1, error.desc)
+                    })
+                    .collect();
+                return Err(ErrorKind::ConfigParseError(messages.join("; ")).into());
+            }
+        };
+
+        let planet = try!(Section::new(&root, "planet"));
+        try!(read_planet(&planet, &mut self.planet));
+
+        // Bodies start out like the main planet, as read above.
+        let bodies = try!(Section::new(&root, "body"));
+        for (name, body) in try!(bodies.tables()) {
+            let index = match self.bodies.iter().position(|spec| spec.name == name) {
+                Some(index) => index,
+                None => {
+                    self.bodies.push(BodySpec {
+                        name: name.to_owned(),
+                        seed: None,
+                        position: Vec3f::new(0.0, 0.0, 0.0),
+                        orbit: None,
+                        planet: self.planet.clone(),
+                    });
+                    self.bodies.len() - 1
+                }
+            };
+            let spec = &mut self.bodies[index];
+            if let Some(seed) = try!(body.read_integer("seed", u32::max_value() as i64)) {
+                spec.seed = Some(seed as u32);
+            }
+            try!(body.read_vec3("position", &mut spec.position));
+            try!(read_planet(&body, &mut spec.planet));
+        }
+
+        // Moons start out from a cratered version of the main planet and
+        // orbit it, unless they are given another centre.
+        let moons = try!(Section::new(&root, "moon"));
+        for (name, moon) in try!(moons.tables()) {
+            let index = match self.bodies.iter().position(|spec| {
+                spec.name == name && spec.orbit.is_some()
+            }) {
+                Some(index) => index,
+                None => {
+                    self.bodies.push(BodySpec::moon(name, &self.planet));
+                    self.bodies.len() - 1
+                }
+            };
+            let spec = &mut self.bodies[index];
+            if let Some(seed) = try!(moon.read_integer("seed", u32::max_value() as i64)) {
+                spec.seed = Some(seed as u32);
+            }
+            try!(moon.read_vec3("position", &mut spec.position));
+            if let Some(ref mut orbit) = spec.orbit {
+                try!(moon.read_f32("orbit_radius", &mut orbit.semi_major_axis));
+                try!(moon.read_f32("eccentricity", &mut orbit.eccentricity));
+                try!(moon.read_f32("inclination", &mut orbit.inclination));
+                try!(moon.read_f32("period", &mut orbit.period));
+                try!(moon.read_f32("phase", &mut orbit.phase));
+                if orbit.eccentricity < 0.0 || orbit.eccentricity >= 1.0 {
+                    return Err(moon.invalid("eccentricity", "a number in [0, 1)").into());
+                }
+                if orbit.period <= 0.0 {
+                    return Err(moon.invalid("period", "a positive number").into());
+                }
+            }
+            try!(read_planet(&moon, &mut spec.planet));
+        }
+
+        let window = try!(Section::new(&root, "window"));
+        try!(window.read_u32("width", &mut self.window.width));
+        try!(window.read_u32("height", &mut self.window.height));
+        try!(window.read_fullscreen("fullscreen", &mut self.window.fullscreen));
+        try!(window.read_bool("vsync", &mut self.window.vsync));
+        if let Some(max_fps) = try!(window.read_integer("max_fps", u32::max_value() as i64)) {
+            self.window.max_fps = Some(max_fps as u32);
+        }
+        if let Some(monitor) = try!(window.read_integer("monitor", i64::max_value())) {
+            self.window.monitor = Some(monitor as usize);
+        }
+        if let Some(samples) = try!(window.read_integer("multisampling", 16)) {
+            if samples == 1 || (samples != 0 && (samples as u16).count_ones() != 1) {
+                return Err(window.invalid("multisampling", "one of 0, 2, 4, 8 or 16").into());
+            }
+            self.window.multisampling = samples as u16;
+        }
+        try!(window.read_u32("supersampling", &mut self.window.supersampling));
+        if supersampling_scale(self.window.supersampling).is_none() {
+            return Err(window.invalid("supersampling", "one of 1, 4, 9 or 16").into());
+        }
+
+        let lod = try!(Section::new(&root, "lod"));
+        try!(lod.read_u8("max_level", &mut self.lod.max_level));
+        try!(lod.read_f32("step", &mut self.lod.step));
+        try!(lod.read_f32("size", &mut self.lod.size));
+        try!(lod.read_f32("chunk_num_steps", &mut self.lod.chunk_num_steps));
+        try!(lod.read_f32("voxel_density", &mut self.lod.voxel_density));
+        try!(lod.read_usize(
+            "loaded_chunks_capacity",
+            &mut self.lod.loaded_chunks_capacity,
+        ));
+        try!(lod.read_usize(
+            "empty_chunks_capacity",
+            &mut self.lod.empty_chunks_capacity,
+        ));
+        try!(lod.read_usize(
+            "chunk_memory_budget_mb",
+            &mut self.lod.chunk_memory_budget_mb,
+        ));
+        try!(lod.read_u32("upload_budget_ms", &mut self.lod.upload_budget_ms));
+        try!(lod.read_usize(
+            "sample_cache_size",
+            &mut self.lod.sample_cache_size,
+        ));
+        try!(lod.read_u8("surface_nets_levels", &mut self.lod.surface_nets_levels));
+        try!(lod.read_f32("skirt_length", &mut self.lod.skirt_length));
+        try!(lod.read_f32("decimation_error", &mut self.lod.decimation_error));
+        try!(lod.read_f32(
+            "collision_decimation_error",
+            &mut self.lod.collision_decimation_error,
+        ));
+        try!(lod.read_usize("occlusion_rays", &mut self.lod.occlusion_rays));
+        try!(lod.read_f32(
+            "occlusion_distance",
+            &mut self.lod.occlusion_distance,
+        ));
+        try!(lod.read_f32("max_screen_error", &mut self.lod.max_screen_error));
+        try!(lod.read_f32("merge_screen_error", &mut self.lod.merge_screen_error));
+        try!(lod.read_u32("min_dwell_ms", &mut self.lod.min_dwell_ms));
+        try!(lod.read_f32("target_frame_ms", &mut self.lod.target_frame_ms));
+        try!(lod.read_f32("max_detail_reduction", &mut self.lod.max_detail_reduction));
+        try!(lod.read_usize("mesh_cache_size_mb", &mut self.lod.mesh_cache_size_mb));
+        try!(self.lod.validate());
+
+        let controls = try!(Section::new(&root, "controls"));
+        try!(controls.read_key("forward", &mut self.controls.forward));
+        try!(controls.read_key("backward", &mut self.controls.backward));
+        try!(controls.read_key("left", &mut self.controls.left));
+        try!(controls.read_key("right", &mut self.controls.right));
+        try!(controls.read_key("jump", &mut self.controls.jump));
+        try!(controls.read_key("roll_left", &mut self.controls.roll_left));
+        try!(controls.read_key("roll_right", &mut self.controls.roll_right));
+        try!(controls.read_key("torch", &mut self.controls.torch));
+        try!(controls.read_f32(
+            "movement_force",
+            &mut self.controls.movement_force,
+        ));
+        try!(controls.read_f32(
+            "mouse_sensitivity",
+            &mut self.controls.mouse_sensitivity,
+        ));
+        try!(controls.read_f32("look_step", &mut self.controls.look_step));
+        try!(controls.read_f32(
+            "brush_radius",
+            &mut self.controls.brush_radius,
+        ));
+
+        let physics = try!(Section::new(&root, "physics"));
+        try!(physics.read_f32("gravity", &mut self.physics.gravity));
+
+        let fog = try!(Section::new(&root, "fog"));
+        try!(fog.read_f32("distance", &mut self.fog.distance));
+        try!(fog.read_vec3("color", &mut self.fog.color));
+        if self.fog.distance < 0.0 {
+            return Err(fog.invalid("distance", "a non-negative number").into());
+        }
+
+        let erosion = try!(Section::new(&root, "erosion"));
+        try!(erosion.read_usize("num_droplets", &mut self.erosion.num_droplets));
+        try!(erosion.read_usize("resolution", &mut self.erosion.resolution));
+        try!(erosion.read_usize("max_steps", &mut self.erosion.max_steps));
+        try!(erosion.read_f32("inertia", &mut self.erosion.inertia));
+        try!(erosion.read_f32("capacity", &mut self.erosion.capacity));
+        try!(erosion.read_f32("min_capacity", &mut self.erosion.min_capacity));
+        try!(erosion.read_f32("erosion_rate", &mut self.erosion.erosion_rate));
+        try!(erosion.read_f32(
+            "deposition_rate",
+            &mut self.erosion.deposition_rate,
+        ));
+        try!(erosion.read_f32(
+            "evaporation_rate",
+            &mut self.erosion.evaporation_rate,
+        ));
+        try!(erosion.read_f32("gravity", &mut self.erosion.gravity));
+        try!(erosion.read_usize("radius", &mut self.erosion.radius));
+        try!(erosion.read_f32("talus_angle", &mut self.erosion.talus_angle));
+        try!(erosion.read_usize(
+            "thermal_iterations",
+            &mut self.erosion.thermal_iterations,
+        ));
+
+        let asteroids = try!(Section::new(&root, "asteroids"));
+        try!(asteroids.read_usize("num_asteroids", &mut self.asteroids.num_asteroids));
+        try!(asteroids.read_f32("inner_radius", &mut self.asteroids.inner_radius));
+        try!(asteroids.read_f32("outer_radius", &mut self.asteroids.outer_radius));
+        try!(asteroids.read_f32("thickness", &mut self.asteroids.thickness));
+        try!(asteroids.read_f32("min_size", &mut self.asteroids.min_size));
+        try!(asteroids.read_f32("max_size", &mut self.asteroids.max_size));
+        try!(asteroids.read_f32("roughness", &mut self.asteroids.roughness));
+        try!(asteroids.read_usize("num_meshed", &mut self.asteroids.num_meshed));
+
+        let atmosphere = try!(Section::new(&root, "atmosphere"));
+        try!(atmosphere.read_f32("height", &mut self.atmosphere.height));
+        try!(atmosphere.read_f32("sun_intensity", &mut self.atmosphere.sun_intensity));
+        try!(atmosphere.read_f32("rayleigh_density", &mut self.atmosphere.rayleigh_density));
+        try!(atmosphere.read_f32("mie_density", &mut self.atmosphere.mie_density));
+
+        let clouds = try!(Section::new(&root, "clouds"));
+        try!(clouds.read_f32("bottom", &mut self.clouds.bottom));
+        try!(clouds.read_f32("top", &mut self.clouds.top));
+        try!(clouds.read_f32("coverage", &mut self.clouds.coverage));
+        try!(clouds.read_f32("density", &mut self.clouds.density));
+        try!(clouds.read_f32("wind_speed", &mut self.clouds.wind_speed));
+
+        let shadows = try!(Section::new(&root, "shadows"));
+        try!(shadows.read_u32("map_size", &mut self.shadows.map_size));
+        try!(shadows.read_usize("num_cascades", &mut self.shadows.num_cascades));
+        try!(shadows.read_f32("distance", &mut self.shadows.distance));
+
+        let skybox = try!(Section::new(&root, "skybox"));
+        try!(skybox.read_string("path", &mut self.skybox.path));
+        for (name, face) in try!(skybox.tables()) {
+            let index = match FACE_NAMES.iter().position(|face_name| *face_name == name) {
+                Some(index) => index,
+                None => return Err(skybox.invalid(name, FACE_NAMES_EXPECTED).into()),
+            };
+            let orientation = &mut self.skybox.faces[index];
+            try!(face.read_u32("rotation", &mut orientation.rotation));
+            if orientation.rotation % 90 != 0 {
+                return Err(face.invalid("rotation", "a multiple of 90").into());
+            }
+            try!(face.read_bool("flip_x", &mut orientation.flip_x));
+            try!(face.read_bool("flip_y", &mut orientation.flip_y));
+        }
+
+        let lens_flare = try!(Section::new(&root, "lens_flare"));
+        try!(lens_flare.read_f32("intensity", &mut self.lens_flare.intensity));
+        // The ghosts replace all of the default ones, if any are listed.
+        if let Some(ghosts) = try!(lens_flare.table_array("ghosts")) {
+            self.lens_flare.ghosts.clear();
+            for ghost in ghosts {
+                let mut spec = FlareGhost {
+                    offset: 0.0,
+                    size: 0.05,
+                    color: Vec3f::new(1.0, 1.0, 1.0),
+                };
+                try!(ghost.read_f32("offset", &mut spec.offset));
+                try!(ghost.read_f32("size", &mut spec.size));
+                try!(ghost.read_vec3("color", &mut spec.color));
+                self.lens_flare.ghosts.push(spec);
+            }
+        }
+
+        let flora = try!(Section::new(&root, "flora"));
+        try!(flora.read_f32("density", &mut self.flora.density));
+        try!(flora.read_f32("max_slope", &mut self.flora.max_slope));
+        try!(flora.read_f32("tree_line", &mut self.flora.tree_line));
+        try!(flora.read_f32("shrub_line", &mut self.flora.shrub_line));
+        try!(flora.read_f32("max_step", &mut self.flora.max_step));
+
+        let structures = try!(Section::new(&root, "structures"));
+        try!(structures.read_usize("num_structures", &mut self.structures.num_structures));
+        try!(structures.read_f32("max_slope", &mut self.structures.max_slope));
+        try!(structures.read_f32("min_spacing", &mut self.structures.min_spacing));
+
+        let islands = try!(Section::new(&root, "floating_islands"));
+        let spec = &mut self.floating_islands;
+        try!(islands.read_usize("num_layers", &mut spec.num_layers));
+        try!(islands.read_f32("altitude", &mut spec.altitude));
+        try!(islands.read_f32("layer_spacing", &mut spec.layer_spacing));
+        try!(islands.read_f32("thickness", &mut spec.thickness));
+        try!(islands.read_f32("coverage", &mut spec.coverage));
+        try!(islands.read_f32("island_size", &mut spec.island_size));
+        try!(islands.read_f32("ground_roughness", &mut spec.ground_roughness));
+
+        let gas_giant = try!(Section::new(&root, "gas_giant"));
+        try!(gas_giant.read_f32("radius", &mut self.gas_giant.radius));
+        try!(gas_giant.read_usize("num_bands", &mut self.gas_giant.num_bands));
+        try!(gas_giant.read_f32("turbulence", &mut self.gas_giant.turbulence));
+        try!(gas_giant.read_usize("num_storms", &mut self.gas_giant.num_storms));
+        try!(gas_giant.read_f32(
+            "rotation_period",
+            &mut self.gas_giant.rotation_period,
+        ));
+
+        Ok(())
+    }
+}
+
+/// Polls a config file for modifications, so tunable values can be changed
+/// while the app is running.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    overrides: Box<Fn(&mut Config)>,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl ConfigWatcher {
+    /// `overrides` is applied to every reloaded config, e.g. to keep the
+    /// values given on the command line.
+    pub fn new<P: Into<PathBuf>>(path: P, overrides: Box<Fn(&mut Config)>) -> Self {
+        let path = path.into();
+        ConfigWatcher {
+            modified: modified_time(&path),
+            path: path,
+            overrides: overrides,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns the reloaded config if the file changed since it was last read.
+    /// The file is checked at most every `POLL_INTERVAL_MS` milliseconds.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        if self.last_poll.elapsed() < Duration::from_millis(POLL_INTERVAL_MS) {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(Config::from_file(&self.path).and_then(|mut config| {
+            (self.overrides)(&mut config);
+            try!(config.lod.validate());
+            Ok(config)
+        }))
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+const POLL_INTERVAL_MS: u64 = 500;
+
+/// Reads the `PlanetSpec` values in `planet`, either the `[planet]` table or
+/// that of a body.
+fn read_planet(planet: &Section, spec: &mut PlanetSpec) -> Result<()> {
+    try!(planet.read_f32("base_radius", &mut spec.base_radius));
+    try!(planet.read_f32("deviation", &mut spec.landscape_deviation));
+    try!(planet.read_usize("num_octaves", &mut spec.num_octaves));
+    try!(planet.read_f32("persistence", &mut spec.persistence));
+    try!(planet.read_f32("wavelength", &mut spec.wavelength));
+    try!(planet.read_f32("lacunarity", &mut spec.lacunarity));
+    try!(planet.read_named(
+        "mountain_basis",
+        NOISE_BASIS_NAMES,
+        NoiseBasis::from_name,
+        &mut spec.mountain_basis,
+    ));
+    try!(planet.read_named(
+        "mountain_fractal",
+        FRACTAL_NAMES,
+        Fractal::from_name,
+        &mut spec.mountain_fractal,
+    ));
+    try!(planet.read_named(
+        "plains_basis",
+        NOISE_BASIS_NAMES,
+        NoiseBasis::from_name,
+        &mut spec.plains_basis,
+    ));
+    try!(planet.read_named(
+        "plains_fractal",
+        FRACTAL_NAMES,
+        Fractal::from_name,
+        &mut spec.plains_fractal,
+    ));
+    try!(planet.read_named(
+        "surface_mapping",
+        SURFACE_MAPPING_NAMES,
+        SurfaceMapping::from_name,
+        &mut spec.surface_mapping,
+    ));
+    try!(planet.read_usize("num_plates", &mut spec.num_plates));
+    try!(planet.read_f32(
+        "continental_fraction",
+        &mut spec.continental_fraction,
+    ));
+    try!(planet.read_usize("terracing", &mut spec.terracing));
+    try!(planet.read_f32("terrace_hardness", &mut spec.terrace_hardness));
+    try!(planet.read_optional_f32("sea_level", &mut spec.sea_level));
+    try!(planet.read_usize("num_craters", &mut spec.num_craters));
+    try!(planet.read_f32("min_crater_radius", &mut spec.min_crater_radius));
+    try!(planet.read_f32("max_crater_radius", &mut spec.max_crater_radius));
+    try!(planet.read_f32("crater_size_exponent", &mut spec.crater_size_exponent));
+    try!(planet.read_usize("num_volcanoes", &mut spec.num_volcanoes));
+    try!(planet.read_f32("volcano_radius", &mut spec.volcano_radius));
+    try!(planet.read_f32("volcano_height", &mut spec.volcano_height));
+    try!(planet.read_f32("polar_cap_latitude", &mut spec.polar_cap_latitude));
+    try!(planet.read_optional_f32("snow_line", &mut spec.snow_line));
+    try!(planet.read_f32("beach_height", &mut spec.beach_height));
+    try!(planet.read_named("mesher", MESHER_NAMES, Mesher::from_name, &mut spec.mesher));
+    if let Some(solids) = try!(planet.table_array("solids")) {
+        spec.solids.clear();
+        for solid in solids {
+            let mut solid_spec = SolidSpec::default();
+            try!(solid.read_named(
+                "shape",
+                SOLID_SHAPE_NAMES,
+                SolidShape::from_name,
+                &mut solid_spec.shape,
+            ));
+            try!(solid.read_named(
+                "operation",
+                SOLID_OPERATION_NAMES,
+                SolidOperation::from_name,
+                &mut solid_spec.operation,
+            ));
+            try!(solid.read_vec3("position", &mut solid_spec.position));
+            try!(solid.read_f32("size", &mut solid_spec.size));
+            try!(solid.read_f32("thickness", &mut solid_spec.thickness));
+            try!(solid.read_f32("rounding", &mut solid_spec.rounding));
+            try!(solid.read_f32("blend", &mut solid_spec.blend));
+            if solid_spec.size <= 0.0 {
+                return Err(solid.invalid("size", "a positive number").into());
+            }
+            if solid_spec.blend <= 0.0 {
+                return Err(solid.invalid("blend", "a positive number").into());
+            }
+            spec.solids.push(solid_spec);
+        }
+    }
+    Ok(())
+}
+
+/// A (possibly missing) table in the config file. Missing tables and keys
+/// leave the target values untouched.
+struct Section<'a> {
+    name: String,
+    table: Option<&'a Table>,
+}
+
+impl<'a> Section<'a> {
+    fn new(root: &'a Table, name: &str) -> Result<Self> {
+        let table = match root.get(name) {
+            Some(&Value::Table(ref table)) => Some(table),
+            Some(_) => return Err(ErrorKind::InvalidConfigValue(name.to_owned(), "a table").into()),
+            None => None,
+        };
+        Ok(Section {
+            name: name.to_owned(),
+            table: table,
+        })
+    }
+
+    /// The tables within this one, e.g. `[body.moon]` in `[body]`, by name.
+    fn tables(&self) -> Result<Vec<(&'a str, Section<'a>)>> {
+        let mut sections = vec![];
+        for (name, value) in self.table.iter().flat_map(|table| table.iter()) {
+            match *value {
+                Value::Table(ref table) => {
+                    sections.push((
+                        &name[..],
+                        Section {
+                            name: format!("{}.{}", self.name, name),
+                            table: Some(table),
+                        },
+                    ))
+                }
+                _ => return Err(self.invalid(name, "a table").into()),
+            }
+        }
+        Ok(sections)
+    }
+
+    /// The tables in the array `key`, e.g. `[[lens_flare.ghosts]]` in
+    /// `[lens_flare]`, if there is one.
+    fn table_array(&self, key: &str) -> Result<Option<Vec<Section<'a>>>> {
+        let values = match self.get(key) {
+            Some(&Value::Array(ref values)) => values,
+            Some(_) => return Err(self.invalid(key, "an array of tables").into()),
+            None => return Ok(None),
+        };
+        let mut sections = vec![];
+        for (index, value) in values.iter().enumerate() {
+            match *value {
+                Value::Table(ref table) => {
+                    sections.push(Section {
+                        name: format!("{}.{}[{}]", self.name, key, index),
+                        table: Some(table),
+                    })
+                }
+                _ => return Err(self.invalid(key, "an array of tables").into()),
+            }
+        }
+        Ok(Some(sections))
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Value> {
+        self.table.and_then(|table| table.get(key))
+    }
+
+    fn invalid(&self, key: &str, expected: &'static str) -> ErrorKind {
+        ErrorKind::InvalidConfigValue(format!("{}.{}", self.name, key), expected)
+    }
+
+    fn read_f32(&self, key: &str, target: &mut f32) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::Float(value)) => *target = value as f32,
+            Some(&Value::Integer(value)) => *target = value as f32,
+            Some(_) => return Err(self.invalid(key, "a number").into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn read_optional_f32(&self, key: &str, target: &mut Option<f32>) -> Result<()> {
+        if self.get(key).is_some() {
+            let mut value = 0.0;
+            try!(self.read_f32(key, &mut value));
+            *target = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Reads an array of three numbers.
+    fn read_vec3(&self, key: &str, target: &mut Vec3f) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::Array(ref values)) if values.len() == 3 => {
+                let mut vector = [0.0; 3];
+                for (component, value) in vector.iter_mut().zip(values.iter()) {
+                    *component = match *value {
+                        Value::Float(value) => value as f32,
+                        Value::Integer(value) => value as f32,
+                        _ => return Err(self.invalid(key, "an array of 3 numbers").into()),
+                    };
+                }
+                *target = Vec3f::new(vector[0], vector[1], vector[2]);
+            }
+            Some(_) => return Err(self.invalid(key, "an array of 3 numbers").into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn read_bool(&self, key: &str, target: &mut bool) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::Boolean(value)) => *target = value,
+            Some(_) => return Err(self.invalid(key, "a boolean").into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn read_string(&self, key: &str, target: &mut String) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::String(ref value)) => *target = value.clone(),
+            Some(_) => return Err(self.invalid(key, "a string").into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn read_integer(&self, key: &str, max: i64) -> Result<Option<i64>> {
+        match self.get(key) {
+            Some(&Value::Integer(value)) if 0 <= value && value <= max => Ok(Some(value)),
+            Some(_) => Err(self.invalid(key, "a non-negative integer in range").into()),
+            None => Ok(None),
+        }
+    }
+
+    fn read_u8(&self, key: &str, target: &mut u8) -> Result<()> {
+        if let Some(value) = try!(self.read_integer(key, u8::max_value() as i64)) {
+            *target = value as u8;
+        }
+        Ok(())
+    }
+
+    fn read_u32(&self, key: &str, target: &mut u32) -> Result<()> {
+        if let Some(value) = try!(self.read_integer(key, u32::max_value() as i64)) {
+            *target = value as u32;
+        }
+        Ok(())
+    }
+
+    fn read_usize(&self, key: &str, target: &mut usize) -> Result<()> {
+        if let Some(value) = try!(self.read_integer(key, i64::max_value())) {
+            *target = value as usize;
+        }
+        Ok(())
+    }
+
+    fn read_key(&self, key: &str, target: &mut KeyCode) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::String(ref name)) => {
+                match key_code_from_name(name) {
+                    Some(key_code) => *target = key_code,
+                    None => return Err(self.invalid(key, "a key name").into()),
+                }
+            }
+            Some(_) => return Err(self.invalid(key, "a key name").into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Reads a string naming a value of `T`, `expected` describes the names.
+    fn read_named<T>(
+        &self,
+        key: &str,
+        expected: &'static str,
+        from_name: fn(&str) -> Option<T>,
+        target: &mut T,
+    ) -> Result<()> {
+        match self.get(key) {
+            Some(&Value::String(ref name)) => {
+                match from_name(name) {
+                    Some(value) => *target = value,
+                    None => return Err(self.invalid(key, expected).into()),
+                }
+            }
+            Some(_) => return Err(self.invalid(key, expected).into()),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn read_fullscreen(&self, key: &str, target: &mut FullscreenMode) -> Result<()> {
+        let expected = "one of \"windowed\", \"exclusive\" or \"borderless\"";
+        match self.get(key) {
+            Some(&Value::String(ref name)) => {
+                match FullscreenMode::from_name(name) {
+                    Some(mode) => *target = mode,
+                    None => return Err(self.invalid(key, expected).into()),
+                }
+            }
+            Some(_) => return Err(self.invalid(key, expected).into()),
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use gfx::KeyCode;
+    use math::Vec3f;
+
+    #[test]
+    fn test_partial_config_keeps_defaults() {
+        let config = Config::from_str(
+            "[planet]\nnum_octaves = 7\nbase_radius = 100\n\n[controls]\njump = \"LShift\"\n",
+        ).unwrap();
+        let default = Config::default();
+        assert_eq!(7, config.planet.num_octaves);
+        assert_eq!(100.0, config.planet.base_radius);
+        assert_eq!(default.planet.persistence, config.planet.persistence);
+        assert_eq!(default.lod.max_level, config.lod.max_level);
+        assert_eq!(KeyCode::LShift, config.controls.jump);
+        assert_eq!(default.physics.gravity, config.physics.gravity);
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(Config::from_str("[lod]\nmax_level = 300\n").is_err());
+        assert!(Config::from_str("[lod]\nchunk_num_steps = 0.5\n").is_err());
+        assert!(Config::from_str("[lod]\nvoxel_density = 1e4\n").is_err());
+        assert!(Config::from_str("[lod]\nmax_level = 20\n").is_err());
+        assert!(Config::from_str("[planet]\nwavelength = \"long\"\n").is_err());
+        assert!(Config::from_str("[controls]\nforward = \"NotAKey\"\n").is_err());
+        assert!(Config::from_str("[window]\nfullscreen = \"maybe\"\n").is_err());
+        assert!(Config::from_str("[window]\nmultisampling = 3\n").is_err());
+        assert!(Config::from_str("[window]\nmultisampling = 1\n").is_err());
+        assert!(Config::from_str("[window]\nsupersampling = 2\n").is_err());
+        assert!(Config::from_str("[fog]\ndistance = -1.0\n").is_err());
+        assert!(Config::from_str("[[planet.solids]]\nshape = \"cone\"\n").is_err());
+        assert!(Config::from_str("[[planet.solids]]\nsize = 0.0\n").is_err());
+        assert!(Config::from_str("[planet]\nmountain_fractal = \"fuzzy\"\n").is_err());
+        assert!(Config::from_str("[planet]\nmesher = \"voxels\"\n").is_err());
+        assert!(Config::from_str("planet = 1\n").is_err());
+        assert!(Config::from_str("[body.moon]\nposition = [1.0, 2.0]\n").is_err());
+        assert!(Config::from_str("[moon.luna]\neccentricity = 1.0\n").is_err());
+        assert!(Config::from_str("[skybox.up]\nrotation = 90\n").is_err());
+        assert!(Config::from_str("[skybox.posx]\nrotation = 45\n").is_err());
+    }
+
+    #[test]
+    fn test_bodies_start_like_the_planet() {
+        let config = Config::from_str(
+            "[planet]\nnum_octaves = 3\n\n\
+             [body.moon]\nseed = 4\nposition = [0.0, 2e4, 0.5]\nbase_radius = 800.0\n",
+        ).unwrap();
+        assert_eq!(1, config.bodies.len());
+        let moon = &config.bodies[0];
+        assert_eq!("moon", moon.name);
+        assert_eq!(Some(4), moon.seed);
+        assert_eq!(Vec3f::new(0.0, 2e4, 0.5), moon.position);
+        assert_eq!(800.0, moon.planet.base_radius);
+        assert_eq!(3, moon.planet.num_octaves);
+        assert_eq!(config.planet.persistence, moon.planet.persistence);
+    }
+
+    #[test]
+    fn test_lens_flare_ghosts_replace_the_defaults() {
+        let config = Config::from_str(
+            "[lens_flare]\nintensity = 0.8\n\n\
+             [[lens_flare.ghosts]]\noffset = 0.5\ncolor = [1.0, 0.0, 0.0]\n\n\
+             [[lens_flare.ghosts]]\nsize = 0.2\n",
+        ).unwrap();
+        let ghosts = &config.lens_flare.ghosts;
+        assert_eq!(0.8, config.lens_flare.intensity);
+        assert_eq!(2, ghosts.len());
+        assert_eq!(0.5, ghosts[0].offset);
+        assert_eq!(Vec3f::new(1.0, 0.0, 0.0), ghosts[0].color);
+        assert_eq!(0.2, ghosts[1].size);
+        assert!(Config::from_str("[lens_flare]\nghosts = 3\n").is_err());
+        assert_eq!(
+            Config::default().lens_flare,
+            Config::from_str("[lens_flare]\n").unwrap().lens_flare
+        );
+    }
+
+    #[test]
+    fn test_moons_orbit_the_planet() {
+        let config = Config::from_str(
+            "[planet]\nbase_radius = 1000.0\n\n\
+             [moon.luna]\norbit_radius = 4000.0\nnum_craters = 3\n",
+        ).unwrap();
+        assert_eq!(1, config.bodies.len());
+        let moon = &config.bodies[0];
+        assert_eq!("luna", moon.name);
+        assert_eq!(4000.0, moon.orbit.unwrap().semi_major_axis);
+        assert_eq!(3, moon.planet.num_craters);
+        assert!(moon.planet.base_radius < config.planet.base_radius);
+    }
+}
+
+const NOISE_BASIS_NAMES: &'static str = "one of \"simplex\", \"perlin\" or \"worley\"";
+const FRACTAL_NAMES: &'static str = "one of \"fbm\", \"ridged\", \"billow\" or \"hybrid\"";
+const SURFACE_MAPPING_NAMES: &'static str = "one of \"sphere\" or \"cube\"";
+const MESHER_NAMES: &'static str = "one of \"marching_cubes\" or \"dual_contouring\"";
+const SOLID_SHAPE_NAMES: &'static str = "one of \"sphere\", \"cube\" or \"torus\"";
+const SOLID_OPERATION_NAMES: &'static str = "one of \"union\", \"intersection\", \"difference\" \
+                                             or \"smooth_union\"";
+const FACE_NAMES_EXPECTED: &'static str = "one of \"posx\", \"negx\", \"posy\", \"negy\", \
+                                           \"posz\" or \"negz\"";
@@ -0,0 +1,131 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Write;
+use std::panic::{self, UnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::{Mutex, Once};
+use std::thread;
+
+use backtrace::Backtrace;
+
+use errors::{ChainErr, Result};
+use utils::timestamp_millis;
+
+/// Logs the message and a backtrace of every panic, on any thread, and writes
+/// them together with the current crash context to `crash-<unix time ms>.txt`
+/// in the working directory. Panics caught by `catch_panic` are only logged,
+/// as they're recovered from.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let thread = thread::current();
+        let thread_name = thread.name().unwrap_or("<unnamed>");
+        let location = info.location().map_or_else(String::new, |location| {
+            format!(" at {}:{}", location.file(), location.line())
+        });
+        let summary = format!(
+            "Thread '{}' panicked{}: {}",
+            thread_name,
+            location,
+            panic_message(info.payload())
+        );
+        let backtrace = Backtrace::new();
+        if CATCHING.with(|catching| catching.get() > 0) {
+            warn!("{}", summary);
+            debug!("{:?}", backtrace);
+            return;
+        }
+        error!("{}\n{:?}", summary, backtrace);
+
+        let report = format!(
+            "{}\n\n{}\n\nBacktrace:\n{:?}\n",
+            summary,
+            crash_context().lock().map(|context| context.clone()).unwrap_or_default(),
+            backtrace
+        );
+        match write_report(&report) {
+            Ok(path) => error!("Wrote crash report to {:?}", path),
+            Err(err) => error!("Could not write crash report: {}", err),
+        }
+    }));
+}
+
+/// Describes what the app is doing (e.g. the world seed and planet spec), for
+/// inclusion in crash reports.
+pub fn set_context(context: String) {
+    if let Ok(mut current) = crash_context().lock() {
+        *current = context;
+    }
+}
+
+/// Runs `f`, catching a panic like `panic::catch_unwind`. The panic hook
+/// doesn't write a crash report for it, the caller handling the failure.
+pub fn catch_panic<F, R>(f: F) -> thread::Result<R>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    CATCHING.with(|catching| catching.set(catching.get() + 1));
+    let result = panic::catch_unwind(f);
+    CATCHING.with(|catching| catching.set(catching.get() - 1));
+    result
+}
+
+/// The message a panic was started with, e.g. by `panic!` or `unwrap()`.
+pub fn panic_message(payload: &Any) -> String {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<unknown panic payload>".to_owned()
+    }
+}
+
+fn write_report(report: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(format!("crash-{}.txt", timestamp_millis()));
+    let mut file = try!(File::create(&path).chain_err(|| {
+        format!("Could not create {:?}", path)
+    }));
+    try!(file.write_all(report.as_bytes()).chain_err(|| {
+        format!("Could not write to {:?}", path)
+    }));
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use super::{catch_panic, panic_message, CATCHING};
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("static message")).unwrap_err();
+        assert_eq!("static message", panic_message(&*payload));
+        let payload = panic::catch_unwind(|| panic!("chunk {}", 7)).unwrap_err();
+        assert_eq!("chunk 7", panic_message(&*payload));
+    }
+
+    #[test]
+    fn test_catch_panic_recovers() {
+        assert_eq!(3, catch_panic(|| 3).unwrap());
+        let payload = catch_panic(|| panic!("caught")).unwrap_err();
+        assert_eq!("caught", panic_message(&*payload));
+        CATCHING.with(|catching| assert_eq!(0, catching.get()));
+    }
+}
+
+/// The crash context, created on first use.
+fn crash_context() -> &'static Mutex<String> {
+    static INIT: Once = Once::new();
+    static mut CONTEXT: *const Mutex<String> = ptr::null();
+    unsafe {
+        INIT.call_once(|| CONTEXT = Box::into_raw(Box::new(Mutex::new(String::new()))));
+        &*CONTEXT
+    }
+}
+
+thread_local! {
+    // How many calls to `catch_panic` the thread is in.
+    static CATCHING: Cell<usize> = Cell::new(0);
+}
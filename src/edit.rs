@@ -0,0 +1,658 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use nalgebra::{Norm, Point3, Vector3};
+
+use erosion::ErosionSpec;
+use errors::{ChainErr, ErrorKind, Result};
+use fields::{SolidOperation, SolidShape};
+use fractal::{Fractal, NoiseBasis};
+use math::{CpuScalar, Material, Mesher, ScalarField3, Vec3f, difference_gradient,
+           difference_gradients};
+use planet::{PlanetSpec, SurfaceMapping};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushKind {
+    /// Fills the sphere with solid.
+    Add,
+    /// Empties the sphere.
+    Subtract,
+}
+
+/// A spherical edit of the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub kind: BrushKind,
+    pub centre: Point3<CpuScalar>,
+    pub radius: CpuScalar,
+}
+
+impl Brush {
+    /// The corners of the box around the sphere.
+    pub fn bounds(&self) -> (Vec3f, Vec3f) {
+        let centre = Vec3f::new(self.centre[0], self.centre[1], self.centre[2]);
+        (centre - self.radius, centre + self.radius)
+    }
+
+    #[inline]
+    fn apply(&self, value: CpuScalar, position: &Point3<CpuScalar>) -> CpuScalar {
+        let distance = (*position - self.centre).norm() - self.radius;
+        match self.kind {
+            BrushKind::Add => value.min(distance),
+            BrushKind::Subtract => value.max(-distance),
+        }
+    }
+}
+
+/// Wraps a field with a list of brushes applied on top of it, in order. The
+/// brushes can be added to while chunks are being meshed from other threads.
+pub struct EditedField<Field> {
+    field: Field,
+    brushes: RwLock<Vec<Brush>>,
+}
+
+impl<Field: ScalarField3> EditedField<Field> {
+    pub fn new(field: Field) -> Self {
+        EditedField {
+            field: field,
+            brushes: RwLock::new(vec![]),
+        }
+    }
+
+    /// Only affects chunks meshed from now on, the caller has to invalidate
+    /// the ones within `brush.bounds()`.
+    pub fn add_brush(&self, brush: Brush) {
+        self.brushes.write().unwrap().push(brush);
+    }
+
+    fn apply_brushes(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        for brush in self.brushes.read().unwrap().iter() {
+            for (position, value) in positions.iter().zip(values.iter_mut()) {
+                *value = brush.apply(*value, position);
+            }
+        }
+    }
+}
+
+impl<Field: ScalarField3> ScalarField3 for EditedField<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let value = self.field.value_at(position);
+        self.brushes.read().unwrap().iter().fold(
+            value,
+            |value, brush| brush.apply(value, position),
+        )
+    }
+
+    #[inline]
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        self.field.values_at(positions, values);
+        self.apply_brushes(positions, values);
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        let value = self.field.value_at_lod(position, step);
+        self.brushes.read().unwrap().iter().fold(
+            value,
+            |value, brush| brush.apply(value, position),
+        )
+    }
+
+    #[inline]
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        step: CpuScalar,
+    ) {
+        self.field.values_at_lod(positions, values, step);
+        self.apply_brushes(positions, values);
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(position)
+    }
+
+    #[inline]
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        self.field.stratum_at(position)
+    }
+
+    /// The field's own gradient, which may be exact, unless a brush changes
+    /// the values it would be estimated from.
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        let brushes = self.brushes.read().unwrap();
+        if !brushes.is_empty() {
+            // A brush only changes values its distance is below, near the
+            // surface that's about inside it.
+            let value = self.field.value_at(position).abs() + BRUSH_GRADIENT_MARGIN;
+            if brushes.iter().any(|brush| {
+                (*position - brush.centre).norm() - brush.radius < value
+            })
+            {
+                return difference_gradient(self, position);
+            }
+        }
+        self.field.gradient_at(position)
+    }
+
+    /// Like `gradient_at`, with the field's own gradients batched and those
+    /// near the brushes estimated in another batch.
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        self.field.gradients_at(positions, gradients);
+        let brushes = self.brushes.read().unwrap();
+        if brushes.is_empty() {
+            return;
+        }
+        let mut values = vec![0.0; positions.len()];
+        self.field.values_at(positions, &mut values);
+        let brushed: Vec<usize> = (0..positions.len())
+            .filter(|&index| {
+                let value = values[index].abs() + BRUSH_GRADIENT_MARGIN;
+                brushes.iter().any(|brush| {
+                    (positions[index] - brush.centre).norm() - brush.radius < value
+                })
+            })
+            .collect();
+        drop(brushes);
+        let brushed_positions: Vec<Point3<CpuScalar>> =
+            brushed.iter().map(|&index| positions[index]).collect();
+        let mut brushed_gradients = vec![Vector3::new(0.0, 0.0, 0.0); brushed.len()];
+        difference_gradients(self, &brushed_positions, &mut brushed_gradients);
+        for (&index, gradient) in brushed.iter().zip(brushed_gradients.into_iter()) {
+            gradients[index] = gradient;
+        }
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+
+    #[inline]
+    fn gpu_cell_triangles(
+        &self,
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        self.field.gpu_cell_triangles(shape, values, iso_value)
+    }
+
+    /// The field's, shrunk below any hole dug into it.
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        self.field.occluder_radius().map(|radius| {
+            self.brushes
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|brush| brush.kind == BrushKind::Subtract)
+                .map(|brush| brush.centre.to_vector().norm() - brush.radius)
+                .fold(radius, |radius, bottom| radius.min(bottom))
+        })
+    }
+
+    fn set_finest_step(&self, finest_step: CpuScalar) {
+        self.field.set_finest_step(finest_step)
+    }
+}
+
+/// The directory the edits are saved to, in a log per body of each world.
+/// Worlds are told apart by their field, seed and specs, so edits are only
+/// ever replayed on the terrain they were made on.
+#[derive(Clone, Debug)]
+pub struct WorldDir {
+    directory: PathBuf,
+    field_name: String,
+}
+
+impl WorldDir {
+    pub fn new<P: Into<PathBuf>>(directory: P, field_name: &str) -> Self {
+        WorldDir {
+            directory: directory.into(),
+            field_name: field_name.to_owned(),
+        }
+    }
+
+    /// Opens the log of the body generated from `seed`, `planet` and
+    /// `erosion`, creating it if missing, along with the edits already in it.
+    /// Bodies other than the main planet are also told apart by their name.
+    pub fn open_edit_log(
+        &self,
+        body_name: Option<&str>,
+        seed: u32,
+        planet: &PlanetSpec,
+        erosion: &ErosionSpec,
+    ) -> Result<(EditLog, Vec<Brush>)> {
+        try!(fs::create_dir_all(&self.directory).chain_err(|| {
+            format!("Could not create world directory {:?}", self.directory)
+        }));
+        let spec_hash = spec_hash(planet, erosion);
+        let body_prefix = body_name.map_or_else(String::new, |name| format!("{}-", name));
+        let path = self.directory.join(format!(
+            "{}-{}{}-{:016x}.edits",
+            self.field_name,
+            body_prefix,
+            seed,
+            spec_hash
+        ));
+        EditLog::open(&path, seed, spec_hash)
+    }
+}
+
+// An edit log is a header
+//     magic: "TEDT", version: u32, seed: u32, spec hash: u64
+// followed by a record per brush, in the order they were applied
+//     kind: u8 (0 is add, 1 is subtract), centre: 3 x f32, radius: f32
+// all little endian. Records are appended to the log as the edits are made.
+pub struct EditLog {
+    file: File,
+    path: PathBuf,
+}
+
+impl EditLog {
+    pub fn open(path: &Path, seed: u32, spec_hash: u64) -> Result<(Self, Vec<Brush>)> {
+        let mut file = try!(
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(path)
+                .chain_err(|| format!("Could not open edit log {:?}", path))
+        );
+        let mut data = vec![];
+        try!(file.read_to_end(&mut data).chain_err(|| {
+            format!("Could not read edit log {:?}", path)
+        }));
+
+        let brushes = if data.is_empty() {
+            try!(file.write_all(&encode_header(seed, spec_hash)).chain_err(|| {
+                format!("Could not write edit log {:?}", path)
+            }));
+            vec![]
+        } else {
+            let (brushes, valid_len) = try!(decode_edits(&data, seed, spec_hash).map_err(|msg| {
+                ErrorKind::InvalidEditLog(format!("{:?}", path), msg)
+            }));
+            // A partial record is left behind if we died while appending it.
+            if valid_len < data.len() {
+                warn!("Dropping a truncated edit at the end of {:?}", path);
+                try!(file.set_len(valid_len as u64).chain_err(|| {
+                    format!("Could not truncate edit log {:?}", path)
+                }));
+            }
+            brushes
+        };
+        info!("Loaded {} edits from {:?}", brushes.len(), path);
+
+        Ok((
+            EditLog {
+                file: file,
+                path: path.to_owned(),
+            },
+            brushes,
+        ))
+    }
+
+    pub fn append(&mut self, brush: &Brush) -> Result<()> {
+        let path = &self.path;
+        self.file.write_all(&encode_brush(brush)).chain_err(|| {
+            format!("Could not write edit log {:?}", path)
+        })
+    }
+}
+
+fn encode_header(seed: u32, spec_hash: u64) -> Vec<u8> {
+    let mut data = EDIT_LOG_MAGIC.to_vec();
+    // Writing to a `Vec` cannot fail.
+    data.write_u32::<LittleEndian>(EDIT_LOG_VERSION).unwrap();
+    data.write_u32::<LittleEndian>(seed).unwrap();
+    data.write_u64::<LittleEndian>(spec_hash).unwrap();
+    data
+}
+
+fn encode_brush(brush: &Brush) -> Vec<u8> {
+    let mut data = Vec::with_capacity(RECORD_SIZE);
+    data.push(match brush.kind {
+        BrushKind::Add => 0,
+        BrushKind::Subtract => 1,
+    });
+    for &value in [brush.centre[0], brush.centre[1], brush.centre[2], brush.radius].iter() {
+        data.write_f32::<LittleEndian>(value).unwrap();
+    }
+    data
+}
+
+/// The brushes in the log and the length of the data up to the last whole
+/// record.
+fn decode_edits(
+    data: &[u8],
+    seed: u32,
+    spec_hash: u64,
+) -> ::std::result::Result<(Vec<Brush>, usize), String> {
+    if data.len() < HEADER_SIZE || &data[..EDIT_LOG_MAGIC.len()] != EDIT_LOG_MAGIC {
+        return Err("not an edit log".to_owned());
+    }
+    let mut header = Cursor::new(&data[EDIT_LOG_MAGIC.len()..HEADER_SIZE]);
+    let version = header.read_u32::<LittleEndian>().unwrap();
+    if version != EDIT_LOG_VERSION {
+        return Err(format!("unsupported version {}", version));
+    }
+    let (log_seed, log_spec_hash) = (
+        header.read_u32::<LittleEndian>().unwrap(),
+        header.read_u64::<LittleEndian>().unwrap(),
+    );
+    if log_seed != seed || log_spec_hash != spec_hash {
+        return Err(format!(
+            "made for seed {} and spec {:016x}, not seed {} and spec {:016x}",
+            log_seed,
+            log_spec_hash,
+            seed,
+            spec_hash
+        ));
+    }
+
+    let mut brushes = vec![];
+    for record in data[HEADER_SIZE..].chunks(RECORD_SIZE) {
+        if record.len() < RECORD_SIZE {
+            break;
+        }
+        let kind = match record[0] {
+            0 => BrushKind::Add,
+            1 => BrushKind::Subtract,
+            kind => return Err(format!("unknown brush kind {}", kind)),
+        };
+        let mut values = Cursor::new(&record[1..]);
+        let mut next = || values.read_f32::<LittleEndian>().unwrap();
+        brushes.push(Brush {
+            kind: kind,
+            centre: Point3::new(next(), next(), next()),
+            radius: next(),
+        });
+    }
+    let valid_len = HEADER_SIZE + brushes.len() * RECORD_SIZE;
+    Ok((brushes, valid_len))
+}
+
+/// A hash of the specs the terrain is generated from, which the edits and
+/// the cached meshes of a world are kept for. Unlike `DefaultHasher` and the
+/// specs' `Debug` it's stable across releases: the fields are listed here,
+/// those added after the first version only when they're not their default,
+/// so the worlds saved before them stay valid. `SPEC_HASH_VERSION` is bumped
+/// when the same specs generate different terrain.
+pub fn spec_hash(planet: &PlanetSpec, erosion: &ErosionSpec) -> u64 {
+    let mut hash = SpecHash(FNV_OFFSET_BASIS);
+    hash.u64(SPEC_HASH_VERSION);
+    hash.f32(planet.base_radius);
+    hash.f32(planet.landscape_deviation);
+    hash.u64(planet.num_octaves as u64);
+    hash.f32(planet.persistence);
+    hash.f32(planet.wavelength);
+    hash.f32(planet.lacunarity);
+    hash.u64(noise_basis_code(planet.mountain_basis));
+    hash.u64(fractal_code(planet.mountain_fractal));
+    hash.u64(noise_basis_code(planet.plains_basis));
+    hash.u64(fractal_code(planet.plains_fractal));
+    hash.optional_f32(planet.sea_level);
+    hash.u64(planet.num_craters as u64);
+    hash.f32(planet.min_crater_radius);
+    hash.f32(planet.max_crater_radius);
+    hash.f32(planet.crater_size_exponent);
+    hash.u64(planet.num_volcanoes as u64);
+    hash.f32(planet.volcano_radius);
+    hash.f32(planet.volcano_height);
+    hash.f32(planet.polar_cap_latitude);
+    hash.optional_f32(planet.snow_line);
+
+    // The erosion's parameters don't matter unless it erodes.
+    hash.u64(erosion.is_enabled() as u64);
+    if erosion.is_enabled() {
+        hash.u64(erosion.num_droplets as u64);
+        hash.u64(erosion.resolution as u64);
+        hash.u64(erosion.max_steps as u64);
+        hash.f32(erosion.inertia);
+        hash.f32(erosion.capacity);
+        hash.f32(erosion.min_capacity);
+        hash.f32(erosion.erosion_rate);
+        hash.f32(erosion.deposition_rate);
+        hash.f32(erosion.evaporation_rate);
+        hash.f32(erosion.gravity);
+        hash.u64(erosion.radius as u64);
+        hash.f32(erosion.talus_angle);
+        hash.u64(erosion.thermal_iterations as u64);
+    }
+
+    // Added later, each tagged so that leaving out the default ones doesn't
+    // make two lists of them hash the same.
+    let default = PlanetSpec::default();
+    if planet.surface_mapping != default.surface_mapping {
+        hash.u64(1);
+        hash.u64(match planet.surface_mapping {
+            SurfaceMapping::Sphere => 0,
+            SurfaceMapping::CubeSphere => 1,
+        });
+    }
+    if planet.num_plates != default.num_plates ||
+        planet.continental_fraction != default.continental_fraction
+    {
+        hash.u64(2);
+        hash.u64(planet.num_plates as u64);
+        hash.f32(planet.continental_fraction);
+    }
+    if planet.terracing != default.terracing ||
+        planet.terrace_hardness != default.terrace_hardness
+    {
+        hash.u64(3);
+        hash.u64(planet.terracing as u64);
+        hash.f32(planet.terrace_hardness);
+    }
+    if planet.mesher != default.mesher {
+        hash.u64(4);
+        hash.u64(match planet.mesher {
+            Mesher::MarchingCubes => 0,
+            Mesher::DualContouring => 1,
+            Mesher::SurfaceNets => 2,
+        });
+    }
+    if planet.beach_height != default.beach_height {
+        hash.u64(5);
+        hash.f32(planet.beach_height);
+    }
+    if !planet.solids.is_empty() {
+        hash.u64(6);
+        hash.u64(planet.solids.len() as u64);
+        for solid in planet.solids.iter() {
+            hash.u64(match solid.shape {
+                SolidShape::Sphere => 0,
+                SolidShape::Cube => 1,
+                SolidShape::Torus => 2,
+            });
+            hash.u64(match solid.operation {
+                SolidOperation::Union => 0,
+                SolidOperation::Intersection => 1,
+                SolidOperation::Difference => 2,
+                SolidOperation::SmoothUnion => 3,
+            });
+            for axis in 0..3 {
+                hash.f32(solid.position[axis]);
+            }
+            hash.f32(solid.size);
+            hash.f32(solid.thickness);
+            hash.f32(solid.rounding);
+            hash.f32(solid.blend);
+        }
+    }
+    hash.0
+}
+
+fn noise_basis_code(basis: NoiseBasis) -> u64 {
+    match basis {
+        NoiseBasis::Simplex => 0,
+        NoiseBasis::Perlin => 1,
+        NoiseBasis::Worley => 2,
+    }
+}
+
+fn fractal_code(fractal: Fractal) -> u64 {
+    match fractal {
+        Fractal::Fbm => 0,
+        Fractal::Ridged => 1,
+        Fractal::Billow => 2,
+        Fractal::Hybrid => 3,
+    }
+}
+
+/// FNV-1a of the values written to it, in little endian.
+struct SpecHash(u64);
+
+impl SpecHash {
+    fn u64(&mut self, value: u64) {
+        let mut bytes = [0; 8];
+        LittleEndian::write_u64(&mut bytes, value);
+        for &byte in bytes.iter() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.u64(value.to_bits() as u64);
+    }
+
+    fn optional_f32(&mut self, value: Option<f32>) {
+        match value {
+            Some(value) => {
+                self.u64(1);
+                self.f32(value);
+            }
+            None => self.u64(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use nalgebra::{Norm, Point3, Vector3};
+
+    use fields::FlatField;
+    use math::ScalarField3;
+    use erosion::ErosionSpec;
+    use math::Mesher;
+    use planet::PlanetSpec;
+    use super::{Brush, BrushKind, EditLog, EditedField, spec_hash};
+
+    #[test]
+    fn test_brushes_dig_and_build() {
+        let field = EditedField::new(FlatField::new(0.0));
+        let below = Point3::new(0.0, -1.0, 0.0);
+        let above = Point3::new(10.0, 1.0, 0.0);
+        assert_eq!(-1.0, field.value_at(&below));
+
+        field.add_brush(Brush {
+            kind: BrushKind::Subtract,
+            centre: Point3::new(0.0, 0.0, 0.0),
+            radius: 2.0,
+        });
+        assert_eq!(1.0, field.value_at(&below));
+
+        field.add_brush(Brush {
+            kind: BrushKind::Add,
+            centre: Point3::new(10.0, 0.0, 0.0),
+            radius: 2.0,
+        });
+        assert_eq!(-1.0, field.value_at(&above));
+        // Filling the hole back in undoes the dig, brushes apply in order.
+        field.add_brush(Brush {
+            kind: BrushKind::Add,
+            centre: Point3::new(0.0, 0.0, 0.0),
+            radius: 2.0,
+        });
+        assert!(field.value_at(&below) < 0.0);
+
+        // The batched gradients are those taken one at a time, inside the
+        // brushes and away from them.
+        let positions = [below, above, Point3::new(50.0, 0.5, 0.0)];
+        let mut gradients = [Vector3::new(0.0, 0.0, 0.0); 3];
+        field.gradients_at(&positions, &mut gradients);
+        for (position, gradient) in positions.iter().zip(gradients.iter()) {
+            assert!((field.gradient_at(position) - *gradient).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_edit_log_round_trip() {
+        let path = env::temp_dir().join(format!("terrain-test-{}.edits", ::std::process::id()));
+        let _ = fs::remove_file(&path);
+        let brushes = vec![
+            Brush {
+                kind: BrushKind::Subtract,
+                centre: Point3::new(1.0, -2.5, 3e3),
+                radius: 4.0,
+            },
+            Brush {
+                kind: BrushKind::Add,
+                centre: Point3::new(0.0, 0.5, -7.0),
+                radius: 0.25,
+            },
+        ];
+        {
+            let (mut log, loaded) = EditLog::open(&path, 7, 42).unwrap();
+            assert!(loaded.is_empty());
+            for brush in brushes.iter() {
+                log.append(brush).unwrap();
+            }
+        }
+        assert_eq!(brushes, EditLog::open(&path, 7, 42).unwrap().1);
+
+        // A record cut short is dropped, the log stays usable.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| ::std::io::Write::write_all(&mut file, &[1, 2, 3]))
+            .unwrap();
+        assert_eq!(brushes, EditLog::open(&path, 7, 42).unwrap().1);
+
+        assert!(EditLog::open(&path, 8, 42).is_err());
+        assert!(EditLog::open(&path, 7, 43).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_spec_hash_is_stable() {
+        let planet = PlanetSpec::default();
+        let erosion = ErosionSpec::default();
+        let hash = spec_hash(&planet, &erosion);
+        // Saved worlds are lost if this changes.
+        assert_eq!(0x6b36_abb5_1069_fa88, hash);
+
+        let unused_erosion = ErosionSpec {
+            inertia: 0.5,
+            ..ErosionSpec::default()
+        };
+        assert_eq!(hash, spec_hash(&planet, &unused_erosion));
+        let planet = PlanetSpec {
+            mesher: Mesher::DualContouring,
+            ..PlanetSpec::default()
+        };
+        assert!(hash != spec_hash(&planet, &erosion));
+    }
+}
+
+// Twice the step of the differences gradients are estimated from, enough for
+// a brush to change none of them.
+const BRUSH_GRADIENT_MARGIN: CpuScalar = 2.0;
+const EDIT_LOG_MAGIC: &'static [u8] = b"TEDT";
+const EDIT_LOG_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 20;
+const RECORD_SIZE: usize = 17;
+// Bumped when the same specs generate different terrain, see `spec_hash`.
+const SPEC_HASH_VERSION: u64 = 1;
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
@@ -0,0 +1,502 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Instant;
+
+use nalgebra::{Norm, Point3, Vector3};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use rayon::prelude::*;
+
+use math::{CpuScalar, Material, Mesher, ScalarField3, Vec3f, difference_gradients};
+use math::cube_sphere::{NUM_FACES, direction_to_equal_angle, equal_angle_to_direction};
+use trace;
+
+/// Parameters of the droplet based hydraulic erosion and the thermal erosion
+/// which follows it. Each droplet starts with one unit of water at a random
+/// cell, runs downhill picking up sediment while it speeds up and drops it
+/// where it slows down or evaporates. Thermal erosion then crumbles slopes
+/// steeper than `talus_angle`, piling scree at the foot of cliffs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErosionSpec {
+    /// Droplets simulated on each face of the cube-sphere, no erosion if zero.
+    pub num_droplets: usize,
+    /// Number of cells along the side of each face.
+    pub resolution: usize,
+    /// Steps a droplet is simulated for, unless it leaves the face first.
+    pub max_steps: usize,
+    /// How much of a droplet's direction is kept at each step, in [0, 1].
+    pub inertia: f32,
+    /// Sediment carried per unit of water, speed and height lost.
+    pub capacity: f32,
+    pub min_capacity: f32,
+    /// Fraction of the free capacity picked up at each step.
+    pub erosion_rate: f32,
+    /// Fraction of the excess sediment dropped at each step.
+    pub deposition_rate: f32,
+    /// Fraction of a droplet's water lost at each step.
+    pub evaporation_rate: f32,
+    pub gravity: f32,
+    /// Radius, in cells, of the area a droplet erodes around itself.
+    pub radius: usize,
+    /// Steepest stable slope, in degrees.
+    pub talus_angle: f32,
+    /// Passes of thermal erosion over each face, none if zero.
+    pub thermal_iterations: usize,
+}
+
+impl Default for ErosionSpec {
+    fn default() -> Self {
+        ErosionSpec {
+            num_droplets: 0,
+            resolution: 512,
+            max_steps: 64,
+            inertia: 0.05,
+            capacity: 4.0,
+            min_capacity: 0.01,
+            erosion_rate: 0.3,
+            deposition_rate: 0.3,
+            evaporation_rate: 0.01,
+            gravity: 4.0,
+            radius: 3,
+            talus_angle: 35.0,
+            thermal_iterations: 0,
+        }
+    }
+}
+
+impl ErosionSpec {
+    pub fn is_enabled(&self) -> bool {
+        self.num_droplets > 0 || self.thermal_iterations > 0
+    }
+}
+
+/// A square lattice of heights, `size` cells a side.
+#[derive(Clone, Debug)]
+pub struct HeightGrid {
+    size: usize,
+    heights: Vec<f32>,
+}
+
+impl HeightGrid {
+    pub fn from_fn<F: Fn(usize, usize) -> f32>(size: usize, height_at: F) -> Self {
+        assert!(size >= 2, "A height grid needs at least 2x2 cells.");
+        let mut heights = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                heights.push(height_at(x, y));
+            }
+        }
+        HeightGrid {
+            size: size,
+            heights: heights,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.heights[y * self.size + x]
+    }
+
+    /// Bilinearly interpolated height, `x` and `y` are clamped to the grid.
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let max = (self.size - 1) as f32;
+        let (x, y) = (x.max(0.0).min(max), y.max(0.0).min(max));
+        let (node_x, node_y) = (
+            (x as usize).min(self.size - 2),
+            (y as usize).min(self.size - 2),
+        );
+        self.height_and_gradient(node_x, node_y, x - node_x as f32, y - node_y as f32)
+            .0
+    }
+
+    /// Runs `spec.num_droplets` droplets over the grid.
+    pub fn erode<R: Rng>(&mut self, spec: &ErosionSpec, rng: &mut R) {
+        let brush = Brush::new(spec.radius);
+        let max = (self.size - 1) as f32;
+        for _ in 0..spec.num_droplets {
+            let (mut x, mut y) = (rng.gen_range(0.0, max), rng.gen_range(0.0, max));
+            let (mut direction_x, mut direction_y) = (0.0, 0.0);
+            let (mut speed, mut water, mut sediment) = (1.0, 1.0, 0.0);
+
+            for _ in 0..spec.max_steps {
+                let (node_x, node_y) = (x as usize, y as usize);
+                let (u, v) = (x - node_x as f32, y - node_y as f32);
+                let (height, gradient_x, gradient_y) =
+                    self.height_and_gradient(node_x, node_y, u, v);
+
+                direction_x = direction_x * spec.inertia - gradient_x * (1.0 - spec.inertia);
+                direction_y = direction_y * spec.inertia - gradient_y * (1.0 - spec.inertia);
+                let length = (direction_x * direction_x + direction_y * direction_y).sqrt();
+                if length < 1e-6 {
+                    break;
+                }
+                direction_x /= length;
+                direction_y /= length;
+                x += direction_x;
+                y += direction_y;
+                if x < 0.0 || x >= max || y < 0.0 || y >= max {
+                    break;
+                }
+
+                let delta = self.sample(x, y) - height;
+                let capacity = (-delta * speed * water * spec.capacity).max(spec.min_capacity);
+                if sediment > capacity || delta > 0.0 {
+                    // Going uphill fills the pit behind the droplet.
+                    let amount = if delta > 0.0 {
+                        delta.min(sediment)
+                    } else {
+                        (sediment - capacity) * spec.deposition_rate
+                    };
+                    sediment -= amount;
+                    self.deposit(node_x, node_y, u, v, amount);
+                } else {
+                    let amount = ((capacity - sediment) * spec.erosion_rate).min(-delta);
+                    self.erode_around(&brush, node_x, node_y, amount);
+                    sediment += amount;
+                }
+
+                speed = (speed * speed - delta * spec.gravity).max(0.0).sqrt();
+                water *= 1.0 - spec.evaporation_rate;
+            }
+        }
+    }
+
+    /// Moves material from every cell to its lower neighbours wherever the
+    /// slope between them is steeper than `talus_angle` degrees, repeated
+    /// `iterations` times. Heights are in cells, as for `erode`.
+    pub fn erode_thermal(&mut self, talus_angle: f32, iterations: usize) {
+        let talus = talus_angle.to_radians().tan();
+        let size = self.size as isize;
+        let mut deltas = vec![0.0; self.heights.len()];
+        for _ in 0..iterations {
+            for y in 0..size {
+                for x in 0..size {
+                    let height = self.heights[(y * size + x) as usize];
+                    let mut excesses = [0.0; 8];
+                    let (mut total_excess, mut max_excess) = (0.0, 0.0);
+                    for (index, &(offset_x, offset_y, distance)) in
+                        NEIGHBOURS.iter().enumerate()
+                    {
+                        let (neighbour_x, neighbour_y) = (x + offset_x, y + offset_y);
+                        if neighbour_x < 0 || neighbour_y < 0 || neighbour_x >= size ||
+                            neighbour_y >= size
+                        {
+                            continue;
+                        }
+                        let neighbour = self.heights[(neighbour_y * size + neighbour_x) as usize];
+                        let excess = height - neighbour - talus * distance;
+                        if excess > 0.0 {
+                            excesses[index] = excess;
+                            total_excess += excess;
+                            max_excess = f32::max(max_excess, excess);
+                        }
+                    }
+                    if total_excess == 0.0 {
+                        continue;
+                    }
+
+                    // Moving half the largest excess would level the steepest
+                    // pair, a smaller step keeps the pass stable.
+                    let moved = THERMAL_RATE * max_excess * 0.5;
+                    deltas[(y * size + x) as usize] -= moved;
+                    for (index, &(offset_x, offset_y, _)) in NEIGHBOURS.iter().enumerate() {
+                        if excesses[index] > 0.0 {
+                            let neighbour = ((y + offset_y) * size + x + offset_x) as usize;
+                            deltas[neighbour] += moved * excesses[index] / total_excess;
+                        }
+                    }
+                }
+            }
+            for (height, delta) in self.heights.iter_mut().zip(deltas.iter_mut()) {
+                *height += *delta;
+                *delta = 0.0;
+            }
+        }
+    }
+
+    /// The height and its gradient at offset `(u, v)` in the cell whose lower
+    /// corner is `(x, y)`.
+    fn height_and_gradient(&self, x: usize, y: usize, u: f32, v: f32) -> (f32, f32, f32) {
+        let h00 = self.get(x, y);
+        let h10 = self.get(x + 1, y);
+        let h01 = self.get(x, y + 1);
+        let h11 = self.get(x + 1, y + 1);
+        let gradient_x = (h10 - h00) * (1.0 - v) + (h11 - h01) * v;
+        let gradient_y = (h01 - h00) * (1.0 - u) + (h11 - h10) * u;
+        let height = h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v +
+            h11 * u * v;
+        (height, gradient_x, gradient_y)
+    }
+
+    fn deposit(&mut self, x: usize, y: usize, u: f32, v: f32, amount: f32) {
+        let size = self.size;
+        self.heights[y * size + x] += amount * (1.0 - u) * (1.0 - v);
+        self.heights[y * size + x + 1] += amount * u * (1.0 - v);
+        self.heights[(y + 1) * size + x] += amount * (1.0 - u) * v;
+        self.heights[(y + 1) * size + x + 1] += amount * u * v;
+    }
+
+    fn erode_around(&mut self, brush: &Brush, x: usize, y: usize, amount: f32) {
+        for &(offset_x, offset_y, weight) in brush.0.iter() {
+            let (brush_x, brush_y) = (x as isize + offset_x, y as isize + offset_y);
+            if brush_x >= 0 && brush_y >= 0 && (brush_x as usize) < self.size &&
+                (brush_y as usize) < self.size
+            {
+                self.heights[brush_y as usize * self.size + brush_x as usize] -= amount * weight;
+            }
+        }
+    }
+}
+
+/// Cell offsets within a radius and their weights, which fall off linearly
+/// with distance and sum up to one. Spreading erosion over several cells
+/// stops droplets from digging single cell pits.
+struct Brush(Vec<(isize, isize, f32)>);
+
+impl Brush {
+    fn new(radius: usize) -> Self {
+        let radius = radius.max(1) as isize;
+        let mut offsets = vec![];
+        for offset_y in -radius..radius + 1 {
+            for offset_x in -radius..radius + 1 {
+                let distance = ((offset_x * offset_x + offset_y * offset_y) as f32).sqrt();
+                if distance < radius as f32 {
+                    offsets.push((offset_x, offset_y, 1.0 - distance / radius as f32));
+                }
+            }
+        }
+        let total: f32 = offsets.iter().map(|&(_, _, weight)| weight).sum();
+        for offset in offsets.iter_mut() {
+            offset.2 /= total;
+        }
+        Brush(offsets)
+    }
+}
+
+/// Carves eroded valleys and sediment fans into a planet-like field, one whose
+/// value is the distance from the origin minus a surface radius depending only
+/// on direction, such as `PlanetField`.
+///
+/// The surface is baked into an equal angle cube-sphere heightmap, whose cells
+/// are about the same size everywhere, which is eroded face by face, first by
+/// droplets then thermally. Only the change in height is added back, so detail
+/// finer than a cell is kept, and it fades out towards the edges of the faces,
+/// which material can't cross, to keep the seams closed.
+pub struct ErodedField<Field> {
+    field: Field,
+    resolution: usize,
+    // The change in radius on each face, in world units.
+    faces: Vec<HeightGrid>,
+    // The lowest of them, the samples in between don't go below it.
+    lowest_delta: CpuScalar,
+}
+
+impl<Field: ScalarField3 + Sync> ErodedField<Field> {
+    pub fn new(field: Field, spec: &ErosionSpec, seed: u32) -> Self {
+        let _span = trace::span("erosion");
+        let time = Instant::now();
+        let resolution = spec.resolution.max(2);
+        let max = (resolution - 1) as f32;
+
+        let face_indices: Vec<usize> = (0..NUM_FACES).collect();
+        let mut faces = vec![];
+        face_indices
+            .par_iter()
+            .map(|&face| {
+                let radii = HeightGrid::from_fn(resolution, |x, y| {
+                    let direction = equal_angle_to_direction(
+                        face,
+                        2.0 * x as f32 / max - 1.0,
+                        2.0 * y as f32 / max - 1.0,
+                    );
+                    1.0 - field.value_at(&Point3::new(direction[0], direction[1], direction[2]))
+                });
+
+                // Droplets work in cells, so heights are scaled to match.
+                let mean_radius = radii.heights.iter().sum::<f32>() / radii.heights.len() as f32;
+                let cell_size = FRAC_PI_2 * mean_radius / max;
+                let mut eroded = HeightGrid::from_fn(resolution, |x, y| {
+                    (radii.get(x, y) - mean_radius) / cell_size
+                });
+                let mut rng = XorShiftRng::from_seed(
+                    [seed, face as u32 + 1, 0x9e37_79b9, 0x7f4a_7c15],
+                );
+                eroded.erode(spec, &mut rng);
+                eroded.erode_thermal(spec.talus_angle, spec.thermal_iterations);
+
+                HeightGrid::from_fn(resolution, |x, y| {
+                    let from_edge = x.min(y).min(resolution - 1 - x).min(resolution - 1 - y);
+                    let fade = (from_edge as f32 / EDGE_FADE_CELLS).min(1.0);
+                    let original = (radii.get(x, y) - mean_radius) / cell_size;
+                    (eroded.get(x, y) - original) * cell_size * fade
+                })
+            })
+            .collect_into(&mut faces);
+
+        info!(
+            "Eroded {}x{} cube faces with {} droplets and {} thermal passes each in {:?}.",
+            resolution,
+            resolution,
+            spec.num_droplets,
+            spec.thermal_iterations,
+            time.elapsed()
+        );
+        let lowest_delta = faces
+            .iter()
+            .flat_map(|face| face.heights.iter())
+            .fold(0.0f32, |lowest, &delta| lowest.min(delta));
+        ErodedField {
+            field: field,
+            resolution: resolution,
+            faces: faces,
+            lowest_delta: lowest_delta,
+        }
+    }
+}
+
+impl<Field> ErodedField<Field> {
+    /// How much was eroded from the terrain in the direction of `position`.
+    #[inline]
+    fn delta_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let direction = Vec3f::new(position[0], position[1], position[2]);
+        if direction.norm() < 1e-6 {
+            return 0.0;
+        }
+        let (face, u, v) = direction_to_equal_angle(&direction);
+        let max = (self.resolution - 1) as f32;
+        self.faces[face].sample((u + 1.0) * 0.5 * max, (v + 1.0) * 0.5 * max)
+    }
+}
+
+impl<Field: ScalarField3> ScalarField3 for ErodedField<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.field.value_at(position) - self.delta_at(position)
+    }
+
+    #[inline]
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        self.field.values_at(positions, values);
+        for (position, value) in positions.iter().zip(values.iter_mut()) {
+            *value -= self.delta_at(position);
+        }
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        self.field.value_at_lod(position, step) - self.delta_at(position)
+    }
+
+    #[inline]
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        step: CpuScalar,
+    ) {
+        self.field.values_at_lod(positions, values, step);
+        for (position, value) in positions.iter().zip(values.iter_mut()) {
+            *value -= self.delta_at(position);
+        }
+    }
+
+    #[inline]
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        difference_gradients(self, positions, gradients)
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(position)
+    }
+
+    #[inline]
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        self.field.stratum_at(position)
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+
+    #[inline]
+    fn gpu_cell_triangles(
+        &self,
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        self.field.gpu_cell_triangles(shape, values, iso_value)
+    }
+
+    /// The field's, shrunk by as much as the erosion lowered it anywhere.
+    #[inline]
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        self.field.occluder_radius().map(|radius| radius + self.lowest_delta.min(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+    use super::{ErosionSpec, HeightGrid};
+
+    #[test]
+    fn test_erosion_carves_slope() {
+        // A cone: droplets run down its flanks, eroding the top and
+        // depositing at the bottom.
+        let cone = |x: usize, y: usize| {
+            let (dx, dy) = (x as f32 - 31.5, y as f32 - 31.5);
+            20.0 - (dx * dx + dy * dy).sqrt() * 0.5
+        };
+        let mut grid = HeightGrid::from_fn(64, &cone);
+        let spec = ErosionSpec {
+            num_droplets: 2000,
+            ..Default::default()
+        };
+        grid.erode(&spec, &mut XorShiftRng::from_seed([1, 2, 3, 4]));
+
+        let mut changed = 0;
+        for y in 0..64 {
+            for x in 0..64 {
+                let height = grid.get(x, y);
+                assert!(height.is_finite());
+                if (height - cone(x, y)).abs() > 1e-3 {
+                    changed += 1;
+                }
+            }
+        }
+        assert!(changed > 64 * 64 / 4, "only {} cells changed", changed);
+        assert!(grid.get(24, 24) < cone(24, 24));
+    }
+
+    #[test]
+    fn test_thermal_erosion_relaxes_cliff() {
+        let cliff = |x: usize, _: usize| if x < 16 { 10.0 } else { 0.0 };
+        let mut grid = HeightGrid::from_fn(32, &cliff);
+        let total: f32 = grid.heights.iter().sum();
+        grid.erode_thermal(45.0, 500);
+
+        let steepest = (0..31)
+            .map(|x| (grid.get(x, 16) - grid.get(x + 1, 16)).abs())
+            .fold(0.0, f32::max);
+        assert!(steepest < 1.1, "steepest slope is {}", steepest);
+        let relaxed_total: f32 = grid.heights.iter().sum();
+        assert!((total - relaxed_total).abs() < 1e-2 * total);
+    }
+}
+
+// Fraction of the excess material a cell sheds in each thermal pass.
+const THERMAL_RATE: f32 = 0.5;
+// Offsets to the 8 neighbours of a cell and their distances.
+const NEIGHBOURS: [(isize, isize, f32); 8] = [
+    (-1, 0, 1.0),
+    (1, 0, 1.0),
+    (0, -1, 1.0),
+    (0, 1, 1.0),
+    (-1, -1, ::std::f32::consts::SQRT_2),
+    (1, -1, ::std::f32::consts::SQRT_2),
+    (-1, 1, ::std::f32::consts::SQRT_2),
+    (1, 1, ::std::f32::consts::SQRT_2),
+];
+// Cells over which erosion fades out towards the edges of a face.
+const EDGE_FADE_CELLS: f32 = 16.0;
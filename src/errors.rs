@@ -11,10 +11,59 @@ error_chain! {
     // the same as `quick_error!`, but the `from()` and `cause()`
     // syntax is not supported.
     errors {
+        AssetNotFound(name: String, root: String) {
+            description("Asset not found.")
+            display("Could not find asset '{}' in {}", name, root)
+        }
+        ChunkGenerationFailed(chunk: String, msg: String) {
+            description("Chunk generation failed.")
+            display("Could not generate chunk {}: {}", chunk, msg)
+        }
+        ConfigParseError(msg: String) {
+            description("Config parse error.")
+            display("Config parse error: {}", msg)
+        }
+        GpuDensityUnavailable(msg: String) {
+            description("The density field cannot be evaluated on the GPU.")
+            display("Cannot evaluate the density field on the GPU: {}", msg)
+        }
+        HeightmapSizeMismatch(path: String, expected: String, found: String) {
+            description("The heightmap is not of the expected size.")
+            display("Heightmap {} is {}, but {} was expected", path, found, expected)
+        }
+        InvalidConfigValue(key: String, expected: &'static str) {
+            description("Invalid value in config file.")
+            display("Invalid value for '{}' in config file, expected {}", key, expected)
+        }
+        InvalidEditLog(path: String, msg: String) {
+            description("Invalid terrain edit log.")
+            display("Invalid terrain edit log {}: {}", path, msg)
+        }
+        InvalidPdsLabel(path: String, msg: String) {
+            description("Invalid PDS label.")
+            display("Invalid PDS label {}: {}", path, msg)
+        }
+        InvalidMonitor(index: usize, num_monitors: usize) {
+            description("Invalid monitor index.")
+            display("Cannot use monitor {}, there are only {} monitors", index, num_monitors)
+        }
+        InvalidLogFilter(filter: String) {
+            description("Invalid log filter.")
+            display("Invalid log filter '{}', expected [module=]level", filter)
+        }
+        InvalidMeshData(msg: String) {
+            description("Invalid mesh data.")
+            display("Invalid mesh data: {}", msg)
+        }
         LoadAssetError(msg: String) {
             description("Asset load error.")
             display("Asset load error: '{}'", msg)
         }
+        MeshNotClosed(open_edges: usize, non_manifold_edges: usize) {
+            description("The mesh is not closed.")
+            display("The mesh is not closed: {} edges border holes and {} are shared by more \
+                     than two triangles or wound the same way", open_edges, non_manifold_edges)
+        }
         MissingGlutinWindow {
             description("The glutin window is missing.")
             display("The glutin window is missing.")
@@ -23,6 +72,10 @@ error_chain! {
             description("Could not set cursor position.")
             display("Could not set cursor position to ({}, {})", x, y)
         }
+        UnsupportedPdsSampleType(sample_type: String, bits: usize) {
+            description("Unsupported PDS sample type.")
+            display("PDS samples of type {} and {} bits are not supported", sample_type, bits)
+        }
         UnexhaustedHeightmapFile {
             description("More data than expected in heightmap file.")
             display("More data than expected in heightmap file.")
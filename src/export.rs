@@ -0,0 +1,693 @@
+use std::f32::consts::PI;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use nalgebra::{Dot, Norm, Point3};
+use png::{self, HasParameters};
+use rayon::prelude::*;
+
+use errors::{ChainErr, ErrorKind, Result};
+use flora::{Biome, FloraSpec};
+use gfx::{LodSpec, Mesh, Vertex, NO_SEAMS};
+use gfx::lod::{chunk_to_mesh, surface_chunk_ids, ChunkId};
+use gfx::mesh::{write_mesh_to_stl, write_obj_object};
+use gfx::mesh_format::{write_mesh, MeshCompression};
+use math::{CpuScalar, Material, ScalarField3};
+
+/// Meshes every chunk at octree `level` that intersects the surface of
+/// `scalar_field` and writes the meshes to `path` as an OBJ file, an object
+/// with normals for each chunk. Unlike `App`, this doesn't need a window or a
+/// GL context.
+///
+/// The triangles are grouped by what the ground is, with what grows on the
+/// rock of a body of `base_radius` going by `flora_spec`, and the colours of
+/// these materials go in an MTL file next to the OBJ.
+pub fn export_to_obj<Field, P>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    flora_spec: &FloraSpec,
+    base_radius: f32,
+    level: u8,
+    path: P,
+) -> Result<()>
+where
+    Field: ScalarField3 + Sync,
+    P: AsRef<Path> + Debug,
+{
+    let mut chunks = try!(surface_chunk_meshes(scalar_field, lod_spec, level));
+    let materials: Vec<Vec<&str>> = chunks
+        .iter()
+        .map(|&(_, ref mesh)| {
+            mesh.indices
+                .chunks(3)
+                .map(|triangle| {
+                    ObjMaterial::of_triangle(scalar_field, flora_spec, base_radius, mesh, triangle)
+                        .name()
+                })
+                .collect()
+        })
+        .collect();
+    for &mut (ref chunk_id, ref mut mesh) in chunks.iter_mut() {
+        mesh.name = chunk_name(chunk_id, lod_spec.voxel_density);
+    }
+
+    let path = path.as_ref();
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = match mtl_path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(format!("Invalid OBJ path {:?}.", path).into()),
+    };
+    let file = try!(File::create(&mtl_path).chain_err(
+        || format!("Could not create {:?}.", mtl_path),
+    ));
+    try!(write_mtl(&mut BufWriter::new(file)).chain_err(
+        || format!("Could not write materials to {:?}.", mtl_path),
+    ));
+    let file = try!(File::create(path).chain_err(
+        || format!("Could not create {:?}.", path),
+    ));
+    try!(write_obj(&chunks, &materials, &mtl_name, &mut BufWriter::new(file)).chain_err(
+        || format!("Could not write mesh to {:?}.", path),
+    ));
+    info!("Wrote {} chunks to {:?}.", chunks.len(), path);
+    Ok(())
+}
+
+/// The name of the object of the chunk with `chunk_id`, on a grid of
+/// `voxel_density`, in exported files.
+fn chunk_name(chunk_id: &ChunkId, voxel_density: f32) -> String {
+    let position = chunk_id.position(voxel_density);
+    format!(
+        "chunk_{}_{}_{}_{}",
+        position[0],
+        position[1],
+        position[2],
+        chunk_id.size(voxel_density)
+    )
+}
+
+/// The materials of the triangles of exported OBJs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ObjMaterial {
+    Rock = 0,
+    Forest = 1,
+    Scrub = 2,
+    Lava = 3,
+    Snow = 4,
+    Sand = 5,
+}
+
+impl ObjMaterial {
+    /// That of the ground at the centroid of `triangle` in `mesh`.
+    fn of_triangle<Field: ScalarField3>(
+        scalar_field: &Field,
+        flora_spec: &FloraSpec,
+        base_radius: f32,
+        mesh: &Mesh<Vertex>,
+        triangle: &[u32],
+    ) -> Self {
+        let (a, b, c) = (
+            &mesh.vertices[triangle[0] as usize],
+            &mesh.vertices[triangle[1] as usize],
+            &mesh.vertices[triangle[2] as usize],
+        );
+        let centroid = (*a.position + *b.position + *c.position) / 3.0;
+        match scalar_field.material_at(&centroid.to_point()) {
+            Material::Lava => return ObjMaterial::Lava,
+            Material::Snow => return ObjMaterial::Snow,
+            Material::Sand => return ObjMaterial::Sand,
+            Material::Rock => {}
+        }
+        // The normals of the vertices point into the ground.
+        let (up, normal) = (centroid.normalize(), -(*a.normal + *b.normal + *c.normal));
+        let slope = (normal.dot(&up) / normal.norm())
+            .max(-1.0)
+            .min(1.0)
+            .acos()
+            .to_degrees();
+        let altitude = centroid.norm() - base_radius;
+        match Biome::at(flora_spec, Material::Rock, altitude, slope) {
+            Biome::Forest => ObjMaterial::Forest,
+            Biome::Scrub => ObjMaterial::Scrub,
+            Biome::Barren => ObjMaterial::Rock,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        OBJ_MATERIALS[*self as usize].0
+    }
+}
+
+/// Writes every material in `OBJ_MATERIALS` in the MTL format.
+fn write_mtl<W: Write>(writer: &mut W) -> ::std::io::Result<()> {
+    for &(name, color) in OBJ_MATERIALS.iter() {
+        try!(writeln!(writer, "newmtl {}", name));
+        try!(writeln!(writer, "Kd {} {} {}", color[0], color[1], color[2]));
+        try!(writeln!(writer, "illum 1"));
+    }
+    writer.flush()
+}
+
+/// Writes each mesh of `chunks` as an object, with its triangles grouped by
+/// their `materials` from the library `mtl_name`.
+fn write_obj<W: Write>(
+    chunks: &[(ChunkId, Mesh<Vertex>)],
+    materials: &[Vec<&str>],
+    mtl_name: &str,
+    writer: &mut W,
+) -> ::std::io::Result<()> {
+    try!(writeln!(writer, "mtllib {}", mtl_name));
+    let mut offset = 0;
+    for (&(_, ref mesh), materials) in chunks.iter().zip(materials.iter()) {
+        try!(write_obj_object(mesh, offset, materials, writer));
+        offset += mesh.vertices.len() as u32;
+    }
+    writer.flush()
+}
+
+/// Like `export_to_obj`, but as a binary STL for 3D printing. The meshes of
+/// the chunks are welded into one, which must be closed for a printer to
+/// make sense of it, so an error is returned if it has any holes.
+pub fn export_to_stl<Field, P>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    level: u8,
+    path: P,
+) -> Result<()>
+where
+    Field: ScalarField3 + Sync,
+    P: AsRef<Path> + Debug,
+{
+    // The chunks share the points of the grid on the faces between them.
+    let mut mesh = try!(surface_mesh(scalar_field, lod_spec, level));
+    mesh.weld(0.0);
+    // The meshes wind their triangles facing into the solid.
+    for triangle in mesh.indices.chunks_mut(3) {
+        triangle.swap(1, 2);
+    }
+    try!(check_closed(&mesh));
+    info!(
+        "Welded into a closed mesh with {} vertices and {} triangles.",
+        mesh.vertices.len(),
+        mesh.indices.len() / 3
+    );
+
+    let file = try!(File::create(path.as_ref()).chain_err(
+        || format!("Could not create {:?}.", path),
+    ));
+    try!(write_mesh_to_stl(&mesh, &mut BufWriter::new(file)));
+    info!("Wrote mesh to {:?}.", path);
+    Ok(())
+}
+
+/// Like `export_to_obj`, but in the binary mesh format of `mesh_format`, the
+/// mesh of each chunk after the other with their bodies stored with
+/// `compression`, for tools which read them back with `read_mesh`.
+pub fn export_to_meshes<Field, P>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    level: u8,
+    compression: MeshCompression,
+    path: P,
+) -> Result<()>
+where
+    Field: ScalarField3 + Sync,
+    P: AsRef<Path> + Debug,
+{
+    let chunks = try!(surface_chunk_meshes(scalar_field, lod_spec, level));
+    let file = try!(File::create(path.as_ref()).chain_err(
+        || format!("Could not create {:?}.", path),
+    ));
+    let mut writer = BufWriter::new(file);
+    for (chunk_id, mut mesh) in chunks.into_iter() {
+        mesh.name = chunk_name(&chunk_id, lod_spec.voxel_density);
+        try!(write_mesh(&mesh, compression, &mut writer).chain_err(
+            || format!("Could not write mesh to {:?}.", path),
+        ));
+    }
+    try!(writer.flush().chain_err(
+        || format!("Could not write mesh to {:?}.", path),
+    ));
+    info!("Wrote meshes to {:?}.", path);
+    Ok(())
+}
+
+/// The merged meshes of the chunks at octree `level` that intersect the
+/// surface of `scalar_field`.
+fn surface_mesh<Field>(scalar_field: &Field, lod_spec: &LodSpec, level: u8) -> Result<Mesh<Vertex>>
+where
+    Field: ScalarField3 + Sync,
+{
+    let chunks = try!(surface_chunk_meshes(scalar_field, lod_spec, level));
+    let chunk_meshes: Vec<_> = chunks.into_iter().map(|(_, mesh)| mesh).collect();
+    let mut mesh = Mesh::merge(&chunk_meshes);
+    mesh.name = "planet".to_owned();
+    Ok(mesh)
+}
+
+/// The meshes of the chunks at octree `level` that intersect the surface of
+/// `scalar_field`, leaving out those that turn out empty.
+fn surface_chunk_meshes<Field>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    level: u8,
+) -> Result<Vec<(ChunkId, Mesh<Vertex>)>>
+where
+    Field: ScalarField3 + Sync,
+{
+    let time = Instant::now();
+    let chunk_ids = surface_chunk_ids(scalar_field, lod_spec, level);
+    info!("Meshing {} chunks at level {}.", chunk_ids.len(), level);
+
+    let mut chunk_meshes = vec![];
+    chunk_ids
+        .par_iter()
+        .map(|chunk_id| {
+            let mesher = lod_spec.mesher(scalar_field, chunk_id);
+            chunk_to_mesh(scalar_field, lod_spec, chunk_id, mesher, &NO_SEAMS, true)
+        })
+        .collect_into(&mut chunk_meshes);
+
+    let mut chunks = vec![];
+    let (mut num_vertices, mut num_triangles) = (0, 0);
+    for (chunk_id, chunk_mesh) in chunk_ids.into_iter().zip(chunk_meshes.into_iter()) {
+        let chunk_mesh = try!(chunk_mesh);
+        if !chunk_mesh.indices.is_empty() {
+            num_vertices += chunk_mesh.vertices.len();
+            num_triangles += chunk_mesh.indices.len() / 3;
+            chunks.push((chunk_id, chunk_mesh));
+        }
+    }
+    info!(
+        "Generated meshes with {} vertices and {} triangles in {:?}.",
+        num_vertices,
+        num_triangles,
+        time.elapsed()
+    );
+    Ok(chunks)
+}
+
+/// Checks that every edge of `mesh` is shared by exactly two triangles, which
+/// go along it in opposite directions, so it encloses a solid.
+fn check_closed(mesh: &Mesh<Vertex>) -> Result<()> {
+    let mut edges = HashMap::new();
+    for triangle in mesh.indices.chunks(3) {
+        for corner in 0..3 {
+            let edge = (triangle[corner], triangle[(corner + 1) % 3]);
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+    let mut num_open = 0;
+    let mut num_non_manifold = 0;
+    for (&(a, b), &count) in edges.iter() {
+        match (count, edges.get(&(b, a)).cloned().unwrap_or(0)) {
+            (1, 1) => {}
+            (1, 0) => num_open += 1,
+            _ => num_non_manifold += 1,
+        }
+    }
+    if num_open > 0 || num_non_manifold > 0 || mesh.indices.is_empty() {
+        return Err(ErrorKind::MeshNotClosed(num_open, num_non_manifold).into());
+    }
+    Ok(())
+}
+
+/// Bakes the surface of `scalar_field`, a body of `radius`, to an
+/// equirectangular 16 bit grayscale PNG at `path` of `size` samples, in the
+/// longitudes and latitudes of `Heightmap`: the first and last columns are
+/// both on the meridian, the first and last rows at the poles. The surface is
+/// looked for along rays from the centre up to `max_relief` above and below
+/// `radius`. Next to the image, with the extension `json`, goes the radius and
+/// how to scale the samples back to heights, so other tools can use it. They
+/// are in world units, kilometres as `Heightmap` takes them, and those which
+/// aren't finite, e.g. when no surface was found, are null.
+pub fn export_heightmap<Field, P>(
+    scalar_field: &Field,
+    radius: CpuScalar,
+    max_relief: CpuScalar,
+    (width, height): (usize, usize),
+    path: P,
+) -> Result<()>
+where
+    Field: ScalarField3 + Sync,
+    P: AsRef<Path> + Debug,
+{
+    let time = Instant::now();
+    info!("Baking a heightmap of {} x {} samples.", width, height);
+    let rows: Vec<usize> = (0..height).collect();
+    let mut row_heights = vec![];
+    rows.par_iter()
+        .map(|&y| {
+            let lat = y as CpuScalar / (height - 1) as CpuScalar;
+            (0..width)
+                .map(|x| {
+                    let long = x as CpuScalar / (width - 1) as CpuScalar;
+                    surface_height(scalar_field, radius, max_relief, long, lat)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect_into(&mut row_heights);
+    let heights: Vec<CpuScalar> = row_heights.into_iter().flat_map(|row| row).collect();
+    let (min_height, max_height) = heights.iter().fold(
+        (CpuScalar::INFINITY, CpuScalar::NEG_INFINITY),
+        |(low, high), &height| (low.min(height), high.max(height)),
+    );
+    info!(
+        "Baked heights in [{}, {}] in {:?}.",
+        min_height,
+        max_height,
+        time.elapsed()
+    );
+
+    let scale = (max_height - min_height) / u16::max_value() as CpuScalar;
+    let mut bytes = Vec::with_capacity(heights.len() * 2);
+    for &height in &heights {
+        let value = if scale > 0.0 {
+            ((height - min_height) / scale).round() as u16
+        } else {
+            0
+        };
+        bytes.push((value >> 8) as u8);
+        bytes.push(value as u8);
+    }
+    let file = try!(File::create(path.as_ref()).chain_err(
+        || format!("Could not create heightmap {:?}.", path),
+    ));
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set(png::ColorType::Grayscale).set(png::BitDepth::Sixteen);
+    try!(
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&bytes))
+            .chain_err(|| format!("Could not write heightmap {:?}.", path))
+    );
+
+    // A sample of value v is at the height min_height + scale * v above the
+    // radius.
+    let sidecar_path = path.as_ref().with_extension("json");
+    let mut sidecar = try!(File::create(&sidecar_path).chain_err(
+        || format!("Could not create {:?}.", sidecar_path),
+    ));
+    try!(
+        write!(
+            sidecar,
+            "{{\n  \"projection\": \"equirectangular\",\n  \"width\": {},\n  \"height\": {},\n  \
+             \"units\": \"km\",\n  \"radius\": {},\n  \"min_height\": {},\n  \
+             \"max_height\": {},\n  \"scale\": {}\n}}\n",
+            width,
+            height,
+            json_number(radius),
+            json_number(min_height),
+            json_number(max_height),
+            json_number(scale)
+        ).chain_err(|| format!("Could not write {:?}.", sidecar_path))
+    );
+    info!("Wrote heightmap to {:?} and {:?}.", path, sidecar_path);
+    Ok(())
+}
+
+/// `value` as a JSON number, or null if it isn't finite, as JSON has no NaN
+/// or infinity.
+fn json_number(value: CpuScalar) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// The height above `radius` of the outermost surface in the direction of
+/// the longitude and latitude, in [0, 1] like those of `Heightmap`. Heights
+/// beyond `max_relief` are clamped to it.
+fn surface_height<Field>(
+    scalar_field: &Field,
+    radius: CpuScalar,
+    max_relief: CpuScalar,
+    long: CpuScalar,
+    lat: CpuScalar,
+) -> CpuScalar
+where
+    Field: ScalarField3 + ?Sized,
+{
+    let (angle, polar) = (long * 2.0 * PI - PI, lat * PI);
+    let direction = (polar.sin() * angle.cos(), polar.cos(), polar.sin() * angle.sin());
+    let value_at = |height: CpuScalar| {
+        let distance = radius + height;
+        scalar_field.value_at(&Point3::new(
+            direction.0 * distance,
+            direction.1 * distance,
+            direction.2 * distance,
+        ))
+    };
+
+    // Marches in from above the highest surface to the first step inside
+    // it, then bisects the step. Overhangs and caves below are missed.
+    let step = 2.0 * max_relief / BAKE_MARCH_STEPS as CpuScalar;
+    let mut above = max_relief;
+    if value_at(above) <= 0.0 {
+        return max_relief;
+    }
+    let mut inside = None;
+    for _ in 0..BAKE_MARCH_STEPS {
+        let next = above - step;
+        if value_at(next) <= 0.0 {
+            inside = Some(next);
+            break;
+        }
+        above = next;
+    }
+    let mut below = match inside {
+        Some(below) => below,
+        None => return -max_relief,
+    };
+    for _ in 0..BAKE_BISECTIONS {
+        let middle = (above + below) / 2.0;
+        if value_at(middle) > 0.0 {
+            above = middle;
+        } else {
+            below = middle;
+        }
+    }
+    (above + below) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::f32::consts::PI;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use nalgebra::{Cross, Dot, Norm, Point2, Point3, Vector3};
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use gfx::{LodSpec, Mesh, Vertex};
+    use gfx::mesh_format::{read_mesh, MeshCompression};
+    use wavefront_obj;
+
+    use flora::FloraSpec;
+    use heightmap::Heightmap;
+    use math::{CpuScalar, Material, ScalarField2, ScalarField3};
+    use super::{export_heightmap, export_to_meshes, export_to_obj, export_to_stl,
+                json_number, surface_height};
+
+    /// A sphere of radius 10 higher in the north and the east.
+    struct Bumpy;
+
+    impl ScalarField3 for Bumpy {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            let offset = Vector3::new(position[0], position[1], position[2]);
+            let distance = offset.norm();
+            distance - 10.0 - 0.5 * position[1] / distance - 0.2 * position[0] / distance
+        }
+    }
+
+    fn bump(long: CpuScalar, lat: CpuScalar) -> CpuScalar {
+        0.5 * (lat * PI).cos() + 0.2 * (lat * PI).sin() * (long * 2.0 * PI - PI).cos()
+    }
+
+    /// A ball of radius 10, off the grid points so its centre isn't sampled,
+    /// with a cap of snow.
+    struct Ball;
+
+    impl ScalarField3 for Ball {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            (Vector3::new(position[0], position[1], position[2]) - Vector3::new(0.1, 0.2, 0.3))
+                .norm() - 10.0
+        }
+
+        fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+            if position[1] > 8.0 {
+                Material::Snow
+            } else {
+                Material::Rock
+            }
+        }
+    }
+
+    /// The ground below y = 0.
+    struct Plane;
+
+    impl ScalarField3 for Plane {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            position[1]
+        }
+    }
+
+    #[test]
+    fn test_export_to_obj() {
+        let lod_spec = LodSpec {
+            size: 64.0,
+            max_level: 2,
+            ..LodSpec::default()
+        };
+        let path = env::temp_dir().join(format!("terrain-test-{}.obj", ::std::process::id()));
+        export_to_obj(&Ball, &lod_spec, &FloraSpec::default(), 10.0, 2, &path).unwrap();
+        let mut mtl = String::new();
+        File::open(path.with_extension("mtl"))
+            .unwrap()
+            .read_to_string(&mut mtl)
+            .unwrap();
+        let mut obj = String::new();
+        File::open(&path).unwrap().read_to_string(&mut obj).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("mtl"));
+
+        let obj_set = wavefront_obj::obj::parse(obj).unwrap();
+        let mtl_name = format!("terrain-test-{}.mtl", ::std::process::id());
+        assert_eq!(Some(mtl_name), obj_set.material_library);
+        // The ball is within the eight chunks around the centre.
+        assert_eq!(8, obj_set.objects.len());
+        let mut materials = vec![];
+        for object in obj_set.objects.iter() {
+            assert!(object.name.starts_with("chunk_"));
+            assert_eq!(object.vertices.len(), object.normals.len());
+            for geometry in object.geometry.iter().filter(|g| !g.shapes.is_empty()) {
+                let material = geometry.material_name.clone().unwrap();
+                assert!(mtl.contains(&format!("newmtl {}\n", material)));
+                materials.push(material);
+            }
+        }
+        materials.sort();
+        materials.dedup();
+        // Flat, low rock is forested.
+        assert_eq!(vec!["forest".to_owned(), "snow".to_owned()], materials);
+    }
+
+    #[test]
+    fn test_export_to_stl() {
+        let lod_spec = LodSpec {
+            size: 64.0,
+            max_level: 2,
+            ..LodSpec::default()
+        };
+        let path = env::temp_dir().join(format!("terrain-test-{}.stl", ::std::process::id()));
+        export_to_stl(&Ball, &lod_spec, 2, &path).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let mut header = [0u8; 80];
+        file.read_exact(&mut header).unwrap();
+        let num_triangles = file.read_u32::<LittleEndian>().unwrap() as usize;
+        assert!(num_triangles > 0);
+        assert_eq!((84 + 50 * num_triangles) as u64, fs::metadata(&path).unwrap().len());
+
+        // Wound outwards, the triangles add up to the volume of the ball.
+        let mut volume = 0.0;
+        for _ in 0..num_triangles {
+            let mut values = [0.0; 12];
+            for value in values.iter_mut() {
+                *value = file.read_f32::<LittleEndian>().unwrap();
+            }
+            file.read_u16::<LittleEndian>().unwrap();
+            let corner = |i: usize| Vector3::new(values[i], values[i + 1], values[i + 2]);
+            volume += corner(3).dot(&corner(6).cross(&corner(9))) / 6.0;
+        }
+        let _ = fs::remove_file(&path);
+        let ball = 4.0 / 3.0 * PI * 1000.0;
+        assert!((volume - ball).abs() < ball * 0.02, "{} {}", volume, ball);
+
+        // The plane leaves the octree through its sides.
+        assert!(export_to_stl(&Plane, &lod_spec, 2, &path).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_export_to_meshes() {
+        let lod_spec = LodSpec {
+            size: 64.0,
+            max_level: 2,
+            ..LodSpec::default()
+        };
+        let path = env::temp_dir().join(format!("terrain-test-{}.meshes", ::std::process::id()));
+        export_to_meshes(&Ball, &lod_spec, 2, MeshCompression::Deflate, &path).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let mut meshes: Vec<Mesh<Vertex>> = vec![];
+        while let Some(mesh) = read_mesh(&mut file).unwrap() {
+            meshes.push(mesh);
+        }
+        let _ = fs::remove_file(&path);
+        assert_eq!(8, meshes.len());
+        for mesh in meshes.iter() {
+            assert!(mesh.name.starts_with("chunk_"));
+            assert!(!mesh.indices.is_empty());
+            let radius = mesh.vertices[0].position.norm();
+            assert!((radius - 10.0).abs() < 0.5, "{}", radius);
+        }
+    }
+
+    #[test]
+    fn test_export_heightmap() {
+        for &(long, lat) in &[(0.0, 0.0), (0.3, 0.2), (0.5, 0.5), (0.9, 0.8), (1.0, 1.0)] {
+            let height = surface_height(&Bumpy, 10.0, 1.0, long, lat);
+            assert!((height - bump(long, lat)).abs() < 1e-3, "{} {} {}", long, lat, height);
+        }
+        // Past the relief the height is clamped to it.
+        assert_eq!(0.25, surface_height(&Bumpy, 10.0, 0.25, 0.5, 0.0));
+        assert_eq!("null", json_number(CpuScalar::NAN));
+        assert_eq!("null", json_number(CpuScalar::INFINITY));
+        assert_eq!("0.5", json_number(0.5));
+
+        // Loaded back with the heights of the sidecar, in metres on a body
+        // measured in kilometres.
+        let path = env::temp_dir().join(format!("terrain-test-{}.png", ::std::process::id()));
+        export_heightmap(&Bumpy, 10.0, 1.0, (64, 32), &path).unwrap();
+        let mut sidecar = String::new();
+        File::open(path.with_extension("json"))
+            .unwrap()
+            .read_to_string(&mut sidecar)
+            .unwrap();
+        let number = |key: &str| -> CpuScalar {
+            let start = sidecar.find(&format!("\"{}\": ", key)).unwrap() + key.len() + 4;
+            let end = start + sidecar[start..].find(|c| c == ',' || c == '\n').unwrap();
+            sidecar[start..end].parse().unwrap()
+        };
+        assert!(sidecar.contains("\"units\": \"km\""));
+        assert_eq!(10.0, number("radius"));
+        let range = (number("min_height") * 1000.0, number("max_height") * 1000.0);
+        let heightmap = Heightmap::from_image(10.0, &path, Some(range), None).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("json"));
+        for &(long, lat) in &[(0.1, 0.3), (0.5, 0.5), (0.75, 0.9)] {
+            let height = ScalarField2::value_at(&heightmap, &Point2::new(long, lat)) / 1000.0;
+            assert!((height - bump(long, lat)).abs() < 1e-2, "{} {} {}", long, lat, height);
+        }
+    }
+}
+
+// The names of the materials of exported OBJs and their colours, like those
+// the planet and its plants are drawn with, in the order of `ObjMaterial`.
+const OBJ_MATERIALS: [(&'static str, [f32; 3]); 6] = [
+    ("rock", [0.83, 0.25, 0.07]),
+    ("forest", [0.12, 0.32, 0.1]),
+    ("scrub", [0.35, 0.4, 0.14]),
+    ("lava", [1.0, 0.35, 0.05]),
+    ("snow", [0.92, 0.95, 1.0]),
+    ("sand", [0.86, 0.76, 0.52]),
+];
+// Steps of the march through the relief along each ray when baking a
+// heightmap, and halvings of the step the surface is found in.
+const BAKE_MARCH_STEPS: usize = 48;
+const BAKE_BISECTIONS: usize = 16;
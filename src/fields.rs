@@ -0,0 +1,231 @@
+use nalgebra::{Point3, Vector2, Vector3, Norm};
+
+use math::{CpuScalar, ScalarField3, Vec3f};
+use math::scalar_field::{Difference, Intersection, Offset, Scale, SmoothUnion, Translate, Union};
+
+/// A solid added to or carved out of a planet, e.g. a tunnel through it or a
+/// ring around it, listed in the `[[planet.solids]]` tables of the config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolidSpec {
+    pub shape: SolidShape,
+    pub operation: SolidOperation,
+    /// Where the centre of the shape is, relative to that of the planet.
+    pub position: Vec3f,
+    /// The radius of the sphere or of the torus' ring, or half the side of
+    /// the cube, in world units.
+    pub size: f32,
+    /// The radius of the torus' tube, as a fraction of `size`.
+    pub thickness: f32,
+    /// Grows the solid by this many world units all around, rounding off its
+    /// edges.
+    pub rounding: f32,
+    /// How far apart the surfaces are blended in a smooth union.
+    pub blend: f32,
+}
+
+impl Default for SolidSpec {
+    fn default() -> Self {
+        SolidSpec {
+            shape: SolidShape::Sphere,
+            operation: SolidOperation::Union,
+            position: Vec3f::new(0.0, 0.0, 0.0),
+            size: 100.0,
+            thickness: 0.25,
+            rounding: 0.0,
+            blend: 50.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolidShape {
+    Sphere,
+    Cube,
+    Torus,
+}
+
+impl SolidShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sphere" => Some(SolidShape::Sphere),
+            "cube" => Some(SolidShape::Cube),
+            "torus" => Some(SolidShape::Torus),
+            _ => None,
+        }
+    }
+}
+
+/// How a solid is combined with the planet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolidOperation {
+    Union,
+    /// Only what's inside both is kept.
+    Intersection,
+    /// The solid is carved out of the planet.
+    Difference,
+    /// A union with the surfaces blended where they meet.
+    SmoothUnion,
+}
+
+impl SolidOperation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "union" => Some(SolidOperation::Union),
+            "intersection" => Some(SolidOperation::Intersection),
+            "difference" => Some(SolidOperation::Difference),
+            "smooth_union" => Some(SolidOperation::SmoothUnion),
+            _ => None,
+        }
+    }
+}
+
+/// `field` with each of `solids` combined with it in turn.
+pub fn with_solids(
+    field: Box<ScalarField3 + Send + Sync>,
+    solids: &[SolidSpec],
+) -> Box<ScalarField3 + Send + Sync> {
+    solids.iter().fold(field, |field, solid| {
+        let shape: Box<ScalarField3 + Send + Sync> = match solid.shape {
+            SolidShape::Sphere => Box::new(SphereField::new(1.0)),
+            SolidShape::Cube => Box::new(SquareField::new(1.0)),
+            SolidShape::Torus => Box::new(TorusField::new(1.0, solid.thickness)),
+        };
+        let shape = Translate::new(
+            Offset::new(Scale::new(shape, solid.size), solid.rounding),
+            Vector3::new(solid.position[0], solid.position[1], solid.position[2]),
+        );
+        match solid.operation {
+            SolidOperation::Union => Box::new(Union::new(field, shape)),
+            SolidOperation::Intersection => Box::new(Intersection::new(field, shape)),
+            SolidOperation::Difference => Box::new(Difference::new(field, shape)),
+            SolidOperation::SmoothUnion => Box::new(SmoothUnion::new(field, shape, solid.blend)),
+        }
+    })
+}
+
+/// Signed distance to a sphere centred at the origin.
+pub struct SphereField {
+    radius: CpuScalar,
+}
+
+impl SphereField {
+    pub fn new(radius: CpuScalar) -> Self {
+        SphereField { radius: radius }
+    }
+}
+
+impl ScalarField3 for SphereField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        position.to_vector().norm() - self.radius
+    }
+}
+
+/// Signed distance to an axis aligned cube centred at the origin.
+pub struct SquareField {
+    half_size: CpuScalar,
+}
+
+impl SquareField {
+    pub fn new(half_size: CpuScalar) -> Self {
+        SquareField { half_size: half_size }
+    }
+}
+
+impl ScalarField3 for SquareField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let q = Vector3::new(
+            position[0].abs() - self.half_size,
+            position[1].abs() - self.half_size,
+            position[2].abs() - self.half_size,
+        );
+        let outside = Vector3::new(q[0].max(0.0), q[1].max(0.0), q[2].max(0.0)).norm();
+        let inside = q[0].max(q[1]).max(q[2]).min(0.0);
+        outside + inside
+    }
+}
+
+/// Signed distance to a torus lying in the xz plane, centred at the origin.
+pub struct TorusField {
+    major_radius: CpuScalar,
+    minor_radius: CpuScalar,
+}
+
+impl TorusField {
+    pub fn new(major_radius: CpuScalar, minor_radius: CpuScalar) -> Self {
+        TorusField {
+            major_radius: major_radius,
+            minor_radius: minor_radius,
+        }
+    }
+}
+
+impl ScalarField3 for TorusField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let ring_distance = Vector2::new(position[0], position[2]).norm() - self.major_radius;
+        Vector2::new(ring_distance, position[1]).norm() - self.minor_radius
+    }
+}
+
+/// The half-space below the `y = height` plane.
+pub struct FlatField {
+    height: CpuScalar,
+}
+
+impl FlatField {
+    pub fn new(height: CpuScalar) -> Self {
+        FlatField { height: height }
+    }
+}
+
+impl ScalarField3 for FlatField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        position[1] - self.height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use math::{ScalarField3, Vec3f};
+    use super::*;
+
+    #[test]
+    fn test_distance_fields() {
+        let square = SquareField::new(1.0);
+        assert_eq!(-1.0, square.value_at(&Point3::new(0.0, 0.0, 0.0)));
+        assert_eq!(0.0, square.value_at(&Point3::new(1.0, 0.5, -0.5)));
+        assert_eq!(2.0, square.value_at(&Point3::new(0.0, -3.0, 0.0)));
+
+        let torus = TorusField::new(4.0, 1.0);
+        assert_eq!(-1.0, torus.value_at(&Point3::new(4.0, 0.0, 0.0)));
+        assert_eq!(3.0, torus.value_at(&Point3::new(0.0, 0.0, 0.0)));
+
+        let flat = FlatField::new(2.0);
+        assert_eq!(-2.0, flat.value_at(&Point3::new(10.0, 0.0, -10.0)));
+    }
+
+    #[test]
+    fn test_solids_carve_the_field() {
+        let tunnel = SolidSpec {
+            operation: SolidOperation::Difference,
+            position: Vec3f::new(10.0, 0.0, 0.0),
+            size: 2.0,
+            ..SolidSpec::default()
+        };
+        let moon = SolidSpec {
+            position: Vec3f::new(0.0, 20.0, 0.0),
+            size: 3.0,
+            rounding: 1.0,
+            ..SolidSpec::default()
+        };
+        let field = with_solids(Box::new(SphereField::new(10.0)), &[tunnel, moon]);
+        assert!(field.value_at(&Point3::new(5.0, 0.0, 0.0)) < 0.0);
+        assert_eq!(2.0, field.value_at(&Point3::new(10.0, 0.0, 0.0)));
+        assert_eq!(-4.0, field.value_at(&Point3::new(0.0, 20.0, 0.0)));
+    }
+}
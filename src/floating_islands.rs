@@ -0,0 +1,194 @@
+use nalgebra::{Norm, Point3};
+use noise::Seed;
+
+use fractal::{Fractal, FractalNoise, NoiseBasis};
+use math::{CpuScalar, ScalarField3, Vec3f};
+
+/// Islands of rock hovering in layers above the surface of a planet, with
+/// flat tops and undersides tapering to a point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatingIslandsSpec {
+    /// Layers of islands, one above the other.
+    pub num_layers: usize,
+    /// Height of the middle of the lowest layer above the base radius, and
+    /// between the middles of consecutive layers, in world units.
+    pub altitude: f32,
+    pub layer_spacing: f32,
+    /// Thickness of the islands at their middle, not counting the underside.
+    pub thickness: f32,
+    /// Roughly the fraction of each layer filled with rock, in [0, 1].
+    pub coverage: f32,
+    /// Typical width of an island, in world units.
+    pub island_size: f32,
+    /// Height of the hills on the surface below, in world units.
+    pub ground_roughness: f32,
+}
+
+impl Default for FloatingIslandsSpec {
+    fn default() -> Self {
+        FloatingIslandsSpec {
+            num_layers: 2,
+            altitude: 400.0,
+            layer_spacing: 350.0,
+            thickness: 120.0,
+            coverage: 0.35,
+            island_size: 500.0,
+            ground_roughness: 60.0,
+        }
+    }
+}
+
+/// A sphere of `radius` with gentle hills and layers of floating islands
+/// above it, so unlike the planets there's geometry far from the surface.
+pub struct FloatingIslandsField {
+    seed: Seed,
+    spec: FloatingIslandsSpec,
+    radius: f32,
+    islands: FractalNoise,
+    ground: FractalNoise,
+}
+
+impl FloatingIslandsField {
+    pub fn new(seed: u32, radius: f32, spec: &FloatingIslandsSpec) -> Self {
+        FloatingIslandsField {
+            seed: Seed::new(seed),
+            spec: spec.clone(),
+            radius: radius,
+            islands: FractalNoise {
+                basis: NoiseBasis::Simplex,
+                fractal: Fractal::Fbm,
+                num_octaves: 5,
+                persistence: 0.5,
+                wavelength: spec.island_size,
+                lacunarity: 2.0,
+            },
+            ground: FractalNoise {
+                basis: NoiseBasis::Simplex,
+                fractal: Fractal::Fbm,
+                num_octaves: 4,
+                persistence: 0.5,
+                wavelength: GROUND_WAVELENGTH,
+                lacunarity: 2.0,
+            },
+        }
+    }
+
+    /// The field leaving out the octaves with wavelengths shorter than
+    /// `step`.
+    fn value_with_detail(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        let spec = &self.spec;
+        let point = Vec3f::new(position[0], position[1], position[2]);
+        let distance = point.norm();
+        let direction = if distance > 0.0 {
+            point / distance
+        } else {
+            Vec3f::new(0.0, 1.0, 0.0)
+        };
+        let ground_octaves = self.ground.octaves_above(step);
+        let hills = self.ground.apply_octaves(
+            &self.seed,
+            (direction * self.radius).as_ref(),
+            ground_octaves,
+        );
+        let mut value = distance - (self.radius + spec.ground_roughness * hills);
+
+        let altitude = distance - self.radius;
+        let half_thickness = spec.thickness / 2.0;
+        let threshold = (0.5 - spec.coverage.max(0.0).min(1.0)) * COVERAGE_RANGE;
+        let island_octaves = self.islands.octaves_above(step);
+        for layer in 0..spec.num_layers {
+            let middle = spec.altitude + layer as f32 * spec.layer_spacing;
+            let (top, bottom) = (
+                middle + half_thickness,
+                middle - half_thickness * UNDERSIDE_DEPTH,
+            );
+            // Away from the layer the distance to it will do, which saves
+            // sampling the noise.
+            let layer_value = if altitude > top {
+                altitude - top
+            } else if altitude < bottom {
+                bottom - altitude
+            } else {
+                // In [0, 1], 1 in the middle of the layer and 0 at its top and
+                // bottom, with a flat top and a long underside.
+                let offset = (altitude - middle) / half_thickness;
+                let profile = if offset > 0.0 {
+                    1.0 - offset.powi(4)
+                } else {
+                    1.0 - (offset / UNDERSIDE_DEPTH).powi(2)
+                };
+                let layer_point = point + LAYER_OFFSET * layer as f32;
+                let noise =
+                    self.islands.apply_octaves(&self.seed, layer_point.as_ref(), island_octaves);
+                (threshold - noise + (1.0 - profile) * EDGE_FALLOFF) * half_thickness
+            };
+            value = value.min(layer_value);
+        }
+        value
+    }
+}
+
+impl ScalarField3 for FloatingIslandsField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.value_with_detail(position, 0.0)
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        self.value_with_detail(position, step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Norm, Point3};
+
+    use math::{ScalarField3, Vec3f};
+    use super::{FloatingIslandsField, FloatingIslandsSpec, UNDERSIDE_DEPTH};
+
+    #[test]
+    fn test_islands_hover_above_the_ground() {
+        let spec = FloatingIslandsSpec::default();
+        let radius = 2000.0;
+        let field = FloatingIslandsField::new(11, radius, &spec);
+        let gap = spec.altitude - spec.thickness / 2.0 * UNDERSIDE_DEPTH;
+        let (mut rock, mut air) = (0, 0);
+        for index in 0..500 {
+            let angle = index as f32 * 0.013;
+            let direction = Vec3f::new(angle.cos(), (angle * 0.7).sin(), angle.sin());
+            let direction = Vec3f::from(direction.normalize());
+            let at = |altitude: f32| field.value_at(&(direction * (radius + altitude)).to_point());
+
+            assert!(at(-spec.ground_roughness - 1.0) < 0.0);
+            assert!(at(spec.ground_roughness + 1.0) > 0.0);
+            // Nothing between the hills and the undersides of the islands.
+            assert!(at((spec.ground_roughness + gap) / 2.0) > 0.0);
+            if at(spec.altitude) < 0.0 {
+                rock += 1;
+            } else {
+                air += 1;
+            }
+        }
+        // The lowest layer is made of islands rather than a solid shell.
+        assert!(rock > 50 && air > 50, "{} {}", rock, air);
+        let position = Point3::new(0.0, radius + spec.altitude, 0.0);
+        assert_eq!(
+            field.value_at(&position),
+            FloatingIslandsField::new(11, radius, &spec).value_at(&position)
+        );
+    }
+}
+
+// Wavelength of the hills on the ground, in world units.
+const GROUND_WAVELENGTH: f32 = 800.0;
+// The threshold of the island noise goes from half this for no islands to
+// minus half of it for solid layers, about the spread of the noise.
+const COVERAGE_RANGE: f32 = 0.6;
+// How much the threshold rises towards the top and bottom of a layer, which
+// is enough for the noise never to reach them.
+const EDGE_FALLOFF: f32 = 1.0;
+// Depth of the undersides below the middle of a layer, in half thicknesses.
+const UNDERSIDE_DEPTH: f32 = 2.5;
+// Keeps the islands of different layers apart in the noise.
+const LAYER_OFFSET: f32 = 1.0e4;
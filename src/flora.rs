@@ -0,0 +1,323 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Cross, Dot, Norm};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use errors::Result;
+use gfx::{Mesh, Vertex, Window};
+use math::{Material, ScalarField3, Vec3f};
+use planet::PlanetSpec;
+use prefab::{instance_transform, placement, Prefab, PrefabInstances};
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloraSpec {
+    /// Plants per 1000 square metres of flat ground, 0 for none.
+    pub density: f32,
+    /// No plants grow on slopes steeper than this, in degrees, and fewer the
+    /// closer a slope is to it.
+    pub max_slope: f32,
+    /// Trees grow up to this altitude above the planet's base radius, and
+    /// shrubs above them up to `shrub_line`.
+    pub tree_line: f32,
+    pub shrub_line: f32,
+    /// Only chunks whose samples are at most this far apart get plants, the
+    /// coarser ones are too far to make them out.
+    pub max_step: f32,
+}
+
+impl Default for FloraSpec {
+    fn default() -> Self {
+        FloraSpec {
+            density: 0.0,
+            max_slope: 30.0,
+            tree_line: 300.0,
+            shrub_line: 500.0,
+            max_step: 1.0,
+        }
+    }
+}
+
+/// What grows on the ground, from what it's made of, its altitude and slope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Biome {
+    Forest,
+    Scrub,
+    Barren,
+}
+
+impl Biome {
+    /// `slope` is in degrees, `altitude` relative to the base radius.
+    pub fn at(spec: &FloraSpec, material: Material, altitude: f32, slope: f32) -> Self {
+        if material != Material::Rock || slope > spec.max_slope {
+            Biome::Barren
+        } else if altitude < spec.tree_line {
+            Biome::Forest
+        } else if altitude < spec.shrub_line {
+            Biome::Scrub
+        } else {
+            Biome::Barren
+        }
+    }
+}
+
+/// The values are indices into `PLANT_PREFABS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlantKind {
+    Tree = 0,
+    Shrub = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plant {
+    pub kind: PlantKind,
+    /// Where the plant's base is, relative to the centre of the body.
+    pub position: Vec3f,
+    /// Away from the centre of the body, the way the plant grows.
+    pub up: Vec3f,
+    /// Rotation around `up`, in radians.
+    pub yaw: f32,
+    pub scale: f32,
+}
+
+impl Plant {
+    /// Maps the plant's mesh to where it grows on the body.
+    pub fn transform(&self) -> [[f32; 4]; 4] {
+        instance_transform(&placement(&self.position, &self.up, self.yaw), self.scale)
+    }
+}
+
+/// Scatters the plants of one body over its chunks. The chunk workers call
+/// `scatter` after meshing a chunk, so its plants are uploaded with its mesh
+/// and freed with it.
+#[derive(Clone, Debug)]
+pub struct Flora {
+    spec: FloraSpec,
+    seed: u32,
+    base_radius: f32,
+    sea_radius: Option<f32>,
+}
+
+impl Flora {
+    pub fn new(spec: &FloraSpec, seed: u32, planet: &PlanetSpec) -> Self {
+        Flora {
+            spec: spec.clone(),
+            seed: seed,
+            base_radius: planet.base_radius,
+            sea_radius: planet.sea_radius(),
+        }
+    }
+
+    /// Whether chunks meshed with samples `step` apart get plants.
+    pub fn grows_at_step(&self, step: f32) -> bool {
+        self.spec.density > 0.0 && step <= self.spec.max_step
+    }
+
+    /// The plants on the triangles of `mesh` in the box at `position` with
+    /// sides of `size`, the same ones for a given box and seed. The mesh may
+    /// overrun the box, as chunks overlap their neighbours by a step, but no
+    /// plant is placed outside it.
+    pub fn scatter<Field>(
+        &self,
+        scalar_field: &Field,
+        mesh: &Mesh<Vertex>,
+        position: &Vec3f,
+        size: f32,
+    ) -> Vec<Plant>
+    where
+        Field: ScalarField3,
+    {
+        let _span = trace::span("scatter_flora");
+        let mut rng = self.chunk_rng(position, size);
+        let spec = &self.spec;
+        let mut plants = vec![];
+        for triangle in mesh.indices.chunks(3) {
+            let a = *mesh.vertices[triangle[0] as usize].position;
+            let ab = *mesh.vertices[triangle[1] as usize].position - a;
+            let ac = *mesh.vertices[triangle[2] as usize].position - a;
+            let expected = ab.cross(&ac).norm() / 2.0 * spec.density / 1000.0;
+            let mut num_plants = expected.floor() as usize;
+            if rng.gen::<f32>() < expected.fract() {
+                num_plants += 1;
+            }
+
+            for _ in 0..num_plants {
+                // Uniform over the triangle: a point in the parallelogram,
+                // folded back if it's in the other half.
+                let (mut u, mut v) = (rng.gen::<f32>(), rng.gen::<f32>());
+                if u + v > 1.0 {
+                    u = 1.0 - u;
+                    v = 1.0 - v;
+                }
+                let point = a + ab * u + ac * v;
+                let thinning = rng.gen::<f32>();
+                let kind_roll = rng.gen::<f32>();
+                let yaw = rng.gen_range(0.0, 2.0 * PI);
+                let scale = rng.gen_range(MIN_SCALE, MAX_SCALE);
+
+                let inside = (0..3).all(|axis| {
+                    point[axis] >= position[axis] && point[axis] < position[axis] + size
+                });
+                let radius = point.norm();
+                if !inside || radius < 1e-3 || self.sea_radius.map_or(false, |sea| radius < sea) {
+                    continue;
+                }
+                let up = point / radius;
+                let gradient = scalar_field.gradient_at(&point.to_point());
+                let gradient_norm = gradient.norm();
+                if gradient_norm < 1e-6 {
+                    continue;
+                }
+                let slope = (gradient.dot(&up) / gradient_norm)
+                    .max(-1.0)
+                    .min(1.0)
+                    .acos()
+                    .to_degrees();
+                let material = scalar_field.material_at(&point.to_point());
+                let kind = match Biome::at(spec, material, radius - self.base_radius, slope) {
+                    Biome::Forest if kind_roll < FOREST_SHRUBS => PlantKind::Shrub,
+                    Biome::Forest => PlantKind::Tree,
+                    Biome::Scrub => PlantKind::Shrub,
+                    Biome::Barren => continue,
+                };
+                // Plants thin out as the ground steepens.
+                if thinning < slope / spec.max_slope {
+                    continue;
+                }
+                plants.push(Plant {
+                    kind: kind,
+                    position: Vec3f::from(point),
+                    up: Vec3f::from(up),
+                    yaw: yaw,
+                    scale: scale,
+                });
+            }
+        }
+        plants
+    }
+
+    fn chunk_rng(&self, position: &Vec3f, size: f32) -> XorShiftRng {
+        let cell = |axis: usize| (position[axis] * CHUNK_KEY_DENSITY).floor() as i32 as u32;
+        let size = (size * CHUNK_KEY_DENSITY) as u32;
+        // Neighbouring chunks have similar keys, which the xorshift generator
+        // would turn into similar sequences without mixing them first. A zero
+        // state is invalid, hence the forced bit.
+        XorShiftRng::from_seed([
+            mix_bits(self.seed) | 1,
+            mix_bits(cell(0) ^ self.seed.rotate_left(8)),
+            mix_bits(cell(1) ^ self.seed.rotate_left(16)),
+            mix_bits(cell(2) ^ size.rotate_left(24)),
+        ])
+    }
+}
+
+/// The finalizer of MurmurHash3, every input bit affects every output bit.
+fn mix_bits(mut bits: u32) -> u32 {
+    bits ^= bits >> 16;
+    bits = bits.wrapping_mul(0x85eb_ca6b);
+    bits ^= bits >> 13;
+    bits = bits.wrapping_mul(0xc2b2_ae35);
+    bits ^ (bits >> 16)
+}
+
+/// Uploads `plants`, to be drawn by a `PrefabRenderer` of `PLANT_PREFABS`.
+pub fn upload_plants(window: &Window, plants: &[Plant]) -> Result<PrefabInstances> {
+    let placements: Vec<_> = plants
+        .iter()
+        .map(|plant| (plant.kind as usize, plant.transform()))
+        .collect();
+    PrefabInstances::new(window, &placements)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Norm, Point3};
+
+    use gfx::marching_cubes;
+    use math::{CpuScalar, ScalarField3, Vec3f};
+    use planet::PlanetSpec;
+    use super::{Flora, FloraSpec, PlantKind};
+
+    struct Ball(CpuScalar);
+
+    impl ScalarField3 for Ball {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            position.to_vector().norm() - self.0
+        }
+    }
+
+    #[test]
+    fn test_scatter_plants() {
+        let field = Ball(100.0);
+        let planet = PlanetSpec {
+            base_radius: 100.0,
+            ..Default::default()
+        };
+        let spec = FloraSpec {
+            density: 200.0,
+            tree_line: 10.0,
+            ..Default::default()
+        };
+        // A chunk around the north pole, flat enough for plants.
+        let position = Vec3f::new(-16.0, 90.0, -16.0);
+        let size = 32.0;
+        let end = position + size + 1.0;
+        let mesh = marching_cubes(&field, &position, &end, 1.0, 0.0);
+
+        let flora = Flora::new(&spec, 3, &planet);
+        let plants = flora.scatter(&field, &mesh, &position, size);
+        assert!(plants.len() > 50);
+        assert_eq!(plants, flora.scatter(&field, &mesh, &position, size));
+        assert!(plants.iter().any(|plant| plant.kind == PlantKind::Tree));
+        for plant in plants.iter() {
+            assert!((plant.position.norm() - 100.0).abs() < 0.1);
+            assert!((0..3).all(|axis| {
+                plant.position[axis] >= position[axis] &&
+                    plant.position[axis] < position[axis] + size
+            }));
+            // The transform keeps the plant upright on the ground.
+            let transform = plant.transform();
+            let up = Vec3f::new(transform[1][0], transform[1][1], transform[1][2]) / plant.scale;
+            assert!((up - plant.up).norm() < 1e-4);
+        }
+        assert!(flora.scatter(&field, &mesh, &(position + 1.0), size) != plants);
+
+        // Above the tree line only shrubs grow, and nothing above the shrubs.
+        let shrubs = FloraSpec {
+            tree_line: -10.0,
+            ..spec.clone()
+        };
+        let shrubs = Flora::new(&shrubs, 3, &planet);
+        let plants = shrubs.scatter(&field, &mesh, &position, size);
+        assert!(!plants.is_empty());
+        assert!(plants.iter().all(|plant| plant.kind == PlantKind::Shrub));
+        let barren = FloraSpec {
+            tree_line: -10.0,
+            shrub_line: -5.0,
+            ..spec
+        };
+        let barren = Flora::new(&barren, 3, &planet);
+        assert!(barren.scatter(&field, &mesh, &position, size).is_empty());
+    }
+}
+
+// Lit like the terrain.
+pub const PLANT_PREFABS: [Prefab; 2] = [
+    Prefab {
+        mesh: "tree.obj",
+        color: [0.12, 0.32, 0.1],
+        ambient: 0.02,
+    },
+    Prefab {
+        mesh: "shrub.obj",
+        color: [0.35, 0.4, 0.14],
+        ambient: 0.02,
+    },
+];
+// The fraction of plants in a forest which are shrubs.
+const FOREST_SHRUBS: f32 = 0.25;
+const MIN_SCALE: f32 = 0.7;
+const MAX_SCALE: f32 = 1.3;
+// Chunk corners are rounded to a grid this fine, per metre, to key the
+// scattering, like `ChunkId`.
+const CHUNK_KEY_DENSITY: f32 = 8.0;
@@ -0,0 +1,238 @@
+use noise::{self, Seed};
+
+/// The noise function summed over the octaves of a `FractalNoise`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseBasis {
+    Simplex,
+    Perlin,
+    /// Distance to the nearest of a set of random points, giving cells.
+    Worley,
+}
+
+impl NoiseBasis {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "simplex" => Some(NoiseBasis::Simplex),
+            "perlin" => Some(NoiseBasis::Perlin),
+            "worley" => Some(NoiseBasis::Worley),
+            _ => None,
+        }
+    }
+
+    /// The noise at `point`, roughly in [-1, 1].
+    #[inline]
+    fn apply(&self, seed: &Seed, point: &[f32; 3]) -> f32 {
+        match *self {
+            NoiseBasis::Simplex => noise::open_simplex3(seed, point),
+            NoiseBasis::Perlin => noise::perlin3(seed, point),
+            NoiseBasis::Worley => 2.0 * noise::cell3_range(seed, point).sqrt().min(1.0) - 1.0,
+        }
+    }
+}
+
+/// How the octaves of a `FractalNoise` are combined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fractal {
+    /// Fractional Brownian motion, the plain weighted sum of the octaves.
+    Fbm,
+    /// Sharp crests where the noise crosses zero, with detail concentrated
+    /// on the ridges.
+    Ridged,
+    /// Rounded bumps, the absolute value of each octave.
+    Billow,
+    /// Smooth valleys and rough peaks, each octave is scaled by the ones
+    /// before it.
+    Hybrid,
+}
+
+impl Fractal {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "fbm" => Some(Fractal::Fbm),
+            "ridged" => Some(Fractal::Ridged),
+            "billow" => Some(Fractal::Billow),
+            "hybrid" => Some(Fractal::Hybrid),
+            _ => None,
+        }
+    }
+
+    /// The range of each octave's signal before it's weighted by the ones
+    /// before it.
+    fn signal_range(&self) -> (f32, f32) {
+        match *self {
+            Fractal::Fbm | Fractal::Billow => (-1.0, 1.0),
+            Fractal::Ridged | Fractal::Hybrid => (0.0, 1.0),
+        }
+    }
+}
+
+/// A sum of octaves of `basis`, each `lacunarity` times the frequency and
+/// `persistence` times the amplitude of the previous one. With the simplex
+/// basis and `Fbm` this is the same as `noise::Brownian3`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FractalNoise {
+    pub basis: NoiseBasis,
+    pub fractal: Fractal,
+    pub num_octaves: usize,
+    pub persistence: f32,
+    pub wavelength: f32,
+    pub lacunarity: f32,
+}
+
+impl FractalNoise {
+    /// The noise at `point`, roughly in [-1, 1], with only its first
+    /// `num_octaves`. The rest are replaced by the middle of their range and
+    /// the sum is mapped to [-1, 1] from the range of the fractal with all
+    /// its octaves, so any `num_octaves` gives heights on the same scale,
+    /// off by no more than the share of the amplitude left out.
+    pub fn apply_octaves(&self, seed: &Seed, point: &[f32; 3], num_octaves: usize) -> f32 {
+        let (min_signal, max_signal) = self.fractal.signal_range();
+        let mut frequency = 1.0 / self.wavelength;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut result = 0.0;
+        // Ridged and hybrid octaves are damped where the previous ones were low.
+        let mut weight = 1.0;
+        for octave in 0..self.num_octaves {
+            if octave >= num_octaves {
+                // Damping the middle of the range leaves the weight as is.
+                result += (min_signal + max_signal) * 0.5 * weight * amplitude;
+                total_amplitude += amplitude;
+                amplitude *= self.persistence;
+                continue;
+            }
+            let scaled_point = [point[0] * frequency, point[1] * frequency, point[2] * frequency];
+            let value = self.basis.apply(seed, &scaled_point);
+            let signal = match self.fractal {
+                Fractal::Fbm => value,
+                Fractal::Billow => 2.0 * value.abs() - 1.0,
+                Fractal::Ridged => {
+                    let ridge = 1.0 - value.abs();
+                    let signal = ridge * ridge * weight;
+                    weight = (signal * 2.0).max(0.0).min(1.0);
+                    signal
+                }
+                Fractal::Hybrid => {
+                    let signal = (value + 1.0) * 0.5 * weight;
+                    weight = (signal * 2.0).max(0.0).min(1.0);
+                    signal
+                }
+            };
+            result += signal * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+        if total_amplitude == 0.0 {
+            return 0.0;
+        }
+        let min = min_signal * total_amplitude;
+        let max = max_signal * total_amplitude;
+        2.0 * (result - min) / (max - min) - 1.0
+    }
+
+    /// How many octaves, at least one, have a wavelength of `min_wavelength`
+    /// or longer.
+    pub fn octaves_above(&self, min_wavelength: f32) -> usize {
+        let mut wavelength = self.wavelength;
+        let mut num_octaves = 1;
+        while num_octaves < self.num_octaves {
+            wavelength /= self.lacunarity;
+            if wavelength < min_wavelength {
+                break;
+            }
+            num_octaves += 1;
+        }
+        num_octaves.min(self.num_octaves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use noise::{self, Brownian3, Seed};
+    use super::{Fractal, FractalNoise, NoiseBasis};
+
+    #[test]
+    fn test_fbm_matches_brownian() {
+        let seed = Seed::new(11);
+        let fbm = FractalNoise {
+            basis: NoiseBasis::Simplex,
+            fractal: Fractal::Fbm,
+            num_octaves: 5,
+            persistence: 0.8,
+            wavelength: 1.7,
+            lacunarity: 1.91,
+        };
+        let brownian = Brownian3::new(noise::open_simplex3, 5)
+            .persistence(0.8)
+            .wavelength(1.7)
+            .lacunarity(1.91);
+        for &point in [[0.1, 0.2, 0.3], [-2.5, 1.0, 4.0], [10.0, -3.3, 0.7]].iter() {
+            let value = fbm.apply_octaves(&seed, &point, 5);
+            assert!((value - brownian.apply(&seed, &point)).abs() < 1e-5);
+        }
+
+        for &fractal in [Fractal::Ridged, Fractal::Billow, Fractal::Hybrid].iter() {
+            for &basis in [NoiseBasis::Simplex, NoiseBasis::Perlin, NoiseBasis::Worley].iter() {
+                let noise = FractalNoise {
+                    basis: basis,
+                    fractal: fractal,
+                    ..fbm.clone()
+                };
+                let value = noise.apply_octaves(&seed, &[0.3, -1.2, 2.2], 5);
+                assert!(value >= -1.5 && value <= 1.5, "{:?} {:?}: {}", basis, fractal, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_octaves_above() {
+        let noise = FractalNoise {
+            basis: NoiseBasis::Simplex,
+            fractal: Fractal::Fbm,
+            num_octaves: 5,
+            persistence: 0.5,
+            wavelength: 8.0,
+            lacunarity: 2.0,
+        };
+        assert_eq!(5, noise.octaves_above(0.0));
+        assert_eq!(3, noise.octaves_above(2.0));
+        assert_eq!(1, noise.octaves_above(100.0));
+
+        let seed = Seed::new(3);
+        let point = [0.4, 1.3, -0.2];
+        let fine = noise.apply_octaves(&seed, &point, 5);
+        let coarse = noise.apply_octaves(&seed, &point, 3);
+        assert!(fine != coarse && (fine - coarse).abs() < 0.25);
+    }
+
+    #[test]
+    fn test_coarse_octaves_stay_near_the_fine_ones() {
+        let seed = Seed::new(5);
+        for &fractal in [Fractal::Fbm, Fractal::Ridged, Fractal::Billow, Fractal::Hybrid].iter() {
+            let noise = FractalNoise {
+                basis: NoiseBasis::Simplex,
+                fractal: fractal,
+                num_octaves: 5,
+                persistence: 0.5,
+                wavelength: 8.0,
+                lacunarity: 2.0,
+            };
+            // The share of the amplitude in the last two octaves.
+            let left_out = (0.125 + 0.0625) / 1.9375;
+            for i in 0..100 {
+                let point = [i as f32 * 0.37, i as f32 * -0.61 + 3.0, (i % 7) as f32 * 1.3];
+                let fine = noise.apply_octaves(&seed, &point, 5);
+                let coarse = noise.apply_octaves(&seed, &point, 3);
+                assert!(
+                    (fine - coarse).abs() <= left_out + 1e-5,
+                    "{:?} at {:?}: {} and {}",
+                    fractal,
+                    point,
+                    fine,
+                    coarse
+                );
+            }
+        }
+    }
+}
@@ -1,3 +1,3 @@
 pub mod player;
 
-pub use self::player::Player;
+pub use self::player::{ControlsSpec, Player};
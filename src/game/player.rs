@@ -1,24 +1,72 @@
-use nphysics3d::object::RigidBodyHandle;
+use ncollide::shape::{Ball, ShapeHandle};
+use nphysics3d::object::{RigidBody, RigidBodyHandle};
+use nphysics3d::volumetric::Volumetric;
+use nphysics3d::world::World;
 use num::Zero;
 
-use gfx::{Analog2d, Gesture, Input, KeyCode};
-use math::{GpuScalar, Matrix4f};
+use edit::BrushKind;
+use gfx::{Analog2d, Gesture, Input, KeyCode, MouseButton};
+use gfx::lights::{PointLight, Spot};
+use math::{GpuScalar, Matrix4f, Vec3f};
 use nalgebra::{Isometry3, Translation, Point3, Rotation, Vector2, Vector3, Inverse, ToHomogeneous};
 
+#[derive(Clone, Debug)]
+pub struct ControlsSpec {
+    pub forward: KeyCode,
+    pub backward: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub jump: KeyCode,
+    pub roll_left: KeyCode,
+    pub roll_right: KeyCode,
+    /// Turns the torch the player carries on and off.
+    pub torch: KeyCode,
+    pub movement_force: GpuScalar,
+    pub mouse_sensitivity: GpuScalar,
+    pub look_step: GpuScalar,
+    /// The radius of the sphere dug or built by a click.
+    pub brush_radius: GpuScalar,
+}
+
+impl Default for ControlsSpec {
+    fn default() -> Self {
+        ControlsSpec {
+            forward: KeyCode::W,
+            backward: KeyCode::S,
+            left: KeyCode::A,
+            right: KeyCode::D,
+            jump: KeyCode::Space,
+            roll_left: KeyCode::Q,
+            roll_right: KeyCode::E,
+            torch: KeyCode::F,
+            movement_force: 500.0,
+            mouse_sensitivity: 0.8,
+            look_step: 0.5,
+            brush_radius: 4.0,
+        }
+    }
+}
+
 pub struct ControllerBindings {
     pub movement: Analog2d,
     pub look: Analog2d,
     pub jump: Gesture,
+    pub roll_left: Gesture,
+    pub roll_right: Gesture,
+    pub torch: Gesture,
+    pub stop: Gesture,
+    pub dig: Gesture,
+    pub build: Gesture,
 }
 
-impl Default for ControllerBindings {
-    fn default() -> Self {
+impl ControllerBindings {
+    pub fn new(spec: &ControlsSpec) -> Self {
         ControllerBindings {
             movement: Analog2d::Gestures {
-                x_positive: Gesture::KeyHold(KeyCode::D),
-                x_negative: Gesture::KeyHold(KeyCode::A),
-                y_positive: Gesture::KeyHold(KeyCode::W),
-                y_negative: Gesture::KeyHold(KeyCode::S),
+                x_positive: Gesture::KeyHold(spec.right),
+                x_negative: Gesture::KeyHold(spec.left),
+                y_positive: Gesture::KeyHold(spec.forward),
+                y_negative: Gesture::KeyHold(spec.backward),
                 step: 1.0,
             },
             look: Analog2d::Sum {
@@ -28,20 +76,40 @@ impl Default for ControllerBindings {
                         x_negative: Gesture::KeyHold(KeyCode::Left),
                         y_positive: Gesture::KeyHold(KeyCode::Down),
                         y_negative: Gesture::KeyHold(KeyCode::Up),
-                        step: 0.05,
+                        step: spec.look_step,
                     },
-                    Analog2d::Mouse { sensitivity: 0.008 },
+                    Analog2d::Mouse { sensitivity: spec.mouse_sensitivity },
                 ],
             },
-            jump: Gesture::KeyHold(KeyCode::Space),
+            jump: Gesture::KeyHold(spec.jump),
+            roll_left: Gesture::KeyHold(spec.roll_left),
+            roll_right: Gesture::KeyHold(spec.roll_right),
+            torch: Gesture::KeyDownTrigger(spec.torch),
+            stop: Gesture::AnyOf(vec![
+                Gesture::KeyUpTrigger(spec.forward),
+                Gesture::KeyUpTrigger(spec.backward),
+                Gesture::KeyUpTrigger(spec.left),
+                Gesture::KeyUpTrigger(spec.right),
+            ]),
+            dig: Gesture::ButtonDownTrigger(MouseButton::Left),
+            build: Gesture::ButtonDownTrigger(MouseButton::Right),
         }
     }
 }
 
+impl Default for ControllerBindings {
+    fn default() -> Self {
+        ControllerBindings::new(&ControlsSpec::default())
+    }
+}
+
 pub struct Player {
     player: RigidBodyHandle<GpuScalar>,
+    bindings: ControllerBindings,
     keyboard_speed: GpuScalar,
     mouse_speed: GpuScalar,
+    brush_radius: GpuScalar,
+    torch_on: bool,
     pub observer: Isometry3<GpuScalar>,
 }
 
@@ -51,6 +119,7 @@ impl Player {
         position: &Point3<GpuScalar>,
         target: &Point3<GpuScalar>,
         up: &Vector3<GpuScalar>,
+        controls: &ControlsSpec,
     ) -> Self {
         player.borrow_mut().set_translation(position.to_vector());
         player.borrow_mut().set_deactivation_threshold(None);
@@ -59,12 +128,80 @@ impl Player {
         let observer = Isometry3::new_observer_frame(position, &target, &up);
         Player {
             player: player,
-            keyboard_speed: 500.0,
+            bindings: ControllerBindings::new(controls),
+            keyboard_speed: controls.movement_force,
             mouse_speed: 0.04,
+            brush_radius: controls.brush_radius,
+            torch_on: false,
             observer: observer,
         }
     }
 
+    /// Adds the player's body, a ball, to `physics_world` at `position`.
+    pub fn spawn(
+        physics_world: &mut World<GpuScalar>,
+        position: &Point3<GpuScalar>,
+        target: &Point3<GpuScalar>,
+        up: &Vector3<GpuScalar>,
+        controls: &ControlsSpec,
+    ) -> Self {
+        let ball = ShapeHandle::new(Ball::new(3.0 as GpuScalar));
+        let ball_mass = 100.0;
+        let props = Some((
+            ball_mass,
+            ball.center_of_mass(),
+            ball.angular_inertia(ball_mass),
+        ));
+        let handle = physics_world.add_rigid_body(RigidBody::new(ball, props, 0.01, 2.0));
+        Player::new(handle, position, target, up, controls)
+    }
+
+    pub fn set_controls(&mut self, controls: &ControlsSpec) {
+        self.bindings = ControllerBindings::new(controls);
+        self.keyboard_speed = controls.movement_force;
+        self.brush_radius = controls.brush_radius;
+    }
+
+    /// The unit vector the player is looking along.
+    pub fn view_direction(&self) -> Vector3<GpuScalar> {
+        self.observer.rotation * Vector3::z()
+    }
+
+    /// The edit requested this frame, if any, with the radius of its brush.
+    /// Left click digs and right click builds.
+    pub fn poll_edit(&self, input: &Input) -> Option<(BrushKind, GpuScalar)> {
+        if input.poll_gesture(&self.bindings.dig) {
+            Some((BrushKind::Subtract, self.brush_radius))
+        } else if input.poll_gesture(&self.bindings.build) {
+            Some((BrushKind::Add, self.brush_radius))
+        } else {
+            None
+        }
+    }
+
+    /// The spot light of the torch the player carries, shining where they
+    /// look, if it's on.
+    pub fn torch(&self) -> Option<PointLight> {
+        if !self.torch_on {
+            return None;
+        }
+        Some(PointLight {
+            position: Vec3f::from(self.observer.translation),
+            color: Vec3f::new(1.0, 0.85, 0.6) * TORCH_INTENSITY,
+            radius: TORCH_RANGE,
+            spot: Some(Spot {
+                direction: Vec3f::from(self.view_direction()),
+                inner_angle: TORCH_INNER_ANGLE,
+                outer_angle: TORCH_OUTER_ANGLE,
+            }),
+        })
+    }
+
+    pub fn set_translation(&mut self, translation: Vector3<GpuScalar>) {
+        self.player.borrow_mut().set_translation(translation);
+        self.update_position();
+    }
+
     pub fn view_matrix(&self) -> Matrix4f {
         Matrix4f::from(self.observer.inverse().unwrap().to_homogeneous())
     }
@@ -79,58 +216,32 @@ impl Player {
     pub fn update(&mut self, delta_time: f32, input: &Input) -> () {
         self.update_position();
         let mut player = self.player.borrow_mut();
-        if input.poll_gesture(&Gesture::AnyOf(vec![
-            Gesture::KeyUpTrigger(KeyCode::W),
-            Gesture::KeyUpTrigger(KeyCode::A),
-            Gesture::KeyUpTrigger(KeyCode::S),
-            Gesture::KeyUpTrigger(KeyCode::D),
-        ]))
-        {
+        if input.poll_gesture(&self.bindings.stop) {
             player.clear_forces();
         }
-
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::W)) {
-            let movement = self.observer.rotation * Vector3::z() * self.keyboard_speed;
-            player.append_lin_force(movement);
-        }
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::S)) {
-            let movement = self.observer.rotation * Vector3::z() * self.keyboard_speed * -1.0;
-            player.append_lin_force(movement);
-        }
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::A)) {
-            let movement = self.observer.rotation * Vector3::x() * self.keyboard_speed * -1.0;
-            player.append_lin_force(movement);
+        if input.poll_gesture(&self.bindings.torch) {
+            self.torch_on = !self.torch_on;
         }
 
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::D)) {
-            let movement = self.observer.rotation * Vector3::x() * self.keyboard_speed;
-            player.append_lin_force(movement);
+        let movement = input.poll_analog2d(&self.bindings.movement);
+        if movement != Vector2::zero() {
+            let direction = Vector3::x() * movement[0] + Vector3::z() * movement[1];
+            player.append_lin_force(self.observer.rotation * direction * self.keyboard_speed);
         }
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::Space)) {
+        if input.poll_gesture(&self.bindings.jump) {
             let movement = self.observer.rotation * Vector3::y() * self.keyboard_speed * 0.1;
             player.apply_central_impulse(movement);
         }
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::Q)) {
+        if input.poll_gesture(&self.bindings.roll_left) {
             let angle = self.observer.rotation * Vector3::z() * delta_time;
             self.observer.rotation.append_rotation_mut(&angle);
         }
-        if input.poll_gesture(&Gesture::KeyHold(KeyCode::E)) {
+        if input.poll_gesture(&self.bindings.roll_right) {
             let angle = self.observer.rotation * Vector3::z() * delta_time * -1.0;
             self.observer.rotation.append_rotation_mut(&angle);
         }
 
-        let mut mouse_rel = input.poll_analog2d(&Analog2d::Sum {
-            analogs: vec![
-                Analog2d::Gestures {
-                    x_positive: Gesture::KeyHold(KeyCode::Right),
-                    x_negative: Gesture::KeyHold(KeyCode::Left),
-                    y_positive: Gesture::KeyHold(KeyCode::Down),
-                    y_negative: Gesture::KeyHold(KeyCode::Up),
-                    step: 0.5,
-                },
-                Analog2d::Mouse { sensitivity: 0.8 },
-            ],
-        });
+        let mut mouse_rel = input.poll_analog2d(&self.bindings.look);
 
         if mouse_rel != Vector2::zero() {
             mouse_rel *= self.mouse_speed * delta_time;
@@ -150,3 +261,10 @@ impl Player {
         }
     }
 }
+
+// The torch lights the ground within its range as brightly as the sun at
+// about a fifth of it, in a cone fading out between the angles, in degrees.
+const TORCH_INTENSITY: GpuScalar = 150.0;
+const TORCH_RANGE: GpuScalar = 60.0;
+const TORCH_INNER_ANGLE: GpuScalar = 15.0;
+const TORCH_OUTER_ANGLE: GpuScalar = 30.0;
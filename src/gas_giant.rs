@@ -0,0 +1,134 @@
+use glium::{Depth, DrawParameters, Frame, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest, TimeElapsedQuery};
+use glium::index::PrimitiveType;
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::{Mesh, Vertex, Window};
+use math::{GpuScalar, Matrix4f, Vec3f};
+use trace;
+
+/// A planet without a surface, its atmosphere drawn with bands of clouds
+/// and storms drifting along them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GasGiantSpec {
+    pub radius: f32,
+    /// Light and dark bands from pole to pole.
+    pub num_bands: usize,
+    /// How much the bands are warped by eddies, 0 for straight bands.
+    pub turbulence: f32,
+    pub num_storms: usize,
+    /// Seconds per revolution at the equator, the bands near the poles are
+    /// slower.
+    pub rotation_period: f32,
+}
+
+impl Default for GasGiantSpec {
+    fn default() -> Self {
+        GasGiantSpec {
+            radius: 5000.0,
+            num_bands: 14,
+            turbulence: 0.3,
+            num_storms: 3,
+            rotation_period: 600.0,
+        }
+    }
+}
+
+/// Draws a gas giant centred at the origin. There is nothing to mesh or
+/// collide with, the clouds are a shader on a sphere.
+pub struct GasGiantRenderer<'a> {
+    spec: GasGiantSpec,
+    seed: u32,
+    draw_parameters: DrawParameters<'a>,
+    program: Program,
+    vertex_buffer: VertexBuffer<Vertex>,
+    index_buffer: IndexBuffer<u32>,
+}
+
+impl<'a> GasGiantRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets, seed: u32, spec: &GasGiantSpec) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let mesh = Mesh::icosphere(spec.radius, SPHERE_SUBDIVISIONS);
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &mesh.vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer = try!(
+            IndexBuffer::new(
+                window.facade(),
+                PrimitiveType::TrianglesList,
+                &mesh.indices,
+            ).chain_err(|| "Cannot create index buffer.")
+        );
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling: BackfaceCullingMode::CullClockwise,
+            ..Default::default()
+        };
+        Ok(GasGiantRenderer {
+            spec: spec.clone(),
+            seed: seed,
+            draw_parameters: draw_parameters,
+            program: program,
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+        })
+    }
+
+    /// Draws the clouds as they are `time` seconds in.
+    pub fn render(
+        &self,
+        frame: &mut Frame,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        light: &Vec3f,
+        time: f32,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_gas_giant");
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            u_light: light,
+            u_time: time,
+            u_rotation_period: self.spec.rotation_period,
+            u_num_bands: self.spec.num_bands as GpuScalar,
+            u_turbulence: self.spec.turbulence,
+            u_num_storms: self.spec.num_storms.min(MAX_STORMS) as i32,
+            // Large seeds lose precision as floats, the shader only needs a
+            // few distinct offsets.
+            u_seed: (self.seed % 4096) as GpuScalar,
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        frame
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            )
+            .chain_err(|| "Could not render the gas giant.")
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/gas_giant.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/gas_giant.frag";
+
+// The bands are smooth, unlike the coastline of the ocean, so fewer triangles
+// than for the ocean are enough.
+const SPHERE_SUBDIVISIONS: u32 = 5;
+// The `MAX_STORMS` of the fragment shader.
+const MAX_STORMS: usize = 16;
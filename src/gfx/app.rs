@@ -1,15 +1,39 @@
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use nalgebra::{Rotation, Translation};
+use glium::Frame;
+use glium::draw_parameters::TimeElapsedQuery;
+use nalgebra::{Norm, Rotation, Translation, Vector3};
+use nphysics3d::world::World;
 use threadpool::ThreadPool;
 
+use assets::Assets;
+use config::{Config, ConfigWatcher};
+use edit::{EditLog, WorldDir};
 use errors::{ChainErr, Result};
-use gfx::{Camera, Gesture, Input, KeyCode, SkyboxRenderer, Window};
-use math::{Point3f, Vec3f};
-use planet::{PlanetField, PlanetRenderer};
-use heightmap::Heightmap;
+use gfx::capture::FrameCapture;
+use gfx::graph::{FrameTimeGraph, FrameTimes};
+use gfx::mesh_cache::CacheDir;
+use gfx::octree_debug::OctreeDebugMode;
+use gfx::screenshot::save_screenshot;
+use game::Player;
+use gas_giant::GasGiantRenderer;
+use gfx::{perspective_matrix, Camera, Gesture, Input, KeyCode, LodSpec, SkyboxRenderer,
+          TextRenderer, Window, WindowSpec};
+use math::{Point3f, ScalarField3, Vec3f};
+use planet::PlanetSpec;
+use solar_system::{BodySpec, ShadingMode, SolarSystemRenderer};
+use trace;
 
 pub struct App {
+    assets: Assets,
+    capture_dir: Option<PathBuf>,
+    config_watcher: Option<ConfigWatcher>,
+    world_dir: Option<WorldDir>,
+    cache_dir: Option<CacheDir>,
+    max_fps: Option<u32>,
     window: Window,
     input: Input,
     camera: Camera,
@@ -17,10 +41,16 @@ pub struct App {
 }
 
 impl App {
-    pub fn new(width: u32, height: u32, num_workers: usize) -> Result<Self> {
-        let mut window = try!(Window::new(width, height, "Rusty Terrain"));
+    pub fn new(window_spec: &WindowSpec, assets: Assets, num_workers: usize) -> Result<Self> {
+        let mut window = try!(Window::new(window_spec, "Rusty Terrain"));
         let input = try!(Input::new(&mut window));
         Ok(App {
+            assets: assets,
+            capture_dir: None,
+            config_watcher: None,
+            world_dir: None,
+            cache_dir: None,
+            max_fps: window_spec.max_fps,
             window: window,
             input: input,
             camera: Camera::new(
@@ -32,63 +62,761 @@ impl App {
         })
     }
 
-    pub fn run(&mut self, planet_field: PlanetField) -> Result<()> {
+    /// Writes every rendered frame to numbered PNGs in `directory`. Capturing
+    /// can be paused and resumed with F9.
+    pub fn capture_frames(&mut self, directory: PathBuf) {
+        self.capture_dir = Some(directory);
+    }
+
+    /// Saves the terrain edits to, and restores them from, `world_dir`.
+    pub fn persist_edits(&mut self, world_dir: WorldDir) {
+        self.world_dir = Some(world_dir);
+    }
+
+    /// Keeps the meshes of the chunks in `cache_dir`, to reuse them rather
+    /// than mesh them again, in this run or the next.
+    pub fn cache_meshes(&mut self, cache_dir: CacheDir) {
+        self.cache_dir = Some(cache_dir);
+    }
+
+    /// Reloads the config whenever `watcher` sees it change. Controls, gravity
+    /// and the maximum LOD level are applied in place; other planet or LOD
+    /// changes regenerate the world around the player.
+    pub fn watch_config(&mut self, watcher: ConfigWatcher) {
+        self.config_watcher = Some(watcher);
+    }
+
+    /// Runs the main loop on the world whose bodies are created by
+    /// `make_field` from their spec and seed. The bodies are created again to
+    /// regenerate the world when the planet config changes.
+    pub fn run<Field, MakeField>(
+        &mut self,
+        config: &Config,
+        seed: u32,
+        make_field: MakeField,
+    ) -> Result<()>
+    where
+        Field: 'static + ScalarField3 + Send + Sync,
+        MakeField: Fn(&Config, &PlanetSpec, u32) -> Result<Field>,
+    {
         let App {
+            ref assets,
+            ref capture_dir,
+            ref mut config_watcher,
+            ref world_dir,
+            ref cache_dir,
+            max_fps,
+            ref mut window,
             ref mut input,
+            ref mut camera,
             ref thread_pool,
+        } = *self;
+
+        let mut planet = try!(new_solar_system(
+            try!(make_bodies(config, seed, &make_field)),
+            config,
+            window,
+            assets,
+            thread_pool,
+        ));
+        try!(open_mesh_caches(cache_dir, config, seed, &mut planet));
+        let edit_logs = try!(restore_edits(world_dir, config, seed, &mut planet));
+        let mut scene = PlanetScene {
+            planet: planet,
+            edit_logs: edit_logs,
+            make_field: make_field,
+            seed: seed,
+            assets: assets,
+            thread_pool: thread_pool,
+            world_dir: world_dir,
+            cache_dir: cache_dir,
+            octree_debug_mode: OctreeDebugMode::Off,
+            shading_mode: ShadingMode::Lit,
+        };
+        let main_loop = MainLoop {
+            assets: assets,
+            capture_dir: capture_dir,
+            config_watcher: config_watcher,
+            max_fps: max_fps,
+            window: window,
+            input: input,
+            camera: camera,
+        };
+        main_loop.run(&mut scene, config)
+    }
+
+    /// Runs the main loop around a gas giant, which has no surface to walk on:
+    /// the player floats freely and the world is drawn without chunks.
+    pub fn run_gas_giant(&mut self, config: &Config, seed: u32) -> Result<()> {
+        let App {
+            ref assets,
+            ref capture_dir,
+            ref mut config_watcher,
+            max_fps,
             ref mut window,
+            ref mut input,
+            ref mut camera,
             ..
         } = *self;
 
-        let heightmap = try!(Heightmap::from_pds(
-            3396.0,
-            11520 * 4,
-            5632 * 4,
-            "/home/marius/w/terrain/assets/128/megdr-128-stiched.img",
-        ));
-        // let heightmap = try!(Heightmap::from_image(3396.0,
-        //                                            "/home/marius/w/terrain/assets/earth-21600x10800.jpg"));
+        // The world holds nothing but the player, without gravity.
+        let mut physics_world = World::new();
+        let start = Point3f::new(0.0, 0.0, 0.0) +
+            Vector3::new(1.0, 1.0, 1.0) * config.gas_giant.radius * GAS_GIANT_START_DISTANCE;
+        let player = Player::spawn(
+            &mut physics_world,
+            &start,
+            &Point3f::new(0.0, 0.0, 0.0),
+            &Vector3::y(),
+            &config.controls,
+        );
+        let mut scene = GasGiantScene {
+            gas_giant: try!(GasGiantRenderer::new(window, assets, seed, &config.gas_giant)),
+            assets: assets,
+            seed: seed,
+            radius: config.gas_giant.radius,
+            light: Vec3f::new(-40.0f32, 0.0, -4000.0),
+            time: 0.0,
+            physics_world: physics_world,
+            player: player,
+        };
+        let main_loop = MainLoop {
+            assets: assets,
+            capture_dir: capture_dir,
+            config_watcher: config_watcher,
+            max_fps: max_fps,
+            window: window,
+            input: input,
+            camera: camera,
+        };
+        main_loop.run(&mut scene, config)
+    }
+}
+
+/// What the main loop draws around the player: how it's stepped, drawn,
+/// summed up in the overlay and changed by input or a reloaded config. The
+/// loop itself times the frames, draws the skybox and the HUD, captures and
+/// applies the gestures common to every scene.
+trait Scene {
+    fn player(&mut self) -> &mut Player;
+
+    fn step_physics(&mut self, timestep: f32);
+
+    fn render(
+        &mut self,
+        window: &Window,
+        frame: &mut Frame,
+        camera: &mut Camera,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()>;
+
+    /// The time the last frame took to render, in milliseconds.
+    fn report_frame_time(&mut self, _frame_ms: f32) {}
+
+    /// How long the latest chunks took from request to drawing, in seconds,
+    /// if the scene has any.
+    fn chunk_latency(&self) -> Option<f32> {
+        None
+    }
+
+    fn overlay(&self, camera: &Camera, frame_times: &FrameTimes) -> String;
+
+    /// Polls the gestures only this scene responds to.
+    fn poll_input(&mut self, _input: &mut Input) {}
+
+    /// Applies `new`, the config reloaded in place of `old`. False if the
+    /// world couldn't be regenerated for it, which leaves the scene as it
+    /// was with `old`.
+    fn reload(&mut self, window: &Window, old: &Config, new: &Config) -> Result<bool>;
+}
+
+/// The parts of the app every scene's main loop shares.
+struct MainLoop<'a> {
+    assets: &'a Assets,
+    capture_dir: &'a Option<PathBuf>,
+    config_watcher: &'a mut Option<ConfigWatcher>,
+    max_fps: Option<u32>,
+    window: &'a mut Window,
+    input: &'a mut Input,
+    camera: &'a mut Camera,
+}
+
+impl<'a> MainLoop<'a> {
+    fn run<S: Scene>(self, scene: &mut S, config: &Config) -> Result<()> {
+        let mut config = config.clone();
+        let MainLoop {
+            assets,
+            capture_dir,
+            config_watcher,
+            max_fps,
+            window,
+            input,
+            camera,
+        } = self;
 
-        let mut planet = try!(PlanetRenderer::new(heightmap, window, thread_pool));
-        let mut skybox = try!(SkyboxRenderer::new(window));
-        // try!(skybox.load(window, "/home/marius/w/terrain/assets/skybox-galaxy.jpg"));
-        info!("Loaded the skybox.");
+        let mut skybox = try!(SkyboxRenderer::new(window, assets));
+        let skybox_loaded = match assets.resolve_entry(&config.skybox.path) {
+            Ok(path) => {
+                try!(skybox.load(window, path, &config.skybox.faces));
+                info!("Loaded the skybox.");
+                true
+            }
+            Err(err) => {
+                warn!("Rendering without a skybox: {}", err);
+                false
+            }
+        };
+
+        let text = try!(TextRenderer::new(window, assets));
+        let mut graph = try!(FrameTimeGraph::new(window, assets));
+        // GPU time is measured with one timer query per frame, read back once
+        // the result is available so we never wait on the GPU.
+        let mut pending_time_queries = VecDeque::new();
+        let mut time_queries_supported = true;
 
         let quit_gesture = Gesture::AnyOf(vec![
             Gesture::QuitTrigger,
             Gesture::KeyDownTrigger(KeyCode::Escape),
         ]);
+        let screenshot_gesture = Gesture::KeyDownTrigger(KeyCode::F12);
+        let capture_gesture = Gesture::KeyDownTrigger(KeyCode::F9);
+        let overlay_gesture = Gesture::KeyDownTrigger(KeyCode::F3);
+        let mut show_overlay = true;
+        let mut capture = match *capture_dir {
+            Some(ref directory) => Some(try!(FrameCapture::new(directory.clone()))),
+            None => None,
+        };
+        let mut capturing = capture.is_some();
+
+        // Physics is stepped with a fixed timestep, independent of the frame
+        // rate. The camera is interpolated between the last two physics states
+        // by the fraction of a timestep left in the accumulator.
+        let mut accumulator = 0.0;
+        let mut previous_translation = scene.player().update_position().translation();
+        let mut current_translation = previous_translation;
+        let min_frame_time = match max_fps {
+            Some(max_fps) if max_fps > 0 => Some(Duration::new(0, 1_000_000_000 / max_fps)),
+            _ => None,
+        };
 
         info!("Entering main loop.");
+        let mut last_frame = Instant::now();
         let mut running = true;
         while running {
-            let time = Instant::now();
+            let _span = trace::span("frame");
+            let frame_start = Instant::now();
+            let frame_time = duration_to_secs(frame_start - last_frame).min(MAX_FRAME_TIME);
+            last_frame = frame_start;
 
+            accumulator += frame_time;
+            while accumulator >= PHYSICS_TIMESTEP {
+                scene.step_physics(PHYSICS_TIMESTEP);
+                previous_translation = current_translation;
+                current_translation = scene.player().update_position().translation();
+                accumulator -= PHYSICS_TIMESTEP;
+            }
+            let alpha = accumulator / PHYSICS_TIMESTEP;
+
+            let time_query = new_time_query(window, &mut time_queries_supported);
             let mut target = window.draw();
 
-            let player_pos = planet.player.update_position();
-            self.camera.observer_mut().set_translation(
-                player_pos.translation(),
-            );
-            self.camera.observer_mut().set_rotation(
-                player_pos.rotation(),
+            let player_pos = scene.player().update_position();
+            camera.observer_mut().set_translation(
+                previous_translation * (1.0 - alpha) + current_translation * alpha,
             );
+            camera.observer_mut().set_rotation(player_pos.rotation());
+
+            if skybox_loaded {
+                try!(skybox.render(&mut target, camera, time_query.as_ref()));
+            }
+            try!(scene.render(window, &mut target, camera, time_query.as_ref()));
 
-            // try!(skybox.render(&mut target, &mut self.camera));
-            try!(planet.render(window, &mut target, &mut self.camera));
-            try!(target.finish().chain_err(|| "Could not render frame."));
+            let gpu_time = latest_gpu_time(&mut pending_time_queries);
+            pending_time_queries.extend(time_query);
+            graph.push(&FrameTimes {
+                cpu: duration_to_secs(frame_start.elapsed()),
+                gpu: gpu_time,
+                chunk_latency: scene.chunk_latency(),
+            });
+            // Waiting on the swap isn't work, so the frame costs whichever of
+            // the CPU and the GPU took longer.
+            let latest_times = graph.latest();
+            scene.report_frame_time(latest_times.cpu.max(latest_times.gpu.unwrap_or(0.0)) * 1e3);
 
-            let elapsed = time.elapsed();
-            let delta = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
-            planet.update_physics(delta);
+            if show_overlay {
+                let overlay = scene.overlay(camera, &graph.latest());
+                try!(text.draw(window, &mut target, &overlay, OVERLAY_POSITION, OVERLAY_SCALE));
+                try!(graph.draw(window, &mut target));
+            }
+            if let Some(ref mut capture) = capture {
+                if capturing {
+                    try!(capture.capture(window, &target));
+                }
+            }
+            {
+                let _span = trace::span("finish_frame");
+                try!(target.finish().chain_err(|| "Could not render frame."));
+            }
 
             try!(input.update(window));
+            if input.poll_gesture(&screenshot_gesture) {
+                let path = save_screenshot(window, Path::new("."));
+                info!("Saving screenshot to {:?}", path);
+            }
+            if input.poll_gesture(&capture_gesture) {
+                if let Some(ref mut capture) = capture {
+                    capturing = !capturing;
+                    info!("Frame capture {}.", if capturing { "resumed" } else { "paused" });
+                    if !capturing {
+                        try!(capture.flush());
+                    }
+                }
+            }
+            if input.poll_gesture(&overlay_gesture) {
+                show_overlay = !show_overlay;
+            }
             if input.poll_gesture(&quit_gesture) {
                 info!("Quit gesture detected, exiting...");
                 running = false;
             }
-            planet.player.update(delta, input);
+            scene.player().update(frame_time, input);
+            scene.poll_input(input);
+
+            match config_watcher.as_mut().and_then(|watcher| watcher.poll()) {
+                Some(Ok(new_config)) => {
+                    if try!(scene.reload(window, &config, &new_config)) {
+                        // The scene may have respawned the player where it was.
+                        current_translation = scene.player().update_position().translation();
+                        previous_translation = current_translation;
+                        info!("Reloaded the config.");
+                        config = new_config;
+                    } else {
+                        error!("Kept the previous config, the new one was not applied.");
+                    }
+                }
+                Some(Err(err)) => error!("Could not reload the config: {}", err),
+                None => {}
+            }
+
+            if let Some(min_frame_time) = min_frame_time {
+                let elapsed = frame_start.elapsed();
+                if elapsed < min_frame_time {
+                    thread::sleep(min_frame_time - elapsed);
+                }
+            }
         }
         Ok(())
     }
 }
+
+/// The solar system of the bodies `make_field` creates, walked on and edited.
+struct PlanetScene<'a, 'b, Field: ScalarField3, MakeField> {
+    planet: SolarSystemRenderer<'a, 'b, Field>,
+    /// In the same order as the bodies.
+    edit_logs: Vec<EditLog>,
+    make_field: MakeField,
+    seed: u32,
+    assets: &'a Assets,
+    thread_pool: &'a ThreadPool,
+    world_dir: &'a Option<WorldDir>,
+    cache_dir: &'a Option<CacheDir>,
+    octree_debug_mode: OctreeDebugMode,
+    shading_mode: ShadingMode,
+}
+
+impl<'a, 'b, Field, MakeField> Scene for PlanetScene<'a, 'b, Field, MakeField>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+    MakeField: Fn(&Config, &PlanetSpec, u32) -> Result<Field>,
+{
+    fn player(&mut self) -> &mut Player {
+        &mut self.planet.player
+    }
+
+    fn step_physics(&mut self, timestep: f32) {
+        self.planet.update_physics(timestep);
+    }
+
+    fn render(
+        &mut self,
+        window: &Window,
+        frame: &mut Frame,
+        camera: &mut Camera,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        self.planet.render(window, frame, camera, time_query)
+    }
+
+    fn report_frame_time(&mut self, frame_ms: f32) {
+        self.planet.report_frame_time(frame_ms);
+    }
+
+    fn chunk_latency(&self) -> Option<f32> {
+        self.planet.lod_stats().generation_latency.map(duration_to_secs)
+    }
+
+    fn overlay(&self, camera: &Camera, frame_times: &FrameTimes) -> String {
+        debug_overlay(&self.planet, camera, frame_times)
+    }
+
+    fn poll_input(&mut self, input: &mut Input) {
+        // Cycles the octree wireframe through its coloring modes and off.
+        if input.poll_gesture(&Gesture::KeyDownTrigger(KeyCode::F4)) {
+            self.octree_debug_mode = self.octree_debug_mode.next();
+            info!("Octree debug view: {:?}.", self.octree_debug_mode);
+            self.planet.set_octree_debug_mode(self.octree_debug_mode);
+        }
+        // Cycles the terrain through lit and the debug shadings.
+        if input.poll_gesture(&Gesture::KeyDownTrigger(KeyCode::F5)) {
+            self.shading_mode = self.shading_mode.next();
+            info!("Terrain shading: {:?}.", self.shading_mode);
+            self.planet.set_shading_mode(self.shading_mode);
+        }
+        if let Some((kind, radius)) = self.planet.player.poll_edit(input) {
+            match self.planet.edit(kind, radius) {
+                Some((body, brush)) => {
+                    if let Some(edit_log) = self.edit_logs.get_mut(body) {
+                        if let Err(err) = edit_log.append(&brush) {
+                            error!("Could not save the edit: {}", err);
+                        }
+                    }
+                }
+                None => debug!("Nothing to edit within reach."),
+            }
+        }
+    }
+
+    fn reload(&mut self, window: &Window, old: &Config, new: &Config) -> Result<bool> {
+        if !needs_regeneration(old, new) {
+            self.planet.set_max_level(new.lod.max_level);
+            self.planet.set_screen_errors(new.lod.max_screen_error, new.lod.merge_screen_error);
+            self.planet.set_min_dwell(new.lod.min_dwell_ms);
+            self.planet.set_target_frame_time(
+                new.lod.target_frame_ms,
+                new.lod.max_detail_reduction,
+            );
+            self.planet.set_physics_spec(&new.physics);
+            self.planet.player.set_controls(&new.controls);
+            try!(self.planet.set_supersampling(new.window.supersampling));
+            self.planet.set_fog(&new.fog);
+            return Ok(true);
+        }
+        info!("Planet config changed, regenerating the world.");
+        let bodies = match make_bodies(new, self.seed, &self.make_field) {
+            Ok(bodies) => bodies,
+            Err(err) => {
+                error!("Could not regenerate the world: {}", err);
+                return Ok(false);
+            }
+        };
+        let translation = self.planet.player.update_position().translation();
+        self.planet = try!(new_solar_system(bodies, new, window, self.assets, self.thread_pool));
+        self.planet.player.set_translation(translation);
+        try!(open_mesh_caches(self.cache_dir, new, self.seed, &mut self.planet));
+        self.edit_logs = try!(restore_edits(self.world_dir, new, self.seed, &mut self.planet));
+        Ok(true)
+    }
+}
+
+/// A gas giant floated around, with no surface and no chunks.
+struct GasGiantScene<'a> {
+    gas_giant: GasGiantRenderer<'a>,
+    assets: &'a Assets,
+    seed: u32,
+    radius: f32,
+    light: Vec3f,
+    time: f32,
+    physics_world: World<f32>,
+    player: Player,
+}
+
+impl<'a> Scene for GasGiantScene<'a> {
+    fn player(&mut self) -> &mut Player {
+        &mut self.player
+    }
+
+    fn step_physics(&mut self, timestep: f32) {
+        self.time += timestep;
+        self.physics_world.step(timestep);
+    }
+
+    fn render(
+        &mut self,
+        _window: &Window,
+        frame: &mut Frame,
+        _camera: &mut Camera,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let perspective = perspective_matrix(frame);
+        self.gas_giant.render(
+            frame,
+            perspective,
+            &self.player.view_matrix(),
+            &self.light,
+            self.time,
+            time_query,
+        )
+    }
+
+    fn overlay(&self, camera: &Camera, frame_times: &FrameTimes) -> String {
+        let position = camera.position().translation();
+        format!(
+            "Position: {:.1} {:.1} {:.1}\n\
+             Altitude: {:.1} above the clouds\n\
+             CPU (green) {:.1} ms, GPU (red) {} ms",
+            position[0],
+            position[1],
+            position[2],
+            position.norm() - self.radius,
+            frame_times.cpu * 1e3,
+            format_millis(frame_times.gpu)
+        )
+    }
+
+    fn reload(&mut self, window: &Window, old: &Config, new: &Config) -> Result<bool> {
+        if new.gas_giant != old.gas_giant {
+            self.gas_giant =
+                try!(GasGiantRenderer::new(window, self.assets, self.seed, &new.gas_giant));
+            self.radius = new.gas_giant.radius;
+        }
+        self.player.set_controls(&new.controls);
+        Ok(true)
+    }
+}
+
+/// A query for the GPU time of the next frame, unless they turned out not to
+/// be supported.
+fn new_time_query(window: &Window, supported: &mut bool) -> Option<TimeElapsedQuery> {
+    if !*supported {
+        return None;
+    }
+    match TimeElapsedQuery::new(window.facade()) {
+        Ok(query) => Some(query),
+        Err(err) => {
+            warn!("GPU frame times are unavailable: {:?}", err);
+            *supported = false;
+            None
+        }
+    }
+}
+
+/// Pops the queries whose results are in and returns the latest of them.
+fn latest_gpu_time(pending_time_queries: &mut VecDeque<TimeElapsedQuery>) -> Option<f32> {
+    let mut gpu_time = None;
+    while pending_time_queries.front().map_or(false, TimeElapsedQuery::is_ready) {
+        let query = pending_time_queries.pop_front().unwrap();
+        gpu_time = Some(query.get() as f32 * 1e-9);
+    }
+    gpu_time
+}
+
+/// The renderer of `bodies`, set up as `config` says.
+fn new_solar_system<'a, 'b, Field>(
+    bodies: Vec<(BodySpec, Field)>,
+    config: &Config,
+    window: &Window,
+    assets: &Assets,
+    thread_pool: &'a ThreadPool,
+) -> Result<SolarSystemRenderer<'a, 'b, Field>>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let mut planet = try!(SolarSystemRenderer::new(
+        bodies,
+        window,
+        assets,
+        thread_pool,
+        &config.asteroids,
+        &config.atmosphere,
+        &config.clouds,
+        &config.lens_flare,
+        &config.shadows,
+        &config.flora,
+        &config.structures,
+        &config.lod,
+        &config.controls,
+        &config.physics,
+    ));
+    try!(planet.set_supersampling(config.window.supersampling));
+    planet.set_fog(&config.fog);
+    Ok(planet)
+}
+
+/// The main planet followed by the other bodies, with their seeds resolved.
+fn body_specs(config: &Config, seed: u32) -> Vec<BodySpec> {
+    let mut bodies = vec![
+        BodySpec {
+            seed: Some(seed),
+            ..BodySpec::main_planet(&config.planet)
+        },
+    ];
+    for (index, body) in config.bodies.iter().enumerate() {
+        bodies.push(BodySpec {
+            seed: Some(body.seed.unwrap_or(seed.wrapping_add(index as u32 + 1))),
+            ..body.clone()
+        });
+    }
+    bodies
+}
+
+fn make_bodies<Field, MakeField>(
+    config: &Config,
+    seed: u32,
+    make_field: &MakeField,
+) -> Result<Vec<(BodySpec, Field)>>
+where
+    MakeField: Fn(&Config, &PlanetSpec, u32) -> Result<Field>,
+{
+    let mut bodies = vec![];
+    for body in body_specs(config, seed).into_iter() {
+        let field = try!(make_field(config, &body.planet, body.seed.unwrap()));
+        bodies.push((body, field));
+    }
+    Ok(bodies)
+}
+
+/// Opens the mesh caches of the bodies generated from `config`, if meshes
+/// are cached, for `planet` to mesh their chunks through.
+fn open_mesh_caches<'a, 'b, Field>(
+    cache_dir: &Option<CacheDir>,
+    config: &Config,
+    seed: u32,
+    planet: &mut SolarSystemRenderer<'a, 'b, Field>,
+) -> Result<()>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let cache_dir = match *cache_dir {
+        Some(ref cache_dir) => cache_dir,
+        None => return Ok(()),
+    };
+    let capacity = config.lod.mesh_cache_size_mb as u64 * 1024 * 1024;
+    for (index, body) in body_specs(config, seed).iter().enumerate() {
+        let mesh_cache = try!(cache_dir.open_mesh_cache(
+            if index == 0 { None } else { Some(&body.name) },
+            body.seed.unwrap(),
+            &body.planet,
+            &config.erosion,
+            config.lod.chunk_num_steps,
+            config.lod.voxel_density,
+            capacity,
+        ));
+        planet.cache_meshes(index, mesh_cache);
+    }
+    Ok(())
+}
+
+/// Opens the edit logs of the bodies generated from `config`, if edits are
+/// persisted, and replays their edits on `planet`. The logs are in the same
+/// order as the bodies.
+fn restore_edits<'a, 'b, Field>(
+    world_dir: &Option<WorldDir>,
+    config: &Config,
+    seed: u32,
+    planet: &mut SolarSystemRenderer<'a, 'b, Field>,
+) -> Result<Vec<EditLog>>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let world_dir = match *world_dir {
+        Some(ref world_dir) => world_dir,
+        None => return Ok(vec![]),
+    };
+    let mut edit_logs = vec![];
+    for (index, body) in body_specs(config, seed).iter().enumerate() {
+        let (edit_log, brushes) = try!(world_dir.open_edit_log(
+            if index == 0 { None } else { Some(&body.name) },
+            body.seed.unwrap(),
+            &body.planet,
+            &config.erosion,
+        ));
+        planet.restore_edits(index, &brushes);
+        edit_logs.push(edit_log);
+    }
+    Ok(edit_logs)
+}
+
+/// Whether the changes from `old` to `new` can only be applied by recreating
+/// the planet, as opposed to tweaking the running one.
+fn needs_regeneration(old: &Config, new: &Config) -> bool {
+    let live_lod = LodSpec {
+        max_level: old.lod.max_level,
+        max_screen_error: old.lod.max_screen_error,
+        merge_screen_error: old.lod.merge_screen_error,
+        min_dwell_ms: old.lod.min_dwell_ms,
+        target_frame_ms: old.lod.target_frame_ms,
+        max_detail_reduction: old.lod.max_detail_reduction,
+        ..new.lod.clone()
+    };
+    old.planet != new.planet || old.bodies != new.bodies || old.asteroids != new.asteroids ||
+        old.atmosphere != new.atmosphere || old.clouds != new.clouds ||
+        old.lens_flare != new.lens_flare || old.shadows != new.shadows ||
+        old.flora != new.flora || old.structures != new.structures ||
+        old.floating_islands != new.floating_islands || old.erosion != new.erosion ||
+        old.lod != live_lod
+}
+
+fn debug_overlay<Field>(
+    planet: &SolarSystemRenderer<Field>,
+    camera: &Camera,
+    frame_times: &FrameTimes,
+) -> String
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let position = camera.position().translation();
+    let (nearest_body, altitude) = planet.nearest_body(&position.to_point());
+    let lod_stats = planet.lod_stats();
+    format!(
+        "Position: {:.1} {:.1} {:.1}\n\
+         Altitude: {:.1} above {}\n\
+         Chunks: {} loaded ({} pinned) in {:.0} MB, {} pending, {} empty, {} failed\n\
+         Screen error: x{:.2}\n\
+         Meshed: {} chunks, {} ms mean, {:.0} MB uploaded, {} evicted, cache {} hits\n\
+         Bodies: {}\n\
+         CPU (green) {:.1} ms, GPU (red) {} ms, chunks (yellow) {} ms",
+        position[0],
+        position[1],
+        position[2],
+        altitude,
+        planet.body_name(nearest_body),
+        lod_stats.loaded_chunks,
+        lod_stats.pinned_chunks,
+        lod_stats.loaded_bytes as f32 / (1024.0 * 1024.0),
+        lod_stats.pending_chunks,
+        lod_stats.empty_chunks,
+        lod_stats.failed_chunks,
+        lod_stats.screen_error_scale,
+        lod_stats.chunks_generated,
+        format_millis(lod_stats.overall_mean_meshing_time().map(duration_to_secs)),
+        lod_stats.bytes_uploaded as f32 / (1024.0 * 1024.0),
+        lod_stats.evictions,
+        lod_stats.mesh_cache_hit_rate().map_or_else(
+            || "-".to_owned(),
+            |rate| format!("{:.0}%", rate * 100.0),
+        ),
+        planet.num_rigid_bodies(),
+        frame_times.cpu * 1e3,
+        format_millis(frame_times.gpu),
+        format_millis(frame_times.chunk_latency)
+    )
+}
+
+fn format_millis(seconds: Option<f32>) -> String {
+    seconds.map_or_else(|| "-".to_owned(), |seconds| format!("{:.1}", seconds * 1e3))
+}
+
+#[inline]
+fn duration_to_secs(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
+}
+
+const OVERLAY_POSITION: (f32, f32) = (8.0, 8.0);
+const OVERLAY_SCALE: f32 = 2.0;
+const PHYSICS_TIMESTEP: f32 = 1.0 / 60.0;
+// Distance of the player from the centre of a gas giant at the start, in
+// radii along each axis.
+const GAS_GIANT_START_DISTANCE: f32 = 1.2;
+// Upper bound on the time simulated in one frame, so a long stall (e.g. while
+// the window is being dragged) doesn't trigger a burst of physics steps.
+const MAX_FRAME_TIME: f32 = 0.25;
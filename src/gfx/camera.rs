@@ -1,3 +1,4 @@
+use glium::{Frame, Surface};
 use glium::glutin::{Window, Event, ElementState, VirtualKeyCode};
 use nalgebra::{Isometry3, Rotation, ToHomogeneous, Translation, Vector3, Inverse};
 
@@ -106,3 +107,24 @@ impl Camera {
         &mut self.observer
     }
 }
+
+/// The projection of the terrain and the bodies drawn with it.
+pub fn perspective_matrix(frame: &Frame) -> [[f32; 4]; 4] {
+    let (width, height) = frame.get_dimensions();
+    let aspect_ratio = height as f32 / width as f32;
+
+    let zfar = 1e4;
+    let znear = 0.1;
+
+    let f = 1.0 / (FIELD_OF_VIEW / 2.0).tan();
+
+    [
+        [f * aspect_ratio, 0.0, 0.0, 0.0],
+        [0.0, f, 0.0, 0.0],
+        [0.0, 0.0, (zfar + znear) / (zfar - znear), 1.0],
+        [0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0],
+    ]
+}
+
+// The vertical field of view of the terrain and the sky, in radians.
+pub const FIELD_OF_VIEW: f32 = 3.141592 / 3.0;
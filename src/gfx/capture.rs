@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+
+use chan::{self, Sender};
+use glium::{BlitTarget, Frame, Rect, Surface};
+use glium::texture::{MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat};
+use glium::texture::pixel_buffer::PixelBuffer;
+use glium::uniforms::MagnifySamplerFilter;
+
+use errors::{ChainErr, Result};
+use gfx::Window;
+use gfx::screenshot::{raw_to_image, save_image};
+
+/// Writes every captured frame to `<directory>/frame-<index>.png`.
+///
+/// Frames are copied to one of two pixel buffers and only read back on the
+/// following frame, by which time the GPU has finished the transfer, so
+/// capturing doesn't stall the render loop. PNG encoding happens on a
+/// background thread.
+pub struct FrameCapture {
+    directory: PathBuf,
+    target: Option<CaptureTarget>,
+    num_frames: usize,
+    sender: Option<Sender<(usize, RawImage2d<'static, u8>)>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+struct CaptureTarget {
+    dimensions: (u32, u32),
+    texture: Texture2d,
+    pixel_buffers: [PixelBuffer<(u8, u8, u8, u8)>; 2],
+    // The index of the frame waiting to be read back in each pixel buffer.
+    pending: [Option<usize>; 2],
+    next_buffer: usize,
+}
+
+impl FrameCapture {
+    pub fn new(directory: PathBuf) -> Result<Self> {
+        try!(fs::create_dir_all(&directory).chain_err(|| {
+            format!("Could not create capture directory {:?}", directory)
+        }));
+
+        let (sender, receiver) = chan::sync::<(usize, RawImage2d<'static, u8>)>(MAX_QUEUED_FRAMES);
+        let writer_directory = directory.clone();
+        let writer = thread::spawn(move || for (index, raw) in receiver {
+            let path = writer_directory.join(format!("frame-{:06}.png", index));
+            if let Err(err) = save_image(&raw_to_image(raw), &path) {
+                error!("{}", err);
+            }
+        });
+
+        Ok(FrameCapture {
+            directory: directory,
+            target: None,
+            num_frames: 0,
+            sender: Some(sender),
+            writer: Some(writer),
+        })
+    }
+
+    /// Queues `frame` for capture. Must be called before `frame.finish()`.
+    pub fn capture(&mut self, window: &Window, frame: &Frame) -> Result<()> {
+        let dimensions = frame.get_dimensions();
+        if self.target.as_ref().map_or(true, |target| target.dimensions != dimensions) {
+            try!(self.flush());
+            self.target = Some(try!(CaptureTarget::new(window, dimensions)));
+            info!("Capturing {:?} frames to {:?}", dimensions, self.directory);
+        }
+
+        let index = self.num_frames;
+        self.num_frames += 1;
+        let previous = {
+            let target = self.target.as_mut().unwrap();
+            let buffer = target.next_buffer;
+            target.next_buffer = 1 - buffer;
+
+            frame.blit_whole_color_to(
+                &target.texture.as_surface(),
+                &BlitTarget {
+                    left: 0,
+                    bottom: 0,
+                    width: dimensions.0 as i32,
+                    height: dimensions.1 as i32,
+                },
+                MagnifySamplerFilter::Nearest,
+            );
+            // The other buffer holds the previous frame, which has had a whole
+            // frame's worth of time to finish transferring.
+            let previous = try!(target.take(1 - buffer));
+            target.start_read(buffer, index);
+            previous
+        };
+        if let Some(previous) = previous {
+            self.send(previous);
+        }
+        Ok(())
+    }
+
+    /// Reads back and queues any frames still sitting in the pixel buffers.
+    pub fn flush(&mut self) -> Result<()> {
+        let mut frames = vec![];
+        if let Some(ref mut target) = self.target {
+            let oldest = target.next_buffer;
+            for &buffer in [oldest, 1 - oldest].iter() {
+                if let Some(frame) = try!(target.take(buffer)) {
+                    frames.push(frame);
+                }
+            }
+        }
+        for frame in frames.into_iter() {
+            self.send(frame);
+        }
+        Ok(())
+    }
+
+    fn send(&self, frame: (usize, RawImage2d<'static, u8>)) {
+        if let Some(ref sender) = self.sender {
+            sender.send(frame);
+        }
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!("Could not flush captured frames: {}", err);
+        }
+        // Closing the channel stops the writer once it has saved all the frames.
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        info!("Captured {} frames to {:?}", self.num_frames, self.directory);
+    }
+}
+
+impl CaptureTarget {
+    fn new(window: &Window, dimensions: (u32, u32)) -> Result<Self> {
+        let (width, height) = dimensions;
+        let texture = try!(
+            Texture2d::empty_with_format(
+                window.facade(),
+                UncompressedFloatFormat::U8U8U8U8,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            ).chain_err(|| "Could not create frame capture texture.")
+        );
+        let num_pixels = width as usize * height as usize;
+        Ok(CaptureTarget {
+            dimensions: dimensions,
+            texture: texture,
+            pixel_buffers: [
+                PixelBuffer::new_empty(window.facade(), num_pixels),
+                PixelBuffer::new_empty(window.facade(), num_pixels),
+            ],
+            pending: [None, None],
+            next_buffer: 0,
+        })
+    }
+
+    /// Starts an asynchronous copy of the capture texture into `buffer`.
+    fn start_read(&mut self, buffer: usize, index: usize) {
+        let (width, height) = self.dimensions;
+        self.texture
+            .main_level()
+            .first_layer()
+            .into_image(None)
+            .expect("Capture texture has no image.")
+            .raw_read_to_pixel_buffer(
+                &Rect {
+                    left: 0,
+                    bottom: 0,
+                    width: width,
+                    height: height,
+                },
+                &self.pixel_buffers[buffer],
+            );
+        self.pending[buffer] = Some(index);
+    }
+
+    /// Reads back the frame in `buffer`, if there is one.
+    fn take(&mut self, buffer: usize) -> Result<Option<(usize, RawImage2d<'static, u8>)>> {
+        match self.pending[buffer].take() {
+            Some(index) => {
+                let raw = try!(self.pixel_buffers[buffer].read_as_texture_2d().map_err(|_| {
+                    "Could not read back captured frame."
+                }));
+                Ok(Some((index, raw)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+const MAX_QUEUED_FRAMES: usize = 8;
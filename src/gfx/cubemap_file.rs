@@ -0,0 +1,331 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use errors::Result;
+
+/// The faces of a cubemap read from a DDS or KTX file, only the largest of
+/// their mipmaps, so space skies can be stored pre-encoded and in HDR.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubemapFile {
+    /// The texels along the side of each face.
+    pub size: u32,
+    /// In the order of `FACE_NAMES`, the RGB of each texel from the top left,
+    /// 8 bit channels scaled to [0, 1].
+    pub faces: Vec<Vec<f32>>,
+    /// Whether the texels were floats, rather than 8 bit channels.
+    pub hdr: bool,
+}
+
+/// How the texels of a file are stored, of those a face can be read from:
+/// 8 bits per channel, or half or full floats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TexelFormat {
+    Rgb8,
+    Bgr8,
+    Rgba8,
+    Bgra8,
+    Rgb16f,
+    Rgba16f,
+    Rgb32f,
+    Rgba32f,
+}
+
+impl TexelFormat {
+    fn is_float(&self) -> bool {
+        self.size() > 4
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            TexelFormat::Rgb8 | TexelFormat::Bgr8 => 3,
+            TexelFormat::Rgba8 | TexelFormat::Bgra8 => 4,
+            TexelFormat::Rgb16f => 6,
+            TexelFormat::Rgba16f => 8,
+            TexelFormat::Rgb32f => 12,
+            TexelFormat::Rgba32f => 16,
+        }
+    }
+
+    /// The RGB of the texels in `bytes`, dropping the alpha.
+    fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+        let mut rgb = Vec::with_capacity(bytes.len() / self.size() * 3);
+        for texel in bytes.chunks(self.size()) {
+            match *self {
+                TexelFormat::Rgb8 | TexelFormat::Rgba8 => {
+                    rgb.extend(texel[..3].iter().map(|&value| value as f32 / 255.0))
+                }
+                TexelFormat::Bgr8 | TexelFormat::Bgra8 => {
+                    rgb.extend(texel[..3].iter().rev().map(|&value| value as f32 / 255.0))
+                }
+                TexelFormat::Rgb16f | TexelFormat::Rgba16f => {
+                    rgb.extend(texel[..6].chunks(2).map(|half| {
+                        half_to_f32(LittleEndian::read_u16(half))
+                    }))
+                }
+                TexelFormat::Rgb32f | TexelFormat::Rgba32f => {
+                    rgb.extend(texel[..12].chunks(4).map(LittleEndian::read_f32))
+                }
+            }
+        }
+        rgb
+    }
+}
+
+impl CubemapFile {
+    /// Whether `bytes` start like a DDS or a KTX file.
+    pub fn is_cubemap_file(bytes: &[u8]) -> bool {
+        bytes.starts_with(DDS_MAGIC) || bytes.starts_with(KTX_IDENTIFIER)
+    }
+
+    /// Reads a DDS or KTX file, told apart by their first bytes, which must
+    /// hold a cubemap of one of the uncompressed formats.
+    pub fn read(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(DDS_MAGIC) {
+            read_dds(bytes)
+        } else if bytes.starts_with(KTX_IDENTIFIER) {
+            read_ktx(bytes)
+        } else {
+            Err("Not a DDS or KTX file.".into())
+        }
+    }
+
+    /// The faces, all of `size` and in `format`, from `offset` in `bytes`
+    /// and each `stride` bytes from the last.
+    fn from_faces(
+        bytes: &[u8],
+        format: TexelFormat,
+        size: u32,
+        offset: usize,
+        stride: usize,
+    ) -> Result<Self> {
+        let face_bytes = size as usize * size as usize * format.size();
+        let mut faces = Vec::with_capacity(6);
+        for face in 0..6 {
+            let start = offset + face * stride;
+            match bytes.get(start..start + face_bytes) {
+                Some(texels) => faces.push(format.decode(texels)),
+                None => return Err("The cubemap file ends before its faces.".into()),
+            }
+        }
+        Ok(CubemapFile {
+            size: size,
+            faces: faces,
+            hdr: format.is_float(),
+        })
+    }
+}
+
+fn read_dds(bytes: &[u8]) -> Result<CubemapFile> {
+    if bytes.len() < DDS_HEADER_END {
+        return Err("The DDS file ends in its header.".into());
+    }
+    let header = |offset: usize| LittleEndian::read_u32(&bytes[4 + offset..]);
+    let (height, width) = (header(8), header(12));
+    let num_mipmaps = header(24).max(1);
+    let (pixel_flags, four_cc, bit_count) = (header(76), header(80), header(84));
+    let (red_mask, caps2) = (header(88), header(108));
+    if caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES {
+        return Err("The DDS file isn't a cubemap with all six faces.".into());
+    }
+    if width != height || width == 0 {
+        return Err(format!("The faces of the DDS cubemap are {}x{}.", width, height).into());
+    }
+
+    let mut offset = DDS_HEADER_END;
+    let format = if pixel_flags & DDPF_FOURCC != 0 && four_cc == DX10_FOUR_CC {
+        if bytes.len() < DDS_HEADER_END + 20 {
+            return Err("The DDS file ends in its DX10 header.".into());
+        }
+        offset += 20;
+        match LittleEndian::read_u32(&bytes[DDS_HEADER_END..]) {
+            2 => TexelFormat::Rgba32f,
+            6 => TexelFormat::Rgb32f,
+            10 => TexelFormat::Rgba16f,
+            28 | 29 => TexelFormat::Rgba8,
+            87 | 91 => TexelFormat::Bgra8,
+            format => {
+                return Err(format!("Unsupported DXGI format {} in the DDS file.", format).into())
+            }
+        }
+    } else if pixel_flags & DDPF_FOURCC != 0 {
+        match four_cc {
+            113 => TexelFormat::Rgba16f,
+            116 => TexelFormat::Rgba32f,
+            _ => {
+                return Err(
+                    "Block-compressed DDS cubemaps are unsupported, store them uncompressed."
+                        .into(),
+                )
+            }
+        }
+    } else if pixel_flags & DDPF_RGB != 0 {
+        match (bit_count, red_mask) {
+            (24, 0xff) => TexelFormat::Rgb8,
+            (24, 0xff0000) => TexelFormat::Bgr8,
+            (32, 0xff) => TexelFormat::Rgba8,
+            (32, 0xff0000) => TexelFormat::Bgra8,
+            _ => return Err(format!("Unsupported {} bit RGB in the DDS file.", bit_count).into()),
+        }
+    } else {
+        return Err("The DDS file has no colours.".into());
+    };
+
+    // Each face is followed by its mipmaps.
+    let stride = (0..num_mipmaps)
+        .map(|level| {
+            let size = (width >> level).max(1) as usize;
+            size * size * format.size()
+        })
+        .sum();
+    CubemapFile::from_faces(bytes, format, width, offset, stride)
+}
+
+fn read_ktx(bytes: &[u8]) -> Result<CubemapFile> {
+    if bytes.len() < KTX_HEADER_END {
+        return Err("The KTX file ends in its header.".into());
+    }
+    let header = |index: usize| LittleEndian::read_u32(&bytes[12 + index * 4..]);
+    if header(0) != KTX_ENDIANNESS {
+        return Err("Big endian KTX files are unsupported.".into());
+    }
+    let (gl_type, gl_format) = (header(1), header(3));
+    let (width, height, num_faces) = (header(6), header(7), header(10));
+    if num_faces != 6 || header(9) > 1 {
+        return Err("The KTX file isn't a single cubemap.".into());
+    }
+    if width != height || width == 0 {
+        return Err(format!("The faces of the KTX cubemap are {}x{}.", width, height).into());
+    }
+    let format = match (gl_type, gl_format) {
+        (0, _) => {
+            return Err(
+                "Block-compressed KTX cubemaps are unsupported, store them uncompressed.".into(),
+            )
+        }
+        (GL_UNSIGNED_BYTE, GL_RGB) => TexelFormat::Rgb8,
+        (GL_UNSIGNED_BYTE, GL_BGR) => TexelFormat::Bgr8,
+        (GL_UNSIGNED_BYTE, GL_RGBA) => TexelFormat::Rgba8,
+        (GL_UNSIGNED_BYTE, GL_BGRA) => TexelFormat::Bgra8,
+        (GL_HALF_FLOAT, GL_RGB) => TexelFormat::Rgb16f,
+        (GL_HALF_FLOAT, GL_RGBA) => TexelFormat::Rgba16f,
+        (GL_FLOAT, GL_RGB) => TexelFormat::Rgb32f,
+        (GL_FLOAT, GL_RGBA) => TexelFormat::Rgba32f,
+        _ => {
+            return Err(
+                format!(
+                    "Unsupported GL type {:#x} and format {:#x} in the KTX file.",
+                    gl_type,
+                    gl_format
+                ).into(),
+            )
+        }
+    };
+
+    // The first mipmap level comes after the key and value pairs and its
+    // size, each of its faces padded to 4 bytes.
+    let offset = KTX_HEADER_END + header(12) as usize + 4;
+    let face_bytes = width as usize * width as usize * format.size();
+    CubemapFile::from_faces(bytes, format, width, offset, (face_bytes + 3) / 4 * 4)
+}
+
+/// The value of an IEEE 754 half precision float.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign *
+        match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => ::std::f32::INFINITY,
+            0x1f => ::std::f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    use super::{half_to_f32, CubemapFile, DDS_MAGIC, KTX_IDENTIFIER};
+
+    #[test]
+    fn test_half_floats() {
+        assert_eq!(0.0, half_to_f32(0));
+        assert_eq!(1.0, half_to_f32(0x3c00));
+        assert_eq!(-2.0, half_to_f32(0xc000));
+        assert_eq!(65504.0, half_to_f32(0x7bff));
+        assert_eq!(2f32.powi(-24), half_to_f32(1));
+    }
+
+    #[test]
+    fn test_read_cubemap_files() {
+        // A DDS cubemap of 1x1 BGRA faces, with a mipmap each to skip.
+        let mut dds = DDS_MAGIC.to_vec();
+        let mut header = [0u32; 31];
+        header[0] = 124;
+        header[2] = 1;
+        header[3] = 1;
+        header[6] = 2;
+        header[18] = 32;
+        header[19] = 0x40 | 0x1;
+        header[21] = 32;
+        header[22] = 0xff0000;
+        header[26] = 0x1000 | 0x8;
+        header[27] = 0x200 | 0xfc00;
+        for value in header.iter() {
+            dds.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        for face in 0..6u8 {
+            dds.extend_from_slice(&[0, 0, 255 - face, 255]);
+            dds.extend_from_slice(&[9, 9, 9, 255]);
+        }
+        let cubemap = CubemapFile::read(&dds).unwrap();
+        assert_eq!(1, cubemap.size);
+        assert_eq!(6, cubemap.faces.len());
+        assert_eq!(vec![1.0, 0.0, 0.0], cubemap.faces[0]);
+        assert_eq!(250.0 / 255.0, cubemap.faces[5][0]);
+        assert!(!cubemap.hdr);
+        assert!(CubemapFile::read(&dds[..dds.len() - 8]).is_err());
+
+        // The same faces as a KTX cubemap of half floats.
+        let mut ktx = KTX_IDENTIFIER.to_vec();
+        let header = [0x04030201, 0x140b, 2, 0x1908, 0x881a, 0x1908, 1, 1, 0, 0, 6, 1, 4];
+        for value in header.iter() {
+            ktx.write_u32::<LittleEndian>(*value).unwrap();
+        }
+        ktx.extend_from_slice(&[0; 4]);
+        ktx.write_u32::<LittleEndian>(8).unwrap();
+        for face in 0..6u16 {
+            for &channel in &[0x3c00 + face, 0, 0xc000, 0x3c00] {
+                ktx.write_u16::<LittleEndian>(channel).unwrap();
+            }
+        }
+        let cubemap = CubemapFile::read(&ktx).unwrap();
+        assert_eq!(vec![1.0, 0.0, -2.0], cubemap.faces[0]);
+        assert!(cubemap.faces[5][0] > 1.0);
+        assert!(cubemap.hdr);
+        assert!(CubemapFile::is_cubemap_file(&ktx));
+        assert!(!CubemapFile::is_cubemap_file(b"\x89PNG"));
+    }
+}
+
+const DDS_MAGIC: &'static [u8] = b"DDS ";
+// The magic, the header and its pixel format, before the optional DX10
+// header and the texels.
+const DDS_HEADER_END: usize = 4 + 124;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0x200 | 0xfc00;
+// "DX10" as a little endian four character code.
+const DX10_FOUR_CC: u32 = 0x30315844;
+
+const KTX_IDENTIFIER: &'static [u8] = b"\xabKTX 11\xbb\r\n\x1a\n";
+// The identifier and 13 fields of 4 bytes.
+const KTX_HEADER_END: usize = 12 + 13 * 4;
+const KTX_ENDIANNESS: u32 = 0x04030201;
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_HALF_FLOAT: u32 = 0x140b;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_BGR: u32 = 0x80e0;
+const GL_BGRA: u32 = 0x80e1;
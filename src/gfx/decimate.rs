@@ -0,0 +1,363 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use nalgebra::{Cross, Dot, Norm};
+
+use math::Vec3f;
+use super::mesh::{Mesh, Vertex};
+
+/// Simplifies `mesh` by collapsing its edges one at a time, the one moving
+/// the surface least first as measured by the quadric error metric (Garland
+/// and Heckbert), until it's down to `max_triangles` or the next collapse
+/// would move it by more than about `max_error`. Use 0 triangles to bound
+/// only the error and an infinite error to target only the count.
+///
+/// The vertices on the border, where the mesh meets the neighbouring chunks,
+/// are never moved, and collapses which would flip a triangle or pinch the
+/// mesh are skipped.
+pub fn decimate(mesh: &Mesh<Vertex>, max_triangles: usize, max_error: f32) -> Mesh<Vertex> {
+    let mut vertices = mesh.vertices.clone();
+    let mut triangles: Vec<[u32; 3]> = mesh.indices
+        .chunks(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    let mut alive = vec![true; triangles.len()];
+    let mut num_triangles = triangles.len();
+
+    let mut quadrics = vec![Quadric::zero(); vertices.len()];
+    let mut vertex_triangles = vec![vec![]; vertices.len()];
+    let mut edges = HashMap::new();
+    for (index, triangle) in triangles.iter().enumerate() {
+        let corner = |corner: usize| &vertices[triangle[corner] as usize].position;
+        let plane = Quadric::plane(corner(0), corner(1), corner(2));
+        for corner in 0..3 {
+            let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+            quadrics[a as usize].add(&plane);
+            vertex_triangles[a as usize].push(index);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    let mut locked = vec![false; vertices.len()];
+    for (&(a, b), &count) in edges.iter() {
+        if count == 1 {
+            locked[a as usize] = true;
+            locked[b as usize] = true;
+        }
+    }
+
+    let mut versions = vec![0u32; vertices.len()];
+    let mut removed = vec![false; vertices.len()];
+    let mut heap = BinaryHeap::new();
+    let mut sorted_edges: Vec<(u32, u32)> = edges.keys().cloned().collect();
+    sorted_edges.sort();
+    for &(a, b) in sorted_edges.iter() {
+        if let Some(collapse) = Collapse::new(&vertices, &quadrics, &locked, &versions, a, b) {
+            heap.push(collapse);
+        }
+    }
+
+    let max_cost = (max_error as f64) * (max_error as f64);
+    while num_triangles > max_triangles {
+        let collapse = match heap.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        let (a, b) = (collapse.keep as usize, collapse.remove as usize);
+        if removed[a] || removed[b] || versions[a] != collapse.versions.0 ||
+            versions[b] != collapse.versions.1
+        {
+            continue;
+        }
+        if collapse.cost > max_cost {
+            break;
+        }
+        if !can_collapse(&vertices, &triangles, &alive, &vertex_triangles, &collapse) {
+            continue;
+        }
+
+        let normal = if collapse.position == vertices[b].position {
+            vertices[b].normal
+        } else if collapse.position == vertices[a].position {
+            vertices[a].normal
+        } else {
+            let sum = vertices[a].normal + vertices[b].normal;
+            let length = sum.norm();
+            if length > 0.0 {
+                sum / length
+            } else {
+                vertices[a].normal
+            }
+        };
+        vertices[a] = Vertex {
+            position: collapse.position,
+            normal: normal,
+        };
+        let quadric = quadrics[b];
+        quadrics[a].add(&quadric);
+        let moved = vertex_triangles[b].clone();
+        for &index in moved.iter() {
+            if !alive[index] {
+                continue;
+            }
+            if triangles[index].contains(&(a as u32)) {
+                alive[index] = false;
+                num_triangles -= 1;
+            } else {
+                for corner in triangles[index].iter_mut() {
+                    if *corner == b as u32 {
+                        *corner = a as u32;
+                    }
+                }
+                vertex_triangles[a].push(index);
+            }
+        }
+        removed[b] = true;
+        versions[a] += 1;
+        vertex_triangles[a].retain(|&index| alive[index]);
+        vertex_triangles[b].clear();
+
+        let mut neighbours: Vec<u32> = vertex_triangles[a]
+            .iter()
+            .flat_map(|&index| triangles[index].iter().cloned())
+            .filter(|&corner| corner != a as u32)
+            .collect();
+        neighbours.sort();
+        neighbours.dedup();
+        for &neighbour in neighbours.iter() {
+            let collapse =
+                Collapse::new(&vertices, &quadrics, &locked, &versions, a as u32, neighbour);
+            if let Some(collapse) = collapse {
+                heap.push(collapse);
+            }
+        }
+    }
+
+    // Only the vertices of the remaining triangles are kept.
+    let mut remap = vec![::std::u32::MAX; vertices.len()];
+    let mut kept_vertices = vec![];
+    let mut indices = Vec::with_capacity(num_triangles * 3);
+    for (triangle, _) in triangles.iter().zip(alive.iter()).filter(|&(_, &alive)| alive) {
+        for &corner in triangle.iter() {
+            if remap[corner as usize] == ::std::u32::MAX {
+                remap[corner as usize] = kept_vertices.len() as u32;
+                kept_vertices.push(vertices[corner as usize]);
+            }
+            indices.push(remap[corner as usize]);
+        }
+    }
+    Mesh {
+        name: mesh.name.clone(),
+        vertices: kept_vertices,
+        indices: indices,
+    }
+}
+
+/// The sum of the squared distances to some planes, as the upper triangle of
+/// a symmetric 4 x 4 matrix.
+#[derive(Clone, Copy, Debug)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn zero() -> Self {
+        Quadric([0.0; 10])
+    }
+
+    /// The quadric of the plane through a triangle, none if it's degenerate.
+    fn plane(a: &Vec3f, b: &Vec3f, c: &Vec3f) -> Self {
+        let normal = (*b - *a).cross(&(*c - *a));
+        let length = normal.norm();
+        if length == 0.0 {
+            return Quadric::zero();
+        }
+        let normal = normal / length;
+        let d = -(normal.dot(a) as f64);
+        let (a, b, c) = (normal[0] as f64, normal[1] as f64, normal[2] as f64);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (sum, &value) in self.0.iter_mut().zip(other.0.iter()) {
+            *sum += value;
+        }
+    }
+
+    fn error(&self, position: &Vec3f) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (position[0] as f64, position[1] as f64, position[2] as f64);
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x + q[4] * y * y +
+            2.0 * q[5] * y * z + 2.0 * q[6] * y + q[7] * z * z + 2.0 * q[8] * z + q[9]
+    }
+}
+
+/// Merging the vertex `remove` into `keep`, moved to `position`, which is
+/// only valid while neither has changed since, as told by their versions.
+#[derive(Clone, Debug)]
+struct Collapse {
+    cost: f64,
+    keep: u32,
+    remove: u32,
+    position: Vec3f,
+    versions: (u32, u32),
+}
+
+impl Collapse {
+    /// The cheapest collapse of the edge between `a` and `b`, onto either end
+    /// or its middle, none if both are on the border.
+    fn new(
+        vertices: &[Vertex],
+        quadrics: &[Quadric],
+        locked: &[bool],
+        versions: &[u32],
+        a: u32,
+        b: u32,
+    ) -> Option<Self> {
+        let (keep, remove) = match (locked[a as usize], locked[b as usize]) {
+            (true, true) => return None,
+            (false, true) => (b, a),
+            _ => (a, b),
+        };
+        let (k, r) = (keep as usize, remove as usize);
+        let mut quadric = quadrics[k];
+        quadric.add(&quadrics[r]);
+        let mut candidates = vec![vertices[k].position];
+        if !locked[k] {
+            candidates.push(vertices[r].position);
+            candidates.push((vertices[k].position + vertices[r].position) / 2.0);
+        }
+        candidates
+            .into_iter()
+            .map(|position| (quadric.error(&position), position))
+            .fold(None, |best: Option<(f64, Vec3f)>, candidate| match best {
+                Some(best) if best.0 <= candidate.0 => Some(best),
+                _ => Some(candidate),
+            })
+            .map(|(cost, position)| {
+                Collapse {
+                    cost: cost.max(0.0),
+                    keep: keep,
+                    remove: remove,
+                    position: position,
+                    versions: (versions[k], versions[r]),
+                }
+            })
+    }
+}
+
+// Ordered by cost only and reversed, the binary heap popping the cheapest.
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Whether `collapse` keeps the mesh a manifold, its edge shared by exactly
+/// two triangles whose other corners are the only neighbours of both ends,
+/// and turns none of the triangles around.
+fn can_collapse(
+    vertices: &[Vertex],
+    triangles: &[[u32; 3]],
+    alive: &[bool],
+    vertex_triangles: &[Vec<usize>],
+    collapse: &Collapse,
+) -> bool {
+    let (keep, remove) = (collapse.keep, collapse.remove);
+    let neighbours = |vertex: u32| {
+        let mut neighbours: Vec<u32> = vertex_triangles[vertex as usize]
+            .iter()
+            .filter(|&&index| alive[index])
+            .flat_map(|&index| triangles[index].iter().cloned())
+            .filter(|&corner| corner != vertex)
+            .collect();
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+    };
+    let keep_neighbours = neighbours(keep);
+    let shared = neighbours(remove)
+        .into_iter()
+        .filter(|neighbour| keep_neighbours.binary_search(neighbour).is_ok())
+        .count();
+    if shared != 2 {
+        return false;
+    }
+
+    for &vertex in &[keep, remove] {
+        for &index in vertex_triangles[vertex as usize].iter() {
+            let triangle = &triangles[index];
+            if !alive[index] || (triangle.contains(&keep) && triangle.contains(&remove)) {
+                continue;
+            }
+            let corner = |corner: u32| vertices[corner as usize].position;
+            let moved = |corner: u32| if corner == vertex {
+                collapse.position
+            } else {
+                vertices[corner as usize].position
+            };
+            let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+            let before = (corner(b) - corner(a)).cross(&(corner(c) - corner(a)));
+            let after = (moved(b) - moved(a)).cross(&(moved(c) - moved(a)));
+            // Slivers of no area, of which marching cubes makes a few, have
+            // no way round to keep.
+            if before.norm_squared() > 0.0 && after.dot(&before) <= 0.0 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use fields::FlatField;
+    use gfx::marching_cubes;
+    use gfx::testing::{assert_closed, Sphere};
+    use math::Vec3f;
+    use super::decimate;
+
+    #[test]
+    fn test_decimate_plane() {
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = marching_cubes(&FlatField::new(0.3), &min, &max, 0.25, 0.0);
+        let decimated = decimate(&mesh, 0, 1e-3);
+        assert!(decimated.indices.len() * 4 < mesh.indices.len());
+        for vertex in decimated.vertices.iter() {
+            assert!((vertex.position[1] - 0.3).abs() < 1e-4);
+        }
+        // Everything on the border is kept, for the neighbouring chunks.
+        let on_border = |position: &Vec3f| {
+            position[0] == min[0] || position[2] == min[2] || position[0] == 1.75 ||
+                position[2] == 1.75
+        };
+        let border = mesh.vertices.iter().filter(|vertex| on_border(&vertex.position)).count();
+        assert_eq!(
+            border,
+            decimated.vertices.iter().filter(|vertex| on_border(&vertex.position)).count()
+        );
+    }
+
+    #[test]
+    fn test_decimate_max_triangles() {
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = marching_cubes(&Sphere, &min, &max, 0.1, 0.0);
+        let decimated = decimate(&mesh, 200, ::std::f32::INFINITY);
+        assert!(decimated.indices.len() <= 200 * 3, "{}", decimated.indices.len() / 3);
+        assert!(decimated.indices.len() > 100 * 3);
+        assert_closed(&decimated);
+    }
+}
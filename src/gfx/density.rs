@@ -0,0 +1,457 @@
+use std::thread;
+
+use chan::{self, Receiver, Sender};
+use glium::backend::glutin_backend::GlutinFacade;
+use glium::glutin::HeadlessRendererBuilder;
+use glium::program::ComputeShader;
+use glium::uniforms::UniformBuffer;
+use glium::DisplayBuild;
+use nalgebra::{Norm, Point3, Vector3};
+
+use assets::Assets;
+use errors::{ChainErr, ErrorKind, Result};
+use fractal::{Fractal, FractalNoise, NoiseBasis};
+use math::{CpuScalar, Material, Mesher, ScalarField3, Vec3f, difference_gradients};
+use planet::{PlanetField, PlanetSpec};
+use super::marching_cubes::flat_triangle_table;
+use utils::read_utf8_file;
+
+/// A `PlanetField` whose batches of samples, i.e. the grids sampled by the
+/// chunk workers, have their noise evaluated by a compute shader. The shader
+/// runs on a thread of its own with a headless GL context, so the field can
+/// be sampled from any thread. Single samples stay on the CPU, while the
+/// samples of the gradients at the vertices of a chunk go in one batch too.
+///
+/// With `gpu_meshing`, another shader on the same thread finds the triangles
+/// of marching cubes through the sampled grids, leaving the CPU to place
+/// their vertices. The count of the corners found is read back first, then
+/// only those corners. Every chunk drawn is also a collider in the physics
+/// world, so its triangles have to come back to the CPU either way.
+pub struct GpuPlanetField {
+    field: PlanetField,
+    requests: Sender<GpuRequest>,
+    gpu_meshing: bool,
+}
+
+impl GpuPlanetField {
+    /// Fails if the spec uses noise which the shader doesn't implement or if
+    /// no OpenGL 4.3 context can be created to run it. If only the meshing
+    /// shader fails, the chunks are meshed on the CPU.
+    pub fn new(
+        seed: u32,
+        planet_spec: PlanetSpec,
+        assets: &Assets,
+        gpu_meshing: bool,
+    ) -> Result<Self> {
+        if planet_spec.mountain_basis != NoiseBasis::Simplex ||
+            planet_spec.plains_basis != NoiseBasis::Simplex
+        {
+            return Err(
+                ErrorKind::GpuDensityUnavailable(
+                    "only simplex noise is implemented on the GPU".to_owned(),
+                ).into(),
+            );
+        }
+        if planet_spec.num_plates > 0 {
+            return Err(
+                ErrorKind::GpuDensityUnavailable(
+                    "plate tectonics are not implemented on the GPU".to_owned(),
+                ).into(),
+            );
+        }
+        let source = try!(read_utf8_file(try!(assets.resolve(DENSITY_SHADER))));
+        let meshing_source = if gpu_meshing {
+            Some(try!(read_utf8_file(try!(assets.resolve(MARCHING_CUBES_SHADER)))))
+        } else {
+            None
+        };
+        let field = PlanetField::new(seed, planet_spec);
+        let permutation = field.permutation();
+        let uniforms = NoiseUniforms::new(field.spec());
+
+        let (request_send, request_recv) = chan::async();
+        let (ready_send, ready_recv) = chan::sync(1);
+        try!(
+            thread::Builder::new()
+                .name("gpu_density".to_owned())
+                .spawn(move || {
+                    run_density_shader(
+                        &source,
+                        meshing_source.as_ref().map(|source| &source[..]),
+                        permutation,
+                        uniforms,
+                        ready_send,
+                        request_recv,
+                    )
+                })
+                .chain_err(|| "Could not start the GPU density thread.")
+        );
+        match ready_recv.recv() {
+            Some(Ok(gpu_meshing)) => {
+                Ok(GpuPlanetField {
+                    field: field,
+                    requests: request_send,
+                    gpu_meshing: gpu_meshing,
+                })
+            }
+            Some(Err(message)) => Err(ErrorKind::GpuDensityUnavailable(message).into()),
+            None => Err(
+                ErrorKind::GpuDensityUnavailable("the GPU density thread died".to_owned())
+                    .into(),
+            ),
+        }
+    }
+}
+
+impl ScalarField3 for GpuPlanetField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.field.value_at(position)
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        self.field.value_at_lod(position, step)
+    }
+
+    /// The shader always evaluates every octave, it is fast enough not to
+    /// need the level of detail.
+    #[inline]
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        _step: CpuScalar,
+    ) {
+        self.values_at(positions, values)
+    }
+
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        assert_eq!(positions.len(), values.len());
+        if positions.is_empty() {
+            return;
+        }
+        let directions = positions
+            .iter()
+            .map(|position| {
+                let direction = Vec3f::new(position[0], position[1], position[2]).normalize();
+                let point = self.field.noise_point(&Vec3f::from(direction));
+                [point[0], point[1], point[2], 0.0]
+            })
+            .collect();
+        let (reply_send, reply_recv) = chan::sync(1);
+        self.requests.send(GpuRequest::Noise(NoiseRequest {
+            directions: directions,
+            reply: reply_send,
+        }));
+
+        match reply_recv.recv() {
+            Some(noise) => {
+                for ((position, value), noise) in
+                    positions.iter().zip(values.iter_mut()).zip(noise.into_iter())
+                {
+                    *value = self.field.value_from_noise(position, noise);
+                }
+            }
+            // The shader failed and the error was logged, redo the batch on
+            // the CPU.
+            None => self.field.values_at(positions, values),
+        }
+    }
+
+    /// The samples of the gradients go to the shader in one batch, rather
+    /// than six at a time on the CPU.
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        difference_gradients(self, positions, gradients)
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(position)
+    }
+
+    #[inline]
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        self.field.stratum_at(position)
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+
+    fn gpu_cell_triangles(
+        &self,
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        if !self.gpu_meshing {
+            return None;
+        }
+        let (reply_send, reply_recv) = chan::sync(1);
+        self.requests.send(GpuRequest::Cells(CellsRequest {
+            shape: [shape[0] as u32, shape[1] as u32, shape[2] as u32],
+            values: values.to_vec(),
+            iso_value: iso_value,
+            reply: reply_send,
+        }));
+        // None if the shader failed, the error was logged.
+        reply_recv.recv()
+    }
+
+    #[inline]
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        self.field.occluder_radius()
+    }
+}
+
+enum GpuRequest {
+    Noise(NoiseRequest),
+    Cells(CellsRequest),
+}
+
+struct NoiseRequest {
+    directions: Vec<[f32; 4]>,
+    reply: Sender<Vec<f32>>,
+}
+
+struct CellsRequest {
+    shape: [u32; 3],
+    values: Vec<f32>,
+    iso_value: f32,
+    reply: Sender<Vec<u32>>,
+}
+
+/// The parameters of a `FractalNoise` as passed to the shader.
+#[derive(Clone, Copy)]
+struct FractalUniforms {
+    num_octaves: i32,
+    fractal: i32,
+    params: [f32; 3],
+}
+
+impl FractalUniforms {
+    fn new(noise: &FractalNoise) -> Self {
+        FractalUniforms {
+            num_octaves: noise.num_octaves as i32,
+            // The `FRACTAL_*` constants in the shader.
+            fractal: match noise.fractal {
+                Fractal::Fbm => 0,
+                Fractal::Ridged => 1,
+                Fractal::Billow => 2,
+                Fractal::Hybrid => 3,
+            },
+            params: [noise.persistence, noise.wavelength, noise.lacunarity],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct NoiseUniforms {
+    mix: FractalUniforms,
+    mountains: FractalUniforms,
+    plains: FractalUniforms,
+}
+
+impl NoiseUniforms {
+    fn new(spec: &PlanetSpec) -> Self {
+        NoiseUniforms {
+            mix: FractalUniforms::new(&spec.terrain_mix()),
+            mountains: FractalUniforms::new(&spec.mountains()),
+            plains: FractalUniforms::new(&spec.plains()),
+        }
+    }
+}
+
+/// Serves the requests until the field, and with it the sender, is dropped.
+/// Whether the shaders could be set up is sent on `ready` first, true if the
+/// one of `meshing_source` was too.
+fn run_density_shader(
+    source: &str,
+    meshing_source: Option<&str>,
+    permutation: [u32; 256],
+    uniforms: NoiseUniforms,
+    ready: Sender<::std::result::Result<bool, String>>,
+    requests: Receiver<GpuRequest>,
+) {
+    let display = match HeadlessRendererBuilder::new(1, 1).build_glium() {
+        Ok(display) => display,
+        Err(err) => {
+            ready.send(Err(format!("could not create a headless context: {:?}", err)));
+            return;
+        }
+    };
+    if !ComputeShader::is_supported(&display) {
+        ready.send(Err("compute shaders need OpenGL 4.3".to_owned()));
+        return;
+    }
+    let shader = match ComputeShader::from_source(&display, source) {
+        Ok(shader) => shader,
+        Err(err) => {
+            ready.send(Err(format!("could not compile {}: {:?}", DENSITY_SHADER, err)));
+            return;
+        }
+    };
+    let permutation = match UniformBuffer::new(&display, permutation) {
+        Ok(permutation) => permutation,
+        Err(err) => {
+            ready.send(Err(format!("could not upload the permutation table: {:?}", err)));
+            return;
+        }
+    };
+    let meshing = meshing_source.and_then(|source| {
+        match set_up_meshing(&display, source) {
+            Ok(meshing) => Some(meshing),
+            Err(err) => {
+                warn!("Meshing the chunks on the CPU: {}", err);
+                None
+            }
+        }
+    });
+    ready.send(Ok(meshing.is_some()));
+    info!("Evaluating the planet's noise on the GPU.");
+    if meshing.is_some() {
+        info!("Finding the triangles of the chunks on the GPU.");
+    }
+
+    for request in requests.iter() {
+        match request {
+            GpuRequest::Noise(NoiseRequest { directions, reply }) => {
+                match evaluate_noise(&display, &shader, &permutation, &uniforms, &directions) {
+                    Ok(noise) => reply.send(noise),
+                    Err(err) => error!("{}", err),
+                }
+            }
+            GpuRequest::Cells(request) => {
+                if let Some((ref shader, ref triangle_table)) = meshing {
+                    match find_cell_triangles(&display, shader, triangle_table, &request) {
+                        Ok(corners) => request.reply.send(corners),
+                        Err(err) => error!("{}", err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Compiles the marching cubes shader and uploads the table of the triangles
+/// of each kind of cell.
+fn set_up_meshing(
+    display: &GlutinFacade,
+    source: &str,
+) -> Result<(ComputeShader, UniformBuffer<[i32]>)> {
+    let shader = try!(ComputeShader::from_source(display, source).map_err(|err| {
+        format!("could not compile {}: {:?}", MARCHING_CUBES_SHADER, err)
+    }));
+    let table = flat_triangle_table();
+    let mut triangle_table: UniformBuffer<[i32]> = try!(
+        UniformBuffer::empty_unsized(display, table.len() * 4)
+            .chain_err(|| "Could not create the triangle table buffer.")
+    );
+    triangle_table.map().copy_from_slice(&table);
+    Ok((shader, triangle_table))
+}
+
+fn evaluate_noise(
+    display: &GlutinFacade,
+    shader: &ComputeShader,
+    permutation: &UniformBuffer<[u32; 256]>,
+    uniforms: &NoiseUniforms,
+    directions: &[[f32; 4]],
+) -> Result<Vec<f32>> {
+    let num_positions = directions.len();
+    let mut positions: UniformBuffer<[[f32; 4]]> = try!(
+        UniformBuffer::empty_unsized(display, num_positions * 16)
+            .chain_err(|| "Could not create the positions buffer.")
+    );
+    positions.map().copy_from_slice(directions);
+    let mut noise: UniformBuffer<[f32]> = try!(
+        UniformBuffer::empty_unsized(display, num_positions * 4)
+            .chain_err(|| "Could not create the noise buffer.")
+    );
+
+    let NoiseUniforms {
+        mix,
+        mountains,
+        plains,
+    } = *uniforms;
+    shader.execute(
+        uniform! {
+            Positions: &*positions,
+            Noise: &*noise,
+            Permutation: &**permutation,
+            num_positions: num_positions as u32,
+            mix_octaves: mix.num_octaves,
+            mix_fractal: mix.fractal,
+            mix_params: mix.params,
+            mountains_octaves: mountains.num_octaves,
+            mountains_fractal: mountains.fractal,
+            mountains_params: mountains.params,
+            plains_octaves: plains.num_octaves,
+            plains_fractal: plains.fractal,
+            plains_params: plains.params,
+        },
+        ((num_positions + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE) as u32,
+        1,
+        1,
+    );
+    let noise = noise.map().to_vec();
+    Ok(noise)
+}
+
+/// Runs the marching cubes shader on the grid of `request`, reading back just
+/// the corners it found.
+fn find_cell_triangles(
+    display: &GlutinFacade,
+    shader: &ComputeShader,
+    triangle_table: &UniformBuffer<[i32]>,
+    request: &CellsRequest,
+) -> Result<Vec<u32>> {
+    let shape = request.shape;
+    let num_cells = (0..3).map(|axis| shape[axis].max(1) as usize - 1).product::<usize>();
+    if num_cells == 0 {
+        return Ok(vec![]);
+    }
+    let mut values: UniformBuffer<[f32]> = try!(
+        UniformBuffer::empty_unsized(display, request.values.len() * 4)
+            .chain_err(|| "Could not create the values buffer.")
+    );
+    values.map().copy_from_slice(&request.values);
+    let corners: UniformBuffer<[u32]> = try!(
+        UniformBuffer::empty_unsized(display, num_cells * MAX_CELL_CORNERS * 4)
+            .chain_err(|| "Could not create the corners buffer.")
+    );
+    let mut num_corners = try!(
+        UniformBuffer::new(display, 0u32).chain_err(|| "Could not create the corner count.")
+    );
+
+    shader.execute(
+        uniform! {
+            Values: &*values,
+            TriangleTable: &**triangle_table,
+            Count: &num_corners,
+            Corners: &*corners,
+            shape: shape,
+            iso_value: request.iso_value,
+        },
+        ((num_cells + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE) as u32,
+        1,
+        1,
+    );
+    let num_corners = *num_corners.map() as usize;
+    if num_corners == 0 {
+        return Ok(vec![]);
+    }
+    match corners.slice(0..num_corners).map(|corners| corners.read()) {
+        Some(Ok(corners)) => Ok(corners),
+        _ => Err("Could not read back the triangles.".into()),
+    }
+}
+
+const DENSITY_SHADER: &'static str = "shaders/density.comp";
+const MARCHING_CUBES_SHADER: &'static str = "shaders/marching_cubes.comp";
+// Marching cubes makes up to 5 triangles in a cell.
+const MAX_CELL_CORNERS: usize = 15;
+// The `local_size_x` of the shader.
+const WORKGROUP_SIZE: usize = 64;
@@ -0,0 +1,530 @@
+use nalgebra::{Dot, Norm, Point3, Vector3};
+use rayon::prelude::*;
+
+use math::{ScalarField3, Vec3f};
+use super::marching_cubes::{crosses_surface, Grid, grid_coordinates, iso_value_interpolation,
+                            sample_grid};
+use super::mesh::{Mesh, Vertex};
+
+/// Polygonises the iso-surface of `field` between `min` and `max` with a
+/// vertex in each cell the surface crosses and a quad around each edge it
+/// crosses. The vertex is the point nearest to the planes tangent to the
+/// surface where it crosses the cell's edges, so unlike with marching cubes
+/// creases and corners inside a cell are kept.
+///
+/// The grid is sampled one step past `max`, so that the quads around the
+/// edges on the faces at `max` are complete. The edges on the faces at `min`
+/// are left to the chunks below, which makes neighbouring chunks meet without
+/// gaps or overlaps.
+pub fn dual_contouring<Field: ScalarField3 + Sync>(
+    field: &Field,
+    min: &Vec3f,
+    max: &Vec3f,
+    step: f32,
+    iso_value: f32,
+) -> Mesh<Vertex> {
+    dual_mesh(field, min, max, step, iso_value, |grid, i, j, k| {
+        cell_vertex(field, grid, i, j, k, step, iso_value)
+    })
+}
+
+/// Meshes the iso-surface like `dual_contouring`, with the vertex of each
+/// cell the surface crosses placed by `cell_vertex`, given the grid and the
+/// grid point the cell is from.
+pub fn dual_mesh<Field, Place>(
+    field: &Field,
+    min: &Vec3f,
+    max: &Vec3f,
+    step: f32,
+    iso_value: f32,
+    cell_vertex: Place,
+) -> Mesh<Vertex>
+where
+    Field: ScalarField3,
+    Place: Fn(&Grid, usize, usize, usize) -> Option<Vertex> + Sync,
+{
+    let xs = extended_grid_coordinates(min[0], max[0], step);
+    let ys = extended_grid_coordinates(min[1], max[1], step);
+    let zs = extended_grid_coordinates(min[2], max[2], step);
+    let values = sample_grid(field, step, &xs, &ys, &zs);
+
+    if !crosses_surface(&values, iso_value) {
+        return Mesh {
+            name: "test".to_owned(),
+            vertices: vec![],
+            indices: vec![],
+        };
+    }
+
+    let grid = Grid {
+        xs: &xs,
+        ys: &ys,
+        zs: &zs,
+        values: &values,
+    };
+    let (nx, ny, nz) = (xs.len(), ys.len(), zs.len());
+    let value = |i: usize, j: usize, k: usize| values[(i * ny + j) * nz + k];
+
+    // The vertices of the cells, placed slab by slab in parallel since that's
+    // where the gradients are sampled.
+    let slabs: Vec<usize> = (0..nx - 1).collect();
+    let mut slab_vertices = vec![];
+    slabs
+        .par_iter()
+        .map(|&i| {
+            let mut vertices = vec![];
+            for j in 0..ny - 1 {
+                for k in 0..nz - 1 {
+                    if let Some(vertex) = cell_vertex(&grid, i, j, k) {
+                        vertices.push((j * (nz - 1) + k, vertex));
+                    }
+                }
+            }
+            vertices
+        })
+        .collect_into(&mut slab_vertices);
+
+    let slab_len = (ny - 1) * (nz - 1);
+    let mut cell_vertices = vec![NO_VERTEX; (nx - 1) * slab_len];
+    let mut vertices = vec![];
+    for (i, slab) in slab_vertices.into_iter().enumerate() {
+        for (cell, vertex) in slab {
+            cell_vertices[i * slab_len + cell] = vertices.len() as u32;
+            vertices.push(vertex);
+        }
+    }
+    let cell = |i: usize, j: usize, k: usize| cell_vertices[(i * (ny - 1) + j) * (nz - 1) + k];
+
+    // A chunk owns the edges from its grid points up to the one before last
+    // along each axis, and the edges at its faces at `max` but not at `min`
+    // across it. The cells around each edge are listed anticlockwise seen
+    // from its end.
+    let mut indices = vec![];
+    for i in 0..nx - 1 {
+        for j in 0..ny - 1 {
+            for k in 0..nz - 1 {
+                let inside = value(i, j, k) < iso_value;
+                if i + 2 < nx && j > 0 && k > 0 && (value(i + 1, j, k) < iso_value) != inside {
+                    let quad = [
+                        cell(i, j - 1, k - 1),
+                        cell(i, j, k - 1),
+                        cell(i, j, k),
+                        cell(i, j - 1, k),
+                    ];
+                    push_quad(&mut indices, quad, inside);
+                }
+                if j + 2 < ny && i > 0 && k > 0 && (value(i, j + 1, k) < iso_value) != inside {
+                    let quad = [
+                        cell(i - 1, j, k - 1),
+                        cell(i - 1, j, k),
+                        cell(i, j, k),
+                        cell(i, j, k - 1),
+                    ];
+                    push_quad(&mut indices, quad, inside);
+                }
+                if k + 2 < nz && i > 0 && j > 0 && (value(i, j, k + 1) < iso_value) != inside {
+                    let quad = [
+                        cell(i - 1, j - 1, k),
+                        cell(i, j - 1, k),
+                        cell(i, j, k),
+                        cell(i - 1, j, k),
+                    ];
+                    push_quad(&mut indices, quad, inside);
+                }
+            }
+        }
+    }
+
+    Mesh {
+        name: "test".to_owned(),
+        vertices: vertices,
+        indices: indices,
+    }
+}
+
+/// The grid coordinates of `grid_coordinates` and one more step after them.
+fn extended_grid_coordinates(min: f32, max: f32, step: f32) -> Vec<f32> {
+    let mut coordinates = grid_coordinates(min, max, step);
+    let last = coordinates[coordinates.len() - 1];
+    coordinates.push(last + step);
+    coordinates
+}
+
+/// Two triangles wound like those of `marching_cubes`, anticlockwise seen
+/// from the inside of the surface, which is at the start of the edge the
+/// quad is around if `inside_first`.
+fn push_quad(indices: &mut Vec<u32>, quad: [u32; 4], inside_first: bool) {
+    let (a, b, c, d) = if inside_first {
+        (quad[0], quad[3], quad[2], quad[1])
+    } else {
+        (quad[0], quad[1], quad[2], quad[3])
+    };
+    indices.extend_from_slice(&[a, b, c, a, c, d]);
+}
+
+/// An edge of a cell which the surface crosses.
+pub struct CellEdge {
+    /// The grid point the edge starts from.
+    pub start: Vector3<f32>,
+    /// The axis along the edge, 0 for x, 1 for y and 2 for z.
+    pub axis: usize,
+    /// The coordinates along `axis` of the ends of the edge, and the values
+    /// of the field there.
+    pub ends: (f32, f32),
+    pub values: (f32, f32),
+}
+
+impl CellEdge {
+    /// The linear interpolation of where the surface crosses the edge.
+    pub fn interpolated_crossing(&self, iso_value: f32) -> Vector3<f32> {
+        let mut position = self.start;
+        position[self.axis] = iso_value_interpolation(
+            iso_value,
+            self.ends.0,
+            self.ends.1,
+            self.values.0,
+            self.values.1,
+        );
+        position
+    }
+}
+
+/// The edges of the cell from grid point `(i, j, k)` which the surface
+/// crosses.
+pub fn crossed_edges(grid: &Grid, i: usize, j: usize, k: usize, iso_value: f32) -> Vec<CellEdge> {
+    let (ny, nz) = (grid.ys.len(), grid.zs.len());
+    // Corners are numbered by their offsets from `(i, j, k)`, one bit each.
+    let offset = |corner: usize| (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+    let mut values = [0.0; 8];
+    for corner in 0..8 {
+        let (di, dj, dk) = offset(corner);
+        values[corner] = grid.values[((i + di) * ny + j + dj) * nz + k + dk];
+    }
+
+    let mut edges = vec![];
+    for from in 0..8 {
+        for &bit in &[1, 2, 4] {
+            let to = from | bit;
+            if from & bit != 0 || (values[from] < iso_value) == (values[to] < iso_value) {
+                continue;
+            }
+            let (di, dj, dk) = offset(from);
+            let start = Vector3::new(grid.xs[i + di], grid.ys[j + dj], grid.zs[k + dk]);
+            let (axis, adjacent) = match bit {
+                1 => (0, grid.xs[i + 1]),
+                2 => (1, grid.ys[j + 1]),
+                _ => (2, grid.zs[k + 1]),
+            };
+            edges.push(CellEdge {
+                start: start,
+                axis: axis,
+                ends: (start[axis], adjacent),
+                values: (values[from], values[to]),
+            });
+        }
+    }
+    edges
+}
+
+/// The vertex of the cell from grid point `(i, j, k)`, if the surface
+/// crosses it.
+fn cell_vertex<Field: ScalarField3>(
+    field: &Field,
+    grid: &Grid,
+    i: usize,
+    j: usize,
+    k: usize,
+    step: f32,
+    iso_value: f32,
+) -> Option<Vertex> {
+    let mut crossings: Vec<(Vector3<f32>, Vector3<f32>)> = vec![];
+    for edge in crossed_edges(grid, i, j, k, iso_value) {
+        let position = edge_crossing(field, &edge, step, iso_value);
+        let gradient = field.gradient_at(&Point3::new(position[0], position[1], position[2]));
+        let length = gradient.norm();
+        let normal = if length > 0.0 {
+            gradient / length
+        } else {
+            gradient
+        };
+        crossings.push((position, normal));
+    }
+    if crossings.is_empty() {
+        return None;
+    }
+
+    // Minimises the squared distances to the tangent planes relative to the
+    // average of the crossings, which the vertex falls back to along the
+    // directions the planes don't pin down, e.g. along a crease.
+    let mut mass_point = Vector3::new(0.0, 0.0, 0.0);
+    for &(position, _) in crossings.iter() {
+        mass_point = mass_point + position;
+    }
+    mass_point = mass_point / crossings.len() as f32;
+    let mut normal_sum = Vector3::new(0.0, 0.0, 0.0);
+    let mut ata = [[0.0; 3]; 3];
+    let mut atb = [0.0; 3];
+    for &(position, normal) in crossings.iter() {
+        let distance = normal.dot(&(position - mass_point));
+        for row in 0..3 {
+            for column in 0..3 {
+                ata[row][column] += normal[row] * normal[column];
+            }
+            atb[row] += normal[row] * distance;
+        }
+        normal_sum = normal_sum + normal;
+    }
+    let offset = solve_qef(ata, atb);
+    let position = Vec3f::new(
+        clamp(mass_point[0] + offset[0], grid.xs[i], grid.xs[i + 1]),
+        clamp(mass_point[1] + offset[1], grid.ys[j], grid.ys[j + 1]),
+        clamp(mass_point[2] + offset[2], grid.zs[k], grid.zs[k + 1]),
+    );
+    let length = normal_sum.norm();
+    let normal = if length > 0.0 {
+        normal_sum / -length
+    } else {
+        normal_sum
+    };
+    Some(Vertex {
+        position: position,
+        normal: Vec3f::from(normal),
+    })
+}
+
+/// Where the surface crosses `edge`. Interpolating the values linearly is
+/// refined by false position, since the field needn't be linear along the
+/// edge, e.g. where it crosses a crease, and the planes through crossings off
+/// the surface would pull the vertex off it.
+fn edge_crossing<Field: ScalarField3>(
+    field: &Field,
+    edge: &CellEdge,
+    step: f32,
+    iso_value: f32,
+) -> Vector3<f32> {
+    let (mut ends, mut values) = (edge.ends, edge.values);
+    let mut position = edge.start;
+    for iteration in 0..CROSSING_REFINEMENTS + 1 {
+        position[edge.axis] =
+            iso_value_interpolation(iso_value, ends.0, ends.1, values.0, values.1);
+        if iteration == CROSSING_REFINEMENTS {
+            break;
+        }
+        let point = Point3::new(position[0], position[1], position[2]);
+        let value = field.value_at_lod(&point, step);
+        if (value < iso_value) == (values.0 < iso_value) {
+            ends.0 = position[edge.axis];
+            values.0 = value;
+        } else {
+            ends.1 = position[edge.axis];
+            values.1 = value;
+        }
+    }
+    position
+}
+
+/// The least squares solution of `ata * x = atb` with the smallest norm,
+/// ignoring the eigenvectors of `ata` with eigenvalues too small to trust.
+fn solve_qef(ata: [[f32; 3]; 3], atb: [f32; 3]) -> [f32; 3] {
+    // Jacobi rotations diagonalise the symmetric `ata` into `eigenvalues`,
+    // its eigenvectors being the columns of `rotation`.
+    let mut eigenvalues = ata;
+    let mut rotation = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..QEF_SWEEPS {
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if eigenvalues[p][q].abs() < 1e-12 {
+                continue;
+            }
+            let theta = (eigenvalues[q][q] - eigenvalues[p][p]) / (2.0 * eigenvalues[p][q]);
+            let tan = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let cos = 1.0 / (tan * tan + 1.0).sqrt();
+            let sin = tan * cos;
+            for row in 0..3 {
+                let (a, b) = (eigenvalues[row][p], eigenvalues[row][q]);
+                eigenvalues[row][p] = cos * a - sin * b;
+                eigenvalues[row][q] = sin * a + cos * b;
+                let (a, b) = (rotation[row][p], rotation[row][q]);
+                rotation[row][p] = cos * a - sin * b;
+                rotation[row][q] = sin * a + cos * b;
+            }
+            for column in 0..3 {
+                let (a, b) = (eigenvalues[p][column], eigenvalues[q][column]);
+                eigenvalues[p][column] = cos * a - sin * b;
+                eigenvalues[q][column] = sin * a + cos * b;
+            }
+        }
+    }
+
+    let largest = eigenvalues[0][0].max(eigenvalues[1][1]).max(eigenvalues[2][2]);
+    let mut solution = [0.0; 3];
+    for axis in 0..3 {
+        let eigenvalue = eigenvalues[axis][axis];
+        if eigenvalue <= QEF_TRUNCATION * largest || eigenvalue <= 0.0 {
+            continue;
+        }
+        let mut projection = 0.0;
+        for row in 0..3 {
+            projection += rotation[row][axis] * atb[row];
+        }
+        for row in 0..3 {
+            solution[row] += rotation[row][axis] * projection / eigenvalue;
+        }
+    }
+    solution
+}
+
+#[inline]
+fn clamp(value: f32, low: f32, high: f32) -> f32 {
+    value.max(low).min(high)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Cross, Dot, Norm, Point3, Vector3};
+
+    use gfx::{marching_cubes, Mesh};
+    use gfx::testing::{assert_closed, Sphere};
+    use math::{CpuScalar, ScalarField3, Vec3f};
+    use super::{dual_contouring, solve_qef};
+
+    /// The cube from -1 to 1, with its exact gradient.
+    struct Cube;
+
+    impl ScalarField3 for Cube {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            position[0].abs().max(position[1].abs()).max(position[2].abs()) - 1.0
+        }
+
+        fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+            let (x, y, z) = (position[0].abs(), position[1].abs(), position[2].abs());
+            if x >= y && x >= z {
+                Vector3::new(position[0].signum(), 0.0, 0.0)
+            } else if y >= z {
+                Vector3::new(0.0, position[1].signum(), 0.0)
+            } else {
+                Vector3::new(0.0, 0.0, position[2].signum())
+            }
+        }
+    }
+
+    fn triangles(mesh: &Mesh<::gfx::Vertex>) -> Vec<(Vec3f, Vec3f, Vec3f)> {
+        mesh.indices
+            .chunks(3)
+            .map(|triangle| {
+                (
+                    mesh.vertices[triangle[0] as usize].position,
+                    mesh.vertices[triangle[1] as usize].position,
+                    mesh.vertices[triangle[2] as usize].position,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_solve_qef() {
+        // Three planes through (0.2, -0.1, 0.3) meet at a corner there.
+        let normals = [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+        let corner = Vector3::new(0.2, -0.1, 0.3);
+        let (mut ata, mut atb) = ([[0.0; 3]; 3], [0.0; 3]);
+        for normal in normals.iter() {
+            for row in 0..3 {
+                for column in 0..3 {
+                    ata[row][column] += normal[row] * normal[column];
+                }
+                atb[row] += normal[row] * normal.dot(&corner);
+            }
+        }
+        let solution = solve_qef(ata, atb);
+        for axis in 0..3 {
+            assert!((solution[axis] - corner[axis]).abs() < 1e-5);
+        }
+
+        // Two planes leave the solution free along their crease, where it's
+        // the one nearest the origin.
+        let normals = [Vector3::new(1.0, 1.0, 0.0), Vector3::new(1.0, -1.0, 0.0)];
+        let (mut ata, mut atb) = ([[0.0; 3]; 3], [0.0; 3]);
+        for normal in normals.iter() {
+            let normal = *normal * (0.5 as f32).sqrt();
+            for row in 0..3 {
+                for column in 0..3 {
+                    ata[row][column] += normal[row] * normal[column];
+                }
+                atb[row] += normal[row] * normal.dot(&corner);
+            }
+        }
+        let solution = solve_qef(ata, atb);
+        assert!((solution[0] - 0.2).abs() < 1e-5 && (solution[1] + 0.1).abs() < 1e-5);
+        assert!(solution[2].abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_dual_contouring_keeps_corners() {
+        // The faces of the cube are between grid planes, so marching cubes
+        // cuts its corners off.
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = dual_contouring(&Cube, &min, &max, 0.35, 0.0);
+        assert!(!mesh.indices.is_empty());
+        for vertex in mesh.vertices.iter() {
+            assert!(Cube.value_at(vertex.position.as_point()).abs() < 1e-4);
+        }
+        let nearest_to_corner = |vertices: &[::gfx::Vertex]| {
+            vertices
+                .iter()
+                .map(|vertex| (vertex.position - Vec3f::new(1.0, 1.0, 1.0)).norm())
+                .fold(::std::f32::INFINITY, f32::min)
+        };
+        assert!(nearest_to_corner(&mesh.vertices) < 1e-4);
+        let smooth = marching_cubes(&Cube, &min, &max, 0.35, 0.0);
+        assert!(nearest_to_corner(&smooth.vertices) > 0.1);
+
+        // Closed, with the triangles wound like those of marching cubes, to
+        // face the inside.
+        assert_closed(&mesh);
+        let facing_in = |triangles: &[(Vec3f, Vec3f, Vec3f)]| {
+            triangles.iter().all(|&(a, b, c)| (b - a).cross(&(c - a)).dot(&(a + b + c)) < 0.0)
+        };
+        assert!(facing_in(&triangles(&mesh)) && facing_in(&triangles(&smooth)));
+    }
+
+    #[test]
+    fn test_dual_contouring_chunks_meet() {
+        // Two chunks side by side mesh into one closed sphere, like chunks of
+        // the level of detail which are meshed a step past their size.
+        let step = 0.25;
+        let mesh = |min: Vec3f, max: Vec3f| {
+            dual_contouring(&Sphere, &min, &(max + step), step, 0.0)
+        };
+        let left = mesh(Vec3f::new(-2.0, -2.0, -2.0), Vec3f::new(0.0, 2.0, 2.0));
+        let right = mesh(Vec3f::new(0.0, -2.0, -2.0), Vec3f::new(2.0, 2.0, 2.0));
+        assert!(!left.indices.is_empty() && !right.indices.is_empty());
+        let mut both = Mesh::merge(&[left, right]);
+        both.weld(0.0);
+        assert_closed(&both);
+
+        // Nothing is polygonised inside the sphere.
+        let inside = dual_contouring(
+            &Sphere,
+            &Vec3f::new(-0.5, -0.5, -0.5),
+            &Vec3f::new(0.5, 0.5, 0.5),
+            step,
+            0.0,
+        );
+        assert!(inside.vertices.is_empty() && inside.indices.is_empty());
+    }
+}
+
+// Sweeps of Jacobi rotations over the matrix of a cell's planes, plenty for
+// a 3 x 3 matrix to converge.
+const QEF_SWEEPS: usize = 6;
+// Eigenvalues this fraction of the largest or smaller are taken for zero, so
+// nearly parallel planes don't throw the vertex far off along their crease.
+const QEF_TRUNCATION: f32 = 0.1;
+// Steps of false position finding where the surface crosses an edge.
+const CROSSING_REFINEMENTS: usize = 4;
+// Marks the cells the surface doesn't cross.
+const NO_VERTEX: u32 = ::std::u32::MAX;
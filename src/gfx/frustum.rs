@@ -0,0 +1,78 @@
+use nalgebra::{Point3, Vector4};
+use ncollide::bounding_volume::AABB;
+
+use math::{GpuScalar, Matrix4f};
+
+/// The volume a projection shows, as the planes bounding it. Each plane
+/// `(a, b, c, d)` has the points inside on the side where
+/// `a * x + b * y + c * z + d >= 0`.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<GpuScalar>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a matrix taking points to clip space, e.g.
+    /// `perspective * view * model`, which puts the frustum in the frame the
+    /// model matrix takes from.
+    pub fn from_matrix(matrix: &Matrix4f) -> Self {
+        let row = |index: usize| {
+            Vector4::new(
+                matrix[(index, 0)],
+                matrix[(index, 1)],
+                matrix[(index, 2)],
+                matrix[(index, 3)],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        Frustum { planes: [w + x, w - x, w + y, w - y, w + z, w - z] }
+    }
+
+    /// Whether any of `aabb` may be in view. Boxes near the corners of the
+    /// frustum can pass without being visible, but none in view is culled.
+    pub fn intersects_aabb(&self, aabb: &AABB<Point3<GpuScalar>>) -> bool {
+        let (mins, maxs) = (aabb.mins(), aabb.maxs());
+        self.planes.iter().all(|plane| {
+            // The corner of the box furthest along the normal of the plane.
+            let corner = |axis: usize| if plane[axis] >= 0.0 { maxs[axis] } else { mins[axis] };
+            plane[0] * corner(0) + plane[1] * corner(1) + plane[2] * corner(2) + plane[3] >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Eye, Matrix4, Point3};
+    use ncollide::bounding_volume::AABB;
+
+    use math::Matrix4f;
+    use super::Frustum;
+
+    #[test]
+    fn test_frustum_culls_boxes_out_of_view() {
+        // Looking down +z, like `perspective_matrix` with a square frame.
+        let (near, far, f) = (0.1f32, 100.0f32, 1.0f32);
+        let perspective = [
+            [f, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, (far + near) / (far - near), 1.0],
+            [0.0, 0.0, -(2.0 * far * near) / (far - near), 0.0],
+        ];
+        let view: Matrix4<f32> = Eye::new_identity(4);
+        let frustum = Frustum::from_matrix(&(Matrix4f::from(&perspective) * Matrix4f::from(view)));
+        let aabb = |min: [f32; 3], max: [f32; 3]| {
+            AABB::new(Point3::new(min[0], min[1], min[2]), Point3::new(max[0], max[1], max[2]))
+        };
+
+        assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, 5.0], [1.0, 1.0, 6.0])));
+        // Straddling the edge of the view.
+        assert!(frustum.intersects_aabb(&aabb([9.0, -1.0, 9.5], [11.0, 1.0, 10.0])));
+        // Around the eye.
+        assert!(frustum.intersects_aabb(&aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 1.0])));
+        // Behind, beside, above and beyond the far plane.
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, -6.0], [1.0, 1.0, -5.0])));
+        assert!(!frustum.intersects_aabb(&aabb([11.0, -1.0, 9.0], [12.0, 1.0, 10.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, 11.0, 9.0], [1.0, 12.0, 10.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-1.0, -1.0, 101.0], [1.0, 1.0, 102.0])));
+    }
+}
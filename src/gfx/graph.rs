@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+
+use glium::{Blend, DrawParameters, Frame, Program, Surface, VertexBuffer};
+use glium::index::{NoIndices, PrimitiveType};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+
+#[derive(Copy, Clone, Debug)]
+pub struct GraphVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+implement_vertex!(GraphVertex, position, color);
+
+/// Timings of a single frame, in seconds. A missing timing (e.g. no chunk
+/// arrived this frame) repeats the previous sample.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimes {
+    pub cpu: f32,
+    pub gpu: Option<f32>,
+    pub chunk_latency: Option<f32>,
+}
+
+/// A rolling line graph of the last `NUM_SAMPLES` frame times, drawn in the
+/// bottom left corner with reference lines at 60 and 30 fps.
+pub struct FrameTimeGraph<'a> {
+    draw_parameters: DrawParameters<'a>,
+    program: Program,
+    cpu: VecDeque<f32>,
+    gpu: VecDeque<f32>,
+    chunk_latency: VecDeque<f32>,
+}
+
+impl<'a> FrameTimeGraph<'a> {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        Ok(FrameTimeGraph {
+            draw_parameters: DrawParameters {
+                blend: Blend::alpha_blending(),
+                ..Default::default()
+            },
+            program: program,
+            cpu: VecDeque::with_capacity(NUM_SAMPLES),
+            gpu: VecDeque::with_capacity(NUM_SAMPLES),
+            chunk_latency: VecDeque::with_capacity(NUM_SAMPLES),
+        })
+    }
+
+    pub fn push(&mut self, times: &FrameTimes) {
+        push_sample(&mut self.cpu, Some(times.cpu));
+        push_sample(&mut self.gpu, times.gpu);
+        push_sample(&mut self.chunk_latency, times.chunk_latency);
+    }
+
+    /// The most recent sample of each series, as `FrameTimes`.
+    pub fn latest(&self) -> FrameTimes {
+        FrameTimes {
+            cpu: self.cpu.back().cloned().unwrap_or(0.0),
+            gpu: self.gpu.back().cloned(),
+            chunk_latency: self.chunk_latency.back().cloned(),
+        }
+    }
+
+    pub fn draw(&self, window: &Window, frame: &mut Frame) -> Result<()> {
+        let (width, height) = frame.get_dimensions();
+        let to_screen = |x: f32, y: f32| {
+            [
+                2.0 * (GRAPH_MARGIN + x * GRAPH_WIDTH) / width as f32 - 1.0,
+                2.0 * (GRAPH_MARGIN + y.min(1.0) * GRAPH_HEIGHT) / height as f32 - 1.0,
+            ]
+        };
+
+        let mut lines = vec![];
+        for &reference in [1.0 / 60.0, 1.0 / 30.0].iter() {
+            let y = reference / MAX_FRAME_TIME;
+            lines.push(vec![
+                GraphVertex {
+                    position: to_screen(0.0, y),
+                    color: REFERENCE_COLOR,
+                },
+                GraphVertex {
+                    position: to_screen(1.0, y),
+                    color: REFERENCE_COLOR,
+                },
+            ]);
+        }
+        for &(samples, color) in [
+            (&self.chunk_latency, CHUNK_LATENCY_COLOR),
+            (&self.gpu, GPU_COLOR),
+            (&self.cpu, CPU_COLOR),
+        ].iter()
+        {
+            // Samples are right aligned so the newest is always at the edge.
+            let offset = NUM_SAMPLES - samples.len();
+            lines.push(
+                samples
+                    .iter()
+                    .enumerate()
+                    .map(|(index, sample)| {
+                        GraphVertex {
+                            position: to_screen(
+                                (offset + index) as f32 / (NUM_SAMPLES - 1) as f32,
+                                sample / MAX_FRAME_TIME,
+                            ),
+                            color: color,
+                        }
+                    })
+                    .collect(),
+            );
+        }
+
+        for vertices in lines.iter().filter(|vertices| vertices.len() > 1) {
+            let vertex_buffer = try!(
+                VertexBuffer::new(window.facade(), vertices)
+                    .chain_err(|| "Cannot create vertex buffer.")
+            );
+            try!(
+                frame
+                    .draw(
+                        &vertex_buffer,
+                        &NoIndices(PrimitiveType::LineStrip),
+                        &self.program,
+                        &uniform!{},
+                        &self.draw_parameters,
+                    )
+                    .chain_err(|| "Could not render frame time graph.")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<f32>, sample: Option<f32>) {
+    let sample = match sample.or_else(|| samples.back().cloned()) {
+        Some(sample) => sample,
+        None => return,
+    };
+    if samples.len() == NUM_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+const VERTEX_SHADER: &'static str = "shaders/graph.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/graph.frag";
+
+const NUM_SAMPLES: usize = 240;
+// Frame times are clamped to this many seconds at the top of the graph.
+const MAX_FRAME_TIME: f32 = 0.05;
+
+const GRAPH_MARGIN: f32 = 8.0;
+const GRAPH_WIDTH: f32 = 480.0;
+const GRAPH_HEIGHT: f32 = 120.0;
+
+const CPU_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const GPU_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+const CHUNK_LATENCY_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+const REFERENCE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.3];
@@ -229,6 +229,78 @@ fn mouse_button_to_index(button: MouseButton) -> Option<usize> {
     })
 }
 
+/// Parses a key name as used in config files (e.g. `"W"`, `"Space"`, `"F12"`)
+/// into a `KeyCode`. Names are matched case-insensitively.
+pub fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    let name = name.to_lowercase();
+    Some(match name.as_str() {
+        "a" => KeyCode::A,
+        "b" => KeyCode::B,
+        "c" => KeyCode::C,
+        "d" => KeyCode::D,
+        "e" => KeyCode::E,
+        "f" => KeyCode::F,
+        "g" => KeyCode::G,
+        "h" => KeyCode::H,
+        "i" => KeyCode::I,
+        "j" => KeyCode::J,
+        "k" => KeyCode::K,
+        "l" => KeyCode::L,
+        "m" => KeyCode::M,
+        "n" => KeyCode::N,
+        "o" => KeyCode::O,
+        "p" => KeyCode::P,
+        "q" => KeyCode::Q,
+        "r" => KeyCode::R,
+        "s" => KeyCode::S,
+        "t" => KeyCode::T,
+        "u" => KeyCode::U,
+        "v" => KeyCode::V,
+        "w" => KeyCode::W,
+        "x" => KeyCode::X,
+        "y" => KeyCode::Y,
+        "z" => KeyCode::Z,
+        "0" | "key0" => KeyCode::Key0,
+        "1" | "key1" => KeyCode::Key1,
+        "2" | "key2" => KeyCode::Key2,
+        "3" | "key3" => KeyCode::Key3,
+        "4" | "key4" => KeyCode::Key4,
+        "5" | "key5" => KeyCode::Key5,
+        "6" | "key6" => KeyCode::Key6,
+        "7" | "key7" => KeyCode::Key7,
+        "8" | "key8" => KeyCode::Key8,
+        "9" | "key9" => KeyCode::Key9,
+        "f1" => KeyCode::F1,
+        "f2" => KeyCode::F2,
+        "f3" => KeyCode::F3,
+        "f4" => KeyCode::F4,
+        "f5" => KeyCode::F5,
+        "f6" => KeyCode::F6,
+        "f7" => KeyCode::F7,
+        "f8" => KeyCode::F8,
+        "f9" => KeyCode::F9,
+        "f10" => KeyCode::F10,
+        "f11" => KeyCode::F11,
+        "f12" => KeyCode::F12,
+        "escape" | "esc" => KeyCode::Escape,
+        "space" => KeyCode::Space,
+        "return" | "enter" => KeyCode::Return,
+        "tab" => KeyCode::Tab,
+        "back" | "backspace" => KeyCode::Back,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "lshift" => KeyCode::LShift,
+        "rshift" => KeyCode::RShift,
+        "lcontrol" | "lctrl" => KeyCode::LControl,
+        "rcontrol" | "rctrl" => KeyCode::RControl,
+        "lalt" => KeyCode::LAlt,
+        "ralt" => KeyCode::RAlt,
+        _ => return None,
+    })
+}
+
 fn center_cursor(window: &mut Window) -> Result<()> {
     let size = window.size();
     window.set_cursor_position((size.width as i32) / 2, (size.height as i32) / 2)
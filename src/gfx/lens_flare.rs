@@ -0,0 +1,175 @@
+use glium::{Blend, DrawParameters, Program, Surface};
+use glium::draw_parameters::{BlendingFunction, LinearBlendingFactor, TimeElapsedQuery};
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::{MipmapsOption, Texture2d, UncompressedFloatFormat};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+use gfx::render_graph::{screen_sampler, RenderGraph};
+use math::Vec3f;
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LensFlareSpec {
+    /// How bright the flare is over the frame, none if 0.
+    pub intensity: f32,
+    pub ghosts: Vec<FlareGhost>,
+}
+
+/// One of the discs of light the lens reflects the sun into, along the line
+/// from the sun through the centre of the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FlareGhost {
+    /// Where along the line, 0 on the sun, 0.5 at the centre and 1 where the
+    /// sun would be mirrored across it.
+    pub offset: f32,
+    /// The radius, as a fraction of the screen's height.
+    pub size: f32,
+    pub color: Vec3f,
+}
+
+impl Default for LensFlareSpec {
+    fn default() -> Self {
+        let ghost = |offset, size, red, green, blue| {
+            FlareGhost {
+                offset: offset,
+                size: size,
+                color: Vec3f::new(red, green, blue),
+            }
+        };
+        LensFlareSpec {
+            intensity: 0.4,
+            ghosts: vec![
+                ghost(0.0, 0.2, 1.0, 0.9, 0.7),
+                ghost(0.3, 0.04, 0.4, 0.6, 1.0),
+                ghost(0.55, 0.08, 0.3, 1.0, 0.5),
+                ghost(0.7, 0.03, 1.0, 0.5, 0.3),
+                ghost(0.9, 0.12, 0.5, 0.4, 1.0),
+            ],
+        }
+    }
+}
+
+impl LensFlareSpec {
+    pub fn enabled(&self) -> bool {
+        self.intensity > 0.0 && !self.ghosts.is_empty()
+    }
+}
+
+/// Adds the ghosts of the sun to the frame after the post pass, each a quad
+/// around where it is. They're as bright as the sun is visible, which is
+/// tested against the g-buffer's positions around it, so the flare fades as
+/// the sun sets behind the mountains.
+pub struct LensFlareRenderer<'a> {
+    // A texel, the fraction of the sun not hidden by the surfaces.
+    visibility: Texture2d,
+    visibility_program: Program,
+    ghost_program: Program,
+    draw_parameters: DrawParameters<'a>,
+    spec: LensFlareSpec,
+}
+
+impl<'a> LensFlareRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets, spec: &LensFlareSpec) -> Result<Self> {
+        let visibility = try!(
+            Texture2d::empty_with_format(
+                window.facade(),
+                UncompressedFloatFormat::F16,
+                MipmapsOption::NoMipmap,
+                1,
+                1,
+            ).chain_err(|| "Could not create the visibility of the sun.")
+        );
+        // The flare is added to the frame, which stays as opaque.
+        let draw_parameters = DrawParameters {
+            blend: Blend {
+                color: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::One,
+                    destination: LinearBlendingFactor::One,
+                },
+                alpha: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::Zero,
+                    destination: LinearBlendingFactor::One,
+                },
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            ..Default::default()
+        };
+        Ok(LensFlareRenderer {
+            visibility: visibility,
+            visibility_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(VISIBILITY_FRAGMENT_SHADER)),
+            )),
+            ghost_program: try!(window.program(
+                try!(assets.resolve(GHOST_VERTEX_SHADER)),
+                try!(assets.resolve(GHOST_FRAGMENT_SHADER)),
+            )),
+            draw_parameters: draw_parameters,
+            spec: spec.clone(),
+        })
+    }
+
+    /// `sun` is where the sun is on the screen, in [0, 1] across it.
+    pub fn render<S: Surface>(
+        &self,
+        window: &Window,
+        render_graph: &RenderGraph,
+        target: &mut S,
+        sun: [f32; 2],
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_lens_flare");
+        let (width, height) = target.get_dimensions();
+        let aspect = width as f32 / height as f32;
+        {
+            let mut visibility = try!(
+                SimpleFrameBuffer::new(window.facade(), &self.visibility)
+                    .chain_err(|| "Could not create the framebuffer of the sun's visibility.")
+            );
+            let uniforms =
+                uniform! {
+                u_position: screen_sampler(&render_graph.targets().position),
+                u_sun: sun,
+                u_aspect: aspect,
+            };
+            try!(render_graph.draw_quad(
+                &mut visibility,
+                &self.visibility_program,
+                &uniforms,
+                None,
+            ));
+        }
+
+        let sun = [sun[0] * 2.0 - 1.0, sun[1] * 2.0 - 1.0];
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        for ghost in self.spec.ghosts.iter() {
+            let scale = 1.0 - 2.0 * ghost.offset;
+            let color = ghost.color * self.spec.intensity;
+            let uniforms =
+                uniform! {
+                u_visibility: screen_sampler(&self.visibility),
+                u_centre: [sun[0] * scale, sun[1] * scale],
+                u_size: ghost.size,
+                u_aspect: aspect,
+                u_color: &color,
+            };
+            try!(render_graph.draw_quad_with(
+                target,
+                &self.ghost_program,
+                &uniforms,
+                &draw_parameters,
+            ));
+        }
+        Ok(())
+    }
+}
+
+const QUAD_VERTEX_SHADER: &'static str = "shaders/quad.vert";
+const VISIBILITY_FRAGMENT_SHADER: &'static str = "shaders/flare_visibility.frag";
+const GHOST_VERTEX_SHADER: &'static str = "shaders/flare.vert";
+const GHOST_FRAGMENT_SHADER: &'static str = "shaders/flare.frag";
@@ -0,0 +1,176 @@
+use glium::{Blend, DrawParameters, Program};
+use glium::draw_parameters::{BlendingFunction, LinearBlendingFactor, TimeElapsedQuery};
+use nalgebra::Point3;
+use ncollide::bounding_volume::AABB;
+use num::Zero;
+
+use assets::Assets;
+use errors::Result;
+use gfx::Frustum;
+use gfx::render_graph::{screen_sampler, Pass, RenderGraph};
+use gfx::Window;
+use math::{GpuScalar, Matrix4f, Vec3f};
+use trace;
+
+/// A light besides the sun, like a torch or the glow of lava, which lights
+/// the surfaces within `radius` of it, dimming with the square of the
+/// distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    /// In the world.
+    pub position: Vec3f,
+    /// Times the intensity, the light at a distance of 1.
+    pub color: Vec3f,
+    pub radius: GpuScalar,
+    /// Shines only within a cone if a spot light, all around otherwise.
+    pub spot: Option<Spot>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spot {
+    /// The unit vector along the axis of the cone.
+    pub direction: Vec3f,
+    /// The angles, in degrees, from the axis to where the light starts to
+    /// fade and to the edge of the cone.
+    pub inner_angle: GpuScalar,
+    pub outer_angle: GpuScalar,
+}
+
+impl PointLight {
+    /// The box around all it lights.
+    pub fn aabb(&self) -> AABB<Point3<GpuScalar>> {
+        let (position, radius) = (self.position, self.radius);
+        AABB::new(
+            Point3::new(position[0] - radius, position[1] - radius, position[2] - radius),
+            Point3::new(position[0] + radius, position[1] + radius, position[2] + radius),
+        )
+    }
+
+    /// The cosines of the inner and outer angles of the cone, which any
+    /// direction is within for a light shining all around.
+    fn cone_cosines(&self) -> (GpuScalar, GpuScalar) {
+        match self.spot {
+            Some(ref spot) => (
+                spot.inner_angle.to_radians().cos(),
+                spot.outer_angle.to_radians().cos(),
+            ),
+            None => (-1.0, -2.0),
+        }
+    }
+}
+
+/// Adds the lights to the scene after the lighting pass, a quad over the
+/// screen for each of them in view. The surfaces of every body are lit, as
+/// positions relative to the camera are the same in the frames of all of
+/// them.
+pub struct LightRenderer<'a> {
+    program: Program,
+    draw_parameters: DrawParameters<'a>,
+}
+
+impl<'a> LightRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        // The light is added to the scene, which stays as opaque.
+        let draw_parameters = DrawParameters {
+            blend: Blend {
+                color: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::One,
+                    destination: LinearBlendingFactor::One,
+                },
+                alpha: BlendingFunction::Addition {
+                    source: LinearBlendingFactor::Zero,
+                    destination: LinearBlendingFactor::One,
+                },
+                constant_value: (0.0, 0.0, 0.0, 0.0),
+            },
+            ..Default::default()
+        };
+        Ok(LightRenderer {
+            program: program,
+            draw_parameters: draw_parameters,
+        })
+    }
+
+    /// `eye` is the camera's position in the world.
+    pub fn render(
+        &self,
+        window: &Window,
+        render_graph: &RenderGraph,
+        lights: &[PointLight],
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        eye: &Vec3f,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_lights");
+        let frustum = Frustum::from_matrix(&(Matrix4f::from(&perspective) * *view));
+        let targets = render_graph.targets();
+        let mut lighting = try!(render_graph.target(window, Pass::Lighting));
+        for light in lights.iter().filter(|light| frustum.intersects_aabb(&light.aabb())) {
+            let position = light.position - *eye;
+            let direction = light.spot.map_or(Vec3f::zero(), |spot| spot.direction);
+            let (inner, outer) = light.cone_cosines();
+            let uniforms =
+                uniform! {
+                u_albedo: screen_sampler(&targets.albedo),
+                u_normal: screen_sampler(&targets.normal),
+                u_position: screen_sampler(&targets.position),
+                u_light_position: &position,
+                u_light_color: &light.color,
+                u_light_radius: light.radius,
+                u_spot_direction: &direction,
+                u_spot_inner: inner,
+                u_spot_outer: outer,
+            };
+            let draw_parameters = DrawParameters {
+                time_elapsed_query: time_query,
+                ..self.draw_parameters.clone()
+            };
+            try!(render_graph.draw_quad_with(
+                &mut lighting,
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use math::Vec3f;
+
+    use super::{PointLight, Spot};
+
+    #[test]
+    fn test_light_bounds_and_cones() {
+        let mut light = PointLight {
+            position: Vec3f::new(1.0, 2.0, 3.0),
+            color: Vec3f::new(1.0, 1.0, 1.0),
+            radius: 10.0,
+            spot: None,
+        };
+        let (inner, outer) = light.cone_cosines();
+        assert!(inner <= -1.0 && outer < inner);
+        let aabb = light.aabb();
+        assert_eq!(-9.0, aabb.mins()[0]);
+        assert_eq!(13.0, aabb.maxs()[2]);
+
+        light.spot = Some(Spot {
+            direction: Vec3f::new(0.0, 0.0, 1.0),
+            inner_angle: 0.0,
+            outer_angle: 90.0,
+        });
+        let (inner, outer) = light.cone_cosines();
+        assert!((inner - 1.0).abs() < 1e-6);
+        assert!(outer.abs() < 1e-6);
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/quad.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/light.frag";
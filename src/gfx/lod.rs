@@ -1,20 +1,344 @@
-use std::collections::{VecDeque, HashSet};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
 use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use chan::{self, Receiver, Sender};
 use glium::index::PrimitiveType;
 use glium::{IndexBuffer, VertexBuffer};
 use lru_time_cache::LruCache;
+use ncollide::bounding_volume::{AABB, BoundingSphere};
 use ncollide::shape::{ShapeHandle, TriMesh};
-use nalgebra::{Isometry3, Point3, Translation};
+use nalgebra::{Isometry3, Norm, Point3};
 use num::Zero;
 use threadpool::ThreadPool;
 
-use errors::{ChainErr, Result};
-use gfx::{marching_cubes, BarycentricVertex, Camera, Mesh, Window};
-use math::{GpuScalar, Vec3f, ScalarField3};
+use crash;
+use errors::{ChainErr, Error, ErrorKind, Result};
+use flora::{upload_plants, Flora, Plant};
+use gfx::{ambient_occlusion, decimate, dual_contouring, marching_cubes_with_seams, surface_nets,
+          BarycentricVertex, Mesh, Seams, Vertex, Window, FIELD_OF_VIEW, NO_SEAMS};
+use gfx::mesh_cache::MeshCache;
+use math::{GpuScalar, Mesher, Vec3f, ScalarField3};
+use prefab::PrefabInstances;
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodSpec {
+    pub max_level: u8,
+    pub step: f32,
+    pub size: f32,
+    /// The steps across the grid each chunk is meshed on, whatever its size.
+    pub chunk_num_steps: f32,
+    /// The chunks are told apart by their positions and sizes rounded to a
+    /// grid this many cells a unit long, which the finest chunks must span
+    /// at least a cell of, and the root at most `MAX_VOXEL_CELLS`.
+    pub voxel_density: f32,
+    /// At most this many chunks are kept loaded, the least recently drawn
+    /// are evicted beyond that, and this many are remembered to be empty.
+    pub loaded_chunks_capacity: usize,
+    pub empty_chunks_capacity: usize,
+    /// Megabytes of GPU memory the loaded chunks of all the bodies may take,
+    /// as estimated from their buffers, the least recently drawn are evicted
+    /// beyond that too. 0 for no limit but the capacity.
+    pub chunk_memory_budget_mb: usize,
+    /// Milliseconds each frame may spend uploading the meshes of the chunks
+    /// which arrived, the others wait for the next frames. 0 for no limit.
+    pub upload_budget_ms: u32,
+    /// Number of field samples memoized across chunks, 0 disables the cache.
+    pub sample_cache_size: usize,
+    /// The chunks of this many levels from the root, the largest and so the
+    /// furthest away, are meshed with surface nets on a grid half as fine
+    /// rather than with the field's own mesher. 0 for none.
+    pub surface_nets_levels: u8,
+    /// Length of the skirts hung from the borders of the chunks, which hide
+    /// the cracks left between levels, in steps of the chunk's grid. 0 for
+    /// none.
+    pub skirt_length: f32,
+    /// The chunks coarser than the finest level are decimated as long as
+    /// their surface moves by less than this, in steps of their grid. 0
+    /// leaves them as meshed.
+    pub decimation_error: f32,
+    /// The same for the meshes the collisions are tested against, which can
+    /// be much coarser than those drawn.
+    pub collision_decimation_error: f32,
+    /// Rays cast from each vertex for its ambient occlusion, 0 for none, and
+    /// how far they go, in steps of the chunk's grid.
+    pub occlusion_rays: usize,
+    pub occlusion_distance: f32,
+    /// Chunks are split into finer ones while a step of their grid covers
+    /// more than this many pixels on screen, and merged back once it covers
+    /// less than the second. The gap keeps them from flickering between the
+    /// two at the threshold.
+    pub max_screen_error: f32,
+    pub merge_screen_error: f32,
+    /// A chunk stays split, or merged, for at least this long, in
+    /// milliseconds.
+    pub min_dwell_ms: u32,
+    /// While frames take longer than this many milliseconds to render, the
+    /// screen errors are raised so fewer, coarser chunks are drawn, and
+    /// lowered back once there's headroom. At most they're multiplied by
+    /// `max_detail_reduction`. 0 keeps them as they are.
+    pub target_frame_ms: f32,
+    pub max_detail_reduction: f32,
+    /// Megabytes of chunk meshes each body keeps in the mesh cache, if any,
+    /// the least recently used are deleted beyond that.
+    pub mesh_cache_size_mb: usize,
+}
+
+impl Default for LodSpec {
+    fn default() -> Self {
+        LodSpec {
+            max_level: 12,
+            step: 16.0,
+            size: 32768.0,
+            chunk_num_steps: 32.0,
+            voxel_density: 8.0,
+            loaded_chunks_capacity: 2048,
+            empty_chunks_capacity: 65536,
+            chunk_memory_budget_mb: 1024,
+            upload_budget_ms: 4,
+            sample_cache_size: 1 << 20,
+            surface_nets_levels: 0,
+            skirt_length: 0.0,
+            decimation_error: 0.0,
+            collision_decimation_error: 0.0,
+            occlusion_rays: 0,
+            occlusion_distance: 4.0,
+            max_screen_error: 12.0,
+            merge_screen_error: 8.0,
+            min_dwell_ms: 500,
+            target_frame_ms: 0.0,
+            max_detail_reduction: 4.0,
+            mesh_cache_size_mb: 1024,
+        }
+    }
+}
+
+impl LodSpec {
+    /// Whether the chunks can be meshed and told apart, their ids losing no
+    /// precision at any level.
+    pub fn validate(&self) -> Result<()> {
+        if self.chunk_num_steps < 1.0 || self.chunk_num_steps.fract() != 0.0 {
+            let key = "lod.chunk_num_steps".to_owned();
+            return Err(ErrorKind::InvalidConfigValue(key, "a positive whole number").into());
+        }
+        let finest_cells = self.size / 2.0f32.powi(self.max_level as i32) * self.voxel_density;
+        let root_cells = self.size * self.voxel_density;
+        if !(finest_cells >= 1.0) || !(root_cells <= MAX_VOXEL_CELLS) {
+            let key = "lod.voxel_density".to_owned();
+            let expected = "a cell in the finest chunks and at most 2^24 across the root";
+            return Err(ErrorKind::InvalidConfigValue(key, expected).into());
+        }
+        Ok(())
+    }
+
+    /// The distance between the samples of the chunks at `max_level`.
+    pub fn finest_step(&self) -> f32 {
+        self.size / (1u32 << self.max_level) as f32 / self.chunk_num_steps
+    }
+
+    /// The level of the octree the chunk with id `chunk_id` is on.
+    pub fn chunk_level(&self, chunk_id: &ChunkId) -> u8 {
+        (self.size / chunk_id.size(self.voxel_density)).log2().round() as u8
+    }
+
+    /// The step of the grid the chunk with id `chunk_id` is meshed on.
+    pub fn chunk_step(&self, chunk_id: &ChunkId, mesher: Mesher) -> f32 {
+        match mesher {
+            Mesher::SurfaceNets => {
+                chunk_id.size(self.voxel_density) / self.chunk_num_steps * SURFACE_NETS_COARSENING
+            }
+            _ => chunk_id.size(self.voxel_density) / self.chunk_num_steps,
+        }
+    }
+
+    /// The bytes of `chunk_memory_budget_mb`, if there's a limit.
+    pub fn memory_budget(&self) -> Option<usize> {
+        if self.chunk_memory_budget_mb > 0 {
+            Some(self.chunk_memory_budget_mb * 1024 * 1024)
+        } else {
+            None
+        }
+    }
+
+    /// The mesher of the chunk with `chunk_id` of `field`.
+    pub fn mesher<Field: ScalarField3>(&self, field: &Field, chunk_id: &ChunkId) -> Mesher {
+        // Between the sizes of the chunks on the last level meshed with
+        // surface nets and on the next one, so rounding doesn't matter.
+        let threshold = self.size / 2.0f32.powi(self.surface_nets_levels as i32) * 1.5;
+        if chunk_id.size(self.voxel_density) > threshold {
+            Mesher::SurfaceNets
+        } else {
+            field.mesher()
+        }
+    }
+}
+
+/// Chunk cache occupancy and the counters of the chunk pipeline since it
+/// started, for the HUD and the benchmark to poll.
+#[derive(Clone, Debug, Default)]
+pub struct LodStats {
+    pub loaded_chunks: usize,
+    /// Of the loaded chunks, those pinned around the player.
+    pub pinned_chunks: usize,
+    /// The estimated GPU memory of the loaded chunks, in bytes.
+    pub loaded_bytes: usize,
+    /// Chunks being meshed by the workers.
+    pub pending_chunks: usize,
+    pub empty_chunks: usize,
+    /// Chunks whose meshing failed, waiting to be retried.
+    pub failed_chunks: usize,
+    /// How much the screen errors are raised by to keep to the target frame
+    /// time, 1 at full detail.
+    pub screen_error_scale: f32,
+    /// Mean time from submission to arrival of the chunks which arrived on
+    /// the last update, if any did.
+    pub generation_latency: Option<Duration>,
+    /// Chunks meshed, empty ones included, and those whose meshing failed.
+    pub chunks_generated: u64,
+    pub chunks_failed: u64,
+    pub mesh_cache_hits: u64,
+    pub mesh_cache_misses: u64,
+    /// Loaded chunks evicted, over the capacity or the memory budget.
+    pub evictions: u64,
+    /// Bytes of vertex and index buffers uploaded for the chunks.
+    pub bytes_uploaded: u64,
+    // How many chunks of each level were meshed, and in how long overall.
+    meshing_times: Vec<(u32, Duration)>,
+}
+
+impl LodStats {
+    /// Counts a chunk at `level` which took `time` to mesh.
+    pub fn record_meshing(&mut self, level: u8, time: Duration) {
+        let level = level as usize;
+        if self.meshing_times.len() <= level {
+            self.meshing_times.resize(level + 1, (0, Duration::new(0, 0)));
+        }
+        let (ref mut count, ref mut total) = self.meshing_times[level];
+        *count += 1;
+        *total += time;
+    }
+
+    /// The mean time the chunks at `level` took to mesh, if any were.
+    pub fn mean_meshing_time(&self, level: u8) -> Option<Duration> {
+        match self.meshing_times.get(level as usize) {
+            Some(&(count, total)) if count > 0 => Some(total / count),
+            _ => None,
+        }
+    }
+
+    /// The same over the chunks of all levels.
+    pub fn overall_mean_meshing_time(&self) -> Option<Duration> {
+        let (count, total) = self.meshing_times.iter().fold(
+            (0, Duration::new(0, 0)),
+            |(count, total), &(level_count, level_total)| {
+                (count + level_count, total + level_total)
+            },
+        );
+        if count > 0 { Some(total / count) } else { None }
+    }
+
+    /// The share of the chunks looked up in the mesh cache found there.
+    pub fn mesh_cache_hit_rate(&self) -> Option<f32> {
+        let lookups = self.mesh_cache_hits + self.mesh_cache_misses;
+        if lookups > 0 {
+            Some(self.mesh_cache_hits as f32 / lookups as f32)
+        } else {
+            None
+        }
+    }
+
+    /// Adds up the chunks and counters of `other`, e.g. of another body, the
+    /// screen error scale and latency being the worst of the two.
+    pub fn merge(&mut self, other: &LodStats) {
+        self.loaded_chunks += other.loaded_chunks;
+        self.pinned_chunks += other.pinned_chunks;
+        self.loaded_bytes += other.loaded_bytes;
+        self.pending_chunks += other.pending_chunks;
+        self.empty_chunks += other.empty_chunks;
+        self.failed_chunks += other.failed_chunks;
+        self.screen_error_scale = self.screen_error_scale.max(other.screen_error_scale);
+        self.generation_latency = self.generation_latency.max(other.generation_latency);
+        self.chunks_generated += other.chunks_generated;
+        self.chunks_failed += other.chunks_failed;
+        self.mesh_cache_hits += other.mesh_cache_hits;
+        self.mesh_cache_misses += other.mesh_cache_misses;
+        self.evictions += other.evictions;
+        self.bytes_uploaded += other.bytes_uploaded;
+        for (level, &(count, total)) in other.meshing_times.iter().enumerate() {
+            if self.meshing_times.len() <= level {
+                self.meshing_times.resize(level + 1, (0, Duration::new(0, 0)));
+            }
+            self.meshing_times[level].0 += count;
+            self.meshing_times[level].1 += total;
+        }
+    }
+}
+
+/// A chunk a `LevelOfDetail` needs meshed, fetched anew or re-meshed while
+/// its old mesh is drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkRequest {
+    chunk_id: ChunkId,
+    mesher: Mesher,
+    seams: Seams,
+    remesh: bool,
+    /// Pinned chunks are granted past the limit on chunks in flight.
+    pinned: bool,
+    priority: f32,
+}
+
+/// Shares the workers of a thread pool between the `LevelOfDetail`s of
+/// several bodies, granting the chunks they request by their priority
+/// across all of them rather than body by body.
+pub struct ChunkScheduler {
+    max_in_flight: usize,
+}
+
+impl ChunkScheduler {
+    pub fn new(thread_pool: &ThreadPool) -> Self {
+        ChunkScheduler { max_in_flight: PENDING_CHUNKS_PER_WORKER * thread_pool.max_count() }
+    }
+
+    /// Grants the requests of each body, given with its chunks in flight,
+    /// returning those granted in the same order as the bodies. The chunks
+    /// in flight of each stay within what its channel has room for.
+    pub fn schedule(&self, bodies: Vec<(usize, Vec<ChunkRequest>)>) -> Vec<Vec<ChunkRequest>> {
+        let mut in_flight: Vec<usize> = bodies.iter().map(|&(in_flight, _)| in_flight).collect();
+        let mut total: usize = in_flight.iter().sum();
+        let mut requests: Vec<(usize, ChunkRequest)> = bodies
+            .into_iter()
+            .enumerate()
+            .flat_map(|(index, (_, requests))| {
+                requests.into_iter().map(move |request| (index, request))
+            })
+            .collect();
+        // Stable, so equal priorities keep the order they were requested in.
+        requests.sort_by(|&(_, ref a), &(_, ref b)| {
+            b.priority.partial_cmp(&a.priority).unwrap_or(cmp::Ordering::Equal)
+        });
+
+        let mut granted = vec![vec![]; in_flight.len()];
+        for (index, request) in requests.into_iter() {
+            let grant = if request.pinned {
+                in_flight[index] < self.max_in_flight + MAX_PINNED_CHUNKS
+            } else {
+                total < self.max_in_flight && in_flight[index] < self.max_in_flight
+            };
+            if grant {
+                in_flight[index] += 1;
+                total += 1;
+                granted[index].push(request);
+            }
+        }
+        granted
+    }
+}
 
 pub struct LevelOfDetail<'a, Field>
 where
@@ -23,55 +347,256 @@ where
     chunk_renderer: ChunkRenderer<'a, Field>,
     octree: Octree,
     max_level: u8,
+    max_screen_error: f32,
+    merge_screen_error: f32,
+    detail: DetailController,
+    chunk_num_steps: f32,
     step: f32,
+    /// The chunks to draw, from the last `prepare`.
+    draw_chunks: Vec<(ChunkId, Seams)>,
 }
 
 impl<'a, Field: 'static + ScalarField3 + Send + Sync> LevelOfDetail<'a, Field> {
+    /// The chunks are scattered with `flora`, if any, as they are meshed.
     pub fn new(
         scalar_field: Arc<Field>,
+        flora: Option<Flora>,
         thread_pool: &'a ThreadPool,
-        max_level: u8,
-        step: f32,
-        size: f32,
+        spec: &LodSpec,
         uid_start: usize,
     ) -> Self {
         LevelOfDetail {
-            chunk_renderer: ChunkRenderer::new(scalar_field.clone(), thread_pool, uid_start),
-            octree: Octree::new(Vec3f::zero() - size / 2.0, size),
-            max_level: max_level,
-            step: step,
+            chunk_renderer: ChunkRenderer::new(
+                scalar_field.clone(),
+                flora.map(Arc::new),
+                thread_pool,
+                uid_start,
+                spec,
+            ),
+            octree: Octree::new(
+                Vec3f::zero() - spec.size / 2.0,
+                spec.size,
+                Duration::from_millis(spec.min_dwell_ms as u64),
+                spec.voxel_density,
+            ),
+            max_level: spec.max_level,
+            max_screen_error: spec.max_screen_error,
+            merge_screen_error: spec.merge_screen_error,
+            detail: DetailController::new(spec.target_frame_ms, spec.max_detail_reduction),
+            chunk_num_steps: spec.chunk_num_steps,
+            step: spec.step,
+            draw_chunks: vec![],
         }
     }
 
-    pub fn update(&mut self, window: &Window, camera: &Camera) -> Result<Vec<&Chunk>> {
-        let (draw_chunk_ids, fetch_chunk_ids) =
+    /// Refines the octree around `eye`, the camera's position relative to the
+    /// field, takes in the chunks which arrived and returns those to mesh,
+    /// for a `ChunkScheduler` to grant. Those hidden behind the field's
+    /// occluder, if it has one, are neither drawn nor meshed. Over
+    /// `memory_budget` bytes, if any, the least recently used chunks are
+    /// evicted.
+    pub fn prepare(
+        &mut self,
+        window: &Window,
+        eye: &Vec3f,
+        memory_budget: Option<usize>,
+    ) -> Result<Vec<ChunkRequest>> {
+        let scale = self.detail.scale;
+        let screen = ScreenError::new(
+            window.size().height,
+            self.max_screen_error * scale,
+            self.merge_screen_error * scale,
+            self.chunk_num_steps,
+        );
+        let horizon = self.chunk_renderer
+            .scalar_field
+            .occluder_radius()
+            .and_then(|radius| Horizon::new(*eye, radius));
+        let (draw_chunks, fetch_chunk_ids) = {
+            let _span = trace::span("octree_rebuild");
             self.octree.rebuild(
                 self.max_level,
-                Vec3f::from(camera.position().translation()),
+                *eye,
+                screen,
+                horizon,
+                Instant::now(),
                 &mut self.chunk_renderer,
-            );
-        self.chunk_renderer.render(
+            )
+        };
+        let octree_chunk_ids = self.octree.chunk_ids();
+        self.chunk_renderer.pin(self.octree.pinned_chunk_ids(eye));
+        try!(self.chunk_renderer.receive(
             window,
-            &draw_chunk_ids,
-            fetch_chunk_ids,
-        )
+            &draw_chunks,
+            &fetch_chunk_ids,
+            &octree_chunk_ids,
+            memory_budget,
+        ));
+        let requests = self.chunk_renderer.requests(&draw_chunks, fetch_chunk_ids, eye);
+        self.draw_chunks = draw_chunks;
+        Ok(requests)
+    }
+
+    /// Submits the `granted` requests of the last `prepare` and returns the
+    /// chunks to draw.
+    pub fn finish(&mut self, granted: Vec<ChunkRequest>) -> Vec<&Chunk> {
+        self.chunk_renderer.submit(granted);
+        self.chunk_renderer.drawn_chunks(&self.draw_chunks)
+    }
+
+    /// The chunks submitted to the workers whose results are yet to arrive.
+    pub fn num_in_flight(&self) -> usize {
+        self.chunk_renderer.num_in_flight()
+    }
+
+    /// Re-meshes the chunks overlapping the box from `min` to `max`, after the
+    /// field was edited there. The old meshes are drawn until the new ones
+    /// arrive.
+    pub fn invalidate(&mut self, min: &Vec3f, max: &Vec3f) {
+        self.chunk_renderer.invalidate(min, max);
     }
+
+    /// Reads the meshes of the chunks from `mesh_cache` rather than meshing
+    /// them again, and writes those it's missing to it. Chunks the field was
+    /// edited over aren't cached, the edits aren't part of its key.
+    pub fn cache_meshes(&mut self, mesh_cache: MeshCache) {
+        self.chunk_renderer.mesh_cache = Some(Arc::new(mesh_cache));
+    }
+
+    /// Takes effect on the next `prepare`, chunks already meshed are kept.
+    pub fn set_max_level(&mut self, max_level: u8) {
+        self.max_level = max_level;
+        self.chunk_renderer.lod_spec.max_level = max_level;
+        let finest_step = self.chunk_renderer.lod_spec.finest_step();
+        self.chunk_renderer.scalar_field.set_finest_step(finest_step);
+    }
+
+    /// Feeds the controller of the detail with the time the last frame took
+    /// to render, in milliseconds, which takes effect on the next `prepare`.
+    pub fn report_frame_time(&mut self, frame_ms: f32) {
+        self.detail.update(frame_ms);
+    }
+
+    /// Changes the target of the controller, keeping the detail it reached.
+    pub fn set_target_frame_time(&mut self, target_frame_ms: f32, max_detail_reduction: f32) {
+        self.detail.target_ms = target_frame_ms;
+        self.detail.max_scale = max_detail_reduction.max(1.0);
+        self.detail.scale = self.detail.scale.min(self.detail.max_scale);
+    }
+
+    /// Takes effect on the next `prepare` too.
+    pub fn set_screen_errors(&mut self, max_screen_error: f32, merge_screen_error: f32) {
+        self.max_screen_error = max_screen_error;
+        self.merge_screen_error = merge_screen_error;
+    }
+
+    /// As does this, for the nodes split or merged before as well.
+    pub fn set_min_dwell(&mut self, min_dwell_ms: u32) {
+        self.octree.history.min_dwell = Duration::from_millis(min_dwell_ms as u64);
+    }
+
+    /// The nodes of the octree of the last `prepare` which aren't hidden, with
+    /// the states of their chunks.
+    pub fn octree_boxes(&self) -> Vec<OctreeBox> {
+        let LevelOfDetail {
+            ref chunk_renderer,
+            ref octree,
+            ..
+        } = *self;
+        octree
+            .nodes
+            .iter()
+            .filter(|node| !node.hidden)
+            .map(|node| {
+                OctreeBox {
+                    position: node.position,
+                    size: node.size,
+                    level: node.level,
+                    draw: node.draw,
+                    state: chunk_renderer.peek_chunk_state(&node.chunk_id),
+                }
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> LodStats {
+        let ChunkRenderer {
+            ref loaded_chunks,
+            ref pinned_chunks,
+            ref pending_chunks,
+            ref remeshing_chunks,
+            ref empty_chunks,
+            ref failed_chunks,
+            generation_latency,
+            ref counters,
+            ..
+        } = self.chunk_renderer;
+        let loaded_bytes: usize = loaded_chunks
+            .peek_iter()
+            .map(|(_, chunk)| chunk)
+            .chain(pinned_chunks.values())
+            .map(|chunk| chunk.gpu_bytes)
+            .sum();
+        LodStats {
+            loaded_chunks: loaded_chunks.len() + pinned_chunks.len(),
+            pinned_chunks: pinned_chunks.len(),
+            loaded_bytes: loaded_bytes,
+            pending_chunks: pending_chunks.len() + remeshing_chunks.len(),
+            empty_chunks: empty_chunks.len(),
+            failed_chunks: failed_chunks.len(),
+            screen_error_scale: self.detail.scale,
+            generation_latency: generation_latency,
+            ..counters.clone()
+        }
+    }
+}
+
+/// A node of the octree of the last `prepare`, to draw for debugging.
+#[derive(Clone, Copy, Debug)]
+pub struct OctreeBox {
+    pub position: Vec3f,
+    pub size: f32,
+    pub level: u8,
+    pub draw: bool,
+    pub state: ChunkState,
 }
 
 pub struct Chunk {
     pub uid: usize,
+    /// The level of the octree the chunk is on.
+    pub level: u8,
     pub tri_mesh: TriMeshHandle,
     pub index_buffer: IndexBuffer<u32>,
     pub vertex_buffer: VertexBuffer<BarycentricVertex>,
+    /// The plants growing on the chunk, if any.
+    pub flora: Option<PrefabInstances>,
+    /// Bound the geometry of the chunk, more tightly than its octree node.
+    pub aabb: AABB<Point3<GpuScalar>>,
+    pub bounding_sphere: BoundingSphere<Point3<GpuScalar>>,
+    /// An estimate of the GPU memory the buffers of the chunk take, in bytes.
+    pub gpu_bytes: usize,
+    /// The seams the chunk was meshed with, it's re-meshed when drawn next to
+    /// chunks of other levels.
+    seams: Seams,
+    /// The last frame of the `ChunkRenderer` the chunk was looked up on.
+    last_used: u64,
 }
 
 impl Chunk {
     fn new(
         uid: usize,
+        level: u8,
         window: &Window,
         mesh: Mesh<BarycentricVertex>,
         tri_mesh: TriMeshHandle,
+        plants: Vec<Plant>,
+        seams: Seams,
     ) -> Result<Self> {
+        let _span = trace::span("upload_chunk");
+        let (aabb, bounding_sphere) = match (mesh.aabb(), mesh.bounding_sphere()) {
+            (Some(aabb), Some(bounding_sphere)) => (aabb, bounding_sphere),
+            _ => return Err("Cannot upload a chunk without vertices.".into()),
+        };
         let vertex_buffer = try!(
             VertexBuffer::new(window.facade(), &mesh.vertices)
                 .chain_err(|| "Cannot create vertex buffer.")
@@ -81,35 +606,130 @@ impl Chunk {
                 IndexBuffer::new(window.facade(), PrimitiveType::TrianglesList, &mesh.indices)
                     .chain_err(|| "Cannot create index buffer.")
             );
+        let flora = if plants.is_empty() {
+            None
+        } else {
+            Some(try!(upload_plants(window, &plants)))
+        };
+        let gpu_bytes = vertex_buffer.get_size() + index_buffer.get_size() +
+            flora.as_ref().map_or(0, |flora| flora.gpu_bytes());
 
         Ok(Chunk {
             uid: uid,
+            level: level,
             tri_mesh: tri_mesh,
             vertex_buffer: vertex_buffer,
             index_buffer: index_buffer,
+            flora: flora,
+            aabb: aabb,
+            bounding_sphere: bounding_sphere,
+            gpu_bytes: gpu_bytes,
+            seams: seams,
+            last_used: 0,
         })
     }
 }
 
+/// Meshes the chunk with id `chunk_id` with `mesher`, on the grid of
+/// `lod_spec` or a coarser one for surface nets. Marching cubes meshes also
+/// meet the coarser chunks across the faces given by `seams`. With `skirts`,
+/// the mesh has the skirts of `lod_spec` hung from its borders. This is the
+/// unit of work executed by the `ChunkRenderer` workers and it doesn't
+/// require a window.
+pub fn chunk_to_mesh<Field>(
+    scalar_field: &Field,
+    lod_spec: &LodSpec,
+    chunk_id: &ChunkId,
+    mesher: Mesher,
+    seams: &Seams,
+    skirts: bool,
+) -> Result<Mesh<Vertex>>
+where
+    Field: ScalarField3 + Sync,
+{
+    let position = chunk_id.position(lod_spec.voxel_density);
+    let chunk_size = chunk_id.size(lod_spec.voxel_density);
+    let step_size = lod_spec.chunk_step(chunk_id, mesher);
+    let skirt_length = if skirts {
+        lod_spec.skirt_length * step_size
+    } else {
+        0.0
+    };
+    field_to_mesh(
+        scalar_field,
+        position,
+        chunk_size + step_size,
+        step_size,
+        0.0,
+        mesher,
+        seams,
+        skirt_length,
+    )
+}
+
+/// Returns the ids of all the octree nodes at `level` which may intersect the
+/// iso-surface of `scalar_field`. Nodes are pruned using the field value at
+/// their centre, which assumes the field behaves like a distance function.
+pub fn surface_chunk_ids<Field>(scalar_field: &Field, spec: &LodSpec, level: u8) -> Vec<ChunkId>
+where
+    Field: ScalarField3,
+{
+    let mut chunk_ids = vec![];
+    let mut node_stack = vec![(Vec3f::zero() - spec.size / 2.0, spec.size, 0)];
+    while let Some((position, size, node_level)) = node_stack.pop() {
+        let centre = position + size / 2.0;
+        let half_diagonal = size * 0.5 * (3.0 as f32).sqrt();
+        if scalar_field.value_at(&centre.to_point()).abs() > SURFACE_MARGIN * half_diagonal {
+            continue;
+        }
+
+        if node_level >= level {
+            chunk_ids.push(ChunkId::new(&position, size, spec.voxel_density));
+        } else {
+            let (children_positions, child_size) = Octree::children_positions(&position, size);
+            for child_position in children_positions.iter() {
+                node_stack.push((*child_position, child_size, node_level + 1));
+            }
+        }
+    }
+    chunk_ids
+}
+
+/// Meshes the cube at `position` `size` across, with skirts `skirt_length`
+/// long hung from its borders unless that's 0.
 fn field_to_mesh<Field>(
     scalar_field: &Field,
     position: Vec3f,
     size: f32,
     step: f32,
     iso_value: f32,
-) -> Result<Mesh<BarycentricVertex>>
+    mesher: Mesher,
+    seams: &Seams,
+    skirt_length: f32,
+) -> Result<Mesh<Vertex>>
 where
-    Field: ScalarField3,
+    Field: ScalarField3 + Sync,
 {
-    let time = Instant::now();
     let p = position + size;
-    let mesh = marching_cubes(scalar_field, &position, &p, step, iso_value)
-        .with_barycentric_coordinates();
-    let elapsed = time.elapsed();
-    let delta = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+    let mut mesh = match mesher {
+        Mesher::MarchingCubes => {
+            let _span = trace::span("marching_cubes");
+            marching_cubes_with_seams(scalar_field, &position, &p, step, iso_value, seams)
+        }
+        Mesher::DualContouring => {
+            let _span = trace::span("dual_contouring");
+            dual_contouring(scalar_field, &position, &p, step, iso_value)
+        }
+        Mesher::SurfaceNets => {
+            let _span = trace::span("surface_nets");
+            surface_nets(scalar_field, &position, &p, step, iso_value)
+        }
+    };
+    if skirt_length > 0.0 {
+        mesh.add_skirts(skirt_length);
+    }
     debug!(
-        "Took {:.2}s to create chunk at {:?} (size {:?}) from field ({:?} vertices)",
-        delta,
+        "Created chunk at {:?} (size {:?}) from field ({:?} vertices)",
         position,
         size,
         mesh.vertices.len()
@@ -121,14 +741,20 @@ struct Octree {
     nodes: Vec<OctreeNode>,
     node_stack: VecDeque<usize>,
     root: OctreeNode,
+    history: SplitHistory,
+    voxel_density: f32,
 }
 
 impl Octree {
-    pub fn new(position: Vec3f, size: f32) -> Self {
+    /// The nodes are held split, or merged, for at least `min_dwell`, and
+    /// their chunks have ids on a grid of `voxel_density`.
+    pub fn new(position: Vec3f, size: f32, min_dwell: Duration, voxel_density: f32) -> Self {
         let octree = Octree {
             nodes: vec![],
             node_stack: VecDeque::with_capacity(64),
-            root: OctreeNode::new(position, size, 0, true),
+            root: OctreeNode::new(position, size, 0, true, voxel_density),
+            history: SplitHistory::new(min_dwell),
+            voxel_density: voxel_density,
         };
         octree
     }
@@ -137,8 +763,11 @@ impl Octree {
         &mut self,
         max_level: u8,
         focus: Vec3f,
+        screen: ScreenError,
+        horizon: Option<Horizon>,
+        now: Instant,
         chunk_cache: &mut Cache,
-    ) -> (Vec<ChunkId>, Vec<ChunkId>)
+    ) -> (Vec<(ChunkId, Seams)>, Vec<ChunkId>)
     where
         Cache: ChunkCache,
     {
@@ -146,27 +775,106 @@ impl Octree {
             ref mut nodes,
             ref mut node_stack,
             ref root,
+            ref mut history,
+            voxel_density,
         } = *self;
 
         assert!(node_stack.is_empty());
         nodes.clear();
         nodes.push(root.clone());
         node_stack.push_back(0);
-        Octree::extend_node(node_stack, nodes, max_level, focus, chunk_cache);
+        Octree::extend_node(
+            node_stack,
+            nodes,
+            max_level,
+            focus,
+            screen,
+            horizon,
+            history,
+            now,
+            voxel_density,
+            chunk_cache,
+        );
+        history.record(nodes, now);
 
-        let mut draw_chunk_ids = vec![];
+        let mut draw_chunks = vec![];
         let mut fetch_chunk_ids = vec![];
 
         for node in nodes.iter() {
             if node.draw {
-                draw_chunk_ids.push(node.chunk_id);
+                draw_chunks.push((node.chunk_id, Octree::seams(nodes, node)));
             }
 
-            if chunk_cache.is_unknown(&node.chunk_id) {
+            if !node.hidden && chunk_cache.is_unknown(&node.chunk_id) {
                 fetch_chunk_ids.push(node.chunk_id);
             }
         }
-        (draw_chunk_ids, fetch_chunk_ids)
+        (draw_chunks, fetch_chunk_ids)
+    }
+
+    /// The chunks of the nodes of the last `rebuild` which aren't hidden.
+    fn chunk_ids(&self) -> HashSet<ChunkId> {
+        self.nodes
+            .iter()
+            .filter(|node| !node.hidden)
+            .map(|node| node.chunk_id)
+            .collect()
+    }
+
+    /// The chunks the player collides with, of the nodes of the last
+    /// `rebuild` which are drawn or leaves and within a chunk of `eye`, at
+    /// most `MAX_PINNED_CHUNKS` of the nearest.
+    fn pinned_chunk_ids(&self, eye: &Vec3f) -> HashSet<ChunkId> {
+        let mut nearby: Vec<(f32, ChunkId)> = self.nodes
+            .iter()
+            .filter(|node| !node.hidden && (node.draw || node.children.is_none()))
+            .map(|node| (distance_to_cube(&node.position, node.size, eye), node))
+            .filter(|&(distance, node)| distance <= node.size)
+            .map(|(distance, node)| (distance, node.chunk_id))
+            .collect();
+        nearby.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        nearby
+            .into_iter()
+            .take(MAX_PINNED_CHUNKS)
+            .map(|(_, chunk_id)| chunk_id)
+            .collect()
+    }
+
+    /// How many levels coarser the drawn chunks across each face of `node`
+    /// are, for its mesh to meet theirs.
+    fn seams(nodes: &[OctreeNode], node: &OctreeNode) -> Seams {
+        let mut seams = [0; 6];
+        let centre = node.position + node.size / 2.0;
+        for face in 0..6 {
+            // A chunk across the face as large or larger covers the whole of
+            // it, so looking across its middle is enough.
+            let mut across = centre;
+            across[face / 2] += if face % 2 == 0 { -0.75 } else { 0.75 } * node.size;
+            if let Some(level) = Octree::coarser_drawn_level(nodes, &across, node.level) {
+                seams[face] = node.level - level;
+            }
+        }
+        seams
+    }
+
+    /// The level of the drawn node containing `point`, if it's above `level`.
+    fn coarser_drawn_level(nodes: &[OctreeNode], point: &Vec3f, level: u8) -> Option<u8> {
+        let mut index = 0;
+        loop {
+            let node = &nodes[index];
+            if node.level >= level || !node.contains(point) {
+                return None;
+            } else if node.draw {
+                return Some(node.level);
+            }
+            let child = node.children.and_then(|children| {
+                children.iter().cloned().find(|&child| nodes[child].contains(point))
+            });
+            match child {
+                Some(child) => index = child,
+                None => return None,
+            }
+        }
     }
 
     fn extend_node<Cache>(
@@ -174,6 +882,11 @@ impl Octree {
         nodes: &mut Vec<OctreeNode>,
         max_level: u8,
         focus: Vec3f,
+        screen: ScreenError,
+        horizon: Option<Horizon>,
+        history: &SplitHistory,
+        now: Instant,
+        voxel_density: f32,
         chunk_cache: &mut Cache,
     ) where
         Cache: ChunkCache,
@@ -185,14 +898,16 @@ impl Octree {
                 position,
                 chunk_id,
                 level,
+                hidden,
                 ..
             } = nodes[current_index];
 
             let is_available = chunk_cache.is_available(&chunk_id);
-            if !is_available || level >= max_level ||
-                distance_to_cube(&position, size, &focus) > 2.5 * size
+            let distance = distance_to_cube(&position, size, &focus);
+            if hidden || !is_available || level >= max_level ||
+                !history.should_split(&chunk_id, screen, size, distance, now)
             {
-                if !is_available {
+                if hidden || !is_available {
                     nodes[current_index].draw = false;
                 }
             } else {
@@ -201,19 +916,27 @@ impl Octree {
                     Some(Octree::new_children_indices(first_child_index));
                 let (children_positions, child_size) = Octree::children_positions(&position, size);
                 for (num_child, &child_position) in children_positions.iter().enumerate() {
-                    nodes.push(OctreeNode::new(
+                    let mut child = OctreeNode::new(
                         child_position,
                         child_size,
                         level + 1,
                         false,
-                    ));
+                        voxel_density,
+                    );
+                    child.hidden = horizon.map_or(false, |horizon| {
+                        horizon.hides(&child_position, child_size)
+                    });
+                    nodes.push(child);
                     node_stack.push_back(nodes[current_index].children.unwrap()[num_child]);
                 }
+                // Hidden children are never meshed, they don't hold up their
+                // siblings.
                 let draw_children = if nodes[current_index].draw {
                     let missing_child = nodes[current_index].children.unwrap().iter().any(
                         |child_index| {
-                            !(chunk_cache.is_available(&nodes[*child_index].chunk_id) ||
-                                  chunk_cache.is_empty(&nodes[*child_index].chunk_id))
+                            let child = &nodes[*child_index];
+                            !(child.hidden || chunk_cache.is_available(&child.chunk_id) ||
+                                  chunk_cache.is_empty(&child.chunk_id))
                         },
                     );
                     !missing_child
@@ -278,51 +1001,277 @@ struct OctreeNode {
     chunk_id: ChunkId,
     children: Option<[usize; 8]>,
     draw: bool,
+    // Behind the horizon, so neither drawn nor meshed.
+    hidden: bool,
 }
 
 impl OctreeNode {
-    fn new(position: Vec3f, size: f32, level: u8, draw: bool) -> Self {
+    fn new(position: Vec3f, size: f32, level: u8, draw: bool, voxel_density: f32) -> Self {
         OctreeNode {
             position: position,
             size: size,
             level: level,
-            chunk_id: ChunkId::new(&position, size),
+            chunk_id: ChunkId::new(&position, size, voxel_density),
             children: None,
             draw: draw,
+            hidden: false,
+        }
+    }
+
+    fn contains(&self, point: &Vec3f) -> bool {
+        (0..3).all(|axis| {
+            point[axis] >= self.position[axis] && point[axis] < self.position[axis] + self.size
+        })
+    }
+}
+
+/// Decides which chunks are coarse enough by how large the steps of their
+/// grids, the most their meshes are off the surface by, look on screen.
+#[derive(Clone, Copy, Debug)]
+struct ScreenError {
+    // The pixels a unit length, facing the eye a unit away from it, covers.
+    pixels_per_unit: f32,
+    max_error: f32,
+    merge_error: f32,
+    num_steps: f32,
+}
+
+impl ScreenError {
+    /// For a viewport `height` pixels tall, with the camera's field of view,
+    /// and chunks meshed on grids of `num_steps` steps across.
+    fn new(height: u32, max_error: f32, merge_error: f32, num_steps: f32) -> Self {
+        ScreenError {
+            pixels_per_unit: height as f32 / (2.0 * (FIELD_OF_VIEW / 2.0).tan()),
+            max_error: max_error,
+            merge_error: merge_error.min(max_error),
+            num_steps: num_steps,
+        }
+    }
+
+    /// Whether a chunk of `size`, `distance` away from the eye, would look
+    /// coarser than the maximum error.
+    fn too_coarse(&self, size: f32, distance: f32) -> bool {
+        self.pixels(size) > self.max_error * distance
+    }
+
+    /// Whether its children would look finer than needed to merge them.
+    fn too_fine(&self, size: f32, distance: f32) -> bool {
+        self.pixels(size) < self.merge_error * distance
+    }
+
+    // A step of the chunk's grid a unit away, in pixels.
+    #[inline]
+    fn pixels(&self, size: f32) -> f32 {
+        size / self.num_steps * self.pixels_per_unit
+    }
+}
+
+/// Scales the screen errors by how long the frames take to render, up while
+/// they're slower than the target and back down once they're well within
+/// it. The frame times are smoothed so a single slow frame, e.g. uploading
+/// many chunks, doesn't drop the detail.
+#[derive(Clone, Copy, Debug)]
+struct DetailController {
+    target_ms: f32,
+    max_scale: f32,
+    scale: f32,
+    smoothed_ms: Option<f32>,
+}
+
+impl DetailController {
+    fn new(target_ms: f32, max_scale: f32) -> Self {
+        DetailController {
+            target_ms: target_ms,
+            max_scale: max_scale.max(1.0),
+            scale: 1.0,
+            smoothed_ms: None,
+        }
+    }
+
+    fn update(&mut self, frame_ms: f32) {
+        if self.target_ms <= 0.0 {
+            self.scale = 1.0;
+            return;
+        }
+        let smoothed = match self.smoothed_ms {
+            Some(smoothed) => smoothed + (frame_ms - smoothed) * FRAME_TIME_SMOOTHING,
+            None => frame_ms,
+        };
+        self.smoothed_ms = Some(smoothed);
+        if smoothed > self.target_ms {
+            self.scale = (self.scale * (1.0 + DETAIL_STEP)).min(self.max_scale);
+        } else if smoothed < self.target_ms * DETAIL_HEADROOM {
+            self.scale = (self.scale / (1.0 + DETAIL_STEP)).max(1.0);
+        }
+    }
+}
+
+/// Since when the nodes of the octree were split, and since when those
+/// merged less than `min_dwell` ago have been merged.
+struct SplitHistory {
+    split: HashMap<ChunkId, Instant>,
+    merged: HashMap<ChunkId, Instant>,
+    min_dwell: Duration,
+}
+
+impl SplitHistory {
+    fn new(min_dwell: Duration) -> Self {
+        SplitHistory {
+            split: HashMap::new(),
+            merged: HashMap::new(),
+            min_dwell: min_dwell,
         }
     }
+
+    /// Whether to split the node with `chunk_id` of `size`, `distance` away
+    /// from the eye. A split node is only merged once it's well finer than
+    /// needed, and neither changes before it's had `min_dwell` to settle.
+    fn should_split(
+        &self,
+        chunk_id: &ChunkId,
+        screen: ScreenError,
+        size: f32,
+        distance: f32,
+        now: Instant,
+    ) -> bool {
+        match self.split.get(chunk_id) {
+            Some(&since) => {
+                now.duration_since(since) < self.min_dwell || !screen.too_fine(size, distance)
+            }
+            None => {
+                let settled = self.merged.get(chunk_id).map_or(true, |&since| {
+                    now.duration_since(since) >= self.min_dwell
+                });
+                settled && screen.too_coarse(size, distance)
+            }
+        }
+    }
+
+    /// Takes note of which `nodes` are split `now`.
+    fn record(&mut self, nodes: &[OctreeNode], now: Instant) {
+        let previous = mem::replace(&mut self.split, HashMap::new());
+        for node in nodes.iter().filter(|node| node.children.is_some()) {
+            let since = previous.get(&node.chunk_id).cloned().unwrap_or(now);
+            self.split.insert(node.chunk_id, since);
+            self.merged.remove(&node.chunk_id);
+        }
+        for chunk_id in previous.keys() {
+            if !self.split.contains_key(chunk_id) {
+                self.merged.insert(*chunk_id, now);
+            }
+        }
+        let min_dwell = self.min_dwell;
+        self.merged.retain(|_, since| now.duration_since(*since) < min_dwell);
+    }
+}
+
+/// The sphere of the field's occluder hides whatever is below the horizon
+/// of the eye.
+#[derive(Clone, Copy, Debug)]
+struct Horizon {
+    eye: Vec3f,
+    radius: f32,
+    // From the eye to the horizon, along the tangents to the occluder.
+    eye_to_horizon: f32,
+}
+
+impl Horizon {
+    /// None if the eye is within the occluder, it hides nothing then.
+    fn new(eye: Vec3f, radius: f32) -> Option<Self> {
+        let eye_distance = eye.norm();
+        if eye_distance <= radius {
+            return None;
+        }
+        Some(Horizon {
+            eye: eye,
+            radius: radius,
+            eye_to_horizon: (eye_distance * eye_distance - radius * radius).sqrt(),
+        })
+    }
+
+    /// Whether all of the cube at `position` of `size` is hidden. A point as
+    /// far from the centre as the furthest corner of the cube can only be
+    /// seen from within its own distance to the horizon plus the eye's.
+    fn hides(&self, position: &Vec3f, size: f32) -> bool {
+        let centre = *position + size / 2.0;
+        let furthest = centre.norm() + size * HALF_CUBE_DIAGONAL;
+        if furthest <= self.radius {
+            // Inside the occluder, which is solid.
+            return true;
+        }
+        let corner_to_horizon = (furthest * furthest - self.radius * self.radius).sqrt();
+        distance_to_cube(position, size, &self.eye) > self.eye_to_horizon + corner_to_horizon
+    }
 }
 
+/// A chunk's position and size, in cells of a grid `voxel_density` a unit,
+/// the density being the `LodSpec`'s the chunk was split with.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
 pub struct ChunkId(i32, i32, i32, u32);
 
 impl ChunkId {
     #[inline]
-    fn new(position: &Vec3f, size: f32) -> Self {
+    pub fn new(position: &Vec3f, size: f32, voxel_density: f32) -> Self {
         ChunkId(
-            (position[0] * OCTREE_VOXEL_DENSITY).floor() as i32,
-            (position[1] * OCTREE_VOXEL_DENSITY).floor() as i32,
-            (position[2] * OCTREE_VOXEL_DENSITY).floor() as i32,
-            (size * OCTREE_VOXEL_DENSITY) as u32,
+            (position[0] * voxel_density).floor() as i32,
+            (position[1] * voxel_density).floor() as i32,
+            (position[2] * voxel_density).floor() as i32,
+            (size * voxel_density) as u32,
         )
     }
 
     #[inline]
-    pub fn position(&self) -> Vec3f {
+    pub fn position(&self, voxel_density: f32) -> Vec3f {
         Vec3f::new(
-            self.0 as f32 / OCTREE_VOXEL_DENSITY,
-            self.1 as f32 / OCTREE_VOXEL_DENSITY,
-            self.2 as f32 / OCTREE_VOXEL_DENSITY,
+            self.0 as f32 / voxel_density,
+            self.1 as f32 / voxel_density,
+            self.2 as f32 / voxel_density,
         )
     }
 
     #[inline]
-    pub fn size(&self) -> f32 {
-        self.3 as f32 / OCTREE_VOXEL_DENSITY
+    pub fn size(&self, voxel_density: f32) -> f32 {
+        self.3 as f32 / voxel_density
+    }
+
+    /// Whether the region sampled for this chunk, meshed on a grid of
+    /// `num_steps`, overlaps the box from `min` to `max`. It's one step
+    /// larger than the chunk itself, and another for the dual meshers, whose
+    /// steps may be coarser.
+    fn overlaps(&self, min: &Vec3f, max: &Vec3f, lod_spec: &LodSpec) -> bool {
+        let position = self.position(lod_spec.voxel_density);
+        let size = self.size(lod_spec.voxel_density) *
+            (1.0 + 2.0 * SURFACE_NETS_COARSENING / lod_spec.chunk_num_steps);
+        (0..3).all(|axis| {
+            position[axis] <= max[axis] && position[axis] + size >= min[axis]
+        })
     }
 }
 
-const OCTREE_VOXEL_DENSITY: f32 = 8.0;
+// The most cells of the grid of the chunk ids across the root, for all of
+// them to be whole numbers an `f32` holds exactly.
+const MAX_VOXEL_CELLS: f32 = 16777216.0;
+// How many times coarser the grid of the chunks meshed with surface nets is.
+const SURFACE_NETS_COARSENING: f32 = 2.0;
+const PENDING_CHUNKS_PER_WORKER: usize = 3;
+// Chunks pinned around the player, which are submitted past the pending
+// chunks and so also have room in the channel.
+const MAX_PINNED_CHUNKS: usize = 27;
+// The weight of each frame in the smoothed frame time, by how much the screen
+// errors change a frame while it's off the target, and the fraction of the
+// target under which the detail is raised back.
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+const DETAIL_STEP: f32 = 0.02;
+const DETAIL_HEADROOM: f32 = 0.8;
+// A chunk whose meshing failed is retried after this many milliseconds, and
+// at most this many after failing repeatedly.
+const RETRY_BACKOFF_MS: u64 = 500;
+const MAX_RETRY_BACKOFF_MS: u64 = 60000;
+// The most boxes of edits kept apart, checked for every chunk submitted.
+const MAX_EDITED_BOXES: usize = 64;
+const SURFACE_MARGIN: f32 = 1.5;
+// From the centre of a cube to its corners, in sides.
+const HALF_CUBE_DIAGONAL: f32 = 0.8660254;
 const OCTREE_OFFSETS: [(f32, f32, f32); 8] = [
     (0.0, 0.0, 0.0),
     (0.0, 0.0, 1.0),
@@ -351,160 +1300,661 @@ fn distance_to_cube(cube_position: &Vec3f, size: f32, query: &Vec3f) -> f32 {
     (dx * dx + dy * dy + dz * dz).sqrt()
 }
 
-type TriMeshHandle = ShapeHandle<Point3<GpuScalar>, Isometry3<GpuScalar>>;
+pub type TriMeshHandle = ShapeHandle<Point3<GpuScalar>, Isometry3<GpuScalar>>;
 
 struct ChunkRendererWork {
     chunk_id: ChunkId,
+    seams: Seams,
     meshes: ChunkMeshes,
+    submitted: Instant,
+    /// Set once the chunk is no longer needed, the work is then dropped.
+    cancelled: Arc<AtomicBool>,
+    /// How long the worker took, and whether the mesh was found in the mesh
+    /// cache, if it was looked up.
+    meshing_time: Duration,
+    mesh_cache_hit: Option<bool>,
 }
 
 enum ChunkMeshes {
     Empty,
-    Present(Mesh<BarycentricVertex>, TriMeshHandle),
+    Present(Mesh<BarycentricVertex>, TriMeshHandle, Vec<Plant>),
+    Failed(String),
+    /// The worker gave up on the chunk after it was cancelled.
+    Cancelled,
+}
+
+/// The meshes of the chunk with `chunk_id`, unless it's `cancelled` before
+/// they're done, and whether they were found in the mesh cache, if any.
+fn chunk_meshes<Field>(
+    scalar_field: &Field,
+    flora: Option<&Flora>,
+    mesh_cache: Option<&MeshCache>,
+    chunk_id: &ChunkId,
+    mesher: Mesher,
+    seams: &Seams,
+    lod_spec: &LodSpec,
+    cancelled: &AtomicBool,
+) -> Result<(ChunkMeshes, Option<bool>)>
+where
+    Field: ScalarField3 + Sync,
+{
+    let cached = mesh_cache.and_then(|mesh_cache| {
+        let _span = trace::span("mesh_cache_read");
+        mesh_cache.get(chunk_id, mesher, seams)
+    });
+    let cache_hit = mesh_cache.map(|_| cached.is_some());
+    let mut mesh = match cached {
+        Some(mesh) => mesh,
+        None => {
+            // Without the skirts, which would keep the borders from being
+            // held in place as the mesh is decimated.
+            let mesh = try!(chunk_to_mesh(
+                scalar_field,
+                lod_spec,
+                chunk_id,
+                mesher,
+                seams,
+                false,
+            ));
+            if let Some(mesh_cache) = mesh_cache {
+                let _span = trace::span("mesh_cache_write");
+                mesh_cache.insert(chunk_id, mesher, seams, &mesh);
+            }
+            mesh
+        }
+    };
+    if mesh.vertices.len() == 0 {
+        return Ok((ChunkMeshes::Empty, cache_hit));
+    }
+    // The mesh is cached either way, but there's no use finishing it.
+    if cancelled.load(Ordering::Relaxed) {
+        return Ok((ChunkMeshes::Cancelled, cache_hit));
+    }
+    let step = lod_spec.chunk_step(chunk_id, mesher);
+    let grid_step = chunk_id.size(lod_spec.voxel_density) / lod_spec.chunk_num_steps;
+    // The finest chunks, around the camera, keep all their detail.
+    let is_finest = grid_step < lod_spec.finest_step() * 1.5;
+    if lod_spec.decimation_error > 0.0 && !is_finest {
+        let _span = trace::span("decimate");
+        mesh = decimate(&mesh, 0, lod_spec.decimation_error * step);
+    }
+    let plants = match flora {
+        Some(flora) if flora.grows_at_step(grid_step) => {
+            let voxel_density = lod_spec.voxel_density;
+            flora.scatter(
+                scalar_field,
+                &mesh,
+                &chunk_id.position(voxel_density),
+                chunk_id.size(voxel_density),
+            )
+        }
+        _ => vec![],
+    };
+    let tri_mesh = if lod_spec.collision_decimation_error > 0.0 {
+        let _span = trace::span("decimate");
+        collision_shape(&decimate(&mesh, 0, lod_spec.collision_decimation_error * step))
+    } else {
+        collision_shape(&mesh)
+    };
+    // After the plants are scattered, so none grow on the skirts.
+    if lod_spec.skirt_length > 0.0 {
+        mesh.add_skirts(lod_spec.skirt_length * step);
+    }
+    if cancelled.load(Ordering::Relaxed) {
+        return Ok((ChunkMeshes::Cancelled, cache_hit));
+    }
+    let occlusion = if lod_spec.occlusion_rays > 0 {
+        let _span = trace::span("ambient_occlusion");
+        let distance = lod_spec.occlusion_distance * step;
+        Some((
+            mesh.indices.clone(),
+            ambient_occlusion(scalar_field, &mesh, lod_spec.occlusion_rays, distance, step),
+        ))
+    } else {
+        None
+    };
+    let mut mesh = mesh.with_barycentric_coordinates();
+    mesh.paint_triangles(|centroid| scalar_field.material_at(&centroid.to_point()));
+    mesh.paint_strata(|position| scalar_field.stratum_at(&position.to_point()));
+    if let Some((indices, occlusion)) = occlusion {
+        mesh.paint_occlusion(&indices, &occlusion);
+    }
+    Ok((ChunkMeshes::Present(mesh, tri_mesh, plants), cache_hit))
+}
+
+/// The shape of `mesh` for the physics engine.
+pub fn collision_shape(mesh: &Mesh<Vertex>) -> TriMeshHandle {
+    let tri_mesh = TriMesh::new(
+        Arc::new(
+            mesh.vertices
+                .iter()
+                .map(|x| x.position.to_point())
+                .collect(),
+        ),
+        Arc::new(
+            mesh.indices
+                .chunks(3)
+                .map(|x| Point3::new(x[0] as usize, x[1] as usize, x[2] as usize))
+                .collect(),
+        ),
+        None,
+        None,
+    );
+    ShapeHandle::new(tri_mesh)
 }
 
 struct ChunkRenderer<'a, Field: ScalarField3> {
     scalar_field: Arc<Field>,
+    flora: Option<Arc<Flora>>,
+    mesh_cache: Option<Arc<MeshCache>>,
     thread_pool: &'a ThreadPool,
     chunk_send: Sender<ChunkRendererWork>,
     chunk_recv: Receiver<ChunkRendererWork>,
+    lod_spec: LodSpec,
     loaded_chunks: LruCache<ChunkId, Chunk>,
+    /// The chunks around the player, which are fetched before any other and
+    /// whose loaded chunks are kept in `pinned_chunks` rather than the LRU,
+    /// so they're never evicted.
+    pinned_chunk_ids: HashSet<ChunkId>,
+    pinned_chunks: HashMap<ChunkId, Chunk>,
     pending_chunks: HashSet<ChunkId>,
+    /// Set to cancel the work on each of the `pending_chunks`.
+    pending_cancellations: HashMap<ChunkId, Arc<AtomicBool>>,
+    /// Work cancelled whose results are yet to arrive, and dropped. It still
+    /// counts towards the chunks in flight, which fit in the channel.
+    num_cancelled: usize,
     empty_chunks: LruCache<ChunkId, ()>,
+    /// Chunks whose meshes are out of date with the field. They stay loaded
+    /// (or pending) and are re-meshed when next drawn.
+    stale_chunks: HashSet<ChunkId>,
+    /// Loaded chunks being re-meshed. Unlike `pending_chunks` their old mesh
+    /// is still available.
+    remeshing_chunks: HashSet<ChunkId>,
+    /// Chunks whose meshing failed, with how many times in a row and when
+    /// they're retried. Until then they're neither fetched nor re-meshed.
+    failed_chunks: HashMap<ChunkId, (u32, Instant)>,
+    /// The boxes the field was edited in, the chunks overlapping them bypass
+    /// the mesh cache. Overlapping edits share a box, see `add_edited_box`.
+    edited_boxes: Vec<(Vec3f, Vec3f)>,
     empty_uid: usize,
+    /// Counts the calls to `receive`, for when the loaded chunks were used.
+    frame: u64,
+    /// The chunks to fetch among the last requests, to tell when the
+    /// workers are starved.
+    num_fetch_requests: usize,
+    thread_starved: bool,
+    generation_latency: Option<Duration>,
+    // Only the counters of the pipeline, see `LevelOfDetail::stats`.
+    counters: LodStats,
 }
 
 impl<'a, Field> ChunkRenderer<'a, Field>
 where
     Field: 'static + ScalarField3 + Send + Sync,
 {
-    fn new(scalar_field: Arc<Field>, thread_pool: &'a ThreadPool, uid_start: usize) -> Self {
-        let (send, recv) = chan::sync(128);
+    fn new(
+        scalar_field: Arc<Field>,
+        flora: Option<Arc<Flora>>,
+        thread_pool: &'a ThreadPool,
+        uid_start: usize,
+        spec: &LodSpec,
+    ) -> Self {
+        // Room for every pending chunk, so a worker never blocks on sending
+        // even after the renderer (and with it the receiver) is dropped.
+        let (send, recv) = chan::sync(
+            PENDING_CHUNKS_PER_WORKER * thread_pool.max_count() + MAX_PINNED_CHUNKS,
+        );
         ChunkRenderer {
             scalar_field: scalar_field,
+            flora: flora,
+            mesh_cache: None,
             thread_pool: thread_pool,
             chunk_send: send,
             chunk_recv: recv,
-            loaded_chunks: LruCache::with_capacity(2048),
+            lod_spec: spec.clone(),
+            loaded_chunks: LruCache::with_capacity(spec.loaded_chunks_capacity),
+            pinned_chunk_ids: HashSet::new(),
+            pinned_chunks: HashMap::with_capacity(MAX_PINNED_CHUNKS),
             pending_chunks: HashSet::with_capacity(128),
-            empty_chunks: LruCache::with_capacity(65536),
+            pending_cancellations: HashMap::with_capacity(128),
+            num_cancelled: 0,
+            empty_chunks: LruCache::with_capacity(spec.empty_chunks_capacity),
+            stale_chunks: HashSet::new(),
+            remeshing_chunks: HashSet::new(),
+            failed_chunks: HashMap::new(),
+            edited_boxes: vec![],
             empty_uid: uid_start,
+            frame: 0,
+            num_fetch_requests: 0,
+            thread_starved: false,
+            generation_latency: None,
+            counters: LodStats::default(),
+        }
+    }
+
+    /// Pins the chunks with `chunk_ids`, moving those loaded out of the LRU,
+    /// and moves those no longer pinned back into it.
+    fn pin(&mut self, chunk_ids: HashSet<ChunkId>) {
+        let unpinned: Vec<ChunkId> = self.pinned_chunks
+            .keys()
+            .filter(|chunk_id| !chunk_ids.contains(chunk_id))
+            .cloned()
+            .collect();
+        for chunk_id in unpinned.into_iter() {
+            let chunk = self.pinned_chunks.remove(&chunk_id).unwrap();
+            self.loaded_chunks.insert(chunk_id, chunk);
+        }
+        for chunk_id in chunk_ids.iter() {
+            // Removing from the LRU is linear, even for chunks it hasn't got.
+            if self.loaded_chunks.contains_key(chunk_id) {
+                let chunk = self.loaded_chunks.remove(chunk_id).unwrap();
+                self.pinned_chunks.insert(*chunk_id, chunk);
+            }
         }
+        self.pinned_chunk_ids = chunk_ids;
     }
 
-    fn render(
+    fn invalidate(&mut self, min: &Vec3f, max: &Vec3f) {
+        add_edited_box(&mut self.edited_boxes, min, max);
+        let lod_spec = &self.lod_spec;
+        // Empty chunks are simply forgotten, they are fetched again if drawn.
+        let empty_chunk_ids: Vec<ChunkId> = self.empty_chunks
+            .peek_iter()
+            .map(|(chunk_id, _)| *chunk_id)
+            .filter(|chunk_id| chunk_id.overlaps(min, max, lod_spec))
+            .collect();
+        for chunk_id in empty_chunk_ids.iter() {
+            self.empty_chunks.remove(chunk_id);
+        }
+        // The edit may have fixed the failed chunks, they're retried now.
+        self.failed_chunks.retain(|chunk_id, _| !chunk_id.overlaps(min, max, lod_spec));
+
+        let ChunkRenderer {
+            ref loaded_chunks,
+            ref pinned_chunks,
+            ref pending_chunks,
+            ref remeshing_chunks,
+            ref mut stale_chunks,
+            ..
+        } = *self;
+        stale_chunks.extend(
+            loaded_chunks
+                .peek_iter()
+                .map(|(chunk_id, _)| chunk_id)
+                .chain(pinned_chunks.keys())
+                .chain(pending_chunks.iter())
+                .chain(remeshing_chunks.iter())
+                .filter(|chunk_id| chunk_id.overlaps(min, max, lod_spec)),
+        );
+        debug!(
+            "Invalidated {} empty and {} stale chunks.",
+            empty_chunk_ids.len(),
+            stale_chunks.len()
+        );
+    }
+
+    /// Takes in the chunks which arrived from the workers, evicts those over
+    /// `memory_budget` bytes, if any, and cancels those the octree no longer
+    /// needs.
+    fn receive(
         &mut self,
         window: &Window,
-        draw_chunk_ids: &Vec<ChunkId>,
-        fetch_chunk_ids: Vec<ChunkId>,
-    ) -> Result<Vec<&Chunk>> {
+        draw_chunks: &Vec<(ChunkId, Seams)>,
+        fetch_chunk_ids: &[ChunkId],
+        octree_chunk_ids: &HashSet<ChunkId>,
+        memory_budget: Option<usize>,
+    ) -> Result<()> {
 
         // The invariant required to hold when calling this function is:
-        //   - the meshes for all `draw_chunk_ids` are available
+        //   - the meshes for all `draw_chunks` are available
         //   - the meshes for all `fetch_chunk_ids` are unknown
         //
         // A mesh with `chunk_id` is defined to be available iff
         //     `get_chunk_state(&chunk_id) == ChunkState::Available`
-        // println!("draw: {:?}", draw_chunk_ids);
+        // println!("draw: {:?}", draw_chunks);
 
-        assert!(draw_chunk_ids.iter().all(|chunk_id| {
+        assert!(draw_chunks.iter().all(|&(ref chunk_id, _)| {
             self.get_chunk_state(chunk_id) == ChunkState::Available
         }));
         assert!(fetch_chunk_ids.iter().all(|chunk_id| {
             self.get_chunk_state(chunk_id) == ChunkState::Unknown
         }));
+        self.frame += 1;
+        let frame = self.frame;
 
         let ChunkRenderer {
-            ref scalar_field,
-            ref thread_pool,
-            ref chunk_send,
             ref chunk_recv,
+            ref lod_spec,
             ref mut loaded_chunks,
+            ref pinned_chunk_ids,
+            ref mut pinned_chunks,
             ref mut pending_chunks,
+            ref mut pending_cancellations,
+            ref mut num_cancelled,
             ref mut empty_chunks,
+            ref mut stale_chunks,
+            ref mut remeshing_chunks,
+            ref mut failed_chunks,
+            ref mut generation_latency,
+            ref mut counters,
             ..
         } = *self;
 
-        while let Some(message) = (|| {
-            chan_select! {
-                default => { return None; },
-                chunk_recv.recv() -> message => { return message; },
-            }
-        })()
+        let now = Instant::now();
+        let mut total_latency = Duration::new(0, 0);
+        let mut num_received = 0;
+
+        // Uploading the meshes of many chunks arriving together would hitch
+        // the frame, so past the budget the rest are left in the channel for
+        // the next frames. At least one arrives on each.
+        let upload_budget = Duration::from_millis(lod_spec.upload_budget_ms as u64);
+        while num_received == 0 || lod_spec.upload_budget_ms == 0 ||
+            now.elapsed() < upload_budget
         {
-            let ChunkRendererWork { chunk_id, meshes } = message;
+            let message = match (|| {
+                chan_select! {
+                    default => { return None; },
+                    chunk_recv.recv() -> message => { return message; },
+                }
+            })()
+            {
+                Some(message) => message,
+                None => break,
+            };
+            let ChunkRendererWork {
+                chunk_id,
+                seams,
+                meshes,
+                submitted,
+                cancelled,
+                meshing_time,
+                mesh_cache_hit,
+            } = message;
+            match mesh_cache_hit {
+                Some(true) => counters.mesh_cache_hits += 1,
+                Some(false) => counters.mesh_cache_misses += 1,
+                None => {}
+            }
+            if cancelled.load(Ordering::Relaxed) {
+                *num_cancelled -= 1;
+                continue;
+            }
+            total_latency += now - submitted;
+            num_received += 1;
 
             pending_chunks.remove(&chunk_id);
+            pending_cancellations.remove(&chunk_id);
+            let remeshed = remeshing_chunks.remove(&chunk_id);
+            if let ChunkMeshes::Failed(message) = meshes {
+                counters.chunks_failed += 1;
+                // A chunk being re-meshed keeps its old mesh, and stays stale
+                // to be re-meshed again once it's retried.
+                if remeshed {
+                    stale_chunks.insert(chunk_id);
+                }
+                let attempts = failed_chunks.get(&chunk_id).map_or(0, |&(attempts, _)| attempts);
+                let backoff_ms = retry_backoff_ms(attempts + 1);
+                let retry_at = now + Duration::from_millis(backoff_ms);
+                failed_chunks.insert(chunk_id, (attempts + 1, retry_at));
+                let chunk_name = format!("{:?}", chunk_id);
+                let err: Error = ErrorKind::ChunkGenerationFailed(chunk_name, message).into();
+                error!("{}, retrying in {} ms.", err, backoff_ms);
+                continue;
+            }
+            failed_chunks.remove(&chunk_id);
+            counters.chunks_generated += 1;
+            counters.record_meshing(lod_spec.chunk_level(&chunk_id), meshing_time);
+            if remeshed {
+                loaded_chunks.remove(&chunk_id);
+                pinned_chunks.remove(&chunk_id);
+            }
             match meshes {
                 ChunkMeshes::Empty => {
-                    empty_chunks.insert(chunk_id, ());
+                    // An empty chunk invalidated while being meshed is left
+                    // unknown, it would never be re-meshed otherwise.
+                    if !stale_chunks.remove(&chunk_id) {
+                        empty_chunks.insert(chunk_id, ());
+                    }
                 }
-                ChunkMeshes::Present(mesh, tri_mesh) => {
-                    loaded_chunks.insert(
-                        chunk_id,
-                        try!(Chunk::new(self.empty_uid, window, mesh, tri_mesh)),
-                    );
+                ChunkMeshes::Present(mesh, tri_mesh, plants) => {
+                    let level = lod_spec.chunk_level(&chunk_id);
+                    let mut chunk = try!(Chunk::new(
+                        self.empty_uid,
+                        level,
+                        window,
+                        mesh,
+                        tri_mesh,
+                        plants,
+                        seams,
+                    ));
+                    chunk.last_used = frame;
+                    counters.bytes_uploaded += chunk.gpu_bytes as u64;
+                    if pinned_chunk_ids.contains(&chunk_id) {
+                        pinned_chunks.insert(chunk_id, chunk);
+                    } else {
+                        // The LRU evicts one silently when full.
+                        if loaded_chunks.len() >= lod_spec.loaded_chunks_capacity &&
+                            !loaded_chunks.contains_key(&chunk_id)
+                        {
+                            counters.evictions += 1;
+                        }
+                        loaded_chunks.insert(chunk_id, chunk);
+                    }
                     self.empty_uid += 1;
                 }
+                ChunkMeshes::Failed(_) | ChunkMeshes::Cancelled => unreachable!(),
             }
         }
 
-        for chunk_id in fetch_chunk_ids.into_iter() {
-            if pending_chunks.len() > 8 {
-                break;
+        // Over the memory budget, the least recently used chunks go, but not
+        // those drawn now or whose old mesh is drawn while they're re-meshed.
+        // The pinned chunks take their share of it first.
+        let pinned_bytes: usize = pinned_chunks.values().map(|chunk| chunk.gpu_bytes).sum();
+        let budget = memory_budget.map_or(usize::MAX, |budget| budget.saturating_sub(pinned_bytes));
+        let loaded_bytes: usize = loaded_chunks.peek_iter().map(|(_, chunk)| chunk.gpu_bytes).sum();
+        if loaded_bytes > budget {
+            let keep: HashSet<ChunkId> = draw_chunks
+                .iter()
+                .map(|&(chunk_id, _)| chunk_id)
+                .chain(remeshing_chunks.iter().cloned())
+                .collect();
+            let chunks: Vec<_> = loaded_chunks
+                .peek_iter()
+                .map(|(chunk_id, chunk)| (*chunk_id, chunk.gpu_bytes, chunk.last_used))
+                .collect();
+            let evicted = chunks_over_budget(chunks, budget, &keep);
+            debug!("Evicted {} chunks over the memory budget.", evicted.len());
+            counters.evictions += evicted.len() as u64;
+            for chunk_id in evicted.iter() {
+                loaded_chunks.remove(chunk_id);
+                stale_chunks.remove(chunk_id);
             }
+        }
 
-            debug!("Submitted chunk {:?}.", chunk_id);
-            let position = chunk_id.position();
-            let chunk_size = chunk_id.size();
-
-            let num_steps = 32.0;
-            let step_size = chunk_size / num_steps;
-            let scalar_field = scalar_field.clone();
-            let sender = chunk_send.clone();
-            thread_pool.execute(move || {
-                let mesh = field_to_mesh(
-                    scalar_field.deref(),
-                    position,
-                    chunk_size + step_size,
-                    step_size,
-                    0.0,
-                ).unwrap();
-                if mesh.vertices.len() == 0 {
-                    sender.send(ChunkRendererWork {
-                        chunk_id: chunk_id,
-                        meshes: ChunkMeshes::Empty,
-                    });
+        *generation_latency = if num_received > 0 {
+            Some(total_latency / num_received)
+        } else {
+            None
+        };
+
+        // The chunks the octree moved away from before they were meshed are
+        // no longer worth meshing, those needed again are fetched anew.
+        let unneeded_chunk_ids: Vec<ChunkId> = pending_chunks
+            .iter()
+            .filter(|chunk_id| !octree_chunk_ids.contains(chunk_id))
+            .cloned()
+            .collect();
+        for chunk_id in unneeded_chunk_ids.iter() {
+            pending_chunks.remove(chunk_id);
+            stale_chunks.remove(chunk_id);
+            if let Some(cancelled) = pending_cancellations.remove(chunk_id) {
+                cancelled.store(true, Ordering::Relaxed);
+                *num_cancelled += 1;
+            }
+        }
+        if !unneeded_chunk_ids.is_empty() {
+            debug!("Cancelled {} chunks no longer in the octree.", unneeded_chunk_ids.len());
+        }
+
+        Ok(())
+    }
+
+    /// The chunks submitted to the workers whose results are yet to arrive,
+    /// cancelled or not. They all fit in the channel.
+    fn num_in_flight(&self) -> usize {
+        self.pending_chunks.len() + self.remeshing_chunks.len() + self.num_cancelled
+    }
+
+    /// The chunks to mesh: the stale chunks in view, the `fetch_chunk_ids`
+    /// and the chunks drawn with other seams than they were meshed with,
+    /// ranked by how close they are to `eye` for their size.
+    fn requests(
+        &mut self,
+        draw_chunks: &Vec<(ChunkId, Seams)>,
+        fetch_chunk_ids: Vec<ChunkId>,
+        eye: &Vec3f,
+    ) -> Vec<ChunkRequest> {
+        let now = Instant::now();
+        let ChunkRenderer {
+            ref scalar_field,
+            ref lod_spec,
+            ref loaded_chunks,
+            ref pinned_chunk_ids,
+            ref pinned_chunks,
+            ref stale_chunks,
+            ref remeshing_chunks,
+            ref failed_chunks,
+            ..
+        } = *self;
+        let priority = |chunk_id: &ChunkId| {
+            let size = chunk_id.size(lod_spec.voxel_density);
+            let position = chunk_id.position(lod_spec.voxel_density);
+            size / (distance_to_cube(&position, size, eye) + size)
+        };
+        let mut requests = Vec::with_capacity(fetch_chunk_ids.len());
+
+        // Fetched chunks are meshed with no seams, and the levels around any
+        // chunk change as the octree does, so those drawn with other seams
+        // than they were meshed with are re-meshed, their old mesh drawn
+        // until then. Stale chunks in view take priority, they are where the
+        // player is editing the terrain.
+        for &(chunk_id, seams) in draw_chunks.iter() {
+            let retrying = failed_chunks.get(&chunk_id).map_or(false, |&(_, retry_at)| {
+                retry_at > now
+            });
+            if remeshing_chunks.contains(&chunk_id) || retrying {
+                continue;
+            }
+            let mesher = lod_spec.mesher(scalar_field.deref(), &chunk_id);
+            let seams = mesher_seams(mesher, seams);
+            let meshed_seams = loaded_chunk(loaded_chunks, pinned_chunks, &chunk_id)
+                .map(|chunk| chunk.seams);
+            let stale = stale_chunks.contains(&chunk_id);
+            if stale || meshed_seams != Some(seams) {
+                requests.push(ChunkRequest {
+                    chunk_id: chunk_id,
+                    mesher: mesher,
+                    seams: seams,
+                    remesh: true,
+                    pinned: false,
+                    priority: if stale { f32::MAX } else { priority(&chunk_id) },
+                });
+            }
+        }
+
+        // The pinned chunks are granted first, and past the limit on chunks in
+        // flight.
+        for chunk_id in fetch_chunk_ids.into_iter() {
+            let pinned = pinned_chunk_ids.contains(&chunk_id);
+            requests.push(ChunkRequest {
+                chunk_id: chunk_id,
+                mesher: lod_spec.mesher(scalar_field.deref(), &chunk_id),
+                seams: NO_SEAMS,
+                remesh: false,
+                pinned: pinned,
+                priority: if pinned { f32::INFINITY } else { priority(&chunk_id) },
+            });
+        }
+        self.num_fetch_requests = requests.iter().filter(|request| !request.remesh).count();
+        requests
+    }
+
+    /// Submits the `granted` requests to the workers.
+    fn submit(&mut self, granted: Vec<ChunkRequest>) {
+        let ChunkRenderer {
+            ref scalar_field,
+            ref flora,
+            ref mesh_cache,
+            ref edited_boxes,
+            ref thread_pool,
+            ref chunk_send,
+            ref lod_spec,
+            ref mut pending_chunks,
+            ref mut pending_cancellations,
+            ref mut stale_chunks,
+            ref mut remeshing_chunks,
+            ref mut thread_starved,
+            num_fetch_requests,
+            ..
+        } = *self;
+
+        let mut num_submitted_chunks = 0;
+        for request in granted.into_iter() {
+            let ChunkRequest {
+                chunk_id,
+                mesher,
+                seams,
+                remesh,
+                ..
+            } = request;
+            let cancelled = submit_chunk(
+                scalar_field,
+                flora,
+                mesh_cache,
+                edited_boxes,
+                thread_pool,
+                chunk_send,
+                chunk_id,
+                mesher,
+                seams,
+                lod_spec,
+            );
+            if remesh {
+                if stale_chunks.remove(&chunk_id) {
+                    debug!("Submitted stale chunk {:?}.", chunk_id);
                 } else {
-                    let tri_mesh = TriMesh::new(
-                        Arc::new(
-                            mesh.vertices
-                                .iter()
-                                .map(|x| x.position.to_point())
-                                .collect(),
-                        ),
-                        Arc::new(
-                            mesh.indices
-                                .chunks(3)
-                                .map(|x| Point3::new(x[0] as usize, x[1] as usize, x[2] as usize))
-                                .collect(),
-                        ),
-                        None,
-                        None,
-                    );
-                    sender.send(ChunkRendererWork {
-                        chunk_id: chunk_id,
-                        meshes: ChunkMeshes::Present(mesh, ShapeHandle::new(tri_mesh)),
-                    });
+                    debug!("Submitted chunk {:?} with seams {:?}.", chunk_id, seams);
                 }
-            });
-            pending_chunks.insert(chunk_id);
+                remeshing_chunks.insert(chunk_id);
+            } else {
+                debug!("Submitted chunk {:?}.", chunk_id);
+                num_submitted_chunks += 1;
+                pending_chunks.insert(chunk_id);
+                pending_cancellations.insert(chunk_id, cancelled);
+            }
         }
 
-        let mut draw_chunks = vec![];
-        for chunk_id in draw_chunk_ids.iter() {
-            if let Some(chunk) = loaded_chunks.peek(chunk_id) {
-                draw_chunks.push(chunk);
+        // The workers are starved when there are chunks to mesh left that we
+        // couldn't submit because all of them are busy.
+        let starved = num_submitted_chunks < num_fetch_requests;
+        if starved && !*thread_starved {
+            warn!(
+                "Chunk generation is thread-starved: {} chunks waiting for {} workers.",
+                num_fetch_requests - num_submitted_chunks,
+                thread_pool.max_count()
+            );
+        } else if !starved && *thread_starved {
+            info!("Chunk generation caught up with the octree.");
+        }
+        *thread_starved = starved;
+    }
+
+    /// The loaded chunks of `draw_chunks`.
+    fn drawn_chunks(&self, draw_chunks: &Vec<(ChunkId, Seams)>) -> Vec<&Chunk> {
+        let mut chunks = vec![];
+        for &(ref chunk_id, _) in draw_chunks.iter() {
+            if let Some(chunk) = loaded_chunk(&self.loaded_chunks, &self.pinned_chunks, chunk_id) {
+                chunks.push(chunk);
             } else {
                 warn!(
                     "A chunk needed to be drawn was evicted after collecting new chunks from \
@@ -512,17 +1962,184 @@ where
                 );
             }
         }
+        chunks
+    }
+}
+
+/// The chunk with `chunk_id` if it's loaded, pinned or not.
+fn loaded_chunk<'a>(
+    loaded_chunks: &'a LruCache<ChunkId, Chunk>,
+    pinned_chunks: &'a HashMap<ChunkId, Chunk>,
+    chunk_id: &ChunkId,
+) -> Option<&'a Chunk> {
+    loaded_chunks.peek(chunk_id).or_else(|| pinned_chunks.get(chunk_id))
+}
+
+/// The milliseconds to wait before retrying a chunk whose meshing failed
+/// `attempts` times in a row, doubling with each up to `MAX_RETRY_BACKOFF_MS`.
+fn retry_backoff_ms(attempts: u32) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (RETRY_BACKOFF_MS << doublings).min(MAX_RETRY_BACKOFF_MS)
+}
+
+/// The least recently used of `chunks`, given with their size in bytes and
+/// when they were last used, to evict for the rest to fit in `budget` bytes.
+/// Those in `keep` are never evicted, even if they don't fit.
+fn chunks_over_budget(
+    mut chunks: Vec<(ChunkId, usize, u64)>,
+    budget: usize,
+    keep: &HashSet<ChunkId>,
+) -> Vec<ChunkId> {
+    let mut total: usize = chunks.iter().map(|&(_, bytes, _)| bytes).sum();
+    chunks.sort_by_key(|&(_, _, last_used)| last_used);
+    let mut evicted = vec![];
+    for (chunk_id, bytes, _) in chunks.into_iter() {
+        if total <= budget {
+            break;
+        }
+        if !keep.contains(&chunk_id) {
+            total -= bytes;
+            evicted.push(chunk_id);
+        }
+    }
+    evicted
+}
+
+/// The seams a chunk meshed with `mesher` is meshed with, only marching
+/// cubes meshes have vertices on the faces of the chunks to meet.
+fn mesher_seams(mesher: Mesher, seams: Seams) -> Seams {
+    if mesher == Mesher::MarchingCubes {
+        seams
+    } else {
+        NO_SEAMS
+    }
+}
 
-        Ok(draw_chunks)
+/// Adds the box from `min` to `max` to `boxes`, merged with the ones it
+/// overlaps. Past `MAX_EDITED_BOXES` it's merged with the box it grows the
+/// least instead, so repeated edits keep the boxes few, if larger than the
+/// edits.
+fn add_edited_box(boxes: &mut Vec<(Vec3f, Vec3f)>, min: &Vec3f, max: &Vec3f) {
+    let union = |a: &(Vec3f, Vec3f), b: &(Vec3f, Vec3f)| {
+        let mut union = *a;
+        for axis in 0..3 {
+            union.0[axis] = a.0[axis].min(b.0[axis]);
+            union.1[axis] = a.1[axis].max(b.1[axis]);
+        }
+        union
+    };
+    let volume = |edited: &(Vec3f, Vec3f)| {
+        (0..3).map(|axis| edited.1[axis] - edited.0[axis]).product::<f32>()
+    };
+    let mut merged = (*min, *max);
+    loop {
+        let overlapping = boxes.iter().position(|edited| {
+            (0..3).all(|axis| {
+                edited.0[axis] <= merged.1[axis] && edited.1[axis] >= merged.0[axis]
+            })
+        });
+        match overlapping {
+            Some(index) => merged = union(&boxes.swap_remove(index), &merged),
+            None => break,
+        }
     }
+    if boxes.len() >= MAX_EDITED_BOXES {
+        let growth = |edited: &(Vec3f, Vec3f)| volume(&union(edited, &merged)) - volume(edited);
+        let nearest = (0..boxes.len())
+            .min_by(|&a, &b| {
+                growth(&boxes[a]).partial_cmp(&growth(&boxes[b])).unwrap_or(cmp::Ordering::Equal)
+            })
+            .unwrap();
+        merged = union(&boxes.swap_remove(nearest), &merged);
+    }
+    boxes.push(merged);
+}
+
+/// Meshes the chunk on one of the workers of `thread_pool`, through
+/// `mesh_cache` unless the chunk overlaps any of the `edited_boxes`. Setting
+/// the flag returned cancels the work.
+fn submit_chunk<Field>(
+    scalar_field: &Arc<Field>,
+    flora: &Option<Arc<Flora>>,
+    mesh_cache: &Option<Arc<MeshCache>>,
+    edited_boxes: &[(Vec3f, Vec3f)],
+    thread_pool: &ThreadPool,
+    chunk_send: &Sender<ChunkRendererWork>,
+    chunk_id: ChunkId,
+    mesher: Mesher,
+    seams: Seams,
+    lod_spec: &LodSpec,
+) -> Arc<AtomicBool>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let scalar_field = scalar_field.clone();
+    let flora = flora.clone();
+    let edited = edited_boxes.iter().any(|&(ref min, ref max)| {
+        chunk_id.overlaps(min, max, lod_spec)
+    });
+    let mesh_cache = if edited { None } else { mesh_cache.clone() };
+    let lod_spec = lod_spec.clone();
+    let sender = chunk_send.clone();
+    let submitted = Instant::now();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let token = cancelled.clone();
+    thread_pool.execute(move || {
+        // Cancelled work is still sent back, for the main loop to count it.
+        if cancelled.load(Ordering::Relaxed) {
+            sender.send(ChunkRendererWork {
+                chunk_id: chunk_id,
+                seams: seams,
+                meshes: ChunkMeshes::Cancelled,
+                submitted: submitted,
+                cancelled: cancelled,
+                meshing_time: Duration::new(0, 0),
+                mesh_cache_hit: None,
+            });
+            return;
+        }
+        let _span = trace::span("mesh_chunk");
+        let started = Instant::now();
+        // Failures, panics included, are sent back as messages so the
+        // pool thread survives and the main loop can report them.
+        let (meshes, mesh_cache_hit) = match crash::catch_panic(AssertUnwindSafe(|| {
+            let flora = flora.as_ref().map(Deref::deref);
+            let mesh_cache = mesh_cache.as_ref().map(Deref::deref);
+            chunk_meshes(
+                scalar_field.deref(),
+                flora,
+                mesh_cache,
+                &chunk_id,
+                mesher,
+                &seams,
+                &lod_spec,
+                &cancelled,
+            )
+        })) {
+            Ok(Ok(meshes)) => meshes,
+            Ok(Err(err)) => (ChunkMeshes::Failed(err.to_string()), None),
+            Err(payload) => (ChunkMeshes::Failed(crash::panic_message(&*payload)), None),
+        };
+        sender.send(ChunkRendererWork {
+            chunk_id: chunk_id,
+            seams: seams,
+            meshes: meshes,
+            submitted: submitted,
+            cancelled: cancelled,
+            meshing_time: started.elapsed(),
+            mesh_cache_hit: mesh_cache_hit,
+        });
+    });
+    token
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum ChunkState {
+pub enum ChunkState {
     Unknown, // The chunk's mesh has not been computed
     Pending, // The chunk's mesh is being computed
     Empty, // The chunk's mesh does not contain any vertices
     Available, // The chunk's mesh is available to draw
+    Failed, // The chunk's meshing failed, it's fetched again after a while
 }
 
 trait ChunkCache {
@@ -551,7 +2168,24 @@ where
 {
     #[inline]
     fn get_chunk_state(&mut self, chunk_id: &ChunkId) -> ChunkState {
-        if self.loaded_chunks.get(chunk_id).is_some() {
+        let frame = self.frame;
+        if let Some(chunk) = self.loaded_chunks.get_mut(chunk_id) {
+            chunk.last_used = frame;
+        }
+        self.peek_chunk_state(chunk_id)
+    }
+}
+
+impl<'a, Field> ChunkRenderer<'a, Field>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    /// The state of the chunk with `chunk_id`, like `get_chunk_state` but
+    /// without counting it as used, so it's kept no longer for it.
+    fn peek_chunk_state(&self, chunk_id: &ChunkId) -> ChunkState {
+        let loaded = self.loaded_chunks.peek(chunk_id).is_some() ||
+            self.pinned_chunks.contains_key(chunk_id);
+        if loaded {
             assert!(
                 !self.empty_chunks.contains_key(chunk_id) && !self.pending_chunks.contains(chunk_id)
             );
@@ -562,7 +2196,267 @@ where
         } else if self.pending_chunks.contains(chunk_id) {
             ChunkState::Pending
         } else {
-            ChunkState::Unknown
+            let retrying = self.failed_chunks.get(chunk_id).map_or(false, |&(_, retry_at)| {
+                retry_at > Instant::now()
+            });
+            if retrying {
+                ChunkState::Failed
+            } else {
+                ChunkState::Unknown
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    use gfx::marching_cubes::NO_SEAMS;
+    use math::{Mesher, Vec3f};
+    use super::{add_edited_box, chunks_over_budget, distance_to_cube, retry_backoff_ms,
+                ChunkCache, ChunkId, ChunkRequest, ChunkScheduler, ChunkState,
+                DetailController, Horizon, LodStats, Octree, OctreeNode, ScreenError,
+                SplitHistory, MAX_EDITED_BOXES, MAX_PINNED_CHUNKS, MAX_RETRY_BACKOFF_MS,
+                RETRY_BACKOFF_MS};
+
+    struct Meshed;
+
+    impl ChunkCache for Meshed {
+        fn get_chunk_state(&mut self, _chunk_id: &ChunkId) -> ChunkState {
+            ChunkState::Available
+        }
+    }
+
+    #[test]
+    fn test_octree_skips_chunks_below_the_horizon() {
+        let (radius, size) = (1000.0, 4096.0);
+        let eye = Vec3f::new(radius + 20.0, 0.0, 0.0);
+        let horizon = Horizon::new(eye, radius).unwrap();
+        assert!(Horizon::new(Vec3f::new(0.0, radius * 0.5, 0.0), radius).is_none());
+        assert!(horizon.hides(&Vec3f::new(-radius - 10.0, 0.0, 0.0), 10.0));
+        assert!(!horizon.hides(&Vec3f::new(radius, 10.0, 0.0), 10.0));
+
+        let (screen, now) = (ScreenError::new(1080, 12.0, 8.0, 32.0), Instant::now());
+        let corner = Vec3f::new(-size / 2.0, -size / 2.0, -size / 2.0);
+        let mut octree = Octree::new(corner, size, Duration::new(0, 0), 8.0);
+        let (all_chunks, _) = octree.rebuild(6, eye, screen, None, now, &mut Meshed);
+        let (visible_chunks, _) = octree.rebuild(6, eye, screen, Some(horizon), now, &mut Meshed);
+        assert!(visible_chunks.len() < all_chunks.len());
+        for &(chunk_id, _) in visible_chunks.iter() {
+            assert!(!horizon.hides(&chunk_id.position(8.0), chunk_id.size(8.0)));
+        }
+    }
+
+    #[test]
+    fn test_screen_error_adapts_to_the_viewport() {
+        let (size, distance) = (64.0, 300.0);
+        assert!(!ScreenError::new(720, 12.0, 8.0, 32.0).too_coarse(size, distance));
+        assert!(ScreenError::new(2160, 12.0, 8.0, 32.0).too_coarse(size, distance));
+        assert!(ScreenError::new(720, 4.0, 4.0, 32.0).too_coarse(size, distance));
+        // Chunks around the eye are always split.
+        assert!(ScreenError::new(1, 12.0, 8.0, 32.0).too_coarse(size, 0.0));
+
+        let size = 4096.0;
+        let eye = Vec3f::new(100.0, 200.0, 300.0);
+        let corner = Vec3f::new(-size / 2.0, -size / 2.0, -size / 2.0);
+        let now = Instant::now();
+        let mut octree = Octree::new(corner, size, Duration::new(0, 0), 8.0);
+        let screen = ScreenError::new(480, 12.0, 8.0, 32.0);
+        let (small_window, _) = octree.rebuild(8, eye, screen, None, now, &mut Meshed);
+        let screen = ScreenError::new(2160, 12.0, 8.0, 32.0);
+        let (large_window, _) = octree.rebuild(8, eye, screen, None, now, &mut Meshed);
+        assert!(small_window.len() < large_window.len());
+    }
+
+    #[test]
+    fn test_split_history_hysteresis() {
+        // A step of a chunk of 64 covers 12 pixels 156 away and 8 234 away.
+        let (size, screen) = (64.0, ScreenError::new(1080, 12.0, 8.0, 32.0));
+        let dwell = Duration::from_millis(500);
+        let mut history = SplitHistory::new(dwell);
+        let mut node = OctreeNode::new(Vec3f::new(0.0, 0.0, 0.0), size, 3, true, 8.0);
+        let chunk_id = node.chunk_id;
+        let start = Instant::now();
+        let later = |millis| start + Duration::from_millis(millis);
+
+        assert!(!history.should_split(&chunk_id, screen, size, 200.0, start));
+        assert!(history.should_split(&chunk_id, screen, size, 100.0, start));
+        node.children = Some([0; 8]);
+        history.record(&[node.clone()], start);
+        // Split, it isn't merged between the two thresholds, nor at all
+        // before it's settled.
+        assert!(history.should_split(&chunk_id, screen, size, 200.0, later(1000)));
+        assert!(history.should_split(&chunk_id, screen, size, 300.0, later(100)));
+        assert!(!history.should_split(&chunk_id, screen, size, 300.0, later(1000)));
+
+        history.record(&[], later(1000));
+        assert!(!history.should_split(&chunk_id, screen, size, 100.0, later(1100)));
+        assert!(history.should_split(&chunk_id, screen, size, 100.0, later(1500)));
+        history.record(&[], later(2000));
+        assert!(history.merged.is_empty());
+    }
+
+    #[test]
+    fn test_octree_pins_the_chunks_around_the_eye() {
+        let size = 4096.0;
+        let eye = Vec3f::new(100.0, 200.0, -300.0);
+        let (screen, now) = (ScreenError::new(1080, 12.0, 8.0, 32.0), Instant::now());
+        let corner = Vec3f::new(-size / 2.0, -size / 2.0, -size / 2.0);
+        let mut octree = Octree::new(corner, size, Duration::new(0, 0), 8.0);
+        let (draw_chunks, _) = octree.rebuild(6, eye, screen, None, now, &mut Meshed);
+        let pinned = octree.pinned_chunk_ids(&eye);
+        assert!(!pinned.is_empty() && pinned.len() <= MAX_PINNED_CHUNKS);
+        // The finest chunk, which the eye is in, is pinned.
+        let finest = size / 64.0;
+        assert!(pinned.iter().any(|chunk_id| {
+            chunk_id.size(8.0) == finest &&
+                distance_to_cube(&chunk_id.position(8.0), finest, &eye) == 0.0
+        }));
+        for chunk_id in pinned.iter() {
+            assert!(draw_chunks.iter().any(|&(drawn, _)| drawn == *chunk_id));
+            assert!(distance_to_cube(&chunk_id.position(8.0), chunk_id.size(8.0), &eye) <= finest);
+        }
+    }
+
+    #[test]
+    fn test_chunks_over_budget_evicts_least_recently_used() {
+        let chunk_id = |x: f32| ChunkId::new(&Vec3f::new(x * 64.0, 0.0, 0.0), 64.0, 8.0);
+        let chunks = vec![
+            (chunk_id(0.0), 300, 5),
+            (chunk_id(1.0), 200, 2),
+            (chunk_id(2.0), 400, 9),
+            (chunk_id(3.0), 100, 1),
+        ];
+        let mut keep = HashSet::new();
+        assert!(chunks_over_budget(chunks.clone(), 1000, &keep).is_empty());
+        let evicted = chunks_over_budget(chunks.clone(), 800, &keep);
+        assert_eq!(vec![chunk_id(3.0), chunk_id(1.0)], evicted);
+
+        // Those kept are skipped, even if the rest can't make room.
+        keep.insert(chunk_id(3.0));
+        keep.insert(chunk_id(2.0));
+        let evicted = chunks_over_budget(chunks, 100, &keep);
+        assert_eq!(vec![chunk_id(1.0), chunk_id(0.0)], evicted);
+    }
+
+    #[test]
+    fn test_lod_stats_means_and_merge() {
+        let millis = |millis| Duration::from_millis(millis);
+        let mut stats = LodStats::default();
+        assert_eq!(None, stats.overall_mean_meshing_time());
+        assert_eq!(None, stats.mesh_cache_hit_rate());
+        stats.record_meshing(2, millis(10));
+        stats.record_meshing(2, millis(30));
+        stats.record_meshing(0, millis(5));
+        assert_eq!(Some(millis(20)), stats.mean_meshing_time(2));
+        assert_eq!(None, stats.mean_meshing_time(1));
+        assert_eq!(None, stats.mean_meshing_time(7));
+        assert_eq!(Some(millis(15)), stats.overall_mean_meshing_time());
+
+        let mut other = LodStats::default();
+        other.record_meshing(4, millis(45));
+        other.mesh_cache_hits = 3;
+        other.mesh_cache_misses = 1;
+        stats.merge(&other);
+        assert_eq!(Some(millis(45)), stats.mean_meshing_time(4));
+        assert_eq!(Some(millis(20)), stats.mean_meshing_time(2));
+        assert_eq!(Some(0.75), stats.mesh_cache_hit_rate());
+    }
+
+    #[test]
+    fn test_detail_controller_degrades_and_recovers() {
+        let mut detail = DetailController::new(16.0, 2.0);
+        for _ in 0..10 {
+            detail.update(10.0);
+        }
+        assert_eq!(1.0, detail.scale);
+        // A single slow frame barely moves the smoothed time.
+        detail.update(40.0);
+        assert_eq!(1.0, detail.scale);
+        for _ in 0..200 {
+            detail.update(30.0);
         }
+        assert_eq!(2.0, detail.scale);
+        // Between the headroom and the target it holds.
+        for _ in 0..100 {
+            detail.update(14.0);
+        }
+        assert_eq!(2.0, detail.scale);
+        for _ in 0..200 {
+            detail.update(5.0);
+        }
+        assert_eq!(1.0, detail.scale);
+
+        let mut disabled = DetailController::new(0.0, 4.0);
+        disabled.update(100.0);
+        assert_eq!(1.0, disabled.scale);
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_up_to_the_maximum() {
+        assert_eq!(RETRY_BACKOFF_MS, retry_backoff_ms(1));
+        assert_eq!(2 * RETRY_BACKOFF_MS, retry_backoff_ms(2));
+        assert_eq!(8 * RETRY_BACKOFF_MS, retry_backoff_ms(4));
+        assert_eq!(MAX_RETRY_BACKOFF_MS, retry_backoff_ms(20));
+        assert_eq!(MAX_RETRY_BACKOFF_MS, retry_backoff_ms(u32::max_value()));
+    }
+
+    #[test]
+    fn test_edited_boxes_merge_when_they_overlap() {
+        let mut boxes = vec![];
+        let edit = |x: f32| (Vec3f::new(x, 0.0, 0.0), Vec3f::new(x + 2.0, 2.0, 2.0));
+        add_edited_box(&mut boxes, &edit(0.0).0, &edit(0.0).1);
+        add_edited_box(&mut boxes, &edit(10.0).0, &edit(10.0).1);
+        add_edited_box(&mut boxes, &edit(1.0).0, &edit(1.0).1);
+        assert_eq!(2, boxes.len());
+        assert!(boxes.contains(&(Vec3f::new(0.0, 0.0, 0.0), Vec3f::new(3.0, 2.0, 2.0))));
+        // Apart, the edits are merged once there are too many boxes.
+        for index in 0..2 * MAX_EDITED_BOXES {
+            let x = 100.0 + index as f32 * 10.0;
+            add_edited_box(&mut boxes, &edit(x).0, &edit(x).1);
+        }
+        assert_eq!(MAX_EDITED_BOXES, boxes.len());
+    }
+
+    #[test]
+    fn test_chunk_scheduler_grants_by_priority_across_bodies() {
+        let request = |x: f32, pinned: bool, priority: f32| {
+            ChunkRequest {
+                chunk_id: ChunkId::new(&Vec3f::new(x, 0.0, 0.0), 1.0, 8.0),
+                mesher: Mesher::MarchingCubes,
+                seams: NO_SEAMS,
+                remesh: false,
+                pinned: pinned,
+                priority: priority,
+            }
+        };
+        let granted_xs = |granted: &Vec<ChunkRequest>| -> Vec<f32> {
+            granted.iter().map(|request| request.chunk_id.position(8.0)[0]).collect()
+        };
+        let scheduler = ChunkScheduler { max_in_flight: 3 };
+
+        // The nearest chunks of either body go first, whichever asked first.
+        let granted = scheduler.schedule(vec![
+            (0, vec![request(0.0, false, 0.1), request(1.0, false, 0.9)]),
+            (0, vec![request(2.0, false, 0.5), request(3.0, false, 0.2)]),
+        ]);
+        assert_eq!(vec![1.0], granted_xs(&granted[0]));
+        assert_eq!(vec![2.0, 3.0], granted_xs(&granted[1]));
+
+        // The chunks already in flight count against the limit, but pinned
+        // chunks are granted past it.
+        let granted = scheduler.schedule(vec![
+            (2, vec![request(0.0, false, 0.9), request(1.0, true, f32::INFINITY)]),
+            (0, vec![request(2.0, false, 0.5)]),
+        ]);
+        assert_eq!(vec![1.0], granted_xs(&granted[0]));
+        assert!(granted[1].is_empty());
+        let pinned: Vec<_> = (0..MAX_PINNED_CHUNKS + 1)
+            .map(|x| request(x as f32, true, f32::INFINITY))
+            .collect();
+        let granted = scheduler.schedule(vec![(3, pinned)]);
+        assert_eq!(MAX_PINNED_CHUNKS, granted[0].len());
     }
 }
@@ -1,270 +1,513 @@
+use std::mem;
+
 use num::{Float, FromPrimitive, Zero};
 
 use nalgebra::{Norm, Point3, Vector3};
-use math::{ScalarField3, Vec3f};
+use rayon::prelude::*;
+
+use math::{CpuScalar, ScalarField3, Vec3f};
 use super::mesh::{Mesh, Vertex, triangle_normal};
 
-pub fn marching_cubes<Field: ScalarField3>(
+pub fn marching_cubes<Field: ScalarField3 + Sync>(
     field: &Field,
     min: &Vec3f,
     max: &Vec3f,
     step: f32,
     iso_value: f32,
 ) -> Mesh<Vertex> {
-    let mut vertices = vec![];
-    let mut indices = vec![];
+    marching_cubes_with_seams(field, min, max, step, iso_value, &NO_SEAMS)
+}
 
-    let max_x = max[0];
-    let max_y = max[1];
-    let max_z = max[2];
+/// Like `marching_cubes`, with the faces of the box next to coarser meshes,
+/// as given by `seams`, meshed to meet them without cracks.
+///
+/// On such a face the field is taken to be the bilinear interpolation of the
+/// values at the coarse grid points, so the crossings on the coarse edges are
+/// where the coarse mesh has them. The vertices between them are then moved
+/// along their edges onto the segment the coarse mesh joins them with, which
+/// leaves only cells with four crossings on the face (the ambiguous ones)
+/// unstitched.
+pub fn marching_cubes_with_seams<Field: ScalarField3 + Sync>(
+    field: &Field,
+    min: &Vec3f,
+    max: &Vec3f,
+    step: f32,
+    iso_value: f32,
+    seams: &Seams,
+) -> Mesh<Vertex> {
+    let xs = grid_coordinates(min[0], max[0], step);
+    let ys = grid_coordinates(min[1], max[1], step);
+    let zs = grid_coordinates(min[2], max[2], step);
 
-    let mut x = min[0];
+    // The grid is sampled in parts of consecutive planes of constant x, which
+    // are contiguous in `values`, in parallel, so one large chunk keeps every
+    // core busy. Each part samples the planes it starts at, the last one the
+    // last plane too, so every grid point is sampled once.
+    let sample_parts = split_slabs(xs.len() - 1);
+    let mut part_values = vec![];
+    sample_parts
+        .par_iter()
+        .map(|&(start, end)| {
+            let end = if end == xs.len() - 1 { end + 1 } else { end };
+            sample_grid(field, step, &xs[start..end], &ys, &zs)
+        })
+        .collect_into(&mut part_values);
+    let mut values: Vec<f32> = part_values.into_iter().flat_map(|values| values).collect();
+    let faces: Vec<SeamFace> = (0..6)
+        .filter(|&face| seams[face] > 0)
+        .map(|face| {
+            SeamFace::new(field, step, [&xs, &ys, &zs], face, seams[face], &mut values)
+        })
+        .collect();
 
-    let mut index_map: [usize; 12] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-    while x + step < max_x {
-        let x_dx = x + step;
-        let mut y = min[1];
+    if !crosses_surface(&values, iso_value) {
+        return Mesh {
+            name: "test".to_owned(),
+            vertices: vec![],
+            indices: vec![],
+        };
+    }
 
-        while y + step < max_y {
-            let y_dy = y + step;
-            let mut z = min[2];
+    let grid = Grid {
+        xs: &xs,
+        ys: &ys,
+        zs: &zs,
+        values: &values,
+    };
+    let shape = [xs.len(), ys.len(), zs.len()];
+    let mut mesh = match field.gpu_cell_triangles(shape, &values, iso_value) {
+        Some(corners) => mesh_from_edges(field, &grid, &corners, iso_value),
+        None => {
+            // The cells are polygonised in z-slabs in parallel too, and the
+            // meshes of the slabs stitched together.
+            let mut part_meshes = vec![];
+            split_slabs(zs.len() - 1)
+                .par_iter()
+                .map(|&(start, end)| polygonise(field, &grid, start, end, iso_value))
+                .collect_into(&mut part_meshes);
+            stitch(part_meshes)
+        }
+    };
+    for face in faces.iter() {
+        face.snap(&mut mesh, [&xs, &ys, &zs], iso_value);
+    }
+    mesh
+}
+
+/// How many levels coarser than a box the mesh across each of its faces is,
+/// in the order -x, +x, -y, +y, -z, +z, with 0 where it's no coarser.
+pub type Seams = [u8; 6];
+
+/// A face of the grid next to a coarser mesh, its grid points indexed by the
+/// two axes along it.
+struct SeamFace {
+    axis: usize,
+    plane: usize,
+    along: (usize, usize),
+    /// The coarse grid is every `ratio` points of the grid, and its last one.
+    ratio: usize,
+    /// The values at the coarse grid points, the others are unused.
+    coarse_values: Vec<f32>,
+}
+
+impl SeamFace {
+    /// Samples the coarse grid points on `face` of the grid, `levels` coarser
+    /// and so with the detail of a step that many times larger, and replaces
+    /// the `values` on the face with their interpolation.
+    fn new<Field: ScalarField3>(
+        field: &Field,
+        step: f32,
+        coordinates: [&[f32]; 3],
+        face: usize,
+        levels: u8,
+        values: &mut [f32],
+    ) -> Self {
+        let axis = face / 2;
+        let plane = if face % 2 == 0 {
+            0
+        } else {
+            coordinates[axis].len() - 1
+        };
+        let along = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let (len_u, len_v) = (coordinates[along.0].len(), coordinates[along.1].len());
+        // The coarse grid can't be coarser than the face, the levels of
+        // neighbouring chunks never differ that much.
+        let ratio = (1usize << levels.min(MAX_SEAM_LEVELS)).min(len_u.min(len_v) - 1).max(1);
+        let mut seam = SeamFace {
+            axis: axis,
+            plane: plane,
+            along: along,
+            ratio: ratio,
+            coarse_values: vec![0.0; len_u * len_v],
+        };
+
+        let mut coarse_points = vec![];
+        let mut positions = vec![];
+        for u in 0..len_u {
+            for v in 0..len_v {
+                if seam.is_coarse(u, len_u) && seam.is_coarse(v, len_v) {
+                    let point = seam.grid_point(u, v);
+                    coarse_points.push(u * len_v + v);
+                    positions.push(Point3::new(
+                        coordinates[0][point[0]],
+                        coordinates[1][point[1]],
+                        coordinates[2][point[2]],
+                    ));
+                }
+            }
+        }
+        let mut coarse_values = vec![0.0; positions.len()];
+        field.values_at_lod(&positions, &mut coarse_values, step * ratio as f32);
+        for (&point, &value) in coarse_points.iter().zip(coarse_values.iter()) {
+            seam.coarse_values[point] = value;
+        }
+
+        let (us, vs) = (coordinates[along.0], coordinates[along.1]);
+        let (len_y, len_z) = (coordinates[1].len(), coordinates[2].len());
+        for u in 0..len_u {
+            let (u0, u1) = seam.coarse_cell(u, len_u);
+            let s = fraction(us, u0, u, u1);
+            for v in 0..len_v {
+                let (v0, v1) = seam.coarse_cell(v, len_v);
+                let t = fraction(vs, v0, v, v1);
+                let coarse = |u: usize, v: usize| seam.coarse_values[u * len_v + v];
+                let value = (coarse(u0, v0) * (1.0 - t) + coarse(u0, v1) * t) * (1.0 - s) +
+                    (coarse(u1, v0) * (1.0 - t) + coarse(u1, v1) * t) * s;
+                let point = seam.grid_point(u, v);
+                values[(point[0] * len_y + point[1]) * len_z + point[2]] = value;
+            }
+        }
+        seam
+    }
+
+    /// The index of the grid point at `u` and `v` along the face.
+    fn grid_point(&self, u: usize, v: usize) -> [usize; 3] {
+        let mut point = [0; 3];
+        point[self.axis] = self.plane;
+        point[self.along.0] = u;
+        point[self.along.1] = v;
+        point
+    }
+
+    fn is_coarse(&self, index: usize, len: usize) -> bool {
+        index % self.ratio == 0 || index == len - 1
+    }
+
+    /// The coarse grid points either side of `index`, the same one twice for
+    /// the last.
+    fn coarse_cell(&self, index: usize, len: usize) -> (usize, usize) {
+        let start = (index / self.ratio * self.ratio).min(len - 1);
+        (start, (start + self.ratio).min(len - 1))
+    }
+
+    /// Moves the vertices of `mesh` on the face between the coarse edges
+    /// onto the segments the coarse mesh has instead.
+    fn snap(&self, mesh: &mut Mesh<Vertex>, coordinates: [&[f32]; 3], iso_value: f32) {
+        let plane_coordinate = coordinates[self.axis][self.plane];
+        let (us, vs) = (coordinates[self.along.0], coordinates[self.along.1]);
+        for vertex in mesh.vertices.iter_mut() {
+            if vertex.position[self.axis] != plane_coordinate {
+                continue;
+            }
+            let (u, v) = (vertex.position[self.along.0], vertex.position[self.along.1]);
+            // Vertices are on the edges of the grid, so one of their
+            // coordinates along the face is that of a grid point.
+            let snapped = match (grid_index(us, u), grid_index(vs, v)) {
+                (Some(u_index), None) if !self.is_coarse(u_index, us.len()) => {
+                    let v_index = cell_index(vs, v);
+                    self.coarse_crossing(us, vs, u_index, v_index, u, false, iso_value)
+                        .map(|v| (u, v))
+                }
+                (None, Some(v_index)) if !self.is_coarse(v_index, vs.len()) => {
+                    let u_index = cell_index(us, u);
+                    self.coarse_crossing(vs, us, v_index, u_index, v, true, iso_value)
+                        .map(|u| (u, v))
+                }
+                _ => None,
+            };
+            if let Some((u, v)) = snapped {
+                vertex.position[self.along.0] = u;
+                vertex.position[self.along.1] = v;
+            }
+        }
+    }
+
+    /// Where the segment joining the crossings on the edges of the coarse
+    /// cell around the grid points at `fixed_index` and `moving_index` meets
+    /// the line at `fixed`, as a coordinate along the moving axis. The axes
+    /// are flipped relative to the face's own along `transposed`.
+    fn coarse_crossing(
+        &self,
+        fixed_coordinates: &[f32],
+        moving_coordinates: &[f32],
+        fixed_index: usize,
+        moving_index: usize,
+        fixed: f32,
+        transposed: bool,
+        iso_value: f32,
+    ) -> Option<f32> {
+        let (f0, f1) = self.coarse_cell(fixed_index, fixed_coordinates.len());
+        let (m0, m1) = self.coarse_cell(moving_index, moving_coordinates.len());
+        let len_v = if transposed {
+            fixed_coordinates.len()
+        } else {
+            moving_coordinates.len()
+        };
+        let value = |f: usize, m: usize| if transposed {
+            self.coarse_values[m * len_v + f]
+        } else {
+            self.coarse_values[f * len_v + m]
+        };
+        let (fs, ms) = (fixed_coordinates, moving_coordinates);
 
-            while z + step < max_z {
-                let z_dz = z + step;
-                let values_on_cube = eval_field_at_corners(field, x, y, z, x_dx, y_dy, z_dz);
+        // The crossings on the four coarse edges as (fixed, moving) points.
+        let mut crossings = vec![];
+        for &(a, b) in &[((f0, m0), (f1, m0)), ((f0, m1), (f1, m1))] {
+            if (value(a.0, a.1) < iso_value) != (value(b.0, b.1) < iso_value) {
+                let f = iso_value_interpolation(
+                    iso_value,
+                    fs[a.0],
+                    fs[b.0],
+                    value(a.0, a.1),
+                    value(b.0, b.1),
+                );
+                crossings.push((f, ms[a.1]));
+            }
+        }
+        for &(a, b) in &[((f0, m0), (f0, m1)), ((f1, m0), (f1, m1))] {
+            if (value(a.0, a.1) < iso_value) != (value(b.0, b.1) < iso_value) {
+                let m = iso_value_interpolation(
+                    iso_value,
+                    ms[a.1],
+                    ms[b.1],
+                    value(a.0, a.1),
+                    value(b.0, b.1),
+                );
+                crossings.push((fs[a.0], m));
+            }
+        }
+        if crossings.len() != 2 || crossings[0].0 == crossings[1].0 {
+            return None;
+        }
+        let ((fa, ma), (fb, mb)) = (crossings[0], crossings[1]);
+        Some(ma + (mb - ma) * (fixed - fa) / (fb - fa))
+    }
+}
+
+/// Where `index` is between the grid points `start` and `end`, in [0, 1].
+fn fraction(coordinates: &[f32], start: usize, index: usize, end: usize) -> f32 {
+    if start == end {
+        0.0
+    } else {
+        (coordinates[index] - coordinates[start]) / (coordinates[end] - coordinates[start])
+    }
+}
+
+/// The index of the grid point at `coordinate`, if there's one.
+fn grid_index(coordinates: &[f32], coordinate: f32) -> Option<usize> {
+    coordinates.iter().position(|&grid| grid == coordinate)
+}
+
+/// The index of the grid point starting the cell containing `coordinate`.
+fn cell_index(coordinates: &[f32], coordinate: f32) -> usize {
+    coordinates
+        .iter()
+        .rposition(|&grid| grid <= coordinate)
+        .unwrap_or(0)
+        .min(coordinates.len() - 2)
+}
+
+/// The grid points and the values at them, ordered by x, y then z.
+pub struct Grid<'a> {
+    pub xs: &'a [f32],
+    pub ys: &'a [f32],
+    pub zs: &'a [f32],
+    pub values: &'a [f32],
+}
+
+/// Splits `num_slabs` slabs of cells into parts of about `SLABS_PER_PART`
+/// consecutive ones, given by the plane each starts and ends at.
+fn split_slabs(num_slabs: usize) -> Vec<(usize, usize)> {
+    let num_parts = (num_slabs / SLABS_PER_PART).max(1);
+    (0..num_parts)
+        .map(|part| {
+            (part * num_slabs / num_parts, (part + 1) * num_slabs / num_parts)
+        })
+        .collect()
+}
+
+/// The mesh of some consecutive z-slabs of cells, with the vertices on the x
+/// and y edges of the planes of constant z at either end, by grid point
+/// ordered by x then y, which the parts on the other side have too.
+struct PartMesh {
+    mesh: Mesh<Vertex>,
+    first_plane: Vec<[u32; 3]>,
+    last_plane: Vec<[u32; 3]>,
+}
+
+/// Polygonises the cells between the planes of constant z `start` and `end`.
+fn polygonise<Field: ScalarField3>(
+    field: &Field,
+    grid: &Grid,
+    start: usize,
+    end: usize,
+    iso_value: f32,
+) -> PartMesh {
+    let (xs, ys, zs) = (grid.xs, grid.ys, grid.zs);
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    // The vertices on the edges from each grid point of the two slabs, along
+    // x, y and z, so the cells sharing an edge share its vertex. Those along
+    // x from the next slab are only known once it's the current one.
+    let slab_len = ys.len() * zs.len();
+    let mut edge_vertices = vec![[NO_VERTEX; 3]; slab_len];
+    let mut next_edge_vertices = vec![[NO_VERTEX; 3]; slab_len];
+    let plane_len = xs.len() * ys.len();
+    let (mut first_plane, mut last_plane) =
+        (vec![[NO_VERTEX; 3]; plane_len], vec![[NO_VERTEX; 3]; plane_len]);
+    // Keeps the vertices of the x slab `i` on the planes at either end, once
+    // it's done.
+    let mut keep_planes = |i: usize, edge_vertices: &[[u32; 3]]| {
+        for j in 0..ys.len() {
+            first_plane[i * ys.len() + j] = edge_vertices[j * zs.len() + start];
+            last_plane[i * ys.len() + j] = edge_vertices[j * zs.len() + end];
+        }
+    };
+
+    let mut index_map: [u32; 12] = [0; 12];
+    for i in 0..xs.len() - 1 {
+        let slab = &grid.values[i * slab_len..(i + 1) * slab_len];
+        let next_slab = &grid.values[(i + 1) * slab_len..(i + 2) * slab_len];
+        for j in 0..ys.len() - 1 {
+            for k in start..end {
+                let (corner, corner_dy) = (j * zs.len() + k, (j + 1) * zs.len() + k);
+                let values_on_cube = [
+                    slab[corner],
+                    next_slab[corner],
+                    next_slab[corner_dy],
+                    slab[corner_dy],
+                    slab[corner + 1],
+                    next_slab[corner + 1],
+                    next_slab[corner_dy + 1],
+                    slab[corner_dy + 1],
+                ];
                 let cube_index = find_cube_index(iso_value, values_on_cube);
 
                 // `edges[cube_index]` is a 12 bit number with a 1 for each of
                 // the edges that cross the iso-surface
                 let edges = EDGE_TABLE[cube_index];
-                // println!("{}, {}, {} - edges{:?}", x, y, z, edges);
                 if edges == 0 {
-                    z += step;
                     continue;
                 }
 
                 // edges counted like in http://paulbourke.net/geometry/polygonise/
-                // edge 0
-                if edges & 0x001 != 0 {
-                    index_map[0] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y,
-                        z,
-                        x_dx,
-                        Axis::X,
-                        values_on_cube[0],
-                        values_on_cube[1],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 1
-                if edges & 0x002 != 0 {
-                    index_map[1] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y,
-                        z,
-                        y_dy,
-                        Axis::Y,
-                        values_on_cube[1],
-                        values_on_cube[2],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 2
-                if edges & 0x004 != 0 {
-                    index_map[2] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y_dy,
-                        z,
-                        x,
-                        Axis::X,
-                        values_on_cube[2],
-                        values_on_cube[3],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 3
-                if edges & 0x008 != 0 {
-                    index_map[3] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y_dy,
-                        z,
-                        y,
-                        Axis::Y,
-                        values_on_cube[3],
-                        values_on_cube[0],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 4
-                if edges & 0x010 != 0 {
-                    index_map[4] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y,
-                        z_dz,
-                        x_dx,
-                        Axis::X,
-                        values_on_cube[4],
-                        values_on_cube[5],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 5
-                if edges & 0x020 != 0 {
-                    index_map[5] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y,
-                        z_dz,
-                        y_dy,
-                        Axis::Y,
-                        values_on_cube[5],
-                        values_on_cube[6],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 6
-                if edges & 0x040 != 0 {
-                    index_map[6] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y_dy,
-                        z_dz,
-                        x,
-                        Axis::X,
-                        values_on_cube[6],
-                        values_on_cube[7],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 7
-                if edges & 0x080 != 0 {
-                    index_map[7] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y_dy,
-                        z_dz,
-                        y,
-                        Axis::Y,
-                        values_on_cube[7],
-                        values_on_cube[4],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 8
-                if edges & 0x100 != 0 {
-                    index_map[8] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y,
-                        z,
-                        z_dz,
-                        Axis::Z,
-                        values_on_cube[0],
-                        values_on_cube[4],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 9
-                if edges & 0x200 != 0 {
-                    index_map[9] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y,
-                        z,
-                        z_dz,
-                        Axis::Z,
-                        values_on_cube[1],
-                        values_on_cube[5],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 10
-                if edges & 0x400 != 0 {
-                    index_map[10] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x_dx,
-                        y_dy,
-                        z,
-                        z_dz,
-                        Axis::Z,
-                        values_on_cube[2],
-                        values_on_cube[6],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
-                }
-                // edge 11
-                if edges & 0x800 != 0 {
-                    index_map[11] = vertices.len();
-                    let vertex = intersection_vertex(
-                        x,
-                        y_dy,
-                        z,
-                        z_dz,
-                        Axis::Z,
-                        values_on_cube[3],
-                        values_on_cube[7],
-                        iso_value,
-                    );
-                    vertices.push(vertex);
+                for (edge, &(di, dj, dk, axis, from, to)) in EDGE_LATTICE.iter().enumerate() {
+                    if edges & (1 << edge) == 0 {
+                        continue;
+                    }
+                    let point = (j + dj) * zs.len() + k + dk;
+                    let cached = if di == 0 {
+                        &mut edge_vertices[point][axis]
+                    } else {
+                        &mut next_edge_vertices[point][axis]
+                    };
+                    if *cached == NO_VERTEX {
+                        let (x, y, z) = (xs[i + di], ys[j + dj], zs[k + dk]);
+                        let (axis, adjacent) = match axis {
+                            0 => (Axis::X, xs[i + 1]),
+                            1 => (Axis::Y, ys[j + 1]),
+                            _ => (Axis::Z, zs[k + 1]),
+                        };
+                        let mut vertex = intersection_vertex(
+                            x,
+                            y,
+                            z,
+                            adjacent,
+                            axis,
+                            values_on_cube[from],
+                            values_on_cube[to],
+                            iso_value,
+                        );
+                        vertex.normal =
+                            normalized_field_gradient_at_vertex::<Field>(field, &vertex.position) *
+                                -1.0;
+                        *cached = vertices.len() as u32;
+                        vertices.push(vertex);
+                    }
+                    index_map[edge] = *cached;
                 }
 
                 let triangles_ixes = TRIANGLE_TABLE[cube_index];
-
                 for ix in triangles_ixes.chunks(3) {
                     if ix[0] == -1 {
                         break;
                     }
-
-                    let i0 = index_map[ix[0] as usize];
-                    let i1 = index_map[ix[1] as usize];
-                    let i2 = index_map[ix[2] as usize];
-
-                    vertices[i0].normal = normalized_field_gradient_at_vertex::<Field>(
-                        field,
-                        &vertices[i0].position,
-                    ) * -1.0;
-                    vertices[i1].normal = normalized_field_gradient_at_vertex::<Field>(
-                        field,
-                        &vertices[i1].position,
-                    ) * -1.0;
-                    vertices[i2].normal = normalized_field_gradient_at_vertex::<Field>(
-                        field,
-                        &vertices[i2].position,
-                    ) * -1.0;
-
-                    // let n = triangle_normal(&vertices[i0], &vertices[i1], &vertices[i2]);
-                    // vertices[i0].normal = n;
-                    // vertices[i1].normal = n;
-                    // vertices[i2].normal = n;
-
-                    indices.push(i0 as u32);
-                    indices.push(i1 as u32);
-                    indices.push(i2 as u32);
+                    indices.push(index_map[ix[0] as usize]);
+                    indices.push(index_map[ix[1] as usize]);
+                    indices.push(index_map[ix[2] as usize]);
                 }
-                z += step;
             }
-            y += step;
         }
-        x += step;
+        keep_planes(i, &edge_vertices);
+        mem::swap(&mut edge_vertices, &mut next_edge_vertices);
+        for point in next_edge_vertices.iter_mut() {
+            *point = [NO_VERTEX; 3];
+        }
+    }
+    keep_planes(xs.len() - 1, &edge_vertices);
+
+    PartMesh {
+        mesh: Mesh {
+            name: "test".to_owned(),
+            vertices: vertices,
+            indices: indices,
+        },
+        first_plane: first_plane,
+        last_plane: last_plane,
     }
+}
 
+/// Joins the meshes of consecutive parts, each vertex on the plane between
+/// two of them being kept once.
+fn stitch(parts: Vec<PartMesh>) -> Mesh<Vertex> {
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut shared_plane: Vec<[u32; 3]> = vec![];
+    for part in parts {
+        let mut remap = vec![NO_VERTEX; part.mesh.vertices.len()];
+        for (own, shared) in part.first_plane.iter().zip(shared_plane.iter()) {
+            // The edges along z belong to this part only.
+            for axis in 0..2 {
+                if own[axis] != NO_VERTEX && shared[axis] != NO_VERTEX {
+                    remap[own[axis] as usize] = shared[axis];
+                }
+            }
+        }
+        for (index, vertex) in part.mesh.vertices.into_iter().enumerate() {
+            if remap[index] == NO_VERTEX {
+                remap[index] = vertices.len() as u32;
+                vertices.push(vertex);
+            }
+        }
+        indices.extend(part.mesh.indices.iter().map(|&index| remap[index as usize]));
+        shared_plane = part.last_plane
+            .iter()
+            .map(|point| {
+                let mut point = *point;
+                for index in point.iter_mut() {
+                    if *index != NO_VERTEX {
+                        *index = remap[*index as usize];
+                    }
+                }
+                point
+            })
+            .collect();
+    }
     Mesh {
         name: "test".to_owned(),
         vertices: vertices,
@@ -272,26 +515,111 @@ pub fn marching_cubes<Field: ScalarField3>(
     }
 }
 
-#[inline]
-fn eval_field_at_corners<Field: ScalarField3>(
+/// The mesh of the triangles `gpu_cell_triangles` found through `grid`, given
+/// by the edges their `corners` are on, with a vertex on each edge placed
+/// like `polygonise` places it. The normals are taken in one batch of
+/// `gradients_at`, which fields meshed on the GPU evaluate there too.
+fn mesh_from_edges<Field: ScalarField3 + Sync>(
+    field: &Field,
+    grid: &Grid,
+    corners: &[u32],
+    iso_value: f32,
+) -> Mesh<Vertex> {
+    let mut edges = corners.to_vec();
+    edges.sort();
+    edges.dedup();
+    let (xs, ys, zs) = (grid.xs, grid.ys, grid.zs);
+    let mut vertices = vec![];
+    edges
+        .par_iter()
+        .map(|&edge| {
+            let (point, axis) = ((edge / 3) as usize, edge % 3);
+            let (i, j, k) = (
+                point / (ys.len() * zs.len()),
+                (point / zs.len()) % ys.len(),
+                point % zs.len(),
+            );
+            let (axis, adjacent, next) = match axis {
+                0 => (Axis::X, xs[i + 1], point + ys.len() * zs.len()),
+                1 => (Axis::Y, ys[j + 1], point + zs.len()),
+                _ => (Axis::Z, zs[k + 1], point + 1),
+            };
+            intersection_vertex(
+                xs[i],
+                ys[j],
+                zs[k],
+                adjacent,
+                axis,
+                grid.values[point],
+                grid.values[next],
+                iso_value,
+            )
+        })
+        .collect_into(&mut vertices);
+    let positions: Vec<Point3<CpuScalar>> =
+        vertices.iter().map(|vertex| *vertex.position.as_point()).collect();
+    let mut gradients = vec![Vector3::new(0.0, 0.0, 0.0); positions.len()];
+    field.gradients_at(&positions, &mut gradients);
+    for (vertex, gradient) in vertices.iter_mut().zip(gradients.iter()) {
+        vertex.normal = Vec3f::from(gradient.normalize()) * -1.0;
+    }
+    Mesh {
+        name: "test".to_owned(),
+        vertices: vertices,
+        indices: corners
+            .iter()
+            .map(|corner| edges.binary_search(corner).unwrap() as u32)
+            .collect(),
+    }
+}
+
+/// `TRIANGLE_TABLE` row by row, for the GPU.
+pub fn flat_triangle_table() -> Vec<i32> {
+    TRIANGLE_TABLE
+        .iter()
+        .flat_map(|row| row.iter().map(|&edge| edge as i32))
+        .collect()
+}
+
+/// The coordinates of the grid points from `min` along an axis, accumulated
+/// step by step so neighbouring chunks sample exactly the same points.
+pub fn grid_coordinates(min: f32, max: f32, step: f32) -> Vec<f32> {
+    let mut coordinates = vec![min];
+    let mut coordinate = min;
+    while coordinate + step < max {
+        coordinate += step;
+        coordinates.push(coordinate);
+    }
+    coordinates
+}
+
+/// Whether the surface at `iso_value` passes between any of `values`. Chunks
+/// it doesn't cross have no cells to polygonise.
+pub fn crosses_surface(values: &[f32], iso_value: f32) -> bool {
+    let first_inside = values[0] < iso_value;
+    values.iter().any(|&value| (value < iso_value) != first_inside)
+}
+
+/// Samples `field` on the grid points, ordered by x, y then z, with the
+/// detail a grid of `step` can resolve.
+pub fn sample_grid<Field: ScalarField3>(
     field: &Field,
-    x: f32,
-    y: f32,
-    z: f32,
-    x_dx: f32,
-    y_dy: f32,
-    z_dz: f32,
-) -> [f32; 8] {
-    [
-        field.value_at(&Point3::new(x, y, z)),
-        field.value_at(&Point3::new(x_dx, y, z)),
-        field.value_at(&Point3::new(x_dx, y_dy, z)),
-        field.value_at(&Point3::new(x, y_dy, z)),
-        field.value_at(&Point3::new(x, y, z_dz)),
-        field.value_at(&Point3::new(x_dx, y, z_dz)),
-        field.value_at(&Point3::new(x_dx, y_dy, z_dz)),
-        field.value_at(&Point3::new(x, y_dy, z_dz)),
-    ]
+    step: f32,
+    xs: &[f32],
+    ys: &[f32],
+    zs: &[f32],
+) -> Vec<f32> {
+    let mut positions = Vec::with_capacity(xs.len() * ys.len() * zs.len());
+    for &x in xs.iter() {
+        for &y in ys.iter() {
+            for &z in zs.iter() {
+                positions.push(Point3::new(x, y, z));
+            }
+        }
+    }
+    let mut values = vec![0.0; positions.len()];
+    field.values_at_lod(&positions, &mut values, step);
+    values
 }
 
 #[inline]
@@ -306,7 +634,7 @@ fn find_cube_index(iso_value: f32, values_on_cube: [f32; 8]) -> usize {
 }
 
 #[inline]
-fn iso_value_interpolation(
+pub fn iso_value_interpolation(
     iso_value: f32,
     p1: f32,
     p2: f32,
@@ -403,9 +731,33 @@ impl<Scalar: Float + FromPrimitive> Iterator for Linspace<Scalar> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use nalgebra::{Dot, Norm, Point3, Vector3};
+
     use super::*;
     use super::Linspace;
-    use math::{ScalarField3, Vec3f};
+    use fields::FlatField;
+    use assets::Assets;
+    use gfx::density::GpuPlanetField;
+    use gfx::testing::{assert_closed, Sphere};
+    use planet::PlanetSpec;
+    use math::{CpuScalar, ScalarField3, Vec3f};
+
+    /// The test sphere, counting how many times it's sampled.
+    struct CountingSphere {
+        samples: AtomicUsize,
+    }
+
+    impl ScalarField3 for CountingSphere {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            self.samples.fetch_add(1, Ordering::SeqCst);
+            Sphere.value_at(position)
+        }
+
+        fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+            Sphere.gradient_at(position)
+        }
+    }
 
     #[test]
     fn test_linspace() {
@@ -424,8 +776,238 @@ mod tests {
         // let l1_elems: Vec<f32> = l1.collect();
         // assert_eq!(vec![10.0, -5.0, 0.0, 5.0, 10.0], l1_elems);
     }
+
+    #[test]
+    fn test_marching_cubes_plane() {
+        let field = FlatField::new(0.3);
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = marching_cubes(&field, &min, &max, 0.5, 0.0);
+        // 7 x 7 cells cross the plane, each with two triangles, sharing the
+        // vertices on the 8 x 8 edges through it.
+        assert_eq!(7 * 7 * 2 * 3, mesh.indices.len());
+        assert_eq!(8 * 8, mesh.vertices.len());
+        for vertex in mesh.vertices.iter() {
+            assert!((vertex.position[1] - 0.3).abs() < 1e-4);
+            assert!(vertex.normal[1].abs() > 0.99);
+        }
+    }
+
+    #[test]
+    fn test_marching_cubes_sphere() {
+        let field = CountingSphere { samples: AtomicUsize::new(0) };
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = marching_cubes(&field, &min, &max, 0.5, 0.0);
+        assert!(!mesh.indices.is_empty());
+        assert_eq!(8 * 8 * 8, field.samples.load(Ordering::SeqCst));
+
+        // Meshed in several parts, the sphere is still closed: every edge of
+        // a triangle is shared with exactly one other.
+        let samples_before = field.samples.load(Ordering::SeqCst);
+        let mesh = marching_cubes(&field, &min, &max, 0.1, 0.0);
+        assert_eq!(40 * 40 * 40, field.samples.load(Ordering::SeqCst) - samples_before);
+        assert_closed(&mesh);
+
+        // Nothing is polygonised inside the sphere.
+        let mesh = marching_cubes(&field, &(min * 0.25), &(max * 0.25), 0.1, 0.0);
+        assert!(mesh.vertices.is_empty() && mesh.indices.is_empty());
+    }
+
+    #[test]
+    fn test_marching_cubes_seams() {
+        let field = CountingSphere { samples: AtomicUsize::new(0) };
+        let fine_min = Vec3f::new(-2.0, -2.0, -2.0);
+        let fine_max = Vec3f::new(0.0625, 2.0625, 2.0625);
+        let coarse_min = Vec3f::new(0.0, -2.0, -2.0);
+        let coarse_max = Vec3f::new(2.125, 2.125, 2.125);
+        let coarse = marching_cubes(&field, &coarse_min, &coarse_max, 0.25, 0.0);
+
+        // The edges of the coarse mesh on the face it shares with the fine one.
+        let on_face = |vertex: &Vertex| vertex.position[0] == 0.0;
+        let mut segments = vec![];
+        for triangle in coarse.indices.chunks(3) {
+            for &(a, b) in &[(triangle[0], triangle[1]), (triangle[1], triangle[2]),
+                             (triangle[2], triangle[0])]
+            {
+                let (a, b) = (&coarse.vertices[a as usize], &coarse.vertices[b as usize]);
+                if on_face(a) && on_face(b) {
+                    segments.push((a.position, b.position));
+                }
+            }
+        }
+        assert!(!segments.is_empty());
+        let gap = |mesh: &Mesh<Vertex>| {
+            mesh.vertices.iter().filter(|vertex| on_face(vertex)).fold(0.0f32, |gap, vertex| {
+                let distance = segments.iter().fold(::std::f32::MAX, |distance, &(a, b)| {
+                    let (p, ab) = (vertex.position - a, b - a);
+                    let t = (p.dot(&ab) / ab.dot(&ab)).max(0.0).min(1.0);
+                    distance.min((p - ab * t).norm())
+                });
+                gap.max(distance)
+            })
+        };
+
+        // Meshed on its own the fine chunk leaves cracks, with the seam the
+        // vertices on the face are all on the coarse edges.
+        let fine = marching_cubes(&field, &fine_min, &fine_max, 0.125, 0.0);
+        assert!(gap(&fine) > 1e-3);
+        let seams = [0, 1, 0, 0, 0, 0];
+        let fine = marching_cubes_with_seams(&field, &fine_min, &fine_max, 0.125, 0.0, &seams);
+        assert!(gap(&fine) < 1e-5, "{}", gap(&fine));
+    }
+
+    /// The corners `marching_cubes.comp` is meant to find through a grid of
+    /// `values`, worked out on the CPU, in the order of the cells.
+    fn reference_cell_triangles(
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Vec<u32> {
+        let cube_corners = [
+            (0, 0, 0),
+            (1, 0, 0),
+            (1, 1, 0),
+            (0, 1, 0),
+            (0, 0, 1),
+            (1, 0, 1),
+            (1, 1, 1),
+            (0, 1, 1),
+        ];
+        let point = |i: usize, j: usize, k: usize| (i * shape[1] + j) * shape[2] + k;
+        let mut corners = vec![];
+        for i in 0..shape[0] - 1 {
+            for j in 0..shape[1] - 1 {
+                for k in 0..shape[2] - 1 {
+                    let mut cube_index = 0;
+                    for (corner, &(di, dj, dk)) in cube_corners.iter().enumerate() {
+                        if values[point(i + di, j + dj, k + dk)] < iso_value {
+                            cube_index |= 1 << corner;
+                        }
+                    }
+                    for &edge in TRIANGLE_TABLE[cube_index].iter() {
+                        if edge == -1 {
+                            break;
+                        }
+                        let (di, dj, dk, axis, _, _) = EDGE_LATTICE[edge as usize];
+                        corners.push((point(i + di, j + dj, k + dk) * 3 + axis) as u32);
+                    }
+                }
+            }
+        }
+        corners
+    }
+
+    /// The test sphere, with the triangles of its cells given by
+    /// `reference_cell_triangles` rather than a shader, to test meshing
+    /// through `gpu_cell_triangles` without a GPU.
+    struct GpuSphere;
+
+    impl ScalarField3 for GpuSphere {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            Sphere.value_at(position)
+        }
+
+        fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+            Sphere.gradient_at(position)
+        }
+
+        fn gpu_cell_triangles(
+            &self,
+            shape: [usize; 3],
+            values: &[CpuScalar],
+            iso_value: CpuScalar,
+        ) -> Option<Vec<u32>> {
+            Some(reference_cell_triangles(shape, values, iso_value))
+        }
+    }
+
+    #[test]
+    fn test_marching_cubes_gpu_cell_triangles() {
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let sphere = CountingSphere { samples: AtomicUsize::new(0) };
+        let cpu = marching_cubes(&sphere, &min, &max, 0.25, 0.0);
+        let gpu = marching_cubes(&GpuSphere, &min, &max, 0.25, 0.0);
+        assert!(!cpu.indices.is_empty());
+        assert_eq!(cpu.vertices.len(), gpu.vertices.len());
+
+        // The same triangles, though in another order and with the vertices
+        // numbered differently.
+        let triangles = |mesh: &Mesh<Vertex>| {
+            let mut triangles: Vec<Vec<[u32; 3]>> = mesh.indices
+                .chunks(3)
+                .map(|triangle| {
+                    let corners: Vec<[u32; 3]> = triangle
+                        .iter()
+                        .map(|&index| {
+                            let position = mesh.vertices[index as usize].position;
+                            [position[0].to_bits(), position[1].to_bits(), position[2].to_bits()]
+                        })
+                        .collect();
+                    let first = (0..3).min_by_key(|&corner| corners[corner]).unwrap();
+                    (0..3).map(|corner| corners[(first + corner) % 3]).collect()
+                })
+                .collect();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(triangles(&cpu), triangles(&gpu));
+    }
+
+    /// Runs the shader itself, so it needs an OpenGL 4.3 context and is only
+    /// run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn test_marching_cubes_shader_finds_the_reference_triangles() {
+        let assets = Assets::from_default_location();
+        let field = GpuPlanetField::new(0, PlanetSpec::default(), &assets, true).unwrap();
+        let coordinates = grid_coordinates(-2.0, 2.0, 0.25);
+        let values = sample_grid(&Sphere, 0.25, &coordinates, &coordinates, &coordinates);
+        let shape = [coordinates.len(); 3];
+        let gpu = field
+            .gpu_cell_triangles(shape, &values, 0.0)
+            .expect("The marching cubes shader is unavailable.");
+        let reference = reference_cell_triangles(shape, &values, 0.0);
+        assert!(!reference.is_empty());
+        // The cells append their triangles in any order.
+        let sorted_triangles = |corners: &[u32]| {
+            let mut triangles: Vec<Vec<u32>> =
+                corners.chunks(3).map(|triangle| triangle.to_vec()).collect();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(sorted_triangles(&reference), sorted_triangles(&gpu));
+    }
 }
 
+// Slabs of cells each part of a chunk has at least, when meshed in parallel.
+const SLABS_PER_PART: usize = 8;
+// No face of the box is next to a coarser mesh.
+pub const NO_SEAMS: Seams = [0; 6];
+// The most levels coarser a seam is stitched to, 32 cells across a chunk.
+const MAX_SEAM_LEVELS: u8 = 5;
+// Marks the edges whose vertex hasn't been made yet.
+const NO_VERTEX: u32 = ::std::u32::MAX;
+
+// For each edge of a cell, the grid point it starts from, as offsets from the
+// cell's first corner, its axis and the corners at its start and end.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const EDGE_LATTICE: [(usize, usize, usize, usize, usize, usize); 12] = [
+    (0, 0, 0, 0, 0, 1),
+    (1, 0, 0, 1, 1, 2),
+    (0, 1, 0, 0, 3, 2),
+    (0, 0, 0, 1, 0, 3),
+    (0, 0, 1, 0, 4, 5),
+    (1, 0, 1, 1, 5, 6),
+    (0, 1, 1, 0, 7, 6),
+    (0, 0, 1, 1, 4, 7),
+    (0, 0, 0, 2, 0, 4),
+    (1, 0, 0, 2, 1, 5),
+    (1, 1, 0, 2, 2, 6),
+    (0, 1, 0, 2, 3, 7),
+];
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const EDGE_TABLE: [u16; 256] =
     [0x000, 0x109, 0x203, 0x30a, 0x406, 0x50f, 0x605, 0x70c, 0x80c, 0x905, 0xa0f, 0xb06, 0xc0a,
@@ -1,15 +1,19 @@
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::io::Write;
 use std::iter::FromIterator;
 use std::mem::size_of;
+use byteorder::{LittleEndian, WriteBytesExt};
 use glium::vertex::{self, Attribute, AttributeType, VertexFormat};
-use nalgebra::{Cross, Norm};
+use ncollide::bounding_volume::{AABB, BoundingSphere};
+use nalgebra::{Cross, Norm, Point3, Vector3};
 use num::Zero;
 use wavefront_obj::obj as wavefront_obj;
 
 use errors::*;
 use utils::read_utf8_file;
-use math::{GpuScalar, Vec2f, Vec3f};
+use math::{GpuScalar, Material, Vec3f};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct PlainVertex {
@@ -97,6 +101,12 @@ pub struct BarycentricVertex {
     pub position: Vec3f,
     pub normal: Vec3f,
     pub bary_coord: Vec3f,
+    /// A `Material` as a float, the same for the three corners of a triangle.
+    pub material: GpuScalar,
+    /// The `stratum_at` the vertex, negative if the rock isn't layered.
+    pub stratum: GpuScalar,
+    /// How much of the sky the vertex sees, 1 where nothing is in the way.
+    pub occlusion: GpuScalar,
 }
 
 impl NormalVertex for BarycentricVertex {
@@ -109,7 +119,15 @@ impl NormalVertex for BarycentricVertex {
     }
 }
 
-implement_vertex!(BarycentricVertex, position, normal, bary_coord);
+implement_vertex!(
+    BarycentricVertex,
+    position,
+    normal,
+    bary_coord,
+    material,
+    stratum,
+    occlusion
+);
 
 #[inline]
 pub fn triangle_normal(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec3f {
@@ -120,9 +138,36 @@ pub fn triangle_normal(v1: &Vertex, v2: &Vertex, v3: &Vertex) -> Vec3f {
     )
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct TexVertex {
-    pub uv: Vec2f,
+impl Mesh<BarycentricVertex> {
+    /// Sets the material of every triangle to `material_at` its centroid.
+    pub fn paint_triangles<F: Fn(&Vec3f) -> Material>(&mut self, material_at: F) {
+        for triangle in self.indices.chunks(3) {
+            let centroid = (self.vertices[triangle[0] as usize].position +
+                                self.vertices[triangle[1] as usize].position +
+                                self.vertices[triangle[2] as usize].position) /
+                3.0;
+            let material = material_at(&centroid) as u8 as GpuScalar;
+            for &index in triangle.iter() {
+                self.vertices[index as usize].material = material;
+            }
+        }
+    }
+
+    /// Sets the stratum of every vertex to `stratum_at` its position.
+    pub fn paint_strata<F: Fn(&Vec3f) -> Option<f32>>(&mut self, stratum_at: F) {
+        for vertex in self.vertices.iter_mut() {
+            vertex.stratum = stratum_at(&vertex.position).unwrap_or(NO_STRATUM);
+        }
+    }
+
+    /// Sets the occlusion of every vertex made by `with_barycentric_coordinates`
+    /// from a mesh with `indices` to the `occlusion` of the vertex it was made
+    /// from.
+    pub fn paint_occlusion(&mut self, indices: &[u32], occlusion: &[f32]) {
+        for (vertex, &index) in self.vertices.iter_mut().zip(indices.iter()) {
+            vertex.occlusion = occlusion[index as usize];
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -132,7 +177,262 @@ pub struct Mesh<V: NormalVertex> {
     pub indices: Vec<u32>,
 }
 
+impl<V: NormalVertex> Mesh<V> {
+    /// The smallest box aligned with the axes around the vertices, None if
+    /// there are none.
+    pub fn aabb(&self) -> Option<AABB<Point3<GpuScalar>>> {
+        let mut vertices = self.vertices.iter();
+        let first = match vertices.next() {
+            Some(vertex) => *vertex.position(),
+            None => return None,
+        };
+        let (mut min, mut max) = (first, first);
+        for vertex in vertices {
+            let position = vertex.position();
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+        Some(AABB::new(min.to_point(), max.to_point()))
+    }
+
+    /// A sphere around the vertices, centred on their `aabb`. None if there
+    /// are none.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere<Point3<GpuScalar>>> {
+        self.aabb().map(|aabb| {
+            let centre = aabb.center();
+            let radius = self.vertices.iter().fold(0.0, |radius: GpuScalar, vertex| {
+                radius.max((vertex.position().to_point() - centre).norm_squared())
+            });
+            BoundingSphere::new(centre, radius.sqrt())
+        })
+    }
+}
+
+impl<V: NormalVertex + Clone> Mesh<V> {
+    /// One mesh with the triangles of all of `meshes`, named after the first.
+    pub fn merge(meshes: &[Mesh<V>]) -> Self {
+        let mut merged = Mesh {
+            name: meshes.first().map_or(String::new(), |mesh| mesh.name.clone()),
+            vertices: Vec::with_capacity(meshes.iter().map(|mesh| mesh.vertices.len()).sum()),
+            indices: Vec::with_capacity(meshes.iter().map(|mesh| mesh.indices.len()).sum()),
+        };
+        for mesh in meshes.iter() {
+            merged.append(mesh);
+        }
+        merged
+    }
+
+    /// Adds the vertices and triangles of `other` after those of the mesh.
+    pub fn append(&mut self, other: &Mesh<V>) {
+        let offset = self.vertices.len() as u32;
+        let start = self.indices.len();
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices.extend_from_slice(&other.indices);
+        rebase_indices(&mut self.indices[start..], offset);
+    }
+}
+
+/// Shifts `indices` into the vertices of a mesh to refer to the same vertices
+/// after `offset` other ones were put before them.
+pub fn rebase_indices(indices: &mut [u32], offset: u32) {
+    for index in indices.iter_mut() {
+        *index += offset;
+    }
+}
+
 impl Mesh<Vertex> {
+    /// A sphere centred at the origin made by splitting each triangle of an
+    /// icosahedron into four, `subdivisions` times, giving `20 * 4^subdivisions`
+    /// triangles. Triangles are counter-clockwise when seen from outside.
+    pub fn icosphere(radius: GpuScalar, subdivisions: u32) -> Self {
+        let t = (1.0 + (5.0 as GpuScalar).sqrt()) / 2.0;
+        let mut positions: Vec<Vec3f> = vec![
+            Vec3f::new(-1.0, t, 0.0),
+            Vec3f::new(1.0, t, 0.0),
+            Vec3f::new(-1.0, -t, 0.0),
+            Vec3f::new(1.0, -t, 0.0),
+            Vec3f::new(0.0, -1.0, t),
+            Vec3f::new(0.0, 1.0, t),
+            Vec3f::new(0.0, -1.0, -t),
+            Vec3f::new(0.0, 1.0, -t),
+            Vec3f::new(t, 0.0, -1.0),
+            Vec3f::new(t, 0.0, 1.0),
+            Vec3f::new(-t, 0.0, -1.0),
+            Vec3f::new(-t, 0.0, 1.0),
+        ].into_iter()
+            .map(|position| Vec3f::from(position.normalize()))
+            .collect();
+        let mut triangles: Vec<[u32; 3]> = ICOSAHEDRON_TRIANGLES.iter().cloned().collect();
+
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vec3f>| {
+                let key = if a < b { (a, b) } else { (b, a) };
+                *midpoints.entry(key).or_insert_with(|| {
+                    let middle = positions[a as usize] + positions[b as usize];
+                    positions.push(Vec3f::from(middle.normalize()));
+                    positions.len() as u32 - 1
+                })
+            };
+            let mut subdivided = Vec::with_capacity(triangles.len() * 4);
+            for &[a, b, c] in triangles.iter() {
+                let ab = midpoint(a, b, &mut positions);
+                let bc = midpoint(b, c, &mut positions);
+                let ca = midpoint(c, a, &mut positions);
+                subdivided.push([a, ab, ca]);
+                subdivided.push([b, bc, ab]);
+                subdivided.push([c, ca, bc]);
+                subdivided.push([ab, bc, ca]);
+            }
+            triangles = subdivided;
+        }
+
+        Mesh {
+            name: "icosphere".to_owned(),
+            vertices: positions
+                .into_iter()
+                .map(|direction| {
+                    Vertex {
+                        position: direction * radius,
+                        normal: direction,
+                    }
+                })
+                .collect(),
+            indices: triangles
+                .into_iter()
+                .flat_map(|triangle| triangle.to_vec().into_iter())
+                .collect(),
+        }
+    }
+
+    /// Merges the vertices closer together than `epsilon` into the first of
+    /// them, like the copies along the faces of neighbouring chunks or those
+    /// of imported meshes with split normals, dropping the triangles left
+    /// with two corners in one vertex. With an `epsilon` of 0 only vertices at
+    /// the same position merge. The normals are then smoothed, each being the
+    /// average of those of its triangles weighted by their areas.
+    pub fn weld(&mut self, epsilon: GpuScalar) {
+        // Vertices to merge are in neighbouring cells `epsilon` across, or in
+        // the same cell keyed by the bits of their position.
+        let cell = |position: &Vec3f| -> [i64; 3] {
+            let mut key = [0; 3];
+            for axis in 0..3 {
+                key[axis] = if epsilon > 0.0 {
+                    (position[axis] / epsilon).floor() as i64
+                } else {
+                    // Adding 0 turns the negative zeros positive, so they match.
+                    (position[axis] + 0.0).to_bits() as i64
+                };
+            }
+            key
+        };
+        let reach = if epsilon > 0.0 { 1 } else { 0 };
+        let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+        let mut vertices: Vec<Vertex> = vec![];
+        let mut remap = Vec::with_capacity(self.vertices.len());
+        for vertex in self.vertices.iter() {
+            let key = cell(&vertex.position);
+            let mut welded = None;
+            'search: for dx in -reach..reach + 1 {
+                for dy in -reach..reach + 1 {
+                    for dz in -reach..reach + 1 {
+                        let neighbour = [key[0] + dx, key[1] + dy, key[2] + dz];
+                        for &other in cells.get(&neighbour).map_or(&[][..], |cell| &cell[..]) {
+                            let offset = vertices[other as usize].position - vertex.position;
+                            if offset.norm() <= epsilon {
+                                welded = Some(other);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            remap.push(match welded {
+                Some(index) => index,
+                None => {
+                    vertices.push(*vertex);
+                    let index = vertices.len() as u32 - 1;
+                    cells.entry(key).or_insert_with(Vec::new).push(index);
+                    index
+                }
+            });
+        }
+
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for triangle in self.indices.chunks(3) {
+            let (a, b, c) = (
+                remap[triangle[0] as usize],
+                remap[triangle[1] as usize],
+                remap[triangle[2] as usize],
+            );
+            if a != b && b != c && c != a {
+                indices.extend_from_slice(&[a, b, c]);
+            }
+        }
+        let mut normals = vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()];
+        for triangle in indices.chunks(3) {
+            let (a, b, c) = (
+                vertices[triangle[0] as usize].position,
+                vertices[triangle[1] as usize].position,
+                vertices[triangle[2] as usize].position,
+            );
+            // Twice the area of the triangle long.
+            let normal = (b - a).cross(&(c - a));
+            for &index in triangle.iter() {
+                normals[index as usize] = normals[index as usize] + normal;
+            }
+        }
+        for (vertex, normal) in vertices.iter_mut().zip(normals.into_iter()) {
+            let length = normal.norm();
+            if length > 0.0 {
+                vertex.normal = Vec3f::from(normal / length);
+            }
+        }
+        self.vertices = vertices;
+        self.indices = indices;
+    }
+
+    /// Hangs a strip `length` long from each edge only one triangle has, the
+    /// borders of a chunk's mesh, along the normals at its ends (which point
+    /// into the ground). Where the meshes of neighbouring chunks don't quite
+    /// meet, the gap shows the strip rather than the sky through it.
+    pub fn add_skirts(&mut self, length: GpuScalar) {
+        let mut edges = HashMap::new();
+        for triangle in self.indices.chunks(3) {
+            for &(a, b) in &[(triangle[0], triangle[1]), (triangle[1], triangle[2]),
+                             (triangle[2], triangle[0])]
+            {
+                edges.entry((a.min(b), a.max(b))).or_insert((a, b, 0)).2 += 1;
+            }
+        }
+        let mut border: Vec<(u32, u32)> = edges
+            .values()
+            .filter(|&&(_, _, count)| count == 1)
+            .map(|&(a, b, _)| (a, b))
+            .collect();
+        border.sort();
+
+        let mut bottoms = HashMap::new();
+        for (a, b) in border {
+            let mut bottom = |top: u32, vertices: &mut Vec<Vertex>| {
+                *bottoms.entry(top).or_insert_with(|| {
+                    let vertex = vertices[top as usize];
+                    vertices.push(Vertex {
+                        position: vertex.position + vertex.normal * length,
+                        normal: vertex.normal,
+                    });
+                    vertices.len() as u32 - 1
+                })
+            };
+            let a_bottom = bottom(a, &mut self.vertices);
+            let b_bottom = bottom(b, &mut self.vertices);
+            // Wound like the triangle above, which has the edge the other way.
+            self.indices.extend_from_slice(&[b, a, a_bottom, b, a_bottom, b_bottom]);
+        }
+    }
+
     pub fn with_barycentric_coordinates(self) -> Mesh<BarycentricVertex> {
         // TODO(mcobzarenco): This doesn't work if the vertices are used by more
         // than one triangle. Does it become a coloring problem then?
@@ -164,18 +464,27 @@ impl Mesh<Vertex> {
                 position: self.vertices[a].position,
                 normal: self.vertices[a].normal,
                 bary_coord: Vec3f::new(0.0, 0.0, 1.0),
+                material: Material::Rock as u8 as GpuScalar,
+                stratum: NO_STRATUM,
+                occlusion: 1.0,
             });
             bary_indices.push(bary_vertices.len() as u32);
             bary_vertices.push(BarycentricVertex {
                 position: self.vertices[b].position,
                 normal: self.vertices[b].normal,
                 bary_coord: Vec3f::new(0.0, 1.0, 0.0),
+                material: Material::Rock as u8 as GpuScalar,
+                stratum: NO_STRATUM,
+                occlusion: 1.0,
             });
             bary_indices.push(bary_vertices.len() as u32);
             bary_vertices.push(BarycentricVertex {
                 position: self.vertices[c].position,
                 normal: self.vertices[c].normal,
                 bary_coord: Vec3f::new(1.0, 0.0, 0.0),
+                material: Material::Rock as u8 as GpuScalar,
+                stratum: NO_STRATUM,
+                occlusion: 1.0,
             });
         }
 
@@ -242,6 +551,121 @@ pub fn load_mesh_from_str(mesh_raw: String) -> Result<Vec<Mesh<Vertex>>> {
     )
 }
 
+/// Writes `mesh` as an object of an OBJ file, after `offset` vertices of the
+/// objects before it, which its faces count from. Given `materials`, one for
+/// each triangle, the triangles are grouped by them.
+pub fn write_obj_object<V, W>(
+    mesh: &Mesh<V>,
+    offset: u32,
+    materials: &[&str],
+    writer: &mut W,
+) -> ::std::io::Result<()>
+where
+    V: NormalVertex,
+    W: Write,
+{
+    if !mesh.name.is_empty() {
+        try!(writeln!(writer, "o {}", mesh.name));
+    }
+    for vertex in mesh.vertices.iter() {
+        let position = vertex.position();
+        try!(writeln!(writer, "v {} {} {}", position[0], position[1], position[2]));
+    }
+    for vertex in mesh.vertices.iter() {
+        let normal = vertex.normal();
+        try!(writeln!(writer, "vn {} {} {}", normal[0], normal[1], normal[2]));
+    }
+    let mut triangles: Vec<usize> = (0..mesh.indices.len() / 3).collect();
+    if !materials.is_empty() {
+        triangles.sort_by_key(|&triangle| materials[triangle]);
+    }
+    for (i, &triangle) in triangles.iter().enumerate() {
+        if !materials.is_empty() && (i == 0 || materials[triangles[i - 1]] != materials[triangle]) {
+            try!(writeln!(writer, "usemtl {}", materials[triangle]));
+        }
+        // OBJ indices are 1-based.
+        let face = &mesh.indices[triangle * 3..triangle * 3 + 3];
+        let (a, b, c) = (face[0] + offset + 1, face[1] + offset + 1, face[2] + offset + 1);
+        try!(writeln!(writer, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c));
+    }
+    Ok(())
+}
+
+/// Writes `mesh` to `writer` as binary STL. The normal of each triangle is
+/// worked out from its corners, which STL expects counter-clockwise seen from
+/// outside the solid.
+pub fn write_mesh_to_stl<V, W>(mesh: &Mesh<V>, writer: &mut W) -> Result<()>
+where
+    V: NormalVertex,
+    W: Write,
+{
+    write_stl_triangles(mesh, writer).chain_err(|| "Could not write STL.")
+}
+
+fn write_stl_triangles<V, W>(mesh: &Mesh<V>, writer: &mut W) -> ::std::io::Result<()>
+where
+    V: NormalVertex,
+    W: Write,
+{
+    // Any header will do, as long as it doesn't start like an ASCII STL.
+    let mut header = [0u8; STL_HEADER_LEN];
+    for (byte, &name_byte) in header.iter_mut().zip(b"binary STL: ".iter().chain(
+        mesh.name.as_bytes(),
+    ))
+    {
+        *byte = name_byte;
+    }
+    try!(writer.write_all(&header));
+    try!(writer.write_u32::<LittleEndian>((mesh.indices.len() / 3) as u32));
+    for face in mesh.indices.chunks(3) {
+        let (a, b, c) = (
+            *mesh.vertices[face[0] as usize].position(),
+            *mesh.vertices[face[1] as usize].position(),
+            *mesh.vertices[face[2] as usize].position(),
+        );
+        let normal = (b - a).cross(&(c - a));
+        let length = normal.norm();
+        let normal = if length > 0.0 { normal / length } else { normal };
+        for &value in normal.as_ref().iter().chain(a.as_ref()).chain(b.as_ref()).chain(
+            c.as_ref(),
+        )
+        {
+            try!(writer.write_f32::<LittleEndian>(value));
+        }
+        // The attribute byte count, which no one uses.
+        try!(writer.write_u16::<LittleEndian>(0));
+    }
+    writer.flush()
+}
+
+// The `stratum` of vertices where the rock isn't layered.
+const NO_STRATUM: GpuScalar = -1.0;
+// Bytes of the header of a binary STL, before the number of triangles.
+pub const STL_HEADER_LEN: usize = 80;
+
+const ICOSAHEDRON_TRIANGLES: [[u32; 3]; 20] = [
+    [0, 11, 5],
+    [0, 5, 1],
+    [0, 1, 7],
+    [0, 7, 10],
+    [0, 10, 11],
+    [1, 5, 9],
+    [5, 11, 4],
+    [11, 10, 2],
+    [10, 7, 6],
+    [7, 1, 8],
+    [3, 9, 4],
+    [3, 4, 2],
+    [3, 2, 6],
+    [3, 6, 8],
+    [3, 8, 9],
+    [4, 9, 5],
+    [2, 4, 11],
+    [6, 2, 10],
+    [8, 6, 7],
+    [9, 8, 1],
+];
+
 unsafe impl Attribute for Vec3f {
     fn get_type() -> AttributeType {
         AttributeType::F32F32F32
@@ -253,4 +677,183 @@ mod tests {
 
     #[test]
     fn test_triangle_normal() {}
+
+    #[test]
+    fn test_merge() {
+        use nalgebra::Vector3;
+
+        let sphere = Mesh::icosphere(1.0, 1);
+        let mut moved = sphere.clone();
+        for vertex in moved.vertices.iter_mut() {
+            vertex.position = Vec3f::from(*vertex.position + Vector3::new(5.0, 0.0, 0.0));
+        }
+        let merged = Mesh::merge(&[sphere.clone(), moved]);
+        assert_eq!(sphere.vertices.len() * 2, merged.vertices.len());
+        assert_eq!(sphere.indices.len() * 2, merged.indices.len());
+        let (first, second) = merged.indices.split_at(sphere.indices.len());
+        for (&a, &b) in first.iter().zip(second.iter()) {
+            assert_eq!(a + sphere.vertices.len() as u32, b);
+            let (a, b) = (&merged.vertices[a as usize], &merged.vertices[b as usize]);
+            assert!((*b.position - *a.position - Vector3::new(5.0, 0.0, 0.0)).norm() < 1e-5);
+            assert!((*b.normal - *a.normal).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_bounding_volumes() {
+        use nalgebra::Vector3;
+
+        let mut sphere = Mesh::icosphere(2.0, 2);
+        for vertex in sphere.vertices.iter_mut() {
+            vertex.position = Vec3f::from(*vertex.position + Vector3::new(1.0, 2.0, 3.0));
+        }
+        let aabb = sphere.aabb().unwrap();
+        for axis in 0..3 {
+            let centre = (axis + 1) as f32;
+            assert!((aabb.mins()[axis] - (centre - 2.0)).abs() < 1e-5);
+            assert!((aabb.maxs()[axis] - (centre + 2.0)).abs() < 1e-5);
+        }
+        let bounding_sphere = sphere.bounding_sphere().unwrap();
+        assert!((bounding_sphere.center()[2] - 3.0).abs() < 1e-5);
+        assert!((bounding_sphere.radius() - 2.0).abs() < 1e-5);
+
+        let empty: Mesh<Vertex> = Mesh {
+            name: String::new(),
+            vertices: vec![],
+            indices: vec![],
+        };
+        assert!(empty.aabb().is_none() && empty.bounding_sphere().is_none());
+    }
+
+    #[test]
+    fn test_weld() {
+        use nalgebra::Dot;
+
+        // Every triangle of the sphere with its own corners, a little apart
+        // and with the normals of the faces.
+        let sphere = Mesh::icosphere(2.0, 2);
+        let mut split = Mesh {
+            name: sphere.name.clone(),
+            vertices: vec![],
+            indices: vec![],
+        };
+        for (i, triangle) in sphere.indices.chunks(3).enumerate() {
+            let corners: Vec<Vertex> =
+                triangle.iter().map(|&index| sphere.vertices[index as usize]).collect();
+            let normal = (corners[1].position - corners[0].position)
+                .cross(&(corners[2].position - corners[0].position))
+                .normalize();
+            for (j, corner) in corners.iter().enumerate() {
+                let jitter = ((i * 3 + j) % 7) as f32 * 1e-5;
+                split.indices.push(split.vertices.len() as u32);
+                split.vertices.push(Vertex {
+                    position: corner.position + jitter,
+                    normal: Vec3f::from(normal),
+                });
+            }
+        }
+
+        let mut exact = split.clone();
+        exact.weld(0.0);
+        assert!(exact.vertices.len() > sphere.vertices.len());
+        split.weld(1e-3);
+        assert_eq!(sphere.vertices.len(), split.vertices.len());
+        assert_eq!(sphere.indices.len(), split.indices.len());
+        for vertex in split.vertices.iter() {
+            let radial = vertex.position.normalize();
+            assert!(radial.dot(&vertex.normal) > 0.999, "{:?}", vertex);
+        }
+
+        // Welding a triangle's corners together drops it.
+        let mut sliver = split.clone();
+        sliver.weld(10.0);
+        assert!(sliver.indices.is_empty());
+    }
+
+    #[test]
+    fn test_icosphere() {
+        use nalgebra::Dot;
+
+        let sphere = Mesh::icosphere(2.0, 2);
+        assert_eq!(162, sphere.vertices.len());
+        assert_eq!(320 * 3, sphere.indices.len());
+        for vertex in sphere.vertices.iter() {
+            assert!((vertex.position.norm() - 2.0).abs() < 1e-5);
+            assert!((vertex.normal.norm() - 1.0).abs() < 1e-5);
+        }
+        // Counter-clockwise seen from outside means the face normal points
+        // away from the centre.
+        for triangle in sphere.indices.chunks(3) {
+            let (a, b, c) = (
+                &sphere.vertices[triangle[0] as usize],
+                &sphere.vertices[triangle[1] as usize],
+                &sphere.vertices[triangle[2] as usize],
+            );
+            assert!(triangle_normal(a, b, c).dot(&*a.position) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_add_skirts() {
+        let vertex = |x, z| {
+            Vertex {
+                position: Vec3f::new(x, 0.0, z),
+                normal: Vec3f::new(0.0, -1.0, 0.0),
+            }
+        };
+        let mut mesh = Mesh {
+            name: "square".to_owned(),
+            vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0)],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        };
+        mesh.add_skirts(0.5);
+        // A quad down from each of the four sides, their bottom corners
+        // shared.
+        assert_eq!(8, mesh.vertices.len());
+        assert_eq!((2 + 4 * 2) * 3, mesh.indices.len());
+        for vertex in mesh.vertices[4..].iter() {
+            assert_eq!(-0.5, vertex.position[1]);
+        }
+        // The top edges now have a triangle either side, each one the other
+        // way round.
+        let mut edges = HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for &(a, b) in &[(triangle[0], triangle[1]), (triangle[1], triangle[2]),
+                             (triangle[2], triangle[0])]
+            {
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        for &(a, b) in edges.keys() {
+            if a < 4 && b < 4 {
+                assert_eq!(Some(&1), edges.get(&(b, a)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_obj_object() {
+        let vertex = |x, y, z| {
+            Vertex {
+                position: Vec3f::new(x, y, z),
+                normal: Vec3f::new(0.0, 0.0, 1.0),
+            }
+        };
+        let mesh = Mesh {
+            name: "triangle".to_owned(),
+            vertices: vec![vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0), vertex(0.0, 1.0, 0.0)],
+            indices: vec![0, 1, 2],
+        };
+        let mut obj = vec![];
+        write_obj_object(&mesh, 0, &[], &mut obj).unwrap();
+
+        let meshes = load_mesh_from_str(String::from_utf8(obj).unwrap()).unwrap();
+        assert_eq!(1, meshes.len());
+        assert_eq!(mesh.name, meshes[0].name);
+        assert_eq!(mesh.vertices, meshes[0].vertices);
+        // The OBJ parser may rotate the corners of a triangle, but it must
+        // preserve its winding.
+        let indices = &meshes[0].indices;
+        assert!((0..3).any(|r| (0..3).all(|i| indices[(i + r) % 3] == mesh.indices[i])));
+    }
 }
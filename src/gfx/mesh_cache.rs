@@ -0,0 +1,336 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use edit::spec_hash;
+use erosion::ErosionSpec;
+use errors::{ChainErr, Result};
+use math::Mesher;
+use planet::PlanetSpec;
+use super::lod::ChunkId;
+use super::marching_cubes::Seams;
+use super::mesh::{Mesh, Vertex};
+use super::mesh_format::{decode_mesh, encode_mesh, MeshCompression};
+
+/// The directory the chunk meshes are cached in across runs, in a cache per
+/// body of each world. Worlds are told apart like by `WorldDir`, by their
+/// field, seed and specs.
+#[derive(Clone, Debug)]
+pub struct CacheDir {
+    directory: PathBuf,
+    field_name: String,
+}
+
+impl CacheDir {
+    pub fn new<P: Into<PathBuf>>(directory: P, field_name: &str) -> Self {
+        CacheDir {
+            directory: directory.into(),
+            field_name: field_name.to_owned(),
+        }
+    }
+
+    /// Opens the cache of the body generated from `seed`, `planet` and
+    /// `erosion`, with chunks meshed on grids of `chunk_num_steps` and ids on
+    /// a grid of `voxel_density`, creating it if missing, which keeps at most
+    /// `capacity` bytes of meshes. Bodies other than the main planet are also
+    /// told apart by their name.
+    pub fn open_mesh_cache(
+        &self,
+        body_name: Option<&str>,
+        seed: u32,
+        planet: &PlanetSpec,
+        erosion: &ErosionSpec,
+        chunk_num_steps: f32,
+        voxel_density: f32,
+        capacity: u64,
+    ) -> Result<MeshCache> {
+        let body_prefix = body_name.map_or_else(String::new, |name| format!("{}-", name));
+        let directory = self.directory.join(format!(
+            "{}-{}{}-{:016x}-{}",
+            self.field_name,
+            body_prefix,
+            seed,
+            spec_hash(planet, erosion),
+            chunk_num_steps
+        ));
+        MeshCache::open(directory, voxel_density, capacity)
+    }
+}
+
+/// Meshes of chunks, before they're decimated or painted, in a file each.
+/// The least recently used files are deleted to keep them under the
+/// capacity. It's shared by the workers meshing the chunks and a failure to
+/// read or write it only costs meshing the chunk again, so it's logged
+/// rather than returned.
+pub struct MeshCache {
+    directory: PathBuf,
+    voxel_density: f32,
+    capacity: u64,
+    index: Mutex<CacheIndex>,
+}
+
+struct CacheIndex {
+    // The size of each file and when it was last used, by `clock`.
+    files: HashMap<String, (u64, u64)>,
+    // The files by when they were last used, the least recently first.
+    by_last_use: BTreeMap<u64, String>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl CacheIndex {
+    /// Marks the file `name` as just used, returning whether it's cached.
+    fn touch(&mut self, name: &str) -> bool {
+        let clock = self.clock + 1;
+        let last_used = match self.files.get_mut(name) {
+            Some(&mut (_, ref mut last_used)) => last_used,
+            None => return false,
+        };
+        let name = self.by_last_use.remove(last_used).unwrap();
+        *last_used = clock;
+        self.by_last_use.insert(clock, name);
+        self.clock = clock;
+        true
+    }
+
+    /// Adds the file `name` of `size` bytes as just used, replacing any
+    /// entry it had.
+    fn insert(&mut self, name: String, size: u64) {
+        self.remove(&name);
+        self.clock += 1;
+        self.files.insert(name.clone(), (size, self.clock));
+        self.by_last_use.insert(self.clock, name);
+        self.total_size += size;
+    }
+
+    /// Removes the file `name`, returning its size if it was cached.
+    fn remove(&mut self, name: &str) -> Option<u64> {
+        self.files.remove(name).map(|(size, last_used)| {
+            self.by_last_use.remove(&last_used);
+            self.total_size -= size;
+            size
+        })
+    }
+
+    /// Removes the least recently used file, returning its name.
+    fn remove_oldest(&mut self) -> Option<String> {
+        let oldest = self.by_last_use.keys().next().cloned();
+        oldest.map(|last_used| {
+            let name = self.by_last_use.remove(&last_used).unwrap();
+            let (size, _) = self.files.remove(&name).unwrap();
+            self.total_size -= size;
+            name
+        })
+    }
+}
+
+impl MeshCache {
+    pub fn open(directory: PathBuf, voxel_density: f32, capacity: u64) -> Result<Self> {
+        try!(fs::create_dir_all(&directory).chain_err(|| {
+            format!("Could not create mesh cache directory {:?}", directory)
+        }));
+        let entries = try!(fs::read_dir(&directory).chain_err(|| {
+            format!("Could not read mesh cache directory {:?}", directory)
+        }));
+        // Reading a file doesn't update when it was modified, so the files
+        // from earlier runs are taken to be used in the order they were
+        // written.
+        let mut files = vec![];
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(TEMPORARY_EXTENSION) {
+                // Left behind by a run which died while writing it.
+                let _ = fs::remove_file(entry.path());
+            } else if name.ends_with(MESH_EXTENSION) {
+                if let Ok(metadata) = entry.metadata() {
+                    let modified = metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |modified| modified.as_secs());
+                    files.push((modified, name, metadata.len()));
+                }
+            }
+        }
+        files.sort();
+
+        let mut index = CacheIndex {
+            files: HashMap::with_capacity(files.len()),
+            by_last_use: BTreeMap::new(),
+            total_size: 0,
+            clock: 0,
+        };
+        for (_, name, size) in files.into_iter() {
+            index.insert(name, size);
+        }
+        info!(
+            "Opened mesh cache {:?} with {} meshes in {} bytes.",
+            directory,
+            index.files.len(),
+            index.total_size
+        );
+        let cache = MeshCache {
+            directory: directory,
+            voxel_density: voxel_density,
+            capacity: capacity,
+            index: Mutex::new(index),
+        };
+        cache.evict(&mut cache.index.lock().unwrap());
+        Ok(cache)
+    }
+
+    /// The mesh of the chunk with `chunk_id` meshed with `mesher` and
+    /// `seams`, if it's cached. Empty chunks are cached as empty meshes.
+    pub fn get(&self, chunk_id: &ChunkId, mesher: Mesher, seams: &Seams) -> Option<Mesh<Vertex>> {
+        let name = file_name(chunk_id, self.voxel_density, mesher, seams);
+        if !self.index.lock().unwrap().touch(&name) {
+            return None;
+        }
+
+        let path = self.directory.join(&name);
+        let mut data = vec![];
+        let decoded = File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|err| err.to_string())
+            .and_then(|_| decode_mesh(&data).map_err(|err| err.to_string()));
+        match decoded {
+            Ok(mesh) => Some(mesh),
+            Err(message) => {
+                warn!("Dropping cached mesh {:?}: {}", path, message);
+                let _ = fs::remove_file(&path);
+                self.index.lock().unwrap().remove(&name);
+                None
+            }
+        }
+    }
+
+    /// Caches `mesh` as that of the chunk with `chunk_id` meshed with
+    /// `mesher` and `seams`, replacing any it had.
+    pub fn insert(&self, chunk_id: &ChunkId, mesher: Mesher, seams: &Seams, mesh: &Mesh<Vertex>) {
+        let name = file_name(chunk_id, self.voxel_density, mesher, seams);
+        let data = encode_mesh(mesh, MeshCompression::Deflate);
+        let path = self.directory.join(&name);
+        // Written aside and moved in place, so a reader never sees half a file.
+        let temporary = {
+            let mut index = self.index.lock().unwrap();
+            index.clock += 1;
+            self.directory.join(format!("{}.{}{}", name, index.clock, TEMPORARY_EXTENSION))
+        };
+        let written = File::create(&temporary)
+            .and_then(|mut file| file.write_all(&data))
+            .and_then(|_| fs::rename(&temporary, &path));
+        if let Err(err) = written {
+            warn!("Could not cache mesh {:?}: {}", path, err);
+            let _ = fs::remove_file(&temporary);
+            return;
+        }
+
+        let mut index = self.index.lock().unwrap();
+        index.insert(name, data.len() as u64);
+        self.evict(&mut index);
+    }
+
+    /// Deletes the least recently used files until they fit in the capacity.
+    fn evict(&self, index: &mut CacheIndex) {
+        while index.total_size > self.capacity {
+            let name = match index.remove_oldest() {
+                Some(name) => name,
+                None => break,
+            };
+            if let Err(err) = fs::remove_file(self.directory.join(&name)) {
+                warn!("Could not evict cached mesh {:?}: {}", name, err);
+            }
+        }
+    }
+}
+
+fn file_name(chunk_id: &ChunkId, voxel_density: f32, mesher: Mesher, seams: &Seams) -> String {
+    let position = chunk_id.position(voxel_density);
+    let seams: String = seams.iter().map(|seam| format!("{:x}", seam)).collect();
+    format!(
+        "{}_{}_{}_{}-{:?}-{}{}",
+        position[0],
+        position[1],
+        position[2],
+        chunk_id.size(voxel_density),
+        mesher,
+        seams,
+        MESH_EXTENSION
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use gfx::lod::ChunkId;
+    use gfx::marching_cubes::NO_SEAMS;
+    use gfx::mesh::{Mesh, Vertex};
+    use gfx::mesh_format::{encode_mesh, MeshCompression};
+    use math::{Mesher, Vec3f};
+    use super::MeshCache;
+
+    fn triangle(name: &str) -> Mesh<Vertex> {
+        let vertex = |x, y| {
+            Vertex {
+                position: Vec3f::new(x, y, -0.5),
+                normal: Vec3f::new(0.0, 0.0, 1.0),
+            }
+        };
+        Mesh {
+            name: name.to_owned(),
+            vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(0.0, 1.5)],
+            indices: vec![0, 1, 2],
+        }
+    }
+
+    #[test]
+    fn test_mesh_cache_round_trip_and_eviction() {
+        let process = ::std::process::id();
+        let directory = env::temp_dir().join(format!("terrain-test-{}.meshes", process));
+        let _ = fs::remove_dir_all(&directory);
+        let mesh = triangle("chunk");
+        let mesh_bytes = encode_mesh(&mesh, MeshCompression::Deflate).len() as u64;
+
+        let chunk_ids: Vec<_> = (0..3)
+            .map(|x| ChunkId::new(&Vec3f::new(x as f32 * 64.0, -32.0, 0.0), 64.0, 8.0))
+            .collect();
+        let seams = [1, 0, 0, 2, 0, 0];
+        {
+            let cache = MeshCache::open(directory.clone(), 8.0, 2 * mesh_bytes).unwrap();
+            assert_eq!(None, cache.get(&chunk_ids[0], Mesher::MarchingCubes, &NO_SEAMS));
+            cache.insert(&chunk_ids[0], Mesher::MarchingCubes, &NO_SEAMS, &mesh);
+            cache.insert(&chunk_ids[1], Mesher::MarchingCubes, &seams, &mesh);
+            let cached = cache.get(&chunk_ids[0], Mesher::MarchingCubes, &NO_SEAMS);
+            assert_eq!(Some(mesh.clone()), cached);
+            assert_eq!(None, cache.get(&chunk_ids[1], Mesher::MarchingCubes, &NO_SEAMS));
+            assert_eq!(None, cache.get(&chunk_ids[0], Mesher::SurfaceNets, &NO_SEAMS));
+            // The second chunk was used less recently than the first.
+            cache.insert(&chunk_ids[2], Mesher::MarchingCubes, &NO_SEAMS, &mesh);
+            assert_eq!(None, cache.get(&chunk_ids[1], Mesher::MarchingCubes, &seams));
+            assert!(cache.get(&chunk_ids[2], Mesher::MarchingCubes, &NO_SEAMS).is_some());
+        }
+
+        // Reopened, the cache still has the meshes, and drops a corrupt one.
+        let cache = MeshCache::open(directory.clone(), 8.0, 2 * mesh_bytes).unwrap();
+        let cached = cache.get(&chunk_ids[0], Mesher::MarchingCubes, &NO_SEAMS);
+        assert_eq!(Some(mesh), cached);
+        let corrupt = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.file_name().unwrap().to_string_lossy().starts_with("128_"))
+            .unwrap();
+        File::create(&corrupt).unwrap().write_all(b"TMSH").unwrap();
+        assert_eq!(None, cache.get(&chunk_ids[2], Mesher::MarchingCubes, &NO_SEAMS));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
+
+// The extensions of the files of cached meshes, and of those being written.
+const MESH_EXTENSION: &'static str = ".mesh";
+const TEMPORARY_EXTENSION: &'static str = ".tmp";
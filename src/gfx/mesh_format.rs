@@ -0,0 +1,423 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use errors::{ChainErr, ErrorKind, Result};
+use math::{GpuScalar, Vec3f};
+use super::mesh::{BarycentricVertex, Mesh, NormalVertex, Vertex};
+
+/// A vertex which can be written in the binary mesh format, as a fixed
+/// number of floats.
+pub trait PackedVertex: NormalVertex + Sized {
+    /// Tells the kinds of vertices apart in the header, so a mesh is only
+    /// read back as the vertices it was written with.
+    fn vertex_kind() -> u8;
+
+    fn num_floats() -> usize;
+
+    /// Appends the `num_floats` floats of the vertex to `floats`.
+    fn pack(&self, floats: &mut Vec<GpuScalar>);
+
+    fn unpack(floats: &[GpuScalar]) -> Self;
+}
+
+impl PackedVertex for Vertex {
+    fn vertex_kind() -> u8 {
+        1
+    }
+
+    fn num_floats() -> usize {
+        6
+    }
+
+    fn pack(&self, floats: &mut Vec<GpuScalar>) {
+        floats.extend_from_slice(&[self.position[0], self.position[1], self.position[2],
+                                   self.normal[0], self.normal[1], self.normal[2]]);
+    }
+
+    fn unpack(floats: &[GpuScalar]) -> Self {
+        Vertex {
+            position: Vec3f::new(floats[0], floats[1], floats[2]),
+            normal: Vec3f::new(floats[3], floats[4], floats[5]),
+        }
+    }
+}
+
+impl PackedVertex for BarycentricVertex {
+    fn vertex_kind() -> u8 {
+        2
+    }
+
+    fn num_floats() -> usize {
+        12
+    }
+
+    fn pack(&self, floats: &mut Vec<GpuScalar>) {
+        for vector in &[self.position, self.normal, self.bary_coord] {
+            floats.extend_from_slice(&[vector[0], vector[1], vector[2]]);
+        }
+        floats.extend_from_slice(&[self.material, self.stratum, self.occlusion]);
+    }
+
+    fn unpack(floats: &[GpuScalar]) -> Self {
+        BarycentricVertex {
+            position: Vec3f::new(floats[0], floats[1], floats[2]),
+            normal: Vec3f::new(floats[3], floats[4], floats[5]),
+            bary_coord: Vec3f::new(floats[6], floats[7], floats[8]),
+            material: floats[9],
+            stratum: floats[10],
+            occlusion: floats[11],
+        }
+    }
+}
+
+/// How the body of an encoded mesh, after its header, is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshCompression {
+    None,
+    Deflate,
+}
+
+impl MeshCompression {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(MeshCompression::None),
+            "deflate" => Some(MeshCompression::Deflate),
+            _ => None,
+        }
+    }
+}
+
+// An encoded mesh is a header
+//     magic: "TMSH", version: u32, vertex kind: u8, flags: u8,
+//     floats per vertex: u16, name length: u32, vertices: u32, indices: u32,
+//     body length: u32
+// followed by the body of `body length` bytes, deflated with zlib if the
+// flags have `COMPRESSED`: the name, in UTF-8, the floats of each vertex and
+// the indices, u16 each if the flags have `SHORT_INDICES` or u32 otherwise.
+// Everything is little endian. Meshes follow one another in a stream, as the
+// header tells how long each is.
+
+/// `mesh` in the binary mesh format, its body stored with `compression`.
+pub fn encode_mesh<V: PackedVertex>(mesh: &Mesh<V>, compression: MeshCompression) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + raw_body_size::<V>(mesh));
+    // Writing to a `Vec` cannot fail.
+    write_mesh(mesh, compression, &mut data).unwrap();
+    data
+}
+
+/// Decodes the mesh in `data`, which must hold exactly one.
+pub fn decode_mesh<V: PackedVertex>(data: &[u8]) -> Result<Mesh<V>> {
+    let mut reader = data;
+    match try!(read_mesh(&mut reader)) {
+        Some(mesh) => {
+            if reader.is_empty() {
+                Ok(mesh)
+            } else {
+                Err(invalid(format!("{} bytes after the mesh", reader.len())))
+            }
+        }
+        None => Err(invalid("no mesh".to_owned())),
+    }
+}
+
+/// Writes `mesh` to `writer` in the binary mesh format.
+pub fn write_mesh<V, W>(mesh: &Mesh<V>, compression: MeshCompression, writer: &mut W) -> Result<()>
+where
+    V: PackedVertex,
+    W: Write,
+{
+    write_mesh_bytes(mesh, compression, writer).chain_err(|| "Could not write mesh.")
+}
+
+fn write_mesh_bytes<V, W>(
+    mesh: &Mesh<V>,
+    compression: MeshCompression,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    V: PackedVertex,
+    W: Write,
+{
+    let short_indices = mesh.vertices.len() <= u16::max_value() as usize + 1;
+    let mut body = Vec::with_capacity(raw_body_size::<V>(mesh));
+    body.extend_from_slice(mesh.name.as_bytes());
+    let mut floats = Vec::with_capacity(V::num_floats());
+    for vertex in mesh.vertices.iter() {
+        floats.clear();
+        vertex.pack(&mut floats);
+        for &value in floats.iter() {
+            try!(body.write_f32::<LittleEndian>(value));
+        }
+    }
+    for &index in mesh.indices.iter() {
+        if short_indices {
+            try!(body.write_u16::<LittleEndian>(index as u16));
+        } else {
+            try!(body.write_u32::<LittleEndian>(index));
+        }
+    }
+    let mut flags = if short_indices { SHORT_INDICES } else { 0 };
+    if compression == MeshCompression::Deflate {
+        let mut encoder = ZlibEncoder::new(Vec::with_capacity(body.len() / 2), Compression::Fast);
+        try!(encoder.write_all(&body));
+        body = try!(encoder.finish());
+        flags |= COMPRESSED;
+    }
+
+    try!(writer.write_all(MESH_FORMAT_MAGIC));
+    try!(writer.write_u32::<LittleEndian>(MESH_FORMAT_VERSION));
+    try!(writer.write_u8(V::vertex_kind()));
+    try!(writer.write_u8(flags));
+    try!(writer.write_u16::<LittleEndian>(V::num_floats() as u16));
+    for &count in &[mesh.name.len(), mesh.vertices.len(), mesh.indices.len(), body.len()] {
+        try!(writer.write_u32::<LittleEndian>(count as u32));
+    }
+    writer.write_all(&body)
+}
+
+/// Reads the next mesh in the binary mesh format from `reader`, or None if
+/// it's at its end.
+pub fn read_mesh<V: PackedVertex, R: Read>(reader: &mut R) -> Result<Option<Mesh<V>>> {
+    let mut header = [0u8; HEADER_SIZE];
+    let mut header_len = 0;
+    while header_len < HEADER_SIZE {
+        match reader.read(&mut header[header_len..]) {
+            Ok(0) => break,
+            Ok(len) => header_len += len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err).chain_err(|| "Could not read mesh."),
+        }
+    }
+    if header_len == 0 {
+        return Ok(None);
+    } else if header_len < HEADER_SIZE || &header[..MESH_FORMAT_MAGIC.len()] != MESH_FORMAT_MAGIC {
+        return Err(invalid("not a mesh".to_owned()));
+    }
+
+    // Reading from the header, which is long enough, cannot fail.
+    let mut fields = &header[MESH_FORMAT_MAGIC.len()..];
+    let version = fields.read_u32::<LittleEndian>().unwrap();
+    if version != MESH_FORMAT_VERSION {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+    let (vertex_kind, flags) = (fields.read_u8().unwrap(), fields.read_u8().unwrap());
+    let num_floats = fields.read_u16::<LittleEndian>().unwrap() as usize;
+    if vertex_kind != V::vertex_kind() || num_floats != V::num_floats() {
+        return Err(invalid(format!(
+            "vertices of kind {} with {} floats, not {} with {}",
+            vertex_kind,
+            num_floats,
+            V::vertex_kind(),
+            V::num_floats()
+        )));
+    }
+    let mut next_len = || fields.read_u32::<LittleEndian>().unwrap() as usize;
+    let (name_len, num_vertices, num_indices, body_len) =
+        (next_len(), next_len(), next_len(), next_len());
+
+    let mut stored = vec![];
+    try!(
+        reader
+            .take(body_len as u64)
+            .read_to_end(&mut stored)
+            .chain_err(|| "Could not read mesh.")
+    );
+    if stored.len() != body_len {
+        return Err(invalid(format!("{} bytes of body, not {}", stored.len(), body_len)));
+    }
+    let index_size = if flags & SHORT_INDICES != 0 { 2 } else { 4 };
+    // The counts aren't trusted until they fit the body, so a corrupt header
+    // can't make it allocate more than the data could inflate to.
+    let max_len = if flags & COMPRESSED != 0 {
+        body_len.saturating_mul(MAX_DEFLATE_RATIO).min(MAX_BODY_SIZE)
+    } else {
+        body_len
+    };
+    let raw_len = num_vertices
+        .checked_mul(num_floats * 4)
+        .and_then(|len| {
+            num_indices.checked_mul(index_size).and_then(|indices| len.checked_add(indices))
+        })
+        .and_then(|len| len.checked_add(name_len));
+    let raw_len = match raw_len {
+        Some(raw_len) if raw_len <= max_len => raw_len,
+        _ => {
+            return Err(invalid(format!(
+                "{} vertices and {} indices don't fit {} bytes of body",
+                num_vertices,
+                num_indices,
+                body_len
+            )))
+        }
+    };
+    let body = if flags & COMPRESSED != 0 {
+        // One byte more than expected is enough to tell it's too long.
+        let mut body = Vec::with_capacity(raw_len);
+        let decoder = ZlibDecoder::new(&stored[..]);
+        try!(decoder.take(raw_len as u64 + 1).read_to_end(&mut body).map_err(|err| {
+            invalid(format!("could not inflate: {}", err))
+        }));
+        body
+    } else {
+        stored
+    };
+    if body.len() != raw_len {
+        return Err(invalid(format!("{} bytes of data, not {}", body.len(), raw_len)));
+    }
+
+    let name = try!(String::from_utf8(body[..name_len].to_vec()).map_err(|err| {
+        invalid(err.to_string())
+    }));
+    // The body is the length worked out from the header, so reading it
+    // cannot fail either.
+    let mut reader = &body[name_len..];
+    let mut vertices = Vec::with_capacity(num_vertices);
+    let mut floats = vec![0.0; num_floats];
+    for _ in 0..num_vertices {
+        for value in floats.iter_mut() {
+            *value = reader.read_f32::<LittleEndian>().unwrap();
+        }
+        vertices.push(V::unpack(&floats));
+    }
+    let mut indices = Vec::with_capacity(num_indices);
+    for _ in 0..num_indices {
+        let index = if index_size == 2 {
+            reader.read_u16::<LittleEndian>().unwrap() as u32
+        } else {
+            reader.read_u32::<LittleEndian>().unwrap()
+        };
+        if index as usize >= num_vertices {
+            return Err(invalid(format!("index {} out of {} vertices", index, num_vertices)));
+        }
+        indices.push(index);
+    }
+    Ok(Some(Mesh {
+        name: name,
+        vertices: vertices,
+        indices: indices,
+    }))
+}
+
+/// The length of the body of `mesh` before it's compressed, at most.
+fn raw_body_size<V: PackedVertex>(mesh: &Mesh<V>) -> usize {
+    mesh.name.len() + mesh.vertices.len() * V::num_floats() * 4 + mesh.indices.len() * 4
+}
+
+fn invalid(message: String) -> ::errors::Error {
+    ErrorKind::InvalidMeshData(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx::mesh::{BarycentricVertex, Mesh, Vertex};
+    use math::Vec3f;
+    use super::{decode_mesh, encode_mesh, read_mesh, write_mesh, MeshCompression};
+
+    fn grid(size: usize) -> Mesh<Vertex> {
+        let mut mesh = Mesh {
+            name: "grid".to_owned(),
+            vertices: vec![],
+            indices: vec![],
+        };
+        for x in 0..size + 1 {
+            for z in 0..size + 1 {
+                mesh.vertices.push(Vertex {
+                    position: Vec3f::new(x as f32, (x * z) as f32 * 0.01, z as f32),
+                    normal: Vec3f::new(0.0, -1.0, 0.0),
+                });
+            }
+        }
+        for x in 0..size as u32 {
+            for z in 0..size as u32 {
+                let corner = x * (size as u32 + 1) + z;
+                let next = corner + size as u32 + 1;
+                mesh.indices.extend_from_slice(&[corner, corner + 1, next, next, corner + 1,
+                                                 next + 1]);
+            }
+        }
+        mesh
+    }
+
+    #[test]
+    fn test_mesh_format_round_trip() {
+        let mesh = grid(8);
+        let barycentric = mesh.clone().with_barycentric_coordinates();
+        // Too many vertices for 16 bit indices.
+        let large = grid(260);
+        for &compression in &[MeshCompression::None, MeshCompression::Deflate] {
+            let data = encode_mesh(&mesh, compression);
+            assert_eq!(mesh, decode_mesh(&data).unwrap());
+            let data = encode_mesh(&barycentric, compression);
+            assert_eq!(barycentric, decode_mesh(&data).unwrap());
+            let data = encode_mesh(&large, compression);
+            assert_eq!(large, decode_mesh(&data).unwrap());
+        }
+        let empty: Mesh<BarycentricVertex> = Mesh {
+            name: String::new(),
+            vertices: vec![],
+            indices: vec![],
+        };
+        assert_eq!(empty, decode_mesh(&encode_mesh(&empty, MeshCompression::Deflate)).unwrap());
+
+        // The geometry of terrain compresses well.
+        let raw = encode_mesh(&barycentric, MeshCompression::None).len();
+        assert!(encode_mesh(&barycentric, MeshCompression::Deflate).len() < raw / 2);
+
+        // Meshes follow one another in a stream.
+        let mut stream = vec![];
+        write_mesh(&mesh, MeshCompression::Deflate, &mut stream).unwrap();
+        write_mesh(&large, MeshCompression::None, &mut stream).unwrap();
+        let mut reader = &stream[..];
+        assert_eq!(Some(mesh.clone()), read_mesh(&mut reader).unwrap());
+        assert_eq!(Some(large), read_mesh(&mut reader).unwrap());
+        assert_eq!(None, read_mesh::<Vertex, _>(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_mesh_format_rejects_invalid_data() {
+        let mesh = grid(4);
+        for &compression in &[MeshCompression::None, MeshCompression::Deflate] {
+            let data = encode_mesh(&mesh, compression);
+            assert!(decode_mesh::<Vertex>(&data[..data.len() - 1]).is_err());
+            assert!(decode_mesh::<Vertex>(&data[..10]).is_err());
+            assert!(decode_mesh::<Vertex>(&[&data[..], &[0u8][..]].concat()).is_err());
+            // Read back as the wrong kind of vertices.
+            assert!(decode_mesh::<BarycentricVertex>(&data).is_err());
+
+            let mut newer = data.clone();
+            newer[4] += 1;
+            assert!(decode_mesh::<Vertex>(&newer).is_err());
+            let mut corrupt = data.clone();
+            let last = corrupt.len() - 1;
+            corrupt[last] ^= 0xff;
+            corrupt[last - 1] ^= 0xff;
+            assert!(decode_mesh::<Vertex>(&corrupt).is_err());
+            // Counts in the header far past what the body holds.
+            let mut oversized = data.clone();
+            for byte in oversized[16..24].iter_mut() {
+                *byte = 0xff;
+            }
+            assert!(decode_mesh::<Vertex>(&oversized).is_err());
+        }
+        assert_eq!(Some(MeshCompression::Deflate), MeshCompression::from_name("deflate"));
+        assert_eq!(None, MeshCompression::from_name("zip"));
+    }
+}
+
+// How long the header of an encoded mesh is.
+const HEADER_SIZE: usize = 28;
+const MESH_FORMAT_MAGIC: &'static [u8] = b"TMSH";
+// Version 1 was that of the first mesh caches, which could only have vertices
+// with a position and a normal and weren't compressed.
+const MESH_FORMAT_VERSION: u32 = 2;
+// The flags of the header.
+const COMPRESSED: u8 = 1;
+const SHORT_INDICES: u8 = 2;
+// Deflate can't shrink data more than this many times, and no body inflates
+// to more than this many bytes, far more than the largest chunk.
+const MAX_DEFLATE_RATIO: usize = 1032;
+const MAX_BODY_SIZE: usize = 1 << 30;
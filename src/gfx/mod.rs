@@ -1,20 +1,48 @@
 pub mod app;
 pub mod camera;
+pub mod density;
+pub mod capture;
+pub mod cubemap_file;
+pub mod decimate;
+pub mod dual_contouring;
+pub mod frustum;
+pub mod graph;
 pub mod input;
+pub mod lens_flare;
+pub mod lights;
 pub mod lod;
 pub mod marching_cubes;
 pub mod mesh;
+pub mod mesh_cache;
+pub mod mesh_format;
+pub mod occlusion;
+pub mod octree_debug;
+pub mod render_graph;
+pub mod screenshot;
+pub mod shadow_map;
 pub mod skybox;
+pub mod splatting;
+pub mod surface_nets;
+pub mod text;
+#[cfg(test)]
+pub mod testing;
 pub mod window;
 
 pub use self::app::App;
-pub use self::camera::Camera;
-pub use self::input::{Input, Gesture, Analog2d, KeyCode, MouseButton};
-pub use self::lod::LevelOfDetail;
-pub use self::marching_cubes::marching_cubes;
+pub use self::camera::{Camera, perspective_matrix, FIELD_OF_VIEW};
+pub use self::decimate::decimate;
+pub use self::density::GpuPlanetField;
+pub use self::dual_contouring::dual_contouring;
+pub use self::frustum::Frustum;
+pub use self::input::{Input, Gesture, Analog2d, KeyCode, MouseButton, key_code_from_name};
+pub use self::lod::{ChunkScheduler, LevelOfDetail, LodSpec, LodStats};
+pub use self::marching_cubes::{marching_cubes, marching_cubes_with_seams, Seams, NO_SEAMS};
 pub use self::mesh::{BarycentricVertex, Vertex, Mesh};
-pub use self::skybox::SkyboxRenderer;
-pub use self::window::Window;
+pub use self::occlusion::ambient_occlusion;
+pub use self::skybox::{SkyboxRenderer, SkyboxSpec};
+pub use self::surface_nets::surface_nets;
+pub use self::text::TextRenderer;
+pub use self::window::{FullscreenMode, Window, WindowSpec};
 
 use glium::texture::{ClientFormat, PixelValue};
 use glium::uniforms::{AsUniformValue, UniformValue};
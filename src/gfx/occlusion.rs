@@ -0,0 +1,151 @@
+use nalgebra::{Cross, Norm, Point3, Vector3};
+
+use math::ScalarField3;
+use super::mesh::{Mesh, Vertex};
+
+/// How much of the sky each vertex of `mesh` sees, in [0, 1]: the share of
+/// `num_rays` rays over the hemisphere above it, more of them towards the
+/// normal, which get `distance` away without running into `field`.
+///
+/// The rays are sphere traced, which needs the field to be about the
+/// distance to its surface there, and they all march together, each step
+/// sampling the field in one batch with the detail of `step`.
+pub fn ambient_occlusion<Field: ScalarField3>(
+    field: &Field,
+    mesh: &Mesh<Vertex>,
+    num_rays: usize,
+    distance: f32,
+    step: f32,
+) -> Vec<f32> {
+    if num_rays == 0 {
+        return vec![1.0; mesh.vertices.len()];
+    }
+    let directions = hemisphere(num_rays);
+    let mut origins = Vec::with_capacity(mesh.vertices.len());
+    let mut rays = Vec::with_capacity(mesh.vertices.len() * num_rays);
+    for vertex in mesh.vertices.iter() {
+        // Normals point into the ground.
+        let normal = -*vertex.normal;
+        let length = normal.norm();
+        let up = if length > 0.0 {
+            normal / length
+        } else {
+            Vector3::new(0.0, 1.0, 0.0)
+        };
+        let helper = if up[0].abs() < 0.9 {
+            Vector3::new(1.0, 0.0, 0.0)
+        } else {
+            Vector3::new(0.0, 1.0, 0.0)
+        };
+        let tangent = helper.cross(&up).normalize();
+        let bitangent = up.cross(&tangent);
+        // Off the surface a little, so the rays don't start inside it.
+        origins.push(*vertex.position + up * (distance * NORMAL_OFFSET));
+        for direction in directions.iter() {
+            rays.push(tangent * direction[0] + bitangent * direction[1] + up * direction[2]);
+        }
+    }
+
+    let mut travelled = vec![0.0f32; rays.len()];
+    let mut marching = vec![true; rays.len()];
+    let mut blocked = vec![false; rays.len()];
+    let mut active = vec![];
+    let mut positions = vec![];
+    let mut values = vec![];
+    for _ in 0..MARCH_STEPS {
+        active.clear();
+        positions.clear();
+        for ray in (0..rays.len()).filter(|&ray| marching[ray]) {
+            let position = origins[ray / num_rays] + rays[ray] * travelled[ray];
+            active.push(ray);
+            positions.push(Point3::new(position[0], position[1], position[2]));
+        }
+        if active.is_empty() {
+            break;
+        }
+        values.resize(positions.len(), 0.0);
+        field.values_at_lod(&positions, &mut values, step);
+        for (&ray, &value) in active.iter().zip(values.iter()) {
+            if value < distance * HIT_DISTANCE {
+                marching[ray] = false;
+                blocked[ray] = true;
+            } else {
+                travelled[ray] += value.max(distance * MIN_ADVANCE);
+                marching[ray] = travelled[ray] < distance;
+            }
+        }
+    }
+
+    // The rays are spread like the cosine to the normal, so each counts the
+    // same. Those still marching after the last step are taken to escape.
+    blocked
+        .chunks(num_rays)
+        .map(|vertex_blocked| {
+            let escaped = vertex_blocked.iter().filter(|&&blocked| !blocked).count();
+            escaped as f32 / num_rays as f32
+        })
+        .collect()
+}
+
+/// `num_rays` directions over the hemisphere around z, spread in a spiral
+/// with their density proportional to the cosine to z.
+fn hemisphere(num_rays: usize) -> Vec<Vector3<f32>> {
+    (0..num_rays)
+        .map(|ray| {
+            let height = (ray as f32 + 0.5) / num_rays as f32;
+            let (radius, angle) = (height.sqrt(), ray as f32 * GOLDEN_ANGLE);
+            Vector3::new(radius * angle.cos(), radius * angle.sin(), (1.0 - height).sqrt())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use gfx::mesh::{Mesh, Vertex};
+    use math::{CpuScalar, ScalarField3, Vec3f};
+    use super::ambient_occlusion;
+
+    /// The ground below y = 0 and a wall behind x = 0.
+    struct Corner;
+
+    impl ScalarField3 for Corner {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            position[0].min(position[1])
+        }
+    }
+
+    #[test]
+    fn test_ambient_occlusion_corner() {
+        let vertex = |x| {
+            Vertex {
+                position: Vec3f::new(x, 0.0, 0.0),
+                normal: Vec3f::new(0.0, -1.0, 0.0),
+            }
+        };
+        let mesh = Mesh {
+            name: "floor".to_owned(),
+            vertices: vec![vertex(0.05), vertex(0.5), vertex(10.0)],
+            indices: vec![],
+        };
+        let occlusion = ambient_occlusion(&Corner, &mesh, 32, 2.0, 0.0);
+        assert_eq!(1.0, occlusion[2]);
+        // The wall hides about half the sky next to it.
+        assert!(occlusion[0] > 0.3 && occlusion[0] < 0.7, "{:?}", occlusion);
+        assert!(occlusion[0] < occlusion[1] && occlusion[1] < occlusion[2]);
+        assert!(ambient_occlusion(&Corner, &mesh, 0, 2.0, 0.0).iter().all(|&o| o == 1.0));
+    }
+}
+
+// Sphere tracing steps each ray takes at most.
+const MARCH_STEPS: usize = 12;
+// A ray closer than this to the surface has hit it, as a fraction of the
+// distance the rays go.
+const HIT_DISTANCE: f32 = 0.01;
+// And one never advances by less than this, so grazing rays get somewhere.
+const MIN_ADVANCE: f32 = 0.05;
+// How far above the vertices the rays start, also as a fraction.
+const NORMAL_OFFSET: f32 = 0.05;
+// Between consecutive rays of the spiral, in radians.
+const GOLDEN_ANGLE: f32 = 2.399963;
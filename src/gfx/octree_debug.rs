@@ -0,0 +1,235 @@
+use glium::{Blend, DrawParameters, Frame, Program, Surface, VertexBuffer};
+use glium::draw_parameters::TimeElapsedQuery;
+use glium::index::{NoIndices, PrimitiveType};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+use math::Matrix4f;
+use super::lod::{ChunkState, OctreeBox};
+
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+implement_vertex!(DebugVertex, position, color);
+
+/// What the wireframe boxes of the octree nodes are colored by, if they're
+/// drawn at all.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OctreeDebugMode {
+    Off,
+    Level,
+    ChunkState,
+}
+
+impl OctreeDebugMode {
+    /// The mode after this one, cycling back to `Off`.
+    pub fn next(self) -> Self {
+        match self {
+            OctreeDebugMode::Off => OctreeDebugMode::Level,
+            OctreeDebugMode::Level => OctreeDebugMode::ChunkState,
+            OctreeDebugMode::ChunkState => OctreeDebugMode::Off,
+        }
+    }
+}
+
+/// Draws the nodes of an octree as wireframe boxes over everything else.
+/// The nodes whose chunks aren't drawn are faded, to tell them apart from
+/// those which are.
+pub struct OctreeDebugRenderer<'a> {
+    draw_parameters: DrawParameters<'a>,
+    program: Program,
+    /// Reused from frame to frame, it's only replaced by a larger one.
+    vertex_buffer: Option<VertexBuffer<DebugVertex>>,
+}
+
+impl<'a> OctreeDebugRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        Ok(OctreeDebugRenderer {
+            draw_parameters: DrawParameters {
+                blend: Blend::alpha_blending(),
+                ..Default::default()
+            },
+            program: program,
+            vertex_buffer: None,
+        })
+    }
+
+    /// Draws `boxes`, in the frame of the body with `model` matrix, colored
+    /// by `mode`.
+    pub fn render(
+        &mut self,
+        window: &Window,
+        frame: &mut Frame,
+        boxes: &[OctreeBox],
+        mode: OctreeDebugMode,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        model: &Matrix4f,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let vertices = box_vertices(boxes, mode);
+        if vertices.is_empty() {
+            return Ok(());
+        }
+        let fits = self.vertex_buffer.as_ref().map_or(false, |vertex_buffer| {
+            vertex_buffer.len() >= vertices.len()
+        });
+        if !fits {
+            self.vertex_buffer = Some(try!(
+                VertexBuffer::empty_dynamic(window.facade(), vertices.len().next_power_of_two())
+                    .chain_err(|| "Cannot create vertex buffer.")
+            ));
+        }
+        let vertex_buffer = self.vertex_buffer
+            .as_ref()
+            .and_then(|vertex_buffer| vertex_buffer.slice(0..vertices.len()))
+            .unwrap();
+        vertex_buffer.write(&vertices);
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            model: model,
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        frame
+            .draw(
+                vertex_buffer,
+                &NoIndices(PrimitiveType::LinesList),
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            )
+            .chain_err(|| "Could not render the octree.")
+    }
+}
+
+/// The lines along the 12 edges of each of `boxes`, colored by `mode`.
+fn box_vertices(boxes: &[OctreeBox], mode: OctreeDebugMode) -> Vec<DebugVertex> {
+    if mode == OctreeDebugMode::Off {
+        return vec![];
+    }
+    let mut vertices = Vec::with_capacity(boxes.len() * BOX_EDGES.len() * 2);
+    for octree_box in boxes.iter() {
+        let mut color = match mode {
+            OctreeDebugMode::ChunkState => {
+                match octree_box.state {
+                    ChunkState::Available => AVAILABLE_COLOR,
+                    ChunkState::Pending => PENDING_COLOR,
+                    ChunkState::Empty => EMPTY_COLOR,
+                    ChunkState::Unknown => UNKNOWN_COLOR,
+                    ChunkState::Failed => FAILED_COLOR,
+                }
+            }
+            _ => LEVEL_COLORS[octree_box.level as usize % LEVEL_COLORS.len()],
+        };
+        if !octree_box.draw {
+            color[3] *= UNDRAWN_ALPHA;
+        }
+        let corner = |index: usize| {
+            let mut position = [0.0; 3];
+            for axis in 0..3 {
+                let offset = (index >> axis) & 1;
+                position[axis] = octree_box.position[axis] + octree_box.size * offset as f32;
+            }
+            DebugVertex {
+                position: position,
+                color: color,
+            }
+        };
+        for &(start, end) in BOX_EDGES.iter() {
+            vertices.push(corner(start));
+            vertices.push(corner(end));
+        }
+    }
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx::lod::{ChunkState, OctreeBox};
+    use math::Vec3f;
+    use super::{box_vertices, OctreeDebugMode, PENDING_COLOR};
+
+    #[test]
+    fn test_box_vertices_outline_each_box() {
+        let octree_box = OctreeBox {
+            position: Vec3f::new(-1.0, 2.0, 0.0),
+            size: 2.0,
+            level: 3,
+            draw: true,
+            state: ChunkState::Pending,
+        };
+        assert!(box_vertices(&[octree_box], OctreeDebugMode::Off).is_empty());
+
+        let vertices = box_vertices(&[octree_box], OctreeDebugMode::ChunkState);
+        assert_eq!(24, vertices.len());
+        assert!(vertices.iter().all(|vertex| vertex.color == PENDING_COLOR));
+        for edge in vertices.chunks(2) {
+            // Each edge runs the size of the box along a single axis.
+            let lengths: Vec<f32> = (0..3)
+                .map(|axis| (edge[1].position[axis] - edge[0].position[axis]).abs())
+                .collect();
+            assert_eq!(2.0, lengths.iter().sum::<f32>());
+            assert_eq!(2, lengths.iter().filter(|&&length| length == 0.0).count());
+        }
+
+        let undrawn = OctreeBox {
+            draw: false,
+            ..octree_box
+        };
+        let faded = box_vertices(&[undrawn], OctreeDebugMode::Level);
+        let drawn = box_vertices(&[octree_box], OctreeDebugMode::Level);
+        assert!(faded[0].color[3] < drawn[0].color[3]);
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/octree_debug.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/graph.frag";
+
+// The corners of a box are numbered by the bits of their offsets, x first.
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+// Repeated for levels past the last.
+const LEVEL_COLORS: [[f32; 4]; 8] = [
+    [1.0, 1.0, 1.0, 1.0],
+    [1.0, 0.3, 0.3, 1.0],
+    [1.0, 0.6, 0.2, 1.0],
+    [1.0, 0.9, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.2, 0.9, 1.0, 1.0],
+    [0.3, 0.4, 1.0, 1.0],
+    [0.8, 0.3, 1.0, 1.0],
+];
+
+const AVAILABLE_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const PENDING_COLOR: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+const EMPTY_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const UNKNOWN_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+const FAILED_COLOR: [f32; 4] = [1.0, 0.2, 1.0, 1.0];
+// The alpha of the nodes whose chunks aren't drawn is scaled by this.
+const UNDRAWN_ALPHA: f32 = 0.3;
@@ -0,0 +1,673 @@
+use std::time::Instant;
+
+use glium::{Blend, BlitTarget, DrawParameters, Frame, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BlendingFunction, LinearBlendingFactor, TimeElapsedQuery};
+use glium::framebuffer::{MultiOutputFrameBuffer, SimpleFrameBuffer};
+use glium::index::{NoIndices, PrimitiveType};
+use glium::texture::{DepthFormat, DepthTexture2d, MipmapsOption, Texture2d,
+                     UncompressedFloatFormat};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerWrapFunction,
+                      Uniforms};
+use nalgebra::Vector4;
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+use math::{Matrix4f, Vec3f};
+
+#[derive(Copy, Clone, Debug)]
+struct QuadVertex {
+    position: [f32; 2],
+}
+
+implement_vertex!(QuadVertex, position);
+
+/// The passes a frame of the bodies is drawn in, each into the targets the
+/// graph keeps for it, before `RenderGraph::post` puts the scene they make
+/// on the frame, over the sky drawn before it:
+///
+/// - `GBuffer`, the opaque surfaces write what they're made of, their
+///   albedos, normals and positions relative to the camera, and the body
+///   they're on, rather than colors.
+/// - `Lighting`, a quad over the screen per body lights the surfaces on it
+///   into the scene, with the body's sun, moon, shadows and sea.
+/// - `Transparent`, the water and the air are drawn over the lit scene and
+///   tested against the depths of the g-buffer, sampling a copy of what's
+///   drawn before them.
+///
+/// The bodies go through the g-buffer and lighting passes one after the
+/// other, a body lighting only its own surfaces, those of the bodies in
+/// front of it being lit as they're drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pass {
+    GBuffer,
+    Lighting,
+    Transparent,
+}
+
+/// Where the sun is on the screen for its light to shine in shafts through
+/// the gaps between what's in front of it, and how strongly, the shafts
+/// showing most when it's low.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightShafts {
+    /// In [0, 1] across the screen, though it may be off it.
+    pub sun: [f32; 2],
+    pub strength: f32,
+}
+
+impl LightShafts {
+    /// The shafts of the sun in `sun_direction`, seen through
+    /// `view_projection`, if it's in front of the camera.
+    pub fn new(view_projection: &Matrix4f, sun_direction: &Vec3f, strength: f32) -> Option<Self> {
+        if strength <= 0.0 {
+            return None;
+        }
+        screen_direction(view_projection, sun_direction).map(|sun| {
+            LightShafts {
+                sun: sun,
+                strength: strength,
+            }
+        })
+    }
+}
+
+/// Where `direction`, from the camera, is seen through `view_projection`, in
+/// [0, 1] across the screen if it's on it, or none if it's behind the camera.
+pub fn screen_direction(view_projection: &Matrix4f, direction: &Vec3f) -> Option<[f32; 2]> {
+    let clip = **view_projection * Vector4::new(direction[0], direction[1], direction[2], 0.0);
+    if clip[3] <= 0.0 {
+        None
+    } else {
+        Some([clip[0] / clip[3] * 0.5 + 0.5, clip[1] / clip[3] * 0.5 + 0.5])
+    }
+}
+
+/// The textures the passes draw into and sample, the size of the frame times
+/// the scale the scene is supersampled at.
+pub struct RenderTargets {
+    pub albedo: Texture2d,
+    pub normal: Texture2d,
+    pub position: Texture2d,
+    pub depth: DepthTexture2d,
+    pub scene: Texture2d,
+    scene_copy: Texture2d,
+    // The levels of the bloom, each the light blurred and that blurred only
+    // across, half the size of the level before, the first half the scene's.
+    bloom: Vec<(Texture2d, Texture2d)>,
+    // Half the size of the scene, the sky's light around the sun where it's
+    // not hidden, and that blurred towards the sun into shafts.
+    shaft_mask: Texture2d,
+    shafts: Texture2d,
+    size: (u32, u32),
+}
+
+/// The brightness of the scene the eye is adapted to, which sets the exposure
+/// the scene is tonemapped with. It's kept on the GPU: each frame the log
+/// average of the luminances of the scene's surfaces, the sky left out, is
+/// reduced into `luminance`, and the adapted luminance moves towards it from
+/// the one before, the two being swapped.
+struct Exposure {
+    luminance: Texture2d,
+    adapted: [Texture2d; 2],
+    current: usize,
+    last_update: Option<Instant>,
+    luminance_program: Program,
+    adapt_program: Program,
+}
+
+pub struct RenderGraph<'a> {
+    targets: Option<RenderTargets>,
+    // The scene's texels across each pixel of the frame, which the post pass
+    // averages.
+    scale: u32,
+    quad: VertexBuffer<QuadVertex>,
+    exposure: Exposure,
+    bright_program: Program,
+    blur_program: Program,
+    shaft_mask_program: Program,
+    shafts_program: Program,
+    post_program: Program,
+    quad_parameters: DrawParameters<'a>,
+    post_parameters: DrawParameters<'a>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let post_program = try!(window.program(
+            try!(assets.resolve(QUAD_VERTEX_SHADER)),
+            try!(assets.resolve(POST_FRAGMENT_SHADER)),
+        ));
+        let quad = try!(
+            VertexBuffer::new(
+                window.facade(),
+                &[
+                    QuadVertex { position: [-1.0, -1.0] },
+                    QuadVertex { position: [1.0, -1.0] },
+                    QuadVertex { position: [-1.0, 1.0] },
+                    QuadVertex { position: [1.0, 1.0] },
+                ],
+            ).chain_err(|| "Cannot create vertex buffer.")
+        );
+        // The scene's colors are premultiplied by how much of the sky behind
+        // them they cover.
+        let post_parameters = DrawParameters {
+            blend: premultiplied_blending(),
+            ..Default::default()
+        };
+        Ok(RenderGraph {
+            targets: None,
+            scale: 1,
+            quad: quad,
+            exposure: try!(Exposure::new(window, assets)),
+            bright_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(BRIGHT_FRAGMENT_SHADER)),
+            )),
+            blur_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(BLUR_FRAGMENT_SHADER)),
+            )),
+            shaft_mask_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(SHAFT_MASK_FRAGMENT_SHADER)),
+            )),
+            shafts_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(SHAFTS_FRAGMENT_SHADER)),
+            )),
+            post_program: post_program,
+            quad_parameters: Default::default(),
+            post_parameters: post_parameters,
+        })
+    }
+
+    /// Supersamples the scene (SSAA) with `samples` per pixel of the frame
+    /// from the next frame on, a square number in [1, 16]: the targets are
+    /// drawn that much larger and the silhouettes of the bodies are smoothed
+    /// as they're averaged down in the post pass.
+    pub fn set_supersampling(&mut self, samples: u32) -> Result<()> {
+        match supersampling_scale(samples) {
+            Some(scale) => {
+                self.scale = scale;
+                Ok(())
+            }
+            None => Err(format!("Cannot supersample with {} samples per pixel.", samples).into()),
+        }
+    }
+
+    /// Starts a frame the size of `frame`, clearing the targets of all the
+    /// passes.
+    pub fn begin(&mut self, window: &Window, frame: &Frame) -> Result<()> {
+        let (width, height) = frame.get_dimensions();
+        let size = (width * self.scale, height * self.scale);
+        if self.targets.as_ref().map_or(true, |targets| targets.size != size) {
+            self.targets = Some(try!(RenderTargets::new(window, size)));
+        }
+        {
+            let mut gbuffer = try!(self.target(window, Pass::GBuffer));
+            gbuffer.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
+        }
+        self.targets().scene.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+        Ok(())
+    }
+
+    /// The targets of the frame begun last.
+    pub fn targets(&self) -> &RenderTargets {
+        self.targets.as_ref().expect("No frame was begun.")
+    }
+
+    /// The framebuffer `pass` draws into. The g-buffer's outputs are named
+    /// `albedo`, `normal` and `position`, the others' is the scene's color.
+    pub fn target(&self, window: &Window, pass: Pass) -> Result<MultiOutputFrameBuffer> {
+        let targets = self.targets();
+        let framebuffer = match pass {
+            Pass::GBuffer => {
+                MultiOutputFrameBuffer::with_depth_buffer(
+                    window.facade(),
+                    vec![
+                        ("albedo", &targets.albedo),
+                        ("normal", &targets.normal),
+                        ("position", &targets.position),
+                    ],
+                    &targets.depth,
+                )
+            }
+            Pass::Lighting => {
+                MultiOutputFrameBuffer::new(window.facade(), vec![("color", &targets.scene)])
+            }
+            Pass::Transparent => {
+                MultiOutputFrameBuffer::with_depth_buffer(
+                    window.facade(),
+                    vec![("color", &targets.scene)],
+                    &targets.depth,
+                )
+            }
+        };
+        framebuffer.chain_err(|| format!("Could not create the framebuffer of {:?}.", pass))
+    }
+
+    /// Copies the scene lit so far, for the transparent pass to sample.
+    pub fn copy_scene(&self, window: &Window) -> Result<&Texture2d> {
+        let targets = self.targets();
+        let copy = try!(
+            SimpleFrameBuffer::new(window.facade(), &targets.scene_copy)
+                .chain_err(|| "Could not create the framebuffer of the scene's copy.")
+        );
+        targets.scene.as_surface().blit_whole_color_to(
+            &copy,
+            &BlitTarget {
+                left: 0,
+                bottom: 0,
+                width: targets.size.0 as i32,
+                height: targets.size.1 as i32,
+            },
+            MagnifySamplerFilter::Nearest,
+        );
+        Ok(&targets.scene_copy)
+    }
+
+    /// Draws `program` over the whole of `surface`, its vertex shader given
+    /// the corners of the quad in `position`.
+    pub fn draw_quad<S, U>(
+        &self,
+        surface: &mut S,
+        program: &Program,
+        uniforms: &U,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()>
+    where
+        S: Surface,
+        U: Uniforms,
+    {
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.quad_parameters.clone()
+        };
+        self.draw_quad_with(surface, program, uniforms, &draw_parameters)
+    }
+
+    /// Like `draw_quad`, with `draw_parameters` for blending it.
+    pub fn draw_quad_with<S, U>(
+        &self,
+        surface: &mut S,
+        program: &Program,
+        uniforms: &U,
+        draw_parameters: &DrawParameters,
+    ) -> Result<()>
+    where
+        S: Surface,
+        U: Uniforms,
+    {
+        surface
+            .draw(
+                &self.quad,
+                &NoIndices(PrimitiveType::TriangleStrip),
+                program,
+                uniforms,
+                draw_parameters,
+            )
+            .chain_err(|| "Could not draw a quad over the screen.")
+    }
+
+    /// Tonemaps the scene onto `frame`, over what's already on it, exposed
+    /// for its brightness and with the bloom of its brightest lights and the
+    /// shafts of the sun's, if any, added.
+    pub fn post(
+        &mut self,
+        window: &Window,
+        frame: &mut Frame,
+        shafts: Option<LightShafts>,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        try!(self.adapt_exposure(window));
+        try!(self.bloom(window));
+        if let Some(ref shafts) = shafts {
+            try!(self.light_shafts(window, shafts));
+        }
+        let targets = self.targets();
+        let uniforms =
+            uniform! {
+            u_scene: screen_sampler(&targets.scene),
+            u_adapted: screen_sampler(&self.exposure.adapted[self.exposure.current]),
+            u_bloom0: linear_sampler(&targets.bloom[0].0),
+            u_bloom1: linear_sampler(&targets.bloom[1].0),
+            u_bloom2: linear_sampler(&targets.bloom[2].0),
+            u_bloom3: linear_sampler(&targets.bloom[3].0),
+            u_shafts: linear_sampler(&targets.shafts),
+            u_shaft_strength: shafts.map_or(0.0, |shafts| shafts.strength),
+            u_scale: self.scale as i32,
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.post_parameters.clone()
+        };
+        frame
+            .draw(
+                &self.quad,
+                &NoIndices(PrimitiveType::TriangleStrip),
+                &self.post_program,
+                &uniforms,
+                &draw_parameters,
+            )
+            .chain_err(|| "Could not draw the scene on the frame.")
+    }
+
+    /// Measures the brightness of the scene and adapts the exposure to it, as
+    /// fast as the eye does in the time since the last frame.
+    fn adapt_exposure(&mut self, window: &Window) -> Result<()> {
+        let now = Instant::now();
+        let adaptation = match self.exposure.last_update {
+            Some(last_update) => {
+                let elapsed = now.duration_since(last_update);
+                let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+                1.0 - (-seconds * ADAPTATION_RATE).exp()
+            }
+            // The first frame is seen already adapted.
+            None => 1.0,
+        };
+        self.exposure.last_update = Some(now);
+
+        let next = 1 - self.exposure.current;
+        {
+            let exposure = &self.exposure;
+            let mut luminance = try!(
+                SimpleFrameBuffer::new(window.facade(), &exposure.luminance)
+                    .chain_err(|| "Could not create the framebuffer of the luminances.")
+            );
+            let uniforms =
+                uniform! {
+                u_scene: screen_sampler(&self.targets().scene),
+                u_cells: LUMINANCE_SIZE as i32,
+            };
+            try!(self.draw_quad(&mut luminance, &exposure.luminance_program, &uniforms, None));
+
+            let mut adapted = try!(
+                SimpleFrameBuffer::new(window.facade(), &exposure.adapted[next])
+                    .chain_err(|| "Could not create the framebuffer of the exposure.")
+            );
+            let uniforms =
+                uniform! {
+                u_luminance: screen_sampler(&exposure.luminance),
+                u_adapted: screen_sampler(&exposure.adapted[exposure.current]),
+                u_adaptation: adaptation,
+            };
+            try!(self.draw_quad(&mut adapted, &exposure.adapt_program, &uniforms, None));
+        }
+        self.exposure.current = next;
+        Ok(())
+    }
+
+    /// Masks the sky around the sun where nothing hides it, and blurs the
+    /// mask radially towards the sun, so the light streams out of the gaps
+    /// between the mountains and the clouds.
+    fn light_shafts(&self, window: &Window, shafts: &LightShafts) -> Result<()> {
+        let targets = self.targets();
+        let aspect = targets.size.0 as f32 / targets.size.1 as f32;
+        let mut mask = try!(
+            SimpleFrameBuffer::new(window.facade(), &targets.shaft_mask)
+                .chain_err(|| "Could not create the framebuffer of the light shafts.")
+        );
+        let uniforms =
+            uniform! {
+            u_position: screen_sampler(&targets.position),
+            u_scene: screen_sampler(&targets.scene),
+            u_sun: shafts.sun,
+            u_aspect: aspect,
+        };
+        try!(self.draw_quad(&mut mask, &self.shaft_mask_program, &uniforms, None));
+        let mut blurred = try!(
+            SimpleFrameBuffer::new(window.facade(), &targets.shafts)
+                .chain_err(|| "Could not create the framebuffer of the light shafts.")
+        );
+        let uniforms =
+            uniform! {
+            u_mask: linear_sampler(&targets.shaft_mask),
+            u_sun: shafts.sun,
+        };
+        self.draw_quad(&mut blurred, &self.shafts_program, &uniforms, None)
+    }
+
+    /// Blurs the light of the scene much brighter than the eye is adapted to
+    /// into the levels of the bloom, each downsampled from the one before
+    /// and so blurred twice as wide, for the sun's glow, lava and glints to
+    /// spill over what's around them.
+    fn bloom(&self, window: &Window) -> Result<()> {
+        let targets = self.targets();
+        let adapted = &self.exposure.adapted[self.exposure.current];
+        for (level, &(ref blurred, ref across)) in targets.bloom.iter().enumerate() {
+            let mut blurred_surface = try!(
+                SimpleFrameBuffer::new(window.facade(), blurred)
+                    .chain_err(|| "Could not create the framebuffer of the bloom.")
+            );
+            if level == 0 {
+                let uniforms =
+                    uniform! {
+                    u_scene: linear_sampler(&targets.scene),
+                    u_adapted: screen_sampler(adapted),
+                };
+                try!(self.draw_quad(&mut blurred_surface, &self.bright_program, &uniforms, None));
+            } else {
+                let (width, height) = blurred.dimensions();
+                targets.bloom[level - 1].0.as_surface().blit_whole_color_to(
+                    &blurred_surface,
+                    &BlitTarget {
+                        left: 0,
+                        bottom: 0,
+                        width: width as i32,
+                        height: height as i32,
+                    },
+                    MagnifySamplerFilter::Linear,
+                );
+            }
+            let mut across_surface = try!(
+                SimpleFrameBuffer::new(window.facade(), across)
+                    .chain_err(|| "Could not create the framebuffer of the bloom.")
+            );
+            let uniforms =
+                uniform! {
+                u_source: linear_sampler(blurred),
+                u_direction: [1.0f32, 0.0],
+            };
+            try!(self.draw_quad(&mut across_surface, &self.blur_program, &uniforms, None));
+            let uniforms =
+                uniform! {
+                u_source: linear_sampler(across),
+                u_direction: [0.0f32, 1.0],
+            };
+            try!(self.draw_quad(&mut blurred_surface, &self.blur_program, &uniforms, None));
+        }
+        Ok(())
+    }
+}
+
+impl Exposure {
+    fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let texture = |format, size| {
+            Texture2d::empty_with_format(
+                window.facade(),
+                format,
+                MipmapsOption::NoMipmap,
+                size,
+                size,
+            ).chain_err(|| "Could not create the textures of the exposure.")
+        };
+        let adapted = [
+            try!(texture(UncompressedFloatFormat::F32, 1)),
+            try!(texture(UncompressedFloatFormat::F32, 1)),
+        ];
+        // Until a frame has surfaces in it to adapt to.
+        for texture in adapted.iter() {
+            texture.as_surface().clear_color(1.0, 0.0, 0.0, 1.0);
+        }
+        Ok(Exposure {
+            luminance: try!(texture(UncompressedFloatFormat::F32F32, LUMINANCE_SIZE)),
+            adapted: adapted,
+            current: 0,
+            last_update: None,
+            luminance_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(LUMINANCE_FRAGMENT_SHADER)),
+            )),
+            adapt_program: try!(window.program(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(ADAPT_FRAGMENT_SHADER)),
+            )),
+        })
+    }
+}
+
+impl RenderTargets {
+    fn new(window: &Window, size: (u32, u32)) -> Result<Self> {
+        let color = |format| {
+            Texture2d::empty_with_format(
+                window.facade(),
+                format,
+                MipmapsOption::NoMipmap,
+                size.0,
+                size.1,
+            ).chain_err(|| "Could not create a render target.")
+        };
+        let depth = try!(
+            DepthTexture2d::empty_with_format(
+                window.facade(),
+                DepthFormat::F32,
+                MipmapsOption::NoMipmap,
+                size.0,
+                size.1,
+            ).chain_err(|| "Could not create the depth target.")
+        );
+        let mut bloom = Vec::with_capacity(BLOOM_LEVELS);
+        for level in 0..BLOOM_LEVELS {
+            let level_size = ((size.0 >> (level + 1)).max(1), (size.1 >> (level + 1)).max(1));
+            let texture = || {
+                Texture2d::empty_with_format(
+                    window.facade(),
+                    UncompressedFloatFormat::F16F16F16F16,
+                    MipmapsOption::NoMipmap,
+                    level_size.0,
+                    level_size.1,
+                ).chain_err(|| "Could not create a level of the bloom.")
+            };
+            bloom.push((try!(texture()), try!(texture())));
+        }
+        let half = |format| {
+            Texture2d::empty_with_format(
+                window.facade(),
+                format,
+                MipmapsOption::NoMipmap,
+                (size.0 / 2).max(1),
+                (size.1 / 2).max(1),
+            ).chain_err(|| "Could not create the targets of the light shafts.")
+        };
+        Ok(RenderTargets {
+            albedo: try!(color(UncompressedFloatFormat::U8U8U8U8)),
+            normal: try!(color(UncompressedFloatFormat::F16F16F16F16)),
+            position: try!(color(UncompressedFloatFormat::F32F32F32F32)),
+            depth: depth,
+            scene: try!(color(UncompressedFloatFormat::F16F16F16F16)),
+            scene_copy: try!(color(UncompressedFloatFormat::F16F16F16F16)),
+            bloom: bloom,
+            shaft_mask: try!(half(UncompressedFloatFormat::F16F16F16F16)),
+            shafts: try!(half(UncompressedFloatFormat::F16F16F16F16)),
+            size: size,
+        })
+    }
+}
+
+/// The scale the scene is drawn at to have `samples` per pixel, if it's a
+/// square number no more than the post pass averages.
+pub fn supersampling_scale(samples: u32) -> Option<u32> {
+    (1..MAX_SUPERSAMPLING_SCALE + 1).find(|scale| scale * scale == samples)
+}
+
+/// Adds colors premultiplied by their alpha to what's behind them, which is
+/// dimmed by one minus the alpha, as the alpha adds up.
+pub fn premultiplied_blending() -> Blend {
+    Blend {
+        color: BlendingFunction::Addition {
+            source: LinearBlendingFactor::One,
+            destination: LinearBlendingFactor::OneMinusSourceAlpha,
+        },
+        alpha: BlendingFunction::Addition {
+            source: LinearBlendingFactor::One,
+            destination: LinearBlendingFactor::OneMinusSourceAlpha,
+        },
+        constant_value: (0.0, 0.0, 0.0, 0.0),
+    }
+}
+
+/// `texture` sampled per texel, as the targets are the size of the screen.
+pub fn screen_sampler(texture: &Texture2d) -> Sampler<Texture2d> {
+    texture
+        .sampled()
+        .minify_filter(MinifySamplerFilter::Nearest)
+        .magnify_filter(MagnifySamplerFilter::Nearest)
+}
+
+/// `texture` filtered between its texels, as it's smaller than the screen.
+fn linear_sampler(texture: &Texture2d) -> Sampler<Texture2d> {
+    texture
+        .sampled()
+        .wrap_function(SamplerWrapFunction::Clamp)
+        .minify_filter(MinifySamplerFilter::Linear)
+        .magnify_filter(MagnifySamplerFilter::Linear)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::{Matrix4f, Vec3f};
+
+    use super::{supersampling_scale, LightShafts};
+
+    #[test]
+    fn test_light_shafts_follow_the_sun_on_the_screen() {
+        let projection = Matrix4f::from(
+            &[
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 1.0],
+                [0.0, 0.0, -0.2, 0.0],
+            ],
+        );
+        let ahead = LightShafts::new(&projection, &Vec3f::new(0.0, 0.0, 1.0), 1.0).unwrap();
+        assert_eq!([0.5, 0.5], ahead.sun);
+        let right = LightShafts::new(&projection, &Vec3f::new(0.5, 0.0, 1.0), 1.0).unwrap();
+        assert_eq!([0.75, 0.5], right.sun);
+        assert!(LightShafts::new(&projection, &Vec3f::new(0.0, 0.0, -1.0), 1.0).is_none());
+        assert!(LightShafts::new(&projection, &Vec3f::new(0.0, 0.0, 1.0), 0.0).is_none());
+    }
+
+    #[test]
+    fn test_supersampling_scale() {
+        assert_eq!(Some(1), supersampling_scale(1));
+        assert_eq!(Some(2), supersampling_scale(4));
+        assert_eq!(Some(4), supersampling_scale(16));
+        assert_eq!(None, supersampling_scale(0));
+        assert_eq!(None, supersampling_scale(8));
+        assert_eq!(None, supersampling_scale(25));
+    }
+}
+
+// Shared by the passes drawing quads over the screen.
+pub const QUAD_VERTEX_SHADER: &'static str = "shaders/quad.vert";
+// Functions shared by the shaders tracing rays through spheres, see
+// `Window::program_with_library`.
+pub const SPHERE_SHADER_LIBRARY: &'static str = "shaders/sphere.glsl";
+const POST_FRAGMENT_SHADER: &'static str = "shaders/post.frag";
+const LUMINANCE_FRAGMENT_SHADER: &'static str = "shaders/luminance.frag";
+const ADAPT_FRAGMENT_SHADER: &'static str = "shaders/adapt.frag";
+const BRIGHT_FRAGMENT_SHADER: &'static str = "shaders/bright.frag";
+const BLUR_FRAGMENT_SHADER: &'static str = "shaders/blur.frag";
+const SHAFT_MASK_FRAGMENT_SHADER: &'static str = "shaders/shaft_mask.frag";
+const SHAFTS_FRAGMENT_SHADER: &'static str = "shaders/shafts.frag";
+
+// Texels across the luminances the scene is reduced to, each averaging a
+// block of it.
+const LUMINANCE_SIZE: u32 = 32;
+// How fast the eye adapts, the fraction of the way left to the brightness of
+// the scene being 1/e after 1 / ADAPTATION_RATE seconds.
+const ADAPTATION_RATE: f32 = 1.5;
+// Levels of the bloom, as many as post.frag adds up.
+const BLOOM_LEVELS: usize = 4;
+// The most texels across a pixel post.frag averages the scene over.
+const MAX_SUPERSAMPLING_SCALE: u32 = 4;
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use glium::texture::RawImage2d;
+use image::{self, ImageBuffer, RgbaImage};
+
+use errors::{ChainErr, Result};
+use gfx::Window;
+use utils::timestamp_millis;
+
+/// Converts a bottom-to-top glium image into a top-to-bottom `RgbaImage`.
+pub fn raw_to_image(raw: RawImage2d<u8>) -> RgbaImage {
+    let RawImage2d {
+        data,
+        width,
+        height,
+        ..
+    } = raw;
+    let image: RgbaImage = ImageBuffer::from_raw(width, height, data.into_owned()).expect(
+        "Framebuffer size does not match its dimensions.",
+    );
+    image::imageops::flip_vertical(&image)
+}
+
+/// Saves the last presented frame as `screenshot-<unix time ms>.png` in
+/// `directory`. Encoding happens on a background thread; the returned path is
+/// where the file will be written.
+pub fn save_screenshot(window: &Window, directory: &Path) -> PathBuf {
+    let raw: RawImage2d<'static, u8> = window.facade().read_front_buffer();
+    let path = directory.join(format!("screenshot-{}.png", timestamp_millis()));
+    let thread_path = path.clone();
+    thread::spawn(move || if let Err(err) = save_image(&raw_to_image(raw), &thread_path) {
+        error!("{}", err);
+    });
+    path
+}
+
+pub fn save_image(image: &RgbaImage, path: &Path) -> Result<()> {
+    image.save(path).chain_err(|| format!("Could not save image to {:?}", path))
+}
@@ -0,0 +1,301 @@
+use std::cmp;
+
+use glium::{Depth, DrawParameters, Program, Rect, Surface};
+use glium::draw_parameters::DepthTest;
+use glium::framebuffer::SimpleFrameBuffer;
+use glium::texture::{DepthFormat, DepthTexture2d, MipmapsOption};
+use nalgebra::{Cross, Dot, Inverse, Norm, Vector3, Vector4};
+use num::Zero;
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+use math::{Matrix4f, Vec3f};
+use super::lod::Chunk;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShadowSpec {
+    /// Texels across each cascade of the shadow map, no shadows if 0.
+    pub map_size: u32,
+    /// How many maps the view is split into along its depth, from 1 to 4,
+    /// each wider than the one before for the terrain further away to be
+    /// shadowed at a coarser resolution.
+    pub num_cascades: usize,
+    /// How far from the camera the terrain casts and receives shadows, in
+    /// world units. That further is lit as if nothing stood in the way.
+    pub distance: f32,
+}
+
+impl Default for ShadowSpec {
+    fn default() -> Self {
+        ShadowSpec {
+            map_size: 2048,
+            num_cascades: 4,
+            distance: 4000.0,
+        }
+    }
+}
+
+/// The depths of the chunks in view as seen from the sun, for the terrain's
+/// shader to tell which of it the sun doesn't reach. The view is split into
+/// cascades by the distance from the camera, each fitted with a sphere
+/// around its slice of the view frustum. The sun is far away in a direction,
+/// so the chunks are projected orthographically along it into a box around
+/// each sphere, in a map side by side with those of the other cascades.
+/// Disabled, the map is a texel the shader is told to ignore.
+pub struct ShadowMap<'a> {
+    depth_texture: DepthTexture2d,
+    program: Program,
+    draw_parameters: DrawParameters<'a>,
+    spec: ShadowSpec,
+    light_matrices: [Matrix4f; MAX_CASCADES],
+}
+
+impl<'a> ShadowMap<'a> {
+    pub fn new(window: &Window, assets: &Assets, spec: &ShadowSpec) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let num_cascades = cmp::max(cmp::min(spec.num_cascades, MAX_CASCADES), 1) as u32;
+        let depth_texture = try!(
+            DepthTexture2d::empty_with_format(
+                window.facade(),
+                DepthFormat::F32,
+                MipmapsOption::NoMipmap,
+                cmp::max(spec.map_size, 1) * num_cascades,
+                cmp::max(spec.map_size, 1),
+            ).chain_err(|| "Could not create shadow map texture.")
+        );
+        // Both sides of the chunks cast shadows, the sun may be below them.
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let light_matrix = light_matrix(&Vec3f::zero(), &Vec3f::new(0.0, 1.0, 0.0), 1.0, 1);
+        Ok(ShadowMap {
+            depth_texture: depth_texture,
+            program: program,
+            draw_parameters: draw_parameters,
+            spec: spec.clone(),
+            light_matrices: [light_matrix; MAX_CASCADES],
+        })
+    }
+
+    /// The cascades rendered and sampled, none if the shadows are disabled.
+    pub fn num_cascades(&self) -> usize {
+        if self.spec.map_size == 0 {
+            0
+        } else {
+            cmp::max(cmp::min(self.spec.num_cascades, MAX_CASCADES), 1)
+        }
+    }
+
+    /// Renders the `chunks` into the cascades of the view with `perspective`
+    /// and `view_model` matrix, as lit from `sun_direction`, which points
+    /// towards the sun in the chunks' frame.
+    pub fn render(
+        &mut self,
+        window: &Window,
+        chunks: &[&Chunk],
+        perspective: [[f32; 4]; 4],
+        view_model: &Matrix4f,
+        sun_direction: &Vec3f,
+    ) -> Result<()> {
+        let num_cascades = self.num_cascades();
+        let camera_to_chunks = match view_model.inverse() {
+            Some(inverse) => inverse,
+            None => return Ok(()),
+        };
+        // The view is along z, its width and height growing by these with
+        // the depth.
+        let tangents = (1.0 / perspective[0][0], 1.0 / perspective[1][1]);
+        let sun_direction = Vec3f::from(sun_direction.normalize());
+        let map_size = self.spec.map_size;
+
+        let mut target = try!(
+            SimpleFrameBuffer::depth_only(window.facade(), &self.depth_texture)
+                .chain_err(|| "Could not create shadow map framebuffer.")
+        );
+        target.clear_depth(1.0);
+        let mut start = 0.0;
+        let splits = cascade_splits(CASCADES_NEAR, self.spec.distance, num_cascades);
+        for (cascade, &end) in splits.iter().enumerate() {
+            let (centre_depth, radius) = slice_sphere(start, end, tangents);
+            start = end;
+            let centre = camera_to_chunks * Vector4::new(0.0, 0.0, centre_depth, 1.0);
+            let centre = Vec3f::new(centre[0], centre[1], centre[2]);
+            let light_matrix = light_matrix(&centre, &sun_direction, radius, map_size);
+            self.light_matrices[cascade] = light_matrix;
+
+            let uniforms = uniform! { u_light_matrix: &self.light_matrices[cascade] };
+            let draw_parameters = DrawParameters {
+                viewport: Some(Rect {
+                    left: map_size * cascade as u32,
+                    bottom: 0,
+                    width: map_size,
+                    height: map_size,
+                }),
+                ..self.draw_parameters.clone()
+            };
+            let depth = radius * SHADOW_DEPTH_SCALE;
+            for chunk in chunks.iter() {
+                // Those beside or behind the box of the cascade, as seen from
+                // the sun, can't shadow any of it.
+                let sphere = &chunk.bounding_sphere;
+                let offset = Vec3f::from(sphere.center().to_vector()) - centre;
+                let along = offset.dot(&sun_direction);
+                let across = (offset.norm_squared() - along * along).max(0.0).sqrt();
+                let reach = radius + sphere.radius();
+                if across > reach || along < -reach || along > depth + sphere.radius() {
+                    continue;
+                }
+                try!(
+                    target
+                        .draw(
+                            &chunk.vertex_buffer,
+                            &chunk.index_buffer,
+                            &self.program,
+                            &uniforms,
+                            &draw_parameters,
+                        )
+                        .chain_err(|| "Could not render the shadow map.")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes positions in the chunks' frame to the map of `cascade`, in
+    /// [-1, 1].
+    pub fn light_matrix(&self, cascade: usize) -> &Matrix4f {
+        &self.light_matrices[cascade]
+    }
+
+    /// The cascades side by side, from the nearest.
+    pub fn depth_texture(&self) -> &DepthTexture2d {
+        &self.depth_texture
+    }
+}
+
+/// The distances from the camera where each of `num_cascades` cascades up to
+/// `far` ends, between splitting the depth evenly and logarithmically from
+/// `near`, so the nearest cascades are small without the furthest covering
+/// most of the view.
+fn cascade_splits(near: f32, far: f32, num_cascades: usize) -> Vec<f32> {
+    (1..num_cascades + 1)
+        .map(|cascade| {
+            let fraction = cascade as f32 / num_cascades as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let even = near + (far - near) * fraction;
+            CASCADE_SPLIT_BLEND * logarithmic + (1.0 - CASCADE_SPLIT_BLEND) * even
+        })
+        .collect()
+}
+
+/// The depth of the centre and the radius of the smallest sphere around the
+/// view frustum between the depths `start` and `end`, whose width and height
+/// grow by `tangents` with the depth. Unlike a box, it stays the same as the
+/// camera turns, so the shadows don't shimmer.
+fn slice_sphere(start: f32, end: f32, tangents: (f32, f32)) -> (f32, f32) {
+    let spread = tangents.0 * tangents.0 + tangents.1 * tangents.1;
+    // Equally far from the corners at both ends, unless that's past the end.
+    let centre = ((start + end) * (1.0 + spread) / 2.0).min(end);
+    let radius = (end * end * spread + (end - centre) * (end - centre)).sqrt();
+    (centre, radius)
+}
+
+/// The orthographic projection along `direction` of the box `radius` around
+/// `centre`, its depth deeper towards the sun to catch the shadows cast from
+/// outside it. The centre is snapped to the texels of a map of `map_size`,
+/// so the edges of the shadows don't crawl as the camera moves.
+fn light_matrix(centre: &Vec3f, direction: &Vec3f, radius: f32, map_size: u32) -> Matrix4f {
+    let forward = direction.normalize();
+    let helper = if forward[1].abs() < 0.9 {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let right = helper.cross(&forward).normalize();
+    let up = forward.cross(&right);
+    let texel = 2.0 * radius / map_size.max(1) as f32;
+    let snap = |offset: f32| (offset / texel).round() * texel;
+    let (x, y, z) = (snap(right.dot(centre)), snap(up.dot(centre)), forward.dot(centre));
+    let depth = radius * SHADOW_DEPTH_SCALE;
+    // In columns, taking the points nearer the sun to the nearer depths.
+    Matrix4f::from(&[
+        [right[0] / radius, up[0] / radius, -forward[0] / depth, 0.0],
+        [right[1] / radius, up[1] / radius, -forward[1] / depth, 0.0],
+        [right[2] / radius, up[2] / radius, -forward[2] / depth, 0.0],
+        [-x / radius, -y / radius, z / depth, 1.0],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector4;
+
+    use math::{Matrix4f, Vec3f};
+    use super::{cascade_splits, light_matrix, slice_sphere};
+
+    #[test]
+    fn test_light_matrix_projects_along_the_sun() {
+        let centre = Vec3f::new(100.0, 5000.0, -20.0);
+        let sun = Vec3f::new(1.0, 1.0, 0.0);
+        let matrix = light_matrix(&centre, &sun, 50.0, 1 << 20);
+        let project = |matrix: &Matrix4f, point: Vec3f| {
+            **matrix * Vector4::new(point[0], point[1], point[2], 1.0)
+        };
+
+        let projected = project(&matrix, centre);
+        for axis in 0..3 {
+            assert!(projected[axis].abs() < 1e-3, "{:?}", projected);
+        }
+        // Along the sun, only the depth changes, nearer towards it.
+        let towards_sun = project(&matrix, centre + sun * 10.0);
+        assert!((towards_sun[0] - projected[0]).abs() < 1e-3);
+        assert!((towards_sun[1] - projected[1]).abs() < 1e-3);
+        assert!(towards_sun[2] < projected[2]);
+        // And the edges of the box are at the edges of the map.
+        let side = project(&matrix, centre + Vec3f::new(0.0, 0.0, 50.0));
+        assert!((side[0].abs().max(side[1].abs()) - 1.0).abs() < 1e-3, "{:?}", side);
+    }
+
+    #[test]
+    fn test_cascades_cover_the_view_frustum() {
+        let splits = cascade_splits(1.0, 4000.0, 4);
+        assert_eq!(4, splits.len());
+        assert!((splits[3] - 4000.0).abs() < 1e-2, "{:?}", splits);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", splits);
+        // The nearest are much smaller than an even split.
+        assert!(splits[0] < 1000.0 / 4.0, "{:?}", splits);
+
+        let tangents = (0.9, 0.6);
+        let mut start = 0.0;
+        for &end in splits.iter() {
+            let (centre, radius) = slice_sphere(start, end, tangents);
+            for &depth in [start, end].iter() {
+                let (x, y) = (depth * tangents.0, depth * tangents.1);
+                let corner = (x * x + y * y + (depth - centre) * (depth - centre)).sqrt();
+                assert!(corner <= radius * 1.0001, "{} > {}", corner, radius);
+            }
+            start = end;
+        }
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/shadow.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/shadow.frag";
+
+// How much deeper than wide the box of each cascade is.
+const SHADOW_DEPTH_SCALE: f32 = 4.0;
+// As many as the terrain's shader samples.
+const MAX_CASCADES: usize = 4;
+// The distance the cascades are split logarithmically from, and how much
+// they are rather than evenly.
+const CASCADES_NEAR: f32 = 1.0;
+const CASCADE_SPLIT_BLEND: f32 = 0.9;
@@ -1,32 +1,194 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Cursor, Read};
 use std::path::Path;
-use std::time::Instant;
 use std::fmt::Debug;
 use glium::{BlitTarget, DrawParameters, Frame, Program, Rect, Surface, IndexBuffer, VertexBuffer};
-use glium::draw_parameters::BackfaceCullingMode;
+use glium::draw_parameters::{BackfaceCullingMode, TimeElapsedQuery};
 use glium::framebuffer::SimpleFrameBuffer;
-use glium::index::PrimitiveType;
-use glium::texture::{CubeLayer, Cubemap, RawImage2d, Texture2d};
-use glium::uniforms::MagnifySamplerFilter;
-use image;
+use glium::index::{NoIndices, PrimitiveType};
+use glium::texture::{ClientFormat, CubeLayer, Cubemap, MipmapsOption, PixelValue, RawImage2d,
+                     Texture2d, UncompressedFloatFormat};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerWrapFunction};
+use glium::vertex::EmptyVertexAttributes;
+use image::{self, imageops, ImageBuffer, Pixel, Rgb, RgbImage};
+use image::hdr::HDRDecoder;
 use nalgebra::{PerspectiveMatrix3, Translation};
 
+use assets::Assets;
 use errors::{ChainErr, Result};
-use gfx::{Camera, Window};
+use gfx::{Camera, Window, FIELD_OF_VIEW};
+use gfx::cubemap_file::CubemapFile;
 use gfx::mesh::PlainVertex;
 use math::{GpuScalar, Vec3f};
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkyboxSpec {
+    /// The asset the faces are loaded from, see `SkyboxRenderer::load`.
+    pub path: String,
+    /// How the image of each face is turned, in the order of `FACE_NAMES`.
+    pub faces: [FaceOrientation; 6],
+}
+
+impl Default for SkyboxSpec {
+    fn default() -> Self {
+        SkyboxSpec {
+            path: "skybox-galaxy.jpg".to_owned(),
+            faces: [FaceOrientation::default(); 6],
+        }
+    }
+}
+
+/// Turns the image of a face, for the skyboxes made for engines whose
+/// cubemaps are oriented differently: rotated clockwise by `rotation`
+/// degrees, a multiple of 90, and then flipped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FaceOrientation {
+    pub rotation: u32,
+    /// Left to right, and top to bottom.
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl FaceOrientation {
+    pub fn apply<P>(
+        &self,
+        image: ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> ImageBuffer<P, Vec<P::Subpixel>>
+    where
+        P: Pixel + 'static,
+        P::Subpixel: 'static,
+    {
+        let image = match self.rotation % 360 {
+            90 => imageops::rotate90(&image),
+            180 => imageops::rotate180(&image),
+            270 => imageops::rotate270(&image),
+            _ => image,
+        };
+        let image = if self.flip_x { imageops::flip_horizontal(&image) } else { image };
+        if self.flip_y { imageops::flip_vertical(&image) } else { image }
+    }
+}
+
+/// How the six faces are laid out in a single image, told apart by its
+/// size. The crosses unfold the cube around the front face, `posz`, with
+/// `posy` above it and `negy` below; the strips have the faces side by side
+/// in the order of `FACE_NAMES`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkyboxLayout {
+    /// 4×3 faces, `negx`, `posz`, `posx` and `negz` across the middle.
+    HorizontalCross,
+    /// 3×4 faces, with `negz` upside down below `negy`.
+    VerticalCross,
+    /// 6×1 faces.
+    HorizontalStrip,
+    /// 1×6 faces.
+    VerticalStrip,
+}
+
+impl SkyboxLayout {
+    pub fn detect(width: u32, height: u32) -> Option<Self> {
+        if width == 0 || height == 0 {
+            None
+        } else if width * 3 == height * 4 {
+            Some(SkyboxLayout::HorizontalCross)
+        } else if width * 4 == height * 3 {
+            Some(SkyboxLayout::VerticalCross)
+        } else if width == height * 6 {
+            Some(SkyboxLayout::HorizontalStrip)
+        } else if height == width * 6 {
+            Some(SkyboxLayout::VerticalStrip)
+        } else {
+            None
+        }
+    }
+
+    /// The faces across and down the image.
+    pub fn grid(&self) -> (u32, u32) {
+        match *self {
+            SkyboxLayout::HorizontalCross => (4, 3),
+            SkyboxLayout::VerticalCross => (3, 4),
+            SkyboxLayout::HorizontalStrip => (6, 1),
+            SkyboxLayout::VerticalStrip => (1, 6),
+        }
+    }
 
+    /// The column and row, from the top left, of each face in the order of
+    /// `FACE_NAMES`, and whether it's upside down.
+    pub fn cells(&self) -> [(u32, u32, bool); 6] {
+        match *self {
+            SkyboxLayout::HorizontalCross => [
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (3, 1, false),
+            ],
+            SkyboxLayout::VerticalCross => [
+                (2, 1, false),
+                (0, 1, false),
+                (1, 0, false),
+                (1, 2, false),
+                (1, 1, false),
+                (1, 3, true),
+            ],
+            SkyboxLayout::HorizontalStrip => [
+                (0, 0, false),
+                (1, 0, false),
+                (2, 0, false),
+                (3, 0, false),
+                (4, 0, false),
+                (5, 0, false),
+            ],
+            SkyboxLayout::VerticalStrip => [
+                (0, 0, false),
+                (0, 1, false),
+                (0, 2, false),
+                (0, 3, false),
+                (0, 4, false),
+                (0, 5, false),
+            ],
+        }
+    }
+}
+
+/// The faces of the cube in floats, e.g. in HDR.
+type HdrImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
+
+/// What the faces of the cube are made from, read from a skybox's files.
+enum SkyboxSource {
+    Faces(Vec<RgbImage>),
+    /// And whether they're in HDR, rather than scaled to [0, 1].
+    FloatFaces(Vec<HdrImage>, bool),
+    /// The whole sky in HDR, its longitude across and latitude down.
+    Equirectangular(HdrImage),
+}
+
+/// The sky behind everything, a cube around the camera. It's kept in half
+/// floats, so skies in HDR keep their range, tonemapped as they're drawn.
 pub struct SkyboxRenderer<'a> {
     cubemap: Cubemap,
+    hdr: bool,
     draw_parameters: DrawParameters<'a>,
     program: Program,
+    equirectangular_program: Program,
     vertex_buffer: VertexBuffer<PlainVertex>,
     index_buffer: IndexBuffer<u32>,
     perspective: PerspectiveMatrix3<GpuScalar>,
 }
 
 impl<'a> SkyboxRenderer<'a> {
-    pub fn new(window: &Window) -> Result<Self> {
-        let program = try!(window.program(&VERTEX_SHADER, &FRAGMENT_SHADER));
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let equirectangular_program = try!(window.program(
+            try!(assets.resolve(EQUIRECTANGULAR_VERTEX_SHADER)),
+            try!(assets.resolve(EQUIRECTANGULAR_FRAGMENT_SHADER)),
+        ));
         let params = DrawParameters {
             backface_culling: BackfaceCullingMode::CullingDisabled,
             ..Default::default()
@@ -49,145 +211,87 @@ impl<'a> SkyboxRenderer<'a> {
 
         let perspective = perspective_matrix(window.aspect());
         Ok(SkyboxRenderer {
-            cubemap: try!(Cubemap::empty(window.facade(), 1024).chain_err(
-                || "Could not create cubemap texture.",
-            )),
+            cubemap: try!(
+                Cubemap::empty_with_format(
+                    window.facade(),
+                    UncompressedFloatFormat::F16F16F16,
+                    MipmapsOption::NoMipmap,
+                    CUBEMAP_SIZE,
+                ).chain_err(|| "Could not create cubemap texture.")
+            ),
+            hdr: false,
             draw_parameters: params,
             program: program,
+            equirectangular_program: equirectangular_program,
             index_buffer: index_buffer,
             vertex_buffer: vertex_buffer,
             perspective: perspective,
         })
     }
 
-    pub fn load<P>(&mut self, window: &Window, path: P) -> Result<()>
+    /// Loads the faces of the cube from `path`, which is one of:
+    ///
+    /// - a directory with an image for each face, named like `posx.png`,
+    ///   `px.jpg` or `right.png`;
+    /// - a DDS or KTX cubemap, uncompressed and in HDR if in floats;
+    /// - a Radiance HDR map of the whole sky, equirectangular, projected onto
+    ///   the faces on the GPU;
+    /// - or an image with all of the faces, laid out as told by its size, see
+    ///   `SkyboxLayout`.
+    ///
+    /// The kind of file is told by its first bytes rather than its extension.
+    /// Each face but those of a projected map is then turned as given by
+    /// `orientations`, in the order of `FACE_NAMES`.
+    pub fn load<P>(
+        &mut self,
+        window: &Window,
+        path: P,
+        orientations: &[FaceOrientation; 6],
+    ) -> Result<()>
     where
         P: AsRef<Path> + Debug,
     {
-        let instant = Instant::now();
-        let image = try!(image::open(path.as_ref()).chain_err(|| {
-            format!("Could not load image at {:?}", path)
-        })).to_rgb();
-        info!("to_rgba - elapsed {:?}", instant.elapsed());
-
-        let (width, height) = image.dimensions();
-        info!(
-            "Loaded Skybox asset with width={:?} height={:?} path={:?}",
-            width,
-            height,
-            path
-        );
-        assert!((width / 4) as u32 == (height / 3) as u32);
-        let step = (height / 3) as u32;
-        info!("step: {}", step);
-
-        let image = RawImage2d::from_raw_rgb(image.into_raw(), (width, height));
-        info!(
-            "RawImage2d::from_raw_rgba - elapsed {:?}",
-            instant.elapsed()
-        );
-        let source_tex = try!(Texture2d::new(window.facade(), image).chain_err(|| {
-            format!("Could not create texture from {:?}", path)
-        }));
-        info!("Texture2d::new() - elapsed {:?}", instant.elapsed());
-
-
-        let target_rect = BlitTarget {
-            left: 0,
-            bottom: 0,
-            width: 1024,
-            height: 1024,
-        };
-
-        let source_rect = Rect {
-            left: step,
-            bottom: 0,
-            width: step,
-            height: step,
-        };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::PositiveY));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-        let source_rect = Rect {
-            left: step,
-            bottom: step,
-            width: step,
-            height: step,
-        };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::PositiveZ));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-        let source_rect = Rect {
-            left: step,
-            bottom: step * 2,
-            width: step,
-            height: step,
-        };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::NegativeY));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-        let source_rect = Rect {
-            left: step * 2,
-            bottom: step,
-            width: step,
-            height: step,
-        };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::PositiveX));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-
-        let source_rect = Rect {
-            left: step * 3,
-            bottom: step,
-            width: step,
-            height: step,
-        };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::NegativeZ));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-
-        let source_rect = Rect {
-            left: 0,
-            bottom: step,
-            width: step,
-            height: step,
+        let _span = trace::span("load_skybox");
+        let source = {
+            let _span = trace::span("decode_skybox");
+            try!(read_skybox(path.as_ref()))
         };
-        let cube_face = try!(self.surface_for_face(window, CubeLayer::NegativeX));
-        source_tex.as_surface().blit_color(
-            &source_rect,
-            &cube_face,
-            &target_rect,
-            MagnifySamplerFilter::Linear,
-        );
-        info!("Blit - elapsed {:?}", instant.elapsed());
-
+        let _span = trace::span("upload_skybox");
+        match source {
+            SkyboxSource::Faces(faces) => {
+                self.hdr = false;
+                for (index, face) in faces.into_iter().enumerate() {
+                    let face = orientations[index].apply(face);
+                    let (width, height) = face.dimensions();
+                    let face = RawImage2d::from_raw_rgb(face.into_raw(), (width, height));
+                    try!(self.upload_face(window, CUBE_LAYERS[index], face));
+                }
+            }
+            SkyboxSource::FloatFaces(faces, hdr) => {
+                self.hdr = hdr;
+                for (index, face) in faces.into_iter().enumerate() {
+                    let face = float_image(orientations[index].apply(face));
+                    try!(self.upload_face(window, CUBE_LAYERS[index], face));
+                }
+            }
+            SkyboxSource::Equirectangular(map) => {
+                self.hdr = true;
+                try!(self.project_equirectangular(window, map));
+            }
+        }
         Ok(())
     }
 
     #[inline]
-    pub fn render(&mut self, frame: &mut Frame, camera: &Camera) -> Result<()> {
+    pub fn render(
+        &mut self,
+        frame: &mut Frame,
+        camera: &Camera,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
         let SkyboxRenderer {
             ref cubemap,
+            hdr,
             ref draw_parameters,
             ref program,
             ref vertex_buffer,
@@ -218,8 +322,14 @@ impl<'a> SkyboxRenderer<'a> {
             perspective: perspective_matrix2(&frame),
             view: view,
             skybox: cubemap.sampled().magnify_filter(MagnifySamplerFilter::Linear),
+            u_hdr: hdr,
         };
 
+        let _span = trace::span("draw_skybox");
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..draw_parameters.clone()
+        };
         try!(
             frame
                 .draw(
@@ -227,7 +337,7 @@ impl<'a> SkyboxRenderer<'a> {
                     index_buffer,
                     program,
                     &uniforms,
-                    draw_parameters,
+                    &draw_parameters,
                 )
                 .chain_err(|| "Could not render skybox.")
         );
@@ -235,6 +345,79 @@ impl<'a> SkyboxRenderer<'a> {
         Ok(())
     }
 
+    /// Stretches `image` over the whole of the cube's `face`.
+    fn upload_face<T>(&self, window: &Window, face: CubeLayer, image: RawImage2d<T>) -> Result<()>
+    where
+        T: PixelValue + Clone,
+    {
+        let (width, height) = (image.width, image.height);
+        let source_tex = try!(
+            Texture2d::with_format(
+                window.facade(),
+                image,
+                UncompressedFloatFormat::F16F16F16,
+                MipmapsOption::NoMipmap,
+            ).chain_err(|| format!("Could not create the texture of {:?}", face))
+        );
+        let source_rect = Rect {
+            left: 0,
+            bottom: 0,
+            width: width,
+            height: height,
+        };
+        let target_rect = BlitTarget {
+            left: 0,
+            bottom: 0,
+            width: CUBEMAP_SIZE as i32,
+            height: CUBEMAP_SIZE as i32,
+        };
+        let cube_face = try!(self.surface_for_face(window, face));
+        source_tex.as_surface().blit_color(
+            &source_rect,
+            &cube_face,
+            &target_rect,
+            MagnifySamplerFilter::Linear,
+        );
+        Ok(())
+    }
+
+    /// Draws each face of the cube with the directions through its texels
+    /// looked up in `map`, of the whole sky.
+    fn project_equirectangular(&self, window: &Window, map: HdrImage) -> Result<()> {
+        let map = try!(
+            Texture2d::with_format(
+                window.facade(),
+                float_image(map),
+                UncompressedFloatFormat::F16F16F16,
+                MipmapsOption::AutoGeneratedMipmaps,
+            ).chain_err(|| "Could not create the texture of the equirectangular sky.")
+        );
+        for (index, &face) in CUBE_LAYERS.iter().enumerate() {
+            let uniforms =
+                uniform! {
+                u_map: map.sampled()
+                    .wrap_function(SamplerWrapFunction::Repeat)
+                    .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+                    .magnify_filter(MagnifySamplerFilter::Linear),
+                u_face: index as i32,
+                u_size: CUBEMAP_SIZE as f32,
+            };
+            let mut cube_face = try!(self.surface_for_face(window, face));
+            try!(
+                cube_face
+                    .draw(
+                        EmptyVertexAttributes { len: 3 },
+                        NoIndices(PrimitiveType::TrianglesList),
+                        &self.equirectangular_program,
+                        &uniforms,
+                        &Default::default(),
+                    )
+                    .chain_err(|| format!("Could not project the sky onto {:?}", face))
+            );
+        }
+        Ok(())
+    }
+
     #[inline]
     fn surface_for_face(&self, window: &Window, face: CubeLayer) -> Result<SimpleFrameBuffer> {
         SimpleFrameBuffer::new(window.facade(), self.cubemap.main_level().image(face))
@@ -242,24 +425,159 @@ impl<'a> SkyboxRenderer<'a> {
     }
 }
 
+/// What the faces of the skybox at `path` are made from, told by the first
+/// bytes of the file if it's not a directory.
+fn read_skybox(path: &Path) -> Result<SkyboxSource> {
+    if path.is_dir() {
+        return read_faces(path).map(SkyboxSource::Faces);
+    }
+    let mut bytes = vec![];
+    try!(
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .chain_err(|| format!("Could not read the skybox at {:?}", path))
+    );
+    if CubemapFile::is_cubemap_file(&bytes) {
+        let cubemap = try!(CubemapFile::read(&bytes).chain_err(|| {
+            format!("Could not load the cubemap at {:?}", path)
+        }));
+        info!(
+            "Loaded Skybox cubemap with size={:?} hdr={:?} path={:?}",
+            cubemap.size,
+            cubemap.hdr,
+            path
+        );
+        let size = cubemap.size;
+        let faces = cubemap
+            .faces
+            .into_iter()
+            .map(|face| ImageBuffer::from_raw(size, size, face).unwrap())
+            .collect();
+        Ok(SkyboxSource::FloatFaces(faces, cubemap.hdr))
+    } else if bytes.starts_with(RADIANCE_MAGIC) {
+        let decoder = try!(HDRDecoder::new(Cursor::new(&bytes[..])).chain_err(|| {
+            format!("Could not load the HDR map at {:?}", path)
+        }));
+        let (width, height) = {
+            let metadata = decoder.metadata();
+            (metadata.width, metadata.height)
+        };
+        let texels = try!(decoder.read_image_hdr().chain_err(|| {
+            format!("Could not load the HDR map at {:?}", path)
+        }));
+        info!(
+            "Loaded Skybox HDR map with width={:?} height={:?} path={:?}",
+            width,
+            height,
+            path
+        );
+        let texels = texels.iter().flat_map(|texel| texel.data.iter().cloned()).collect();
+        Ok(SkyboxSource::Equirectangular(
+            ImageBuffer::from_raw(width, height, texels).unwrap(),
+        ))
+    } else {
+        split_faces(path, &bytes).map(SkyboxSource::Faces)
+    }
+}
+
+/// `image` to upload, in floats.
+fn float_image(image: HdrImage) -> RawImage2d<'static, f32> {
+    let (width, height) = image.dimensions();
+    RawImage2d {
+        data: Cow::Owned(image.into_raw()),
+        width: width,
+        height: height,
+        format: ClientFormat::F32F32F32,
+    }
+}
+
+/// The images of the faces in the directory `path`, in the order of
+/// `FACE_NAMES`, named after any of the conventions in `FACE_FILE_NAMES`.
+fn read_faces(path: &Path) -> Result<Vec<RgbImage>> {
+    let mut faces = Vec::with_capacity(6);
+    for names in FACE_FILE_NAMES.iter() {
+        let face_path = names
+            .iter()
+            .flat_map(|name| {
+                FACE_EXTENSIONS.iter().map(move |extension| {
+                    path.join(format!("{}.{}", name, extension))
+                })
+            })
+            .find(|face_path| face_path.is_file());
+        let face_path = match face_path {
+            Some(face_path) => face_path,
+            None => {
+                return Err(
+                    format!("Could not find an image of the {} face in {:?}", names[0], path)
+                        .into(),
+                )
+            }
+        };
+        faces.push(try!(image::open(&face_path).chain_err(|| {
+            format!("Could not load image at {:?}", face_path)
+        })).to_rgb());
+    }
+    info!("Loaded Skybox faces from path={:?}", path);
+    Ok(faces)
+}
+
+/// The images of the faces laid out in the single image in `bytes`, read
+/// from `path`, in the order of `FACE_NAMES`.
+fn split_faces(path: &Path, bytes: &[u8]) -> Result<Vec<RgbImage>> {
+    let mut image = try!(image::load_from_memory(bytes).chain_err(|| {
+        format!("Could not load image at {:?}", path)
+    })).to_rgb();
+    let (width, height) = image.dimensions();
+    let layout = match SkyboxLayout::detect(width, height) {
+        Some(layout) => layout,
+        None => {
+            return Err(
+                format!(
+                    "The skybox at {:?} is {}x{}, neither a cross nor a strip of square faces",
+                    path,
+                    width,
+                    height
+                ).into(),
+            )
+        }
+    };
+    info!(
+        "Loaded Skybox asset with width={:?} height={:?} layout={:?} path={:?}",
+        width,
+        height,
+        layout,
+        path
+    );
+    let step = width / layout.grid().0;
+    Ok(
+        layout
+            .cells()
+            .iter()
+            .map(|&(column, row, upside_down)| {
+                let face = imageops::crop(&mut image, column * step, row * step, step, step)
+                    .to_image();
+                if upside_down { imageops::rotate180(&face) } else { face }
+            })
+            .collect(),
+    )
+}
+
 #[inline]
 fn perspective_matrix(aspect: GpuScalar) -> PerspectiveMatrix3<GpuScalar> {
     let aspect = aspect;
-    let fov = 3.141592 / 3.0;
     let zfar = 10.0;
     let znear = 0.1;
-    PerspectiveMatrix3::new(aspect, fov, znear, zfar)
+    PerspectiveMatrix3::new(aspect, FIELD_OF_VIEW, znear, zfar)
 }
 
 fn perspective_matrix2(frame: &Frame) -> [[f32; 4]; 4] {
     let (width, height) = frame.get_dimensions();
     let aspect_ratio = height as f32 / width as f32;
 
-    let fov: f32 = 3.141592 / 3.0;
     let zfar = 10.0;
     let znear = 0.1;
 
-    let f = 1.0 / (fov / 2.0).tan();
+    let f = 1.0 / (FIELD_OF_VIEW / 2.0).tan();
 
     [
         [f * aspect_ratio, 0.0, 0.0, 0.0],
@@ -275,8 +593,81 @@ fn frame_aspect(frame: &Frame) -> GpuScalar {
     height as f32 / width as f32
 }
 
-const VERTEX_SHADER: &'static str = "src/gfx/shaders/skybox.vert";
-const FRAGMENT_SHADER: &'static str = "src/gfx/shaders/skybox.frag";
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb};
+
+    use super::{FaceOrientation, SkyboxLayout};
+
+    #[test]
+    fn test_skybox_layout_is_told_by_its_size() {
+        assert_eq!(Some(SkyboxLayout::HorizontalCross), SkyboxLayout::detect(4096, 3072));
+        assert_eq!(Some(SkyboxLayout::VerticalCross), SkyboxLayout::detect(768, 1024));
+        assert_eq!(Some(SkyboxLayout::HorizontalStrip), SkyboxLayout::detect(1536, 256));
+        assert_eq!(Some(SkyboxLayout::VerticalStrip), SkyboxLayout::detect(256, 1536));
+        assert_eq!(None, SkyboxLayout::detect(1024, 1024));
+        assert_eq!(None, SkyboxLayout::detect(0, 0));
+        for layout in &[SkyboxLayout::HorizontalCross, SkyboxLayout::VerticalCross] {
+            let (columns, rows) = layout.grid();
+            let mut cells: Vec<_> = layout.cells().iter().map(|&(x, y, _)| (x, y)).collect();
+            assert!(cells.iter().all(|&(x, y)| x < columns && y < rows));
+            cells.sort();
+            cells.dedup();
+            assert_eq!(6, cells.len());
+        }
+    }
+
+    #[test]
+    fn test_face_orientation_turns_the_image() {
+        let image = ImageBuffer::from_fn(2, 1, |x, _| Rgb([x as u8, 0, 0]));
+        let rotated = FaceOrientation {
+            rotation: 90,
+            ..Default::default()
+        }.apply(image.clone());
+        assert_eq!((1, 2), rotated.dimensions());
+        assert_eq!(1, rotated.get_pixel(0, 1)[0]);
+        let flipped = FaceOrientation {
+            flip_x: true,
+            ..Default::default()
+        }.apply(image.clone());
+        assert_eq!(1, flipped.get_pixel(0, 0)[0]);
+        let unchanged = FaceOrientation::default().apply(image.clone());
+        assert_eq!(image.into_raw(), unchanged.into_raw());
+    }
+}
+
+/// The faces of the cube, as they're named in the config, each loaded into
+/// the layer with the same index in `CUBE_LAYERS`.
+pub const FACE_NAMES: [&'static str; 6] = ["posx", "negx", "posy", "negy", "posz", "negz"];
+
+const VERTEX_SHADER: &'static str = "shaders/skybox.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/skybox.frag";
+const EQUIRECTANGULAR_VERTEX_SHADER: &'static str = "shaders/equirectangular.vert";
+const EQUIRECTANGULAR_FRAGMENT_SHADER: &'static str = "shaders/equirectangular.frag";
+
+// The size of each face of the cubemap, what the images are stretched to.
+const CUBEMAP_SIZE: u32 = 1024;
+const CUBE_LAYERS: [CubeLayer; 6] = [
+    CubeLayer::PositiveX,
+    CubeLayer::NegativeX,
+    CubeLayer::PositiveY,
+    CubeLayer::NegativeY,
+    CubeLayer::PositiveZ,
+    CubeLayer::NegativeZ,
+];
+// The names the images of the faces may have in a directory, after the
+// conventions of the common tools, with any of the extensions.
+const FACE_FILE_NAMES: [[&'static str; 3]; 6] = [
+    ["posx", "px", "right"],
+    ["negx", "nx", "left"],
+    ["posy", "py", "top"],
+    ["negy", "ny", "bottom"],
+    ["posz", "pz", "front"],
+    ["negz", "nz", "back"],
+];
+const FACE_EXTENSIONS: [&'static str; 5] = ["png", "jpg", "jpeg", "tga", "bmp"];
+// The first bytes of a Radiance HDR file, "#?RADIANCE" or "#?RGBE".
+const RADIANCE_MAGIC: &'static [u8] = b"#?";
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 const SKYBOX_VERTICES: [[f32; 3]; 36] = [
@@ -0,0 +1,325 @@
+use glium::texture::{MipmapsOption, RawImage2d, Texture2dArray};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, Sampler, SamplerWrapFunction};
+use image::{self, FilterType};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use flora::FloraSpec;
+use gfx::Window;
+use planet::PlanetSpec;
+
+/// The materials the terrain is textured with, their values the layers of
+/// `TerrainTextures`, see planet.frag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerrainMaterial {
+    Grass = 0,
+    Rock = 1,
+    Sand = 2,
+    Snow = 3,
+}
+
+impl TerrainMaterial {
+    /// The name of the material's texture in the assets, without extension.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TerrainMaterial::Grass => "grass",
+            TerrainMaterial::Rock => "rock",
+            TerrainMaterial::Sand => "sand",
+            TerrainMaterial::Snow => "snow",
+        }
+    }
+
+    /// How steep the bumps of the material's normal map are, when derived
+    /// from the brightness of its texture.
+    fn relief(&self) -> f32 {
+        match *self {
+            TerrainMaterial::Grass => 2.0,
+            TerrainMaterial::Rock => 6.0,
+            TerrainMaterial::Sand => 1.5,
+            TerrainMaterial::Snow => 1.0,
+        }
+    }
+}
+
+/// Where the materials are splatted on a body, besides the sand and snow of
+/// its field. Grass covers the rock up to the altitude and slope plants
+/// grow to, the rest is bare.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Splatting {
+    /// The radius the altitudes are from.
+    pub base_radius: f32,
+    pub grows_grass: bool,
+    pub grass_line: f32,
+    /// In degrees.
+    pub max_grass_slope: f32,
+}
+
+impl Splatting {
+    /// The grass grows on bodies with plants, up to their shrub line.
+    pub fn new(planet: &PlanetSpec, flora: Option<&FloraSpec>) -> Self {
+        Splatting {
+            base_radius: planet.base_radius,
+            grows_grass: flora.is_some(),
+            grass_line: flora.map_or(0.0, |flora| flora.shrub_line),
+            max_grass_slope: flora.map_or(0.0, |flora| flora.max_slope),
+        }
+    }
+
+    /// Bare rock everywhere, for the asteroids.
+    pub fn barren() -> Self {
+        Splatting {
+            base_radius: 0.0,
+            grows_grass: false,
+            grass_line: 0.0,
+            max_grass_slope: 0.0,
+        }
+    }
+}
+
+/// A layer for each `TerrainMaterial`, which tile as the shader projects
+/// them along the axes over the terrain. Each is loaded from
+/// `textures/<name>.png` in the assets, scaled to the size of the layers, or
+/// generated from noise if there's no such file. The rock is grey, for the
+/// strata to tint it.
+///
+/// The normal maps of the materials, in the same layers, perturb the normals
+/// of the terrain with detail finer than its vertices. They're loaded from
+/// `textures/<name>_normal.png`, or derived from the bumps of the brightness
+/// of the materials. Their normals are in the frame of the projections, each
+/// texel's x and y along the texture's and z out of it.
+pub struct TerrainTextures {
+    layers: Texture2dArray,
+    normals: Texture2dArray,
+}
+
+impl TerrainTextures {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let mut rng = XorShiftRng::from_seed(TEXTURE_SEED);
+        let materials = [
+            TerrainMaterial::Grass,
+            TerrainMaterial::Rock,
+            TerrainMaterial::Sand,
+            TerrainMaterial::Snow,
+        ];
+        let dimensions = (TEXTURE_SIZE as u32, TEXTURE_SIZE as u32);
+        let mut images = Vec::with_capacity(materials.len());
+        let mut normal_images = Vec::with_capacity(materials.len());
+        for &material in materials.iter() {
+            let name = material.name();
+            let pixels = match try!(load_texture(assets, name)) {
+                Some(pixels) => pixels,
+                None => material_pixels(material, TEXTURE_SIZE, &mut rng),
+            };
+            let normals = match try!(load_texture(assets, &format!("{}_normal", name))) {
+                Some(normals) => normals,
+                None => normal_pixels(&pixels, TEXTURE_SIZE, material.relief()),
+            };
+            images.push(RawImage2d::from_raw_rgba(pixels, dimensions));
+            normal_images.push(RawImage2d::from_raw_rgba(normals, dimensions));
+        }
+        let layers = try!(
+            Texture2dArray::with_mipmaps(
+                window.facade(),
+                images,
+                MipmapsOption::AutoGeneratedMipmaps,
+            ).chain_err(|| "Could not create the terrain textures.")
+        );
+        let normals = try!(
+            Texture2dArray::with_mipmaps(
+                window.facade(),
+                normal_images,
+                MipmapsOption::AutoGeneratedMipmaps,
+            ).chain_err(|| "Could not create the terrain normal maps.")
+        );
+        Ok(TerrainTextures {
+            layers: layers,
+            normals: normals,
+        })
+    }
+
+    pub fn sampled(&self) -> Sampler<Texture2dArray> {
+        sampled_layers(&self.layers)
+    }
+
+    pub fn sampled_normals(&self) -> Sampler<Texture2dArray> {
+        sampled_layers(&self.normals)
+    }
+}
+
+fn sampled_layers(layers: &Texture2dArray) -> Sampler<Texture2dArray> {
+    layers
+        .sampled()
+        .wrap_function(SamplerWrapFunction::Repeat)
+        .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+        .magnify_filter(MagnifySamplerFilter::Linear)
+        .anisotropy(TEXTURE_ANISOTROPY)
+}
+
+/// The RGBA pixels of `textures/<name>.png` in the assets, scaled to the size
+/// of the layers, or `None` if there's no such file.
+fn load_texture(assets: &Assets, name: &str) -> Result<Option<Vec<u8>>> {
+    let path = assets.path(format!("{}/{}.png", TEXTURES_DIR, name));
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let texture = try!(image::open(&path).chain_err(|| {
+        format!("Could not load the terrain texture at {:?}", path)
+    })).to_rgba();
+    info!("Loaded the terrain texture {} from {:?}", name, path);
+    let size = TEXTURE_SIZE as u32;
+    if texture.dimensions() == (size, size) {
+        Ok(Some(texture.into_raw()))
+    } else {
+        Ok(Some(image::imageops::resize(&texture, size, size, FilterType::Triangle).into_raw()))
+    }
+}
+
+/// The RGBA pixels of the layer of `material`, `size` texels across.
+fn material_pixels<R: Rng>(material: TerrainMaterial, size: usize, rng: &mut R) -> Vec<u8> {
+    let coarse = tiling_noise(size, &[2, 4, 8], rng);
+    let fine = tiling_noise(size, &[16, 32, 64], rng);
+    let mut pixels = Vec::with_capacity(size * size * 4);
+    for (&coarse, &fine) in coarse.iter().zip(fine.iter()) {
+        let color = match material {
+            TerrainMaterial::Grass => {
+                let shade = 0.8 + 0.4 * fine;
+                [
+                    (0.16 + 0.14 * coarse) * shade,
+                    (0.30 + 0.12 * coarse) * shade,
+                    (0.08 + 0.04 * coarse) * shade,
+                ]
+            }
+            TerrainMaterial::Rock => {
+                let grain = 0.5 + 0.6 * (coarse - 0.5) + 0.4 * (fine - 0.5);
+                [grain, grain, grain]
+            }
+            TerrainMaterial::Sand => {
+                let shade = 0.9 + 0.2 * fine;
+                [0.86 * shade, 0.76 * shade, 0.52 * shade]
+            }
+            TerrainMaterial::Snow => {
+                let shade = 0.94 + 0.06 * coarse;
+                [0.92 * shade, 0.95 * shade, 1.0 * shade]
+            }
+        };
+        for &channel in color.iter() {
+            pixels.push((channel.max(0.0).min(1.0) * 255.0).round() as u8);
+        }
+        pixels.push(255);
+    }
+    pixels
+}
+
+/// A normal map of the bumps of the brightness of the RGBA `pixels`, `size`
+/// texels across, as steep as `relief`. The normals' components in [-1, 1]
+/// are stored in [0, 255], the bumps wrapping around like the texture.
+fn normal_pixels(pixels: &[u8], size: usize, relief: f32) -> Vec<u8> {
+    let height = |x: usize, y: usize| {
+        let texel = &pixels[(y % size * size + x % size) * 4..];
+        (0.3 * texel[0] as f32 + 0.59 * texel[1] as f32 + 0.11 * texel[2] as f32) / 255.0
+    };
+    let mut normals = Vec::with_capacity(size * size * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let dx = height(x + 1, y) - height(x + size - 1, y);
+            let dy = height(x, y + 1) - height(x, y + size - 1);
+            let (nx, ny) = (-relief * dx, -relief * dy);
+            let length = (nx * nx + ny * ny + 1.0).sqrt();
+            for &component in [nx / length, ny / length, 1.0 / length].iter() {
+                normals.push(((component * 0.5 + 0.5) * 255.0).round() as u8);
+            }
+            normals.push(255);
+        }
+    }
+    normals
+}
+
+/// Value noise in [0, 1] over a `size` by `size` grid which wraps around at
+/// its edges, summing octaves with lattices `periods` across, each half as
+/// strong as the one before. The periods divide `size`.
+fn tiling_noise<R: Rng>(size: usize, periods: &[usize], rng: &mut R) -> Vec<f32> {
+    let mut noise = vec![0.0; size * size];
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    for &period in periods.iter() {
+        let lattice: Vec<f32> = (0..period * period).map(|_| rng.gen::<f32>()).collect();
+        let cell = size as f32 / period as f32;
+        for y in 0..size {
+            for x in 0..size {
+                let (fx, fy) = (x as f32 / cell, y as f32 / cell);
+                let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+                let (x1, y1) = ((x0 + 1) % period, (y0 + 1) % period);
+                let (tx, ty) = (smooth(fx.fract()), smooth(fy.fract()));
+                let at = |x: usize, y: usize| lattice[y * period + x];
+                let bottom = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * tx;
+                let top = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * tx;
+                noise[y * size + x] += amplitude * (bottom + (top - bottom) * ty);
+            }
+        }
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    for value in noise.iter_mut() {
+        *value /= total;
+    }
+    noise
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::{normal_pixels, tiling_noise};
+
+    #[test]
+    fn test_tiling_noise_wraps_around() {
+        let size = 64;
+        let noise = tiling_noise(size, &[4, 16], &mut XorShiftRng::from_seed([1, 2, 3, 4]));
+        assert_eq!(size * size, noise.len());
+        assert!(noise.iter().all(|&value| value >= 0.0 && value <= 1.0));
+        // The steps across the seams are no larger than those within the
+        // texture.
+        let at = |x: usize, y: usize| noise[y * size + x];
+        let max_step = (0..size)
+            .flat_map(|y| (1..size).map(move |x| (x, y)))
+            .map(|(x, y)| (at(x, y) - at(x - 1, y)).abs().max((at(y, x) - at(y, x - 1)).abs()))
+            .fold(0.0, f32::max);
+        for y in 0..size {
+            assert!((at(0, y) - at(size - 1, y)).abs() <= max_step * 1.01);
+            assert!((at(y, 0) - at(y, size - 1)).abs() <= max_step * 1.01);
+        }
+    }
+
+    #[test]
+    fn test_normal_pixels_tilt_away_from_the_bumps() {
+        let size = 4;
+        let flat = vec![100; size * size * 4];
+        for normal in normal_pixels(&flat, size, 2.0).chunks(4) {
+            assert_eq!(&[128, 128, 255, 255], normal);
+        }
+        // Brighter towards +x, so higher, the normals lean towards -x.
+        let ramp: Vec<u8> = (0..size * size)
+            .flat_map(|index| vec![40 * (index % size) as u8 + 60; 4])
+            .collect();
+        let normals = normal_pixels(&ramp, size, 2.0);
+        let (x, y) = (1, 2);
+        let normal = &normals[(y * size + x) * 4..(y * size + x) * 4 + 4];
+        assert!(normal[0] < 128);
+        assert_eq!(128, normal[1]);
+        assert!(normal[2] < 255);
+    }
+}
+
+// In the assets.
+const TEXTURES_DIR: &'static str = "textures";
+// Texels across each layer.
+const TEXTURE_SIZE: usize = 256;
+// The layers are the same in every world.
+const TEXTURE_SEED: [u32; 4] = [0x9e37_79b9, 0x7f4a_7c15, 0x85eb_ca6b, 0xc2b2_ae35];
+// The terrain is seen at grazing angles.
+const TEXTURE_ANISOTROPY: u16 = 4;
@@ -0,0 +1,65 @@
+use nalgebra::{Norm, Point3, Vector3};
+
+use math::{ScalarField3, Vec3f};
+use super::dual_contouring::{crossed_edges, dual_mesh};
+use super::mesh::{Mesh, Vertex};
+
+/// Polygonises the iso-surface of `field` between `min` and `max` with naive
+/// surface nets: a vertex at the average of the crossings on the edges of
+/// each cell the surface crosses, joined like those of `dual_contouring`.
+/// Unlike dual contouring it samples the gradient once per vertex rather
+/// than once per crossing, and its quads are better shaped than the slivers
+/// marching cubes makes, which holds up on the coarse grids of distant
+/// chunks.
+pub fn surface_nets<Field: ScalarField3 + Sync>(
+    field: &Field,
+    min: &Vec3f,
+    max: &Vec3f,
+    step: f32,
+    iso_value: f32,
+) -> Mesh<Vertex> {
+    dual_mesh(field, min, max, step, iso_value, |grid, i, j, k| {
+        let edges = crossed_edges(grid, i, j, k, iso_value);
+        if edges.is_empty() {
+            return None;
+        }
+        let mut position = Vector3::new(0.0, 0.0, 0.0);
+        for edge in edges.iter() {
+            position = position + edge.interpolated_crossing(iso_value);
+        }
+        let position = position / edges.len() as f32;
+        let gradient = field.gradient_at(&Point3::new(position[0], position[1], position[2]));
+        let length = gradient.norm();
+        let normal = if length > 0.0 {
+            gradient / -length
+        } else {
+            gradient
+        };
+        Some(Vertex {
+            position: Vec3f::from(position),
+            normal: Vec3f::from(normal),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Dot;
+
+    use gfx::testing::{assert_closed, Sphere};
+    use math::{ScalarField3, Vec3f};
+    use super::surface_nets;
+
+    #[test]
+    fn test_surface_nets_sphere() {
+        let min = Vec3f::new(-2.0, -2.0, -2.0);
+        let max = Vec3f::new(2.0, 2.0, 2.0);
+        let mesh = surface_nets(&Sphere, &min, &max, 0.1, 0.0);
+        assert_closed(&mesh);
+        for vertex in mesh.vertices.iter() {
+            assert!(Sphere.value_at(vertex.position.as_point()).abs() < 0.01);
+            // Normals point inwards, like those of marching cubes.
+            assert!(vertex.normal.dot(&vertex.position) < 0.0);
+        }
+    }
+}
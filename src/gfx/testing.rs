@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+
+use nalgebra::{Point3, Vector3};
+
+use math::{CpuScalar, ScalarField3};
+use super::mesh::{Mesh, Vertex};
+
+/// The ball the meshers are tested on, of radius `SPHERE_RADIUS` around the
+/// origin, negative inside.
+pub struct Sphere;
+
+impl ScalarField3 for Sphere {
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        sphere_value(position)
+    }
+
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        Vector3::new(position[0], position[1], position[2])
+    }
+}
+
+/// The value of `Sphere` at `position`, for test fields sampling it in their
+/// own way.
+pub fn sphere_value(position: &Point3<CpuScalar>) -> CpuScalar {
+    (position[0] * position[0] + position[1] * position[1] + position[2] * position[2]).sqrt() -
+        SPHERE_RADIUS
+}
+
+/// Asserts that `mesh` is closed: every edge of a triangle is shared with
+/// exactly one other.
+pub fn assert_closed(mesh: &Mesh<Vertex>) {
+    let mut edges = HashMap::new();
+    for triangle in mesh.indices.chunks(3) {
+        for &(a, b) in &[(triangle[0], triangle[1]), (triangle[1], triangle[2]),
+                         (triangle[2], triangle[0])]
+        {
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    assert!(!edges.is_empty());
+    for (edge, &count) in edges.iter() {
+        assert!(count == 2, "{} triangles share the edge {:?}", count, edge);
+    }
+}
+
+pub const SPHERE_RADIUS: CpuScalar = 1.5;
@@ -0,0 +1,317 @@
+use glium::{Blend, DrawParameters, Frame, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::index::PrimitiveType;
+use glium::texture::{RawImage2d, Texture2d};
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::Window;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TextVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+}
+
+implement_vertex!(TextVertex, position, tex_coords, color);
+
+/// Draws text on top of a frame using a built-in 5x7 bitmap font. Only
+/// printable ASCII is supported, anything else is drawn as `?`.
+pub struct TextRenderer<'a> {
+    draw_parameters: DrawParameters<'a>,
+    font: Texture2d,
+    program: Program,
+}
+
+impl<'a> TextRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let (atlas, dimensions) = font_atlas();
+        let font = try!(
+            Texture2d::new(window.facade(), RawImage2d::from_raw_rgba(atlas, dimensions))
+                .chain_err(|| "Could not create font texture.")
+        );
+        Ok(TextRenderer {
+            draw_parameters: DrawParameters {
+                blend: Blend::alpha_blending(),
+                ..Default::default()
+            },
+            font: font,
+            program: program,
+        })
+    }
+
+    /// Draws `text` with its top left corner at `position`, in pixels from the
+    /// top left of the frame. Each font pixel is drawn as a `scale` x `scale`
+    /// square; lines are separated by `\n`.
+    pub fn draw(
+        &self,
+        window: &Window,
+        frame: &mut Frame,
+        text: &str,
+        position: (f32, f32),
+        scale: f32,
+    ) -> Result<()> {
+        let (width, height) = frame.get_dimensions();
+        let mut vertices = Vec::with_capacity(text.len() * 8);
+        // A dark copy offset by one font pixel keeps the text readable over
+        // bright terrain.
+        let (x, y) = position;
+        push_text(&mut vertices, text, (x + scale, y + scale), scale, SHADOW_COLOR);
+        push_text(&mut vertices, text, (x, y), scale, TEXT_COLOR);
+        if vertices.is_empty() {
+            return Ok(());
+        }
+        for vertex in vertices.iter_mut() {
+            vertex.position[0] = 2.0 * vertex.position[0] / width as f32 - 1.0;
+            vertex.position[1] = 1.0 - 2.0 * vertex.position[1] / height as f32;
+        }
+
+        let indices: Vec<u32> = (0..vertices.len() as u32 / 4)
+            .flat_map(|quad| {
+                let first = quad * 4;
+                vec![first, first + 1, first + 2, first, first + 2, first + 3]
+            })
+            .collect();
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer = try!(
+            IndexBuffer::new(window.facade(), PrimitiveType::TrianglesList, &indices)
+                .chain_err(|| "Cannot create index buffer.")
+        );
+
+        let uniforms =
+            uniform! {
+            font: self.font
+                .sampled()
+                .magnify_filter(MagnifySamplerFilter::Nearest)
+                .minify_filter(MinifySamplerFilter::Nearest),
+        };
+        frame
+            .draw(
+                &vertex_buffer,
+                &index_buffer,
+                &self.program,
+                &uniforms,
+                &self.draw_parameters,
+            )
+            .chain_err(|| "Could not render text.")
+    }
+}
+
+/// Appends a quad per character of `text`, with positions in pixels.
+fn push_text(
+    vertices: &mut Vec<TextVertex>,
+    text: &str,
+    position: (f32, f32),
+    scale: f32,
+    color: [f32; 4],
+) {
+    let cell_width = GLYPH_CELL_WIDTH as f32 * scale;
+    let cell_height = GLYPH_CELL_HEIGHT as f32 * scale;
+    let atlas_width = (FONT.len() * GLYPH_CELL_WIDTH) as f32;
+    for (line_index, line) in text.lines().enumerate() {
+        let top = position.1 + line_index as f32 * cell_height;
+        for (char_index, character) in line.chars().enumerate() {
+            let glyph = glyph_index(character);
+            if glyph == 0 {
+                continue;
+            }
+            let left = position.0 + char_index as f32 * cell_width;
+            let (u0, u1) = (
+                (glyph * GLYPH_CELL_WIDTH) as f32 / atlas_width,
+                ((glyph + 1) * GLYPH_CELL_WIDTH) as f32 / atlas_width,
+            );
+            let corners = [
+                ([left, top], [u0, 1.0]),
+                ([left + cell_width, top], [u1, 1.0]),
+                ([left + cell_width, top + cell_height], [u1, 0.0]),
+                ([left, top + cell_height], [u0, 0.0]),
+            ];
+            for &(position, tex_coords) in corners.iter() {
+                vertices.push(TextVertex {
+                    position: position,
+                    tex_coords: tex_coords,
+                    color: color,
+                });
+            }
+        }
+    }
+}
+
+/// The index in `FONT` of the glyph used to draw `character`.
+fn glyph_index(character: char) -> usize {
+    let code = character as usize;
+    if code >= FIRST_CHAR && code < FIRST_CHAR + FONT.len() {
+        code - FIRST_CHAR
+    } else {
+        '?' as usize - FIRST_CHAR
+    }
+}
+
+/// Rasterises `FONT` into a single row of RGBA glyph cells, bottom row first.
+fn font_atlas() -> (Vec<u8>, (u32, u32)) {
+    let width = FONT.len() * GLYPH_CELL_WIDTH;
+    let mut atlas = vec![0u8; width * GLYPH_CELL_HEIGHT * 4];
+    for (glyph_index, glyph) in FONT.iter().enumerate() {
+        for (column_index, column) in glyph.iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if column & (1 << row) == 0 {
+                    continue;
+                }
+                let x = glyph_index * GLYPH_CELL_WIDTH + column_index;
+                let y = GLYPH_CELL_HEIGHT - 1 - row;
+                let offset = (y * width + x) * 4;
+                for channel in atlas[offset..offset + 4].iter_mut() {
+                    *channel = 255;
+                }
+            }
+        }
+    }
+    (atlas, (width as u32, GLYPH_CELL_HEIGHT as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FONT, GLYPH_CELL_HEIGHT, GLYPH_CELL_WIDTH, font_atlas, glyph_index};
+
+    #[test]
+    fn test_font_atlas() {
+        let (atlas, (width, height)) = font_atlas();
+        assert_eq!((FONT.len() * GLYPH_CELL_WIDTH) as u32, width);
+        assert_eq!(GLYPH_CELL_HEIGHT as u32, height);
+
+        // 'L' is a vertical bar in its first column with a foot along the
+        // bottom row of the glyph (the second row of the atlas).
+        let alpha = |character: char, x: usize, y: usize| {
+            atlas[(y * width as usize + glyph_index(character) * GLYPH_CELL_WIDTH + x) * 4 + 3]
+        };
+        for y in 1..GLYPH_CELL_HEIGHT {
+            assert_eq!(255, alpha('L', 0, y));
+        }
+        for x in 0..5 {
+            assert_eq!(255, alpha('L', x, 1));
+        }
+        assert_eq!(0, alpha('L', 1, 2));
+        assert_eq!(0, alpha('L', 0, 0));
+        assert_eq!(glyph_index('?'), glyph_index('é'));
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/text.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/text.frag";
+
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+
+const FIRST_CHAR: usize = 32;
+const GLYPH_HEIGHT: usize = 7;
+// Glyphs are 5x7 and get a column and a row of spacing.
+const GLYPH_CELL_WIDTH: usize = 6;
+const GLYPH_CELL_HEIGHT: usize = 8;
+
+/// Printable ASCII from `' '`, one byte per column with the top row in the
+/// least significant bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // "'"
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x14, 0x08, 0x3E, 0x08, 0x14], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3E, 0x41, 0x49, 0x49, 0x7A], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x0C, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x3F, 0x40, 0x38, 0x40, 0x3F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
@@ -1,5 +1,8 @@
+use std::fmt::Debug;
+use std::path::Path;
+
 use glium::{DisplayBuild, Frame, Program, Surface};
-use glium::glutin::{CursorState, WindowBuilder};
+use glium::glutin::{self, CursorState, MonitorId, WindowBuilder};
 use glium::backend::glutin_backend::{GlutinFacade, WinRef as GlutinWindow};
 
 use errors::{Result, ChainErr, ErrorKind};
@@ -8,21 +11,95 @@ use utils::read_utf8_file;
 
 pub const GLSL_VERSION_STRING: &'static str = "330 core";
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FullscreenMode {
+    Windowed,
+    /// Takes over the monitor, changing its video mode if needed.
+    Exclusive,
+    /// An undecorated window covering the whole monitor.
+    Borderless,
+}
+
+impl FullscreenMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "windowed" => Some(FullscreenMode::Windowed),
+            "exclusive" => Some(FullscreenMode::Exclusive),
+            "borderless" => Some(FullscreenMode::Borderless),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WindowSpec {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: FullscreenMode,
+    pub vsync: bool,
+    /// Caps the frame rate of the main loop, uncapped if missing.
+    pub max_fps: Option<u32>,
+    /// Index into the available monitors, the primary monitor is used if missing.
+    pub monitor: Option<usize>,
+    /// Samples per pixel of the window's own framebuffer (MSAA), smoothing
+    /// only what's drawn straight on it, the boxes and text over the scene.
+    /// The bodies are drawn into the render graph's targets, which aren't
+    /// multisampled, see `supersampling`. Not multisampled if 0.
+    pub multisampling: u16,
+    /// Samples per pixel of the scene the bodies are drawn into (SSAA), a
+    /// square number: it's drawn that many times the size of the window and
+    /// averaged down, smoothing the silhouettes of the terrain.
+    pub supersampling: u32,
+}
+
+impl Default for WindowSpec {
+    fn default() -> Self {
+        WindowSpec {
+            width: 1024,
+            height: 768,
+            fullscreen: FullscreenMode::Windowed,
+            vsync: true,
+            max_fps: None,
+            monitor: None,
+            multisampling: 0,
+            supersampling: 1,
+        }
+    }
+}
+
 pub struct Window {
     facade: GlutinFacade,
 }
 
 impl Window {
-    pub fn new<'a>(width: u32, height: u32, title: &str) -> Result<Window> {
-        let facade = try!(
-            WindowBuilder::new()
-                .with_title(title)
-                .with_dimensions(width, height)
-                .with_depth_buffer(24)
-                .build_glium()
-                .chain_err(|| "Could not create a Glutin window.")
-        );
+    pub fn new(spec: &WindowSpec, title: &str) -> Result<Window> {
+        let mut builder = WindowBuilder::new().with_title(title).with_depth_buffer(24);
+        let mut position = None;
+        builder = match spec.fullscreen {
+            FullscreenMode::Windowed => builder.with_dimensions(spec.width, spec.height),
+            FullscreenMode::Exclusive => {
+                builder.with_fullscreen(try!(select_monitor(spec.monitor)))
+            }
+            FullscreenMode::Borderless => {
+                let monitor = try!(select_monitor(spec.monitor));
+                let (width, height) = monitor.get_dimensions();
+                position = Some(monitor_origin(&monitor));
+                builder.with_dimensions(width, height).with_decorations(false)
+            }
+        };
+        if spec.vsync {
+            builder = builder.with_vsync();
+        }
+        if spec.multisampling > 0 {
+            builder = builder.with_multisampling(spec.multisampling);
+        }
 
+        let facade = try!(builder.build_glium().chain_err(
+            || "Could not create a Glutin window.",
+        ));
+        if let (Some((x, y)), Some(window)) = (position, facade.get_window()) {
+            window.set_position(x, y);
+        }
         Ok(Window { facade: facade })
     }
 
@@ -78,28 +155,78 @@ impl Window {
         }
     }
 
-    pub fn program(&self, vertex_src: &str, fragment_src: &str) -> Result<Program> {
-        Program::from_source(
-            &self.facade,
-            &format!(
-                "#version {}\n{}",
-                GLSL_VERSION_STRING,
-                try!(read_utf8_file(vertex_src).chain_err(
-                    || "Failed to read vertex shader.",
-                ))
-            ),
+    pub fn program<P, Q>(&self, vertex_src: P, fragment_src: Q) -> Result<Program>
+    where
+        P: AsRef<Path> + Debug,
+        Q: AsRef<Path> + Debug,
+    {
+        self.program_from_source(
+            &try!(read_shader(&vertex_src, "vertex")),
+            &try!(read_shader(&fragment_src, "fragment")),
+        )
+    }
+
+    /// A program whose fragment shader starts with the functions in
+    /// `library_src`, for those shared between shaders.
+    pub fn program_with_library<P, Q, R>(
+        &self,
+        vertex_src: P,
+        library_src: Q,
+        fragment_src: R,
+    ) -> Result<Program>
+    where
+        P: AsRef<Path> + Debug,
+        Q: AsRef<Path> + Debug,
+        R: AsRef<Path> + Debug,
+    {
+        self.program_from_source(
+            &try!(read_shader(&vertex_src, "vertex")),
             &format!(
-                "#version {}\n{}",
-                GLSL_VERSION_STRING,
-                try!(read_utf8_file(fragment_src).chain_err(
-                    || "Failed to read fragment shader.",
-                ))
+                "{}\n{}",
+                try!(read_shader(&library_src, "library")),
+                try!(read_shader(&fragment_src, "fragment"))
             ),
+        )
+    }
+
+    fn program_from_source(&self, vertex: &str, fragment: &str) -> Result<Program> {
+        Program::from_source(
+            &self.facade,
+            &format!("#version {}\n{}", GLSL_VERSION_STRING, vertex),
+            &format!("#version {}\n{}", GLSL_VERSION_STRING, fragment),
             None,
         ).chain_err(|| "Failed to build program.")
     }
 }
 
+fn read_shader<P: AsRef<Path> + Debug>(path: &P, kind: &str) -> Result<String> {
+    read_utf8_file(path).chain_err(|| format!("Failed to read {} shader {:?}.", kind, path))
+}
+
+fn select_monitor(index: Option<usize>) -> Result<MonitorId> {
+    match index {
+        Some(index) => {
+            let monitors: Vec<MonitorId> = glutin::get_available_monitors().collect();
+            let num_monitors = monitors.len();
+            monitors.into_iter().nth(index).ok_or_else(|| {
+                ErrorKind::InvalidMonitor(index, num_monitors).into()
+            })
+        }
+        None => Ok(glutin::get_primary_monitor()),
+    }
+}
+
+/// Where `monitor` is on the desktop. Glutin doesn't tell, so the monitors
+/// are taken to be side by side, left to right in the order they're listed.
+fn monitor_origin(monitor: &MonitorId) -> (i32, i32) {
+    let id = monitor.get_native_identifier();
+    let x = glutin::get_available_monitors()
+        .take_while(|other| other.get_native_identifier() != id)
+        .map(|other| other.get_dimensions().0 as i32)
+        .sum();
+    (x, 0)
+}
+
 pub struct WindowInnerSize {
     pub width: u32,
     pub height: u32,
@@ -1,103 +1,380 @@
 use std::f32::consts::{FRAC_1_PI, PI};
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use std::sync::Mutex;
+use byteorder::{BigEndian, ByteOrder};
 use image;
-use nalgebra::{FloatPoint, Origin, Point2, Point3, Vector2};
+use lru_time_cache::LruCache;
+use png::{self, HasParameters};
+use nalgebra::{FloatPoint, Origin, Point2, Point3, Vector2, Vector3};
 
 use errors::{ChainErr, ErrorKind, Result};
 use math::{CpuScalar, ScalarField3, ScalarField2};
+use pds::PdsImage;
+use tiff::{GeoModel, TiffRaster};
+
+/// How the samples of a heightmap are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightmapFormat {
+    /// Raw big endian 16 bit integers in rows, as in the PDS archives.
+    Pds,
+    /// Any image format, the brightness of a pixel being its height.
+    Image,
+    /// A GeoTIFF elevation model in metres, covering the part of the body
+    /// given by its georeferencing.
+    GeoTiff,
+}
+
+impl HeightmapFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pds" => Some(HeightmapFormat::Pds),
+            "image" => Some(HeightmapFormat::Image),
+            "geotiff" => Some(HeightmapFormat::GeoTiff),
+            _ => None,
+        }
+    }
+}
 
 pub struct Heightmap {
     radius: CpuScalar,
-    height: Vec<CpuScalar>,
+    /// One for a single map, those of each of its tiles for a mosaic.
+    grids: Vec<Grid>,
+}
+
+/// The samples of a map in rows, covering part of the body.
+struct Grid {
+    samples: Samples,
     x_max: usize,
     y_max: usize,
+    /// The part of the body the samples cover, in the longitudes and
+    /// latitudes in [0, 1] of `ScalarField2::value_at`: the first sample is
+    /// at `origin` and the last one at `origin + extent`. Half a sample
+    /// further out the surface is at the datum, unless another grid covers
+    /// it.
+    origin: Point2<CpuScalar>,
+    extent: Vector2<CpuScalar>,
 }
 
 impl Heightmap {
+    /// Loads a PDS image laid out as its label says, the label being at the
+    /// start of the file or next to it with the extension `.lbl`. Files
+    /// without one are raw big endian 16 bit samples in rows, whose `size`
+    /// must be given. Missing samples and those at most `nodata` are voids,
+    /// filled in from the samples around them. Maps larger than `max_tiles`
+    /// tiles aren't loaded whole, they're streamed from the file keeping the
+    /// tiles most recently sampled in memory, and their voids are filled
+    /// within each tile. The tiles are spread over caches locked apart, so
+    /// the chunks being meshed in parallel seldom wait for each other.
     pub fn from_pds<P>(
         radius: CpuScalar,
-        x_samples: usize,
-        y_samples: usize,
         path: P,
+        size: Option<(usize, usize)>,
+        nodata: Option<CpuScalar>,
+        max_tiles: usize,
     ) -> Result<Self>
     where
         P: AsRef<Path> + Debug,
     {
-        let file = try!(File::open(path).chain_err(
+        let path = path.as_ref();
+        let (image, data_path, labelled) = match try!(PdsImage::find(path)) {
+            Some((image, data_path)) => {
+                let labelled_size = (image.line_samples, image.lines);
+                match size {
+                    Some(size) if size != labelled_size => {
+                        return Err(
+                            ErrorKind::HeightmapSizeMismatch(
+                                format!("{:?}", path),
+                                format!("{} x {}", size.0, size.1),
+                                format!("{} x {} by its label", labelled_size.0, labelled_size.1),
+                            ).into(),
+                        )
+                    }
+                    _ => (image, data_path, true),
+                }
+            }
+            None => {
+                match size {
+                    Some((x_samples, y_samples)) => {
+                        (PdsImage::raw(x_samples, y_samples), path.to_path_buf(), false)
+                    }
+                    None => {
+                        return Err(
+                            format!(
+                                "Heightmap {:?} has no PDS label, its size has to be given.",
+                                path
+                            ).into(),
+                        )
+                    }
+                }
+            }
+        };
+        info!(
+            "Heightmap of {} x {} {:?} samples of {} bytes, {} endian",
+            image.line_samples,
+            image.lines,
+            image.sample_type,
+            image.sample_bytes,
+            if image.big_endian { "big" } else { "little" }
+        );
+
+        let file = try!(File::open(&data_path).chain_err(
             || "Falied opening heightmap file.",
         ));
-        let mut reader = BufReader::new(file);
+        let file_size = try!(file.metadata().chain_err(
+            || "Heightmap creation failed! Could not read the size of the file.",
+        )).len();
+        // Labelled files may hold more objects after the image.
+        if file_size < image.file_size() {
+            return Err(
+                ErrorKind::HeightmapSizeMismatch(
+                    format!("{:?}", data_path),
+                    format!("{} bytes", image.file_size()),
+                    format!("{} bytes", file_size),
+                ).into(),
+            );
+        } else if file_size > image.file_size() && !labelled {
+            error!(
+                "Found unexpected data in heightmap file; expected {} ({} x {}) values)",
+                image.line_samples * image.lines,
+                image.line_samples,
+                image.lines
+            );
+            return Err(ErrorKind::UnexhaustedHeightmapFile.into());
+        }
+
+        let (x_samples, y_samples) = (image.line_samples, image.lines);
+        // Without a map projection the image is taken to cover the body.
+        let (origin, extent) = match image.area {
+            Some(area) => {
+                geographic_area(
+                    (area.west, area.north),
+                    (
+                        (area.east - area.west) / x_samples as f64,
+                        (area.north - area.south) / y_samples as f64,
+                    ),
+                    (x_samples - 1, y_samples - 1),
+                )
+            }
+            None => (Point2::new(0.0, 0.0), Vector2::new(1.0, 1.0)),
+        };
         let num_samples = x_samples * y_samples;
-        let mut height = Vec::with_capacity(num_samples);
+        if num_samples > max_tiles * TILE_SIZE * TILE_SIZE {
+            info!(
+                "Streaming heightmap of {} x {} samples in tiles of {} x {}",
+                x_samples,
+                y_samples,
+                TILE_SIZE,
+                TILE_SIZE
+            );
+            return Ok(Heightmap {
+                radius: radius,
+                grids: vec![
+                    Grid {
+                        samples: Samples::Tiled(
+                            try!(TileCache::open(&data_path, image, nodata, max_tiles)),
+                        ),
+                        x_max: x_samples - 1,
+                        y_max: y_samples - 1,
+                        origin: origin,
+                        extent: extent,
+                    },
+                ],
+            });
+        }
 
-        let mut min_height: CpuScalar = 0.0;
-        let mut max_height: CpuScalar = 0.0;
+        let mut reader = BufReader::new(file);
+        try!(reader.seek(SeekFrom::Start(image.start)).chain_err(
+            || "Heightmap creation failed! Could not read value from file.",
+        ));
+        let mut height = Vec::with_capacity(num_samples);
+        let mut line = vec![0; image.line_size()];
+        let mut line_heights = vec![None; x_samples];
+        let samples_start = image.line_prefix_bytes;
+        let samples_end = samples_start + x_samples * image.sample_bytes;
 
-        while height.len() < num_samples {
-            let value = try!(reader.read_i16::<BigEndian>().chain_err(
+        for _ in 0..y_samples {
+            try!(reader.read_exact(&mut line).chain_err(
                 || "Heightmap creation failed! Could not read value from file.",
-            )) as CpuScalar;
-            min_height = min_height.min(value);
-            max_height = max_height.max(value);
-            height.push(value);
+            ));
+            image.decode(&line[samples_start..samples_end], &mut line_heights);
+            height.extend(line_heights.iter().map(
+                |value| value.unwrap_or(CpuScalar::NAN),
+            ));
         }
-        let remaining = try!(reader.read_to_end(&mut Vec::new()).chain_err(
-            || "Heightmap creation failed! Could not read value from file.",
-        ));
-        if remaining > 0 {
-            error!(
-                "Found unexpected data in heightmap file; expected {} ({} x {}) values)",
-                num_samples,
-                x_samples,
-                y_samples
-            );
-            Err(ErrorKind::UnexhaustedHeightmapFile.into())
-        } else {
-            info!(
-                "Heightmap len: {} [{}, {}]",
-                height.len(),
-                min_height,
-                max_height
+        let num_voids = fill_voids(&mut height, x_samples, nodata);
+        if num_voids > 0 {
+            warn!("Filled {} voids in the heightmap.", num_voids);
+        }
+        let (min_height, max_height) = height_range(&height);
+        info!(
+            "Heightmap len: {} [{}, {}]",
+            height.len(),
+            min_height,
+            max_height
+        );
+
+        Ok(Heightmap {
+            radius: radius,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(height),
+                    x_max: x_samples - 1,
+                    y_max: y_samples - 1,
+                    origin: origin,
+                    extent: extent,
+                },
+            ],
+        })
+    }
+
+    /// Loads a grayscale image, keeping every bit of 16 bit PNG and TIFF
+    /// samples. The darkest to the brightest values of unsigned samples are
+    /// mapped to the heights in metres of `range`, or taken as the heights if
+    /// missing, as signed and floating point TIFF samples always are. Colour
+    /// images are converted to 8 bit grayscale. Heights at most `nodata` are
+    /// voids, filled in from the samples around them.
+    pub fn from_image<P>(
+        radius: CpuScalar,
+        path: P,
+        range: Option<(CpuScalar, CpuScalar)>,
+        nodata: Option<CpuScalar>,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let samples = match try!(read_gray_samples(path.as_ref())) {
+            Some(samples) => samples,
+            None => {
+                let image = try!(image::open(path.as_ref()).chain_err(|| {
+                    format!("Could not open heightmap image at {:?}", path)
+                })).to_luma();
+                let (width, height) = image.dimensions();
+                GraySamples {
+                    width: width,
+                    height: height,
+                    values: image.pixels().map(|pixel| pixel.data[0] as CpuScalar).collect(),
+                    max_value: Some(u8::max_value() as CpuScalar),
+                }
+            }
+        };
+        let (x_samples, y_samples) = (samples.width, samples.height);
+        let num_samples = (x_samples * y_samples) as usize;
+        if samples.values.len() != num_samples {
+            return Err(
+                format!(
+                    "Heightmap image at {:?} has {} samples rather than {} x {}.",
+                    path,
+                    samples.values.len(),
+                    x_samples,
+                    y_samples
+                ).into(),
             );
+        }
 
-            Ok(Heightmap {
-                height: height,
-                radius: radius,
-                x_max: x_samples - 1,
-                y_max: y_samples - 1,
+        let range = match (range, samples.max_value) {
+            (Some(_), None) => {
+                warn!("Ignoring the height range of a heightmap of signed or float samples.");
+                None
+            }
+            (range, _) => range,
+        };
+        let max_value = samples.max_value.unwrap_or(1.0);
+        let mut height: Vec<CpuScalar> = samples
+            .values
+            .into_iter()
+            .map(|value| match range {
+                Some((low, high)) => low + (high - low) * value / max_value,
+                None => value,
             })
+            .collect();
+        let num_voids = fill_voids(&mut height, x_samples as usize, nodata);
+        if num_voids > 0 {
+            warn!("Filled {} voids in the heightmap.", num_voids);
         }
+        let (min_height, max_height) = height_range(&height);
+        info!(
+            "Heightmap len: {} [{}, {}]",
+            height.len(),
+            min_height,
+            max_height
+        );
+
+        Ok(Heightmap {
+            radius: radius,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(height),
+                    x_max: (x_samples - 1) as usize,
+                    y_max: (y_samples - 1) as usize,
+                    origin: Point2::new(0.0, 0.0),
+                    extent: Vector2::new(1.0, 1.0),
+                },
+            ],
+        })
     }
 
-    pub fn from_image<P>(radius: CpuScalar, path: P) -> Result<Self>
+    /// Loads a GeoTIFF elevation model, whose heights are in metres, where
+    /// its georeferencing puts it on a body of `radius`. Missing samples are
+    /// filled in from the samples around them, as are those at most
+    /// `nodata`.
+    pub fn from_geotiff<P>(radius: CpuScalar, path: P, nodata: Option<CpuScalar>) -> Result<Self>
     where
         P: AsRef<Path> + Debug,
     {
-        let image = try!(image::open(path.as_ref()).chain_err(|| {
-            format!("Could not open heightmap image at {:?}", path)
-        })).to_luma();
+        let file = try!(File::open(path.as_ref()).chain_err(|| {
+            format!("Could not open heightmap GeoTIFF at {:?}", path)
+        }));
+        let raster = try!(TiffRaster::read(&mut BufReader::new(file)).chain_err(|| {
+            format!("Could not decode heightmap GeoTIFF at {:?}", path)
+        }));
+        let georeference = match raster.georeference {
+            Some(ref georeference) => {
+                if let GeoModel::Equirectangular(ref projection) = georeference.model {
+                    if let Some(semi_major_axis) = projection.semi_major_axis {
+                        info!("Heightmap projected on a body of radius {} m", semi_major_axis);
+                    }
+                }
+                try!(georeference.to_geographic(radius as f64 * 1000.0))
+            }
+            None => {
+                return Err(
+                    format!(
+                        "Heightmap at {:?} isn't georeferenced, load it with \
+                         --heightmap-format image.",
+                        path
+                    ).into(),
+                )
+            }
+        };
+        if raster.width < 2 || raster.height < 2 {
+            return Err(
+                format!(
+                    "Heightmap at {:?} has {} x {} samples, at least 2 x 2 are needed.",
+                    path,
+                    raster.width,
+                    raster.height
+                ).into(),
+            );
+        }
 
-        let (x_samples, y_samples) = image.dimensions();
-        let num_samples = (x_samples * y_samples) as usize;
-        let mut height = vec![0.0; num_samples];
-        let mut min_height: CpuScalar = 0.0;
-        let mut max_height: CpuScalar = 0.0;
-
-        let num_written = image
-            .enumerate_pixels()
-            .map(|(x, y, pixel)| {
-                let value = pixel.data[0] as CpuScalar;
-                min_height = min_height.min(value);
-                max_height = max_height.max(value);
-                height[(y * x_samples + x) as usize] = value;
+        let missing = raster.nodata;
+        let mut height: Vec<CpuScalar> = raster
+            .samples
+            .into_iter()
+            .map(|value| if Some(value) == missing {
+                CpuScalar::NAN
+            } else {
+                value
             })
-            .count();
-
-        assert!(num_samples == num_written && num_samples == height.len());
+            .collect();
+        let num_voids = fill_voids(&mut height, raster.width as usize, nodata);
+        if num_voids > 0 {
+            warn!("Filled {} voids in the heightmap.", num_voids);
+        }
+        let (min_height, max_height) = height_range(&height);
         info!(
             "Heightmap len: {} [{}, {}]",
             height.len(),
@@ -105,85 +382,624 @@ impl Heightmap {
             max_height
         );
 
+        let (x_max, y_max) = (raster.width as usize - 1, raster.height as usize - 1);
+        let (origin, extent) =
+            geographic_area(georeference.origin, georeference.pixel_size, (x_max, y_max));
         Ok(Heightmap {
-            height: height,
             radius: radius,
-            x_max: (x_samples - 1) as usize,
-            y_max: (y_samples - 1) as usize,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(height),
+                    x_max: x_max,
+                    y_max: y_max,
+                    origin: origin,
+                    extent: extent,
+                },
+            ],
         })
     }
 
-    #[inline]
-    fn discrete_height_at(&self, x: usize, y: usize) -> CpuScalar {
-        self.height[y * (self.x_max + 1) + x]
+    /// Joins maps of the same body, each covering part of it, into one.
+    /// Where they meet their heights are blended, so the seams between them
+    /// don't show.
+    pub fn mosaic(maps: Vec<Heightmap>) -> Result<Self> {
+        let radius = match maps.first() {
+            Some(map) => map.radius,
+            None => return Err("A heightmap mosaic needs at least one map.".into()),
+        };
+        if let Some(map) = maps.iter().find(|map| map.radius != radius) {
+            return Err(
+                format!(
+                    "The maps of a heightmap mosaic are of bodies of radius {} and {}.",
+                    radius,
+                    map.radius
+                ).into(),
+            );
+        }
+        let grids: Vec<Grid> = maps.into_iter().flat_map(|map| map.grids).collect();
+        if grids.len() > 1 &&
+            grids.iter().any(
+                |grid| grid.extent[0] >= 1.0 && grid.extent[1] >= 1.0,
+            )
+        {
+            warn!("A map of the heightmap mosaic covers the whole body, it will be blended in.");
+        }
+        Ok(Heightmap {
+            radius: radius,
+            grids: grids,
+        })
     }
-}
 
-impl ScalarField2 for Heightmap {
-    #[inline]
-    fn value_at(&self, position: &Point2<CpuScalar>) -> CpuScalar {
+    /// The height at `position` and its gradient, blending the grids
+    /// covering it.
+    fn height_and_gradient(
+        &self,
+        position: &Point2<CpuScalar>,
+    ) -> (CpuScalar, Vector2<CpuScalar>) {
         let (long, lat) = (position[0], position[1]);
         assert!(
             0.0 <= long && long <= 1.0 && 0.0 <= lat && lat <= 1.0,
             format!("{} {}", long, lat)
         );
-        let x = self.x_max as CpuScalar * long.min(0.999).max(0.001);
-        let y = self.y_max as CpuScalar * lat.min(0.999).max(0.001);
-
-        // Integer grid coordinates as floats
-        let x0 = (x - 0.5).floor().max(0.0);
-        let x1 = (x + 0.5).floor().min(self.x_max as CpuScalar);
-        let y0 = (y - 0.5).floor().max(0.0);
-        let y1 = (y + 0.5).floor().min(self.y_max as CpuScalar);
-
-        // Heights on the grid
-        let h00 = self.discrete_height_at(x0 as usize, y0 as usize);
-        let h01 = self.discrete_height_at(x0 as usize, y1 as usize);
-        let h10 = self.discrete_height_at(x1 as usize, y0 as usize);
-        let h11 = self.discrete_height_at(x1 as usize, y1 as usize);
-
-        let hx0 = ((x1 - x) * h00 + (x - x0) * h10) / (x1 - x0);
-        let hx1 = ((x1 - x) * h01 + (x - x0) * h11) / (x1 - x0);
-        let hxy = ((y1 - y) * hx0 + (y - y0) * hx1) / (y1 - y0);
-
-        // if hxy != 0.0 {
-        //     println!("long: {} lat: {} -> xy: {} {} {} {} | h: {} {} {} {} | hxy: {} {} {}",
-        //              long,
-        //              lat,
-        //              x0,
-        //              x1,
-        //              y0,
-        //              y1,
-        //              h00,
-        //              h01,
-        //              h10,
-        //              h11,
-        //              hx0,
-        //              hx1,
-        //              hxy);
-        // }
+        let position = Point2::new(long.min(0.999).max(0.001), lat.min(0.999).max(0.001));
+        if self.grids.len() == 1 {
+            let grid = &self.grids[0];
+            let (x, y) = grid.cell_position(&position);
+            return if grid.covers(x, y) {
+                grid.height_and_gradient(x, y)
+            } else {
+                (0.0, Vector2::new(0.0, 0.0))
+            };
+        }
+
+        // A weighted mean of the grids near the position, each fading out
+        // from a sample inside its edges to a sample outside. The surface is
+        // at the datum only where no grid covers it.
+        let (mut covered, mut total) = (false, 0.0);
+        let mut total_gradient = Vector2::new(0.0, 0.0);
+        let (mut sum, mut sum_gradient) = (0.0, Vector2::new(0.0, 0.0));
+        for grid in &self.grids {
+            let (x, y) = grid.cell_position(&position);
+            let (weight, weight_gradient) = match grid.weight_and_gradient(x, y) {
+                Some(weight) => {
+                    covered = true;
+                    weight
+                }
+                None => {
+                    match grid.blend_weight_and_gradient(x, y) {
+                        (weight, _) if weight <= 0.0 => continue,
+                        weight => weight,
+                    }
+                }
+            };
+            let (height, gradient) = grid.height_and_gradient(x, y);
+            total += weight;
+            total_gradient += weight_gradient;
+            sum += weight * height;
+            sum_gradient += weight_gradient * height + gradient * weight;
+        }
+        if !covered || total <= 0.0 {
+            return (0.0, Vector2::new(0.0, 0.0));
+        }
+        let height = sum / total;
+        (height, (sum_gradient - total_gradient * height) / total)
+    }
+}
+
+/// The area of samples at the centres of pixels of `pixel_size` degrees,
+/// the corner of the first one being at the longitude and latitude
+/// `north_west`. Longitudes start at the meridian, like those of the MOLA
+/// map, and grow eastwards.
+fn geographic_area(
+    north_west: (f64, f64),
+    pixel_size: (f64, f64),
+    (x_max, y_max): (usize, usize),
+) -> (Point2<CpuScalar>, Vector2<CpuScalar>) {
+    let ((west, north), (pixel_width, pixel_height)) = (north_west, pixel_size);
+    let long = (west + pixel_width / 2.0) / 360.0;
+    let lat = (90.0 - north + pixel_height / 2.0) / 180.0;
+    (
+        Point2::new((long - long.floor()) as CpuScalar, lat as CpuScalar),
+        Vector2::new(
+            (pixel_width * x_max as f64 / 360.0) as CpuScalar,
+            (pixel_height * y_max as f64 / 180.0) as CpuScalar,
+        ),
+    )
+}
+
+impl Grid {
+    /// Where `position` is in samples from the first one. Longitudes wrap
+    /// around to the side of the grid they're nearest to.
+    fn cell_position(&self, position: &Point2<CpuScalar>) -> (CpuScalar, CpuScalar) {
+        let long = position[0] - self.origin[0];
+        let long = if long < 0.0 { long + 1.0 } else { long };
+        let long = if long > self.extent[0] && 1.0 - long < long - self.extent[0] {
+            long - 1.0
+        } else {
+            long
+        };
+        let lat = position[1] - self.origin[1];
+        (
+            long * self.x_max as CpuScalar / self.extent[0],
+            lat * self.y_max as CpuScalar / self.extent[1],
+        )
+    }
+
+    /// True if the grid covers the position in samples (`x`, `y`), that is
+    /// it's no more than half a sample past the edges, so tiles of a mosaic
+    /// whose samples are one apart across the seam have no gap between them.
+    fn covers(&self, x: CpuScalar, y: CpuScalar) -> bool {
+        let covers_long = self.extent[0] >= 1.0 ||
+            (-0.5 <= x && x <= self.x_max as CpuScalar + 0.5);
+        covers_long && -0.5 <= y && y <= self.y_max as CpuScalar + 0.5
+    }
+
+    /// The weight of the grid in a mosaic, with its gradient in longitude
+    /// and latitude, if the grid covers the position.
+    fn weight_and_gradient(
+        &self,
+        x: CpuScalar,
+        y: CpuScalar,
+    ) -> Option<(CpuScalar, Vector2<CpuScalar>)> {
+        if self.covers(x, y) {
+            Some(self.blend_weight_and_gradient(x, y))
+        } else {
+            None
+        }
+    }
+
+    /// The weight of the grid near its edges, 0 from `BLEND_SAMPLES` past
+    /// them and 1 from as far inside.
+    fn blend_weight_and_gradient(
+        &self,
+        x: CpuScalar,
+        y: CpuScalar,
+    ) -> (CpuScalar, Vector2<CpuScalar>) {
+        // Weights across each axis from the distances inside the edges.
+        let ramp = |position: CpuScalar, max: usize| {
+            let (from_start, from_end) = (position + 0.5, max as CpuScalar + 0.5 - position);
+            let (distance, direction) = if from_start < from_end {
+                (from_start, 1.0)
+            } else {
+                (from_end, -1.0)
+            };
+            let t = (distance + BLEND_SAMPLES) / (2.0 * BLEND_SAMPLES);
+            if t <= 0.0 {
+                (0.0, 0.0)
+            } else if t >= 1.0 {
+                (1.0, 0.0)
+            } else {
+                (
+                    t * t * (3.0 - 2.0 * t),
+                    6.0 * t * (1.0 - t) * direction / (2.0 * BLEND_SAMPLES),
+                )
+            }
+        };
+        let (x_weight, x_slope) = if self.extent[0] >= 1.0 {
+            (1.0, 0.0)
+        } else {
+            ramp(x, self.x_max)
+        };
+        let (y_weight, y_slope) = ramp(y, self.y_max);
+        (
+            x_weight * y_weight,
+            Vector2::new(
+                x_slope * y_weight * self.x_max as CpuScalar / self.extent[0],
+                x_weight * y_slope * self.y_max as CpuScalar / self.extent[1],
+            ),
+        )
+    }
+
+    /// The samples from (x0 - 1, y0 - 1) to (x0 + 2, y0 + 2) around the
+    /// cell at (x0, y0), in rows. Those past the edges of the map are
+    /// extrapolated from the last two samples inside it, so a slope carries
+    /// on to the edge rather than flattening out.
+    fn neighbourhood(&self, x0: usize, y0: usize) -> [[CpuScalar; 4]; 4] {
+        // Clamped to the map, the samples past it are replaced below.
+        let xs = [
+            x0.max(1) - 1,
+            x0,
+            (x0 + 1).min(self.x_max),
+            (x0 + 2).min(self.x_max),
+        ];
+        let ys = [
+            y0.max(1) - 1,
+            y0,
+            (y0 + 1).min(self.y_max),
+            (y0 + 2).min(self.y_max),
+        ];
+        let mut samples = [[0.0; 4]; 4];
+        match self.samples {
+            Samples::InMemory(ref height) => {
+                let row_size = self.x_max + 1;
+                for (row, &y) in samples.iter_mut().zip(ys.iter()) {
+                    for (sample, &x) in row.iter_mut().zip(xs.iter()) {
+                        *sample = height[y * row_size + x];
+                    }
+                }
+            }
+            Samples::Tiled(ref tiles) => {
+                for (row, &y) in samples.iter_mut().zip(ys.iter()) {
+                    for (sample, &x) in row.iter_mut().zip(xs.iter()) {
+                        *sample = tiles.height_at(x, y);
+                    }
+                }
+            }
+        }
+
+        let (first_x, last_x) = (x0 == 0, x0 + 2 > self.x_max);
+        for row in samples.iter_mut() {
+            if first_x {
+                row[0] = 2.0 * row[1] - row[2];
+            }
+            if last_x {
+                row[3] = 2.0 * row[2] - row[1];
+            }
+        }
+        for x in 0..4 {
+            if y0 == 0 {
+                samples[0][x] = 2.0 * samples[1][x] - samples[2][x];
+            }
+            if y0 + 2 > self.y_max {
+                samples[3][x] = 2.0 * samples[2][x] - samples[1][x];
+            }
+        }
+        samples
+    }
+
+    /// The height at the position in samples (`x`, `y`), interpolated with
+    /// Catmull-Rom splines, and its gradient in longitude and latitude.
+    /// Unlike bilinear interpolation the slope is continuous across cells,
+    /// so meshes of the map don't look faceted. Past the edges the height
+    /// carries on along the slope at them.
+    fn height_and_gradient(&self, x: CpuScalar, y: CpuScalar) -> (CpuScalar, Vector2<CpuScalar>) {
+        let (x_scale, y_scale) = (
+            self.x_max as CpuScalar / self.extent[0],
+            self.y_max as CpuScalar / self.extent[1],
+        );
+        let (x_inside, y_inside) = (
+            x.max(0.0).min(self.x_max as CpuScalar),
+            y.max(0.0).min(self.y_max as CpuScalar),
+        );
+
+        // The cell around (x, y), the last one on the right and bottom edges.
+        let x0 = x_inside.floor().min(self.x_max.max(1) as CpuScalar - 1.0);
+        let y0 = y_inside.floor().min(self.y_max.max(1) as CpuScalar - 1.0);
+        let samples = self.neighbourhood(x0 as usize, y0 as usize);
+        let (x_weights, x_slopes) = catmull_rom_weights(x_inside - x0);
+        let (y_weights, y_slopes) = catmull_rom_weights(y_inside - y0);
 
+        let (mut height, mut dx, mut dy) = (0.0, 0.0, 0.0);
+        for (row, y) in samples.iter().zip(0..4) {
+            let (mut row_height, mut row_dx) = (0.0, 0.0);
+            for (&sample, x) in row.iter().zip(0..4) {
+                row_height += x_weights[x] * sample;
+                row_dx += x_slopes[x] * sample;
+            }
+            height += y_weights[y] * row_height;
+            dx += y_weights[y] * row_dx;
+            dy += y_slopes[y] * row_height;
+        }
+        let height = height + dx * (x - x_inside) + dy * (y - y_inside);
         assert!(
-            hxy.is_finite(),
-            format!(
-                "long: {} lat: {} -> xy: {} {} {} {} | h: {} {} {} {} | \
-                         hxy: {} {} {}",
-                long,
-                lat,
-                x0,
-                x1,
-                y0,
-                y1,
-                h00,
-                h01,
-                h10,
-                h11,
-                hx0,
-                hx1,
-                hxy
-            )
+            height.is_finite(),
+            format!("xy: {} {} | h: {:?}", x, y, samples)
         );
-        hxy
+        (height, Vector2::new(dx * x_scale, dy * y_scale))
+    }
+}
+
+/// The weights of four consecutive samples in a Catmull-Rom spline at `t`
+/// between the middle two, and those of their derivative.
+#[inline]
+fn catmull_rom_weights(t: CpuScalar) -> ([CpuScalar; 4], [CpuScalar; 4]) {
+    let (t2, t3) = (t * t, t * t * t);
+    (
+        [
+            -0.5 * t3 + t2 - 0.5 * t,
+            1.5 * t3 - 2.5 * t2 + 1.0,
+            -1.5 * t3 + 2.0 * t2 + 0.5 * t,
+            0.5 * t3 - 0.5 * t2,
+        ],
+        [
+            -1.5 * t2 + 2.0 * t - 0.5,
+            4.5 * t2 - 5.0 * t,
+            -4.5 * t2 + 4.0 * t + 0.5,
+            1.5 * t2 - t,
+        ],
+    )
+}
+
+/// Replaces the voids among `heights`, in rows of `width`, with a smooth
+/// fill from the samples around them, returning how many there were. Voids
+/// are samples that aren't finite or are at most `nodata`.
+fn fill_voids(heights: &mut [CpuScalar], width: usize, nodata: Option<CpuScalar>) -> usize {
+    let mut num_voids = 0;
+    for height in heights.iter_mut() {
+        if !height.is_finite() || nodata.map(|nodata| *height <= nodata).unwrap_or(false) {
+            *height = CpuScalar::NAN;
+            num_voids += 1;
+        }
+    }
+    if num_voids > 0 {
+        fill_nans(heights, width);
+    }
+    num_voids
+}
+
+/// Fills the NaNs among `heights` in rows of `width`. Each level of a
+/// pyramid of averages of two by two samples fills the holes of the one
+/// below, which are then relaxed towards the samples around them, so wide
+/// voids take no longer to fill than narrow ones.
+fn fill_nans(heights: &mut [CpuScalar], width: usize) {
+    let voids: Vec<usize> = (0..heights.len())
+        .filter(|&index| heights[index].is_nan())
+        .collect();
+    if voids.is_empty() {
+        return;
+    } else if voids.len() == heights.len() {
+        // With nothing to fill from the surface is at the datum.
+        for height in heights.iter_mut() {
+            *height = 0.0;
+        }
+        return;
+    }
+
+    let height = heights.len() / width;
+    let (coarse_width, coarse_height) = ((width + 1) / 2, (height + 1) / 2);
+    let mut coarse = vec![CpuScalar::NAN; coarse_width * coarse_height];
+    for (coarse_index, average) in coarse.iter_mut().enumerate() {
+        let (x, y) = (coarse_index % coarse_width * 2, coarse_index / coarse_width * 2);
+        let (mut sum, mut count) = (0.0, 0);
+        for &(x, y) in &[(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)] {
+            if x < width && y < height && !heights[y * width + x].is_nan() {
+                sum += heights[y * width + x];
+                count += 1;
+            }
+        }
+        if count > 0 {
+            *average = sum / count as CpuScalar;
+        }
+    }
+    fill_nans(&mut coarse, coarse_width);
+
+    // Bilinear from the averages, whose centres are between the samples.
+    for &index in &voids {
+        let (x, y) = (index % width, index / width);
+        let u = ((x as CpuScalar - 0.5) / 2.0).max(0.0).min((coarse_width - 1) as CpuScalar);
+        let v = ((y as CpuScalar - 0.5) / 2.0).max(0.0).min((coarse_height - 1) as CpuScalar);
+        let (u0, v0) = (u.floor() as usize, v.floor() as usize);
+        let (u1, v1) = ((u0 + 1).min(coarse_width - 1), (v0 + 1).min(coarse_height - 1));
+        let (s, t) = (u - u0 as CpuScalar, v - v0 as CpuScalar);
+        let top = coarse[v0 * coarse_width + u0] * (1.0 - s) + coarse[v0 * coarse_width + u1] * s;
+        let bottom = coarse[v1 * coarse_width + u0] * (1.0 - s) +
+            coarse[v1 * coarse_width + u1] * s;
+        heights[index] = top * (1.0 - t) + bottom * t;
+    }
+    for _ in 0..VOID_RELAXATION_STEPS {
+        for &index in &voids {
+            let (x, y) = (index % width, index / width);
+            let (mut sum, mut count) = (0.0, 0);
+            if x > 0 {
+                sum += heights[index - 1];
+                count += 1;
+            }
+            if x + 1 < width {
+                sum += heights[index + 1];
+                count += 1;
+            }
+            if y > 0 {
+                sum += heights[index - width];
+                count += 1;
+            }
+            if y + 1 < height {
+                sum += heights[index + width];
+                count += 1;
+            }
+            if count > 0 {
+                heights[index] = sum / count as CpuScalar;
+            }
+        }
+    }
+}
+
+/// The lowest and highest of `heights`.
+fn height_range(heights: &[CpuScalar]) -> (CpuScalar, CpuScalar) {
+    heights.iter().fold(
+        (CpuScalar::INFINITY, CpuScalar::NEG_INFINITY),
+        |(low, high), &height| (low.min(height), high.max(height)),
+    )
+}
+
+/// Where the samples of a heightmap are kept.
+enum Samples {
+    /// All of them, in rows.
+    InMemory(Vec<CpuScalar>),
+    /// Only the tiles near what's being sampled, for maps too large to load.
+    Tiled(TileCache),
+}
+
+/// Square tiles of the samples of a PDS file, read from it the first time
+/// they're sampled and evicted when they're the least recently used of their
+/// shard. Each shard has its own lock and its own handle on the file.
+struct TileCache {
+    image: PdsImage,
+    nodata: Option<CpuScalar>,
+    shards: Vec<Mutex<TileShard>>,
+}
+
+struct TileShard {
+    file: File,
+    tiles: LruCache<(usize, usize), Vec<CpuScalar>>,
+}
+
+impl TileCache {
+    /// Splits `max_tiles` between at most `TILE_SHARDS` shards, opening the
+    /// file at `path` for each.
+    fn open(
+        path: &Path,
+        image: PdsImage,
+        nodata: Option<CpuScalar>,
+        max_tiles: usize,
+    ) -> Result<Self> {
+        let max_tiles = max_tiles.max(1);
+        let num_shards = TILE_SHARDS.min(max_tiles);
+        let mut shards = Vec::with_capacity(num_shards);
+        for _ in 0..num_shards {
+            let file = try!(File::open(path).chain_err(|| "Failed opening heightmap file."));
+            shards.push(Mutex::new(TileShard {
+                file: file,
+                tiles: LruCache::with_capacity(max_tiles / num_shards),
+            }));
+        }
+        Ok(TileCache {
+            image: image,
+            nodata: nodata,
+            shards: shards,
+        })
+    }
+
+    fn height_at(&self, x: usize, y: usize) -> CpuScalar {
+        let key = (x / TILE_SIZE, y / TILE_SIZE);
+        // Neighbouring tiles, sampled together, are in different shards.
+        let shard = (key.0 + key.1 * TILE_SHARD_ROW_STRIDE) % self.shards.len();
+        let mut shard = self.shards[shard].lock().unwrap();
+        let TileShard {
+            ref mut file,
+            ref mut tiles,
+        } = *shard;
+        if !tiles.contains_key(&key) {
+            let tile = self.read_tile(file, key).unwrap_or_else(|err| {
+                // The size of the file was checked when it was opened, so
+                // this is an I/O error the renderer can't do anything about.
+                error!("Could not read heightmap tile {:?}: {}", key, err);
+                vec![0.0; TILE_SIZE * TILE_SIZE]
+            });
+            tiles.insert(key, tile);
+        }
+        tiles.get(&key).unwrap()[(y % TILE_SIZE) * TILE_SIZE + x % TILE_SIZE]
+    }
+
+    /// The tile's rows, including the part short of the right and bottom
+    /// edges of the map when it's on them.
+    fn read_tile(
+        &self,
+        file: &mut File,
+        (tile_x, tile_y): (usize, usize),
+    ) -> Result<Vec<CpuScalar>> {
+        let (left, top) = (tile_x * TILE_SIZE, tile_y * TILE_SIZE);
+        let width = TILE_SIZE.min(self.image.line_samples - left);
+        let mut samples = Vec::with_capacity(width * TILE_SIZE);
+        let mut row = vec![0; width * self.image.sample_bytes];
+        let mut row_heights = vec![None; width];
+        for y in top..(top + TILE_SIZE).min(self.image.lines) {
+            try!(
+                file.seek(SeekFrom::Start(self.image.sample_offset(left, y)))
+                    .and_then(|_| file.read_exact(&mut row))
+                    .chain_err(|| "Could not read heightmap samples.")
+            );
+            self.image.decode(&row, &mut row_heights);
+            samples.extend(row_heights.iter().map(
+                |value| value.unwrap_or(CpuScalar::NAN),
+            ));
+        }
+        fill_voids(&mut samples, width, self.nodata);
+
+        let mut tile = vec![0.0; TILE_SIZE * TILE_SIZE];
+        for (tile_row, row) in tile.chunks_mut(TILE_SIZE).zip(samples.chunks(width)) {
+            tile_row[..width].copy_from_slice(row);
+        }
+        Ok(tile)
+    }
+}
+
+/// The samples of a grayscale image in rows.
+struct GraySamples {
+    width: u32,
+    height: u32,
+    values: Vec<CpuScalar>,
+    /// The brightest value of unsigned integer samples, None if they aren't.
+    max_value: Option<CpuScalar>,
+}
+
+/// Reads the grayscale samples of a PNG or TIFF image at their full depth,
+/// which the `image` crate truncates to 8 bits. None for other formats and
+/// colour images.
+fn read_gray_samples(path: &Path) -> Result<Option<GraySamples>> {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let file = try!(File::open(path).chain_err(|| {
+        format!("Could not open heightmap image at {:?}", path)
+    }));
+    let reader = BufReader::new(file);
+    match extension.as_ref().map(|extension| &extension[..]) {
+        Some("png") => read_png_samples(reader).chain_err(|| {
+            format!("Could not decode heightmap PNG at {:?}", path)
+        }),
+        Some("tif") | Some("tiff") => read_tiff_samples(reader).chain_err(|| {
+            format!("Could not decode heightmap TIFF at {:?}", path)
+        }),
+        _ => Ok(None),
+    }
+}
+
+fn read_png_samples<R: Read>(reader: R) -> Result<Option<GraySamples>> {
+    let mut decoder = png::Decoder::new(reader);
+    // Without transformations 16 bit samples are kept as pairs of big endian
+    // bytes, rather than stripped to 8 bits.
+    decoder.set(png::TRANSFORM_IDENTITY);
+    let (info, mut reader) = try!(decoder.read_info().map_err(|err| err.to_string()));
+    let num_channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        _ => return Ok(None),
+    };
+    // Interlaced images are only deinterlaced right at 8 bits, and lower
+    // depths pack several samples in a byte.
+    if reader.info().interlaced || (info.bit_depth as u8) < 8 {
+        return Ok(None);
+    }
+    let mut data = vec![0; info.buffer_size()];
+    try!(reader.next_frame(&mut data).map_err(|err| err.to_string()));
+    let (values, max_value) = if info.bit_depth as u8 == 16 {
+        let values = data.chunks(2 * num_channels)
+            .map(|pixel| BigEndian::read_u16(pixel) as CpuScalar)
+            .collect();
+        (values, Some(u16::max_value() as CpuScalar))
+    } else {
+        let values = data.chunks(num_channels)
+            .map(|pixel| pixel[0] as CpuScalar)
+            .collect();
+        (values, Some(u8::max_value() as CpuScalar))
+    };
+    Ok(Some(GraySamples {
+        width: info.width,
+        height: info.height,
+        values: values,
+        max_value: max_value,
+    }))
+}
+
+fn read_tiff_samples<R: Read + Seek>(mut reader: R) -> Result<Option<GraySamples>> {
+    let raster = try!(TiffRaster::read(&mut reader));
+    Ok(Some(GraySamples {
+        width: raster.width,
+        height: raster.height,
+        values: raster.samples,
+        max_value: raster.max_value,
+    }))
+}
+
+impl ScalarField2 for Heightmap {
+    #[inline]
+    fn value_at(&self, position: &Point2<CpuScalar>) -> CpuScalar {
+        self.height_and_gradient(position).0
+    }
+
+    #[inline]
+    fn gradient_at(&self, position: &Point2<CpuScalar>) -> Vector2<CpuScalar> {
+        self.height_and_gradient(position).1
     }
 }
 
@@ -199,8 +1015,248 @@ impl ScalarField3 for Heightmap {
 
         r - field_radius
     }
+
+    /// The distance from the centre less the height, differentiated through
+    /// the longitude and latitude.
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        let (x, y, z) = (position[0], position[1], position[2]);
+        let r = position.distance(&Point3::origin()) + 1e-4;
+        let long = (z.atan2(x) + PI) * FRAC_1_PI * 0.5;
+        let lat = (y / r).acos() * FRAC_1_PI;
+        let slope = self.height_and_gradient(&Point2::new(long, lat)).1 / 1000.0;
+
+        // The distance from the axis, kept off 0 at the poles.
+        let axis_squared = (x * x + z * z).max(1e-8);
+        let axis = axis_squared.sqrt();
+        let d_long = Vector3::new(-z, 0.0, x) * (FRAC_1_PI * 0.5 / axis_squared);
+        let d_lat = (Vector3::new(x, y, z) * (y / (r * r)) - Vector3::y()) * (FRAC_1_PI / axis);
+        Vector3::new(x, y, z) / r - d_long * slope[0] - d_lat * slope[1]
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use nalgebra::{Norm, Point2, Point3, Vector2, Vector3};
+
+    use math::ScalarField2;
+    use super::{fill_voids, Grid, Heightmap, Samples, TILE_SIZE};
+
+    #[test]
+    fn test_heightmap_interpolation() {
+        // Two rows of three samples.
+        let heightmap = Heightmap {
+            radius: 100.0,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(vec![0.0, 10.0, 20.0, 30.0, 40.0, 50.0]),
+                    x_max: 2,
+                    y_max: 1,
+                    origin: Point2::new(0.0, 0.0),
+                    extent: Vector2::new(1.0, 1.0),
+                },
+            ],
+        };
+        let at = |long: f32, lat: f32| heightmap.value_at(&Point2::new(long, lat));
+        assert!((at(0.25, 0.0) - 5.0).abs() < 0.1);
+        assert!((at(0.75, 0.5) - 30.0).abs() < 1e-3);
+        // The edges of the map are sampled too, not only between samples.
+        assert!((at(1.0, 1.0) - 50.0).abs() < 0.1);
+        assert!(at(0.5, 1.0).is_finite() && at(0.0, 0.5).is_finite());
+    }
+
+    #[test]
+    fn test_heightmap_gradient() {
+        use math::ScalarField3;
+
+        // Hills of a few kilometres on a body of radius 100 km.
+        let (x_samples, y_samples) = (16, 8);
+        let heightmap = Heightmap {
+            radius: 100.0,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(
+                        (0..x_samples * y_samples)
+                            .map(|index| {
+                                let (x, y) =
+                                    ((index % x_samples) as f32, (index / x_samples) as f32);
+                                2000.0 * (x * 0.9).sin() * (y * 1.3).cos()
+                            })
+                            .collect(),
+                    ),
+                    x_max: x_samples - 1,
+                    y_max: y_samples - 1,
+                    origin: Point2::new(0.0, 0.0),
+                    extent: Vector2::new(1.0, 1.0),
+                },
+            ],
+        };
+        let eps = 1e-2;
+        for index in 0..50 {
+            let angle = index as f32 * 0.37;
+            let position = Point3::new(
+                100.0 * angle.cos() * (angle * 0.3).cos(),
+                100.0 * (angle * 0.3).sin(),
+                100.0 * angle.sin() * (angle * 0.3).cos(),
+            );
+            let difference = |axis: Vector3<f32>| {
+                (ScalarField3::value_at(&heightmap, &(position + axis * eps)) -
+                     ScalarField3::value_at(&heightmap, &(position - axis * eps))) /
+                    (2.0 * eps)
+            };
+            let expected = Vector3::new(
+                difference(Vector3::x()),
+                difference(Vector3::y()),
+                difference(Vector3::z()),
+            );
+            let gradient = ScalarField3::gradient_at(&heightmap, &position);
+            assert!((gradient - expected).norm() < 2e-3, "{:?} {:?}", gradient, expected);
+        }
+    }
+
+    #[test]
+    fn test_heightmap_area() {
+        // Two by two samples across the meridian, outside of which the
+        // surface is at the datum.
+        let heightmap = Heightmap {
+            radius: 100.0,
+            grids: vec![
+                Grid {
+                    samples: Samples::InMemory(vec![10.0, 20.0, 30.0, 40.0]),
+                    x_max: 1,
+                    y_max: 1,
+                    origin: Point2::new(0.9, 0.4),
+                    extent: Vector2::new(0.2, 0.1),
+                },
+            ],
+        };
+        let at = |long: f32, lat: f32| heightmap.value_at(&Point2::new(long, lat));
+        assert!((at(0.9, 0.4) - 10.0).abs() < 1e-3);
+        assert!((at(0.05, 0.45) - 27.5).abs() < 1e-3);
+        assert_eq!(0.0, at(0.5, 0.45));
+        assert_eq!(0.0, at(0.95, 0.6));
+    }
+
+    #[test]
+    fn test_heightmap_mosaic() {
+        // Two flat tiles of two by two samples, a sample apart across the
+        // seam at longitude 0.25.
+        let tile = |long: f32, height: f32| {
+            Heightmap {
+                radius: 100.0,
+                grids: vec![
+                    Grid {
+                        samples: Samples::InMemory(vec![height; 4]),
+                        x_max: 1,
+                        y_max: 1,
+                        origin: Point2::new(long, 0.4),
+                        extent: Vector2::new(0.1, 0.1),
+                    },
+                ],
+            }
+        };
+        let mosaic = Heightmap::mosaic(vec![tile(0.1, 10.0), tile(0.3, 30.0)]).unwrap();
+        let at = |long: f32| mosaic.value_at(&Point2::new(long, 0.45));
+        assert!((at(0.12) - 10.0).abs() < 1e-3);
+        assert!((at(0.38) - 30.0).abs() < 1e-3);
+        assert!((at(0.25) - 20.0).abs() < 1e-3);
+        assert_eq!(0.0, at(0.5));
+
+        // The blend is smooth, and so is its gradient.
+        let eps = 1e-4;
+        let mut previous = at(0.15);
+        for index in 0..100 {
+            let long = 0.15 + index as f32 * 0.002;
+            let height = at(long);
+            assert!((height - previous).abs() < 1.0, "{} {} {}", long, height, previous);
+            previous = height;
+
+            let gradient = mosaic.gradient_at(&Point2::new(long, 0.45));
+            let expected = (at(long + eps) - at(long - eps)) / (2.0 * eps);
+            assert!((gradient[0] - expected).abs() < 2.0, "{} {} {}", long, gradient, expected);
+            assert!(gradient[1].abs() < 1e-3);
+        }
+
+        let other_body = Heightmap {
+            radius: 50.0,
+            ..tile(0.5, 0.0)
+        };
+        assert!(Heightmap::mosaic(vec![tile(0.1, 10.0), other_body]).is_err());
+    }
+
+    #[test]
+    fn test_fill_voids() {
+        // A plane with a wide void and a spike marked missing by its value.
+        let (width, height) = (16, 12);
+        let plane = |index: usize| (index % width) as f32 * 3.0 - (index / width) as f32 * 2.0;
+        let mut heights: Vec<f32> = (0..width * height).map(plane).collect();
+        for y in 2..8 {
+            for x in 3..10 {
+                heights[y * width + x] = f32::NAN;
+            }
+        }
+        heights[width * 10 + 14] = -32768.0;
+        assert_eq!(43, fill_voids(&mut heights, width, Some(-1000.0)));
+        for (index, &value) in heights.iter().enumerate() {
+            assert!((value - plane(index)).abs() < 1.0, "{} {} {}", index, value, plane(index));
+        }
+
+        let mut voids = vec![f32::NAN; 6];
+        assert_eq!(6, fill_voids(&mut voids, 3, None));
+        assert_eq!(vec![0.0; 6], voids);
+    }
+
+    #[test]
+    fn test_streamed_heightmap() {
+        // Three by two tiles, the last column and row of them partial.
+        let (x_samples, y_samples) = (TILE_SIZE * 2 + 10, TILE_SIZE + 3);
+        let path = env::temp_dir().join(format!("terrain-test-{}.img", ::std::process::id()));
+        {
+            let mut file = File::create(&path).unwrap();
+            for index in 0..x_samples * y_samples {
+                file.write_i16::<BigEndian>((index % 1999) as i16 - 999).unwrap();
+            }
+            file.flush().unwrap();
+        }
+        let size = Some((x_samples, y_samples));
+        let whole = Heightmap::from_pds(10.0, &path, size, None, 100).unwrap();
+        let streamed = Heightmap::from_pds(10.0, &path, size, None, 2).unwrap();
+        let _ = fs::remove_file(&path);
+        match streamed.grids[0].samples {
+            Samples::Tiled(_) => {}
+            Samples::InMemory(_) => panic!("The heightmap should be streamed."),
+        }
+        for index in 0..400 {
+            let position = Point2::new(index as f32 * 0.61 % 1.0, index as f32 * 0.37 % 1.0);
+            assert_eq!(whole.value_at(&position), streamed.value_at(&position));
+        }
+        // All the tiles were sampled, but only two are kept.
+        match streamed.grids[0].samples {
+            Samples::Tiled(ref tiles) => {
+                let shards = tiles.shards.iter();
+                let num_tiles: usize = shards.map(|shard| shard.lock().unwrap().tiles.len()).sum();
+                assert_eq!(2, num_tiles);
+            }
+            Samples::InMemory(_) => unreachable!(),
+        }
+    }
+}
+
+// Width and height of the tiles of streamed heightmaps, in samples.
+const TILE_SIZE: usize = 256;
+// The most shards their cache is split in, and how many shards apart the
+// tiles above and below each other are.
+const TILE_SHARDS: usize = 16;
+const TILE_SHARD_ROW_STRIDE: usize = 5;
+// Tiles of a mosaic fade in from this many samples outside their edges to as
+// many inside them.
+const BLEND_SAMPLES: CpuScalar = 1.0;
+// Passes smoothing the fill of voids at each level of its pyramid.
+const VOID_RELAXATION_STEPS: usize = 4;
+
 // pub trait MapProjection {
 //     fn project(&self, position: &Point3<CpuScalar>) -> Point2<CpuScalar>;
 // }
@@ -0,0 +1,240 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+
+use errors::{ChainErr, ErrorKind, Result};
+
+/// Where and how verbosely to log. `filters` uses the same syntax as
+/// `RUST_LOG`: a comma separated list of `module=level` directives and an
+/// optional bare default level, e.g. `"info,terrain::gfx::lod=warn"`.
+#[derive(Clone, Debug)]
+pub struct LogSpec {
+    pub filters: String,
+    pub file: Option<PathBuf>,
+    pub max_file_size: u64,
+    pub max_files: usize,
+}
+
+impl Default for LogSpec {
+    fn default() -> Self {
+        LogSpec {
+            filters: "info".to_owned(),
+            file: None,
+            max_file_size: 16 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+/// Installs the global logger. Records always go to stderr and are also
+/// appended to `spec.file` if set, rotating it once it grows past
+/// `spec.max_file_size`.
+pub fn init(spec: &LogSpec) -> Result<()> {
+    let directives = try!(parse_filters(&spec.filters));
+    let file = match spec.file {
+        Some(ref path) => {
+            Some(Mutex::new(try!(RotatingFile::open(
+                path.clone(),
+                spec.max_file_size,
+                spec.max_files,
+            ))))
+        }
+        None => None,
+    };
+    let max_level = directives.iter().map(|directive| directive.level).max().unwrap_or(
+        LogLevelFilter::Off,
+    );
+
+    log::set_logger(|max_log_level| {
+        max_log_level.set(max_level);
+        Box::new(Logger {
+            directives: directives,
+            file: file,
+        })
+    }).chain_err(|| "Could not initialize logger.")
+}
+
+struct Directive {
+    module: Option<String>,
+    level: LogLevelFilter,
+}
+
+fn parse_filters(filters: &str) -> Result<Vec<Directive>> {
+    let mut directives = vec![];
+    for part in filters.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        let mut fields = part.splitn(2, '=');
+        let (module, level) = match (fields.next(), fields.next()) {
+            (Some(level), None) => (None, level),
+            (Some(module), Some(level)) => (Some(module.to_owned()), level),
+            _ => unreachable!(),
+        };
+        let level = try!(level.parse().map_err(|_| {
+            ErrorKind::InvalidLogFilter(part.to_owned())
+        }));
+        directives.push(Directive {
+            module: module,
+            level: level,
+        });
+    }
+    Ok(directives)
+}
+
+struct Logger {
+    directives: Vec<Directive>,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Logger {
+    /// The level of the most specific directive matching `target`.
+    fn level_for(&self, target: &str) -> LogLevelFilter {
+        let mut best_length = None;
+        let mut level = LogLevelFilter::Off;
+        for directive in self.directives.iter() {
+            let length = match directive.module {
+                Some(ref module) if is_submodule(target, module) => module.len(),
+                Some(_) => continue,
+                None => 0,
+            };
+            if best_length.map_or(true, |best_length| length >= best_length) {
+                best_length = Some(length);
+                level = directive.level;
+            }
+        }
+        level
+    }
+}
+
+fn is_submodule(target: &str, module: &str) -> bool {
+    target == module || (target.starts_with(module) && target[module.len()..].starts_with("::"))
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| {
+                format!("{}.{:03}", elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000)
+            })
+            .unwrap_or_default();
+        let line = format!(
+            "[{}] {:<5} {}: {}\n",
+            timestamp,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        let _ = io::stderr().write_all(line.as_bytes());
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                if let Err(err) = file.write_line(&line) {
+                    let _ = writeln!(io::stderr(), "Could not write to log file: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// A log file which is renamed to `<path>.1` once it exceeds `max_size`
+/// bytes, shifting older files up to `<path>.<max_files>`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> Result<Self> {
+        let file = try!(open_append(&path));
+        let size = try!(file.metadata().chain_err(|| {
+            format!("Could not read metadata of log file {:?}", path)
+        })).len();
+        Ok(RotatingFile {
+            path: path,
+            file: file,
+            size: size,
+            max_size: max_size,
+            max_files: max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            try!(self.rotate());
+        }
+        try!(self.file.write_all(line.as_bytes()).chain_err(|| {
+            format!("Could not write to log file {:?}", self.path)
+        }));
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        for index in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                try!(fs::rename(&from, rotated_path(&self.path, index + 1)).chain_err(|| {
+                    format!("Could not rotate log file {:?}", from)
+                }));
+            }
+        }
+        if self.max_files > 0 {
+            try!(fs::rename(&self.path, rotated_path(&self.path, 1)).chain_err(|| {
+                format!("Could not rotate log file {:?}", self.path)
+            }));
+        } else {
+            try!(fs::remove_file(&self.path).chain_err(|| {
+                format!("Could not truncate log file {:?}", self.path)
+            }));
+        }
+        self.file = try!(open_append(&self.path));
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .chain_err(|| format!("Could not open log file {:?}", path))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use log::LogLevelFilter;
+    use super::{Logger, parse_filters};
+
+    #[test]
+    fn test_most_specific_filter_wins() {
+        let logger = Logger {
+            directives: parse_filters("info, terrain::gfx=debug,terrain::gfx::lod=warn").unwrap(),
+            file: None,
+        };
+        assert_eq!(LogLevelFilter::Info, logger.level_for("terrain::planet"));
+        assert_eq!(LogLevelFilter::Debug, logger.level_for("terrain::gfx::mesh"));
+        assert_eq!(LogLevelFilter::Warn, logger.level_for("terrain::gfx::lod"));
+        assert_eq!(LogLevelFilter::Info, logger.level_for("terrain::gfx_extra"));
+        assert!(parse_filters("terrain=loud").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 #![recursion_limit = "1024"]
 
+extern crate backtrace;
 extern crate byteorder;
 #[macro_use]
 extern crate chan;
@@ -7,12 +8,13 @@ extern crate chan;
 extern crate clap;
 #[macro_use]
 extern crate custom_derive;
-extern crate env_logger;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
 #[macro_use]
 extern crate glium;
 extern crate image;
+extern crate inflate;
 #[macro_use]
 extern crate log;
 extern crate lru_time_cache;
@@ -24,143 +26,1019 @@ extern crate newtype_derive;
 extern crate noise;
 extern crate nphysics3d;
 extern crate num;
+extern crate num_cpus;
+extern crate png;
 extern crate rand;
 extern crate rayon;
 extern crate threadpool;
+extern crate toml;
 extern crate wavefront_obj;
 
+mod assets;
+mod asteroids;
+mod atmosphere;
+mod bench;
+mod clouds;
+mod config;
+mod crash;
+mod edit;
+mod erosion;
 mod errors;
+mod export;
+mod fractal;
+mod fields;
+mod floating_islands;
+mod flora;
+mod gas_giant;
 mod game;
 mod gfx;
 mod math;
 mod utils;
+mod ocean;
+mod pds;
 mod planet;
+mod prefab;
+mod solar_system;
+mod structures;
+mod tectonics;
 mod heightmap;
+mod logging;
+mod tiff;
+mod trace;
 
-use std::error::Error;
-use clap::Arg;
+use std::cmp;
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use clap::{Arg, ArgMatches, SubCommand};
 use rand::Rng;
 
+use assets::Assets;
+use bench::run_benchmark;
+use config::{Config, ConfigWatcher};
+use edit::WorldDir;
+use erosion::ErodedField;
 use errors::Result;
-use gfx::App;
-use planet::{PlanetField, PlanetSpec};
+use export::{export_heightmap, export_to_meshes, export_to_obj, export_to_stl};
+use fields::{FlatField, SquareField, TorusField, with_solids};
+use floating_islands::FloatingIslandsField;
+use fractal::{Fractal, NoiseBasis};
+use gfx::{App, FullscreenMode, GpuPlanetField};
+use gfx::mesh_cache::CacheDir;
+use gfx::mesh_format::MeshCompression;
+use heightmap::{Heightmap, HeightmapFormat};
+use logging::LogSpec;
+use math::{CachedField, Mesher, ScalarField3};
+use planet::{PlanetField, PlanetSpec, SurfaceMapping};
+
+fn config_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("config")
+            .long("config")
+            .value_name("path")
+            .help("TOML file with planet, window, LOD and controls settings.")
+            .takes_value(true),
+        Arg::with_name("assets_dir")
+            .long("assets-dir")
+            .value_name("path")
+            .help("Directory with shaders, textures and heightmaps.")
+            .takes_value(true),
+        Arg::with_name("log_level")
+            .long("log-level")
+            .value_name("filters")
+            .help("Log levels, e.g. 'info,terrain::gfx::lod=warn'. Defaults to RUST_LOG or info.")
+            .takes_value(true),
+        Arg::with_name("log_file")
+            .long("log-file")
+            .value_name("path")
+            .help("Also append logs to this file, rotating it when it gets large.")
+            .takes_value(true),
+        Arg::with_name("trace")
+            .long("trace")
+            .value_name("path")
+            .help("Writes timing spans to this file, open it in chrome://tracing.")
+            .takes_value(true),
+        Arg::with_name("field")
+            .long("field")
+            .value_name("name")
+            .help(
+                "Scalar field to generate the terrain from, or a gas giant without terrain.",
+            )
+            .takes_value(true)
+            .possible_values(
+                &[
+                    "planet",
+                    "heightmap",
+                    "square",
+                    "torus",
+                    "flat",
+                    "floating_islands",
+                    "gas_giant",
+                ],
+            )
+            .default_value("planet"),
+        Arg::with_name("heightmap")
+            .long("heightmap")
+            .value_name("path")
+            .help(
+                "Elevation model to fly over, in metres, instead of a generated planet. Implies \
+                 --field heightmap, which defaults to the MOLA map of Mars in the assets. Given \
+                 more than once, the maps are tiles of a mosaic placed by their georeferencing \
+                 or PDS map projection.",
+            )
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("heightmap_radius")
+            .long("heightmap-radius")
+            .value_name("f32")
+            .help("Radius of the heightmap's body, in kilometres like the rest of its world.")
+            .takes_value(true)
+            .default_value("3396.0"),
+        Arg::with_name("heightmap_format")
+            .long("heightmap-format")
+            .value_name("name")
+            .help(
+                "Raw big endian 16 bit samples of a PDS image, a grayscale image whose \
+                 brightness is the height, keeping 16 bit PNG and TIFF samples whole, or a \
+                 GeoTIFF elevation model placed on the body by its georeferencing.",
+            )
+            .takes_value(true)
+            .possible_values(&HEIGHTMAP_FORMATS)
+            .default_value("pds"),
+        Arg::with_name("heightmap_size")
+            .long("heightmap-size")
+            .value_name("WIDTHxHEIGHT")
+            .help(
+                "Samples per row and rows of a PDS heightmap, read from its label if it has one. \
+                 Images know their own.",
+            )
+            .takes_value(true),
+        Arg::with_name("heightmap_nodata")
+            .long("heightmap-nodata")
+            .value_name("metres")
+            .help(
+                "Heights of a heightmap at or below this are voids, filled in from the samples \
+                 around them like those its file marks missing.",
+            )
+            .takes_value(true),
+        Arg::with_name("heightmap_tiles")
+            .long("heightmap-tiles")
+            .value_name("count")
+            .help(
+                "Tiles of 256 x 256 samples of a PDS heightmap kept in memory. Larger maps are \
+                 streamed from disk rather than loaded whole.",
+            )
+            .takes_value(true)
+            .default_value("1024"),
+        Arg::with_name("heightmap_min")
+            .long("heightmap-min")
+            .value_name("metres")
+            .help(
+                "Height of the darkest samples of an image heightmap, which are taken as the \
+                 heights if missing.",
+            )
+            .takes_value(true)
+            .requires("heightmap_max"),
+        Arg::with_name("heightmap_max")
+            .long("heightmap-max")
+            .value_name("metres")
+            .help("Height of the brightest samples of an image heightmap.")
+            .takes_value(true)
+            .requires("heightmap_min"),
+        Arg::with_name("seed")
+            .long("seed")
+            .value_name("u32")
+            .help("World seed, chosen at random if missing.")
+            .takes_value(true),
+        Arg::with_name("base_radius")
+            .long("base-radius")
+            .value_name("f32")
+            .takes_value(true),
+        Arg::with_name("deviation")
+            .long("deviation")
+            .value_name("f32")
+            .takes_value(true),
+        Arg::with_name("num_octaves")
+            .long("num-octaves")
+            .value_name("usize")
+            .takes_value(true),
+        Arg::with_name("persistence")
+            .long("persistence")
+            .value_name("f32")
+            .takes_value(true),
+        Arg::with_name("wavelength")
+            .long("wavelength")
+            .value_name("f32")
+            .takes_value(true),
+        Arg::with_name("lacunarity")
+            .long("lacunarity")
+            .value_name("f32")
+            .takes_value(true),
+        Arg::with_name("mountain_basis")
+            .long("mountain-basis")
+            .value_name("name")
+            .help("Noise the mountains are made of.")
+            .takes_value(true)
+            .possible_values(&NOISE_BASES),
+        Arg::with_name("mountain_fractal")
+            .long("mountain-fractal")
+            .value_name("name")
+            .help("How the octaves of the mountain noise are combined.")
+            .takes_value(true)
+            .possible_values(&FRACTALS),
+        Arg::with_name("plains_basis")
+            .long("plains-basis")
+            .value_name("name")
+            .help("Noise the plains are made of.")
+            .takes_value(true)
+            .possible_values(&NOISE_BASES),
+        Arg::with_name("plains_fractal")
+            .long("plains-fractal")
+            .value_name("name")
+            .help("How the octaves of the plains noise are combined.")
+            .takes_value(true)
+            .possible_values(&FRACTALS),
+        Arg::with_name("surface_mapping")
+            .long("surface-mapping")
+            .value_name("name")
+            .help("Samples the noise on the sphere or on a cube wrapped around it.")
+            .takes_value(true)
+            .possible_values(&SURFACE_MAPPINGS),
+        Arg::with_name("mesher")
+            .long("mesher")
+            .value_name("name")
+            .help("Polygonises the planet with marching cubes or dual contouring.")
+            .takes_value(true)
+            .possible_values(&MESHERS),
+        Arg::with_name("sea_level")
+            .long("sea-level")
+            .value_name("f32")
+            .help("Adds an ocean at this height, from -1 (lowest terrain) to 1 (highest).")
+            .takes_value(true),
+        Arg::with_name("polar_cap_latitude")
+            .long("polar-cap-latitude")
+            .value_name("degrees")
+            .help("Covers the planet in ice past this latitude.")
+            .takes_value(true),
+        Arg::with_name("snow_line")
+            .long("snow-line")
+            .value_name("f32")
+            .help("Covers peaks above this height in snow, on the same scale as --sea-level.")
+            .takes_value(true),
+        Arg::with_name("beach_height")
+            .long("beach-height")
+            .value_name("f32")
+            .help("Covers the shores in sand up to this far above --sea-level.")
+            .takes_value(true),
+        Arg::with_name("gpu_density")
+            .long("gpu-density")
+            .help(
+                "Evaluates the planet's noise in a compute shader, falling back to the CPU if \
+                 unavailable.",
+            ),
+        Arg::with_name("gpu_meshing")
+            .long("gpu-meshing")
+            .requires("gpu_density")
+            .help(
+                "Also finds the triangles of marching cubes chunks in a compute shader, falling \
+                 back to the CPU if unavailable.",
+            ),
+        Arg::with_name("num_craters")
+            .long("num-craters")
+            .value_name("usize")
+            .help("Impact craters scattered over the planet.")
+            .takes_value(true),
+        Arg::with_name("num_volcanoes")
+            .long("num-volcanoes")
+            .value_name("usize")
+            .help("Volcanoes with lava filled calderas placed at hotspots.")
+            .takes_value(true),
+        Arg::with_name("erosion_droplets")
+            .long("erosion-droplets")
+            .value_name("usize")
+            .help("Hydraulic erosion droplets per cube face of the planet, none if 0.")
+            .takes_value(true),
+        Arg::with_name("thermal_iterations")
+            .long("thermal-iterations")
+            .value_name("usize")
+            .help("Thermal erosion passes flattening slopes past the talus angle, none if 0.")
+            .takes_value(true),
+        Arg::with_name("max_level")
+            .long("max-level")
+            .value_name("u8")
+            .help("Deepest octree level to generate.")
+            .takes_value(true),
+        Arg::with_name("surface_nets_levels")
+            .long("surface-nets-levels")
+            .value_name("u8")
+            .help("Meshes this many of the coarsest octree levels with surface nets.")
+            .takes_value(true),
+        Arg::with_name("skirt_length")
+            .long("skirt-length")
+            .value_name("f32")
+            .help("Hangs skirts this many steps long from the borders of the chunks, none if 0.")
+            .takes_value(true),
+    ]
+}
+
+fn load_config(matches: &ArgMatches) -> Result<Config> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => try!(Config::from_file(path)),
+        None => Config::default(),
+    };
+    apply_overrides(matches, &mut config);
+    // The overrides may break limits the file kept to, e.g. `--max-level`.
+    try!(config.lod.validate());
+    Ok(config)
+}
+
+/// Overrides the values in `config` with the ones given on the command line.
+fn apply_overrides(matches: &ArgMatches, config: &mut Config) {
+    {
+        let planet_spec = &mut config.planet;
+        // The rest of the world, e.g. the octree, camera and gravity, is
+        // laid out around the planet's radius.
+        if field_name(matches) == "heightmap" {
+            planet_spec.base_radius = value_t!(matches, "heightmap_radius", f32)
+                .unwrap_or_else(|e| e.exit());
+        }
+        if matches.is_present("base_radius") {
+            value_t!(matches, "base_radius", f32)
+                .map(|v| planet_spec.base_radius = v)
+                .unwrap();
+        }
+        if matches.is_present("deviation") {
+            value_t!(matches, "deviation", f32)
+                .map(|v| planet_spec.landscape_deviation = v)
+                .unwrap();
+        }
+        if matches.is_present("num_octaves") {
+            value_t!(matches, "num_octaves", usize)
+                .map(|v| planet_spec.num_octaves = v)
+                .unwrap();
+        }
+        if matches.is_present("persistence") {
+            value_t!(matches, "persistence", f32)
+                .map(|v| planet_spec.persistence = v)
+                .unwrap();
+        }
+        if matches.is_present("wavelength") {
+            value_t!(matches, "wavelength", f32)
+                .map(|v| planet_spec.wavelength = v)
+                .unwrap();
+        }
+        if matches.is_present("lacunarity") {
+            value_t!(matches, "lacunarity", f32)
+                .map(|v| planet_spec.lacunarity = v)
+                .unwrap();
+        }
+        if let Some(name) = matches.value_of("mountain_basis") {
+            planet_spec.mountain_basis = NoiseBasis::from_name(name).unwrap();
+        }
+        if let Some(name) = matches.value_of("mountain_fractal") {
+            planet_spec.mountain_fractal = Fractal::from_name(name).unwrap();
+        }
+        if let Some(name) = matches.value_of("plains_basis") {
+            planet_spec.plains_basis = NoiseBasis::from_name(name).unwrap();
+        }
+        if let Some(name) = matches.value_of("plains_fractal") {
+            planet_spec.plains_fractal = Fractal::from_name(name).unwrap();
+        }
+        if let Some(name) = matches.value_of("surface_mapping") {
+            planet_spec.surface_mapping = SurfaceMapping::from_name(name).unwrap();
+        }
+        if let Some(name) = matches.value_of("mesher") {
+            planet_spec.mesher = Mesher::from_name(name).unwrap();
+        }
+        if matches.is_present("sea_level") {
+            value_t!(matches, "sea_level", f32)
+                .map(|v| planet_spec.sea_level = Some(v))
+                .unwrap();
+        }
+        if matches.is_present("polar_cap_latitude") {
+            value_t!(matches, "polar_cap_latitude", f32)
+                .map(|v| planet_spec.polar_cap_latitude = v)
+                .unwrap();
+        }
+        if matches.is_present("snow_line") {
+            value_t!(matches, "snow_line", f32)
+                .map(|v| planet_spec.snow_line = Some(v))
+                .unwrap();
+        }
+        if matches.is_present("beach_height") {
+            value_t!(matches, "beach_height", f32)
+                .map(|v| planet_spec.beach_height = v)
+                .unwrap();
+        }
+        if matches.is_present("num_craters") {
+            value_t!(matches, "num_craters", usize)
+                .map(|v| planet_spec.num_craters = v)
+                .unwrap();
+        }
+        if matches.is_present("num_volcanoes") {
+            value_t!(matches, "num_volcanoes", usize)
+                .map(|v| planet_spec.num_volcanoes = v)
+                .unwrap();
+        }
+    }
+
+    if matches.is_present("erosion_droplets") {
+        value_t!(matches, "erosion_droplets", usize)
+            .map(|v| config.erosion.num_droplets = v)
+            .unwrap();
+    }
+    if matches.is_present("thermal_iterations") {
+        value_t!(matches, "thermal_iterations", usize)
+            .map(|v| config.erosion.thermal_iterations = v)
+            .unwrap();
+    }
+    if matches.is_present("max_level") {
+        value_t!(matches, "max_level", u8)
+            .map(|v| config.lod.max_level = v)
+            .unwrap();
+    }
+    if matches.is_present("surface_nets_levels") {
+        value_t!(matches, "surface_nets_levels", u8)
+            .map(|v| config.lod.surface_nets_levels = v)
+            .unwrap();
+    }
+    if matches.is_present("skirt_length") {
+        value_t!(matches, "skirt_length", f32)
+            .map(|v| config.lod.skirt_length = v)
+            .unwrap();
+    }
+    if matches.is_present("width") {
+        value_t!(matches, "width", u32)
+            .map(|v| config.window.width = v)
+            .unwrap();
+    }
+    if matches.is_present("height") {
+        value_t!(matches, "height", u32)
+            .map(|v| config.window.height = v)
+            .unwrap();
+    }
+    if matches.is_present("fullscreen") {
+        config.window.fullscreen = FullscreenMode::Exclusive;
+    }
+    if matches.is_present("borderless") {
+        config.window.fullscreen = FullscreenMode::Borderless;
+    }
+    if matches.is_present("monitor") {
+        value_t!(matches, "monitor", usize)
+            .map(|v| config.window.monitor = Some(v))
+            .unwrap();
+    }
+    if matches.is_present("max_fps") {
+        value_t!(matches, "max_fps", u32)
+            .map(|v| config.window.max_fps = Some(v))
+            .unwrap();
+    }
+    if matches.is_present("no_vsync") {
+        config.window.vsync = false;
+    }
+}
+
+fn init_logging(matches: &ArgMatches) -> Result<()> {
+    let mut spec = LogSpec::default();
+    if let Some(filters) = matches.value_of("log_level") {
+        spec.filters = filters.to_owned();
+    } else if let Ok(filters) = env::var("RUST_LOG") {
+        spec.filters = filters;
+    }
+    spec.file = matches.value_of("log_file").map(PathBuf::from);
+    logging::init(&spec)
+}
+
+fn assets(matches: &ArgMatches) -> Assets {
+    match matches.value_of("assets_dir") {
+        Some(path) => Assets::new(path),
+        None => Assets::from_default_location(),
+    }
+}
+
+fn world_seed(matches: &ArgMatches) -> u32 {
+    if matches.is_present("seed") {
+        value_t!(matches, "seed", u32).unwrap()
+    } else {
+        rand::thread_rng().gen()
+    }
+}
+
+/// The `--field`, or the heightmap if one is given.
+fn field_name<'a>(matches: &'a ArgMatches) -> &'a str {
+    if matches.is_present("heightmap") {
+        "heightmap"
+    } else {
+        matches.value_of("field").unwrap()
+    }
+}
+
+/// Loads the `--heightmap` of `radius` in the `--heightmap-format`, or the
+/// mosaic of them if there are several.
+fn heightmap(matches: &ArgMatches, radius: f32, assets: &Assets) -> Result<Heightmap> {
+    let paths = match matches.values_of("heightmap") {
+        Some(paths) => paths.map(PathBuf::from).collect(),
+        None => vec![try!(assets.resolve(DEFAULT_HEIGHTMAP))],
+    };
+    let format = HeightmapFormat::from_name(matches.value_of("heightmap_format").unwrap()).unwrap();
+    let size = match matches.value_of("heightmap_size") {
+        Some(size) => {
+            match parse_size(size) {
+                Some(size) => Some(size),
+                None => {
+                    return Err(
+                        format!("Invalid heightmap size {:?}, expected WIDTHxHEIGHT.", size).into(),
+                    )
+                }
+            }
+        }
+        // The default map is raw samples without a label.
+        None if !matches.is_present("heightmap") => Some(DEFAULT_HEIGHTMAP_SIZE),
+        None => None,
+    };
+    let range = if matches.is_present("heightmap_min") {
+        Some((
+            value_t!(matches, "heightmap_min", f32).unwrap_or_else(|e| e.exit()),
+            value_t!(matches, "heightmap_max", f32).unwrap_or_else(|e| e.exit()),
+        ))
+    } else {
+        None
+    };
+    if format == HeightmapFormat::GeoTiff && range.is_some() {
+        warn!("Ignoring the height range of a GeoTIFF heightmap, its heights are in metres.");
+    }
+    let nodata = if matches.is_present("heightmap_nodata") {
+        Some(value_t!(matches, "heightmap_nodata", f32).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let max_tiles = value_t!(matches, "heightmap_tiles", usize).unwrap_or_else(|e| e.exit());
+
+    let mut maps = Vec::with_capacity(paths.len());
+    for path in paths {
+        info!("Loading heightmap from {:?}", path);
+        maps.push(try!(match format {
+            HeightmapFormat::Pds => Heightmap::from_pds(radius, path, size, nodata, max_tiles),
+            HeightmapFormat::Image => Heightmap::from_image(radius, path, range, nodata),
+            HeightmapFormat::GeoTiff => Heightmap::from_geotiff(radius, path, nodata),
+        }));
+    }
+    Heightmap::mosaic(maps)
+}
+
+/// The octree level to export the chunks of, the finest one unless given.
+fn export_level(matches: &ArgMatches, config: &Config) -> Result<u8> {
+    match matches.value_of("level") {
+        Some(level) => {
+            match level.parse() {
+                Ok(level) => Ok(level),
+                Err(_) => Err(format!("Invalid octree level {:?}.", level).into()),
+            }
+        }
+        None => Ok(config.lod.max_level),
+    }
+}
+
+/// Parses a `WIDTHxHEIGHT` size.
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let mut dimensions = size.split('x').map(|dimension| dimension.trim().parse().ok());
+    match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Some(width)), Some(Some(height)), None) if width > 1 && height > 1 => {
+            Some((width, height))
+        }
+        _ => None,
+    }
+}
+
+fn scalar_field(
+    matches: &ArgMatches,
+    config: &Config,
+    planet_spec: &PlanetSpec,
+    assets: &Assets,
+    seed: u32,
+) -> Result<Box<ScalarField3 + Send + Sync>> {
+    let field_name = field_name(matches);
+    crash::set_context(format!(
+        "Field: {}\nSeed: {}\nPlanet: {:?}\nErosion: {:?}\nLOD: {:?}",
+        field_name,
+        seed,
+        planet_spec,
+        config.erosion,
+        config.lod
+    ));
+
+    let radius = planet_spec.base_radius;
+    let field: Box<ScalarField3 + Send + Sync> = match field_name {
+        "heightmap" => Box::new(try!(heightmap(matches, radius, assets))),
+        "square" => Box::new(SquareField::new(radius)),
+        "torus" => Box::new(TorusField::new(radius, radius / 4.0)),
+        "flat" => Box::new(FlatField::new(0.0)),
+        "floating_islands" => {
+            info!("Generating floating islands with params {:?}", config.floating_islands);
+            Box::new(FloatingIslandsField::new(seed, radius, &config.floating_islands))
+        }
+        "gas_giant" => return Err("A gas giant has no terrain to generate.".into()),
+        _ => {
+            info!("The world seed is {}", seed);
+            info!("Generating planet with params {:?}", planet_spec);
+            let planet: Box<ScalarField3 + Send + Sync> = if matches.is_present("gpu_density") {
+                let gpu_meshing = matches.is_present("gpu_meshing");
+                match GpuPlanetField::new(seed, planet_spec.clone(), assets, gpu_meshing) {
+                    Ok(planet) => Box::new(planet),
+                    Err(err) => {
+                        warn!("{}, evaluating the planet on the CPU.", err);
+                        Box::new(PlanetField::new(seed, planet_spec.clone()))
+                    }
+                }
+            } else {
+                Box::new(PlanetField::new(seed, planet_spec.clone()))
+            };
+            let planet: Box<ScalarField3 + Send + Sync> = if config.erosion.is_enabled() {
+                info!("Eroding planet with params {:?}", config.erosion);
+                Box::new(ErodedField::new(planet, &config.erosion, seed))
+            } else {
+                planet
+            };
+            with_solids(planet, &planet_spec.solids)
+        }
+    };
+    // Heightmaps are about as cheap to sample as the cache, and their exact
+    // gradients would be lost behind it.
+    Ok(if config.lod.sample_cache_size > 0 && field_name != "heightmap" {
+        let finest_step = config.lod.finest_step();
+        Box::new(CachedField::new(field, finest_step, config.lod.sample_cache_size))
+    } else {
+        field
+    })
+}
 
 fn start_app() -> Result<()> {
     let matches = clap::App::new("Rusty Terrain.")
         .version("0.1.0")
         .author("Marius C. <marius@reinfer.io>")
         .about("A voxel based planet generator.")
+        .args(&config_args())
         .arg(
-            Arg::with_name("base_radius")
-                .long("base-radius")
-                .value_name("f32")
+            Arg::with_name("width")
+                .long("width")
+                .value_name("u32")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("deviation")
-                .long("deviation")
-                .value_name("f32")
+            Arg::with_name("height")
+                .long("height")
+                .value_name("u32")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("num_octaves")
-                .long("num-octaves")
+            Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Exclusive fullscreen.")
+                .conflicts_with("borderless"),
+        )
+        .arg(
+            Arg::with_name("borderless")
+                .long("borderless")
+                .help("Borderless window covering the whole monitor."),
+        )
+        .arg(
+            Arg::with_name("monitor")
+                .long("monitor")
                 .value_name("usize")
+                .help("Index of the monitor to go fullscreen on.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("persistence")
-                .long("persistence")
-                .value_name("f32")
+            Arg::with_name("workers")
+                .long("workers")
+                .value_name("usize")
+                .help("Number of chunk generation threads, defaults to one less than the CPUs.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("wavelength")
-                .long("wavelength")
-                .value_name("f32")
+            Arg::with_name("max_fps")
+                .long("max-fps")
+                .value_name("u32")
+                .help("Caps the frame rate.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("lacunarity")
-                .long("lacunarity")
-                .value_name("f32")
+            Arg::with_name("capture_dir")
+                .long("capture-dir")
+                .value_name("path")
+                .help("Writes every frame to numbered PNGs in this directory, F9 pauses.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("width")
-                .long("width")
-                .value_name("u32")
+            Arg::with_name("world_dir")
+                .long("world-dir")
+                .value_name("path")
+                .help("Saves the terrain edits in this directory and restores them on startup.")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("height")
-                .long("height")
-                .value_name("u32")
+            Arg::with_name("cache_dir")
+                .long("cache-dir")
+                .value_name("path")
+                .help("Caches the meshes of the chunks in this directory, across runs.")
                 .takes_value(true),
         )
+        .arg(Arg::with_name("no_vsync").long("no-vsync").help(
+            "Disables vertical sync.",
+        ))
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Bakes the planet to an OBJ file without opening a window.")
+                .args(&config_args())
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("path")
+                        .help("Where to write the OBJ file.")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-stl")
+                .about(
+                    "Bakes the planet to a closed binary STL file for 3D printing, without \
+                     opening a window.",
+                )
+                .args(&config_args())
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("path")
+                        .help("Where to write the STL file.")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .value_name("u8")
+                        .help("Octree level of the chunks to mesh, the finest one by default.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-meshes")
+                .about(
+                    "Bakes the meshes of the chunks of the planet to a file of binary meshes, \
+                     without opening a window.",
+                )
+                .args(&config_args())
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("path")
+                        .help("Where to write the meshes.")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .value_name("u8")
+                        .help("Octree level of the chunks to mesh, the finest one by default.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("compression")
+                        .long("compression")
+                        .value_name("name")
+                        .help("How to store the vertices and triangles of the meshes.")
+                        .takes_value(true)
+                        .possible_values(&MESH_COMPRESSIONS)
+                        .default_value("deflate"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-heightmap")
+                .about(
+                    "Bakes the planet to a 16 bit equirectangular PNG heightmap, with a JSON \
+                     file of its radius and height scale next to it.",
+                )
+                .args(&config_args())
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .value_name("path")
+                        .help("Where to write the PNG file.")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .value_name("WIDTHxHEIGHT")
+                        .help("Samples per row and rows of the heightmap.")
+                        .takes_value(true)
+                        .default_value("2048x1024"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Measures chunk generation throughput on the CPU.")
+                .args(&config_args())
+                .arg(
+                    Arg::with_name("levels")
+                        .long("levels")
+                        .value_name("u8")
+                        .help("Comma separated octree levels to benchmark.")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .default_value("2,4,6,8"),
+                )
+                .arg(
+                    Arg::with_name("chunks")
+                        .long("chunks")
+                        .value_name("usize")
+                        .help("Number of chunks to generate at each level.")
+                        .takes_value(true)
+                        .default_value("32"),
+                ),
+        )
         .get_matches();
 
-    let mut planet_spec = PlanetSpec::default();
-    if matches.is_present("base_radius") {
-        value_t!(matches, "base_radius", f32)
-            .map(|v| planet_spec.base_radius = v)
-            .unwrap();
-    }
-    if matches.is_present("deviation") {
-        value_t!(matches, "deviation", f32)
-            .map(|v| planet_spec.landscape_deviation = v)
-            .unwrap();
-    }
-    if matches.is_present("num_octaves") {
-        value_t!(matches, "num_octaves", usize)
-            .map(|v| planet_spec.num_octaves = v)
-            .unwrap();
-    }
-    if matches.is_present("persistence") {
-        value_t!(matches, "persistence", f32)
-            .map(|v| planet_spec.persistence = v)
-            .unwrap();
-    }
-    if matches.is_present("wavelength") {
-        value_t!(matches, "wavelength", f32)
-            .map(|v| planet_spec.wavelength = v)
-            .unwrap();
-    }
-    if matches.is_present("lacunarity") {
-        value_t!(matches, "lacunarity", f32)
-            .map(|v| planet_spec.lacunarity = v)
-            .unwrap();
-    }
+    let command_matches = match matches.subcommand() {
+        (_, Some(subcommand_matches)) => subcommand_matches,
+        _ => &matches,
+    };
+    try!(init_logging(command_matches));
+    crash::install_panic_hook();
+    let _trace = match command_matches.value_of("trace") {
+        Some(path) => Some(try!(trace::start(path))),
+        None => None,
+    };
 
-    let mut width = 1024;
-    let mut height = 768;
-    if matches.is_present("width") {
-        value_t!(matches, "width", u32).map(|v| width = v).unwrap();
-    }
-    if matches.is_present("height") {
-        value_t!(matches, "height", u32)
-            .map(|v| height = v)
-            .unwrap();
-    }
+    match matches.subcommand() {
+        ("export", Some(export_matches)) => {
+            let config = try!(load_config(export_matches));
+            let field = try!(scalar_field(
+                export_matches,
+                &config,
+                &config.planet,
+                &assets(export_matches),
+                world_seed(export_matches),
+            ));
+            export_to_obj(
+                &field,
+                &config.lod,
+                &config.flora,
+                config.planet.base_radius,
+                config.lod.max_level,
+                export_matches.value_of("out").unwrap(),
+            )
+        }
+        ("export-stl", Some(export_matches)) => {
+            let config = try!(load_config(export_matches));
+            let field = try!(scalar_field(
+                export_matches,
+                &config,
+                &config.planet,
+                &assets(export_matches),
+                world_seed(export_matches),
+            ));
+            let level = try!(export_level(export_matches, &config));
+            export_to_stl(
+                &field,
+                &config.lod,
+                level,
+                export_matches.value_of("out").unwrap(),
+            )
+        }
+        ("export-meshes", Some(export_matches)) => {
+            let config = try!(load_config(export_matches));
+            let field = try!(scalar_field(
+                export_matches,
+                &config,
+                &config.planet,
+                &assets(export_matches),
+                world_seed(export_matches),
+            ));
+            let level = try!(export_level(export_matches, &config));
+            let compression = export_matches.value_of("compression").unwrap();
+            let compression = MeshCompression::from_name(compression).unwrap();
+            export_to_meshes(
+                &field,
+                &config.lod,
+                level,
+                compression,
+                export_matches.value_of("out").unwrap(),
+            )
+        }
+        ("export-heightmap", Some(export_matches)) => {
+            let config = try!(load_config(export_matches));
+            let field = try!(scalar_field(
+                export_matches,
+                &config,
+                &config.planet,
+                &assets(export_matches),
+                world_seed(export_matches),
+            ));
+            let size = export_matches.value_of("size").unwrap();
+            let size = match parse_size(size) {
+                Some(size) => size,
+                None => {
+                    return Err(
+                        format!("Invalid heightmap size {:?}, expected WIDTHxHEIGHT.", size).into(),
+                    )
+                }
+            };
+            // The noise reaches about the deviation either side of the base
+            // radius, volcanoes rise above it.
+            let spec = &config.planet;
+            let max_relief = MAX_RELIEF_DEVIATIONS * spec.landscape_deviation * spec.base_radius +
+                spec.volcano_height;
+            export_heightmap(
+                &field,
+                spec.base_radius,
+                max_relief,
+                size,
+                export_matches.value_of("out").unwrap(),
+            )
+        }
+        ("bench", Some(bench_matches)) => {
+            let config = try!(load_config(bench_matches));
+            let field = try!(scalar_field(
+                bench_matches,
+                &config,
+                &config.planet,
+                &assets(bench_matches),
+                world_seed(bench_matches),
+            ));
+            let levels = values_t!(bench_matches, "levels", u8).unwrap_or_else(|e| e.exit());
+            let num_chunks = value_t!(bench_matches, "chunks", usize).unwrap_or_else(|e| e.exit());
+            run_benchmark(&field, &config.lod, &levels, num_chunks)
+        }
+        _ => {
+            let config = try!(load_config(&matches));
+            let assets = assets(&matches);
+            let seed = world_seed(&matches);
 
-    let mut rng = rand::thread_rng();
-    let seed: u32 = rng.gen();
-    info!("The world seed is {}", seed);
-    info!("Generating planet with params {:?}", planet_spec);
-    let field = PlanetField::new(seed, planet_spec);
+            let num_workers = if matches.is_present("workers") {
+                value_t!(matches, "workers", usize).unwrap_or_else(|e| e.exit())
+            } else {
+                num_cpus::get() - 1
+            };
+            let num_workers = cmp::max(1, num_workers);
 
-    info!("Creating app");
-    let mut app = try!(App::new(width, height, 3));
-    app.run(field)
+            info!("Creating app with {} workers", num_workers);
+            let mut app = try!(App::new(&config.window, assets.clone(), num_workers));
+            if let Some(directory) = matches.value_of("capture_dir") {
+                app.capture_frames(PathBuf::from(directory));
+            }
+            if let Some(directory) = matches.value_of("world_dir") {
+                app.persist_edits(WorldDir::new(directory, field_name(&matches)));
+            }
+            if let Some(directory) = matches.value_of("cache_dir") {
+                app.cache_meshes(CacheDir::new(directory, field_name(&matches)));
+            }
+            if let Some(path) = matches.value_of("config") {
+                // Values given on the command line keep precedence over the
+                // reloaded ones.
+                let overrides = matches.clone();
+                app.watch_config(ConfigWatcher::new(
+                    path,
+                    Box::new(move |config| apply_overrides(&overrides, config)),
+                ));
+            }
+            if field_name(&matches) == "gas_giant" {
+                return app.run_gas_giant(&config, seed);
+            }
+            app.run(&config, seed, |config, planet_spec, seed| {
+                scalar_field(&matches, config, planet_spec, &assets, seed)
+            })
+        }
+    }
 }
 
 fn main() {
-    if let Err(err) = env_logger::init() {
-        println!(
-            "Could not initialize logger, exiting: {}",
-            err.description()
-        );
-    } else {
-        start_app().unwrap();
+    if let Err(err) = start_app() {
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "Error: {}", err);
+        for cause in err.iter().skip(1) {
+            let _ = writeln!(stderr, "  caused by: {}", cause);
+        }
+        process::exit(1);
     }
 }
+
+const NOISE_BASES: [&'static str; 3] = ["simplex", "perlin", "worley"];
+const FRACTALS: [&'static str; 4] = ["fbm", "ridged", "billow", "hybrid"];
+const SURFACE_MAPPINGS: [&'static str; 2] = ["sphere", "cube"];
+const MESHERS: [&'static str; 2] = ["marching_cubes", "dual_contouring"];
+const HEIGHTMAP_FORMATS: [&'static str; 3] = ["pds", "image", "geotiff"];
+const MESH_COMPRESSIONS: [&'static str; 2] = ["none", "deflate"];
+// The MOLA elevation model of Mars, at 128 samples per degree.
+const DEFAULT_HEIGHTMAP: &'static str = "128/megdr-128-stiched.img";
+// How far from the base radius a baked heightmap looks for the surface, in
+// landscape deviations.
+const MAX_RELIEF_DEVIATIONS: f32 = 2.0;
+// Samples per row and rows of the default heightmap.
+const DEFAULT_HEIGHTMAP_SIZE: (usize, usize) = (46080, 22528);
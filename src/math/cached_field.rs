@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use nalgebra::{Point3, Vector3};
+
+use math::{CpuScalar, Material, Mesher, ScalarField3};
+
+/// Memoizes the samples of a field, which marching cubes cells and gradients
+/// otherwise recompute for every cell sharing a corner. Positions are snapped
+/// to a grid finer than that of the finest chunks and the field is sampled at
+/// the snapped position, so the values don't depend on which thread sampled
+/// first. Gradients probe off the grid, so they skip the cache.
+///
+/// Samples live in a fixed size table where new samples evict old ones. Each
+/// slot packs a tag of the position's hash and the value in a single atomic,
+/// so lookups never lock. Two positions only collide if they share both the
+/// slot and the 32 bit tag. Samples at a level of detail are cached apart
+/// from full detail ones and keyed by their step as well.
+pub struct CachedField<Field> {
+    field: Field,
+    /// The bits of the inverse of the grid's spacing, which changes with the
+    /// finest chunks.
+    inverse_quantum: AtomicU32,
+    slots: Vec<AtomicU64>,
+}
+
+impl<Field: ScalarField3> CachedField<Field> {
+    /// `finest_step` is the distance between the samples of the finest
+    /// chunks, `capacity` is rounded up to a power of two.
+    pub fn new(field: Field, finest_step: CpuScalar, capacity: usize) -> Self {
+        CachedField {
+            field: field,
+            inverse_quantum: AtomicU32::new(inverse_quantum(finest_step).to_bits()),
+            slots: (0..capacity.next_power_of_two())
+                .map(|_| AtomicU64::new(EMPTY_SLOT))
+                .collect(),
+        }
+    }
+
+    /// The slot and tag of `position` sampled every `step`, with 0 for full
+    /// detail, the position it is snapped to and the value cached for it, if
+    /// any.
+    #[inline]
+    fn lookup(
+        &self,
+        position: &Point3<CpuScalar>,
+        step: CpuScalar,
+    ) -> (&AtomicU64, u64, Point3<CpuScalar>, Option<CpuScalar>) {
+        let inverse_quantum = CpuScalar::from_bits(self.inverse_quantum.load(Ordering::Relaxed));
+        let quantize = |value: CpuScalar| (value * inverse_quantum).round() as i32;
+        let key = [quantize(position[0]), quantize(position[1]), quantize(position[2])];
+        // Samples snapped to an older grid never match.
+        let hash = hash_key(&key, step, inverse_quantum);
+        let slot = &self.slots[hash as usize & (self.slots.len() - 1)];
+        // Tag 0 marks an empty slot.
+        let tag = (hash >> 32).max(1);
+
+        let entry = slot.load(Ordering::Relaxed);
+        let cached = if entry >> 32 == tag {
+            Some(CpuScalar::from_bits(entry as u32))
+        } else {
+            None
+        };
+        let quantum = 1.0 / inverse_quantum;
+        let snapped = Point3::new(
+            key[0] as CpuScalar * quantum,
+            key[1] as CpuScalar * quantum,
+            key[2] as CpuScalar * quantum,
+        );
+        (slot, tag, snapped, cached)
+    }
+}
+
+/// Finer than any chunk's grid, so distinct samples are never merged.
+#[inline]
+fn inverse_quantum(finest_step: CpuScalar) -> CpuScalar {
+    QUANTA_PER_STEP / finest_step
+}
+
+#[inline]
+fn store(slot: &AtomicU64, tag: u64, value: CpuScalar) {
+    slot.store(tag << 32 | value.to_bits() as u64, Ordering::Relaxed);
+}
+
+impl<Field: ScalarField3> ScalarField3 for CachedField<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let (slot, tag, snapped, cached) = self.lookup(position, 0.0);
+        cached.unwrap_or_else(|| {
+            let value = self.field.value_at(&snapped);
+            store(slot, tag, value);
+            value
+        })
+    }
+
+    #[inline]
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        self.values_at_lod(positions, values, 0.0)
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        let (slot, tag, snapped, cached) = self.lookup(position, step);
+        cached.unwrap_or_else(|| {
+            let value = self.field.value_at_lod(&snapped, step);
+            store(slot, tag, value);
+            value
+        })
+    }
+
+    /// Samples all the misses with a single call to the inner `values_at_lod`.
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        step: CpuScalar,
+    ) {
+        assert_eq!(positions.len(), values.len());
+        let mut misses = vec![];
+        let mut miss_positions = vec![];
+        for (index, (position, value)) in positions.iter().zip(values.iter_mut()).enumerate() {
+            let (slot, tag, snapped, cached) = self.lookup(position, step);
+            match cached {
+                Some(cached) => *value = cached,
+                None => {
+                    misses.push((index, slot, tag));
+                    miss_positions.push(snapped);
+                }
+            }
+        }
+        if misses.is_empty() {
+            return;
+        }
+
+        let mut miss_values = vec![0.0; misses.len()];
+        if step == 0.0 {
+            self.field.values_at(&miss_positions, &mut miss_values);
+        } else {
+            self.field.values_at_lod(&miss_positions, &mut miss_values, step);
+        }
+        for (&(index, slot, tag), &value) in misses.iter().zip(miss_values.iter()) {
+            values[index] = value;
+            store(slot, tag, value);
+        }
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(position)
+    }
+
+    #[inline]
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        self.field.stratum_at(position)
+    }
+
+    #[inline]
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        self.field.gradient_at(position)
+    }
+
+    #[inline]
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        self.field.gradients_at(positions, gradients)
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+
+    #[inline]
+    fn gpu_cell_triangles(
+        &self,
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        self.field.gpu_cell_triangles(shape, values, iso_value)
+    }
+
+    #[inline]
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        self.field.occluder_radius()
+    }
+
+    /// Snaps to a grid matching the new finest chunks from now on.
+    fn set_finest_step(&self, finest_step: CpuScalar) {
+        self.field.set_finest_step(finest_step);
+        self.inverse_quantum.store(inverse_quantum(finest_step).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Mixes the quantized coordinates, the step and the grid they were snapped
+/// to with the splitmix64 finalizer.
+#[inline]
+fn hash_key(key: &[i32; 3], step: CpuScalar, inverse_quantum: CpuScalar) -> u64 {
+    let mut hash = (key[0] as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^
+        (key[1] as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f) ^
+        (key[2] as u32 as u64).wrapping_mul(0x1656_67b1_9e37_79f9) ^
+        (step.to_bits() as u64).wrapping_mul(0xd6e8_feb8_6659_fd93) ^
+        (inverse_quantum.to_bits() as u64).wrapping_mul(0xa076_1d64_78bd_642f);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nalgebra::Point3;
+
+    use math::{CpuScalar, ScalarField3};
+    use super::CachedField;
+
+    struct CountingField {
+        num_samples: AtomicUsize,
+    }
+
+    impl ScalarField3 for CountingField {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            self.num_samples.fetch_add(1, Ordering::SeqCst);
+            position[0] + 2.0 * position[1] - position[2]
+        }
+    }
+
+    #[test]
+    fn test_samples_are_memoized() {
+        let counting = CountingField { num_samples: AtomicUsize::new(0) };
+        let field = CachedField::new(counting, 1.0, 1000);
+        assert_eq!(1024, field.slots.len());
+
+        let value = field.value_at(&Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(2.0, value);
+        // Snaps to the same grid point.
+        assert_eq!(value, field.value_at(&Point3::new(1.1, 1.9, 3.0)));
+        assert_eq!(1, field.field.num_samples.load(Ordering::SeqCst));
+
+        assert_eq!(-0.25, field.value_at(&Point3::new(0.0, 0.0, 0.25)));
+        assert_eq!(2, field.field.num_samples.load(Ordering::SeqCst));
+
+        let positions = [
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(0.5, 0.5, 0.5),
+            Point3::new(0.0, 0.0, 0.25),
+        ];
+        let mut values = [0.0; 3];
+        field.values_at(&positions, &mut values);
+        assert_eq!([2.0, 1.0, -0.25], values);
+        assert_eq!(3, field.field.num_samples.load(Ordering::SeqCst));
+
+        // A coarser grid no longer merges these.
+        field.set_finest_step(0.25);
+        assert_eq!(2.0, field.value_at(&Point3::new(1.0, 2.0, 3.0)));
+        assert!(field.value_at(&Point3::new(1.1, 1.9, 3.0)) != 2.0);
+        assert_eq!(5, field.field.num_samples.load(Ordering::SeqCst));
+    }
+}
+
+const EMPTY_SLOT: u64 = 0;
+// The samples along each step of the finest chunks.
+const QUANTA_PER_STEP: CpuScalar = 4.0;
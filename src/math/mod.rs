@@ -1,49 +1,19 @@
+use std::f32::consts::PI;
+
 use num::Zero;
 use nalgebra::{Matrix4, Point2, Point3, Point4, Vector2, Vector3, Vector4};
+use rand::Rng;
 
-pub type GpuScalar = f32;
-pub type CpuScalar = f32;
+pub mod cached_field;
+pub mod cube_sphere;
+pub mod scalar_field;
 
-const EPS: CpuScalar = 1.0;
-
-pub trait ScalarField2 {
-    #[inline]
-    fn value_at(&self, position: &Point2<CpuScalar>) -> CpuScalar;
-
-    #[inline]
-    fn gradient_at(&self, position: &Point2<CpuScalar>) -> Vector2<CpuScalar> {
-        let EPS2 = 2.0 * EPS;
-        let position = *position;
-        let x_perturb = Vector2::x() * EPS;
-        let y_perturb = Vector2::y() * EPS;
-        let dx = (self.value_at(&(position + x_perturb)) -
-                      self.value_at(&(position - x_perturb))) / EPS2;
-        let dy = (self.value_at(&(position + y_perturb)) -
-                      self.value_at(&(position - y_perturb))) / EPS2;
-        Vector2::new(dx, dy)
-    }
-}
+pub use self::cached_field::CachedField;
+pub use self::scalar_field::{Material, Mesher, ScalarField2, ScalarField3, difference_gradient,
+                             difference_gradients};
 
-pub trait ScalarField3 {
-    #[inline]
-    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar;
-
-    #[inline]
-    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
-        let EPS2 = 2.0 * EPS;
-        let position = *position;
-        let x_perturb = Vector3::x() * EPS;
-        let y_perturb = Vector3::y() * EPS;
-        let z_perturb = Vector3::z() * EPS;
-        let dx = (self.value_at(&(position + x_perturb)) -
-                      self.value_at(&(position - x_perturb))) / EPS2;
-        let dy = (self.value_at(&(position + y_perturb)) -
-                      self.value_at(&(position - y_perturb))) / EPS2;
-        let dz = (self.value_at(&(position + z_perturb)) -
-                      self.value_at(&(position - z_perturb))) / EPS2;
-        Vector3::new(dx, dy, dz)
-    }
-}
+pub type GpuScalar = f32;
+pub type CpuScalar = f32;
 
 custom_derive! {
     #[derive(Debug, Copy, Clone, PartialEq,
@@ -245,3 +215,11 @@ where
         Matrix4f(Matrix4::from(value))
     }
 }
+
+/// A unit vector drawn from `rng`, uniform over the sphere.
+pub fn random_direction<R: Rng>(rng: &mut R) -> Vector3<CpuScalar> {
+    let height: CpuScalar = rng.gen_range(-1.0, 1.0);
+    let angle: CpuScalar = rng.gen_range(0.0, 2.0 * PI);
+    let ring = (1.0 - height * height).sqrt();
+    Vector3::new(ring * angle.cos(), height, ring * angle.sin())
+}
@@ -0,0 +1,595 @@
+use nalgebra::{Point2, Point3, Vector2, Vector3};
+
+use super::CpuScalar;
+
+const EPS: CpuScalar = 1.0;
+// Halvings of the segment searched by `surface_between`, enough to go from
+// planet to millimetre scale.
+const BISECTION_ITERATIONS: usize = 32;
+
+pub trait ScalarField2 {
+    #[inline]
+    fn value_at(&self, position: &Point2<CpuScalar>) -> CpuScalar;
+
+    #[inline]
+    fn gradient_at(&self, position: &Point2<CpuScalar>) -> Vector2<CpuScalar> {
+        let EPS2 = 2.0 * EPS;
+        let position = *position;
+        let x_perturb = Vector2::x() * EPS;
+        let y_perturb = Vector2::y() * EPS;
+        let dx = (self.value_at(&(position + x_perturb)) -
+                      self.value_at(&(position - x_perturb))) / EPS2;
+        let dy = (self.value_at(&(position + y_perturb)) -
+                      self.value_at(&(position - y_perturb))) / EPS2;
+        Vector2::new(dx, dy)
+    }
+}
+
+/// What the surface is made of, which selects how it's shaded. The values
+/// are passed to the planet shader as a vertex attribute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Material {
+    Rock = 0,
+    Lava = 1,
+    Snow = 2,
+    Sand = 3,
+}
+
+/// How the surface of a field is polygonised into chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mesher {
+    /// Vertices on the edges of the grid, which round off sharp features.
+    MarchingCubes,
+    /// A vertex inside each cell the surface crosses, placed from the normals
+    /// where it crosses the cell's edges, which keeps creases and corners.
+    DualContouring,
+    /// A vertex at the average of the crossings on the edges of each cell the
+    /// surface crosses. The level of detail meshes distant chunks with it.
+    SurfaceNets,
+}
+
+impl Mesher {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "marching_cubes" => Some(Mesher::MarchingCubes),
+            "dual_contouring" => Some(Mesher::DualContouring),
+            _ => None,
+        }
+    }
+}
+
+pub trait ScalarField3 {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar;
+
+    /// Writes the value at each of `positions` to `values`, of the same
+    /// length. Fields override this to sample whole grids more efficiently.
+    #[inline]
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        assert_eq!(positions.len(), values.len());
+        for (position, value) in positions.iter().zip(values.iter_mut()) {
+            *value = self.value_at(position);
+        }
+    }
+
+    /// The value at `position` as seen by a grid sampled every `step`. Fields
+    /// may leave out detail finer than `step`, which the grid cannot resolve
+    /// anyway, to sample coarse chunks faster.
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, _step: CpuScalar) -> CpuScalar {
+        self.value_at(position)
+    }
+
+    /// `values_at` with the detail of `value_at_lod`.
+    #[inline]
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        step: CpuScalar,
+    ) {
+        assert_eq!(positions.len(), values.len());
+        for (position, value) in positions.iter().zip(values.iter_mut()) {
+            *value = self.value_at_lod(position, step);
+        }
+    }
+
+    /// The material of the surface near `position`.
+    #[inline]
+    fn material_at(&self, _position: &Point3<CpuScalar>) -> Material {
+        Material::Rock
+    }
+
+    /// The layer of rock at `position` on stratified terrain, counted up from
+    /// the lowest, the fractional part being how far up through it. None if
+    /// the rock isn't layered.
+    #[inline]
+    fn stratum_at(&self, _position: &Point3<CpuScalar>) -> Option<f32> {
+        None
+    }
+
+    #[inline]
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        difference_gradient(self, position)
+    }
+
+    /// Writes the gradient at each of `positions` to `gradients`, of the same
+    /// length. Fields whose `values_at` batches override this with
+    /// `difference_gradients`, to take the samples in one batch.
+    #[inline]
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        assert_eq!(positions.len(), gradients.len());
+        for (position, gradient) in positions.iter().zip(gradients.iter_mut()) {
+            *gradient = self.gradient_at(position);
+        }
+    }
+
+    /// The mesher the field's chunks are polygonised with.
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        Mesher::MarchingCubes
+    }
+
+    /// The triangles marching cubes makes through a grid of `values` sampled
+    /// from the field, of `shape` points along x, y and z, found on the GPU.
+    /// Each corner is given by the edge of the grid it's on, the index of the
+    /// grid point the edge starts from times 3 plus its axis. None if the
+    /// field doesn't mesh on the GPU, the CPU then does.
+    #[inline]
+    fn gpu_cell_triangles(
+        &self,
+        _shape: [usize; 3],
+        _values: &[CpuScalar],
+        _iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        None
+    }
+
+    /// The radius of a sphere around the origin which is solid throughout,
+    /// so it hides whatever is behind it. None if the field has none.
+    #[inline]
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        None
+    }
+
+    /// Told when the distance between the samples of the finest chunks
+    /// changes, for fields tuned to it.
+    #[inline]
+    fn set_finest_step(&self, _finest_step: CpuScalar) {}
+
+    /// A point on the surface between `inside`, where the field is negative,
+    /// and `outside`, where it's positive, found by bisection. None if the
+    /// ends are on the same side of the surface. If the segment crosses it
+    /// more than once, any of the crossings may be returned.
+    fn surface_between(
+        &self,
+        inside: &Point3<CpuScalar>,
+        outside: &Point3<CpuScalar>,
+    ) -> Option<Point3<CpuScalar>> {
+        if self.value_at(inside) > 0.0 || self.value_at(outside) < 0.0 {
+            return None;
+        }
+        let (mut inside, mut outside) = (*inside, *outside);
+        for _ in 0..BISECTION_ITERATIONS {
+            let middle = inside + (outside - inside) * 0.5;
+            if self.value_at(&middle) < 0.0 {
+                inside = middle;
+            } else {
+                outside = middle;
+            }
+        }
+        Some(inside + (outside - inside) * 0.5)
+    }
+}
+
+/// The gradient of `field` by central differences `EPS` either side of
+/// `position`, for fields that can't compute it exactly.
+pub fn difference_gradient<Field>(field: &Field, position: &Point3<CpuScalar>) -> Vector3<CpuScalar>
+where
+    Field: ScalarField3 + ?Sized,
+{
+    let EPS2 = 2.0 * EPS;
+    let position = *position;
+    let x_perturb = Vector3::x() * EPS;
+    let y_perturb = Vector3::y() * EPS;
+    let z_perturb = Vector3::z() * EPS;
+    let dx = (field.value_at(&(position + x_perturb)) -
+                  field.value_at(&(position - x_perturb))) / EPS2;
+    let dy = (field.value_at(&(position + y_perturb)) -
+                  field.value_at(&(position - y_perturb))) / EPS2;
+    let dz = (field.value_at(&(position + z_perturb)) -
+                  field.value_at(&(position - z_perturb))) / EPS2;
+    Vector3::new(dx, dy, dz)
+}
+
+/// `difference_gradient` at each of `positions`, with the samples of all of
+/// them taken by one call to `values_at`.
+pub fn difference_gradients<Field>(
+    field: &Field,
+    positions: &[Point3<CpuScalar>],
+    gradients: &mut [Vector3<CpuScalar>],
+) where
+    Field: ScalarField3 + ?Sized,
+{
+    assert_eq!(positions.len(), gradients.len());
+    let perturbs = [Vector3::x() * EPS, Vector3::y() * EPS, Vector3::z() * EPS];
+    let mut samples = Vec::with_capacity(positions.len() * 6);
+    for position in positions.iter() {
+        for perturb in perturbs.iter() {
+            samples.push(*position + *perturb);
+            samples.push(*position - *perturb);
+        }
+    }
+    let mut values = vec![0.0; samples.len()];
+    field.values_at(&samples, &mut values);
+    for (gradient, values) in gradients.iter_mut().zip(values.chunks(6)) {
+        *gradient = Vector3::new(
+            values[0] - values[1],
+            values[2] - values[3],
+            values[4] - values[5],
+        ) / (2.0 * EPS);
+    }
+}
+
+impl<Field: ScalarField3 + ?Sized> ScalarField3 for Box<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        (**self).value_at(position)
+    }
+
+    #[inline]
+    fn values_at(&self, positions: &[Point3<CpuScalar>], values: &mut [CpuScalar]) {
+        (**self).values_at(positions, values)
+    }
+
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        (**self).value_at_lod(position, step)
+    }
+
+    #[inline]
+    fn values_at_lod(
+        &self,
+        positions: &[Point3<CpuScalar>],
+        values: &mut [CpuScalar],
+        step: CpuScalar,
+    ) {
+        (**self).values_at_lod(positions, values, step)
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        (**self).material_at(position)
+    }
+
+    #[inline]
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        (**self).stratum_at(position)
+    }
+
+    #[inline]
+    fn gradient_at(&self, position: &Point3<CpuScalar>) -> Vector3<CpuScalar> {
+        (**self).gradient_at(position)
+    }
+
+    #[inline]
+    fn gradients_at(&self, positions: &[Point3<CpuScalar>], gradients: &mut [Vector3<CpuScalar>]) {
+        (**self).gradients_at(positions, gradients)
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        (**self).mesher()
+    }
+
+    #[inline]
+    fn gpu_cell_triangles(
+        &self,
+        shape: [usize; 3],
+        values: &[CpuScalar],
+        iso_value: CpuScalar,
+    ) -> Option<Vec<u32>> {
+        (**self).gpu_cell_triangles(shape, values, iso_value)
+    }
+
+    #[inline]
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        (**self).occluder_radius()
+    }
+
+    #[inline]
+    fn set_finest_step(&self, finest_step: CpuScalar) {
+        (**self).set_finest_step(finest_step)
+    }
+}
+
+// Adapters combining fields like solids, e.g. `Difference::new(planet,
+// Translate::new(tunnel, offset))`. They keep the sign of the field and a
+// roughly distance-like falloff but not exact distances, which is all the
+// mesher needs.
+
+/// The solid covered by either field.
+pub struct Union<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: ScalarField3, B: ScalarField3> Union<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Union { a: a, b: b }
+    }
+}
+
+impl<A: ScalarField3, B: ScalarField3> ScalarField3 for Union<A, B> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.a.value_at(position).min(self.b.value_at(position))
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        if self.a.value_at(position) <= self.b.value_at(position) {
+            self.a.material_at(position)
+        } else {
+            self.b.material_at(position)
+        }
+    }
+
+    /// The surfaces meet at a crease.
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        Mesher::DualContouring
+    }
+}
+
+/// The solid covered by both fields.
+pub struct Intersection<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: ScalarField3, B: ScalarField3> Intersection<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Intersection { a: a, b: b }
+    }
+}
+
+impl<A: ScalarField3, B: ScalarField3> ScalarField3 for Intersection<A, B> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.a.value_at(position).max(self.b.value_at(position))
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        if self.a.value_at(position) >= self.b.value_at(position) {
+            self.a.material_at(position)
+        } else {
+            self.b.material_at(position)
+        }
+    }
+
+    /// The surfaces meet at a crease.
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        Mesher::DualContouring
+    }
+}
+
+/// `a` with the solid of `b` carved out of it, e.g. a tunnel through a planet.
+/// The carved surface keeps the material of `a`.
+pub struct Difference<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: ScalarField3, B: ScalarField3> Difference<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Difference { a: a, b: b }
+    }
+}
+
+impl<A: ScalarField3, B: ScalarField3> ScalarField3 for Difference<A, B> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.a.value_at(position).max(-self.b.value_at(position))
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.a.material_at(position)
+    }
+
+    /// The carved surface meets the rest at a crease.
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        Mesher::DualContouring
+    }
+}
+
+/// A union which blends the two surfaces together where they're within `k`
+/// of each other, instead of meeting at a crease.
+pub struct SmoothUnion<A, B> {
+    a: A,
+    b: B,
+    k: CpuScalar,
+}
+
+impl<A: ScalarField3, B: ScalarField3> SmoothUnion<A, B> {
+    pub fn new(a: A, b: B, k: CpuScalar) -> Self {
+        assert!(k > 0.0, "The blend distance of a smooth union must be positive.");
+        SmoothUnion { a: a, b: b, k: k }
+    }
+}
+
+impl<A: ScalarField3, B: ScalarField3> ScalarField3 for SmoothUnion<A, B> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let (a, b) = (self.a.value_at(position), self.b.value_at(position));
+        let h = (0.5 + 0.5 * (b - a) / self.k).max(0.0).min(1.0);
+        b * (1.0 - h) + a * h - self.k * h * (1.0 - h)
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        if self.a.value_at(position) <= self.b.value_at(position) {
+            self.a.material_at(position)
+        } else {
+            self.b.material_at(position)
+        }
+    }
+}
+
+/// Moves the surface `offset` outwards, or inwards if negative.
+pub struct Offset<Field> {
+    field: Field,
+    offset: CpuScalar,
+}
+
+impl<Field: ScalarField3> Offset<Field> {
+    pub fn new(field: Field, offset: CpuScalar) -> Self {
+        Offset {
+            field: field,
+            offset: offset,
+        }
+    }
+}
+
+impl<Field: ScalarField3> ScalarField3 for Offset<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.field.value_at(position) - self.offset
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(position)
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+}
+
+/// Scales the field uniformly by `factor` about the origin.
+pub struct Scale<Field> {
+    field: Field,
+    factor: CpuScalar,
+}
+
+impl<Field: ScalarField3> Scale<Field> {
+    pub fn new(field: Field, factor: CpuScalar) -> Self {
+        assert!(factor > 0.0, "A field can only be scaled by a positive factor.");
+        Scale {
+            field: field,
+            factor: factor,
+        }
+    }
+}
+
+impl<Field: ScalarField3> ScalarField3 for Scale<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.field.value_at(&(*position / self.factor)) * self.factor
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(&(*position / self.factor))
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+}
+
+/// Moves the field by `translation`.
+pub struct Translate<Field> {
+    field: Field,
+    translation: Vector3<CpuScalar>,
+}
+
+impl<Field: ScalarField3> Translate<Field> {
+    pub fn new(field: Field, translation: Vector3<CpuScalar>) -> Self {
+        Translate {
+            field: field,
+            translation: translation,
+        }
+    }
+}
+
+impl<Field: ScalarField3> ScalarField3 for Translate<Field> {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        self.field.value_at(&(*position - self.translation))
+    }
+
+    #[inline]
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        self.field.material_at(&(*position - self.translation))
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.field.mesher()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Norm, Point3, Vector3};
+
+    use math::CpuScalar;
+    use super::*;
+
+    struct Sphere(CpuScalar);
+
+    impl ScalarField3 for Sphere {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            position.to_vector().norm() - self.0
+        }
+    }
+
+    #[test]
+    fn test_combinators() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let right = Point3::new(3.0, 0.0, 0.0);
+        let shifted = || Translate::new(Sphere(1.0), Vector3::new(3.0, 0.0, 0.0));
+
+        assert_eq!(-1.0, shifted().value_at(&right));
+        assert_eq!(-2.0, Scale::new(Sphere(1.0), 2.0).value_at(&origin));
+        assert_eq!(-1.5, Offset::new(Sphere(1.0), 0.5).value_at(&origin));
+
+        let union = Union::new(Sphere(1.0), shifted());
+        assert!(union.value_at(&origin) < 0.0 && union.value_at(&right) < 0.0);
+        assert_eq!(0.5, union.value_at(&Point3::new(1.5, 0.0, 0.0)));
+
+        let intersection = Intersection::new(Sphere(2.0), shifted());
+        assert_eq!(0.0, intersection.value_at(&Point3::new(2.0, 0.0, 0.0)));
+        assert!(intersection.value_at(&origin) > 0.0);
+
+        let difference = Difference::new(Sphere(2.0), Sphere(1.0));
+        assert_eq!(1.0, difference.value_at(&origin));
+        assert_eq!(-0.5, difference.value_at(&Point3::new(1.5, 0.0, 0.0)));
+
+        // Blending only lowers the field, and only near both surfaces.
+        let smooth = SmoothUnion::new(Sphere(1.0), shifted(), 1.0);
+        let middle = Point3::new(1.5, 0.0, 0.0);
+        assert!(smooth.value_at(&middle) < union.value_at(&middle));
+        assert_eq!(union.value_at(&Point3::new(-5.0, 0.0, 0.0)),
+                   smooth.value_at(&Point3::new(-5.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_surface_between() {
+        let sphere = Sphere(10.0);
+        let surface = sphere
+            .surface_between(&Point3::new(0.0, 0.0, 0.0), &Point3::new(0.0, 30.0, 0.0))
+            .unwrap();
+        assert!((surface - Point3::new(0.0, 10.0, 0.0)).norm() < 1e-4);
+        let outside = Point3::new(20.0, 0.0, 0.0);
+        assert!(sphere.surface_between(&outside, &Point3::new(30.0, 0.0, 0.0)).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use glium::{Blend, Depth, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest, TimeElapsedQuery};
+use glium::index::PrimitiveType;
+use glium::texture::Texture2d;
+use glium::uniforms::{MinifySamplerFilter, SamplerWrapFunction};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::{Mesh, Vertex, Window};
+use math::{GpuScalar, Matrix4f, Vec3f};
+use trace;
+
+/// A sphere at sea level, drawn over the terrain after it. Its waves are
+/// animated normals, and it refracts the terrain under it and reflects what
+/// it can of the sky and the terrain above it from a copy of the frame, by
+/// how grazing the view is. The water has already absorbed the light of the
+/// terrain under it, in its own shader. The camera and light positions are
+/// relative to the centre of the sphere.
+pub struct OceanRenderer<'a> {
+    draw_parameters: DrawParameters<'a>,
+    program: Program,
+    vertex_buffer: VertexBuffer<Vertex>,
+    index_buffer: IndexBuffer<u32>,
+    radius: GpuScalar,
+}
+
+impl<'a> OceanRenderer<'a> {
+    pub fn new(window: &Window, assets: &Assets, radius: GpuScalar) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let mesh = Mesh::icosphere(radius, SPHERE_SUBDIVISIONS);
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &mesh.vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer = try!(
+            IndexBuffer::new(
+                window.facade(),
+                PrimitiveType::TrianglesList,
+                &mesh.indices,
+            ).chain_err(|| "Cannot create index buffer.")
+        );
+
+        // The terrain's depth is kept so the sea floor is refracted, but the
+        // water itself doesn't write depth and is visible from below.
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: false,
+                ..Default::default()
+            },
+            blend: Blend::alpha_blending(),
+            backface_culling: BackfaceCullingMode::CullingDisabled,
+            ..Default::default()
+        };
+        Ok(OceanRenderer {
+            draw_parameters: draw_parameters,
+            program: program,
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+            radius: radius,
+        })
+    }
+
+    /// The distance from the centre of the planet to the sea level.
+    pub fn radius(&self) -> GpuScalar {
+        self.radius
+    }
+
+    /// Draws the sea over `scene`, a copy of what's drawn before it, its
+    /// waves as they are `time` seconds in.
+    pub fn render<S: Surface>(
+        &self,
+        target: &mut S,
+        scene: &Texture2d,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        model: &Matrix4f,
+        camera_position: &Vec3f,
+        light: &Vec3f,
+        time: f32,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let _span = trace::span("draw_ocean");
+        let uniforms =
+            uniform! {
+            perspective: perspective,
+            view: view,
+            model: model,
+            camera_position: camera_position,
+            u_light: light,
+            u_time: time,
+            u_scene: scene
+                .sampled()
+                .minify_filter(MinifySamplerFilter::Linear)
+                .wrap_function(SamplerWrapFunction::Clamp),
+        };
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        target
+            .draw(
+                &self.vertex_buffer,
+                &self.index_buffer,
+                &self.program,
+                &uniforms,
+                &draw_parameters,
+            )
+            .chain_err(|| "Could not render the ocean.")
+    }
+}
+
+const VERTEX_SHADER: &'static str = "shaders/ocean.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/ocean.frag";
+
+// 81920 triangles, a few metres of error at the default planet radius.
+const SPHERE_SUBDIVISIONS: u32 = 6;
@@ -0,0 +1,660 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use errors::{ChainErr, ErrorKind, Result};
+
+/// How the numbers of a PDS image are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleType {
+    Integer,
+    UnsignedInteger,
+    Real,
+}
+
+/// The part of a body an image covers, in degrees with longitudes growing
+/// eastwards, to the outer edges of its samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapArea {
+    pub west: f64,
+    pub east: f64,
+    pub north: f64,
+    pub south: f64,
+}
+
+/// The layout of the samples of a PDS image in its file, as described by
+/// its label, and how they map to heights.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdsImage {
+    pub lines: usize,
+    pub line_samples: usize,
+    pub sample_type: SampleType,
+    pub sample_bytes: usize,
+    pub big_endian: bool,
+    /// Bytes from the start of the file to the first line.
+    pub start: u64,
+    /// Bytes before and after the samples of each line.
+    pub line_prefix_bytes: usize,
+    pub line_suffix_bytes: usize,
+    /// A height is `offset + scaling_factor * sample`.
+    pub scaling_factor: f32,
+    pub offset: f32,
+    /// Samples whose bytes read as this unsigned integer are missing,
+    /// compared before they're converted so no precision is lost.
+    pub missing_sample: Option<u64>,
+    /// Where the image's cylindrical map projection puts it, if it has one.
+    pub area: Option<MapArea>,
+}
+
+impl PdsImage {
+    /// Raw big endian 16 bit integers in rows, as in the stitched MOLA map,
+    /// for files without a label.
+    pub fn raw(line_samples: usize, lines: usize) -> Self {
+        PdsImage {
+            lines: lines,
+            line_samples: line_samples,
+            sample_type: SampleType::Integer,
+            sample_bytes: 2,
+            big_endian: true,
+            start: 0,
+            line_prefix_bytes: 0,
+            line_suffix_bytes: 0,
+            scaling_factor: 1.0,
+            offset: 0.0,
+            missing_sample: None,
+            area: None,
+        }
+    }
+
+    /// Finds the label of the image at `path`, either at the start of the
+    /// file or next to it with the extension `.lbl`, unless `path` is the
+    /// label itself. Returns the image and the file holding its samples, or
+    /// None if there's no label.
+    pub fn find(path: &Path) -> Result<Option<(PdsImage, PathBuf)>> {
+        let is_label = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.eq_ignore_ascii_case("lbl"))
+            .unwrap_or(false);
+        let label_path = if is_label || try!(starts_with_label(path)) {
+            path.to_path_buf()
+        } else {
+            match ["lbl", "LBL"]
+                .iter()
+                .map(|extension| path.with_extension(extension))
+                .find(|label_path| label_path.is_file()) {
+                Some(label_path) => label_path,
+                None => return Ok(None),
+            }
+        };
+        info!("Reading PDS label {:?}", label_path);
+        let text = try!(read_label_text(&label_path));
+        let label = try!(Label::parse(&text).map_err(|msg| {
+            ErrorKind::InvalidPdsLabel(format!("{:?}", label_path), msg)
+        }));
+        let (image, data_file) = try!(label.image().map_err(|kind| match kind {
+            ErrorKind::Msg(msg) => ErrorKind::InvalidPdsLabel(format!("{:?}", label_path), msg),
+            kind => kind,
+        }));
+        // A detached label names the file it describes, but the one given is
+        // used unless it's the label.
+        let data_path = match data_file {
+            Some(ref name) if is_label => try!(sibling(&label_path, name)),
+            _ => path.to_path_buf(),
+        };
+        Ok(Some((image, data_path)))
+    }
+
+    /// Bytes from the start of one line to the next.
+    pub fn line_size(&self) -> usize {
+        self.line_prefix_bytes + self.line_samples * self.sample_bytes + self.line_suffix_bytes
+    }
+
+    /// The size of the file the image fills to its end.
+    pub fn file_size(&self) -> u64 {
+        self.start + (self.lines * self.line_size()) as u64
+    }
+
+    /// The offset in the file of the sample at (`x`, `y`).
+    pub fn sample_offset(&self, x: usize, y: usize) -> u64 {
+        self.start + (y * self.line_size() + self.line_prefix_bytes + x * self.sample_bytes) as u64
+    }
+
+    /// Converts consecutive samples of a line to heights, missing ones to
+    /// None.
+    pub fn decode(&self, bytes: &[u8], heights: &mut [Option<f32>]) {
+        if self.big_endian {
+            self.decode_with::<BigEndian>(bytes, heights)
+        } else {
+            self.decode_with::<LittleEndian>(bytes, heights)
+        }
+    }
+
+    fn decode_with<B: ByteOrder>(&self, bytes: &[u8], heights: &mut [Option<f32>]) {
+        for (height, sample) in heights.iter_mut().zip(bytes.chunks(self.sample_bytes)) {
+            if Some(B::read_uint(sample, self.sample_bytes)) == self.missing_sample {
+                *height = None;
+                continue;
+            }
+            let value = match (self.sample_type, self.sample_bytes) {
+                (SampleType::Integer, 1) => sample[0] as i8 as f32,
+                (SampleType::Integer, 2) => B::read_i16(sample) as f32,
+                (SampleType::Integer, _) => B::read_i32(sample) as f32,
+                (SampleType::UnsignedInteger, 1) => sample[0] as f32,
+                (SampleType::UnsignedInteger, 2) => B::read_u16(sample) as f32,
+                (SampleType::UnsignedInteger, _) => B::read_u32(sample) as f32,
+                (SampleType::Real, 4) => B::read_f32(sample),
+                (SampleType::Real, _) => B::read_f64(sample) as f32,
+            };
+            *height = if !value.is_finite() {
+                None
+            } else {
+                Some(self.offset + self.scaling_factor * value)
+            };
+        }
+    }
+}
+
+/// The keywords of a PDS label that describe an image: those outside any
+/// object, those of the first `IMAGE` object and those of its map
+/// projection, with their values as written minus any units.
+struct Label {
+    keywords: HashMap<String, String>,
+    image: HashMap<String, String>,
+    projection: HashMap<String, String>,
+}
+
+impl Label {
+    /// Parses the object description language of a label up to its `END`.
+    fn parse(text: &str) -> ::std::result::Result<Self, String> {
+        let mut label = Label {
+            keywords: HashMap::new(),
+            image: HashMap::new(),
+            projection: HashMap::new(),
+        };
+        let mut objects: Vec<String> = vec![];
+        let mut seen_image = false;
+        let mut lines = text.lines().map(strip_comment);
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line == "END" {
+                return Ok(label);
+            } else if line.is_empty() {
+                continue;
+            }
+            let equals = match line.find('=') {
+                Some(equals) => equals,
+                None => return Err(format!("Expected KEYWORD = VALUE, found {:?}.", line)),
+            };
+            let keyword = line[..equals].trim().to_uppercase();
+            let mut value = line[equals + 1..].trim().to_string();
+            // Lists and quoted strings may go on over several lines.
+            while !is_balanced(&value) {
+                match lines.next() {
+                    Some(line) => {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                    None => return Err(format!("Unterminated value of {}.", keyword)),
+                }
+            }
+            // Pointers keep their units, which tell bytes from records.
+            let value = if keyword.starts_with('^') {
+                value
+            } else {
+                strip_units(&value)
+            };
+
+            match &keyword[..] {
+                "OBJECT" => objects.push(value.to_uppercase()),
+                "END_OBJECT" => {
+                    if objects.pop().map(|object| object == "IMAGE").unwrap_or(false) {
+                        seen_image = true;
+                    }
+                }
+                _ if objects.last().map(|object| object == "IMAGE_MAP_PROJECTION")
+                    .unwrap_or(false) => {
+                    label.projection.insert(keyword, value);
+                }
+                _ if objects.is_empty() => {
+                    label.keywords.insert(keyword, value);
+                }
+                _ if objects.len() == 1 && objects[0] == "IMAGE" && !seen_image => {
+                    label.image.insert(keyword, value);
+                }
+                _ => {}
+            }
+        }
+        Err("The label has no END.".into())
+    }
+
+    /// The image the label describes and the name of the file it's in, if
+    /// not the label's own. What's wrong with the label is a message, like
+    /// the errors of `parse`, and images it describes but can't be read are
+    /// other kinds of errors.
+    fn image(&self) -> ::std::result::Result<(PdsImage, Option<String>), ErrorKind> {
+        if self.image.is_empty() {
+            return Err("The label has no IMAGE object.".into());
+        }
+        let bands = try!(self.image_number("BANDS", 1.0)) as usize;
+        if bands != 1 {
+            return Err(format!("Images of {} bands are not supported.", bands).into());
+        }
+        let sample_bits = try!(self.image_number("SAMPLE_BITS", 16.0)) as usize;
+        let sample_type_name = self.image
+            .get("SAMPLE_TYPE")
+            .cloned()
+            .unwrap_or_else(|| "MSB_INTEGER".to_string());
+        let (sample_type, big_endian) = match sample_type_from_name(&sample_type_name) {
+            Some(sample_type) => sample_type,
+            None => return Err(ErrorKind::UnsupportedPdsSampleType(sample_type_name, sample_bits)),
+        };
+        match (sample_type, sample_bits) {
+            (SampleType::Real, 32) |
+            (SampleType::Real, 64) |
+            (SampleType::Integer, 8) |
+            (SampleType::Integer, 16) |
+            (SampleType::Integer, 32) |
+            (SampleType::UnsignedInteger, 8) |
+            (SampleType::UnsignedInteger, 16) |
+            (SampleType::UnsignedInteger, 32) => {}
+            _ => return Err(ErrorKind::UnsupportedPdsSampleType(sample_type_name, sample_bits)),
+        }
+
+        // The pointer is to records of the label's file or another file,
+        // counted from 1, or to a byte if in bytes.
+        let record_bytes = try!(self.number("RECORD_BYTES", 0.0)) as u64;
+        let pointer = match self.keywords.get("^IMAGE") {
+            Some(pointer) => pointer.clone(),
+            None => return Err("The label has no ^IMAGE pointer.".into()),
+        };
+        let in_bytes = pointer.contains("<BYTES>");
+        let pointer = strip_units(&pointer);
+        let (file, location) = if pointer.starts_with('(') {
+            let parts: Vec<&str> = pointer.trim_matches(|c| c == '(' || c == ')')
+                .split(',')
+                .map(|part| part.trim())
+                .collect();
+            (Some(unquote(parts[0])), parts.get(1).cloned())
+        } else if pointer.starts_with('"') {
+            (Some(unquote(&pointer)), None)
+        } else {
+            (None, Some(&pointer[..]))
+        };
+        let start = match location {
+            Some(location) => {
+                let location: u64 = try!(location.parse().map_err(|_| {
+                    ErrorKind::from(format!("Invalid ^IMAGE pointer {:?}.", pointer))
+                }));
+                if in_bytes {
+                    location.max(1) - 1
+                } else if record_bytes == 0 {
+                    return Err(
+                        "The ^IMAGE pointer is in records, but there's no RECORD_BYTES.".into(),
+                    );
+                } else {
+                    (location.max(1) - 1) * record_bytes
+                }
+            }
+            None => 0,
+        };
+
+        let missing_sample = match self.image.get("MISSING_CONSTANT") {
+            Some(text) => Some(try!(missing_sample(text, sample_type, sample_bits))),
+            None => None,
+        };
+        Ok((
+            PdsImage {
+                lines: try!(self.required_image_number("LINES")) as usize,
+                line_samples: try!(self.required_image_number("LINE_SAMPLES")) as usize,
+                sample_type: sample_type,
+                sample_bytes: sample_bits / 8,
+                big_endian: big_endian,
+                start: start,
+                line_prefix_bytes: try!(self.image_number("LINE_PREFIX_BYTES", 0.0)) as usize,
+                line_suffix_bytes: try!(self.image_number("LINE_SUFFIX_BYTES", 0.0)) as usize,
+                scaling_factor: try!(self.image_number("SCALING_FACTOR", 1.0)) as f32,
+                offset: try!(self.image_number("OFFSET", 0.0)) as f32,
+                missing_sample: missing_sample,
+                area: try!(self.map_area()),
+            },
+            file,
+        ))
+    }
+
+    /// The area of a cylindrical projection, others being left unplaced.
+    fn map_area(&self) -> ::std::result::Result<Option<MapArea>, String> {
+        let projection_type = match self.projection.get("MAP_PROJECTION_TYPE") {
+            Some(projection_type) => unquote(projection_type).to_uppercase(),
+            None => return Ok(None),
+        };
+        if projection_type != "SIMPLE CYLINDRICAL" && projection_type != "EQUIRECTANGULAR" {
+            warn!("Ignoring the {} map projection of the PDS image.", projection_type);
+            return Ok(None);
+        }
+        let number = |keyword: &str| match self.projection.get(keyword) {
+            Some(value) => parse_number(keyword, Some(value), 0.0),
+            None => Err(format!("The IMAGE_MAP_PROJECTION object has no {}.", keyword)),
+        };
+        let (west, east) = (
+            try!(number("WESTERNMOST_LONGITUDE")),
+            try!(number("EASTERNMOST_LONGITUDE")),
+        );
+        let positive_west = self.projection
+            .get("POSITIVE_LONGITUDE_DIRECTION")
+            .map(|direction| unquote(direction).eq_ignore_ascii_case("WEST"))
+            .unwrap_or(false);
+        Ok(Some(MapArea {
+            west: if positive_west { -west } else { west },
+            east: if positive_west { -east } else { east },
+            north: try!(number("MAXIMUM_LATITUDE")),
+            south: try!(number("MINIMUM_LATITUDE")),
+        }))
+    }
+
+    fn number(&self, keyword: &str, default: f64) -> ::std::result::Result<f64, String> {
+        parse_number(keyword, self.keywords.get(keyword), default)
+    }
+
+    fn image_number(&self, keyword: &str, default: f64) -> ::std::result::Result<f64, String> {
+        parse_number(keyword, self.image.get(keyword), default)
+    }
+
+    fn required_image_number(&self, keyword: &str) -> ::std::result::Result<f64, String> {
+        match self.image.get(keyword) {
+            Some(_) => self.image_number(keyword, 0.0),
+            None => Err(format!("The IMAGE object has no {}.", keyword)),
+        }
+    }
+}
+
+/// The signedness and byte order of the PDS sample types, including the
+/// older synonyms.
+fn sample_type_from_name(name: &str) -> Option<(SampleType, bool)> {
+    match name {
+        "MSB_INTEGER" | "INTEGER" | "SUN_INTEGER" | "MAC_INTEGER" => {
+            Some((SampleType::Integer, true))
+        }
+        "LSB_INTEGER" | "PC_INTEGER" | "VAX_INTEGER" => Some((SampleType::Integer, false)),
+        "MSB_UNSIGNED_INTEGER" |
+        "UNSIGNED_INTEGER" |
+        "SUN_UNSIGNED_INTEGER" |
+        "MAC_UNSIGNED_INTEGER" => Some((SampleType::UnsignedInteger, true)),
+        "LSB_UNSIGNED_INTEGER" |
+        "PC_UNSIGNED_INTEGER" |
+        "VAX_UNSIGNED_INTEGER" => Some((SampleType::UnsignedInteger, false)),
+        "IEEE_REAL" | "FLOAT" | "REAL" | "SUN_REAL" | "MAC_REAL" => Some((SampleType::Real, true)),
+        "PC_REAL" => Some((SampleType::Real, false)),
+        _ => None,
+    }
+}
+
+/// The bits of the samples with the value `text` of `MISSING_CONSTANT`.
+/// Those of real images are often the bits of the float in hex, e.g.
+/// 16#FF7FFFFB#, which are taken as they are. Integers are read as such,
+/// as a double can't hold all those of 64 bits.
+fn missing_sample(
+    text: &str,
+    sample_type: SampleType,
+    sample_bits: usize,
+) -> ::std::result::Result<u64, String> {
+    let invalid = || format!("Invalid MISSING_CONSTANT = {}.", text);
+    let bits = if text.contains('#') {
+        try!(parse_integer(text).ok_or_else(invalid))
+    } else {
+        match sample_type {
+            SampleType::Real => {
+                let value: f64 = try!(text.parse().map_err(|_| invalid()));
+                if sample_bits == 64 {
+                    value.to_bits()
+                } else {
+                    (value as f32).to_bits() as u64
+                }
+            }
+            SampleType::Integer => try!(text.parse::<i64>().map_err(|_| invalid())) as u64,
+            SampleType::UnsignedInteger => try!(text.parse().map_err(|_| invalid())),
+        }
+    };
+    // Only the sample's bits are read, past which negative integers are
+    // sign extended.
+    Ok(if sample_bits < 64 {
+        bits & ((1 << sample_bits) - 1)
+    } else {
+        bits
+    })
+}
+
+/// An integer written in another base like 16#FF7FFFFB#.
+fn parse_integer(text: &str) -> Option<u64> {
+    let parts: Vec<&str> = text.split('#').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    match parts[0].parse() {
+        Ok(base) if base >= 2 && base <= 36 => u64::from_str_radix(parts[1], base).ok(),
+        _ => None,
+    }
+}
+
+fn parse_number(
+    keyword: &str,
+    value: Option<&String>,
+    default: f64,
+) -> ::std::result::Result<f64, String> {
+    match value {
+        Some(value) => {
+            let invalid = || format!("Invalid {} = {}.", keyword, value);
+            if value.contains('#') {
+                parse_integer(value).map(|number| number as f64).ok_or_else(invalid)
+            } else {
+                value.parse().map_err(|_| invalid())
+            }
+        }
+        None => Ok(default),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find("/*") {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+fn is_balanced(value: &str) -> bool {
+    let quotes = value.chars().filter(|&c| c == '"').count();
+    let opened = value.chars().filter(|&c| c == '(' || c == '{').count();
+    let closed = value.chars().filter(|&c| c == ')' || c == '}').count();
+    quotes % 2 == 0 && opened <= closed
+}
+
+/// The value without units such as `<METERS>`.
+fn strip_units(value: &str) -> String {
+    let mut stripped = String::with_capacity(value.len());
+    let mut in_units = false;
+    for c in value.chars() {
+        match c {
+            '<' => in_units = true,
+            '>' => in_units = false,
+            _ if !in_units => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped.trim().to_string()
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+/// Whether the file begins with a PDS label.
+fn starts_with_label(path: &Path) -> Result<bool> {
+    let mut file = try!(File::open(path).chain_err(
+        || format!("Could not open heightmap file {:?}.", path),
+    ));
+    let mut start = [0; 14];
+    let mut read = 0;
+    while read < start.len() {
+        match try!(file.read(&mut start[read..]).chain_err(
+            || format!("Could not read heightmap file {:?}.", path),
+        )) {
+            0 => break,
+            num_bytes => read += num_bytes,
+        }
+    }
+    Ok(&start[..read] == b"PDS_VERSION_ID" || &start[..read] == b"ODL_VERSION_ID")
+}
+
+/// The text of the label at the start of the file, which is followed by
+/// binary data.
+fn read_label_text(path: &Path) -> Result<String> {
+    let file = try!(File::open(path).chain_err(
+        || format!("Could not open PDS label {:?}.", path),
+    ));
+    let mut bytes = vec![];
+    try!(file.take(MAX_LABEL_SIZE).read_to_end(&mut bytes).chain_err(
+        || format!("Could not read PDS label {:?}.", path),
+    ));
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The file named in a label next to it. Labels give names in upper case,
+/// which the files may not be in.
+fn sibling(label_path: &Path, name: &str) -> Result<PathBuf> {
+    let directory = label_path.parent().unwrap_or_else(|| Path::new(""));
+    let candidates = [name.to_string(), name.to_lowercase(), name.to_uppercase()];
+    match candidates
+        .iter()
+        .map(|candidate| directory.join(candidate))
+        .find(|path| path.is_file()) {
+        Some(path) => Ok(path),
+        None => Err(
+            ErrorKind::InvalidPdsLabel(
+                format!("{:?}", label_path),
+                format!("The image file {:?} does not exist.", name),
+            ).into(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use errors::ErrorKind;
+    use super::{Label, MapArea, PdsImage, SampleType};
+
+    #[test]
+    fn test_parse_label() {
+        let text = r#"PDS_VERSION_ID = PDS3
+RECORD_TYPE = FIXED_LENGTH
+RECORD_BYTES = 1440
+FILE_RECORDS = 22
+LABEL_RECORDS = 2
+^IMAGE = 3
+DATA_SET_ID = "MGS-M-MOLA-5-MEGDR-L3-V1.0" /* A comment */
+NOTE = "Gridded topography, in metres above
+        the areoid."
+OBJECT = IMAGE
+  LINES = 20
+  LINE_SAMPLES = 720
+  SAMPLE_TYPE = LSB_INTEGER
+  SAMPLE_BITS = 16
+  UNIT = METER
+  SCALING_FACTOR = 0.5
+  OFFSET = 100.0 <METERS>
+  MISSING_CONSTANT = -32768
+  OBJECT = MAP_PROJECTION
+    LINES = 99
+  END_OBJECT = MAP_PROJECTION
+END_OBJECT = IMAGE
+END
+"#;
+        let (image, file) = Label::parse(text).unwrap().image().unwrap();
+        assert_eq!(None, file);
+        assert_eq!(
+            PdsImage {
+                lines: 20,
+                line_samples: 720,
+                sample_type: SampleType::Integer,
+                sample_bytes: 2,
+                big_endian: false,
+                start: 2880,
+                line_prefix_bytes: 0,
+                line_suffix_bytes: 0,
+                scaling_factor: 0.5,
+                offset: 100.0,
+                missing_sample: Some(0x8000),
+                area: None,
+            },
+            image
+        );
+        assert_eq!(image.file_size(), 1440 * 22);
+        let mut heights = [None; 3];
+        image.decode(&[0x10, 0x00, 0x00, 0x80, 0xfe, 0xff], &mut heights);
+        assert_eq!([Some(108.0), None, Some(99.0)], heights);
+
+        // Detached labels point to their file, in bytes or records.
+        let detached = text.replace("^IMAGE = 3", "^IMAGE = (\"MEGT90N000CB.IMG\", 11 <BYTES>)");
+        let (image, file) = Label::parse(&detached).unwrap().image().unwrap();
+        assert_eq!((10, Some("MEGT90N000CB.IMG".to_string())), (image.start, file));
+
+        let real = text.replace("LSB_INTEGER", "PC_REAL")
+            .replace("SAMPLE_BITS = 16", "SAMPLE_BITS = 32")
+            .replace("-32768", "16#FF7FFFFB#");
+        let (image, _) = Label::parse(&real).unwrap().image().unwrap();
+        assert_eq!(Some(0xFF7FFFFB), image.missing_sample);
+
+        // The bits of doubles don't fit in one, but are compared exactly,
+        // even with the next double, which is the same float.
+        let double = real.replace("SAMPLE_BITS = 32", "SAMPLE_BITS = 64")
+            .replace("16#FF7FFFFB#", "16#C6293E5939A08CEA#");
+        let (image, _) = Label::parse(&double).unwrap().image().unwrap();
+        assert_eq!(Some(0xC6293E5939A08CEA), image.missing_sample);
+        let mut samples = [0; 16];
+        LittleEndian::write_u64(&mut samples[..8], 0xC6293E5939A08CEA);
+        LittleEndian::write_u64(&mut samples[8..], 0xC6293E5939A08CEB);
+        let mut heights = [None; 2];
+        image.decode(&samples, &mut heights);
+        assert!(heights[0].is_none() && heights[1].is_some());
+
+        // The quadrangles of the MEGDR are placed by their projection.
+        let projection = r#"OBJECT = IMAGE_MAP_PROJECTION
+  MAP_PROJECTION_TYPE = "SIMPLE CYLINDRICAL"
+  POSITIVE_LONGITUDE_DIRECTION = "EAST"
+  MAXIMUM_LATITUDE = 88.0 <DEGREE>
+  MINIMUM_LATITUDE = 44.0 <DEGREE>
+  WESTERNMOST_LONGITUDE = 0.0 <DEGREE>
+  EASTERNMOST_LONGITUDE = 90.0 <DEGREE>
+END_OBJECT = IMAGE_MAP_PROJECTION
+END
+"#;
+        let projected = text.replace("END\n", "") + projection;
+        let (image, _) = Label::parse(&projected).unwrap().image().unwrap();
+        assert_eq!(
+            Some(MapArea {
+                west: 0.0,
+                east: 90.0,
+                north: 88.0,
+                south: 44.0,
+            }),
+            image.area
+        );
+
+        for unsupported in &[
+            text.replace("LSB_INTEGER", "VAX_REAL"),
+            text.replace("SAMPLE_BITS = 16", "SAMPLE_BITS = 24"),
+        ] {
+            match Label::parse(unsupported).unwrap().image() {
+                Err(ErrorKind::UnsupportedPdsSampleType(..)) => {}
+                result => panic!("Expected an unsupported sample type, got {:?}.", result),
+            }
+        }
+        assert!(Label::parse(&text.replace("END\n", "")).is_err());
+    }
+}
+
+// Labels at the start of images are followed by the samples, so only this
+// much of the file is read for them.
+const MAX_LABEL_SIZE: u64 = 1 << 20;
@@ -1,29 +1,91 @@
-use std::collections::{HashSet, HashMap};
-use std::sync::Arc;
-
-use glium::{self, Frame, DrawParameters, Program, Surface};
-use nalgebra::{Eye, Norm, Matrix4, Isometry3, Translation, Point3, Rotation, Vector3};
-use ncollide::shape::{Ball, ShapeHandle};
-use nphysics3d::object::{RigidBody, RigidBodyHandle};
-use nphysics3d::volumetric::Volumetric;
-use nphysics3d::world::World;
+use std::f32::consts::PI;
+
+use nalgebra::{Dot, Norm, Point3};
 use noise::{self, Seed, Brownian3};
-use threadpool::ThreadPool;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use fields::SolidSpec;
+use fractal::{Fractal, FractalNoise, NoiseBasis};
+use math::{CpuScalar, Material, Mesher, Vec3f, ScalarField3};
+use math::cube_sphere::{direction_to_equal_angle, face_point};
+use tectonics::Plates;
+
+/// Where on the surface the noise is sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfaceMapping {
+    /// On the unit sphere, at the direction of each position.
+    Sphere,
+    /// On the unit cube, at the equal angle cube-sphere coordinates of each
+    /// direction. The terrain on a face only depends on its `(u, v)`, so it
+    /// can be baked into a grid per face.
+    CubeSphere,
+}
 
-use errors::{ChainErr, Result};
-use game::Player;
-use gfx::{Camera, LevelOfDetail, Window};
-use math::{CpuScalar, Matrix4f, Vec3f, ScalarField3};
-use utils::read_utf8_file;
+impl SurfaceMapping {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sphere" => Some(SurfaceMapping::Sphere),
+            "cube" => Some(SurfaceMapping::CubeSphere),
+            _ => None,
+        }
+    }
+}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PlanetSpec {
     pub base_radius: f32,
     pub landscape_deviation: f32,
+    /// Octaves of the mountain noise, the plains have fixed parameters.
     pub num_octaves: usize,
     pub persistence: f32,
     pub wavelength: f32,
     pub lacunarity: f32,
+    /// Noise and fractal for the two kinds of terrain, which are mixed by a
+    /// third, low frequency noise, or by the plates if there are any.
+    pub mountain_basis: NoiseBasis,
+    pub mountain_fractal: Fractal,
+    pub plains_basis: NoiseBasis,
+    pub plains_fractal: Fractal,
+    pub surface_mapping: SurfaceMapping,
+    /// Tectonic plates shaping continents, mountain ranges and trenches,
+    /// none if zero. About `continental_fraction` of them are continents.
+    pub num_plates: usize,
+    pub continental_fraction: f32,
+    /// Quantizes the terrain into this many steps from the lowest to the
+    /// highest, none if zero, for mesas and badlands of layered rock.
+    pub terracing: usize,
+    /// How flat the steps are and how steep the cliffs between them, from 0
+    /// for no steps to 1 for flat steps.
+    pub terrace_hardness: f32,
+    /// Height of the ocean surface in the same units as the terrain's noise:
+    /// -1 is about the lowest terrain and 1 the highest. No ocean if missing.
+    pub sea_level: Option<f32>,
+    /// Impact craters scattered over the surface, none if zero.
+    pub num_craters: usize,
+    /// Crater radii, in world units, follow a power law between these two:
+    /// a crater is larger than `r` with probability `(r / min)^-exponent`.
+    pub min_crater_radius: f32,
+    pub max_crater_radius: f32,
+    pub crater_size_exponent: f32,
+    /// Volcanic cones with lava filled calderas, placed at hotspots picked by
+    /// a low frequency noise. None if zero.
+    pub num_volcanoes: usize,
+    /// Radius of a volcano's base and height of its rim, in world units.
+    pub volcano_radius: f32,
+    pub volcano_height: f32,
+    /// Everything nearer a pole than this latitude, in degrees, is ice.
+    pub polar_cap_latitude: f32,
+    /// Height above which the surface is covered in snow, in the same units
+    /// as `sea_level`. No snow if missing.
+    pub snow_line: Option<f32>,
+    /// Sand covers the shores and the sea floor up to this height above
+    /// `sea_level`, in the same units. Only planets with an ocean have any.
+    pub beach_height: f32,
+    /// Dual contouring keeps the sharp edges of terraces and cliffs, which
+    /// marching cubes rounds off.
+    pub mesher: Mesher,
+    /// Added to or carved out of the terrain, in order.
+    pub solids: Vec<SolidSpec>,
 }
 
 impl Default for PlanetSpec {
@@ -35,244 +97,664 @@ impl Default for PlanetSpec {
             persistence: 0.8,
             wavelength: 1.7,
             lacunarity: 1.91,
+            mountain_basis: NoiseBasis::Simplex,
+            mountain_fractal: Fractal::Fbm,
+            plains_basis: NoiseBasis::Simplex,
+            plains_fractal: Fractal::Fbm,
+            surface_mapping: SurfaceMapping::Sphere,
+            num_plates: 0,
+            continental_fraction: 0.4,
+            terracing: 0,
+            terrace_hardness: 0.8,
+            sea_level: None,
+            num_craters: 0,
+            min_crater_radius: 20.0,
+            max_crater_radius: 800.0,
+            crater_size_exponent: 2.0,
+            num_volcanoes: 0,
+            volcano_radius: 400.0,
+            volcano_height: 250.0,
+            polar_cap_latitude: 90.0,
+            snow_line: None,
+            beach_height: 0.02,
+            mesher: Mesher::MarchingCubes,
+            solids: vec![],
         }
     }
 }
 
+impl PlanetSpec {
+    /// A small, airless body for a moon of `planet`: gentler terrain than the
+    /// planet's, no water, ice, volcanoes or terraces, and covered in
+    /// craters.
+    pub fn moon(planet: &PlanetSpec) -> Self {
+        let base_radius = planet.base_radius * MOON_RADIUS_RATIO;
+        PlanetSpec {
+            base_radius: base_radius,
+            landscape_deviation: planet.landscape_deviation / 3.0,
+            num_octaves: planet.num_octaves,
+            num_plates: 0,
+            terracing: 0,
+            sea_level: None,
+            num_craters: 150,
+            min_crater_radius: base_radius / 200.0,
+            max_crater_radius: base_radius / 4.0,
+            crater_size_exponent: 1.8,
+            num_volcanoes: 0,
+            polar_cap_latitude: 90.0,
+            snow_line: None,
+            solids: vec![],
+            ..planet.clone()
+        }
+    }
+
+    pub fn mountains(&self) -> FractalNoise {
+        FractalNoise {
+            basis: self.mountain_basis,
+            fractal: self.mountain_fractal,
+            num_octaves: self.num_octaves,
+            persistence: self.persistence,
+            wavelength: self.wavelength,
+            lacunarity: self.lacunarity,
+        }
+    }
+
+    pub fn plains(&self) -> FractalNoise {
+        FractalNoise {
+            basis: self.plains_basis,
+            fractal: self.plains_fractal,
+            num_octaves: 3,
+            persistence: 0.9,
+            wavelength: 1.9,
+            lacunarity: 1.8,
+        }
+    }
+
+    /// The low frequency noise mixing mountains and plains.
+    pub fn terrain_mix(&self) -> FractalNoise {
+        FractalNoise {
+            basis: NoiseBasis::Simplex,
+            fractal: Fractal::Fbm,
+            num_octaves: 2,
+            persistence: 0.5,
+            wavelength: 2.0,
+            lacunarity: 2.0,
+        }
+    }
+
+    /// The distance from the centre of the planet to the ocean's surface.
+    pub fn sea_radius(&self) -> Option<f32> {
+        self.sea_level.map(|sea_level| {
+            self.base_radius * (1.0 + self.landscape_deviation * sea_level)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PhysicsSpec {
+    /// Acceleration towards the centre of the planet.
+    pub gravity: f32,
+}
+
+impl Default for PhysicsSpec {
+    fn default() -> Self {
+        PhysicsSpec { gravity: 9.6 }
+    }
+}
+
 pub struct PlanetField {
     seed: Seed,
     spec: PlanetSpec,
+    craters: Vec<Crater>,
+    volcanoes: Vec<Volcano>,
+    plates: Option<Plates>,
 }
 
 impl PlanetField {
     pub fn new(seed: u32, planet_spec: PlanetSpec) -> Self {
+        let craters = Crater::scatter(seed, &planet_spec);
+        let noise_seed = Seed::new(seed);
+        let volcanoes = Volcano::scatter(seed, &noise_seed, &planet_spec);
+        let plates = if planet_spec.num_plates > 0 {
+            Some(Plates::generate(seed, &planet_spec))
+        } else {
+            None
+        };
         PlanetField {
-            seed: Seed::new(seed),
+            seed: noise_seed,
             spec: planet_spec,
+            craters: craters,
+            volcanoes: volcanoes,
+            plates: plates,
         }
     }
-}
 
-impl ScalarField3 for PlanetField {
-    #[inline]
-    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
-        let (x, y, z) = (position[0], position[1], position[2]);
-        assert!(
-            x.is_finite() && y.is_finite() && z.is_finite(),
-            format!("{} {} {}", x, y, z)
-        );
-        let PlanetField { ref seed, ref spec } = *self;
+    pub fn spec(&self) -> &PlanetSpec {
+        &self.spec
+    }
 
-        let mut position = Vec3f::new(x, y, z);
-        let distance = position.norm();
-        position.normalize_mut();
-        // info!("pos: {:?}", position);
+    /// The permutation table of the noise seed, for noise implementations
+    /// outside of the `noise` crate.
+    pub fn permutation(&self) -> [u32; 256] {
+        let mut permutation = [0; 256];
+        for (index, value) in permutation.iter_mut().enumerate() {
+            *value = self.seed.get1(index as isize) as u32;
+        }
+        permutation
+    }
 
-        let mountains = Brownian3::new(noise::open_simplex3, spec.num_octaves)
-            .persistence(spec.persistence)
-            .wavelength(spec.wavelength)
-            .lacunarity(spec.lacunarity);
-        let plains = Brownian3::new(noise::open_simplex3, 3)
-            .persistence(0.9)
-            .wavelength(1.9)
-            .lacunarity(1.8);
-        let mix = Brownian3::new(noise::open_simplex3, 2).wavelength(2.0);
+    /// Where the noise is sampled for the unit `direction`, depending on the
+    /// `surface_mapping`.
+    pub fn noise_point(&self, direction: &Vec3f) -> Vec3f {
+        match self.spec.surface_mapping {
+            SurfaceMapping::Sphere => *direction,
+            SurfaceMapping::CubeSphere => {
+                let (face, u, v) = direction_to_equal_angle(direction);
+                face_point(face, u, v)
+            }
+        }
+    }
+
+    /// The mountains and plains noise displacing the surface at the
+    /// `noise_point` of a direction, roughly in [-1, 1]. This is most of the
+    /// cost of sampling the field, `assets/shaders/density.comp` mirrors it on
+    /// the GPU.
+    pub fn surface_noise(&self, point: &Vec3f) -> f32 {
+        self.surface_noise_lod(point, 0.0)
+    }
+
+    /// The `surface_noise` without the octaves shorter than `step` on the
+    /// surface of the planet.
+    pub fn surface_noise_lod(&self, point: &Vec3f, step: CpuScalar) -> f32 {
+        let spec = &self.spec;
+        let mountains = spec.mountains();
+        let plains = spec.plains();
+        let mix = spec.terrain_mix();
+        // The noise is sampled on a sphere of radius `scale` rather than
+        // `base_radius`, which shrinks the step by as much.
+        let sample = |noise: &FractalNoise, point: Vec3f, scale: f32| {
+            let num_octaves = noise.octaves_above(step * scale / spec.base_radius);
+            noise.apply_octaves(&self.seed, point.as_ref(), num_octaves)
+        };
+
+        // The plates raise continents and mountain ranges and leave the rest
+        // to the plains, the noise only adds the detail.
+        if let Some(ref plates) = self.plates {
+            let plate = plates.sample(point);
+            let detail = plate.mountains * sample(&mountains, *point * 4.0, 4.0) +
+                (1.0 - plate.mountains) * sample(&plains, *point * 2.0, 2.0);
+            return plate.elevation + PLATE_DETAIL * detail;
+        }
 
         let mut perturbation = 0.0;
-        let mut alpha = (1.0 + mix.apply(&self.seed, (position * 3.0 + 10.0).as_ref())) / 2.0;
+        let mut alpha = (1.0 + sample(&mix, *point * 3.0 + 10.0, 3.0)) / 2.0;
         if alpha > 0.45 && alpha < 0.55 {
             alpha = (alpha - 0.45) * 10.0;
-            perturbation = alpha * mountains.apply(&self.seed, (position * 4.0).as_ref()) +
-                (1.0 - alpha) * plains.apply(&self.seed, (position * 2.0).as_ref());
+            perturbation = alpha * sample(&mountains, *point * 4.0, 4.0) +
+                (1.0 - alpha) * sample(&plains, *point * 2.0, 2.0);
         } else if alpha < 0.45 {
-            perturbation = plains.apply(&self.seed, (position * 2.0).as_ref());
+            perturbation = sample(&plains, *point * 2.0, 2.0);
         } else {
-            perturbation = mountains.apply(&self.seed, (position * 4.0).as_ref());
+            perturbation = sample(&mountains, *point * 4.0, 4.0);
         }
+        perturbation
+    }
 
-        let radius = spec.base_radius + spec.landscape_deviation * spec.base_radius * perturbation;
-        distance - radius
-        // y
+    /// The field at `position` given the `surface_noise` in its direction.
+    pub fn value_from_noise(&self, position: &Point3<CpuScalar>, noise: f32) -> CpuScalar {
+        let mut direction = Vec3f::new(position[0], position[1], position[2]);
+        let distance = direction.norm();
+        direction.normalize_mut();
+
+        let volcanoes: f32 = self.volcanoes
+            .iter()
+            .map(|volcano| volcano.height_at(&direction))
+            .sum();
+        distance - (self.radius_from_noise(&direction, noise) + volcanoes)
+    }
 
-        // y - (x * x + z * z).sqrt().sin()
+    /// Distance from the centre to the surface in the unit `direction`, not
+    /// counting volcanoes.
+    fn terrain_radius(&self, direction: &Vec3f) -> f32 {
+        self.radius_from_noise(direction, self.surface_noise(&self.noise_point(direction)))
+    }
+
+    fn radius_from_noise(&self, direction: &Vec3f, noise: f32) -> f32 {
+        let spec = &self.spec;
+        let craters: f32 = self.craters
+            .iter()
+            .map(|crater| crater.height_at(direction))
+            .sum();
+        spec.base_radius + spec.landscape_deviation * spec.base_radius * self.terrace(noise) +
+            craters
+    }
+
+    /// The `noise` flattened into `terracing` steps, each a flat tread for
+    /// `terrace_hardness` of it and a cliff rising to the next one after.
+    fn terrace(&self, noise: f32) -> f32 {
+        let spec = &self.spec;
+        if spec.terracing == 0 {
+            return noise;
+        }
+        let step = TERRACED_RANGE / spec.terracing as f32;
+        let hardness = spec.terrace_hardness.max(0.0).min(MAX_TERRACE_HARDNESS);
+        let level = (noise + 1.0) / step;
+        let tread = level.floor();
+        let rise = ((level - tread - hardness) / (1.0 - hardness)).max(0.0);
+        (tread + rise) * step - 1.0
+    }
+
+    /// Height above the base radius at `distance` from the centre, on the
+    /// scale of the noise.
+    fn height_at(&self, distance: f32) -> f32 {
+        let spec = &self.spec;
+        (distance - spec.base_radius) / (spec.landscape_deviation * spec.base_radius)
     }
 }
 
-pub struct PlanetRenderer<'a, 'b, Field: ScalarField3> {
-    lod: LevelOfDetail<'a, Field>,
-    physics_world: World<CpuScalar>,
-    physics_chunks: HashMap<usize, RigidBodyHandle<CpuScalar>>,
-    draw_parameters: DrawParameters<'b>,
-    program: Program,
-    scalar_field: Arc<Field>,
-    pub player: Player,
+/// A bowl shaped depression with a raised rim.
+struct Crater {
+    centre: Vec3f,
+    // Radius as an angle from the centre of the planet to the top of the rim.
+    angle: f32,
+    // Cosine of the angle covered by the crater and its ejecta, outside of
+    // which it doesn't change the surface.
+    min_cos: f32,
+    depth: f32,
+    rim_height: f32,
 }
 
-impl<'a, 'b, Field> PlanetRenderer<'a, 'b, Field>
-where
-    Field: 'static + ScalarField3 + Send + Sync,
-{
-    pub fn new(scalar_field: Field, window: &Window, thread_pool: &'a ThreadPool) -> Result<Self> {
-
-        let vertex_shader = try!(read_utf8_file(VERTEX_SHADER));
-        let fragment_shader = try!(read_utf8_file(FRAGMENT_SHADER));
-        let program =
-            try!(
-                glium::Program::from_source(window.facade(), &vertex_shader, &fragment_shader, None)
-                    .chain_err(|| "Could not compile the shaders.")
-            );
+impl Crater {
+    /// Places `spec.num_craters` craters uniformly over the surface, the same
+    /// ones for a given seed.
+    fn scatter(seed: u32, spec: &PlanetSpec) -> Vec<Crater> {
+        let mut rng = XorShiftRng::from_seed([seed, 0x2545_f491, 0x9e37_79b9, 0x6c07_8965]);
+        let min_radius = spec.min_crater_radius.max(1e-3);
+        let max_radius = spec.max_crater_radius.max(min_radius);
+        (0..spec.num_craters)
+            .map(|_| {
+                let z: f32 = rng.gen_range(-1.0, 1.0);
+                let longitude: f32 = rng.gen_range(0.0, 2.0 * PI);
+                let ring = (1.0 - z * z).sqrt();
+                let centre = Vec3f::new(ring * longitude.cos(), ring * longitude.sin(), z);
+
+                // Inverse transform sampling of the truncated Pareto distribution.
+                let uniform: f32 = rng.gen_range(0.0, 1.0);
+                let max_tail = (max_radius / min_radius).powf(-spec.crater_size_exponent);
+                let radius = min_radius *
+                    (1.0 - uniform * (1.0 - max_tail)).powf(-1.0 / spec.crater_size_exponent);
+
+                let angle = radius / spec.base_radius;
+                Crater {
+                    centre: centre,
+                    angle: angle,
+                    min_cos: (angle * CRATER_EXTENT).min(PI).cos(),
+                    depth: radius * CRATER_DEPTH,
+                    rim_height: radius * CRATER_RIM_HEIGHT,
+                }
+            })
+            .collect()
+    }
 
-        let scalar_field = Arc::new(scalar_field);
-        let lod = LevelOfDetail::new(scalar_field.clone(), thread_pool, 12, 16.0, 32768.0, 10);
+    /// Change in surface radius in the unit `direction`.
+    #[inline]
+    fn height_at(&self, direction: &Vec3f) -> f32 {
+        let cos = direction.dot(&*self.centre);
+        if cos <= self.min_cos {
+            return 0.0;
+        }
+        let distance = cos.min(1.0).acos() / self.angle;
+        crater_profile(distance, self.depth, self.rim_height)
+    }
+}
 
-        let params = glium::DrawParameters {
-            depth: glium::Depth {
-                test: glium::draw_parameters::DepthTest::IfLess,
-                write: true,
-                ..Default::default()
-            },
-            backface_culling: glium::draw_parameters::BackfaceCullingMode::CullClockwise,
-            ..Default::default()
-        };
+/// The height of a crater at `distance` from its centre, in crater radii: a
+/// parabolic bowl rising to the rim at 1 which slopes down to nothing at
+/// `CRATER_EXTENT`.
+fn crater_profile(distance: f32, depth: f32, rim_height: f32) -> f32 {
+    if distance < 1.0 {
+        rim_height - depth * (1.0 - distance * distance)
+    } else if distance < CRATER_EXTENT {
+        let falloff = (CRATER_EXTENT - distance) / (CRATER_EXTENT - 1.0);
+        rim_height * falloff * falloff
+    } else {
+        0.0
+    }
+}
 
-        let mut physics_world = World::new();
-        let ball = ShapeHandle::new(Ball::new(3.0 as CpuScalar));
-        let ball_mass = 100.0;
-        let props = Some((
-            ball_mass,
-            ball.center_of_mass(),
-            ball.angular_inertia(ball_mass),
-        ));
-        let player_handle = physics_world.add_rigid_body(RigidBody::new(ball, props, 0.01, 2.0));
-        let player = Player::new(
-            player_handle,
-            &(Point3::new(1.0, 1.0, 1.0) * 0.5e4),
-            &Point3::new(0.0, 0.0, 0.0),
-            &Vector3::y(),
+impl ScalarField3 for PlanetField {
+    #[inline]
+    fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+        let (x, y, z) = (position[0], position[1], position[2]);
+        assert!(
+            x.is_finite() && y.is_finite() && z.is_finite(),
+            format!("{} {} {}", x, y, z)
         );
+        let mut direction = Vec3f::new(x, y, z);
+        direction.normalize_mut();
+        // info!("pos: {:?}", position);
 
-        Ok(PlanetRenderer {
-            lod: lod,
-            physics_world: physics_world,
-            physics_chunks: HashMap::new(),
-            draw_parameters: params,
-            program: program,
-            scalar_field: scalar_field,
-            player: player,
-        })
+        self.value_from_noise(position, self.surface_noise(&self.noise_point(&direction)))
+        // y
+
+        // y - (x * x + z * z).sqrt().sin()
     }
 
-    pub fn render(
-        &mut self,
-        window: &Window,
-        frame: &mut Frame,
-        camera: &mut Camera,
-    ) -> Result<()> {
-        let PlanetRenderer {
-            ref program,
-            ref draw_parameters,
-            ref mut lod,
-            ref mut physics_world,
-            ref mut physics_chunks,
-            ref mut player,
-            ..
-        } = *self;
-
-        physics_world.set_gravity(player.observer.translation().normalize() * -9.60);
-        // let new_camera = camera.position().translation() + player.position().translation() / 2.0;
-        // camera.observer_mut().set_translation(new_camera);
-
-        // let speed = player.player.borrow().lin_vel();
-        // if speed.norm() > 6.0 {
-        //     player.player.borrow_mut().set_lin_vel(speed.normalize());
-        // }
-
-        // player.borrow_mut().set_rotation(camera.position().rotation());
-        // physics_world.deferred_set_position(0, camera.position());
-        player.update_position();
-
-        let view = player.view_matrix();
-        let light = Vec3f::new(-40.0f32, 0.0, -4000.0);
-        let uniforms =
-            uniform! {
-            perspective: PlanetRenderer::<Field>::perspective_matrix(frame),
-            model: PlanetRenderer::<Field>::model_matrix(),
-            view: view,
-            u_light: &light,
-        };
+    /// Leaves out the octaves of the mountains and plains shorter than `step`.
+    #[inline]
+    fn value_at_lod(&self, position: &Point3<CpuScalar>, step: CpuScalar) -> CpuScalar {
+        let mut direction = Vec3f::new(position[0], position[1], position[2]);
+        direction.normalize_mut();
+        let noise = self.surface_noise_lod(&self.noise_point(&direction), step);
+        self.value_from_noise(position, noise)
+    }
 
-        let screen_chunks = try!(lod.update(window, camera));
-
-        let mut remove_set: HashSet<usize> = physics_chunks.keys().map(|x| *x).collect();
-
-        // {
-        //     let c1: HashSet<_> = physics_chunks.keys().collect();
-        //     let c2: HashSet<_> = screen_chunks.iter().map(|x| x.uid).collect();
-
-        //     info!("initial physics_chunks {:?}", c1);
-        //     info!("screen chunks {:?}", c2);
-        // }
-
-        for chunk in screen_chunks.into_iter() {
-            try!(
-                frame
-                    .draw(
-                        &chunk.vertex_buffer,
-                        &chunk.index_buffer,
-                        program,
-                        &uniforms,
-                        draw_parameters,
-                    )
-                    .chain_err(|| "Could not render frame.")
-            );
+    /// Lava fills the bottom of calderas, the poles and peaks above the snow
+    /// line are white and everything else is rock. The poles are along y.
+    fn material_at(&self, position: &Point3<CpuScalar>) -> Material {
+        let mut direction = Vec3f::new(position[0], position[1], position[2]);
+        let distance = direction.norm();
+        direction.normalize_mut();
+        for volcano in self.volcanoes.iter() {
+            if volcano.in_caldera(&direction) &&
+                distance < self.terrain_radius(&direction) + volcano.height * LAVA_LEVEL
+            {
+                return Material::Lava;
+            }
+        }
+
+        let spec = &self.spec;
+        let latitude = direction[1].abs().min(1.0).asin().to_degrees();
+        let height = self.height_at(distance);
+        if latitude > spec.polar_cap_latitude ||
+            spec.snow_line.map_or(false, |snow_line| height > snow_line)
+        {
+            Material::Snow
+        } else if spec.sea_level.map_or(false, |sea_level| height < sea_level + spec.beach_height) {
+            Material::Sand
+        } else {
+            Material::Rock
+        }
+    }
+
+    /// Terraced planets are layered, with `STRATA_PER_TERRACE` horizontal
+    /// strata to a step, so that the layers show on the cliffs.
+    fn stratum_at(&self, position: &Point3<CpuScalar>) -> Option<f32> {
+        let spec = &self.spec;
+        if spec.terracing == 0 {
+            return None;
+        }
+        let distance = Vec3f::new(position[0], position[1], position[2]).norm();
+        let step = TERRACED_RANGE / spec.terracing as f32;
+        Some(((self.height_at(distance) + 1.0) / step * STRATA_PER_TERRACE).max(0.0))
+    }
+
+    #[inline]
+    fn mesher(&self) -> Mesher {
+        self.spec.mesher
+    }
+
+    /// Below the lowest the noise gets, less the depth of the deepest crater
+    /// where they overlap. Volcanoes only raise the surface.
+    fn occluder_radius(&self) -> Option<CpuScalar> {
+        let spec = &self.spec;
+        let crater_floor = self.craters
+            .iter()
+            .map(|crater| crater.rim_height - crater.depth)
+            .fold(0.0f32, |lowest, floor| lowest.min(floor));
+        let radius = spec.base_radius * (1.0 - spec.landscape_deviation * OCCLUDER_NOISE) +
+            crater_floor;
+        Some(radius.max(0.0))
+    }
+}
+
+/// A cone rising to a rim around a flat bottomed caldera.
+struct Volcano {
+    centre: Vec3f,
+    // Radius of the base as an angle from the centre of the planet.
+    angle: f32,
+    min_cos: f32,
+    caldera_cos: f32,
+    height: f32,
+}
 
-            if !physics_chunks.contains_key(&chunk.uid) {
-                let handle = physics_world.add_rigid_body(RigidBody::new(
-                    chunk.tri_mesh.clone(),
-                    None,
-                    0.1,
-                    1.0,
-                ));
-                physics_chunks.insert(chunk.uid, handle);
+impl Volcano {
+    /// Places `spec.num_volcanoes` volcanoes at random directions where the
+    /// hotspot noise is high, so they cluster into volcanic provinces.
+    fn scatter(seed: u32, noise_seed: &Seed, spec: &PlanetSpec) -> Vec<Volcano> {
+        let mut rng = XorShiftRng::from_seed([seed, 0x5851_f42d, 0x4c95_7f2d, 0x1405_7b7e]);
+        let hotspots = Brownian3::new(noise::open_simplex3, 2).wavelength(1.0);
+        let angle = spec.volcano_radius / spec.base_radius;
+        let mut volcanoes = vec![];
+        for _ in 0..spec.num_volcanoes * MAX_HOTSPOT_ATTEMPTS {
+            if volcanoes.len() == spec.num_volcanoes {
+                break;
             }
-            remove_set.remove(&chunk.uid);
+            let z: f32 = rng.gen_range(-1.0, 1.0);
+            let longitude: f32 = rng.gen_range(0.0, 2.0 * PI);
+            let ring = (1.0 - z * z).sqrt();
+            let centre = Vec3f::new(ring * longitude.cos(), ring * longitude.sin(), z);
+            if hotspots.apply(noise_seed, (centre * 2.0 - 30.0).as_ref()) < HOTSPOT_THRESHOLD {
+                continue;
+            }
+            volcanoes.push(Volcano {
+                centre: centre,
+                angle: angle,
+                min_cos: angle.min(PI).cos(),
+                caldera_cos: (angle * CALDERA_RADIUS).min(PI).cos(),
+                height: spec.volcano_height,
+            });
         }
-        for uid in remove_set.into_iter() {
-            physics_world.remove_rigid_body(&physics_chunks[&uid]);
-            physics_chunks.remove(&uid);
+        if volcanoes.len() < spec.num_volcanoes {
+            warn!(
+                "Only found hotspots for {} of {} volcanoes.",
+                volcanoes.len(),
+                spec.num_volcanoes
+            );
         }
+        volcanoes
+    }
 
-        // info!("Camera: {:?}", camera.position().translation());
+    #[inline]
+    fn height_at(&self, direction: &Vec3f) -> f32 {
+        let cos = direction.dot(&*self.centre);
+        if cos <= self.min_cos {
+            return 0.0;
+        }
+        self.height * volcano_profile(cos.min(1.0).acos() / self.angle)
+    }
 
-        Ok(())
+    #[inline]
+    fn in_caldera(&self, direction: &Vec3f) -> bool {
+        direction.dot(&*self.centre) > self.caldera_cos
     }
+}
 
-    pub fn update_physics(&mut self, delta_time: f32) {
-        self.physics_world.step(delta_time);
+/// The height of a volcano relative to its rim at `distance` from its centre,
+/// in base radii: a concave cone from the base at 1 up to the rim at
+/// `CALDERA_RADIUS`, then a steep wall down to the caldera floor.
+fn volcano_profile(distance: f32) -> f32 {
+    if distance >= 1.0 {
+        0.0
+    } else if distance >= CALDERA_RADIUS {
+        ((1.0 - distance) / (1.0 - CALDERA_RADIUS)).powi(2)
+    } else {
+        let wall = (distance / CALDERA_RADIUS - CALDERA_FLOOR_RADIUS) /
+            (1.0 - CALDERA_FLOOR_RADIUS);
+        let wall = wall.max(0.0);
+        let smooth = wall * wall * (3.0 - 2.0 * wall);
+        1.0 - CALDERA_DEPTH * (1.0 - smooth)
     }
+}
 
-    fn model_matrix() -> Matrix4f {
-        Matrix4f::from(Matrix4::new_identity(4))
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+    use math::{Material, ScalarField3};
+    use super::{PlanetField, PlanetSpec, SurfaceMapping};
+    use super::{CALDERA_DEPTH, CALDERA_RADIUS, CRATER_EXTENT, LAVA_LEVEL, crater_profile,
+                volcano_profile};
+
+    #[test]
+    fn test_crater_profile() {
+        let (depth, rim_height) = (4.0, 1.0);
+        assert_eq!(rim_height - depth, crater_profile(0.0, depth, rim_height));
+        assert!((crater_profile(0.999, depth, rim_height) - rim_height).abs() < 1e-2);
+        assert!((crater_profile(1.001, depth, rim_height) - rim_height).abs() < 1e-2);
+        assert!(crater_profile(0.5, depth, rim_height) < 0.0);
+        assert!(crater_profile(CRATER_EXTENT - 1e-3, depth, rim_height).abs() < 1e-3);
+        assert_eq!(0.0, crater_profile(CRATER_EXTENT + 1.0, depth, rim_height));
     }
 
-    fn perspective_matrix(frame: &Frame) -> [[f32; 4]; 4] {
-        let (width, height) = frame.get_dimensions();
-        let aspect_ratio = height as f32 / width as f32;
+    #[test]
+    fn test_polar_caps_and_snow_line() {
+        let spec = PlanetSpec {
+            polar_cap_latitude: 60.0,
+            snow_line: Some(0.5),
+            ..Default::default()
+        };
+        let (radius, deviation) = (spec.base_radius, spec.landscape_deviation * spec.base_radius);
+        let planet = PlanetField::new(7, spec);
+        assert_eq!(Material::Snow, planet.material_at(&Point3::new(0.0, -radius, 0.0)));
+        assert_eq!(Material::Rock, planet.material_at(&Point3::new(radius, 0.0, 0.0)));
+        assert_eq!(
+            Material::Snow,
+            planet.material_at(&Point3::new(0.0, 0.0, radius + 0.6 * deviation))
+        );
+    }
 
-        let fov: f32 = 3.141592 / 3.0;
-        let zfar = 1e4;
-        let znear = 0.1;
+    #[test]
+    fn test_beaches() {
+        let spec = PlanetSpec {
+            sea_level: Some(0.1),
+            beach_height: 0.05,
+            ..Default::default()
+        };
+        let (radius, deviation) = (spec.base_radius, spec.landscape_deviation * spec.base_radius);
+        let planet = PlanetField::new(7, spec.clone());
+        let at_height = |height: f32| Point3::new(radius + height * deviation, 0.0, 0.0);
+        assert_eq!(Material::Sand, planet.material_at(&at_height(-0.3)));
+        assert_eq!(Material::Sand, planet.material_at(&at_height(0.12)));
+        assert_eq!(Material::Rock, planet.material_at(&at_height(0.2)));
+
+        let dry = PlanetField::new(7, PlanetSpec { sea_level: None, ..spec });
+        assert_eq!(Material::Rock, dry.material_at(&at_height(0.0)));
+    }
+
+    #[test]
+    fn test_cube_sphere_surface_is_seamless() {
+        let spec = PlanetSpec {
+            surface_mapping: SurfaceMapping::CubeSphere,
+            ..Default::default()
+        };
+        let radius = spec.base_radius;
+        let planet = PlanetField::new(5, spec);
+        // Either side of the edge between the +x and +y faces.
+        let near_x = planet.value_at(&Point3::new(radius, radius * 0.9999, radius * 0.3));
+        let near_y = planet.value_at(&Point3::new(radius * 0.9999, radius, radius * 0.3));
+        assert!((near_x - near_y).abs() < 1.0, "{} {}", near_x, near_y);
+
+        let sphere = PlanetField::new(5, PlanetSpec::default());
+        let position = Point3::new(radius * 0.3, radius * 0.8, -radius * 0.5);
+        assert!(planet.value_at(&position) != sphere.value_at(&position));
+    }
+
+    #[test]
+    fn test_terraces_and_strata() {
+        let spec = PlanetSpec {
+            terracing: 4,
+            terrace_hardness: 0.8,
+            ..Default::default()
+        };
+        let radius = spec.base_radius;
+        let planet = PlanetField::new(3, spec.clone());
+        // Steps of 0.5 from -1, flat for 0.4 then rising over 0.1.
+        assert!((planet.terrace(-0.95) + 1.0).abs() < 1e-5);
+        assert!((planet.terrace(-0.7) + 1.0).abs() < 1e-5);
+        assert!((planet.terrace(-0.55) + 0.75).abs() < 1e-5);
+        assert!((planet.terrace(0.2) - 0.0).abs() < 1e-5);
+        let mut previous = planet.terrace(-1.0);
+        for index in 1..200 {
+            let terraced = planet.terrace(-1.0 + index as f32 / 100.0);
+            assert!(terraced >= previous && terraced - previous < 0.1);
+            previous = terraced;
+        }
 
-        let f = 1.0 / (fov / 2.0).tan();
+        let soft = PlanetField::new(3, PlanetSpec { terrace_hardness: 0.0, ..spec });
+        assert!((soft.terrace(0.37) - 0.37).abs() < 1e-5);
+
+        let low = planet.stratum_at(&Point3::new(radius * 0.99, 0.0, 0.0)).unwrap();
+        let high = planet.stratum_at(&Point3::new(0.0, radius * 1.01, 0.0)).unwrap();
+        assert!(0.0 < low && low < high);
+        let flat = PlanetField::new(3, PlanetSpec::default());
+        assert_eq!(None, flat.stratum_at(&Point3::new(radius, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_occluder_is_below_the_surface() {
+        let spec = PlanetSpec {
+            num_plates: 8,
+            ..Default::default()
+        };
+        for planet in &[PlanetField::new(9, spec.clone()),
+                        PlanetField::new(9, PlanetSpec::moon(&spec))]
+        {
+            let radius = planet.occluder_radius().unwrap();
+            assert!(radius > 0.5 * planet.spec().base_radius);
+            for index in 0..500 {
+                // A spiral over the sphere.
+                let z = 1.0 - (index as f32 + 0.5) / 250.0;
+                let (ring, longitude) = ((1.0 - z * z).sqrt(), index as f32 * 2.4);
+                let position =
+                    Point3::new(ring * longitude.cos(), ring * longitude.sin(), z) * radius;
+                assert!(planet.value_at(&position) < 0.0);
+            }
+        }
+    }
 
-        [
-            [f * aspect_ratio, 0.0, 0.0, 0.0],
-            [0.0, f, 0.0, 0.0],
-            [0.0, 0.0, (zfar + znear) / (zfar - znear), 1.0],
-            [0.0, 0.0, -(2.0 * zfar * znear) / (zfar - znear), 0.0],
-        ]
+    #[test]
+    fn test_volcano_profile() {
+        assert_eq!(1.0 - CALDERA_DEPTH, volcano_profile(0.0));
+        assert!((volcano_profile(CALDERA_RADIUS) - 1.0).abs() < 1e-5);
+        assert!((volcano_profile(CALDERA_RADIUS - 1e-4) - 1.0).abs() < 1e-2);
+        assert!(volcano_profile(0.99) < 1e-3);
+        assert_eq!(0.0, volcano_profile(1.0));
+        // The caldera floor is covered by lava, the rim pokes out of it.
+        assert!(volcano_profile(0.0) < LAVA_LEVEL && LAVA_LEVEL < 1.0);
     }
 }
 
-const VERTEX_SHADER: &'static str = "src/gfx/shaders/planet.vert";
-const FRAGMENT_SHADER: &'static str = "src/gfx/shaders/planet.frag";
+// Depth of a crater's bowl below its rim and height of the rim, as fractions
+// of its radius.
+const CRATER_DEPTH: f32 = 0.4;
+const CRATER_RIM_HEIGHT: f32 = 0.08;
+// Distance, in crater radii, at which the rim has sloped back to the surface.
+const CRATER_EXTENT: f32 = 1.6;
+
+// Radius of a volcano's caldera, and of its flat floor within it, as
+// fractions of the radius of its base and of the caldera.
+const CALDERA_RADIUS: f32 = 0.18;
+const CALDERA_FLOOR_RADIUS: f32 = 0.6;
+// Depth of the caldera floor below the rim and height of the lava in it, as
+// fractions of the volcano's height.
+const CALDERA_DEPTH: f32 = 0.4;
+const LAVA_LEVEL: f32 = 0.75;
+// Volcanoes are only placed where the hotspot noise is above this.
+const HOTSPOT_THRESHOLD: f32 = 0.2;
+const MAX_HOTSPOT_ATTEMPTS: usize = 200;
+
+// The radius of a moon, as a fraction of its planet's; about that of the Moon.
+const MOON_RADIUS_RATIO: f32 = 0.27;
+// Amplitude of the mountains and plains noise over the elevation of the
+// plates.
+const PLATE_DETAIL: f32 = 0.35;
+// The range of the noise split into `terracing` steps, and proportion of a
+// step taken by its flat tread at most, leaving a steep cliff rather than a
+// vertical one for the mesher.
+const TERRACED_RANGE: f32 = 2.0;
+const MAX_TERRACE_HARDNESS: f32 = 0.95;
+// Layers of rock to a step.
+const STRATA_PER_TERRACE: f32 = 3.0;
+// Below the lowest surface noise, which the detail over the plates takes
+// past -1.
+const OCCLUDER_NOISE: f32 = 1.4;
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use glium::{Depth, DrawParameters, IndexBuffer, Program, Surface, VertexBuffer};
+use glium::draw_parameters::{BackfaceCullingMode, DepthTest, TimeElapsedQuery};
+use glium::index::PrimitiveType;
+use nalgebra::{Cross, Isometry3, Norm, Rotation3, Vector3};
+
+use assets::Assets;
+use errors::{ChainErr, Result};
+use gfx::{Vertex, Window};
+use gfx::lod::{collision_shape, TriMeshHandle};
+use gfx::mesh::load_mesh_from_file;
+use math::{CpuScalar, GpuScalar, Matrix4f, Vec3f};
+
+/// A mesh placed over the bodies wherever it's needed, like a plant or a
+/// structure, modelled in Wavefront OBJ with its base at the origin and +y
+/// up.
+pub struct Prefab {
+    pub mesh: &'static str,
+    pub color: [GpuScalar; 3],
+    /// Of the light the prefab is lit with, 0 being as dark as the terrain
+    /// when facing away from the sun.
+    pub ambient: f32,
+}
+
+/// Where a prefab stands at `position` on a body, upright along `up` and
+/// turned by `yaw` radians around it.
+pub fn placement(position: &Vec3f, up: &Vec3f, yaw: f32) -> Isometry3<CpuScalar> {
+    let forward = tangent_frame(up, yaw);
+    let rotation = Rotation3::new_observer_frame(&forward, up);
+    Isometry3::from_rotation_matrix(**position, rotation)
+}
+
+/// The matrix mapping the mesh of a prefab to where `isometry` places it,
+/// scaled by `scale`. Columns first, as glium expects of matrices.
+pub fn instance_transform(isometry: &Isometry3<CpuScalar>, scale: f32) -> [[f32; 4]; 4] {
+    let rotation = isometry.rotation.submatrix();
+    let axis = |column: usize| {
+        [
+            rotation[(0, column)] * scale,
+            rotation[(1, column)] * scale,
+            rotation[(2, column)] * scale,
+            0.0,
+        ]
+    };
+    let position = isometry.translation;
+    [axis(0), axis(1), axis(2), [position[0], position[1], position[2], 1.0]]
+}
+
+/// A unit vector perpendicular to `up`, rotated by `angle` around it.
+pub fn tangent_frame(up: &Vector3<CpuScalar>, angle: f32) -> Vector3<CpuScalar> {
+    let other = if up[0].abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let tangent = up.cross(&other).normalize();
+    tangent * angle.cos() + tangent.cross(up) * angle.sin()
+}
+
+/// Placed copies of prefabs as uploaded, one buffer of instances per prefab.
+pub struct PrefabInstances {
+    instances: Vec<(usize, VertexBuffer<PrefabInstance>)>,
+}
+
+impl PrefabInstances {
+    /// `placements` are the index of each copy's prefab with its transform,
+    /// see `instance_transform`.
+    pub fn new(window: &Window, placements: &[(usize, [[f32; 4]; 4])]) -> Result<Self> {
+        let mut by_prefab = BTreeMap::new();
+        for &(prefab, transform) in placements.iter() {
+            by_prefab.entry(prefab).or_insert_with(Vec::new).push(
+                PrefabInstance { transform: transform },
+            );
+        }
+        let mut instances = vec![];
+        for (prefab, transforms) in by_prefab.into_iter() {
+            let buffer = try!(
+                VertexBuffer::new(window.facade(), &transforms)
+                    .chain_err(|| "Cannot create instance buffer.")
+            );
+            instances.push((prefab, buffer));
+        }
+        Ok(PrefabInstances { instances: instances })
+    }
+
+    /// The size of the instance buffers, in bytes.
+    pub fn gpu_bytes(&self) -> usize {
+        self.instances.iter().map(|&(_, ref buffer)| buffer.get_size()).sum()
+    }
+}
+
+/// Draws placed prefabs, instancing the mesh of each.
+pub struct PrefabRenderer<'a> {
+    program: Program,
+    draw_parameters: DrawParameters<'a>,
+    meshes: Vec<PrefabMesh>,
+}
+
+impl<'a> PrefabRenderer<'a> {
+    /// The instances drawn refer to `prefabs` by their index.
+    pub fn new(window: &Window, assets: &Assets, prefabs: &[Prefab]) -> Result<Self> {
+        let program = try!(window.program(
+            try!(assets.resolve(VERTEX_SHADER)),
+            try!(assets.resolve(FRAGMENT_SHADER)),
+        ));
+        let mut meshes = vec![];
+        for prefab in prefabs.iter() {
+            meshes.push(try!(PrefabMesh::load(window, assets, prefab)));
+        }
+        let draw_parameters = DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling: BackfaceCullingMode::CullClockwise,
+            ..Default::default()
+        };
+        Ok(PrefabRenderer {
+            program: program,
+            draw_parameters: draw_parameters,
+            meshes: meshes,
+        })
+    }
+
+    /// The shape the physics world collides the prefab with index `prefab`
+    /// with, in the prefab's frame.
+    pub fn shape(&self, prefab: usize) -> TriMeshHandle {
+        self.meshes[prefab].tri_mesh.clone()
+    }
+
+    /// Draws `instances` on the body with `model` matrix into the g-buffer,
+    /// `camera_position` being relative to the body and `body` its index in
+    /// it.
+    pub fn render<S: Surface>(
+        &self,
+        gbuffer: &mut S,
+        instances: &PrefabInstances,
+        perspective: [[f32; 4]; 4],
+        view: &Matrix4f,
+        model: &Matrix4f,
+        camera_position: &Vec3f,
+        body: f32,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..self.draw_parameters.clone()
+        };
+        for &(prefab, ref instances) in instances.instances.iter() {
+            let mesh = &self.meshes[prefab];
+            let uniforms =
+                uniform! {
+                perspective: perspective,
+                view: view,
+                model: model,
+                u_color: mesh.color,
+                u_ambient: mesh.ambient,
+                u_camera: camera_position,
+                u_body: body,
+            };
+            let per_instance = match instances.per_instance() {
+                Ok(per_instance) => per_instance,
+                Err(_) => return Err("Instancing is not supported.".into()),
+            };
+            try!(
+                gbuffer
+                    .draw(
+                        (&mesh.vertex_buffer, per_instance),
+                        &mesh.index_buffer,
+                        &self.program,
+                        &uniforms,
+                        &draw_parameters,
+                    )
+                    .chain_err(|| "Could not render the prefabs.")
+            );
+        }
+        Ok(())
+    }
+}
+
+struct PrefabMesh {
+    vertex_buffer: VertexBuffer<Vertex>,
+    index_buffer: IndexBuffer<u32>,
+    tri_mesh: TriMeshHandle,
+    color: [GpuScalar; 3],
+    ambient: f32,
+}
+
+impl PrefabMesh {
+    fn load(window: &Window, assets: &Assets, prefab: &Prefab) -> Result<Self> {
+        let path = try!(assets.resolve(prefab.mesh));
+        let meshes = try!(load_mesh_from_file(&path.to_string_lossy()).chain_err(|| {
+            format!("Could not load the prefab mesh {:?}.", path)
+        }));
+        let mesh = match meshes.into_iter().next() {
+            Some(mesh) => mesh,
+            None => return Err(format!("There is no mesh in {:?}.", path).into()),
+        };
+        let vertex_buffer = try!(
+            VertexBuffer::new(window.facade(), &mesh.vertices)
+                .chain_err(|| "Cannot create vertex buffer.")
+        );
+        let index_buffer =
+            try!(
+                IndexBuffer::new(window.facade(), PrimitiveType::TrianglesList, &mesh.indices)
+                    .chain_err(|| "Cannot create index buffer.")
+            );
+        Ok(PrefabMesh {
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+            tri_mesh: collision_shape(&mesh),
+            color: prefab.color,
+            ambient: prefab.ambient,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PrefabInstance {
+    transform: [[f32; 4]; 4],
+}
+
+implement_vertex!(PrefabInstance, transform);
+
+const VERTEX_SHADER: &'static str = "shaders/prefab.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/prefab.frag";
@@ -0,0 +1,1041 @@
+use std::collections::{HashSet, HashMap};
+use std::f32::{self, consts};
+use std::sync::Arc;
+
+use glium::{self, Frame, DrawParameters, Program, Surface};
+use glium::draw_parameters::TimeElapsedQuery;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+use nalgebra::{Dot, Isometry3, Norm, Point3, ToHomogeneous, Translation, Vector3};
+use num::Zero;
+use nphysics3d::object::{RigidBody, RigidBodyHandle};
+use nphysics3d::world::World;
+use threadpool::ThreadPool;
+
+use assets::Assets;
+use asteroids::{AsteroidBeltRenderer, AsteroidBeltSpec};
+use atmosphere::{AtmosphereRenderer, AtmosphereSpec};
+use clouds::{CloudRenderer, CloudSpec};
+use edit::{Brush, BrushKind, EditedField};
+use errors::{ChainErr, Result};
+use flora::{Flora, FloraSpec, PLANT_PREFABS};
+use game::{ControlsSpec, Player};
+use gfx::{perspective_matrix, Camera, ChunkScheduler, Frustum, LevelOfDetail, LodSpec,
+          LodStats, Window};
+use gfx::mesh_cache::MeshCache;
+use gfx::octree_debug::{OctreeDebugMode, OctreeDebugRenderer};
+use gfx::lens_flare::{LensFlareRenderer, LensFlareSpec};
+use gfx::lights::LightRenderer;
+use gfx::render_graph::{screen_direction, screen_sampler, LightShafts, Pass, RenderGraph,
+                        QUAD_VERTEX_SHADER, SPHERE_SHADER_LIBRARY};
+use gfx::splatting::{Splatting, TerrainTextures};
+use gfx::shadow_map::{ShadowMap, ShadowSpec};
+use math::{CpuScalar, Matrix4f, Vec3f, ScalarField3};
+use ocean::OceanRenderer;
+use planet::{PhysicsSpec, PlanetSpec};
+use prefab::{PrefabInstances, PrefabRenderer};
+use structures::{upload_structures, Structure, StructureSpec, STRUCTURE_PREFABS};
+use trace;
+use utils::read_utf8_file;
+
+/// A planet, or any other field, placed in the solar system. The main planet
+/// is the body at the origin, others are listed in the `[body.<name>]` tables
+/// of the config.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BodySpec {
+    pub name: String,
+    /// Defaults to the world seed plus the body's index, the main planet's
+    /// being 0.
+    pub seed: Option<u32>,
+    /// Where the centre of the body's field is, or that of its orbit.
+    pub position: Vec3f,
+    pub orbit: Option<OrbitSpec>,
+    pub planet: PlanetSpec,
+}
+
+impl BodySpec {
+    /// The main planet, generated from the `[planet]` config.
+    pub fn main_planet(planet: &PlanetSpec) -> Self {
+        BodySpec {
+            name: MAIN_PLANET_NAME.to_owned(),
+            seed: None,
+            position: Vec3f::new(0.0, 0.0, 0.0),
+            orbit: None,
+            planet: planet.clone(),
+        }
+    }
+
+    /// A moon of the main planet, listed in a `[moon.<name>]` table.
+    pub fn moon(name: &str, planet: &PlanetSpec) -> Self {
+        BodySpec {
+            name: name.to_owned(),
+            seed: None,
+            position: Vec3f::new(0.0, 0.0, 0.0),
+            orbit: Some(OrbitSpec::moon_of(planet)),
+            planet: PlanetSpec::moon(planet),
+        }
+    }
+}
+
+/// Haze the surfaces of the bodies fade into with their distance from the
+/// camera, lit by the sun.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FogSpec {
+    /// How far from the camera, in world units, the surfaces are all but
+    /// hidden. No fog if 0.
+    pub distance: f32,
+    pub color: Vec3f,
+}
+
+impl Default for FogSpec {
+    fn default() -> Self {
+        FogSpec {
+            distance: 0.0,
+            color: Vec3f::new(0.6, 0.7, 0.8),
+        }
+    }
+}
+
+/// A Keplerian orbit, an ellipse with its centre of mass at a focus, which
+/// is the centre of the orbit given by the body's `position`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrbitSpec {
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    /// The tilt from the xz plane, in degrees.
+    pub inclination: f32,
+    /// In seconds per revolution.
+    pub period: f32,
+    /// The mean anomaly at time 0, in degrees.
+    pub phase: f32,
+}
+
+impl OrbitSpec {
+    /// A close orbit, slightly eccentric and inclined, around `planet`.
+    pub fn moon_of(planet: &PlanetSpec) -> Self {
+        OrbitSpec {
+            semi_major_axis: planet.base_radius * 1.8,
+            eccentricity: 0.05,
+            inclination: 5.0,
+            period: 600.0,
+            phase: 0.0,
+        }
+    }
+
+    /// The offset from the centre of the orbit `time` seconds in.
+    pub fn offset_at(&self, time: f32) -> Vec3f {
+        let eccentricity = self.eccentricity;
+        let mean_anomaly = (2.0 * consts::PI * time / self.period + self.phase.to_radians()) %
+            (2.0 * consts::PI);
+        // Kepler's equation, M = E - e sin(E), solved for the eccentric
+        // anomaly E with Newton's method. Starting from pi converges for very
+        // eccentric orbits too.
+        let mut anomaly = if eccentricity < 0.8 {
+            mean_anomaly
+        } else {
+            consts::PI
+        };
+        for _ in 0..KEPLER_ITERATIONS {
+            anomaly -= (anomaly - eccentricity * anomaly.sin() - mean_anomaly) /
+                (1.0 - eccentricity * anomaly.cos());
+        }
+
+        let x = self.semi_major_axis * (anomaly.cos() - eccentricity);
+        let z = self.semi_major_axis * (1.0 - eccentricity * eccentricity).sqrt() * anomaly.sin();
+        let inclination = self.inclination.to_radians();
+        Vec3f::new(x, z * inclination.sin(), z * inclination.cos())
+    }
+}
+
+/// How the terrain is shaded, lit or colored to debug its meshes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShadingMode {
+    Lit,
+    /// Lit, with the edges of the triangles drawn over.
+    Wireframe,
+    Normals,
+    /// Each chunk colored by the level of the octree it's on.
+    LodLevel,
+    /// Each chunk in a color of its own.
+    ChunkId,
+}
+
+impl ShadingMode {
+    /// The mode after this one, cycling back to `Lit`.
+    pub fn next(self) -> Self {
+        match self {
+            ShadingMode::Lit => ShadingMode::Wireframe,
+            ShadingMode::Wireframe => ShadingMode::Normals,
+            ShadingMode::Normals => ShadingMode::LodLevel,
+            ShadingMode::LodLevel => ShadingMode::ChunkId,
+            ShadingMode::ChunkId => ShadingMode::Lit,
+        }
+    }
+}
+
+/// Renders the bodies of a solar system, each with its own level of detail
+/// and chunks in the physics world, and the player moving between them.
+/// Gravity pulls towards the nearest body. Orbiting bodies move with the
+/// physics steps.
+pub struct SolarSystemRenderer<'a, 'b, Field: ScalarField3> {
+    bodies: Vec<BodyRenderer<'a, 'b, Field>>,
+    // Around the main planet.
+    asteroid_belt: Option<AsteroidBeltRenderer<'a>>,
+    // Draws the plants of the chunks, if any grow.
+    flora: Option<PrefabRenderer<'b>>,
+    structures: Option<PrefabRenderer<'b>>,
+    octree_debug: OctreeDebugRenderer<'b>,
+    octree_debug_mode: OctreeDebugMode,
+    shading_mode: ShadingMode,
+    // The passes the bodies are drawn in, their surfaces lit by the lighting
+    // program for each body.
+    render_graph: RenderGraph<'b>,
+    lighting_program: Program,
+    // Lights besides the sun, added to the scene after it's lit.
+    lights: LightRenderer<'b>,
+    // Of the sun, added to the frame after the post pass.
+    lens_flare: Option<LensFlareRenderer<'b>>,
+    // Splatted over the terrain of every body.
+    terrain_textures: TerrainTextures,
+    // Rendered again for each body, from its chunks in view.
+    shadow_map: ShadowMap<'b>,
+    // The bodies share the workers and the memory budget of their chunks.
+    chunk_scheduler: ChunkScheduler,
+    memory_budget: Option<usize>,
+    // Seconds simulated so far.
+    time: f32,
+    physics_world: World<CpuScalar>,
+    draw_parameters: DrawParameters<'b>,
+    program: Program,
+    gravity: f32,
+    fog: FogSpec,
+    pub player: Player,
+}
+
+struct BodyRenderer<'a, 'b, Field: ScalarField3> {
+    name: String,
+    position: Vec3f,
+    centre: Vec3f,
+    orbit: Option<OrbitSpec>,
+    lod: LevelOfDetail<'a, EditedField<Field>>,
+    physics_chunks: HashMap<usize, RigidBodyHandle<CpuScalar>>,
+    // The static rigid bodies of the structures, in the same order.
+    structures: Vec<Structure>,
+    structure_bodies: Vec<RigidBodyHandle<CpuScalar>>,
+    // The structures as drawn, if there are any.
+    structure_instances: Option<PrefabInstances>,
+    ocean: Option<OceanRenderer<'b>>,
+    atmosphere: Option<AtmosphereRenderer<'b>>,
+    clouds: Option<CloudRenderer<'b>>,
+    splatting: Splatting,
+    scalar_field: Arc<EditedField<Field>>,
+}
+
+impl<'a, 'b, Field> BodyRenderer<'a, 'b, Field>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    /// `position` relative to the centre of the body.
+    fn to_local(&self, position: &Point3<CpuScalar>) -> Point3<CpuScalar> {
+        Point3::new(
+            position[0] - self.position[0],
+            position[1] - self.position[1],
+            position[2] - self.position[2],
+        )
+    }
+
+    /// Moves an orbiting body, with its chunks in the physics world, to where
+    /// it is `time` seconds in.
+    fn update_orbit(&mut self, time: f32) {
+        if let Some(ref orbit) = self.orbit {
+            self.position = self.centre + orbit.offset_at(time);
+            for handle in self.physics_chunks.values() {
+                handle.borrow_mut().set_translation(*self.position);
+            }
+            for (structure, handle) in self.structures.iter().zip(self.structure_bodies.iter()) {
+                handle.borrow_mut().set_transformation(self.structure_isometry(structure));
+            }
+        }
+    }
+
+    /// Where `structure` is in the physics world.
+    fn structure_isometry(&self, structure: &Structure) -> Isometry3<CpuScalar> {
+        Isometry3::new(*self.position, Vector3::zero()) * structure.isometry()
+    }
+
+    fn model_matrix(&self) -> Matrix4f {
+        Matrix4f::from(Isometry3::new(*self.position, Vector3::zero()).to_homogeneous())
+    }
+}
+
+impl<'a, 'b, Field> SolarSystemRenderer<'a, 'b, Field>
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    /// The player starts above the first body, the main planet, which the
+    /// asteroid belt is around. Plants grow on and air surrounds the bodies
+    /// which don't orbit, moons are airless, but structures are placed on
+    /// every body.
+    pub fn new(
+        bodies: Vec<(BodySpec, Field)>,
+        window: &Window,
+        assets: &Assets,
+        thread_pool: &'a ThreadPool,
+        asteroid_belt: &AsteroidBeltSpec,
+        atmosphere: &AtmosphereSpec,
+        clouds: &CloudSpec,
+        lens_flare: &LensFlareSpec,
+        shadows: &ShadowSpec,
+        flora: &FloraSpec,
+        structures: &StructureSpec,
+        lod_spec: &LodSpec,
+        controls: &ControlsSpec,
+        physics: &PhysicsSpec,
+    ) -> Result<Self> {
+        assert!(!bodies.is_empty());
+        let vertex_shader = try!(read_utf8_file(try!(assets.resolve(VERTEX_SHADER))));
+        let fragment_shader = try!(read_utf8_file(try!(assets.resolve(FRAGMENT_SHADER))));
+        let program =
+            try!(
+                glium::Program::from_source(window.facade(), &vertex_shader, &fragment_shader, None)
+                    .chain_err(|| "Could not compile the shaders.")
+            );
+
+        let start = bodies[0].0.position;
+        let asteroid_belt = if asteroid_belt.num_asteroids > 0 {
+            let seed = bodies[0].0.seed.unwrap_or(0);
+            Some(try!(AsteroidBeltRenderer::new(
+                window,
+                assets,
+                thread_pool,
+                seed,
+                asteroid_belt,
+            )))
+        } else {
+            None
+        };
+        let flora_renderer = if flora.density > 0.0 {
+            Some(try!(PrefabRenderer::new(window, assets, &PLANT_PREFABS)))
+        } else {
+            None
+        };
+        let structure_renderer = if structures.num_structures > 0 {
+            Some(try!(PrefabRenderer::new(window, assets, &STRUCTURE_PREFABS)))
+        } else {
+            None
+        };
+        let lens_flare = if lens_flare.enabled() {
+            Some(try!(LensFlareRenderer::new(window, assets, lens_flare)))
+        } else {
+            None
+        };
+        let octree_debug = try!(OctreeDebugRenderer::new(window, assets));
+        let mut physics_world = World::new();
+        let mut body_renderers = vec![];
+        for (spec, scalar_field) in bodies.into_iter() {
+            // Chunks below the ocean are generated like any other, the sea
+            // floor shows through the translucent water.
+            let ocean = match spec.planet.sea_radius() {
+                Some(radius) => Some(try!(OceanRenderer::new(window, assets, radius))),
+                None => None,
+            };
+            let body_atmosphere = if spec.orbit.is_none() && atmosphere.height > 0.0 {
+                Some(try!(AtmosphereRenderer::new(window, assets, &spec.planet, atmosphere)))
+            } else {
+                None
+            };
+            let body_clouds = if spec.orbit.is_none() && clouds.enabled() {
+                Some(try!(CloudRenderer::new(window, assets, &spec.planet, clouds)))
+            } else {
+                None
+            };
+            let scalar_field = Arc::new(EditedField::new(scalar_field));
+            let body_flora = match spec.orbit {
+                Some(_) => None,
+                None => Some(Flora::new(flora, spec.seed.unwrap_or(0), &spec.planet)),
+            };
+            // Grass grows where the plants do.
+            let splatting = match spec.orbit {
+                Some(_) => Splatting::new(&spec.planet, None),
+                None => Splatting::new(&spec.planet, Some(flora)),
+            };
+            let position = match spec.orbit {
+                Some(ref orbit) => spec.position + orbit.offset_at(0.0),
+                None => spec.position,
+            };
+            let body_structures = match structure_renderer {
+                Some(_) => {
+                    let seed = spec.seed.unwrap_or(0);
+                    Structure::place(scalar_field.as_ref(), seed, &spec.planet, structures)
+                }
+                None => vec![],
+            };
+            let mut body = BodyRenderer {
+                name: spec.name,
+                position: position,
+                centre: spec.position,
+                orbit: spec.orbit,
+                lod: LevelOfDetail::new(
+                    scalar_field.clone(),
+                    body_flora,
+                    thread_pool,
+                    lod_spec,
+                    10,
+                ),
+                physics_chunks: HashMap::new(),
+                structure_instances: if body_structures.is_empty() {
+                    None
+                } else {
+                    Some(try!(upload_structures(window, &body_structures)))
+                },
+                structures: body_structures,
+                structure_bodies: vec![],
+                ocean: ocean,
+                atmosphere: body_atmosphere,
+                clouds: body_clouds,
+                splatting: splatting,
+                scalar_field: scalar_field,
+            };
+            if let Some(ref structure_renderer) = structure_renderer {
+                for structure in body.structures.iter() {
+                    let shape = structure_renderer.shape(structure.kind as usize);
+                    let mut rigid_body = RigidBody::new(shape, None, 0.1, 1.0);
+                    rigid_body.set_transformation(body.structure_isometry(structure));
+                    body.structure_bodies.push(physics_world.add_rigid_body(rigid_body));
+                }
+            }
+            body_renderers.push(body);
+        }
+
+        let params = glium::DrawParameters {
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling: glium::draw_parameters::BackfaceCullingMode::CullClockwise,
+            ..Default::default()
+        };
+
+        let start = Point3::new(start[0], start[1], start[2]);
+        let player = Player::spawn(
+            &mut physics_world,
+            &(start + Vector3::new(1.0, 1.0, 1.0) * 0.5e4),
+            &start,
+            &Vector3::y(),
+            controls,
+        );
+
+        Ok(SolarSystemRenderer {
+            bodies: body_renderers,
+            asteroid_belt: asteroid_belt,
+            flora: flora_renderer,
+            structures: structure_renderer,
+            octree_debug: octree_debug,
+            octree_debug_mode: OctreeDebugMode::Off,
+            shading_mode: ShadingMode::Lit,
+            render_graph: try!(RenderGraph::new(window, assets)),
+            lighting_program: try!(window.program_with_library(
+                try!(assets.resolve(QUAD_VERTEX_SHADER)),
+                try!(assets.resolve(SPHERE_SHADER_LIBRARY)),
+                try!(assets.resolve(LIGHTING_FRAGMENT_SHADER)),
+            )),
+            lights: try!(LightRenderer::new(window, assets)),
+            lens_flare: lens_flare,
+            terrain_textures: try!(TerrainTextures::new(window, assets)),
+            shadow_map: try!(ShadowMap::new(window, assets, shadows)),
+            chunk_scheduler: ChunkScheduler::new(thread_pool),
+            memory_budget: lod_spec.memory_budget(),
+            time: 0.0,
+            physics_world: physics_world,
+            draw_parameters: params,
+            program: program,
+            gravity: physics.gravity,
+            fog: FogSpec::default(),
+            player: player,
+        })
+    }
+
+    pub fn render(
+        &mut self,
+        window: &Window,
+        frame: &mut Frame,
+        camera: &mut Camera,
+        time_query: Option<&TimeElapsedQuery>,
+    ) -> Result<()> {
+        let player_position = self.player.observer.translation().to_point();
+        let (nearest, _) = self.nearest_body(&player_position);
+        let SolarSystemRenderer {
+            ref program,
+            ref draw_parameters,
+            ref mut bodies,
+            ref mut asteroid_belt,
+            ref flora,
+            structures: ref structure_renderer,
+            ref mut octree_debug,
+            octree_debug_mode,
+            shading_mode,
+            ref mut render_graph,
+            ref lighting_program,
+            ref lights,
+            ref lens_flare,
+            ref terrain_textures,
+            ref mut shadow_map,
+            ref chunk_scheduler,
+            memory_budget,
+            ref mut physics_world,
+            ref mut player,
+            gravity,
+            fog,
+            time,
+            ..
+        } = *self;
+
+        let down = bodies[nearest].position - Vec3f::from(player_position.to_vector());
+        physics_world.set_gravity(down.normalize() * gravity);
+        player.update_position();
+
+        let view = player.view_matrix();
+        let light = Vec3f::new(-40.0f32, 0.0, -4000.0);
+        let perspective = perspective_matrix(frame);
+        let eye = Vec3f::from(camera.position().translation());
+        let draw_parameters = DrawParameters {
+            time_elapsed_query: time_query,
+            ..draw_parameters.clone()
+        };
+
+        let moonlights: Vec<_> = (0..bodies.len()).map(|index| moonlight(bodies, index)).collect();
+
+        // Each body may take what of the memory budget the others don't, and
+        // the chunks they request are granted across all of them, nearest
+        // first for their size.
+        let loaded_bytes: Vec<usize> =
+            bodies.iter().map(|body| body.lod.stats().loaded_bytes).collect();
+        let total_bytes: usize = loaded_bytes.iter().sum();
+        let mut requests = Vec::with_capacity(bodies.len());
+        for (body, &body_bytes) in bodies.iter_mut().zip(loaded_bytes.iter()) {
+            let budget = memory_budget.map(|budget| {
+                budget.saturating_sub(total_bytes - body_bytes)
+            });
+            let body_requests = try!(body.lod.prepare(window, &(eye - body.position), budget));
+            requests.push((body.lod.num_in_flight(), body_requests));
+        }
+        let granted = chunk_scheduler.schedule(requests);
+
+        let _span = trace::span("draw_planet");
+        try!(render_graph.begin(window, frame));
+        let targets = render_graph.targets();
+        let bodies_granted = bodies.iter_mut().zip(moonlights.iter()).zip(granted.into_iter());
+        for (index, ((body, &(moon, moonlight)), granted)) in bodies_granted.enumerate() {
+            let model = body.model_matrix();
+            let BodyRenderer {
+                position,
+                ref mut lod,
+                ref mut physics_chunks,
+                ref structure_instances,
+                ref ocean,
+                splatting,
+                ..
+            } = *body;
+            // The lighting shader lights positions in the body's frame, the
+            // bodies numbered from 1 in the g-buffer.
+            let local_light = light - position;
+            let local_eye = eye - position;
+            let body_index = index as f32 + 1.0;
+            let sea_radius = ocean.as_ref().map_or(0.0, |ocean| ocean.radius());
+
+            // The chunks are in the body's frame, so is the frustum.
+            let frustum = Frustum::from_matrix(&(Matrix4f::from(&perspective) * view * model));
+            let screen_chunks = lod.finish(granted);
+            // Those out of view cast shadows too.
+            if shadow_map.num_cascades() > 0 {
+                try!(shadow_map.render(
+                    window,
+                    &screen_chunks,
+                    perspective,
+                    &(view * model),
+                    &sun_direction(&light, &position),
+                ));
+            }
+
+            let mut gbuffer = try!(render_graph.target(window, Pass::GBuffer));
+            let mut remove_set: HashSet<usize> = physics_chunks.keys().map(|x| *x).collect();
+            for chunk in screen_chunks.iter() {
+                if !frustum.intersects_aabb(&chunk.aabb) {
+                    // Out of view, but the player may be standing on it, so a
+                    // chunk already in the physics world stays there. It's
+                    // removed once the octree stops drawing it.
+                    remove_set.remove(&chunk.uid);
+                    continue;
+                }
+                let uniforms =
+                    uniform! {
+                    perspective: perspective,
+                    model: &model,
+                    view: &view,
+                    u_camera: &local_eye,
+                    u_body: body_index,
+                    u_materials: terrain_textures.sampled(),
+                    u_material_normals: terrain_textures.sampled_normals(),
+                    u_base_radius: splatting.base_radius,
+                    u_grass: if splatting.grows_grass { 1.0f32 } else { 0.0 },
+                    u_grass_line: splatting.grass_line,
+                    u_max_grass_slope: splatting.max_grass_slope,
+                    u_shading: shading_mode as i32,
+                    u_level: chunk.level as f32,
+                    u_chunk: chunk.uid as f32,
+                };
+                try!(
+                    gbuffer
+                        .draw(
+                            &chunk.vertex_buffer,
+                            &chunk.index_buffer,
+                            program,
+                            &uniforms,
+                            &draw_parameters,
+                        )
+                        .chain_err(|| "Could not render frame.")
+                );
+                if let (Some(flora), Some(chunk_flora)) = (flora.as_ref(), chunk.flora.as_ref()) {
+                    try!(flora.render(
+                        &mut gbuffer,
+                        chunk_flora,
+                        perspective,
+                        &view,
+                        &model,
+                        &local_eye,
+                        body_index,
+                        time_query,
+                    ));
+                }
+
+                if !physics_chunks.contains_key(&chunk.uid) {
+                    let mut rigid_body = RigidBody::new(chunk.tri_mesh.clone(), None, 0.1, 1.0);
+                    rigid_body.set_translation(*position);
+                    let handle = physics_world.add_rigid_body(rigid_body);
+                    physics_chunks.insert(chunk.uid, handle);
+                }
+                remove_set.remove(&chunk.uid);
+            }
+            for uid in remove_set.into_iter() {
+                physics_world.remove_rigid_body(&physics_chunks[&uid]);
+                physics_chunks.remove(&uid);
+            }
+
+            if let (Some(renderer), Some(instances)) =
+                (structure_renderer.as_ref(), structure_instances.as_ref())
+            {
+                try!(renderer.render(
+                    &mut gbuffer,
+                    instances,
+                    perspective,
+                    &view,
+                    &model,
+                    &local_eye,
+                    body_index,
+                    time_query,
+                ));
+            }
+
+            // Only this body's surfaces are lit, those of the bodies drawn
+            // before it which it hides being lit again as its own.
+            let uniforms =
+                uniform! {
+                u_albedo: screen_sampler(&targets.albedo),
+                u_normal: screen_sampler(&targets.normal),
+                u_position: screen_sampler(&targets.position),
+                u_body: body_index,
+                u_camera: &local_eye,
+                u_light: &local_light,
+                u_moon: &moon,
+                u_moonlight: moonlight,
+                u_sea_radius: sea_radius,
+                u_fog_distance: fog.distance,
+                u_fog_color: &fog.color,
+                u_shadow_map: shadow_map
+                    .depth_texture()
+                    .sampled()
+                    .minify_filter(MinifySamplerFilter::Nearest)
+                    .magnify_filter(MagnifySamplerFilter::Nearest),
+                u_num_cascades: shadow_map.num_cascades() as i32,
+                u_shadow_matrix0: shadow_map.light_matrix(0),
+                u_shadow_matrix1: shadow_map.light_matrix(1),
+                u_shadow_matrix2: shadow_map.light_matrix(2),
+                u_shadow_matrix3: shadow_map.light_matrix(3),
+            };
+            let mut lighting = try!(render_graph.target(window, Pass::Lighting));
+            try!(render_graph.draw_quad(&mut lighting, lighting_program, &uniforms, time_query));
+        }
+
+        if let Some(ref mut asteroid_belt) = *asteroid_belt {
+            // Lit as a body of their own, in the frame of the main planet,
+            // without shadows.
+            let body_index = bodies.len() as f32 + 1.0;
+            {
+                let mut gbuffer = try!(render_graph.target(window, Pass::GBuffer));
+                try!(asteroid_belt.render(
+                    window,
+                    &mut gbuffer,
+                    program,
+                    &draw_parameters,
+                    terrain_textures,
+                    perspective,
+                    &view,
+                    &bodies[0].position,
+                    &eye,
+                    body_index,
+                ));
+            }
+            let no_moon = Vec3f::zero();
+            let planet_eye = eye - bodies[0].position;
+            let planet_light = light - bodies[0].position;
+            let uniforms =
+                uniform! {
+                u_albedo: screen_sampler(&targets.albedo),
+                u_normal: screen_sampler(&targets.normal),
+                u_position: screen_sampler(&targets.position),
+                u_body: body_index,
+                u_camera: &planet_eye,
+                u_light: &planet_light,
+                u_moon: &no_moon,
+                u_moonlight: 0.0f32,
+                u_sea_radius: 0.0f32,
+                u_shadow_map: shadow_map
+                    .depth_texture()
+                    .sampled()
+                    .minify_filter(MinifySamplerFilter::Nearest)
+                    .magnify_filter(MagnifySamplerFilter::Nearest),
+                u_num_cascades: 0i32,
+            };
+            let mut lighting = try!(render_graph.target(window, Pass::Lighting));
+            try!(render_graph.draw_quad(&mut lighting, lighting_program, &uniforms, time_query));
+        }
+
+        let player_lights: Vec<_> = player.torch().into_iter().collect();
+        try!(lights.render(
+            window,
+            render_graph,
+            &player_lights,
+            perspective,
+            &view,
+            &eye,
+            time_query,
+        ));
+
+        // The water refracts and reflects the lit scene, so it goes over all
+        // the terrain, and the air scatters light over everything behind it,
+        // the water included.
+        let mut transparent = try!(render_graph.target(window, Pass::Transparent));
+        if bodies.iter().any(|body| body.ocean.is_some()) {
+            let scene = try!(render_graph.copy_scene(window));
+            for body in bodies.iter() {
+                if let Some(ref ocean) = body.ocean {
+                    try!(ocean.render(
+                        &mut transparent,
+                        scene,
+                        perspective,
+                        &view,
+                        &body.model_matrix(),
+                        &(Vec3f::from(player.observer.translation()) - body.position),
+                        &(light - body.position),
+                        time,
+                        time_query,
+                    ));
+                }
+            }
+        }
+        for body in bodies.iter() {
+            if let Some(ref clouds) = body.clouds {
+                try!(clouds.render(
+                    render_graph,
+                    &mut transparent,
+                    &render_graph.targets().position,
+                    perspective,
+                    &view,
+                    &(eye - body.position),
+                    &sun_direction(&light, &body.position),
+                    time,
+                    time_query,
+                ));
+            }
+        }
+        for body in bodies.iter() {
+            if let Some(ref atmosphere) = body.atmosphere {
+                let sun_direction = sun_direction(&light, &body.position);
+                try!(atmosphere.render(
+                    &mut transparent,
+                    perspective,
+                    &view,
+                    &body.model_matrix(),
+                    &(Vec3f::from(player.observer.translation()) - body.position),
+                    &sun_direction,
+                    time_query,
+                ));
+            }
+        }
+        let view_projection = Matrix4f::from(&perspective) * view;
+        let nearest_sun = sun_direction(&light, &bodies[nearest].position);
+        let shafts = {
+            let up = (eye - bodies[nearest].position).normalize();
+            LightShafts::new(
+                &view_projection,
+                &nearest_sun,
+                shaft_strength(up.dot(&nearest_sun)),
+            )
+        };
+        try!(render_graph.post(window, frame, shafts, time_query));
+        if let (Some(lens_flare), Some(sun)) =
+            (lens_flare.as_ref(), screen_direction(&view_projection, &nearest_sun))
+        {
+            try!(lens_flare.render(window, render_graph, frame, sun, time_query));
+        }
+
+        if octree_debug_mode != OctreeDebugMode::Off {
+            for body in bodies.iter() {
+                let boxes = body.lod.octree_boxes();
+                try!(octree_debug.render(
+                    window,
+                    frame,
+                    &boxes,
+                    octree_debug_mode,
+                    perspective,
+                    &view,
+                    &body.model_matrix(),
+                    time_query,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn update_physics(&mut self, delta_time: f32) {
+        let _span = trace::span("physics_step");
+        self.time += delta_time;
+        for body in self.bodies.iter_mut() {
+            body.update_orbit(self.time);
+        }
+        self.physics_world.step(delta_time);
+    }
+
+    /// The index of the body whose surface is nearest to `position` and the
+    /// value of its field there, which approximates the height above the
+    /// surface for distance-like fields.
+    pub fn nearest_body(&self, position: &Point3<CpuScalar>) -> (usize, CpuScalar) {
+        let mut nearest = (0, f32::INFINITY);
+        for (index, body) in self.bodies.iter().enumerate() {
+            let altitude = body.scalar_field.value_at(&body.to_local(position));
+            if altitude < nearest.1 {
+                nearest = (index, altitude);
+            }
+        }
+        nearest
+    }
+
+    pub fn body_name(&self, index: usize) -> &str {
+        &self.bodies[index].name
+    }
+
+    /// Digs or builds a sphere where the player is looking, if the surface
+    /// of a body is within reach. Returns the index of the body which was
+    /// edited and the brush, relative to the body's centre, it was edited
+    /// with.
+    pub fn edit(&mut self, kind: BrushKind, radius: CpuScalar) -> Option<(usize, Brush)> {
+        let origin = self.player.observer.translation();
+        let direction = self.player.view_direction();
+        // Sphere tracing, the fields are close enough to distance functions.
+        let mut distance = 0.0;
+        while distance < EDIT_REACH {
+            let position = (origin + direction * distance).to_point();
+            let (index, value) = self.nearest_body(&position);
+            if value < EDIT_HIT_TOLERANCE {
+                let brush = Brush {
+                    kind: kind,
+                    centre: self.bodies[index].to_local(&position),
+                    radius: radius,
+                };
+                self.apply_brush(index, brush);
+                return Some((index, brush));
+            }
+            distance += value.max(EDIT_HIT_TOLERANCE);
+        }
+        None
+    }
+
+    /// Meshes the chunks of a body through `mesh_cache`.
+    pub fn cache_meshes(&mut self, index: usize, mesh_cache: MeshCache) {
+        self.bodies[index].lod.cache_meshes(mesh_cache);
+    }
+
+    /// Replays edits made to a body earlier, e.g. loaded from disk.
+    pub fn restore_edits(&mut self, index: usize, brushes: &[Brush]) {
+        for brush in brushes.iter() {
+            self.apply_brush(index, *brush);
+        }
+    }
+
+    fn apply_brush(&mut self, index: usize, brush: Brush) {
+        let body = &mut self.bodies[index];
+        body.scalar_field.add_brush(brush);
+        let (min, max) = brush.bounds();
+        body.lod.invalidate(&min, &max);
+    }
+
+    pub fn set_max_level(&mut self, max_level: u8) {
+        for body in self.bodies.iter_mut() {
+            body.lod.set_max_level(max_level);
+        }
+    }
+
+    pub fn set_screen_errors(&mut self, max_screen_error: f32, merge_screen_error: f32) {
+        for body in self.bodies.iter_mut() {
+            body.lod.set_screen_errors(max_screen_error, merge_screen_error);
+        }
+    }
+
+    pub fn set_min_dwell(&mut self, min_dwell_ms: u32) {
+        for body in self.bodies.iter_mut() {
+            body.lod.set_min_dwell(min_dwell_ms);
+        }
+    }
+
+    /// Draws the octree nodes of each body as wireframe boxes colored by
+    /// `mode`, over everything else.
+    pub fn set_shading_mode(&mut self, mode: ShadingMode) {
+        self.shading_mode = mode;
+    }
+
+    pub fn set_octree_debug_mode(&mut self, mode: OctreeDebugMode) {
+        self.octree_debug_mode = mode;
+    }
+
+    /// Supersamples the scene with `samples` per pixel, see
+    /// `RenderGraph::set_supersampling`.
+    pub fn set_supersampling(&mut self, samples: u32) -> Result<()> {
+        self.render_graph.set_supersampling(samples)
+    }
+
+    /// Feeds the detail controllers of the bodies with the time the last
+    /// frame took to render, in milliseconds.
+    pub fn report_frame_time(&mut self, frame_ms: f32) {
+        for body in self.bodies.iter_mut() {
+            body.lod.report_frame_time(frame_ms);
+        }
+    }
+
+    pub fn set_target_frame_time(&mut self, target_frame_ms: f32, max_detail_reduction: f32) {
+        for body in self.bodies.iter_mut() {
+            body.lod.set_target_frame_time(target_frame_ms, max_detail_reduction);
+        }
+    }
+
+    pub fn set_physics_spec(&mut self, physics: &PhysicsSpec) {
+        self.gravity = physics.gravity;
+    }
+
+    pub fn set_fog(&mut self, fog: &FogSpec) {
+        self.fog = *fog;
+    }
+
+    /// The chunks and pipeline counters of all the bodies, added up, see
+    /// `LodStats::merge`.
+    pub fn lod_stats(&self) -> LodStats {
+        let mut stats = LodStats::default();
+        for body in self.bodies.iter() {
+            stats.merge(&body.lod.stats());
+        }
+        stats
+    }
+
+    pub fn num_rigid_bodies(&self) -> usize {
+        self.physics_world.rigid_bodies().count()
+    }
+}
+
+/// The direction towards the sun from the body at `position`, lit from
+/// `light`. The shaders light the surfaces facing away from the light, so
+/// it's this way that their shadows and the air are lit from too.
+fn sun_direction(light: &Vec3f, position: &Vec3f) -> Vec3f {
+    Vec3f::from((*position - *light).normalize())
+}
+
+/// How strongly the sun shines in shafts when it's at `elevation`, the
+/// cosine of its angle from straight above: most when it's low over the
+/// horizon, where the mountains and clouds are in front of it, and not once
+/// it's set.
+fn shaft_strength(elevation: f32) -> f32 {
+    let smoothstep = |edge0: f32, edge1: f32, x: f32| {
+        let t = ((x - edge0) / (edge1 - edge0)).max(0.0).min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    };
+    SHAFT_STRENGTH * (1.0 - smoothstep(0.2, 0.6, elevation)) *
+        smoothstep(-0.1, 0.05, elevation)
+}
+
+/// The position, relative to the body at `index`, of the orbiting body
+/// nearest to it and how brightly the body is lit by it at night.
+fn moonlight<'a, 'b, Field>(bodies: &[BodyRenderer<'a, 'b, Field>], index: usize) -> (Vec3f, f32)
+where
+    Field: 'static + ScalarField3 + Send + Sync,
+{
+    let position = bodies[index].position;
+    bodies
+        .iter()
+        .enumerate()
+        .filter(|&(other, body)| other != index && body.orbit.is_some())
+        .map(|(_, body)| body.position - position)
+        .min_by(|a, b| a.norm().partial_cmp(&b.norm()).unwrap())
+        .map_or((Vec3f::new(0.0, 0.0, 0.0), 0.0), |moon| (moon, MOONLIGHT))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Norm;
+
+    use super::{shaft_strength, OrbitSpec};
+
+    #[test]
+    fn test_kepler_orbit() {
+        let orbit = OrbitSpec {
+            semi_major_axis: 1000.0,
+            eccentricity: 0.5,
+            inclination: 30.0,
+            period: 60.0,
+            phase: 0.0,
+        };
+        // Periapsis at time 0, apoapsis half a period later, and back again.
+        assert!((orbit.offset_at(0.0).norm() - 500.0).abs() < 1e-2);
+        assert!((orbit.offset_at(30.0).norm() - 1500.0).abs() < 1e-2);
+        assert!((orbit.offset_at(60.0) - orbit.offset_at(0.0)).norm() < 1e-1);
+        // Equal areas in equal times: the body is faster near periapsis.
+        let near = (orbit.offset_at(1.0) - orbit.offset_at(0.0)).norm();
+        let far = (orbit.offset_at(31.0) - orbit.offset_at(30.0)).norm();
+        assert!(near > far * 2.0);
+        // The orbit is tilted out of the xz plane.
+        let quarter = orbit.offset_at(15.0);
+        assert!((quarter[1] / quarter[2] - 30f32.to_radians().tan()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_shafts_are_strongest_with_the_sun_low() {
+        assert_eq!(0.0, shaft_strength(1.0));
+        assert_eq!(0.0, shaft_strength(-0.5));
+        assert!(shaft_strength(0.1) > 0.0);
+        assert!(shaft_strength(0.1) > shaft_strength(0.4));
+    }
+}
+
+pub const MAIN_PLANET_NAME: &'static str = "planet";
+
+const VERTEX_SHADER: &'static str = "shaders/planet.vert";
+const FRAGMENT_SHADER: &'static str = "shaders/planet.frag";
+const LIGHTING_FRAGMENT_SHADER: &'static str = "shaders/lighting.frag";
+
+// How far from the player, along the view direction, the terrain can be edited.
+const EDIT_REACH: f32 = 64.0;
+const EDIT_HIT_TOLERANCE: f32 = 0.05;
+// Brightness of the moonlit night side, relative to full sunlight.
+const MOONLIGHT: f32 = 0.12;
+const KEPLER_ITERATIONS: usize = 8;
+// How much of the sky's light around the sun streams into the scene in
+// shafts when the sun is low.
+const SHAFT_STRENGTH: f32 = 0.6;
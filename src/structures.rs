@@ -0,0 +1,238 @@
+use std::f32::consts::PI;
+
+use nalgebra::{Cross, Isometry3, Norm, Point3, Vector3};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use errors::Result;
+use gfx::Window;
+use math::{random_direction, CpuScalar, Material, ScalarField3, Vec3f};
+use planet::PlanetSpec;
+use prefab::{instance_transform, placement, tangent_frame, Prefab, PrefabInstances};
+use trace;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructureSpec {
+    /// Structures placed on each body, fewer if there's not enough flat
+    /// ground for them. 0 for none.
+    pub num_structures: usize,
+    /// The steepest ground, in degrees, a structure is placed on.
+    pub max_slope: f32,
+    /// The least distance between two structures on a body.
+    pub min_spacing: f32,
+}
+
+impl Default for StructureSpec {
+    fn default() -> Self {
+        StructureSpec {
+            num_structures: 0,
+            max_slope: 8.0,
+            min_spacing: 200.0,
+        }
+    }
+}
+
+/// The values are indices into `STRUCTURE_PREFABS` and `FOOTPRINTS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StructureKind {
+    Ruin = 0,
+    Outpost = 1,
+}
+
+/// A prefab standing on the surface of a body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Structure {
+    pub kind: StructureKind,
+    /// Where the structure's base is, relative to the centre of the body.
+    pub position: Vec3f,
+    /// Away from the centre of the body.
+    pub up: Vec3f,
+    /// Rotation around `up`, in radians.
+    pub yaw: f32,
+}
+
+impl Structure {
+    /// Places up to `spec.num_structures` structures on flat patches of the
+    /// surface of `scalar_field`, the same ones for a given seed. Candidate
+    /// sites are anchored to the surface by searching along the radius, and
+    /// kept if the ground all around their footprint is about as high.
+    pub fn place<Field>(
+        scalar_field: &Field,
+        seed: u32,
+        planet: &PlanetSpec,
+        spec: &StructureSpec,
+    ) -> Vec<Structure>
+    where
+        Field: ScalarField3,
+    {
+        let _span = trace::span("place_structures");
+        let mut rng = XorShiftRng::from_seed([seed, 0x2c1b_3c6d, 0x297a_2d39, 0x7fb5_d329]);
+        let max_radius = planet.base_radius * SEARCH_RADIUS_RATIO;
+        let max_rise = spec.max_slope.to_radians().tan();
+        let mut structures: Vec<Structure> = vec![];
+        for _ in 0..spec.num_structures * PLACEMENT_ATTEMPTS {
+            if structures.len() >= spec.num_structures {
+                break;
+            }
+            let kind = if rng.gen::<f32>() < OUTPOSTS {
+                StructureKind::Outpost
+            } else {
+                StructureKind::Ruin
+            };
+            let up = random_direction(&mut rng);
+            let yaw = rng.gen_range(0.0, 2.0 * PI);
+
+            let position = match surface_along(scalar_field, &up, max_radius) {
+                Some(position) => position,
+                None => continue,
+            };
+            let radius = position.norm();
+            if planet.sea_radius().map_or(false, |sea| radius < sea) ||
+                scalar_field.material_at(&position.to_point()) == Material::Lava ||
+                structures.iter().any(|other| {
+                    (other.position - Vec3f::from(position)).norm() < spec.min_spacing
+                })
+            {
+                continue;
+            }
+
+            // The ground around the footprint must be within the rise of the
+            // steepest slope allowed, measured from the centre.
+            let footprint = FOOTPRINTS[kind as usize];
+            let tangent = tangent_frame(&up, 0.0);
+            let bitangent = tangent.cross(&up);
+            let flat = (0..FLATNESS_SAMPLES).all(|sample| {
+                let angle = 2.0 * PI * sample as f32 / FLATNESS_SAMPLES as f32;
+                let offset = (tangent * angle.cos() + bitangent * angle.sin()) * footprint;
+                let direction = (position + offset).normalize();
+                surface_along(scalar_field, &direction, max_radius).map_or(
+                    false,
+                    |around| (around.norm() - radius).abs() <= footprint * max_rise,
+                )
+            });
+            if !flat {
+                continue;
+            }
+            structures.push(Structure {
+                kind: kind,
+                position: Vec3f::from(position - up * SINK_DEPTH),
+                up: Vec3f::from(up),
+                yaw: yaw,
+            });
+        }
+        if structures.len() < spec.num_structures {
+            info!(
+                "Placed {} of {} structures, the rest found no flat ground.",
+                structures.len(),
+                spec.num_structures
+            );
+        }
+        structures
+    }
+
+    /// Maps the structure's mesh to where it stands on the body.
+    pub fn isometry(&self) -> Isometry3<CpuScalar> {
+        placement(&self.position, &self.up, self.yaw)
+    }
+}
+
+/// Where the surface of `scalar_field` is along `direction` from its centre,
+/// which is taken to be inside it, up to `max_radius` away.
+fn surface_along<Field>(
+    scalar_field: &Field,
+    direction: &Vector3<CpuScalar>,
+    max_radius: f32,
+) -> Option<Vector3<CpuScalar>>
+where
+    Field: ScalarField3,
+{
+    scalar_field
+        .surface_between(&Point3::new(0.0, 0.0, 0.0), &(*direction * max_radius).to_point())
+        .map(|surface| surface.to_vector())
+}
+
+/// Uploads `structures`, to be drawn by a `PrefabRenderer` of
+/// `STRUCTURE_PREFABS`.
+pub fn upload_structures(window: &Window, structures: &[Structure]) -> Result<PrefabInstances> {
+    let placements: Vec<_> = structures
+        .iter()
+        .map(|structure| {
+            (structure.kind as usize, instance_transform(&structure.isometry(), 1.0))
+        })
+        .collect();
+    PrefabInstances::new(window, &placements)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Norm, Point3, Vector3};
+
+    use math::{CpuScalar, ScalarField3};
+    use planet::PlanetSpec;
+    use super::{Structure, StructureSpec};
+
+    // A sphere with a cliff, a step of 50 around the equator.
+    struct Terraced;
+
+    impl ScalarField3 for Terraced {
+        fn value_at(&self, position: &Point3<CpuScalar>) -> CpuScalar {
+            let radius = if position[1] > 0.0 { 1000.0 } else { 1050.0 };
+            position.to_vector().norm() - radius
+        }
+    }
+
+    #[test]
+    fn test_structures_stand_on_flat_ground() {
+        let planet = PlanetSpec {
+            base_radius: 1000.0,
+            ..Default::default()
+        };
+        let spec = StructureSpec {
+            num_structures: 12,
+            min_spacing: 100.0,
+            ..Default::default()
+        };
+        let structures = Structure::place(&Terraced, 5, &planet, &spec);
+        assert_eq!(structures.len(), 12);
+        assert_eq!(structures, Structure::place(&Terraced, 5, &planet, &spec));
+        for (index, structure) in structures.iter().enumerate() {
+            // Sunk into the ground, away from the cliff.
+            let altitude = Terraced.value_at(&structure.position.to_point());
+            assert!(altitude < 0.0 && altitude > -1.0);
+            assert!(structure.position[1].abs() > 3.0);
+            for other in structures[index + 1..].iter() {
+                assert!((other.position - structure.position).norm() >= spec.min_spacing);
+            }
+            // The mesh's +y is the body's up there.
+            let up = structure.isometry().rotation * Vector3::y();
+            assert!((up - *structure.up).norm() < 1e-4);
+        }
+    }
+}
+
+// Lit with a little more ambient light than the terrain, so walls facing away
+// from the sun keep their shape.
+pub const STRUCTURE_PREFABS: [Prefab; 2] = [
+    Prefab {
+        mesh: "ruin.obj",
+        color: [0.55, 0.52, 0.46],
+        ambient: 0.08,
+    },
+    Prefab {
+        mesh: "outpost.obj",
+        color: [0.42, 0.3, 0.2],
+        ambient: 0.08,
+    },
+];
+// The radius of ground each kind of structure needs to be flat.
+const FOOTPRINTS: [f32; 2] = [6.0, 3.5];
+// The fraction of structures which are outposts, the rest are ruins.
+const OUTPOSTS: f32 = 0.3;
+// Candidate sites tried per structure before giving up.
+const PLACEMENT_ATTEMPTS: usize = 50;
+// Samples of the ground around the footprint.
+const FLATNESS_SAMPLES: usize = 6;
+// The surface is searched for up to this many base radii from the centre.
+const SEARCH_RADIUS_RATIO: f32 = 2.0;
+// How far structures are sunk into the ground, to hide the gaps under them
+// on uneven ground. The prefabs extend below their base for it.
+const SINK_DEPTH: f32 = 0.5;
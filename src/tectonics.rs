@@ -0,0 +1,260 @@
+use nalgebra::{Cross, Dot, Norm, Vector3};
+use noise::Seed;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use fractal::{Fractal, FractalNoise, NoiseBasis};
+use math::{random_direction, Vec3f};
+use planet::PlanetSpec;
+
+/// A rigid cap of the crust, the region of the sphere nearer to its centre
+/// than to that of any other plate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plate {
+    /// A unit vector.
+    pub centre: Vec3f,
+    /// The plate rotates around this axis through the centre of the planet,
+    /// its length being the angular speed.
+    pub rotation: Vec3f,
+    /// Continental plates float high, oceanic ones form the sea floor and
+    /// sink under continents where they meet.
+    pub continental: bool,
+}
+
+/// What the plates make of the surface in a direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlateSample {
+    /// Height of the crust, on the scale of the terrain noise: about -1 in
+    /// trenches, below 0 on the sea floor and above it on continents.
+    pub elevation: f32,
+    /// How mountainous the terrain is, in [0, 1], highest along convergent
+    /// boundaries.
+    pub mountains: f32,
+}
+
+/// The plates of a planet, generated once before the field is sampled. They
+/// replace the low frequency noise mixing mountains and plains with coherent
+/// continents, mountain arcs where plates collide and trenches where the sea
+/// floor sinks under a continent.
+pub struct Plates {
+    plates: Vec<Plate>,
+    seed: Seed,
+    warp: FractalNoise,
+    // How far the boundaries wander from great circles.
+    warp_strength: f32,
+}
+
+impl Plates {
+    /// Scatters `spec.num_plates` plates uniformly over the sphere, of which
+    /// about `spec.continental_fraction` are continents, the same ones for a
+    /// given seed.
+    pub fn generate(seed: u32, spec: &PlanetSpec) -> Self {
+        let mut rng = XorShiftRng::from_seed([seed, 0x7a0d_ee2e, 0x3c6e_f372, 0xa54f_f53a]);
+        let num_continents =
+            (spec.num_plates as f32 * spec.continental_fraction).round() as usize;
+        let plates = (0..spec.num_plates)
+            .map(|index| {
+                let speed = rng.gen_range(MIN_PLATE_SPEED, 1.0);
+                Plate {
+                    centre: Vec3f::from(random_direction(&mut rng)),
+                    rotation: Vec3f::from(random_direction(&mut rng) * speed),
+                    continental: index < num_continents,
+                }
+            })
+            .collect();
+        Plates::new(seed, plates, PLATE_WARP)
+    }
+
+    fn new(seed: u32, plates: Vec<Plate>, warp_strength: f32) -> Self {
+        Plates {
+            plates: plates,
+            seed: Seed::new(seed),
+            warp_strength: warp_strength,
+            warp: FractalNoise {
+                basis: NoiseBasis::Simplex,
+                fractal: Fractal::Fbm,
+                num_octaves: 3,
+                persistence: 0.5,
+                wavelength: 0.8,
+                lacunarity: 2.0,
+            },
+        }
+    }
+
+    /// The crust in the direction of `point`, which needn't be normalized.
+    pub fn sample(&self, point: &Vec3f) -> PlateSample {
+        if self.plates.is_empty() {
+            return PlateSample {
+                elevation: 0.0,
+                mountains: 0.0,
+            };
+        }
+        // Warping the directions bends the boundaries, which would otherwise
+        // be arcs of great circles.
+        let direction = point.normalize();
+        let noise = |offset: f32| {
+            let shifted = direction * 2.0 + offset;
+            self.warp.apply_octaves(
+                &self.seed,
+                &[shifted[0], shifted[1], shifted[2]],
+                self.warp.num_octaves,
+            )
+        };
+        let warp = Vector3::new(
+            noise(WARP_OFFSETS[0]),
+            noise(WARP_OFFSETS[1]),
+            noise(WARP_OFFSETS[2]),
+        );
+        let direction = (direction + warp * self.warp_strength).normalize();
+
+        // The nearest plate is the one the direction is on, the second
+        // nearest the one across the closest boundary.
+        let (mut nearest, mut second) = ((0, -2.0), (0, -2.0));
+        for (index, plate) in self.plates.iter().enumerate() {
+            let cos = direction.dot(&plate.centre);
+            if cos > nearest.1 {
+                second = nearest;
+                nearest = (index, cos);
+            } else if cos > second.1 {
+                second = (index, cos);
+            }
+        }
+        let own = &self.plates[nearest.0];
+        let own_level = crust_level(own);
+        if self.plates.len() < 2 {
+            return PlateSample {
+                elevation: own_level,
+                mountains: 0.0,
+            };
+        }
+        let other = &self.plates[second.0];
+        let other_level = crust_level(other);
+
+        // Half the difference of the angles to the two centres approximates
+        // the angle to the boundary.
+        let to_boundary = (second.1.min(1.0).acos() - nearest.1.min(1.0).acos()) / 2.0;
+        let across = *other.centre - direction * direction.dot(&other.centre);
+        let normal = if across.norm() > 1e-6 {
+            across.normalize()
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+        // Positive where the plates move towards each other, about [-1, 1].
+        let own_velocity = own.rotation.cross(&direction);
+        let other_velocity = other.rotation.cross(&direction);
+        let convergence = (own_velocity - other_velocity).dot(&normal) / 2.0;
+
+        // Coasts are shelves rather than cliffs, the levels of the two plates
+        // meet halfway at the boundary.
+        let shelf = smoothstep(to_boundary / CONTINENTAL_SHELF);
+        let level = own_level * (1.0 + shelf) / 2.0 + other_level * (1.0 - shelf) / 2.0;
+
+        let falloff = 1.0 - smoothstep(to_boundary / BOUNDARY_WIDTH);
+        let (relief, mountains) = if convergence > 0.0 {
+            if !own.continental && other.continental {
+                // The sea floor sinks under the continent.
+                (-TRENCH_DEPTH * convergence, 0.0)
+            } else {
+                (MOUNTAIN_HEIGHT * convergence, convergence)
+            }
+        } else if own.continental {
+            // Continents tear apart into rift valleys, the sea floor rises
+            // into mid-ocean ridges.
+            (RIFT_DEPTH * convergence, 0.0)
+        } else {
+            (-RIDGE_HEIGHT * convergence, 0.0)
+        };
+        PlateSample {
+            elevation: (level + relief * falloff).max(-1.0).min(1.0),
+            mountains: (mountains * falloff).max(0.0).min(1.0),
+        }
+    }
+}
+
+fn crust_level(plate: &Plate) -> f32 {
+    if plate.continental {
+        CONTINENT_LEVEL
+    } else {
+        OCEAN_FLOOR_LEVEL
+    }
+}
+
+/// 0 at 0, 1 from 1 on, smooth in between.
+fn smoothstep(x: f32) -> f32 {
+    let x = x.max(0.0).min(1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use math::Vec3f;
+    use planet::PlanetSpec;
+    use super::{Plate, Plates, OCEAN_FLOOR_LEVEL};
+
+    #[test]
+    fn test_plate_boundaries() {
+        let spec = PlanetSpec {
+            num_plates: 20,
+            continental_fraction: 0.4,
+            ..Default::default()
+        };
+        let plates = Plates::generate(9, &spec);
+        assert_eq!(plates.plates.len(), 20);
+        assert_eq!(plates.plates.iter().filter(|plate| plate.continental).count(), 8);
+        assert_eq!(plates.plates, Plates::generate(9, &spec).plates);
+
+        // Two plates meeting along the great circle x = 0, moving towards
+        // each other there at the unit speed. The boundary is left straight.
+        let colliding = |continental: [bool; 2]| {
+            Plates::new(
+                9,
+                vec![
+                    Plate {
+                        centre: Vec3f::new(1.0, 0.0, 0.0),
+                        rotation: Vec3f::new(0.0, -1.0, 0.0),
+                        continental: continental[0],
+                    },
+                    Plate {
+                        centre: Vec3f::new(-1.0, 0.0, 0.0),
+                        rotation: Vec3f::new(0.0, 1.0, 0.0),
+                        continental: continental[1],
+                    },
+                ],
+                0.0,
+            )
+        };
+        let interior = Vec3f::new(1.0, 0.0, 0.1);
+        let boundary = Vec3f::new(0.02, 0.0, 1.0);
+
+        // Continents collide into mountains.
+        let continents = colliding([true, true]);
+        let (inland, range) = (continents.sample(&interior), continents.sample(&boundary));
+        assert!(range.elevation > inland.elevation + 0.2);
+        assert!(range.mountains > 0.5 && inland.mountains == 0.0);
+
+        // The sea floor sinks under a continent into a trench.
+        let subduction = colliding([false, true]);
+        let (sea_floor, trench) = (subduction.sample(&interior), subduction.sample(&boundary));
+        assert_eq!(sea_floor.elevation, OCEAN_FLOOR_LEVEL);
+        assert!(trench.elevation < sea_floor.elevation - 0.1);
+        assert_eq!(trench.mountains, 0.0);
+    }
+}
+
+// Elevations of the plates away from their boundaries.
+const CONTINENT_LEVEL: f32 = 0.25;
+const OCEAN_FLOOR_LEVEL: f32 = -0.5;
+// Relief at a boundary between plates converging or diverging at the unit
+// speed.
+const MOUNTAIN_HEIGHT: f32 = 0.6;
+const TRENCH_DEPTH: f32 = 0.5;
+const RIFT_DEPTH: f32 = 0.2;
+const RIDGE_HEIGHT: f32 = 0.15;
+// Angles, in radians, from a boundary that its relief and the shelf between
+// a continent and the sea floor reach.
+const BOUNDARY_WIDTH: f32 = 0.1;
+const CONTINENTAL_SHELF: f32 = 0.05;
+const MIN_PLATE_SPEED: f32 = 0.5;
+// How far the boundaries wander from great circles, and offsets decorrelating
+// the noise warping each axis.
+const PLATE_WARP: f32 = 0.25;
+const WARP_OFFSETS: [f32; 3] = [10.0, 30.0, 50.0];
@@ -0,0 +1,847 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::{Read, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use inflate::InflateStream;
+
+use errors::{ChainErr, Result};
+
+/// The first channel of a baseline TIFF image, e.g. an elevation model,
+/// with every bit of its samples. The `image` crate only decodes TIFFs to
+/// 8 bit pixels and doesn't know about signed or floating point samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiffRaster {
+    pub width: u32,
+    pub height: u32,
+    /// In rows from the top.
+    pub samples: Vec<f32>,
+    /// The brightest value an unsigned sample can take, e.g. 65535 for 16
+    /// bits. None for signed and floating point samples, which are usually
+    /// meant as they are.
+    pub max_value: Option<f32>,
+    /// Samples of this value are missing, as given by GDAL's nodata tag.
+    pub nodata: Option<f32>,
+    /// Where the image lies on its body, for GeoTIFFs.
+    pub georeference: Option<GeoReference>,
+}
+
+impl TiffRaster {
+    /// Reads the first image of a TIFF file, which must be grayscale, in
+    /// strips or tiles, uncompressed or compressed with LZW, PackBits or
+    /// Deflate.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let mut byte_order = [0; 2];
+        try!(reader.read_exact(&mut byte_order).chain_err(
+            || "Could not read the TIFF header.",
+        ));
+        match &byte_order {
+            b"II" => read_raster::<LittleEndian, R>(reader),
+            b"MM" => read_raster::<BigEndian, R>(reader),
+            _ => Err("Not a TIFF file.".into()),
+        }
+    }
+}
+
+/// How the pixels of a GeoTIFF map to the surface of its body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoReference {
+    /// Coordinates of the top left corner of the image in the model, in
+    /// degrees east and north for geographic ones, metres for projections.
+    pub origin: (f64, f64),
+    /// Width and height of a pixel in the same units, going east and south.
+    pub pixel_size: (f64, f64),
+    pub model: GeoModel,
+}
+
+/// The coordinate system of a GeoTIFF.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeoModel {
+    /// Longitudes and latitudes.
+    Geographic,
+    /// The simple cylindrical projection most planetary maps are in.
+    Equirectangular(Equirectangular),
+    /// Any other model or projection, described for error messages.
+    Unsupported(String),
+}
+
+/// The parameters of an equirectangular projection, angles in degrees and
+/// distances in metres.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Equirectangular {
+    pub standard_parallel: f64,
+    pub central_meridian: f64,
+    pub origin_latitude: f64,
+    pub false_easting: f64,
+    pub false_northing: f64,
+    /// None if the file only names a datum rather than giving its radius.
+    pub semi_major_axis: Option<f64>,
+}
+
+impl GeoReference {
+    /// The same georeference in longitudes and latitudes, on a body of
+    /// `radius` metres unless the projection gives its own.
+    pub fn to_geographic(&self, radius: f64) -> Result<GeoReference> {
+        match self.model {
+            GeoModel::Geographic => Ok(self.clone()),
+            GeoModel::Equirectangular(ref projection) => {
+                let radius = projection.semi_major_axis.unwrap_or(radius);
+                let degrees_north = 180.0 / (PI * radius);
+                let degrees_east = degrees_north / projection.standard_parallel.to_radians().cos();
+                Ok(GeoReference {
+                    origin: (
+                        projection.central_meridian +
+                            (self.origin.0 - projection.false_easting) * degrees_east,
+                        projection.origin_latitude +
+                            (self.origin.1 - projection.false_northing) * degrees_north,
+                    ),
+                    pixel_size: (
+                        self.pixel_size.0 * degrees_east,
+                        self.pixel_size.1 * degrees_north,
+                    ),
+                    model: GeoModel::Geographic,
+                })
+            }
+            GeoModel::Unsupported(ref description) => Err(
+                format!(
+                    "GeoTIFFs in {} are not supported, reproject them to longitudes and \
+                     latitudes or the equirectangular projection.",
+                    description
+                ).into(),
+            ),
+        }
+    }
+
+    /// None if the directory has no georeferencing tags.
+    fn read(directory: &Directory) -> Option<Self> {
+        let (origin, pixel_size) = if let (Some(scale), Some(tiepoint)) =
+            (
+                directory.tags.get(&TAG_MODEL_PIXEL_SCALE),
+                directory.tags.get(&TAG_MODEL_TIEPOINT),
+            ) {
+            if scale.len() < 2 || tiepoint.len() < 6 {
+                return None;
+            }
+            // The tiepoint gives the model coordinates of one pixel.
+            let (column, row) = (tiepoint[0], tiepoint[1]);
+            (
+                (tiepoint[3] - column * scale[0], tiepoint[4] + row * scale[1]),
+                (scale[0], scale[1]),
+            )
+        } else if let Some(matrix) = directory.tags.get(&TAG_MODEL_TRANSFORMATION) {
+            // Only scaling and translation, rotated images are rare.
+            if matrix.len() < 16 || matrix[1] != 0.0 || matrix[4] != 0.0 {
+                return None;
+            }
+            ((matrix[3], matrix[7]), (matrix[0], -matrix[5]))
+        } else {
+            return None;
+        };
+
+        let keys = directory.geo_keys();
+        let key = |key: u16| keys.get(&key).cloned();
+        // Tied to the centre of a pixel rather than its top left corner.
+        let origin = if key(GEO_KEY_RASTER_TYPE) == Some(RASTER_PIXEL_IS_POINT) {
+            (origin.0 - pixel_size.0 / 2.0, origin.1 + pixel_size.1 / 2.0)
+        } else {
+            origin
+        };
+        let model_type = key(GEO_KEY_MODEL_TYPE).unwrap_or(0.0);
+        let model = if model_type == MODEL_TYPE_GEOGRAPHIC {
+            GeoModel::Geographic
+        } else if model_type == MODEL_TYPE_PROJECTED {
+            let system = key(GEO_KEY_PROJECTED_CS_TYPE).unwrap_or(USER_DEFINED);
+            let units = key(GEO_KEY_PROJ_LINEAR_UNITS).unwrap_or(LINEAR_UNIT_METRE);
+            let either = |first: u16, second: u16| key(first).or(key(second)).unwrap_or(0.0);
+            let projection = Equirectangular {
+                standard_parallel: key(GEO_KEY_PROJ_STD_PARALLEL_1).unwrap_or(0.0),
+                central_meridian: either(GEO_KEY_PROJ_CENTER_LONG, GEO_KEY_PROJ_NAT_ORIGIN_LONG),
+                origin_latitude: either(GEO_KEY_PROJ_CENTER_LAT, GEO_KEY_PROJ_NAT_ORIGIN_LAT),
+                false_easting: key(GEO_KEY_PROJ_FALSE_EASTING).unwrap_or(0.0),
+                false_northing: key(GEO_KEY_PROJ_FALSE_NORTHING).unwrap_or(0.0),
+                semi_major_axis: key(GEO_KEY_GEOG_SEMI_MAJOR_AXIS),
+            };
+            if units != LINEAR_UNIT_METRE {
+                GeoModel::Unsupported(format!("linear units {}", units))
+            } else if EPSG_EQUIRECTANGULAR_WGS84.contains(&system) {
+                GeoModel::Equirectangular(Equirectangular {
+                    semi_major_axis: Some(WGS84_SEMI_MAJOR_AXIS),
+                    ..projection
+                })
+            } else if system == USER_DEFINED &&
+                       key(GEO_KEY_PROJ_COORD_TRANS) == Some(COORD_TRANS_EQUIRECTANGULAR)
+            {
+                GeoModel::Equirectangular(projection)
+            } else {
+                GeoModel::Unsupported(format!("the projected coordinate system {}", system))
+            }
+        } else {
+            GeoModel::Unsupported(format!("the model type {}", model_type))
+        };
+        Some(GeoReference {
+            origin: origin,
+            pixel_size: pixel_size,
+            model: model,
+        })
+    }
+}
+
+/// The tags of an image file directory, each with its values as floats,
+/// which are exact for all the integer types used by baseline tags, apart
+/// from text ones.
+struct Directory {
+    tags: HashMap<u16, Vec<f64>>,
+    strings: HashMap<u16, String>,
+}
+
+impl Directory {
+    fn read<B: ByteOrder, R: Read + Seek>(
+        reader: &mut R,
+        offset: u32,
+        file_len: u64,
+    ) -> Result<Self> {
+        try!(reader.seek(SeekFrom::Start(offset as u64)).chain_err(
+            || "Could not seek to the TIFF directory.",
+        ));
+        let num_entries = try!(reader.read_u16::<B>().chain_err(
+            || "Could not read the TIFF directory.",
+        ));
+        let mut entries = Vec::with_capacity(num_entries as usize);
+        for _ in 0..num_entries {
+            let mut entry = [0; 12];
+            try!(reader.read_exact(&mut entry).chain_err(
+                || "Could not read a TIFF directory entry.",
+            ));
+            entries.push(entry);
+        }
+
+        let (mut tags, mut strings) = (HashMap::new(), HashMap::new());
+        for entry in entries.iter() {
+            let tag = B::read_u16(&entry[0..2]);
+            let field_type = B::read_u16(&entry[2..4]);
+            let count = B::read_u32(&entry[4..8]) as usize;
+            let size: usize = match field_type {
+                TYPE_BYTE | TYPE_SBYTE | TYPE_UNDEFINED | TYPE_ASCII => 1,
+                TYPE_SHORT | TYPE_SSHORT => 2,
+                TYPE_LONG | TYPE_SLONG | TYPE_FLOAT => 4,
+                TYPE_RATIONAL | TYPE_SRATIONAL | TYPE_DOUBLE => 8,
+                // Types from later versions of the format are of no use here.
+                _ => continue,
+            };
+            let len = match size.checked_mul(count) {
+                Some(len) if len as u64 <= file_len => len,
+                _ => {
+                    return Err(
+                        format!(
+                            "TIFF tag {} has {} values, more than the file holds.",
+                            tag,
+                            count
+                        ).into(),
+                    )
+                }
+            };
+            // Values that fit in four bytes are stored in the entry itself.
+            let bytes = if len <= 4 {
+                entry[8..8 + len].to_vec()
+            } else {
+                let mut bytes = vec![0; len];
+                try!(
+                    reader
+                        .seek(SeekFrom::Start(B::read_u32(&entry[8..12]) as u64))
+                        .and_then(|_| reader.read_exact(&mut bytes))
+                        .chain_err(|| format!("Could not read the values of TIFF tag {}.", tag))
+                );
+                bytes
+            };
+            if field_type == TYPE_ASCII {
+                let text = String::from_utf8_lossy(&bytes);
+                strings.insert(tag, text.split('\0').next().unwrap_or("").to_string());
+                continue;
+            }
+            let values = bytes
+                .chunks(size)
+                .map(|value| match field_type {
+                    TYPE_SBYTE => value[0] as i8 as f64,
+                    TYPE_SHORT => B::read_u16(value) as f64,
+                    TYPE_SSHORT => B::read_i16(value) as f64,
+                    TYPE_LONG => B::read_u32(value) as f64,
+                    TYPE_SLONG => B::read_i32(value) as f64,
+                    TYPE_FLOAT => B::read_f32(value) as f64,
+                    TYPE_DOUBLE => B::read_f64(value),
+                    TYPE_RATIONAL => {
+                        B::read_u32(&value[0..4]) as f64 / B::read_u32(&value[4..8]) as f64
+                    }
+                    TYPE_SRATIONAL => {
+                        B::read_i32(&value[0..4]) as f64 / B::read_i32(&value[4..8]) as f64
+                    }
+                    _ => value[0] as f64,
+                })
+                .collect();
+            tags.insert(tag, values);
+        }
+        Ok(Directory {
+            tags: tags,
+            strings: strings,
+        })
+    }
+
+    fn values(&self, tag: u16) -> Result<&[f64]> {
+        match self.tags.get(&tag) {
+            Some(values) if !values.is_empty() => Ok(values),
+            _ => Err(format!("The TIFF file has no tag {}.", tag).into()),
+        }
+    }
+
+    fn value_or(&self, tag: u16, default: f64) -> f64 {
+        self.tags
+            .get(&tag)
+            .and_then(|values| values.first().cloned())
+            .unwrap_or(default)
+    }
+
+    /// The numeric keys of a GeoTIFF, stored in the entries of its key
+    /// directory or as indices into its doubles.
+    fn geo_keys(&self) -> HashMap<u16, f64> {
+        let mut keys = HashMap::new();
+        let entries = match self.tags.get(&TAG_GEO_KEY_DIRECTORY) {
+            Some(entries) if entries.len() > 4 => &entries[4..],
+            _ => return keys,
+        };
+        let doubles = self.tags.get(&TAG_GEO_DOUBLE_PARAMS);
+        for entry in entries.chunks(4).filter(|entry| entry.len() == 4) {
+            let value = match entry[1] as u16 {
+                0 => Some(entry[3]),
+                TAG_GEO_DOUBLE_PARAMS => {
+                    doubles.and_then(|doubles| doubles.get(entry[3] as usize).cloned())
+                }
+                _ => None,
+            };
+            if let Some(value) = value {
+                keys.insert(entry[0] as u16, value);
+            }
+        }
+        keys
+    }
+}
+
+fn read_raster<B: ByteOrder, R: Read + Seek>(reader: &mut R) -> Result<TiffRaster> {
+    let magic = try!(reader.read_u16::<B>().chain_err(|| "Could not read the TIFF header."));
+    if magic != TIFF_MAGIC {
+        return Err(format!("Not a TIFF file, or a BigTIFF one ({}).", magic).into());
+    }
+    let offset = try!(reader.read_u32::<B>().chain_err(|| "Could not read the TIFF header."));
+    let file_len = try!(reader.seek(SeekFrom::End(0)).chain_err(
+        || "Could not find the length of the TIFF file.",
+    ));
+    let directory = try!(Directory::read::<B, R>(reader, offset, file_len));
+
+    let width = try!(directory.values(TAG_IMAGE_WIDTH))[0] as u32;
+    let height = try!(directory.values(TAG_IMAGE_LENGTH))[0] as u32;
+    let samples_per_pixel = directory.value_or(TAG_SAMPLES_PER_PIXEL, 1.0) as usize;
+    let bits = directory.value_or(TAG_BITS_PER_SAMPLE, 1.0) as usize;
+    let sample_format = directory.value_or(TAG_SAMPLE_FORMAT, SAMPLE_FORMAT_UINT as f64) as u16;
+    let photometric = try!(directory.values(TAG_PHOTOMETRIC_INTERPRETATION))[0] as u16;
+    let compression = directory.value_or(TAG_COMPRESSION, COMPRESSION_NONE as f64) as u16;
+    let predictor = directory.value_or(TAG_PREDICTOR, PREDICTOR_NONE as f64) as u16;
+    let planar = directory.value_or(TAG_PLANAR_CONFIGURATION, 1.0) as u16;
+
+    if photometric != PHOTOMETRIC_WHITE_IS_ZERO && photometric != PHOTOMETRIC_BLACK_IS_ZERO {
+        return Err("Only grayscale TIFFs are supported.".into());
+    }
+    if samples_per_pixel == 0 {
+        return Err("The TIFF file has no samples per pixel.".into());
+    }
+    if samples_per_pixel > 1 && planar != 1 {
+        return Err("Only TIFFs with interleaved channels are supported.".into());
+    }
+    let bytes_per_sample = match (sample_format, bits) {
+        (SAMPLE_FORMAT_UINT, 8) | (SAMPLE_FORMAT_INT, 8) => 1,
+        (SAMPLE_FORMAT_UINT, 16) | (SAMPLE_FORMAT_INT, 16) => 2,
+        (SAMPLE_FORMAT_UINT, 32) | (SAMPLE_FORMAT_INT, 32) | (SAMPLE_FORMAT_FLOAT, 32) => 4,
+        _ => {
+            return Err(
+                format!(
+                    "TIFF samples of {} bits in format {} are not supported.",
+                    bits,
+                    sample_format
+                ).into(),
+            )
+        }
+    };
+    if predictor != PREDICTOR_NONE &&
+        (predictor != PREDICTOR_HORIZONTAL || sample_format == SAMPLE_FORMAT_FLOAT)
+    {
+        return Err(format!("The TIFF predictor {} is not supported.", predictor).into());
+    }
+
+    let mask = if bytes_per_sample == 4 {
+        u32::max_value()
+    } else {
+        (1 << (8 * bytes_per_sample)) - 1
+    };
+    let to_sample = |value: u32| match (sample_format, bytes_per_sample) {
+        (SAMPLE_FORMAT_INT, 1) => value as u8 as i8 as f32,
+        (SAMPLE_FORMAT_INT, 2) => value as u16 as i16 as f32,
+        (SAMPLE_FORMAT_INT, _) => value as i32 as f32,
+        (SAMPLE_FORMAT_FLOAT, _) => f32::from_bits(value),
+        _ if photometric == PHOTOMETRIC_WHITE_IS_ZERO => (mask - value) as f32,
+        _ => value as f32,
+    };
+
+    // Strips are tiles as wide as the image, only the last one stops at
+    // its bottom rather than being padded.
+    let (width, height) = (width as usize, height as usize);
+    let tiled = directory.tags.contains_key(&TAG_TILE_OFFSETS);
+    let (tile_width, tile_height, offsets, byte_counts) = if tiled {
+        (
+            try!(directory.values(TAG_TILE_WIDTH))[0] as usize,
+            try!(directory.values(TAG_TILE_LENGTH))[0] as usize,
+            try!(directory.values(TAG_TILE_OFFSETS)),
+            try!(directory.values(TAG_TILE_BYTE_COUNTS)),
+        )
+    } else {
+        (
+            width,
+            (directory.value_or(TAG_ROWS_PER_STRIP, height as f64) as usize).min(height),
+            try!(directory.values(TAG_STRIP_OFFSETS)),
+            try!(directory.values(TAG_STRIP_BYTE_COUNTS)),
+        )
+    };
+    if tile_width == 0 || tile_height == 0 {
+        return Err("The TIFF file has empty strips or tiles.".into());
+    }
+    let tiles_across = (width + tile_width - 1) / tile_width;
+    let num_tiles = tiles_across * ((height + tile_height - 1) / tile_height);
+    if offsets.len() < num_tiles || byte_counts.len() < num_tiles {
+        return Err(
+            format!(
+                "The TIFF file has {} strips or tiles rather than {}.",
+                offsets.len().min(byte_counts.len()),
+                num_tiles
+            ).into(),
+        );
+    }
+
+    // The sizes aren't trusted until they fit the file, so a corrupt
+    // directory can't make it allocate more than its strips or tiles could
+    // decode to.
+    let max_len = if compression == COMPRESSION_NONE {
+        file_len
+    } else {
+        file_len.saturating_mul(MAX_DECODE_RATIO)
+    };
+    let pixel_size = samples_per_pixel.checked_mul(bytes_per_sample);
+    let tile_row_size = pixel_size.and_then(|size| size.checked_mul(tile_width));
+    let tile_len = tile_row_size.and_then(|size| size.checked_mul(tile_height));
+    let raster_len = pixel_size
+        .and_then(|size| size.checked_mul(width))
+        .and_then(|size| size.checked_mul(height));
+    let tile_row_size = match (tile_row_size, tile_len, raster_len) {
+        (Some(size), Some(tile_len), Some(raster_len))
+            if tile_len as u64 <= max_len && raster_len as u64 <= max_len => size,
+        _ => {
+            return Err(
+                format!(
+                    "A {} x {} TIFF raster in {} x {} tiles doesn't fit {} bytes of file.",
+                    width,
+                    height,
+                    tile_width,
+                    tile_height,
+                    file_len
+                ).into(),
+            )
+        }
+    };
+    let mut samples = vec![0.0; width * height];
+    let mut row = vec![0; tile_width * samples_per_pixel];
+    for index in 0..num_tiles {
+        let (left, top) = (index % tiles_across * tile_width, index / tiles_across * tile_height);
+        let num_rows = if tiled {
+            tile_height
+        } else {
+            tile_height.min(height - top)
+        };
+        let size = tile_row_size * num_rows;
+        if byte_counts[index] as u64 > file_len {
+            return Err(
+                format!(
+                    "A TIFF strip or tile of {} bytes doesn't fit the file.",
+                    byte_counts[index]
+                ).into(),
+            );
+        }
+        let mut bytes = vec![0; byte_counts[index] as usize];
+        try!(
+            reader
+                .seek(SeekFrom::Start(offsets[index] as u64))
+                .and_then(|_| reader.read_exact(&mut bytes))
+                .chain_err(|| "Could not read a TIFF strip or tile.")
+        );
+        let data = match compression {
+            COMPRESSION_NONE => bytes,
+            COMPRESSION_LZW => try!(lzw_decode(&bytes, size)),
+            COMPRESSION_PACKBITS => try!(packbits_decode(&bytes, size)),
+            COMPRESSION_DEFLATE |
+            COMPRESSION_ADOBE_DEFLATE => try!(deflate_decode(&bytes, size)),
+            _ => {
+                return Err(
+                    format!("The TIFF compression {} is not supported.", compression).into(),
+                )
+            }
+        };
+        if data.len() < size {
+            return Err(
+                format!(
+                    "The TIFF file has {} bytes in a strip or tile rather than {}.",
+                    data.len(),
+                    size
+                ).into(),
+            );
+        }
+
+        for (y, row_bytes) in data[..size].chunks(tile_row_size).enumerate() {
+            if top + y >= height {
+                break;
+            }
+            for (value, bytes) in row.iter_mut().zip(row_bytes.chunks(bytes_per_sample)) {
+                *value = match bytes_per_sample {
+                    1 => bytes[0] as u32,
+                    2 => B::read_u16(bytes) as u32,
+                    _ => B::read_u32(bytes),
+                };
+            }
+            // Each sample was stored as the difference from the one on its
+            // left.
+            if predictor == PREDICTOR_HORIZONTAL {
+                for index in samples_per_pixel..row.len() {
+                    row[index] = row[index].wrapping_add(row[index - samples_per_pixel]) & mask;
+                }
+            }
+            // Only the first channel is kept, any others being alpha, and
+            // tiles are cut off at the right of the image.
+            let start = (top + y) * width + left;
+            let num_pixels = tile_width.min(width - left);
+            for (sample, pixel) in samples[start..start + num_pixels]
+                .iter_mut()
+                .zip(row.chunks(samples_per_pixel))
+            {
+                *sample = to_sample(pixel[0]);
+            }
+        }
+    }
+
+    let nodata = directory.strings.get(&TAG_GDAL_NODATA).and_then(|text| {
+        text.trim().parse().ok()
+    });
+    Ok(TiffRaster {
+        width: width as u32,
+        height: height as u32,
+        samples: samples,
+        max_value: if sample_format == SAMPLE_FORMAT_UINT {
+            Some(mask as f32)
+        } else {
+            None
+        },
+        nodata: nodata,
+        georeference: GeoReference::read(&directory),
+    })
+}
+
+/// Decompresses a strip of TIFF flavoured LZW, with codes of 9 to 12 bits
+/// stored from the most significant bit and widened one code early.
+fn lzw_decode(input: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    // Each code of the table is a previous code followed by a byte, the
+    // first 256 codes being the bytes themselves.
+    let mut prefixes: Vec<u16> = (0..LZW_FIRST_CODE).collect();
+    let mut suffixes: Vec<u8> = (0..LZW_FIRST_CODE).map(|code| code as u8).collect();
+    let mut first_bytes = suffixes.clone();
+    let mut lengths = vec![1usize; LZW_FIRST_CODE as usize];
+    let mut width = LZW_MIN_WIDTH;
+    let mut previous: Option<u16> = None;
+    let mut position = 0;
+    while position + width <= input.len() * 8 && output.len() < size {
+        let mut code = 0;
+        for bit in position..position + width {
+            code = code << 1 | ((input[bit / 8] >> (7 - bit % 8)) & 1) as u16;
+        }
+        position += width;
+        if code == LZW_CLEAR_CODE {
+            prefixes.truncate(LZW_FIRST_CODE as usize);
+            suffixes.truncate(LZW_FIRST_CODE as usize);
+            first_bytes.truncate(LZW_FIRST_CODE as usize);
+            lengths.truncate(LZW_FIRST_CODE as usize);
+            width = LZW_MIN_WIDTH;
+            previous = None;
+            continue;
+        } else if code == LZW_END_CODE {
+            break;
+        }
+
+        let next_code = prefixes.len() as u16;
+        if let Some(previous) = previous {
+            // A code may only refer to the entry it's about to add, which
+            // ends with its own first byte.
+            let first_byte = if code < next_code {
+                first_bytes[code as usize]
+            } else if code == next_code {
+                first_bytes[previous as usize]
+            } else {
+                return Err(format!("Invalid LZW code {} in a TIFF strip.", code).into());
+            };
+            if next_code < 1 << LZW_MAX_WIDTH {
+                prefixes.push(previous);
+                suffixes.push(first_byte);
+                first_bytes.push(first_bytes[previous as usize]);
+                lengths.push(lengths[previous as usize] + 1);
+            }
+        } else if code >= next_code {
+            return Err(format!("Invalid LZW code {} in a TIFF strip.", code).into());
+        }
+
+        // Writes the string of the code backwards from its last byte.
+        let start = output.len();
+        output.resize(start + lengths[code as usize], 0);
+        let mut entry = code;
+        for index in (start..output.len()).rev() {
+            output[index] = suffixes[entry as usize];
+            entry = prefixes[entry as usize];
+        }
+        previous = Some(code);
+        if prefixes.len() + 1 >= 1 << width && width < LZW_MAX_WIDTH {
+            width += 1;
+        }
+    }
+    output.truncate(size);
+    Ok(output)
+}
+
+/// Decompresses a strip of PackBits, runs of literal and repeated bytes.
+fn packbits_decode(input: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(size);
+    let mut position = 0;
+    while position < input.len() && output.len() < size {
+        let header = input[position] as i8;
+        position += 1;
+        if header >= 0 {
+            let end = position + header as usize + 1;
+            if end > input.len() {
+                return Err("Truncated PackBits run in a TIFF strip.".into());
+            }
+            output.extend_from_slice(&input[position..end]);
+            position = end;
+        } else if header != -128 {
+            if position >= input.len() {
+                return Err("Truncated PackBits run in a TIFF strip.".into());
+            }
+            let count = 1 - header as isize;
+            output.extend((0..count).map(|_| input[position]));
+            position += 1;
+        }
+    }
+    output.truncate(size);
+    Ok(output)
+}
+
+/// Decompresses a strip or tile of zlib wrapped Deflate.
+fn deflate_decode(input: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut stream = InflateStream::from_zlib();
+    let mut output = Vec::with_capacity(size);
+    let mut position = 0;
+    while position < input.len() && output.len() < size {
+        let (consumed, bytes) = try!(stream.update(&input[position..]).map_err(|err| {
+            format!("Invalid Deflate data in a TIFF strip or tile: {}", err)
+        }));
+        if consumed == 0 && bytes.is_empty() {
+            break;
+        }
+        position += consumed;
+        output.extend_from_slice(bytes);
+    }
+    output.truncate(size);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::io::Cursor;
+    use super::{Equirectangular, GeoModel, GeoReference, TiffRaster, packbits_decode};
+
+    #[test]
+    fn test_read_lzw_tiff() {
+        // Written by libtiff: 8 x 4 big endian signed 16 bit samples, LZW
+        // compressed with the horizontal predictor in strips of two rows.
+        let file = vec![
+            0x4d, 0x4d, 0x00, 0x2a, 0x00, 0x00, 0x00, 0x30, 0x80, 0x3f, 0x03, 0x00,
+            0x04, 0x4c, 0x12, 0x0d, 0x05, 0x84, 0x41, 0x1f, 0xa4, 0xf8, 0x3c, 0x36,
+            0x12, 0x89, 0x80, 0x80, 0x80, 0x3f, 0x90, 0xc0, 0x04, 0x4c, 0x12, 0x0d,
+            0x05, 0x84, 0x41, 0x1f, 0xeb, 0xd8, 0x3c, 0x36, 0x12, 0x89, 0x80, 0x80,
+            0x00, 0x0c, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x08,
+            0x00, 0x00, 0x01, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04,
+            0x00, 0x00, 0x01, 0x02, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x10,
+            0x00, 0x00, 0x01, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x05,
+            0x00, 0x00, 0x01, 0x06, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x01, 0x11, 0x00, 0x04, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+            0x00, 0xc6, 0x01, 0x15, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x01, 0x16, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+            0x00, 0x00, 0x01, 0x17, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x00, 0x14,
+            0x00, 0x14, 0x01, 0x1c, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x01, 0x3d, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+            0x00, 0x00, 0x01, 0x53, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00,
+            0x00, 0x1c,
+        ];
+        let raster = TiffRaster::read(&mut Cursor::new(file.clone())).unwrap();
+        assert_eq!((8, 4, None), (raster.width, raster.height, raster.max_value));
+        for y in 0..4 {
+            for x in 0..8 {
+                let expected = ((x * 137 + y * 311) % 2000 - 1000) as f32;
+                assert_eq!(expected, raster.samples[(y * 8 + x) as usize]);
+            }
+        }
+        assert!(TiffRaster::read(&mut Cursor::new(vec![0x42, 0x4d, 0, 0])).is_err());
+
+        // No samples per pixel, and more strip offsets than the file holds.
+        let mut no_samples = file.clone();
+        no_samples[131] = 0;
+        assert!(TiffRaster::read(&mut Cursor::new(no_samples)).is_err());
+        let mut too_many_strips = file;
+        too_many_strips[114] = 0x7f;
+        assert!(TiffRaster::read(&mut Cursor::new(too_many_strips)).is_err());
+    }
+
+    #[test]
+    fn test_equirectangular_to_geographic() {
+        // A degree along the equator of a body of radius 180 / pi metres is a
+        // metre, two metres along the parallels at 60 degrees.
+        let projection = Equirectangular {
+            standard_parallel: 60.0,
+            central_meridian: 100.0,
+            origin_latitude: 0.0,
+            false_easting: 10.0,
+            false_northing: 0.0,
+            semi_major_axis: None,
+        };
+        let georeference = GeoReference {
+            origin: (30.0, 20.0),
+            pixel_size: (4.0, 0.5),
+            model: GeoModel::Equirectangular(projection),
+        };
+        let geographic = georeference.to_geographic(180.0 / PI).unwrap();
+        assert_eq!(GeoModel::Geographic, geographic.model);
+        assert!((geographic.origin.0 - 140.0).abs() < 1e-9);
+        assert!((geographic.origin.1 - 20.0).abs() < 1e-9);
+        assert!((geographic.pixel_size.0 - 8.0).abs() < 1e-9);
+        assert!((geographic.pixel_size.1 - 0.5).abs() < 1e-9);
+        let unsupported = GeoReference {
+            model: GeoModel::Unsupported("UTM".into()),
+            ..geographic
+        };
+        assert!(unsupported.to_geographic(1.0).is_err());
+    }
+
+    #[test]
+    fn test_packbits_decode() {
+        // The example of Apple's PackBits technical note.
+        let packed = [
+            0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7,
+            0xaa,
+        ];
+        let unpacked = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        assert_eq!(&unpacked[..], &packbits_decode(&packed, 24).unwrap()[..]);
+        assert!(packbits_decode(&[0x05, 0x01], 6).is_err());
+    }
+}
+
+const TIFF_MAGIC: u16 = 42;
+
+// Field types of the directory entries.
+const TYPE_BYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+const TYPE_SBYTE: u16 = 6;
+const TYPE_UNDEFINED: u16 = 7;
+const TYPE_SSHORT: u16 = 8;
+const TYPE_SLONG: u16 = 9;
+const TYPE_SRATIONAL: u16 = 10;
+const TYPE_FLOAT: u16 = 11;
+const TYPE_DOUBLE: u16 = 12;
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+// The GeoTIFF tags, and GDAL's for the value of missing samples.
+const TAG_MODEL_PIXEL_SCALE: u16 = 33550;
+const TAG_MODEL_TIEPOINT: u16 = 33922;
+const TAG_MODEL_TRANSFORMATION: u16 = 34264;
+const TAG_GEO_KEY_DIRECTORY: u16 = 34735;
+const TAG_GEO_DOUBLE_PARAMS: u16 = 34736;
+const TAG_GDAL_NODATA: u16 = 42113;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_LZW: u16 = 5;
+const COMPRESSION_DEFLATE: u16 = 8;
+const COMPRESSION_PACKBITS: u16 = 32773;
+const COMPRESSION_ADOBE_DEFLATE: u16 = 32946;
+const PHOTOMETRIC_WHITE_IS_ZERO: u16 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PREDICTOR_NONE: u16 = 1;
+const PREDICTOR_HORIZONTAL: u16 = 2;
+const SAMPLE_FORMAT_UINT: u16 = 1;
+const SAMPLE_FORMAT_INT: u16 = 2;
+const SAMPLE_FORMAT_FLOAT: u16 = 3;
+
+// A 12 bit LZW code decodes to at most 4096 bytes, more than Deflate or
+// PackBits can make of a byte and a half.
+const MAX_DECODE_RATIO: u64 = 2731;
+
+// Codes 256 and 257 clear the table and end the strip, the strings added
+// to the table start after them.
+const LZW_CLEAR_CODE: u16 = 256;
+const LZW_END_CODE: u16 = 257;
+const LZW_FIRST_CODE: u16 = 258;
+const LZW_MIN_WIDTH: usize = 9;
+const LZW_MAX_WIDTH: usize = 12;
+
+const GEO_KEY_MODEL_TYPE: u16 = 1024;
+const GEO_KEY_RASTER_TYPE: u16 = 1025;
+const GEO_KEY_GEOG_SEMI_MAJOR_AXIS: u16 = 2057;
+const GEO_KEY_PROJECTED_CS_TYPE: u16 = 3072;
+const GEO_KEY_PROJ_COORD_TRANS: u16 = 3075;
+const GEO_KEY_PROJ_LINEAR_UNITS: u16 = 3076;
+const GEO_KEY_PROJ_STD_PARALLEL_1: u16 = 3078;
+const GEO_KEY_PROJ_NAT_ORIGIN_LONG: u16 = 3080;
+const GEO_KEY_PROJ_NAT_ORIGIN_LAT: u16 = 3081;
+const GEO_KEY_PROJ_FALSE_EASTING: u16 = 3082;
+const GEO_KEY_PROJ_FALSE_NORTHING: u16 = 3083;
+const GEO_KEY_PROJ_CENTER_LONG: u16 = 3088;
+const GEO_KEY_PROJ_CENTER_LAT: u16 = 3089;
+// Values of the keys, which are read as floats like everything else.
+const MODEL_TYPE_PROJECTED: f64 = 1.0;
+const MODEL_TYPE_GEOGRAPHIC: f64 = 2.0;
+const RASTER_PIXEL_IS_POINT: f64 = 2.0;
+const USER_DEFINED: f64 = 32767.0;
+const COORD_TRANS_EQUIRECTANGULAR: f64 = 17.0;
+const LINEAR_UNIT_METRE: f64 = 9001.0;
+// EPSG codes of the world equidistant cylindrical projection and plate
+// carree on WGS 84.
+const EPSG_EQUIRECTANGULAR_WGS84: [f64; 2] = [4087.0, 32662.0];
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6378137.0;
@@ -0,0 +1,141 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::ptr;
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use errors::{ChainErr, Result};
+
+/// Starts writing spans to `path` as a JSON array of complete ("X") events,
+/// the format loaded by `chrome://tracing`. Tracing stops, and the file is
+/// flushed, when the returned guard is dropped.
+pub fn start<P: AsRef<Path>>(path: P) -> Result<TraceGuard> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(try!(File::create(path).chain_err(|| {
+        format!("Could not create trace file {:?}", path)
+    })));
+    try!(writer.write_all(b"[\n").chain_err(|| "Could not write trace."));
+
+    *tracer().lock().unwrap() = Some(Tracer {
+        start: Instant::now(),
+        writer: writer,
+        num_events: 0,
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+    info!("Writing trace to {:?}", path);
+    Ok(TraceGuard)
+}
+
+/// Times the enclosing scope, e.g. `let _span = trace::span("physics_step");`.
+/// Does nothing unless tracing was started.
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name: name,
+        start: if ENABLED.load(Ordering::Relaxed) {
+            Some(Instant::now())
+        } else {
+            None
+        },
+    }
+}
+
+pub struct Span {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let end = Instant::now();
+            if let Ok(mut tracer) = tracer().lock() {
+                if let Some(ref mut tracer) = *tracer {
+                    tracer.record(self.name, start, end);
+                }
+            }
+        }
+    }
+}
+
+pub struct TraceGuard;
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        ENABLED.store(false, Ordering::SeqCst);
+        if let Ok(mut tracer) = tracer().lock() {
+            if let Some(mut tracer) = tracer.take() {
+                if let Err(err) = tracer.writer.write_all(b"\n]\n").and_then(|_| {
+                    tracer.writer.flush()
+                })
+                {
+                    error!("Could not write trace: {}", err);
+                }
+                info!("Wrote {} trace events.", tracer.num_events);
+            }
+        }
+    }
+}
+
+struct Tracer {
+    start: Instant,
+    writer: BufWriter<File>,
+    num_events: usize,
+}
+
+impl Tracer {
+    fn record(&mut self, name: &str, start: Instant, end: Instant) {
+        // Spans which started before the tracer are clamped to its start.
+        let since_start = if start > self.start {
+            start - self.start
+        } else {
+            Duration::new(0, 0)
+        };
+        let separator = if self.num_events == 0 { "" } else { ",\n" };
+        let result = write!(
+            self.writer,
+            "{}{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{},\"dur\":{}}}",
+            separator,
+            name,
+            thread_id(),
+            micros(since_start),
+            micros(end - start)
+        );
+        match result {
+            Ok(()) => self.num_events += 1,
+            Err(err) => error!("Could not write trace event: {}", err),
+        }
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1000
+}
+
+/// A small integer identifying the current thread, assigned on first use.
+fn thread_id() -> usize {
+    thread_local!(static THREAD_ID: Cell<Option<usize>> = Cell::new(None));
+    THREAD_ID.with(|id| match id.get() {
+        Some(id) => id,
+        None => {
+            let new_id = NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst);
+            id.set(Some(new_id));
+            new_id
+        }
+    })
+}
+
+/// The tracer spans are recorded into, created on first use.
+fn tracer() -> &'static Mutex<Option<Tracer>> {
+    static INIT: Once = Once::new();
+    static mut TRACER: *const Mutex<Option<Tracer>> = ptr::null();
+    unsafe {
+        INIT.call_once(|| TRACER = Box::into_raw(Box::new(Mutex::new(None))));
+        &*TRACER
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use errors::{Result, ChainErr};
 
@@ -15,3 +16,9 @@ pub fn read_utf8_file<P: AsRef<Path>>(path: P) -> Result<String> {
     }));
     Ok(output)
 }
+
+/// Milliseconds since the unix epoch, for unique-ish file names.
+pub fn timestamp_millis() -> String {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}{:03}", elapsed.as_secs(), elapsed.subsec_nanos() / 1_000_000)
+}